@@ -0,0 +1,80 @@
+/*
+ * C bindings for the kdl crate, built with its `ffi` feature. See the
+ * `kdl::ffi` module documentation for ownership and string conventions.
+ */
+
+#ifndef KDL_H
+#define KDL_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct KdlDocument KdlDocument;
+typedef struct KdlNode KdlNode;
+typedef struct KdlEntry KdlEntry;
+typedef struct KdlFfiError KdlFfiError;
+typedef struct KdlFfiDiagnostic KdlFfiDiagnostic;
+
+typedef enum KdlStatus {
+    KDL_STATUS_OK = 0,
+    KDL_STATUS_INVALID_UTF8 = 1,
+    KDL_STATUS_PARSE_ERROR = 2,
+    KDL_STATUS_NULL_POINTER = 3,
+} KdlStatus;
+
+typedef enum KdlValueType {
+    KDL_VALUE_STRING = 0,
+    KDL_VALUE_INTEGER = 1,
+    KDL_VALUE_FLOAT = 2,
+    KDL_VALUE_BOOL = 3,
+    KDL_VALUE_NULL = 4,
+} KdlValueType;
+
+/* Parsing and freeing */
+KdlStatus kdl_parse(const char *input, size_t len, KdlDocument **document, KdlFfiError **error);
+void kdl_document_free(KdlDocument *document);
+void kdl_error_free(KdlFfiError *error);
+void kdl_string_free(char *string);
+
+/* Diagnostics */
+size_t kdl_error_diagnostic_count(const KdlFfiError *error);
+const KdlFfiDiagnostic *kdl_error_diagnostic_at(const KdlFfiError *error, size_t index);
+const char *kdl_diagnostic_message(const KdlFfiDiagnostic *diagnostic);
+const char *kdl_diagnostic_help(const KdlFfiDiagnostic *diagnostic);
+bool kdl_diagnostic_location(const KdlFfiDiagnostic *diagnostic, size_t *offset, size_t *len,
+                             size_t *line, size_t *column);
+
+/* Documents */
+char *kdl_document_to_string(const KdlDocument *document);
+size_t kdl_document_node_count(const KdlDocument *document);
+const KdlNode *kdl_document_node_at(const KdlDocument *document, size_t index);
+const KdlNode *kdl_document_get_node(const KdlDocument *document, const char *name, size_t name_len);
+
+/* Nodes */
+const char *kdl_node_name(const KdlNode *node, size_t *len);
+const char *kdl_node_type(const KdlNode *node, size_t *len);
+const KdlDocument *kdl_node_children(const KdlNode *node);
+size_t kdl_node_entry_count(const KdlNode *node);
+const KdlEntry *kdl_node_entry_at(const KdlNode *node, size_t index);
+const KdlEntry *kdl_node_get_property(const KdlNode *node, const char *name, size_t name_len);
+char *kdl_node_to_string(const KdlNode *node);
+
+/* Entries */
+const char *kdl_entry_name(const KdlEntry *entry, size_t *len);
+const char *kdl_entry_type(const KdlEntry *entry, size_t *len);
+KdlValueType kdl_entry_value_type(const KdlEntry *entry);
+const char *kdl_entry_as_string(const KdlEntry *entry, size_t *len);
+bool kdl_entry_as_integer(const KdlEntry *entry, int64_t *out);
+bool kdl_entry_as_float(const KdlEntry *entry, double *out);
+bool kdl_entry_as_bool(const KdlEntry *entry, int *out);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* KDL_H */
@@ -0,0 +1,297 @@
+//! Derive macros for [`kdl`](https://docs.rs/kdl)'s `KdlDecode` and
+//! `KdlEncode` traits. Use them through kdl's `derive` feature, rather than
+//! depending on this crate directly.
+//!
+//! ```rust
+//! use kdl::{KdlDecode, KdlDocument, KdlEncode};
+//!
+//! #[derive(KdlDecode, KdlEncode, Debug, PartialEq)]
+//! struct Package {
+//!     #[kdl(argument)]
+//!     name: String,
+//!     #[kdl(property)]
+//!     edition: Option<u16>,
+//!     #[kdl(children, name = "dep")]
+//!     dependencies: Vec<Dependency>,
+//! }
+//!
+//! #[derive(KdlDecode, KdlEncode, Debug, PartialEq)]
+//! struct Dependency {
+//!     #[kdl(argument)]
+//!     name: String,
+//!     #[kdl(property, default)]
+//!     optional: bool,
+//! }
+//!
+//! let doc: KdlDocument = "package kdl edition=2021 { dep miette; dep serde optional=#true; }".parse()?;
+//! let package = Package::decode_node(doc.get("package").unwrap())?;
+//! assert_eq!(package.dependencies[1].name, "serde");
+//! assert!(package.dependencies[1].optional);
+//! assert_eq!(Package::decode_node(&package.encode_node("package"))?, package);
+//! # Ok::<(), miette::Report>(())
+//! ```
+//!
+//! See `KdlDecode` for the full list of field attributes.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    parse_macro_input, spanned::Spanned, Data, DeriveInput, Fields, GenericArgument, Ident, LitStr,
+    PathArguments, Type,
+};
+
+/// Derives `KdlDecode` for a struct with named fields.
+#[proc_macro_derive(KdlDecode, attributes(kdl))]
+pub fn derive_decode(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_decode(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Derives `KdlEncode` for a struct with named fields.
+#[proc_macro_derive(KdlEncode, attributes(kdl))]
+pub fn derive_encode(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_encode(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Where in a node a field is read from and written to. The order here is
+/// the order fields are decoded and encoded in, so catch-all children come
+/// after the children picked out by name.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Kind {
+    NodeName,
+    Argument,
+    Arguments,
+    Property,
+    Child,
+    Children,
+}
+
+struct Field {
+    ident: Ident,
+    kind: Kind,
+    name: Option<String>,
+    optional: bool,
+    default: bool,
+}
+
+impl Field {
+    /// The KDL name for this field: the `name` attribute, or the field name
+    /// with underscores turned into dashes.
+    fn kdl_name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| {
+            self.ident
+                .to_string()
+                .trim_start_matches("r#")
+                .replace('_', "-")
+        })
+    }
+}
+
+fn fields(input: &DeriveInput) -> syn::Result<Vec<Field>> {
+    let data = match &input.data {
+        Data::Struct(data) => data,
+        _ => {
+            return Err(syn::Error::new(
+                input.ident.span(),
+                "KdlDecode and KdlEncode can only be derived for structs",
+            ))
+        }
+    };
+    let named = match &data.fields {
+        Fields::Named(named) => &named.named,
+        Fields::Unit => return Ok(Vec::new()),
+        Fields::Unnamed(_) => {
+            return Err(syn::Error::new(
+                data.fields.span(),
+                "KdlDecode and KdlEncode can only be derived for structs with named fields",
+            ))
+        }
+    };
+    let mut fields: Vec<Field> = Vec::new();
+    for field in named {
+        let ident = field.ident.clone().expect("named fields have names");
+        let mut kind = None;
+        let mut name = None;
+        let mut default = false;
+        for attr in field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("kdl"))
+        {
+            attr.parse_nested_meta(|meta| {
+                let new_kind = if meta.path.is_ident("argument") {
+                    Kind::Argument
+                } else if meta.path.is_ident("arguments") {
+                    Kind::Arguments
+                } else if meta.path.is_ident("property") {
+                    Kind::Property
+                } else if meta.path.is_ident("child") {
+                    Kind::Child
+                } else if meta.path.is_ident("children") {
+                    Kind::Children
+                } else if meta.path.is_ident("node_name") {
+                    Kind::NodeName
+                } else if meta.path.is_ident("name") {
+                    name = Some(meta.value()?.parse::<LitStr>()?.value());
+                    return Ok(());
+                } else if meta.path.is_ident("default") {
+                    default = true;
+                    return Ok(());
+                } else {
+                    return Err(meta.error("unknown kdl attribute"));
+                };
+                if kind.replace(new_kind).is_some() {
+                    return Err(meta.error("a field can only come from one place"));
+                }
+                Ok(())
+            })?;
+        }
+        let kind = kind.ok_or_else(|| {
+            syn::Error::new(
+                ident.span(),
+                "fields need one of #[kdl(argument)], #[kdl(arguments)], #[kdl(property)], \
+                 #[kdl(child)], #[kdl(children)], or #[kdl(node_name)]",
+            )
+        })?;
+        if name.is_some() && matches!(kind, Kind::Argument | Kind::Arguments | Kind::NodeName) {
+            return Err(syn::Error::new(
+                ident.span(),
+                "only properties and children have names",
+            ));
+        }
+        if kind == Kind::NodeName && fields.iter().any(|field| field.kind == Kind::NodeName) {
+            return Err(syn::Error::new(
+                ident.span(),
+                "only one field can be the node name",
+            ));
+        }
+        fields.push(Field {
+            ident,
+            kind,
+            name,
+            optional: is_option(&field.ty),
+            default,
+        });
+    }
+    fields.sort_by_key(|field| {
+        (
+            field.kind,
+            field.kind == Kind::Children && field.name.is_none(),
+        )
+    });
+    Ok(fields)
+}
+
+/// Whether `ty` is spelled as an `Option<T>`.
+fn is_option(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) if path.qself.is_none() => {
+            path.path.segments.last().map_or(false, |segment| {
+                segment.ident == "Option"
+                    && matches!(
+                        &segment.arguments,
+                        PathArguments::AngleBracketed(args)
+                            if matches!(args.args.first(), Some(GenericArgument::Type(_)))
+                    )
+            })
+        }
+        _ => false,
+    }
+}
+
+fn expand_decode(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = fields(input)?;
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let reads = fields.iter().map(|field| {
+        let ident = &field.ident;
+        let name = field.kdl_name();
+        let (required, optional) = match field.kind {
+            Kind::NodeName => return quote!(let #ident = decoder.node_name().into();),
+            Kind::Arguments => return quote!(let #ident = decoder.arguments()?;),
+            Kind::Children => {
+                let name = match &field.name {
+                    Some(name) => quote!(::core::option::Option::Some(#name)),
+                    None => quote!(::core::option::Option::None),
+                };
+                return quote!(let #ident = decoder.children(#name)?;);
+            }
+            Kind::Argument => (quote!(argument), quote!(optional_argument())),
+            Kind::Property => (quote!(property), quote!(optional_property(#name))),
+            Kind::Child => (quote!(child), quote!(optional_child(#name))),
+        };
+        if field.optional {
+            quote!(let #ident = decoder.#optional?;)
+        } else if field.default {
+            quote!(let #ident = decoder.#optional?.unwrap_or_default();)
+        } else {
+            quote!(let #ident = decoder.#required(#name)?;)
+        }
+    });
+    let idents = fields.iter().map(|field| &field.ident);
+    Ok(quote! {
+        impl #impl_generics ::kdl::KdlDecode for #ident #ty_generics #where_clause {
+            fn decode_node(
+                node: &::kdl::KdlNode,
+            ) -> ::core::result::Result<Self, ::kdl::KdlConversionError> {
+                let mut decoder = ::kdl::KdlNodeDecoder::new(node);
+                #(#reads)*
+                decoder.finish()?;
+                ::core::result::Result::Ok(Self { #(#idents),* })
+            }
+        }
+    })
+}
+
+fn expand_encode(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = fields(input)?;
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let node_name = match fields.iter().find(|field| field.kind == Kind::NodeName) {
+        Some(field) => {
+            let ident = &field.ident;
+            quote!(::core::convert::AsRef::<str>::as_ref(&self.#ident))
+        }
+        None => quote!(name),
+    };
+    let writes = fields.iter().filter_map(|field| {
+        let ident = &field.ident;
+        let name = field.kdl_name();
+        let optional = field.optional;
+        Some(match field.kind {
+            Kind::NodeName => return None,
+            Kind::Argument if optional => quote!(encoder.optional_argument(&self.#ident);),
+            Kind::Argument => quote!(encoder.argument(&self.#ident);),
+            Kind::Arguments => quote!(encoder.arguments(&self.#ident);),
+            Kind::Property if optional => {
+                quote!(encoder.optional_property(#name, &self.#ident);)
+            }
+            Kind::Property => quote!(encoder.property(#name, &self.#ident);),
+            Kind::Child if optional => quote!(encoder.optional_child(#name, &self.#ident);),
+            Kind::Child => quote!(encoder.child(#name, &self.#ident);),
+            Kind::Children => {
+                let name = match &field.name {
+                    Some(name) => quote!(::core::option::Option::Some(#name)),
+                    None => quote!(::core::option::Option::None),
+                };
+                quote!(encoder.children(#name, &self.#ident);)
+            }
+        })
+    });
+    Ok(quote! {
+        impl #impl_generics ::kdl::KdlEncode for #ident #ty_generics #where_clause {
+            fn encode_node(&self, name: &str) -> ::kdl::KdlNode {
+                let _ = name;
+                let mut encoder = ::kdl::KdlNodeEncoder::new(#node_name);
+                #(#writes)*
+                encoder.finish()
+            }
+        }
+    })
+}
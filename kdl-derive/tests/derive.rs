@@ -0,0 +1,133 @@
+use kdl::{KdlDecode, KdlDocument, KdlEncode, KdlNode};
+
+#[derive(KdlDecode, KdlEncode, Debug)]
+struct Config {
+    #[kdl(child)]
+    server: Server,
+    #[kdl(child)]
+    log_level: Option<Level>,
+    #[kdl(children, name = "plugin")]
+    plugins: Vec<Plugin>,
+    #[kdl(children)]
+    extra: Vec<KdlNode>,
+}
+
+#[derive(KdlDecode, KdlEncode, Debug, PartialEq)]
+struct Server {
+    #[kdl(argument)]
+    host: String,
+    #[kdl(property)]
+    port: u16,
+    #[kdl(property, name = "tls")]
+    use_tls: Option<bool>,
+    #[kdl(property, default)]
+    max_connections: u32,
+}
+
+#[derive(KdlDecode, KdlEncode, Debug, PartialEq)]
+struct Level {
+    #[kdl(argument)]
+    level: String,
+}
+
+#[derive(KdlDecode, KdlEncode, Debug, PartialEq)]
+struct Plugin {
+    #[kdl(argument)]
+    name: String,
+    #[kdl(arguments)]
+    args: Vec<i64>,
+}
+
+#[derive(KdlDecode, KdlEncode, Debug, PartialEq)]
+struct Named {
+    #[kdl(node_name)]
+    name: String,
+    #[kdl(argument)]
+    r#type: Option<f64>,
+}
+
+#[derive(KdlDecode, KdlEncode, Debug, PartialEq)]
+struct Marker;
+
+#[test]
+fn decode_document() -> miette::Result<()> {
+    let doc: KdlDocument = r#"
+    server localhost port=8080 max-connections=10
+    log-level debug
+    plugin auth 1 2 3
+    something-else
+    plugin cache
+    "#
+    .parse()?;
+    let config = Config::decode_document(&doc)?;
+    assert_eq!(
+        config.server,
+        Server {
+            host: "localhost".into(),
+            port: 8080,
+            use_tls: None,
+            max_connections: 10,
+        }
+    );
+    assert_eq!(config.log_level.as_ref().unwrap().level, "debug");
+    assert_eq!(
+        config.plugins,
+        [
+            Plugin {
+                name: "auth".into(),
+                args: vec![1, 2, 3],
+            },
+            Plugin {
+                name: "cache".into(),
+                args: vec![],
+            },
+        ]
+    );
+    assert_eq!(config.extra.len(), 1);
+    assert_eq!(config.extra[0].name().value(), "something-else");
+
+    let encoded = config.encode_document();
+    assert_eq!(
+        encoded.to_string(),
+        "server localhost port=8080 max-connections=10\nlog-level debug\nplugin auth 1 2 3\nplugin cache\nsomething-else\n"
+    );
+    let decoded = Config::decode_document(&encoded)?;
+    assert_eq!(decoded.server, config.server);
+    assert_eq!(decoded.plugins, config.plugins);
+    assert_eq!(decoded.encode_document().to_string(), encoded.to_string());
+    Ok(())
+}
+
+#[test]
+fn node_names() -> miette::Result<()> {
+    let named = Named::decode_node(&"whatever 1.5".parse()?)?;
+    assert_eq!(named.name, "whatever");
+    assert_eq!(named.r#type, Some(1.5));
+    assert_eq!(named.encode_node("ignored").to_string(), "whatever 1.5\n");
+
+    assert_eq!(Marker::decode_node(&"marker".parse()?)?, Marker);
+    assert!(Marker::decode_node(&"marker 1".parse()?).is_err());
+    Ok(())
+}
+
+#[test]
+fn errors() -> miette::Result<()> {
+    for (input, message) in [
+        ("log-level debug", "Missing child node `server`."),
+        ("server localhost", "Missing property `port`."),
+        ("server localhost port=1 tls=1", "Expected a boolean."),
+        ("server port=1", "Missing argument for `host`."),
+        (
+            "server localhost port=1 mystery=1",
+            "Unexpected property `mystery`.",
+        ),
+        (
+            "server localhost port=1\nplugin x a",
+            "Expected an integer (i64).",
+        ),
+    ] {
+        let err = Config::decode_document(&input.parse()?).unwrap_err();
+        assert_eq!(err.message, message, "{input}");
+    }
+    Ok(())
+}
@@ -0,0 +1,10 @@
+//! Conversions between KDL documents and other configuration formats, each
+//! behind a feature of the same name.
+//!
+//! These formats can't represent everything KDL can, and vice versa, so
+//! the conversions are lossy. Each module documents its mapping rules.
+
+#[cfg(feature = "toml")]
+pub mod toml;
+#[cfg(feature = "yaml")]
+pub mod yaml;
@@ -0,0 +1,418 @@
+//! Conversions between [`KdlDocument`]s and [`toml::Table`](::toml::Table)s,
+//! with the `toml` feature.
+//!
+//! A document is a table with a key for each node name, and a node's value
+//! depends on its shape:
+//!
+//! * With a single argument and nothing else, the argument: `name "kdl"` is
+//!   `name = "kdl"`.
+//! * With several arguments and nothing else, an array of them:
+//!   `keywords a b` is `keywords = ["a", "b"]`. With none at all, and no
+//!   children block, an empty array.
+//! * With only children, all named `-`, an array of their values:
+//!   `matrix { - 1 2; - 3 4; }` is `matrix = [[1, 2], [3, 4]]`.
+//! * Otherwise, a table of its properties and its children:
+//!   `package name=kdl { edition "2021"; }` is
+//!   `package = { name = "kdl", edition = "2021" }`, and `package {}` is
+//!   an empty table. Such a node can't also have arguments.
+//!
+//! Nodes with the same name in one document or children block make an
+//! array of their values, so `bin name=a; bin name=b` is Cargo's
+//! `[[bin]]`. A child and a property with the same name can't both be kept,
+//! so the child wins.
+//!
+//! The conversion is lossy in these ways:
+//!
+//! * Formatting and comments are dropped.
+//! * `#null` has no TOML equivalent, so null arguments and properties are
+//!   dropped, as are nodes whose only value is `#null`.
+//! * Type annotations are dropped, except that strings annotated
+//!   `date-time`, `date`, or `time` become TOML datetimes, and back.
+//! * Integers must fit in an `i64`.
+//!
+//! [`from_table`] writes tables as children, rather than properties, and
+//! writes single-element arrays and arrays of one table with `-` children,
+//! so that [`to_table`] reads back exactly the table it started with.
+//!
+//! # Examples
+//!
+//! ```rust
+//! # use kdl::{convert, KdlDocument};
+//! let doc: KdlDocument = r#"
+//! package name=my-app {
+//!     version "0.1.0"
+//!     authors "Kat" "Alice"
+//! }
+//! bin name=one
+//! bin name=two
+//! released (date)"2024-05-01"
+//! "#.parse()?;
+//! let table = convert::toml::to_table(&doc)?;
+//! assert_eq!(
+//!     table.to_string(),
+//!     r#"released = 2024-05-01
+//!
+//! [package]
+//! name = "my-app"
+//! version = "0.1.0"
+//! authors = ["Kat", "Alice"]
+//!
+//! [[bin]]
+//! name = "one"
+//!
+//! [[bin]]
+//! name = "two"
+//! "#
+//! );
+//! assert_eq!(convert::toml::to_table(&convert::toml::from_table(&table))?, table);
+//! # Ok::<(), miette::Report>(())
+//! ```
+
+use ::toml::{value::Datetime, Table, Value};
+use indexmap::IndexMap;
+
+use crate::{KdlConversionError, KdlDocument, KdlEntry, KdlNode, KdlValue};
+
+/// Converts a document to a TOML table. See the [module docs](self) for the
+/// mapping.
+pub fn to_table(doc: &KdlDocument) -> Result<Table, KdlConversionError> {
+    nodes_to_table(Table::new(), doc.nodes())
+}
+
+/// Converts a TOML table to an autoformatted document. See the
+/// [module docs](self) for the mapping.
+pub fn from_table(table: &Table) -> KdlDocument {
+    let mut doc = KdlDocument::new();
+    *doc.nodes_mut() = table_nodes(table);
+    doc.autoformat();
+    doc
+}
+
+fn nodes_to_table(mut table: Table, nodes: &[KdlNode]) -> Result<Table, KdlConversionError> {
+    let mut groups = IndexMap::<&str, Vec<Value>>::new();
+    for node in nodes {
+        let group = groups.entry(node.name().value()).or_default();
+        if let Some(value) = node_to_value(node)? {
+            group.push(value);
+        }
+    }
+    for (name, mut values) in groups {
+        match values.len() {
+            0 => {}
+            1 => {
+                table.insert(name.into(), values.remove(0));
+            }
+            _ => {
+                table.insert(name.into(), Value::Array(values));
+            }
+        }
+    }
+    Ok(table)
+}
+
+fn node_to_value(node: &KdlNode) -> Result<Option<Value>, KdlConversionError> {
+    let args = node.entries().iter().filter(|entry| entry.name().is_none());
+    let has_props = node.entries().iter().any(|entry| entry.name().is_some());
+    let children = node.children().map(|doc| doc.nodes());
+
+    if !has_props {
+        match children {
+            None => {
+                let mut values = Vec::new();
+                for arg in args.clone() {
+                    values.extend(entry_to_value(arg)?);
+                }
+                return Ok(match (args.count(), values.len()) {
+                    (1, 0) => None,
+                    (1, _) => Some(values.remove(0)),
+                    _ => Some(Value::Array(values)),
+                });
+            }
+            Some(children)
+                if !children.is_empty()
+                    && children.iter().all(|child| child.name().value() == "-") =>
+            {
+                if let Some(arg) = args.clone().next() {
+                    return Err(entry_error(
+                        arg,
+                        "Nodes with `-` children can't also have arguments in TOML.",
+                    ));
+                }
+                let mut values = Vec::new();
+                for child in children {
+                    values.extend(node_to_value(child)?);
+                }
+                return Ok(Some(Value::Array(values)));
+            }
+            Some(_) => {}
+        }
+    }
+
+    if let Some(arg) = args.clone().next() {
+        return Err(entry_error(
+            arg,
+            "Nodes with properties or children can't also have arguments in TOML.",
+        ));
+    }
+    let mut table = Table::new();
+    for prop in node.entries() {
+        if let (Some(name), Some(value)) = (prop.name(), entry_to_value(prop)?) {
+            table.insert(name.value().into(), value);
+        }
+    }
+    Ok(Some(Value::Table(nodes_to_table(
+        table,
+        children.unwrap_or_default(),
+    )?)))
+}
+
+fn entry_to_value(entry: &KdlEntry) -> Result<Option<Value>, KdlConversionError> {
+    let ty = entry.ty().map(|ty| ty.value());
+    Ok(Some(match entry.value() {
+        KdlValue::String(s) if matches!(ty, Some("date-time" | "date" | "time")) => {
+            let datetime = s.parse::<Datetime>().map_err(|_| {
+                entry_error(entry, format!("Invalid TOML {}.", ty.unwrap_or_default()))
+            })?;
+            Value::Datetime(datetime)
+        }
+        KdlValue::String(s) => Value::String(s.clone()),
+        KdlValue::Integer(i) => Value::Integer(
+            i64::try_from(*i)
+                .map_err(|_| entry_error(entry, "TOML integers must fit in an i64."))?,
+        ),
+        KdlValue::Float(f) => Value::Float(*f),
+        KdlValue::Bool(b) => Value::Boolean(*b),
+        KdlValue::Null => return Ok(None),
+    }))
+}
+
+fn table_nodes(table: &Table) -> Vec<KdlNode> {
+    table
+        .iter()
+        .flat_map(|(key, value)| value_nodes(key, value, true))
+        .collect()
+}
+
+/// Writes a key's value as nodes. Arrays of several tables are written as
+/// several nodes when `repeat` is set, which it isn't for `-` elements.
+fn value_nodes(name: &str, value: &Value, repeat: bool) -> Vec<KdlNode> {
+    let mut node = KdlNode::new(name);
+    match value {
+        Value::Table(table) => {
+            node.ensure_children()
+                .nodes_mut()
+                .extend(table_nodes(table));
+        }
+        Value::Array(array) if array.len() != 1 && array.iter().all(is_scalar) => {
+            for value in array {
+                node.push(scalar_entry(value));
+            }
+        }
+        Value::Array(array) if repeat && array.len() > 1 && array.iter().all(Value::is_table) => {
+            return array
+                .iter()
+                .flat_map(|value| value_nodes(name, value, false))
+                .collect();
+        }
+        Value::Array(array) => {
+            node.ensure_children().nodes_mut().extend(
+                array
+                    .iter()
+                    .flat_map(|value| value_nodes("-", value, false)),
+            );
+        }
+        scalar => node.push(scalar_entry(scalar)),
+    }
+    vec![node]
+}
+
+fn is_scalar(value: &Value) -> bool {
+    !matches!(value, Value::Array(_) | Value::Table(_))
+}
+
+fn scalar_entry(value: &Value) -> KdlEntry {
+    match value {
+        Value::String(s) => KdlEntry::new(s.as_str()),
+        Value::Integer(i) => KdlEntry::new(i128::from(*i)),
+        Value::Float(f) => KdlEntry::new(*f),
+        Value::Boolean(b) => KdlEntry::new(*b),
+        Value::Datetime(datetime) => {
+            let mut entry = KdlEntry::new(datetime.to_string());
+            entry.set_ty(match (datetime.date, datetime.time) {
+                (Some(_), Some(_)) => "date-time",
+                (Some(_), None) => "date",
+                _ => "time",
+            });
+            entry
+        }
+        Value::Array(_) | Value::Table(_) => unreachable!("only called on scalars"),
+    }
+}
+
+#[cfg(feature = "span")]
+fn entry_error(entry: &KdlEntry, message: impl Into<String>) -> KdlConversionError {
+    KdlConversionError {
+        message: message.into(),
+        span: Some(entry.span()),
+    }
+}
+
+#[cfg(not(feature = "span"))]
+fn entry_error(_entry: &KdlEntry, message: impl Into<String>) -> KdlConversionError {
+    KdlConversionError {
+        message: message.into(),
+        span: None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn toml(doc: &str) -> Table {
+        to_table(&doc.parse().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn to_toml() {
+        assert_eq!(
+            toml(
+                r#"
+                a 1
+                b 1 2
+                c
+                d {}
+                e { - 1; - 2 3; - x=1; }
+                f x=1 { g #true; g #false; x 2; }
+                nothing #null
+                gaps 1 #null 2
+                t (time)"07:32:00"
+                "#
+            )
+            .to_string(),
+            r#"a = 1
+b = [1, 2]
+c = []
+e = [1, [2, 3], { x = 1 }]
+gaps = [1, 2]
+t = 07:32:00
+
+[d]
+
+[f]
+x = 2
+g = [true, false]
+"#
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let table: Table = r#"
+            scalar = "s"
+            one = [1]
+            none = []
+            nested = [[1], [2, 3], []]
+            mixed = [1, "a", { b = 2 }]
+            when = 1979-05-27T07:32:00Z
+            local = 1979-05-27
+            [[only]]
+            x = 1
+            [[many]]
+            x = 1
+            [[many]]
+            x = 2
+            [[many.sub]]
+            y = [{ z = 1 }, { z = 2 }]
+            [empty]
+        "#
+        .parse()
+        .unwrap();
+        let doc = from_table(&table);
+        assert_eq!(to_table(&doc).unwrap(), table);
+        assert_eq!(
+            doc.to_string(),
+            r#"scalar s
+one {
+    - 1
+}
+none
+nested {
+    - {
+        - 1
+    }
+    - 2 3
+    -
+}
+mixed {
+    - 1
+    - a
+    - {
+        b 2
+    }
+}
+when (date-time)"1979-05-27T07:32:00Z"
+local (date)"1979-05-27"
+only {
+    - {
+        x 1
+    }
+}
+many {
+    x 1
+}
+many {
+    x 2
+    sub {
+        - {
+            y {
+                z 1
+            }
+            y {
+                z 2
+            }
+        }
+    }
+}
+empty {
+}
+"#
+        );
+    }
+
+    #[test]
+    fn errors() {
+        for (input, message, at) in [
+            (
+                "a 1 x=1",
+                "Nodes with properties or children can't also have arguments in TOML.",
+                "1",
+            ),
+            (
+                "a 1 { - 2; }",
+                "Nodes with `-` children can't also have arguments in TOML.",
+                "1",
+            ),
+            (
+                "a { b 9223372036854775808; }",
+                "TOML integers must fit in an i64.",
+                "9223372036854775808",
+            ),
+            (
+                "a (date)\"tomorrow\"",
+                "Invalid TOML date.",
+                "(date)\"tomorrow\"",
+            ),
+        ] {
+            let err = to_table(&input.parse().unwrap()).unwrap_err();
+            assert_eq!(err.message, message, "{input}");
+            #[cfg(feature = "span")]
+            {
+                let span = err.span.unwrap();
+                assert_eq!(&input[span.offset()..][..span.len()], at, "{input}");
+            }
+            #[cfg(not(feature = "span"))]
+            let _ = at;
+        }
+    }
+}
@@ -0,0 +1,504 @@
+//! Conversions between [`KdlDocument`]s and
+//! [`serde_yaml::Value`](::serde_yaml::Value)s, with the `yaml` feature.
+//!
+//! The mapping is the same as [`convert::toml`](super::toml)'s, with a
+//! mapping in place of a table and a sequence in place of an array:
+//!
+//! * A document is a mapping with a key for each node name, and nodes with
+//!   the same name make a sequence of their values.
+//! * A node with a single argument and nothing else is that argument, and
+//!   one with several, or none and no children block, is a sequence of
+//!   them.
+//! * A node with only `-` children is a sequence of their values.
+//! * Any other node is a mapping of its properties and its children, and
+//!   can't also have arguments.
+//!
+//! Unlike TOML, YAML has nulls, so `#null` is kept as `null`. YAML tags and
+//! KDL type annotations map to each other: `!Ref main` is `(Ref)main`, and a
+//! tagged mapping or sequence is a node with a type annotation, like
+//! `(Config)settings { ... }`. Tags from YAML's core schema, like `!!str`,
+//! are resolved by [`serde_yaml`] while parsing, so they don't show up as
+//! annotations.
+//!
+//! The conversion is lossy in these ways:
+//!
+//! * Formatting and comments are dropped.
+//! * Mapping keys that are numbers, booleans, or null become node names
+//!   with the same text, so they come back as strings. Other keys are
+//!   errors.
+//! * Integers must fit in an `i64` or a `u64`.
+//! * Anchors and aliases have no KDL equivalent, so [`from_str`] expands
+//!   each alias into a copy of the value it refers to, and applies `<<`
+//!   merge keys. A [`Value`] has always had its aliases expanded.
+//!
+//! [`from_value`] writes mappings as children, and single-element
+//! sequences and sequences of one mapping with `-` children, so that
+//! [`to_value`] reads back the value it started with.
+//!
+//! # Examples
+//!
+//! ```rust
+//! # use kdl::convert::yaml;
+//! let doc = yaml::from_str(
+//!     r#"
+//! apiVersion: v1
+//! kind: Pod
+//! metadata:
+//!   name: web
+//!   labels: &labels
+//!     app: web
+//! spec:
+//!   selector: *labels
+//!   containers:
+//!     - name: nginx
+//!       ports: [80, 443]
+//!       command: !Ref start
+//! "#,
+//! )?;
+//! assert_eq!(
+//!     doc.to_string(),
+//!     r#"apiVersion v1
+//! kind Pod
+//! metadata {
+//!     name web
+//!     labels {
+//!         app web
+//!     }
+//! }
+//! spec {
+//!     selector {
+//!         app web
+//!     }
+//!     containers {
+//!         - {
+//!             name nginx
+//!             ports 80 443
+//!             command (Ref)start
+//!         }
+//!     }
+//! }
+//! "#
+//! );
+//!
+//! # Ok::<(), miette::Report>(())
+//! ```
+
+use ::serde_yaml::{
+    value::{Tag, TaggedValue},
+    Mapping, Number, Value,
+};
+use indexmap::IndexMap;
+
+use crate::{KdlConversionError, KdlDocument, KdlEntry, KdlNode, KdlValue};
+
+/// Parses a YAML document and converts it, expanding any aliases. See the
+/// [module docs](self) for the mapping.
+pub fn from_str(yaml: &str) -> Result<KdlDocument, KdlConversionError> {
+    let yaml_error = |err: ::serde_yaml::Error| KdlConversionError {
+        span: err.location().map(|location| (location.index(), 0).into()),
+        message: err.to_string(),
+    };
+    let mut value = ::serde_yaml::from_str::<Value>(yaml).map_err(yaml_error)?;
+    value.apply_merge().map_err(yaml_error)?;
+    from_value(&value)
+}
+
+/// Converts a document to a YAML mapping. See the [module docs](self) for
+/// the mapping.
+pub fn to_value(doc: &KdlDocument) -> Result<Value, KdlConversionError> {
+    Ok(Value::Mapping(nodes_to_mapping(
+        Mapping::new(),
+        doc.nodes(),
+    )?))
+}
+
+/// Converts a YAML mapping to an autoformatted document. See the
+/// [module docs](self) for the mapping.
+pub fn from_value(value: &Value) -> Result<KdlDocument, KdlConversionError> {
+    let mapping = value
+        .as_mapping()
+        .ok_or_else(|| error("Only a YAML mapping can be converted to a KDL document."))?;
+    let mut doc = KdlDocument::new();
+    *doc.nodes_mut() = mapping_nodes(mapping)?;
+    doc.autoformat();
+    Ok(doc)
+}
+
+fn error(message: impl Into<String>) -> KdlConversionError {
+    KdlConversionError {
+        message: message.into(),
+        span: None,
+    }
+}
+
+fn nodes_to_mapping(
+    mut mapping: Mapping,
+    nodes: &[KdlNode],
+) -> Result<Mapping, KdlConversionError> {
+    let mut groups = IndexMap::<&str, Vec<Value>>::new();
+    for node in nodes {
+        groups
+            .entry(node.name().value())
+            .or_default()
+            .push(node_to_value(node)?);
+    }
+    for (name, mut values) in groups {
+        let value = match values.len() {
+            1 => values.remove(0),
+            _ => Value::Sequence(values),
+        };
+        mapping.insert(name.into(), value);
+    }
+    Ok(mapping)
+}
+
+fn node_to_value(node: &KdlNode) -> Result<Value, KdlConversionError> {
+    let value = node_shape_to_value(node)?;
+    match node.ty() {
+        Some(_) if matches!(value, Value::Tagged(_)) => Err(node_error(
+            node,
+            "A node with a type annotation can't also have a single annotated argument.",
+        )),
+        Some(ty) => Ok(Value::Tagged(Box::new(TaggedValue {
+            tag: tag(ty.value()).ok_or_else(|| node_error(node, "YAML tags can't be empty."))?,
+            value,
+        }))),
+        None => Ok(value),
+    }
+}
+
+fn node_shape_to_value(node: &KdlNode) -> Result<Value, KdlConversionError> {
+    let mut args = node.entries().iter().filter(|entry| entry.name().is_none());
+    let has_props = node.entries().iter().any(|entry| entry.name().is_some());
+    let children = node.children().map(|doc| doc.nodes());
+
+    if !has_props {
+        match children {
+            None => {
+                let mut values = args.map(entry_to_value).collect::<Result<Vec<_>, _>>()?;
+                return Ok(match values.len() {
+                    1 => values.remove(0),
+                    _ => Value::Sequence(values),
+                });
+            }
+            Some(children)
+                if !children.is_empty()
+                    && children.iter().all(|child| child.name().value() == "-") =>
+            {
+                if let Some(arg) = args.next() {
+                    return Err(entry_error(
+                        arg,
+                        "Nodes with `-` children can't also have arguments in YAML.",
+                    ));
+                }
+                return children
+                    .iter()
+                    .map(node_to_value)
+                    .collect::<Result<_, _>>()
+                    .map(Value::Sequence);
+            }
+            Some(_) => {}
+        }
+    }
+
+    if let Some(arg) = args.next() {
+        return Err(entry_error(
+            arg,
+            "Nodes with properties or children can't also have arguments in YAML.",
+        ));
+    }
+    let mut mapping = Mapping::new();
+    for prop in node.entries() {
+        if let Some(name) = prop.name() {
+            mapping.insert(name.value().into(), entry_to_value(prop)?);
+        }
+    }
+    Ok(Value::Mapping(nodes_to_mapping(
+        mapping,
+        children.unwrap_or_default(),
+    )?))
+}
+
+fn entry_to_value(entry: &KdlEntry) -> Result<Value, KdlConversionError> {
+    let value = match entry.value() {
+        KdlValue::String(s) => Value::String(s.clone()),
+        KdlValue::Integer(i) => {
+            let number = i64::try_from(*i)
+                .map(Number::from)
+                .or_else(|_| u64::try_from(*i).map(Number::from))
+                .map_err(|_| entry_error(entry, "YAML integers must fit in an i64 or a u64."))?;
+            Value::Number(number)
+        }
+        KdlValue::Float(f) => Value::Number(Number::from(*f)),
+        KdlValue::Bool(b) => Value::Bool(*b),
+        KdlValue::Null => Value::Null,
+    };
+    match entry.ty() {
+        Some(ty) => Ok(Value::Tagged(Box::new(TaggedValue {
+            tag: tag(ty.value()).ok_or_else(|| entry_error(entry, "YAML tags can't be empty."))?,
+            value,
+        }))),
+        None => Ok(value),
+    }
+}
+
+fn tag(name: &str) -> Option<Tag> {
+    (!name.is_empty()).then(|| Tag::new(name))
+}
+
+/// The type annotation for a tag, without the leading `!` that `Tag`'s
+/// `Display` always adds.
+fn annotation(tag: &Tag) -> String {
+    tag.to_string()[1..].into()
+}
+
+fn mapping_nodes(mapping: &Mapping) -> Result<Vec<KdlNode>, KdlConversionError> {
+    let mut nodes = Vec::new();
+    for (key, value) in mapping {
+        let name = match key {
+            Value::String(s) => s.clone(),
+            Value::Number(n) => n.to_string(),
+            Value::Bool(b) => b.to_string(),
+            Value::Null => "null".into(),
+            _ => {
+                return Err(error(
+                    "Only string, number, boolean, and null YAML keys can be node names.",
+                ))
+            }
+        };
+        nodes.extend(value_nodes(&name, value, true)?);
+    }
+    Ok(nodes)
+}
+
+/// Writes a key's value as nodes. Sequences of several mappings are written
+/// as several nodes when `repeat` is set, which it isn't for `-` elements
+/// or tagged sequences.
+fn value_nodes(
+    name: &str,
+    value: &Value,
+    repeat: bool,
+) -> Result<Vec<KdlNode>, KdlConversionError> {
+    let mut node = KdlNode::new(name);
+    match value {
+        Value::Tagged(tagged) if !is_scalar(&tagged.value) => {
+            if let Value::Tagged(_) = tagged.value {
+                return Err(error("Values with several YAML tags can't be converted."));
+            }
+            let mut nodes = value_nodes(name, &tagged.value, false)?;
+            nodes[0].set_ty(annotation(&tagged.tag));
+            return Ok(nodes);
+        }
+        Value::Mapping(mapping) => {
+            node.ensure_children()
+                .nodes_mut()
+                .extend(mapping_nodes(mapping)?);
+        }
+        Value::Sequence(seq) if seq.len() != 1 && seq.iter().all(is_scalar) => {
+            for value in seq {
+                node.push(scalar_entry(value)?);
+            }
+        }
+        Value::Sequence(seq) if repeat && seq.len() > 1 && seq.iter().all(Value::is_mapping) => {
+            let mut nodes = Vec::new();
+            for value in seq {
+                nodes.extend(value_nodes(name, value, false)?);
+            }
+            return Ok(nodes);
+        }
+        Value::Sequence(seq) => {
+            let children = node.ensure_children().nodes_mut();
+            for value in seq {
+                children.extend(value_nodes("-", value, false)?);
+            }
+        }
+        scalar => node.push(scalar_entry(scalar)?),
+    }
+    Ok(vec![node])
+}
+
+fn is_scalar(value: &Value) -> bool {
+    match value {
+        Value::Sequence(_) | Value::Mapping(_) => false,
+        Value::Tagged(tagged) => is_scalar(&tagged.value),
+        _ => true,
+    }
+}
+
+fn scalar_entry(value: &Value) -> Result<KdlEntry, KdlConversionError> {
+    Ok(match value {
+        Value::Null => KdlEntry::new(KdlValue::Null),
+        Value::Bool(b) => KdlEntry::new(*b),
+        Value::Number(n) => match (n.as_i64(), n.as_u64(), n.as_f64()) {
+            (Some(i), _, _) => KdlEntry::new(i128::from(i)),
+            (_, Some(u), _) => KdlEntry::new(i128::from(u)),
+            (_, _, Some(f)) => KdlEntry::new(f),
+            _ => unreachable!("YAML numbers are always one of these"),
+        },
+        Value::String(s) => KdlEntry::new(s.as_str()),
+        Value::Tagged(tagged) => {
+            if let Value::Tagged(_) = tagged.value {
+                return Err(error("Values with several YAML tags can't be converted."));
+            }
+            let mut entry = scalar_entry(&tagged.value)?;
+            entry.set_ty(annotation(&tagged.tag));
+            entry
+        }
+        Value::Sequence(_) | Value::Mapping(_) => unreachable!("only called on scalars"),
+    })
+}
+
+#[cfg(feature = "span")]
+fn node_error(node: &KdlNode, message: impl Into<String>) -> KdlConversionError {
+    KdlConversionError {
+        message: message.into(),
+        span: Some(node.span()),
+    }
+}
+
+#[cfg(not(feature = "span"))]
+fn node_error(_node: &KdlNode, message: impl Into<String>) -> KdlConversionError {
+    error(message)
+}
+
+#[cfg(feature = "span")]
+fn entry_error(entry: &KdlEntry, message: impl Into<String>) -> KdlConversionError {
+    KdlConversionError {
+        message: message.into(),
+        span: Some(entry.span()),
+    }
+}
+
+#[cfg(not(feature = "span"))]
+fn entry_error(_entry: &KdlEntry, message: impl Into<String>) -> KdlConversionError {
+    error(message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() -> miette::Result<()> {
+        let yaml = r#"
+            nothing: null
+            flag: true
+            big: 18446744073709551615
+            pi: 3.5
+            empty: []
+            empty_map: {}
+            one: [1]
+            nested: [[1], [2, 3], []]
+            mixed: [1, {a: 2}]
+            tagged: !Ref main
+            typed: !Point [1, 2]
+            items:
+              - name: a
+              - name: b
+                tags: !Set [x]
+            80: http
+        "#;
+        let value: Value = ::serde_yaml::from_str(yaml).unwrap();
+        let doc = from_value(&value)?;
+        assert_eq!(
+            doc.to_string(),
+            r#"nothing #null
+flag #true
+big 18446744073709551615
+pi 3.5
+empty
+empty_map {
+}
+one {
+    - 1
+}
+nested {
+    - {
+        - 1
+    }
+    - 2 3
+    -
+}
+mixed {
+    - 1
+    - {
+        a 2
+    }
+}
+tagged (Ref)main
+(Point)typed 1 2
+items {
+    name a
+}
+items {
+    name b
+    (Set)tags {
+        - x
+    }
+}
+"80" http
+"#
+        );
+        let mut expected = value.clone();
+        let mapping = expected.as_mapping_mut().unwrap();
+        let http = mapping.remove(Value::from(80)).unwrap();
+        mapping.insert("80".into(), http);
+        assert_eq!(to_value(&doc)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn anchors() -> miette::Result<()> {
+        let yaml = "base: &base\n  a: 1\nderived:\n  <<: *base\n  b: 2\n";
+        assert_eq!(
+            from_str(yaml)?.to_string(),
+            "base {\n    a 1\n}\nderived {\n    b 2\n    a 1\n}\n"
+        );
+
+        let err = from_str("a: [").unwrap_err();
+        assert!(err.span.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn errors() {
+        for (input, message, at) in [
+            (
+                "a 1 x=1",
+                "Nodes with properties or children can't also have arguments in YAML.",
+                "1",
+            ),
+            (
+                "a 1 { - 2; }",
+                "Nodes with `-` children can't also have arguments in YAML.",
+                "1",
+            ),
+            (
+                "a -170141183460469231731687303715884105728",
+                "YAML integers must fit in an i64 or a u64.",
+                "-170141183460469231731687303715884105728",
+            ),
+            (
+                "(T)a (U)1",
+                "A node with a type annotation can't also have a single annotated argument.",
+                "(T)a (U)1",
+            ),
+        ] {
+            let err = to_value(&input.parse().unwrap()).unwrap_err();
+            assert_eq!(err.message, message, "{input}");
+            #[cfg(feature = "span")]
+            {
+                let span = err.span.unwrap();
+                assert_eq!(&input[span.offset()..][..span.len()], at, "{input}");
+            }
+            #[cfg(not(feature = "span"))]
+            let _ = at;
+        }
+
+        let err = from_value(&Value::Sequence(vec![])).unwrap_err();
+        assert_eq!(
+            err.message,
+            "Only a YAML mapping can be converted to a KDL document."
+        );
+    }
+}
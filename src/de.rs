@@ -0,0 +1,1331 @@
+//! Reading any [`Deserialize`] type from a KDL document, with the `serde`
+//! feature.
+//!
+//! This reads the layout written by [`ser`](crate::ser), and is lenient
+//! about the ways KDL can say the same thing:
+//!
+//! * A document, or a node with properties or children, can be read as a
+//!   struct or map. Its properties and child nodes are the fields, so a
+//!   field can be written either as `port=8080` or as a `port 8080` child.
+//! * A node with a single argument, and nothing else, can be read as that
+//!   value.
+//! * A node's arguments, followed by its children, can be read as a
+//!   sequence, so both `ports 80 443` and `ports { - 80; - 443; }` work.
+//!   So can a document, with one element per node. Following the KDL
+//!   convention for arrays, a node whose children are all named `-`, and
+//!   which has no properties, is a sequence even when the target type
+//!   doesn't say, as with `serde_json::Value`.
+//! * Enum variants with data are read from a node annotated with the variant
+//!   name, as in `(Circle)shape 5`. Unit variants are read from strings.
+//! * `(base64)` strings can be read as bytes.
+//!
+//! Errors point at the node or entry that couldn't be read. To point at
+//! values yourself, after deserialization, wrap them in [`Spanned`].
+//!
+//! # Examples
+//!
+//! ```rust
+//! # use serde::Deserialize;
+//! #[derive(Deserialize, Debug, PartialEq)]
+//! struct Config {
+//!     name: String,
+//!     server: Server,
+//!     tags: Vec<String>,
+//!     contact: Option<String>,
+//! }
+//!
+//! #[derive(Deserialize, Debug, PartialEq)]
+//! struct Server {
+//!     host: String,
+//!     port: u16,
+//! }
+//!
+//! let config: Config = kdl::de::from_str(
+//!     r#"
+//!     name demo
+//!     server host=localhost {
+//!         port 8080
+//!     }
+//!     tags a b
+//!     "#,
+//! )
+//! .unwrap();
+//! assert_eq!(config.server.port, 8080);
+//! assert_eq!(config.tags, ["a", "b"]);
+//! assert_eq!(config.contact, None);
+//!
+//! let err = kdl::de::from_str::<Config>("name demo\nserver host=localhost port=99999\ntags")
+//!     .unwrap_err();
+//! assert_eq!(
+//!     err.diagnostics[0].to_string(),
+//!     "invalid value: integer `99999`, expected u16"
+//! );
+//! # #[cfg(feature = "span")]
+//! assert_eq!(err.diagnostics[0].span, (32, 10).into());
+//! ```
+
+use std::{fmt::Display, sync::Arc};
+
+use miette::SourceSpan;
+use serde::{
+    de::{
+        self, value::BorrowedStrDeserializer, DeserializeOwned, DeserializeSeed, Error as _,
+        IntoDeserializer, Visitor,
+    },
+    forward_to_deserialize_any, Deserialize,
+};
+
+use crate::{
+    types, KdlDiagnostic, KdlDocument, KdlEntry, KdlErrorKind, KdlNode, KdlParseFailure, KdlValue,
+};
+
+/// Parses `input` and deserializes it as a `T`. See the
+/// [module docs](self) for how nodes are mapped to values.
+///
+/// Syntax errors and values that don't fit `T` both come back as
+/// diagnostics pointing into `input`.
+pub fn from_str<T: DeserializeOwned>(input: &str) -> Result<T, KdlParseFailure> {
+    let doc: KdlDocument = input.parse()?;
+    T::deserialize(DocumentDeserializer(&doc))
+        .map_err(|err| err.into_failure(Arc::new(input.into())))
+}
+
+/// Deserializes `doc` as a `T`, borrowing strings from it where `T` allows.
+///
+/// The diagnostics in a failure point into `doc` as it's written out, which
+/// is also where its spans point, as long as it hasn't been modified since
+/// it was parsed.
+pub fn from_document<'de, T: Deserialize<'de>>(
+    doc: &'de KdlDocument,
+) -> Result<T, KdlParseFailure> {
+    T::deserialize(DocumentDeserializer(doc))
+        .map_err(|err| err.into_failure(Arc::new(doc.to_string())))
+}
+
+/// The error type used while deserializing, before it's turned into a
+/// [`KdlParseFailure`].
+#[derive(Debug)]
+struct Error {
+    message: String,
+    span: Option<SourceSpan>,
+}
+
+impl Error {
+    /// Points this error at `span`, unless something more specific was
+    /// already found.
+    fn at(mut self, span: Option<SourceSpan>) -> Self {
+        if self.span.is_none() {
+            self.span = span;
+        }
+        self
+    }
+
+    fn into_failure(self, input: Arc<String>) -> KdlParseFailure {
+        KdlParseFailure {
+            input: input.clone(),
+            diagnostics: vec![KdlDiagnostic {
+                input,
+                span: self.span.unwrap_or_else(|| (0, 0).into()),
+                label: None,
+                help: None,
+                severity: miette::Severity::Error,
+                kind: KdlErrorKind::Deserialize(self.message),
+            }],
+            diagnostics_truncated: false,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl de::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error {
+            message: msg.to_string(),
+            span: None,
+        }
+    }
+}
+
+#[cfg(feature = "span")]
+fn document_span(doc: &KdlDocument) -> Option<SourceSpan> {
+    Some(doc.span())
+}
+
+#[cfg(feature = "span")]
+fn node_span(node: &KdlNode) -> Option<SourceSpan> {
+    Some(node.span())
+}
+
+#[cfg(feature = "span")]
+fn entry_span(entry: &KdlEntry) -> Option<SourceSpan> {
+    Some(entry.span())
+}
+
+#[cfg(feature = "span")]
+fn name_span(node: &KdlNode) -> Option<SourceSpan> {
+    Some(node.name().span())
+}
+
+#[cfg(not(feature = "span"))]
+fn document_span(_doc: &KdlDocument) -> Option<SourceSpan> {
+    None
+}
+
+#[cfg(not(feature = "span"))]
+fn node_span(_node: &KdlNode) -> Option<SourceSpan> {
+    None
+}
+
+#[cfg(not(feature = "span"))]
+fn entry_span(_entry: &KdlEntry) -> Option<SourceSpan> {
+    None
+}
+
+#[cfg(not(feature = "span"))]
+fn name_span(_node: &KdlNode) -> Option<SourceSpan> {
+    None
+}
+
+/// A value along with where it was found in the source, for reporting
+/// problems that only show up after deserialization. Like
+/// [`KdlDiagnostic`]s from this module, the span points at the node or entry
+/// the value was read from, or at its name if it's used as a map key.
+///
+/// This can only be deserialized from KDL, and serializes as the value it
+/// wraps.
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde::Deserialize;
+/// use kdl::de::Spanned;
+///
+/// #[derive(Deserialize)]
+/// struct Server {
+///     port: Spanned<u16>,
+/// }
+///
+/// let input = "port 80";
+/// let server: Server = kdl::de::from_str(input).unwrap();
+/// assert_eq!(*server.port.get_ref(), 80);
+/// assert_eq!(&input[server.port.span().offset()..][..server.port.span().len()], "port 80");
+/// ```
+#[cfg(feature = "span")]
+#[derive(Debug, Clone, Copy)]
+pub struct Spanned<T> {
+    span: SourceSpan,
+    value: T,
+}
+
+#[cfg(feature = "span")]
+const SPANNED: &str = "$__kdl_private_Spanned";
+#[cfg(feature = "span")]
+const SPANNED_OFFSET: &str = "$__kdl_private_offset";
+#[cfg(feature = "span")]
+const SPANNED_LEN: &str = "$__kdl_private_len";
+#[cfg(feature = "span")]
+const SPANNED_VALUE: &str = "$__kdl_private_value";
+
+#[cfg(feature = "span")]
+impl<T> Spanned<T> {
+    /// Wraps `value`, found at `span`.
+    pub fn new(span: impl Into<SourceSpan>, value: T) -> Self {
+        Self {
+            span: span.into(),
+            value,
+        }
+    }
+
+    /// Where the value was found.
+    pub fn span(&self) -> SourceSpan {
+        self.span
+    }
+
+    /// Gets a reference to the value.
+    pub fn get_ref(&self) -> &T {
+        &self.value
+    }
+
+    /// Gets a mutable reference to the value.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+
+    /// Unwraps the value, dropping its span.
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+#[cfg(feature = "span")]
+impl<T: PartialEq> PartialEq for Spanned<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+#[cfg(feature = "span")]
+impl<T: Eq> Eq for Spanned<T> {}
+
+#[cfg(feature = "span")]
+impl<T: PartialOrd> PartialOrd for Spanned<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self.value.partial_cmp(&other.value)
+    }
+}
+
+#[cfg(feature = "span")]
+impl<T: Ord> Ord for Spanned<T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.value.cmp(&other.value)
+    }
+}
+
+#[cfg(feature = "span")]
+impl<T: std::hash::Hash> std::hash::Hash for Spanned<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.value.hash(state);
+    }
+}
+
+#[cfg(feature = "span")]
+impl std::borrow::Borrow<str> for Spanned<String> {
+    fn borrow(&self) -> &str {
+        &self.value
+    }
+}
+
+#[cfg(feature = "span")]
+impl<T: serde::Serialize> serde::Serialize for Spanned<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.value.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "span")]
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Spanned<T> {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct SpannedVisitor<T>(std::marker::PhantomData<T>);
+
+        impl<'de, T: Deserialize<'de>> Visitor<'de> for SpannedVisitor<T> {
+            type Value = Spanned<T>;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "a value from a KDL document")
+            }
+
+            fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Spanned<T>, A::Error> {
+                fn field<'de, A: de::MapAccess<'de>, V: Deserialize<'de>>(
+                    map: &mut A,
+                    name: &str,
+                ) -> Result<V, A::Error> {
+                    match map.next_key::<&str>()? {
+                        Some(key) if key == name => map.next_value(),
+                        _ => Err(A::Error::custom(
+                            "Spanned can only be deserialized from KDL",
+                        )),
+                    }
+                }
+                let offset: usize = field(&mut map, SPANNED_OFFSET)?;
+                let len: usize = field(&mut map, SPANNED_LEN)?;
+                let value = field(&mut map, SPANNED_VALUE)?;
+                Ok(Spanned::new((offset, len), value))
+            }
+        }
+
+        deserializer.deserialize_struct(
+            SPANNED,
+            &[SPANNED_OFFSET, SPANNED_LEN, SPANNED_VALUE],
+            SpannedVisitor(std::marker::PhantomData),
+        )
+    }
+}
+
+/// Hands a [`Spanned`] its span, then the value, read by `content`.
+#[cfg(feature = "span")]
+struct SpannedAccess<D> {
+    span: SourceSpan,
+    content: D,
+    field: usize,
+}
+
+#[cfg(feature = "span")]
+impl<D> SpannedAccess<D> {
+    fn new(span: SourceSpan, content: D) -> Self {
+        Self {
+            span,
+            content,
+            field: 0,
+        }
+    }
+}
+
+#[cfg(feature = "span")]
+impl<'de, D: de::Deserializer<'de, Error = Error> + Copy> de::MapAccess<'de> for SpannedAccess<D> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        let key = match self.field {
+            0 => SPANNED_OFFSET,
+            1 => SPANNED_LEN,
+            2 => SPANNED_VALUE,
+            _ => return Ok(None),
+        };
+        seed.deserialize(BorrowedStrDeserializer::new(key))
+            .map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        self.field += 1;
+        match self.field {
+            1 => seed.deserialize(self.span.offset().into_deserializer()),
+            2 => seed.deserialize(self.span.len().into_deserializer()),
+            _ => seed.deserialize(self.content),
+        }
+    }
+}
+
+/// Reads a whole document as a map of its nodes, or a sequence of them.
+#[derive(Clone, Copy)]
+struct DocumentDeserializer<'de>(&'de KdlDocument);
+
+impl<'de> de::Deserializer<'de> for DocumentDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let nodes = self.0.nodes();
+        if !nodes.is_empty() && nodes.iter().all(|node| node.name().value() == "-") {
+            self.deserialize_seq(visitor)
+        } else {
+            self.deserialize_map(visitor)
+        }
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor
+            .visit_seq(SeqAccess::new(&[], self.0.nodes()))
+            .map_err(|err| err.at(document_span(self.0)))
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor
+            .visit_map(MapAccess::new(&[], self.0.nodes()))
+            .map_err(|err| err.at(document_span(self.0)))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        #[cfg(feature = "span")]
+        if let (SPANNED, Some(span)) = (name, document_span(self.0)) {
+            return visitor.visit_map(SpannedAccess::new(span, self));
+        }
+        #[cfg(not(feature = "span"))]
+        let _ = name;
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct tuple tuple_struct enum
+        identifier ignored_any
+    }
+}
+
+/// Reads whatever a node holds: a single value, a sequence, or a map.
+#[derive(Clone, Copy)]
+struct NodeDeserializer<'de>(&'de KdlNode);
+
+impl<'de> NodeDeserializer<'de> {
+    fn children(&self) -> &'de [KdlNode] {
+        self.0
+            .children()
+            .map_or(&[][..], |children| children.nodes())
+    }
+
+    /// The node's only entry, if it's an argument and there are no children.
+    fn single_value(&self) -> Option<&'de KdlEntry> {
+        match self.0.entries() {
+            [entry] if entry.name().is_none() && self.children().is_empty() => Some(entry),
+            _ => None,
+        }
+    }
+
+    fn span(&self) -> Option<SourceSpan> {
+        node_span(self.0)
+    }
+}
+
+impl<'de> de::Deserializer<'de> for NodeDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let entries = self.0.entries();
+        let props = entries.iter().any(|entry| entry.name().is_some());
+        let children = self.children();
+        if !props && children.is_empty() {
+            match entries {
+                [] => visitor.visit_unit(),
+                [entry] => ValueDeserializer(entry).deserialize_any(visitor),
+                _ => self.deserialize_seq(visitor),
+            }
+        } else if !props && children.iter().all(|node| node.name().value() == "-") {
+            self.deserialize_seq(visitor)
+        } else {
+            self.deserialize_map(visitor)
+        }
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.single_value() {
+            Some(entry) => ValueDeserializer(entry).deserialize_bytes(visitor),
+            None => self.deserialize_seq(visitor),
+        }
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.single_value() {
+            Some(entry) if *entry.value() == KdlValue::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        if let Some(prop) = self.0.entries().iter().find(|entry| entry.name().is_some()) {
+            return Err(
+                Error::custom("unexpected property, expected a sequence").at(entry_span(prop))
+            );
+        }
+        visitor
+            .visit_seq(SeqAccess::new(self.0.entries(), self.children()))
+            .map_err(|err| err.at(self.span()))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        if let Some(arg) = self.0.entries().iter().find(|entry| entry.name().is_none()) {
+            return Err(Error::custom(
+                "unexpected argument, expected only properties and children",
+            )
+            .at(entry_span(arg)));
+        }
+        visitor
+            .visit_map(MapAccess::new(self.0.entries(), self.children()))
+            .map_err(|err| err.at(self.span()))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        #[cfg(feature = "span")]
+        if let (SPANNED, Some(span)) = (name, self.span()) {
+            return visitor.visit_map(SpannedAccess::new(span, self));
+        }
+        #[cfg(not(feature = "span"))]
+        let _ = name;
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match (self.0.ty(), self.single_value()) {
+            (Some(ty), _) => visitor
+                .visit_enum(EnumAccess {
+                    variant: ty.value(),
+                    content: self,
+                })
+                .map_err(|err| err.at(node_span(self.0))),
+            (None, Some(entry)) => {
+                ValueDeserializer(entry).deserialize_enum(name, variants, visitor)
+            }
+            (None, None) => Err(Error::custom(
+                "expected a variant name, or a node annotated with one",
+            )
+            .at(self.span())),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        unit unit_struct identifier ignored_any
+    }
+}
+
+/// Reads a single argument or property value.
+#[derive(Clone, Copy)]
+struct ValueDeserializer<'de>(&'de KdlEntry);
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let value: Result<V::Value, Error> = match self.0.value() {
+            KdlValue::String(string) => visitor.visit_borrowed_str(string),
+            KdlValue::Integer(int) => {
+                if let Ok(int) = i64::try_from(*int) {
+                    visitor.visit_i64(int)
+                } else if let Ok(int) = u64::try_from(*int) {
+                    visitor.visit_u64(int)
+                } else {
+                    visitor.visit_i128(*int)
+                }
+            }
+            KdlValue::Float(float) => visitor.visit_f64(*float),
+            KdlValue::Bool(bool) => visitor.visit_bool(*bool),
+            KdlValue::Null => visitor.visit_unit(),
+        };
+        value.map_err(|err| err.at(entry_span(self.0)))
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let value: Result<V::Value, Error> = match self.0.value() {
+            KdlValue::String(string) if self.0.ty().map(|ty| ty.value()) == Some("base64") => {
+                match types::decode_base64(string) {
+                    Some(bytes) => visitor.visit_byte_buf(bytes),
+                    None => Err(Error::invalid_value(de::Unexpected::Str(string), &"base64")),
+                }
+            }
+            KdlValue::String(string) => visitor.visit_borrowed_bytes(string.as_bytes()),
+            _ => return self.deserialize_any(visitor),
+        };
+        value.map_err(|err| err.at(entry_span(self.0)))
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0.value() {
+            KdlValue::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match (self.0.ty(), self.0.value()) {
+            (Some(ty), _) => visitor.visit_enum(EnumAccess {
+                variant: ty.value(),
+                content: self,
+            }),
+            (None, KdlValue::String(variant)) => {
+                visitor.visit_enum(BorrowedStrDeserializer::new(variant))
+            }
+            (None, _) => Err(Error::custom(
+                "expected a variant name, or a value annotated with one",
+            )),
+        }
+        .map_err(|err| err.at(entry_span(self.0)))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        #[cfg(feature = "span")]
+        if let (SPANNED, Some(span)) = (name, entry_span(self.0)) {
+            return visitor.visit_map(SpannedAccess::new(span, self));
+        }
+        #[cfg(not(feature = "span"))]
+        let _ = name;
+        self.deserialize_any(visitor)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        unit unit_struct seq tuple tuple_struct map identifier ignored_any
+    }
+}
+
+/// Reads node and property names, which can also stand for numbers and
+/// booleans in map keys.
+#[derive(Clone, Copy)]
+struct KeyDeserializer<'de> {
+    key: &'de str,
+    #[cfg_attr(not(feature = "span"), allow(dead_code))]
+    span: Option<SourceSpan>,
+}
+
+macro_rules! parse_key {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                match self.key.parse() {
+                    Ok(key) => visitor.$visit(key),
+                    Err(_) => self.deserialize_any(visitor),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for KeyDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_borrowed_str(self.key)
+    }
+
+    parse_key! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_enum(BorrowedStrDeserializer::new(self.key))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        #[cfg(feature = "span")]
+        if let (SPANNED, Some(span)) = (name, self.span) {
+            return visitor.visit_map(SpannedAccess::new(span, self));
+        }
+        #[cfg(not(feature = "span"))]
+        let _ = name;
+        self.deserialize_any(visitor)
+    }
+
+    forward_to_deserialize_any! {
+        char str string bytes byte_buf option unit unit_struct seq tuple
+        tuple_struct map identifier ignored_any
+    }
+}
+
+/// A node's arguments, followed by its children.
+struct SeqAccess<'de> {
+    args: std::slice::Iter<'de, KdlEntry>,
+    nodes: std::slice::Iter<'de, KdlNode>,
+}
+
+impl<'de> SeqAccess<'de> {
+    fn new(entries: &'de [KdlEntry], nodes: &'de [KdlNode]) -> Self {
+        Self {
+            args: entries.iter(),
+            nodes: nodes.iter(),
+        }
+    }
+}
+
+impl<'de> de::SeqAccess<'de> for SeqAccess<'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        if let Some(arg) = self.args.next() {
+            return seed
+                .deserialize(ValueDeserializer(arg))
+                .map(Some)
+                .map_err(|err| err.at(entry_span(arg)));
+        }
+        match self.nodes.next() {
+            Some(node) => seed
+                .deserialize(NodeDeserializer(node))
+                .map(Some)
+                .map_err(|err| err.at(node_span(node))),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.args.len() + self.nodes.len())
+    }
+}
+
+/// A node's properties, followed by its children.
+struct MapAccess<'de> {
+    props: std::slice::Iter<'de, KdlEntry>,
+    nodes: std::slice::Iter<'de, KdlNode>,
+    value: Option<Field<'de>>,
+}
+
+enum Field<'de> {
+    Prop(&'de KdlEntry),
+    Node(&'de KdlNode),
+}
+
+impl<'de> MapAccess<'de> {
+    fn new(entries: &'de [KdlEntry], nodes: &'de [KdlNode]) -> Self {
+        Self {
+            props: entries.iter(),
+            nodes: nodes.iter(),
+            value: None,
+        }
+    }
+}
+
+impl<'de> de::MapAccess<'de> for MapAccess<'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        let (key, span, field) = if let Some(prop) = self.props.next() {
+            let name = prop.name().expect("Maps only have properties.");
+            #[cfg(feature = "span")]
+            let span = Some(name.span());
+            #[cfg(not(feature = "span"))]
+            let span = None;
+            (name.value(), span, Field::Prop(prop))
+        } else if let Some(node) = self.nodes.next() {
+            (node.name().value(), name_span(node), Field::Node(node))
+        } else {
+            return Ok(None);
+        };
+        self.value = Some(field);
+        seed.deserialize(KeyDeserializer { key, span })
+            .map(Some)
+            .map_err(|err| err.at(span))
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        match self
+            .value
+            .take()
+            .expect("next_value_seed is always called after next_key_seed")
+        {
+            Field::Prop(prop) => seed
+                .deserialize(ValueDeserializer(prop))
+                .map_err(|err| err.at(entry_span(prop))),
+            Field::Node(node) => seed
+                .deserialize(NodeDeserializer(node))
+                .map_err(|err| err.at(node_span(node))),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.props.len() + self.nodes.len())
+    }
+}
+
+/// An enum variant named by a type annotation, with the annotated node or
+/// value as its content.
+struct EnumAccess<'de, D> {
+    variant: &'de str,
+    content: D,
+}
+
+impl<'de, D> de::EnumAccess<'de> for EnumAccess<'de, D>
+where
+    D: de::VariantAccess<'de, Error = Error>,
+{
+    type Error = Error;
+    type Variant = D;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, D), Error> {
+        let variant = seed.deserialize(self.variant.into_deserializer())?;
+        Ok((variant, self.content))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for NodeDeserializer<'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_map(self, visitor)
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for ValueDeserializer<'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, _visitor: V) -> Result<V::Value, Error> {
+        Err(Error::invalid_type(
+            de::Unexpected::NewtypeVariant,
+            &"tuple variant",
+        ))
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Error> {
+        Err(Error::invalid_type(
+            de::Unexpected::NewtypeVariant,
+            &"struct variant",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Package {
+        name: String,
+        version: (u8, u8, u8),
+        #[serde(with = "bytes")]
+        checksum: Vec<u8>,
+        license: Option<String>,
+        authors: Vec<Author>,
+        build: Build,
+        features: BTreeMap<String, Vec<String>>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Author {
+        name: String,
+        email: Option<String>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Build {
+        None,
+        Script(String),
+        Steps(Vec<String>),
+        Command { program: String, args: Vec<String> },
+    }
+
+    mod bytes {
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub(super) fn serialize<S: Serializer>(
+            bytes: &[u8],
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            serializer.serialize_bytes(bytes)
+        }
+
+        pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Vec<u8>, D::Error> {
+            struct Bytes(Vec<u8>);
+            impl<'de> Deserialize<'de> for Bytes {
+                fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                    struct Visitor;
+                    impl<'de> serde::de::Visitor<'de> for Visitor {
+                        type Value = Bytes;
+                        fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                            write!(f, "bytes")
+                        }
+                        fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Bytes, E> {
+                            Ok(Bytes(v))
+                        }
+                        fn visit_bytes<E>(self, v: &[u8]) -> Result<Bytes, E> {
+                            Ok(Bytes(v.into()))
+                        }
+                    }
+                    deserializer.deserialize_bytes(Visitor)
+                }
+            }
+            Ok(Bytes::deserialize(deserializer)?.0)
+        }
+    }
+
+    fn package(build: Build) -> Package {
+        Package {
+            name: "kdl".into(),
+            version: (6, 0, 0),
+            checksum: vec![0xfb, 0xff],
+            license: None,
+            authors: vec![
+                Author {
+                    name: "Kat".into(),
+                    email: Some("kat@example.com".into()),
+                },
+                Author {
+                    name: "Someone Else".into(),
+                    email: None,
+                },
+            ],
+            build,
+            features: [
+                ("default".into(), vec!["span".into()]),
+                ("span".into(), vec![]),
+            ]
+            .into_iter()
+            .collect(),
+        }
+    }
+
+    #[test]
+    fn round_trips() -> miette::Result<()> {
+        for build in [
+            Build::None,
+            Build::Script("build.rs".into()),
+            Build::Steps(vec!["a".into(), "b".into()]),
+            Build::Command {
+                program: "make".into(),
+                args: vec!["all".into()],
+            },
+        ] {
+            let package = package(build);
+            let text = crate::ser::to_string(&package).unwrap();
+            assert_eq!(from_str::<Package>(&text)?, package, "{text}");
+        }
+
+        assert_eq!(from_str::<Vec<i32>>("- 1\n- 2")?, [1, 2]);
+        assert_eq!(
+            from_str::<BTreeMap<i32, String>>("\"1\" one\n\"2\" two")?,
+            BTreeMap::from([(1, "one".into()), (2, "two".into())])
+        );
+        assert_eq!(from_str::<BTreeMap<String, ()>>("")?, BTreeMap::new());
+        Ok(())
+    }
+
+    #[test]
+    fn lenient_layouts() -> miette::Result<()> {
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Server {
+            host: String,
+            port: u16,
+            ports: Vec<u16>,
+            tls: Option<bool>,
+        }
+
+        let expected = Server {
+            host: "localhost".into(),
+            port: 80,
+            ports: vec![80, 443],
+            tls: None,
+        };
+        for input in [
+            "host localhost\nport 80\nports 80 443",
+            "host localhost; port 80; ports { - 80; - 443; }",
+            "host localhost; port 80; ports 80 { - 443; }",
+            "host localhost; port 80; ports 80 443; tls #null",
+        ] {
+            assert_eq!(from_str::<Server>(input)?, expected, "{input}");
+        }
+
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Wrapper {
+            server: Server,
+        }
+        assert_eq!(
+            from_str::<Wrapper>("server host=localhost port=80 { ports 80 443; }")?.server,
+            expected
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn dash_children() -> miette::Result<()> {
+        #[derive(Deserialize, Debug, PartialEq)]
+        #[serde(untagged)]
+        enum Any {
+            Int(i64),
+            Str(String),
+            List(Vec<Any>),
+            Map(BTreeMap<String, Any>),
+        }
+
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Server {
+            host: String,
+            port: u16,
+        }
+
+        let servers: BTreeMap<String, Vec<Server>> = from_str(
+            r#"
+            servers {
+                - host=a port=1
+                - {
+                    host b
+                    port 2
+                }
+            }
+            "#,
+        )?;
+        assert_eq!(
+            servers["servers"],
+            [
+                Server {
+                    host: "a".into(),
+                    port: 1
+                },
+                Server {
+                    host: "b".into(),
+                    port: 2
+                },
+            ]
+        );
+
+        let any: BTreeMap<String, Any> = from_str(
+            "list 1 { - 2; - x { - 3; }; }
+map a=1 { - 2; }",
+        )?;
+        assert_eq!(
+            any["list"],
+            Any::List(vec![
+                Any::Int(1),
+                Any::Int(2),
+                Any::List(vec![Any::Str("x".into()), Any::Int(3)]),
+            ])
+        );
+        assert!(matches!(any["map"], Any::Map(_)));
+        Ok(())
+    }
+
+    #[test]
+    fn borrowing() -> miette::Result<()> {
+        #[derive(Deserialize)]
+        struct Borrowed<'a> {
+            name: &'a str,
+            #[serde(borrow)]
+            tags: Vec<&'a str>,
+        }
+        let doc: KdlDocument = "name kdl; tags a b".parse()?;
+        let borrowed: Borrowed<'_> = from_document(&doc)?;
+        assert_eq!(borrowed.name, "kdl");
+        assert_eq!(borrowed.tags, ["a", "b"]);
+        Ok(())
+    }
+
+    #[cfg(feature = "span")]
+    #[test]
+    fn spanned() -> miette::Result<()> {
+        #[derive(Deserialize)]
+        struct Config {
+            name: Spanned<String>,
+            server: Spanned<Server>,
+            tags: Vec<Spanned<String>>,
+            limits: BTreeMap<Spanned<String>, Spanned<u32>>,
+            missing: Option<Spanned<bool>>,
+        }
+
+        #[derive(Deserialize)]
+        struct Server {
+            port: Spanned<u16>,
+        }
+
+        let input = "name kdl\nserver port=80\ntags a \"b\"\nlimits { cpu 2; mem 4; }";
+        let at = |span: SourceSpan| &input[span.offset()..][..span.len()];
+
+        let whole: Spanned<Config> = from_str(input)?;
+        assert_eq!(at(whole.span()), input);
+        let config = whole.into_inner();
+        assert_eq!(config.name, Spanned::new((0, 0), "kdl".to_string()));
+        assert_eq!(at(config.name.span()), "name kdl");
+        assert_eq!(at(config.server.span()), "server port=80");
+        assert_eq!(at(config.server.get_ref().port.span()), "port=80");
+        let tags: Vec<_> = config.tags.iter().map(|tag| at(tag.span())).collect();
+        assert_eq!(tags, ["a", "\"b\""]);
+        let limits: Vec<_> = config
+            .limits
+            .iter()
+            .map(|(key, value)| (at(key.span()), *value.get_ref(), at(value.span())))
+            .collect();
+        assert_eq!(limits, [("cpu", 2, "cpu 2"), ("mem", 4, "mem 4")]);
+        assert_eq!(
+            config.limits.get("cpu").map(|limit| *limit.get_ref()),
+            Some(2)
+        );
+        assert!(config.missing.is_none());
+
+        assert_eq!(
+            crate::ser::to_string(&Spanned::new((0, 0), BTreeMap::from([("a", 1)]))).unwrap(),
+            "a 1\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn errors() {
+        #[derive(Deserialize, Debug)]
+        #[serde(deny_unknown_fields)]
+        #[allow(dead_code)]
+        struct Server {
+            host: String,
+            port: u16,
+            kind: Build,
+        }
+
+        for (input, message, span) in [
+            (
+                "host a; port 99999; kind None",
+                "invalid value: integer `99999`, expected u16",
+                "99999",
+            ),
+            (
+                "host a; port 1; kind None; extra 1",
+                "unknown field `extra`, expected one of `host`, `port`, `kind`",
+                "extra",
+            ),
+            ("host a; port 1", "missing field `kind`", "host a; port 1"),
+            (
+                "host a; port 1; kind Other",
+                "unknown variant `Other`, expected one of `None`, `Script`, `Steps`, `Command`",
+                "Other",
+            ),
+            (
+                "host a; port 1; kind { - 1; }",
+                "expected a variant name, or a node annotated with one",
+                "kind { - 1; }",
+            ),
+            (
+                "host a; port 1; kind 1 2",
+                "expected a variant name, or a node annotated with one",
+                "kind 1 2",
+            ),
+            (
+                "host a; port x=1; kind None",
+                "invalid type: map, expected u16",
+                "port x=1",
+            ),
+            (
+                "host a b; port 1; kind None",
+                "invalid type: sequence, expected a string",
+                "host a b",
+            ),
+            (
+                "host a; port 1; (Command)kind 1 program=make",
+                "unexpected argument, expected only properties and children",
+                "1",
+            ),
+            (
+                "host a; port 1; (Steps)kind a=1",
+                "unexpected property, expected a sequence",
+                "a=1",
+            ),
+        ] {
+            let err = from_str::<Server>(input).unwrap_err();
+            assert_eq!(err.diagnostics.len(), 1, "{input}");
+            let diagnostic = &err.diagnostics[0];
+            assert_eq!(diagnostic.to_string(), message, "{input}");
+            assert!(matches!(diagnostic.kind, KdlErrorKind::Deserialize(_)));
+            #[cfg(feature = "span")]
+            assert_eq!(
+                &input[diagnostic.span.offset()..][..diagnostic.span.len()],
+                span,
+                "{input}"
+            );
+            #[cfg(not(feature = "span"))]
+            let _ = span;
+        }
+
+        assert!(from_str::<Server>("host \"unclosed").is_err());
+    }
+}
@@ -0,0 +1,644 @@
+use crate::{KdlConversionError, KdlDocument, KdlEntry, KdlNode, KdlValue};
+
+/// A type that can be read directly from a [`KdlNode`]: its arguments,
+/// properties, and children.
+///
+/// With the `derive` feature, this can be derived for structs with named
+/// fields. Each field says where it comes from with a `#[kdl(...)]`
+/// attribute:
+///
+/// * `argument`: the next argument, read with [`KdlDecodeScalar`].
+/// * `arguments`: all the remaining arguments, as a `Vec`.
+/// * `property`: the property with the field's name.
+/// * `child`: the child node with the field's name, read with
+///   [`KdlDecode`].
+/// * `children`: all the remaining child nodes, as a `Vec`. With a `name`,
+///   only the children with that name.
+/// * `node_name`: the node's own name, as a `String`.
+///
+/// Underscores in field names become dashes, so `max_size` is read from
+/// `max-size`. `name = "..."` picks a different name. An `Option` field may
+/// be missing, and so may a field marked `default`, which then gets its
+/// [`Default`] value. Anything in the node that isn't read by some field is
+/// an error.
+///
+/// The derived implementation uses [`KdlNodeDecoder`], which can also be used
+/// to implement this by hand:
+///
+/// ```rust
+/// use kdl::{KdlDecode, KdlConversionError, KdlDocument, KdlNode, KdlNodeDecoder};
+///
+/// // With the `derive` feature, this is the same as
+/// //
+/// // #[derive(KdlDecode)]
+/// // struct Server {
+/// //     #[kdl(argument)]
+/// //     host: String,
+/// //     #[kdl(property)]
+/// //     port: Option<u16>,
+/// //     #[kdl(children, name = "route")]
+/// //     routes: Vec<Route>,
+/// // }
+/// #[derive(Debug)]
+/// struct Server {
+///     host: String,
+///     port: Option<u16>,
+///     routes: Vec<Route>,
+/// }
+///
+/// impl KdlDecode for Server {
+///     fn decode_node(node: &KdlNode) -> Result<Self, KdlConversionError> {
+///         let mut decoder = KdlNodeDecoder::new(node);
+///         let host = decoder.argument("host")?;
+///         let port = decoder.optional_property("port")?;
+///         let routes = decoder.children(Some("route"))?;
+///         decoder.finish()?;
+///         Ok(Server { host, port, routes })
+///     }
+/// }
+///
+/// #[derive(Debug)]
+/// struct Route(String);
+///
+/// impl KdlDecode for Route {
+///     fn decode_node(node: &KdlNode) -> Result<Self, KdlConversionError> {
+///         let mut decoder = KdlNodeDecoder::new(node);
+///         let path = decoder.argument("path")?;
+///         decoder.finish()?;
+///         Ok(Route(path))
+///     }
+/// }
+///
+/// let doc: KdlDocument = r#"
+/// server localhost port=8080 {
+///     route "/"
+///     route "/api"
+/// }
+/// "#.parse()?;
+/// let server = Server::decode_node(doc.get("server").unwrap())?;
+/// assert_eq!(server.host, "localhost");
+/// assert_eq!(server.port, Some(8080));
+/// assert_eq!(server.routes.len(), 2);
+///
+/// let err = Server::decode_node(&"server localhost oops".parse()?).unwrap_err();
+/// assert_eq!(err.to_string(), "Unexpected argument.");
+/// # Ok::<(), miette::Report>(())
+/// ```
+pub trait KdlDecode: Sized {
+    /// Reads a value from `node`.
+    fn decode_node(node: &KdlNode) -> Result<Self, KdlConversionError>;
+
+    /// Reads a value from a whole document, as if it were the children of a
+    /// node with no entries.
+    fn decode_document(doc: &KdlDocument) -> Result<Self, KdlConversionError> {
+        let mut node = KdlNode::new("-");
+        #[cfg(feature = "span")]
+        node.set_span(doc.span());
+        node.set_children(doc.clone());
+        Self::decode_node(&node)
+    }
+}
+
+/// A type that can be written directly as a [`KdlNode`]. The counterpart to
+/// [`KdlDecode`], which describes how to derive it.
+pub trait KdlEncode {
+    /// Writes this value as a node called `name`, unless it has a field for
+    /// its own node name.
+    fn encode_node(&self, name: &str) -> KdlNode;
+
+    /// Writes this value as a whole document, made of the children
+    /// [`KdlEncode::encode_node`] would write. The result is autoformatted.
+    fn encode_document(&self) -> KdlDocument {
+        let mut doc = self
+            .encode_node("-")
+            .children()
+            .cloned()
+            .unwrap_or_default();
+        doc.autoformat();
+        doc
+    }
+}
+
+/// A type that can be read from a single argument or property value.
+pub trait KdlDecodeScalar: Sized {
+    /// Reads a value from `entry`.
+    fn decode_entry(entry: &KdlEntry) -> Result<Self, KdlConversionError>;
+}
+
+/// A type that can be written as a single argument or property value.
+pub trait KdlEncodeScalar {
+    /// Writes this value.
+    fn encode_value(&self) -> KdlValue;
+}
+
+impl KdlDecode for KdlNode {
+    fn decode_node(node: &KdlNode) -> Result<Self, KdlConversionError> {
+        Ok(node.clone())
+    }
+}
+
+/// Nodes keep their own names.
+impl KdlEncode for KdlNode {
+    fn encode_node(&self, _name: &str) -> KdlNode {
+        self.clone()
+    }
+}
+
+impl KdlDecodeScalar for KdlValue {
+    fn decode_entry(entry: &KdlEntry) -> Result<Self, KdlConversionError> {
+        Ok(entry.value().clone())
+    }
+}
+
+impl KdlEncodeScalar for KdlValue {
+    fn encode_value(&self) -> KdlValue {
+        self.clone()
+    }
+}
+
+impl KdlDecodeScalar for String {
+    fn decode_entry(entry: &KdlEntry) -> Result<Self, KdlConversionError> {
+        match entry.value() {
+            KdlValue::String(string) => Ok(string.clone()),
+            _ => Err(entry_error(entry, "Expected a string.")),
+        }
+    }
+}
+
+impl KdlEncodeScalar for String {
+    fn encode_value(&self) -> KdlValue {
+        self.as_str().into()
+    }
+}
+
+impl KdlEncodeScalar for str {
+    fn encode_value(&self) -> KdlValue {
+        self.into()
+    }
+}
+
+impl KdlDecodeScalar for bool {
+    fn decode_entry(entry: &KdlEntry) -> Result<Self, KdlConversionError> {
+        entry
+            .value()
+            .as_bool()
+            .ok_or_else(|| entry_error(entry, "Expected a boolean."))
+    }
+}
+
+impl KdlEncodeScalar for bool {
+    fn encode_value(&self) -> KdlValue {
+        (*self).into()
+    }
+}
+
+macro_rules! impl_integer {
+    ($($ty:ident),*) => {
+        $(
+            impl KdlDecodeScalar for $ty {
+                fn decode_entry(entry: &KdlEntry) -> Result<Self, KdlConversionError> {
+                    let int = entry.value().as_integer().ok_or_else(|| {
+                        entry_error(entry, concat!("Expected an integer (", stringify!($ty), ")."))
+                    })?;
+                    $ty::try_from(int).map_err(|_| {
+                        entry_error(entry, concat!("Integer is out of range for ", stringify!($ty), "."))
+                    })
+                }
+            }
+
+            impl KdlEncodeScalar for $ty {
+                fn encode_value(&self) -> KdlValue {
+                    KdlValue::Integer((*self).into())
+                }
+            }
+        )*
+    };
+}
+
+impl_integer!(i8, i16, i32, i64, i128, u8, u16, u32, u64);
+
+impl KdlDecodeScalar for f64 {
+    fn decode_entry(entry: &KdlEntry) -> Result<Self, KdlConversionError> {
+        match entry.value() {
+            KdlValue::Float(float) => Ok(*float),
+            KdlValue::Integer(int) => Ok(*int as f64),
+            _ => Err(entry_error(entry, "Expected a number.")),
+        }
+    }
+}
+
+impl KdlEncodeScalar for f64 {
+    fn encode_value(&self) -> KdlValue {
+        (*self).into()
+    }
+}
+
+impl KdlDecodeScalar for f32 {
+    fn decode_entry(entry: &KdlEntry) -> Result<Self, KdlConversionError> {
+        f64::decode_entry(entry).map(|float| float as f32)
+    }
+}
+
+impl KdlEncodeScalar for f32 {
+    fn encode_value(&self) -> KdlValue {
+        f64::from(*self).into()
+    }
+}
+
+#[cfg(feature = "span")]
+fn node_error(node: &KdlNode, message: String) -> KdlConversionError {
+    KdlConversionError {
+        message,
+        span: Some(node.span()),
+    }
+}
+
+#[cfg(not(feature = "span"))]
+fn node_error(_node: &KdlNode, message: String) -> KdlConversionError {
+    KdlConversionError {
+        message,
+        span: None,
+    }
+}
+
+#[cfg(feature = "span")]
+fn entry_error(entry: &KdlEntry, message: impl Into<String>) -> KdlConversionError {
+    KdlConversionError {
+        message: message.into(),
+        span: Some(entry.span()),
+    }
+}
+
+#[cfg(not(feature = "span"))]
+fn entry_error(_entry: &KdlEntry, message: impl Into<String>) -> KdlConversionError {
+    KdlConversionError {
+        message: message.into(),
+        span: None,
+    }
+}
+
+/// Reads the parts of a [`KdlNode`] one at a time, keeping track of what's
+/// been read so [`KdlNodeDecoder::finish`] can reject whatever is left.
+/// This is what derived [`KdlDecode`] implementations use.
+#[derive(Debug)]
+pub struct KdlNodeDecoder<'a> {
+    node: &'a KdlNode,
+    args: std::slice::Iter<'a, KdlEntry>,
+    used_props: Vec<&'a str>,
+    used_children: Vec<bool>,
+}
+
+impl<'a> KdlNodeDecoder<'a> {
+    /// Starts reading `node`.
+    pub fn new(node: &'a KdlNode) -> Self {
+        Self {
+            node,
+            args: node.entries().iter(),
+            used_props: Vec::new(),
+            used_children: vec![false; node.children().map_or(0, |doc| doc.nodes().len())],
+        }
+    }
+
+    /// The node's name.
+    pub fn node_name(&self) -> String {
+        self.node.name().value().to_string()
+    }
+
+    /// Reads the next argument, which `field` names in the error if it's
+    /// missing.
+    pub fn argument<T: KdlDecodeScalar>(&mut self, field: &str) -> Result<T, KdlConversionError> {
+        self.optional_argument()?
+            .ok_or_else(|| node_error(self.node, format!("Missing argument for `{field}`.")))
+    }
+
+    /// Reads the next argument, if there is one.
+    pub fn optional_argument<T: KdlDecodeScalar>(
+        &mut self,
+    ) -> Result<Option<T>, KdlConversionError> {
+        match self.args.find(|entry| entry.name().is_none()) {
+            Some(entry) => T::decode_entry(entry).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads all the remaining arguments.
+    pub fn arguments<T: KdlDecodeScalar>(&mut self) -> Result<Vec<T>, KdlConversionError> {
+        let mut args = Vec::new();
+        while let Some(arg) = self.optional_argument()? {
+            args.push(arg);
+        }
+        Ok(args)
+    }
+
+    /// Reads the property called `name`.
+    pub fn property<T: KdlDecodeScalar>(&mut self, name: &str) -> Result<T, KdlConversionError> {
+        self.optional_property(name)?
+            .ok_or_else(|| node_error(self.node, format!("Missing property `{name}`.")))
+    }
+
+    /// Reads the property called `name`, if there is one. If it's there more
+    /// than once, the last one wins.
+    pub fn optional_property<T: KdlDecodeScalar>(
+        &mut self,
+        name: &str,
+    ) -> Result<Option<T>, KdlConversionError> {
+        let node = self.node;
+        match node
+            .entries()
+            .iter()
+            .rev()
+            .find(|entry| entry.name().map_or(false, |key| key.value() == name))
+        {
+            Some(entry) => {
+                self.used_props
+                    .push(entry.name().expect("just matched").value());
+                T::decode_entry(entry).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Reads the first unread child node called `name`.
+    pub fn child<T: KdlDecode>(&mut self, name: &str) -> Result<T, KdlConversionError> {
+        self.optional_child(name)?
+            .ok_or_else(|| node_error(self.node, format!("Missing child node `{name}`.")))
+    }
+
+    /// Reads the first unread child node called `name`, if there is one.
+    pub fn optional_child<T: KdlDecode>(
+        &mut self,
+        name: &str,
+    ) -> Result<Option<T>, KdlConversionError> {
+        let node = self.node;
+        let found = node
+            .children_iter()
+            .enumerate()
+            .find(|(i, child)| !self.used_children[*i] && child.name().value() == name);
+        match found {
+            Some((i, child)) => {
+                self.used_children[i] = true;
+                T::decode_node(child).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Reads all the unread child nodes, or only the ones called `name`.
+    pub fn children<T: KdlDecode>(
+        &mut self,
+        name: Option<&str>,
+    ) -> Result<Vec<T>, KdlConversionError> {
+        let node = self.node;
+        let mut children = Vec::new();
+        for (i, child) in node.children_iter().enumerate() {
+            if !self.used_children[i] && name.map_or(true, |name| child.name().value() == name) {
+                self.used_children[i] = true;
+                children.push(T::decode_node(child)?);
+            }
+        }
+        Ok(children)
+    }
+
+    /// Checks that every entry and child node was read.
+    pub fn finish(mut self) -> Result<(), KdlConversionError> {
+        if let Some(arg) = self.args.find(|entry| entry.name().is_none()) {
+            return Err(entry_error(arg, "Unexpected argument."));
+        }
+        for entry in self.node.entries() {
+            if let Some(name) = entry.name() {
+                if !self.used_props.contains(&name.value()) {
+                    return Err(entry_error(
+                        entry,
+                        format!("Unexpected property `{}`.", name.value()),
+                    ));
+                }
+            }
+        }
+        for (i, child) in self.node.children_iter().enumerate() {
+            if !self.used_children[i] {
+                return Err(node_error(
+                    child,
+                    format!("Unexpected child node `{}`.", child.name().value()),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Builds a [`KdlNode`] one part at a time. This is what derived
+/// [`KdlEncode`] implementations use.
+#[derive(Debug)]
+pub struct KdlNodeEncoder {
+    node: KdlNode,
+}
+
+impl KdlNodeEncoder {
+    /// Starts a node called `name`.
+    pub fn new(name: &str) -> Self {
+        Self {
+            node: KdlNode::new(name),
+        }
+    }
+
+    /// Adds an argument.
+    pub fn argument<T: KdlEncodeScalar + ?Sized>(&mut self, value: &T) {
+        self.node.push(KdlEntry::new(value.encode_value()));
+    }
+
+    /// Adds an argument, unless `value` is `None`.
+    pub fn optional_argument<T: KdlEncodeScalar>(&mut self, value: &Option<T>) {
+        if let Some(value) = value {
+            self.argument(value);
+        }
+    }
+
+    /// Adds an argument for each value.
+    pub fn arguments<T: KdlEncodeScalar>(&mut self, values: &[T]) {
+        for value in values {
+            self.argument(value);
+        }
+    }
+
+    /// Adds a property.
+    pub fn property<T: KdlEncodeScalar + ?Sized>(&mut self, name: &str, value: &T) {
+        self.node
+            .push(KdlEntry::new_prop(name, value.encode_value()));
+    }
+
+    /// Adds a property, unless `value` is `None`.
+    pub fn optional_property<T: KdlEncodeScalar>(&mut self, name: &str, value: &Option<T>) {
+        if let Some(value) = value {
+            self.property(name, value);
+        }
+    }
+
+    /// Adds a child node called `name`.
+    pub fn child<T: KdlEncode + ?Sized>(&mut self, name: &str, value: &T) {
+        let child = value.encode_node(name);
+        self.node.ensure_children().nodes_mut().push(child);
+    }
+
+    /// Adds a child node called `name`, unless `value` is `None`.
+    pub fn optional_child<T: KdlEncode>(&mut self, name: &str, value: &Option<T>) {
+        if let Some(value) = value {
+            self.child(name, value);
+        }
+    }
+
+    /// Adds a child node for each value, called `name` or `-`.
+    pub fn children<T: KdlEncode>(&mut self, name: Option<&str>, values: &[T]) {
+        for value in values {
+            self.child(name.unwrap_or("-"), value);
+        }
+    }
+
+    /// Returns the finished node.
+    pub fn finish(self) -> KdlNode {
+        self.node
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Package {
+        name: String,
+        version: Option<String>,
+        authors: Vec<String>,
+        edition: u16,
+        publish: bool,
+        dependencies: Vec<KdlNode>,
+    }
+
+    impl KdlDecode for Package {
+        fn decode_node(node: &KdlNode) -> Result<Self, KdlConversionError> {
+            let mut decoder = KdlNodeDecoder::new(node);
+            let name = decoder.argument("name")?;
+            let version = decoder.optional_argument()?;
+            let authors = decoder.arguments()?;
+            let edition = decoder.property("edition")?;
+            let publish = decoder.optional_property("publish")?.unwrap_or(true);
+            let dependencies = decoder.children(Some("dep"))?;
+            decoder.finish()?;
+            Ok(Package {
+                name,
+                version,
+                authors,
+                edition,
+                publish,
+                dependencies,
+            })
+        }
+    }
+
+    impl KdlEncode for Package {
+        fn encode_node(&self, name: &str) -> KdlNode {
+            let mut encoder = KdlNodeEncoder::new(name);
+            encoder.argument(&self.name);
+            encoder.optional_argument(&self.version);
+            encoder.arguments(&self.authors);
+            encoder.property("edition", &self.edition);
+            encoder.property("publish", &self.publish);
+            encoder.children(Some("dep"), &self.dependencies);
+            encoder.finish()
+        }
+    }
+
+    #[test]
+    fn decode_and_encode() -> miette::Result<()> {
+        let doc: KdlDocument = r#"
+        package kdl "6.0.0" kat edition=2021 {
+            dep miette
+            dep winnow
+        }
+        "#
+        .parse()?;
+        let package = Package::decode_node(doc.get("package").unwrap())?;
+        assert_eq!(package.name, "kdl");
+        assert_eq!(package.version.as_deref(), Some("6.0.0"));
+        assert_eq!(package.authors, ["kat"]);
+        assert_eq!(package.edition, 2021);
+        assert!(package.publish);
+        assert_eq!(package.dependencies.len(), 2);
+
+        let mut node = package.encode_node("package");
+        node.autoformat();
+        assert_eq!(
+            node.to_string(),
+            "package kdl \"6.0.0\" kat edition=2021 publish=#true {\n    dep miette\n    dep winnow\n}\n"
+        );
+        assert!(Package::decode_node(&node)?
+            .encode_node("package")
+            .semantic_eq(&node));
+        Ok(())
+    }
+
+    #[test]
+    fn decode_errors() {
+        for (input, message, at) in [
+            ("package", "Missing argument for `name`.", "package"),
+            ("package 1 edition=1", "Expected a string.", "1"),
+            (
+                "package a edition=-1",
+                "Integer is out of range for u16.",
+                "edition=-1",
+            ),
+            (
+                "package a edition=1 extra=2",
+                "Unexpected property `extra`.",
+                "extra=2",
+            ),
+            ("package a", "Missing property `edition`.", "package a"),
+            (
+                "package a edition=1 { other; }",
+                "Unexpected child node `other`.",
+                "other",
+            ),
+        ] {
+            let node: KdlNode = input.parse().unwrap();
+            let err = Package::decode_node(&node).unwrap_err();
+            assert_eq!(err.message, message, "{input}");
+            #[cfg(feature = "span")]
+            {
+                let span = err.span.unwrap();
+                assert_eq!(&input[span.offset()..][..span.len()], at, "{input}");
+            }
+            #[cfg(not(feature = "span"))]
+            let _ = at;
+        }
+    }
+
+    #[test]
+    fn documents() -> miette::Result<()> {
+        struct Deps(Vec<String>);
+
+        impl KdlDecode for Deps {
+            fn decode_node(node: &KdlNode) -> Result<Self, KdlConversionError> {
+                let mut decoder = KdlNodeDecoder::new(node);
+                let deps = decoder.children::<KdlNode>(None)?;
+                decoder.finish()?;
+                Ok(Deps(
+                    deps.iter().map(|dep| dep.name().value().into()).collect(),
+                ))
+            }
+        }
+
+        impl KdlEncode for Deps {
+            fn encode_node(&self, name: &str) -> KdlNode {
+                let mut encoder = KdlNodeEncoder::new(name);
+                for dep in &self.0 {
+                    encoder.child(dep, &KdlNode::new(dep.as_str()));
+                }
+                encoder.finish()
+            }
+        }
+
+        let deps = Deps::decode_document(&"miette\nwinnow".parse()?)?;
+        assert_eq!(deps.0, ["miette", "winnow"]);
+        assert_eq!(deps.encode_document().to_string(), "miette\nwinnow\n");
+        Ok(())
+    }
+}
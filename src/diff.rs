@@ -0,0 +1,620 @@
+use std::{fmt::Write as _, ops::Range};
+
+use crate::{KdlDocument, KdlNode, KdlParseFailure};
+
+/// Parses two KDL documents and describes how to get from `old` to `new` as
+/// a unified diff against `old`'s text, with hunks covering only the nodes
+/// whose meaning changed.
+///
+/// Nodes are compared with [`KdlNode::semantic_eq`], so formatting and
+/// comment changes don't show up in the patch at all. A changed node whose
+/// name, type, and entries are the same as before only gets hunks for the
+/// children that changed within it. Hunks are always made of whole lines,
+/// so changed nodes that share a line with other nodes (or their parent's
+/// braces) pull those into the hunk as well.
+///
+/// The patch has no context lines, and applying it to `old` gives a document
+/// that's semantically equal to `new`, though not necessarily formatted the
+/// same. It's empty if the documents are semantically equal.
+///
+/// Returns the first parse failure, if either document fails to parse.
+///
+/// # Examples
+///
+/// ```rust
+/// let old = "package {\n    name kdl\n    version \"6.0.0\"\n}\n";
+/// let new = "package {\n  name   kdl\n  version \"6.1.0\"\n}\n";
+/// assert_eq!(
+///     kdl::text_patch(old, new)?,
+///     "--- old\n+++ new\n@@ -3,1 +3,1 @@\n-    version \"6.0.0\"\n+  version \"6.1.0\"\n",
+/// );
+/// # Ok::<(), kdl::KdlParseFailure>(())
+/// ```
+pub fn text_patch(old: &str, new: &str) -> Result<String, KdlParseFailure> {
+    let old_doc: KdlDocument = old.parse()?;
+    let new_doc: KdlDocument = new.parse()?;
+    let sides = Sides {
+        old: Source::new(old),
+        new: Source::new(new),
+    };
+    let hunks = diff_block(old_doc.nodes(), new_doc.nodes(), &sides, None)
+        .expect("top-level changes can always be expressed in whole lines");
+    let mut patch = String::new();
+    if hunks.is_empty() {
+        return Ok(patch);
+    }
+    patch.push_str("--- old\n+++ new\n");
+    for hunk in hunks {
+        let start = |range: &Range<usize>| {
+            if range.is_empty() {
+                range.start
+            } else {
+                range.start + 1
+            }
+        };
+        writeln!(
+            patch,
+            "@@ -{},{} +{},{} @@",
+            start(&hunk.old),
+            hunk.old.len(),
+            start(&hunk.new),
+            hunk.new.len()
+        )
+        .unwrap();
+        for line in &sides.old.lines[hunk.old.clone()] {
+            write_line(&mut patch, '-', line, true);
+        }
+        // The last line of `new` only stays unterminated if nothing from
+        // `old` follows it.
+        let at_eof = hunk.old.end == sides.old.lines.len();
+        for line in &sides.new.lines[hunk.new.clone()] {
+            write_line(&mut patch, '+', line, at_eof);
+        }
+    }
+    Ok(patch)
+}
+
+fn write_line(patch: &mut String, prefix: char, line: &str, keep_missing_newline: bool) {
+    patch.push(prefix);
+    patch.push_str(line);
+    if !line.ends_with('\n') {
+        if keep_missing_newline {
+            patch.push_str("\n\\ No newline at end of file\n");
+        } else {
+            patch.push('\n');
+        }
+    }
+}
+
+/// A replacement of some lines of the old text with some lines of the new
+/// text. Line numbers are 0-based.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Hunk {
+    old: Range<usize>,
+    new: Range<usize>,
+}
+
+#[derive(Debug)]
+struct Source<'a> {
+    text: &'a str,
+    lines: Vec<&'a str>,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> Source<'a> {
+    fn new(text: &'a str) -> Self {
+        let lines = text.split_inclusive('\n').collect::<Vec<_>>();
+        let line_starts = lines
+            .iter()
+            .scan(0, |offset, line| {
+                let start = *offset;
+                *offset += line.len();
+                Some(start)
+            })
+            .collect();
+        Self {
+            text,
+            lines,
+            line_starts,
+        }
+    }
+
+    fn line_of(&self, offset: usize) -> usize {
+        self.line_starts
+            .partition_point(|&start| start <= offset)
+            .saturating_sub(1)
+    }
+
+    /// First and last line of `node`, including its terminator.
+    fn node_lines(&self, node: &KdlNode) -> (usize, usize) {
+        let span = node.span();
+        let end = span.offset()
+            + span.len()
+            + node
+                .format()
+                .map_or(0, |fmt| fmt.before_terminator.len() + fmt.terminator.len());
+        (self.line_of(span.offset()), self.line_of(end.max(1) - 1))
+    }
+
+    /// Lines of the opening and closing braces of `node`'s children block.
+    fn brace_lines(&self, node: &KdlNode) -> (usize, usize) {
+        let span = node.span();
+        let header_end = node
+            .entries()
+            .last()
+            .map_or(node.name().span(), |entry| entry.span());
+        let open = header_end.offset()
+            + header_end.len()
+            + node.format().map_or(0, |fmt| fmt.before_children.len());
+        (
+            self.line_of(open),
+            self.line_of(span.offset() + span.len() - 1),
+        )
+    }
+}
+
+#[derive(Debug)]
+struct Sides<'a> {
+    old: Source<'a>,
+    new: Source<'a>,
+}
+
+/// Lines of a children block's braces, on either side. Hunks inside the
+/// block have to stay strictly between them.
+#[derive(Debug, Clone, Copy)]
+struct Bounds {
+    old: (usize, usize),
+    new: (usize, usize),
+}
+
+#[derive(Debug)]
+enum Kind {
+    Same,
+    Nested(Vec<Hunk>),
+    Changed,
+}
+
+/// A run of nodes on both sides, by index.
+#[derive(Debug)]
+struct Segment {
+    old: Range<usize>,
+    new: Range<usize>,
+    kind: Kind,
+}
+
+impl Segment {
+    fn merge(&mut self, other: Segment) {
+        self.old = self.old.start.min(other.old.start)..self.old.end.max(other.old.end);
+        self.new = self.new.start.min(other.new.start)..self.new.end.max(other.new.end);
+        self.kind = Kind::Changed;
+    }
+}
+
+fn same_header(a: &KdlNode, b: &KdlNode) -> bool {
+    a.ty().map(|ty| ty.value()) == b.ty().map(|ty| ty.value())
+        && a.name().value() == b.name().value()
+        && a.entries().len() == b.entries().len()
+        && a.entries()
+            .iter()
+            .zip(b.entries())
+            .all(|(a, b)| a.semantic_eq(b))
+}
+
+/// Pairs of indices of nodes that stay the same, in order.
+fn matching_nodes(old: &[KdlNode], new: &[KdlNode]) -> Vec<(usize, usize)> {
+    let prefix = old
+        .iter()
+        .zip(new)
+        .take_while(|(a, b)| a.semantic_eq(b))
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a.semantic_eq(b))
+        .count();
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    // Longest common subsequence of whatever's left in the middle.
+    let width = new_mid.len() + 1;
+    let mut table = vec![0usize; (old_mid.len() + 1) * width];
+    for i in (0..old_mid.len()).rev() {
+        for j in (0..new_mid.len()).rev() {
+            table[i * width + j] = if old_mid[i].semantic_eq(&new_mid[j]) {
+                table[(i + 1) * width + j + 1] + 1
+            } else {
+                table[(i + 1) * width + j].max(table[i * width + j + 1])
+            };
+        }
+    }
+    let mut pairs = (0..prefix).map(|i| (i, i)).collect::<Vec<_>>();
+    let (mut i, mut j) = (0, 0);
+    while i < old_mid.len() && j < new_mid.len() {
+        if old_mid[i].semantic_eq(&new_mid[j]) {
+            pairs.push((prefix + i, prefix + j));
+            i += 1;
+            j += 1;
+        } else if table[(i + 1) * width + j] >= table[i * width + j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs.extend((0..suffix).map(|k| (old.len() - suffix + k, new.len() - suffix + k)));
+    pairs
+}
+
+/// Diffs two lists of sibling nodes. Returns `None` if the changes can't be
+/// expressed in whole lines within `bounds`, in which case the caller should
+/// replace the whole parent node instead.
+fn diff_block(
+    old: &[KdlNode],
+    new: &[KdlNode],
+    sides: &Sides<'_>,
+    bounds: Option<Bounds>,
+) -> Option<Vec<Hunk>> {
+    let mut segments: Vec<Segment> = Vec::new();
+    let push_changed =
+        |segments: &mut Vec<Segment>, old: Range<usize>, new: Range<usize>| match segments
+            .last_mut()
+        {
+            Some(last) if matches!(last.kind, Kind::Changed) => last.merge(Segment {
+                old,
+                new,
+                kind: Kind::Changed,
+            }),
+            _ => segments.push(Segment {
+                old,
+                new,
+                kind: Kind::Changed,
+            }),
+        };
+    let (mut i, mut j) = (0, 0);
+    let matches = matching_nodes(old, new);
+    for (next_i, next_j) in matches
+        .into_iter()
+        .chain(std::iter::once((old.len(), new.len())))
+    {
+        if next_i - i == next_j - j {
+            // Pairs of nodes changed in place might only need a hunk deep
+            // inside their children.
+            for (o, n) in (i..next_i).zip(j..next_j) {
+                let nested = match (old[o].children(), new[n].children()) {
+                    (Some(old_children), Some(new_children)) if same_header(&old[o], &new[n]) => {
+                        let bounds = Bounds {
+                            old: sides.old.brace_lines(&old[o]),
+                            new: sides.new.brace_lines(&new[n]),
+                        };
+                        diff_block(
+                            old_children.nodes(),
+                            new_children.nodes(),
+                            sides,
+                            Some(bounds),
+                        )
+                    }
+                    _ => None,
+                };
+                match nested {
+                    Some(hunks) => segments.push(Segment {
+                        old: o..o + 1,
+                        new: n..n + 1,
+                        kind: Kind::Nested(hunks),
+                    }),
+                    None => push_changed(&mut segments, o..o + 1, n..n + 1),
+                }
+            }
+        } else {
+            push_changed(&mut segments, i..next_i, j..next_j);
+        }
+        if next_i < old.len() {
+            segments.push(Segment {
+                old: next_i..next_i + 1,
+                new: next_j..next_j + 1,
+                kind: Kind::Same,
+            });
+        }
+        i = next_i + 1;
+        j = next_j + 1;
+    }
+
+    // Grow changed segments until none of them share lines with their
+    // neighbors, or with their parent's braces.
+    let mut idx = 0;
+    while idx < segments.len() {
+        if !matches!(segments[idx].kind, Kind::Changed) {
+            idx += 1;
+            continue;
+        }
+        match conflict(&segments, idx, old, new, sides, bounds)? {
+            Some(neighbor) => {
+                let other = segments.remove(neighbor);
+                let merged = idx.min(neighbor);
+                segments[merged].merge(other);
+                idx = merged.saturating_sub(1);
+            }
+            None => idx += 1,
+        }
+    }
+
+    let mut hunks = Vec::new();
+    for (idx, segment) in segments.iter().enumerate() {
+        match &segment.kind {
+            Kind::Same => {}
+            Kind::Nested(nested) => hunks.extend(nested.iter().cloned()),
+            Kind::Changed => hunks.push(Hunk {
+                old: line_range(
+                    &segments,
+                    idx,
+                    |seg| &seg.old,
+                    old,
+                    &sides.old,
+                    bounds,
+                    |b| b.old,
+                ),
+                new: line_range(
+                    &segments,
+                    idx,
+                    |seg| &seg.new,
+                    new,
+                    &sides.new,
+                    bounds,
+                    |b| b.new,
+                ),
+            }),
+        }
+    }
+    Some(hunks)
+}
+
+/// Lines covered by `nodes[range]`, inclusive.
+fn lines_of(nodes: &[KdlNode], range: &Range<usize>, src: &Source<'_>) -> Option<(usize, usize)> {
+    if range.is_empty() {
+        return None;
+    }
+    let (first, _) = src.node_lines(&nodes[range.start]);
+    let (_, last) = src.node_lines(&nodes[range.end - 1]);
+    Some((first, last))
+}
+
+/// Where lines would be inserted for an empty side of the changed segment at
+/// `idx`: right after the previous segment, or the opening brace.
+fn insertion_line(
+    segments: &[Segment],
+    idx: usize,
+    side: impl Fn(&Segment) -> &Range<usize>,
+    nodes: &[KdlNode],
+    src: &Source<'_>,
+    open: Option<usize>,
+) -> usize {
+    idx.checked_sub(1)
+        .and_then(|prev| lines_of(nodes, side(&segments[prev]), src))
+        .map(|(_, last)| last + 1)
+        .or_else(|| open.map(|open| open + 1))
+        .unwrap_or(0)
+}
+
+fn line_range(
+    segments: &[Segment],
+    idx: usize,
+    side: impl Fn(&Segment) -> &Range<usize>,
+    nodes: &[KdlNode],
+    src: &Source<'_>,
+    bounds: Option<Bounds>,
+    braces: impl Fn(Bounds) -> (usize, usize),
+) -> Range<usize> {
+    match lines_of(nodes, side(&segments[idx]), src) {
+        Some((first, last)) => first..last + 1,
+        None => {
+            let line = insertion_line(segments, idx, side, nodes, src, bounds.map(|b| braces(b).0));
+            line..line
+        }
+    }
+}
+
+/// Picks out one side of a segment.
+type SideOf = fn(&Segment) -> &Range<usize>;
+
+/// Checks whether the changed segment at `idx` overlaps anything else, line
+/// by line. Returns the index of a neighboring segment to merge it with, or
+/// `Some(None)` if there's no conflict. Returns `None` for conflicts that
+/// can't be resolved by merging, with the parent's braces.
+fn conflict(
+    segments: &[Segment],
+    idx: usize,
+    old: &[KdlNode],
+    new: &[KdlNode],
+    sides: &Sides<'_>,
+    bounds: Option<Bounds>,
+) -> Option<Option<usize>> {
+    let prev = idx.checked_sub(1);
+    let next = Some(idx + 1).filter(|&next| next < segments.len());
+    let checks: [(SideOf, &[KdlNode], &Source<'_>, _); 2] = [
+        (|seg| &seg.old, old, &sides.old, bounds.map(|b| b.old)),
+        (|seg| &seg.new, new, &sides.new, bounds.map(|b| b.new)),
+    ];
+    for (is_old, (side, nodes, src, braces)) in [true, false].into_iter().zip(checks) {
+        let prev_last = prev
+            .and_then(|prev| lines_of(nodes, side(&segments[prev]), src))
+            .map(|(_, last)| last);
+        let next_first = next
+            .and_then(|next| lines_of(nodes, side(&segments[next]), src))
+            .map(|(first, _)| first);
+        let (first, last) = match lines_of(nodes, side(&segments[idx]), src) {
+            Some(lines) => lines,
+            None => {
+                // Inserted lines go between whole lines.
+                let line = insertion_line(segments, idx, side, nodes, src, braces.map(|b| b.0));
+                // Nothing can be appended to an unterminated last line
+                // without changing it.
+                let unterminated_end = is_old
+                    && line == src.lines.len()
+                    && !src.text.is_empty()
+                    && !src.text.ends_with('\n');
+                let fits = next_first.map_or(true, |first| line <= first)
+                    && braces.map_or(true, |(_, close)| line <= close)
+                    && !unterminated_end;
+                if fits {
+                    continue;
+                }
+                return match (prev, next) {
+                    (_, Some(next)) if next_first.map_or(false, |first| line > first) => {
+                        Some(Some(next))
+                    }
+                    (Some(prev), _) => Some(Some(prev)),
+                    (None, Some(next)) => Some(Some(next)),
+                    (None, None) => None,
+                };
+            }
+        };
+        if prev_last.map_or(false, |prev_last| prev_last >= first) {
+            return Some(prev);
+        }
+        if next_first.map_or(false, |next_first| next_first <= last) {
+            return Some(next);
+        }
+        if let Some((open, close)) = braces {
+            if first <= open || last >= close {
+                return None;
+            }
+        }
+    }
+    Some(None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Applies a patch made by [`text_patch`] to `old`.
+    fn apply(old: &str, patch: &str) -> String {
+        let old_lines = old.split_inclusive('\n').collect::<Vec<_>>();
+        let mut result = String::new();
+        let mut copied = 0;
+        let mut lines = patch.lines().skip(2).peekable();
+        while let Some(header) = lines.next() {
+            let ranges = header
+                .trim_start_matches("@@ -")
+                .trim_end_matches(" @@")
+                .split(" +")
+                .map(|range| {
+                    let (start, len) = range.split_once(',').unwrap();
+                    (
+                        start.parse::<usize>().unwrap(),
+                        len.parse::<usize>().unwrap(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            let (old_start, old_len) = ranges[0];
+            let old_start = if old_len == 0 {
+                old_start
+            } else {
+                old_start - 1
+            };
+            for line in &old_lines[copied..old_start] {
+                result.push_str(line);
+            }
+            copied = old_start + old_len;
+            while let Some(line) = lines.next_if(|line| !line.starts_with("@@")) {
+                if let Some(added) = line.strip_prefix('+') {
+                    result.push_str(added);
+                    if lines.peek() != Some(&"\\ No newline at end of file") {
+                        result.push('\n');
+                    }
+                }
+            }
+        }
+        for line in &old_lines[copied..] {
+            result.push_str(line);
+        }
+        result
+    }
+
+    fn check(old: &str, new: &str, expected_hunks: &[&str]) {
+        let patch = text_patch(old, new).unwrap();
+        let hunks = patch
+            .lines()
+            .filter(|line| line.starts_with("@@"))
+            .collect::<Vec<_>>();
+        assert_eq!(hunks, expected_hunks, "{patch}");
+        let patched = apply(old, &patch);
+        assert!(
+            crate::semantically_equal(&patched, new).unwrap(),
+            "{patch}\n{patched}"
+        );
+    }
+
+    #[test]
+    fn no_changes() {
+        assert_eq!(text_patch("a 1\nb 2\n", "a 1\nb 2\n").unwrap(), "");
+        assert_eq!(
+            text_patch("a 1\nb 2\n", "// comment\na   1;b 0x2").unwrap(),
+            ""
+        );
+        assert_eq!(text_patch("", "").unwrap(), "");
+    }
+
+    #[test]
+    fn minimal_hunks() {
+        // Changes, insertions, and deletions of whole lines.
+        check("a\nb 1\nc\n", "a\nb 2\nc\n", &["@@ -2,1 +2,1 @@"]);
+        check("a\nc\n", "a\nb\nc\n", &["@@ -1,0 +2,1 @@"]);
+        check("a\nb\nc\n", "a\nc\n", &["@@ -2,1 +1,0 @@"]);
+        check("a\nb\n", "x\na\nb\n", &["@@ -0,0 +1,1 @@"]);
+        check(
+            "a 1\nb\nc 1\n",
+            "a 2\nb\nc 2\n",
+            &["@@ -1,1 +1,1 @@", "@@ -3,1 +3,1 @@"],
+        );
+
+        // Only the changed child.
+        check(
+            "a {\n    b 1\n    c {\n        d 1\n    }\n}\n",
+            "a {\n  b 1\n  c {\n    d 2\n  }\n}\n",
+            &["@@ -4,1 +4,1 @@"],
+        );
+        check(
+            "a {\n    b\n}\n",
+            "a {\n    b\n    c\n}\n",
+            &["@@ -2,0 +3,1 @@"],
+        );
+        check("a {\n    b\n}\n", "a {\n}\n", &["@@ -2,1 +1,0 @@"]);
+
+        // Changing a node's own entries changes the whole node.
+        check(
+            "a 1 {\n    b\n}\nc\n",
+            "a 2 {\n    b\n}\nc\n",
+            &["@@ -1,3 +1,3 @@"],
+        );
+    }
+
+    #[test]
+    fn shared_lines() {
+        // Neighbors on the same line come along.
+        check("a; b 1; c\nd\n", "a; b 2; c\nd\n", &["@@ -1,1 +1,1 @@"]);
+        check("a 1\nb; c\n", "a 1\nb; x; c\n", &["@@ -2,1 +2,1 @@"]);
+        // ...as do parents, when a child shares a line with their braces.
+        check("x\na { b 1; }\n", "x\na { b 2; }\n", &["@@ -2,1 +2,1 @@"]);
+        check(
+            "x\na {\n    b 1 }\ny\n",
+            "x\na {\n    b 2\n}\ny\n",
+            &["@@ -2,2 +2,3 @@"],
+        );
+        check("a {}\n", "a {\n    b\n}\n", &["@@ -1,1 +1,3 @@"]);
+    }
+
+    #[test]
+    fn missing_final_newline() {
+        check("a\nb 1", "a\nb 2", &["@@ -2,1 +2,1 @@"]);
+        check("a", "a\nb", &["@@ -1,1 +1,2 @@"]);
+        check("a 1\n// end\n", "a 2", &["@@ -1,1 +1,1 @@"]);
+        check("", "a", &["@@ -0,0 +1,1 @@"]);
+        check("a", "", &["@@ -1,1 +0,0 @@"]);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(text_patch("a {", "a").is_err());
+        assert!(text_patch("a", "a }").is_err());
+    }
+}
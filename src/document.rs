@@ -2,7 +2,15 @@
 use miette::SourceSpan;
 use std::fmt::Display;
 
-use crate::{KdlNode, KdlParseFailure, KdlValue};
+use indexmap::IndexMap;
+
+use crate::fmt::{is_newline, shift_decor, IndentShift};
+
+use crate::{
+    IntoKdlQuery, KdlDiagnostic, KdlEntry, KdlErrorKind, KdlNode, KdlParseFailure, KdlPath,
+    KdlQueryIterMut, KdlQueryIterator, KdlTrivia, KdlValidationError, KdlValue, NodeKey,
+    ParseOptions, StringStyle, TerminatorKind,
+};
 
 /// Represents a KDL
 /// [`Document`](https://github.com/kdl-org/kdl/blob/main/SPEC.md#document).
@@ -19,16 +27,43 @@ use crate::{KdlNode, KdlParseFailure, KdlValue};
 /// let kdl: KdlDocument = "foo 1 2 3\nbar 4 5 6".parse().expect("parse failed");
 /// ```
 #[derive(Debug, Clone, Eq)]
+#[cfg_attr(not(feature = "span"), derive(Default))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct KdlDocument {
     pub(crate) nodes: Vec<KdlNode>,
     pub(crate) format: Option<KdlDocumentFormat>,
+    pub(crate) version: KdlVersion,
     #[cfg(feature = "span")]
     pub(crate) span: SourceSpan,
 }
 
+/// A version of the KDL language.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum KdlVersion {
+    /// [KDL v1](https://github.com/kdl-org/kdl/blob/1.0.0/SPEC.md).
+    V1,
+    /// [KDL v2](https://github.com/kdl-org/kdl/blob/main/SPEC.md), which is
+    /// what this crate parses and writes by default.
+    V2,
+}
+
+impl Default for KdlVersion {
+    fn default() -> Self {
+        KdlVersion::V2
+    }
+}
+
+fn version_number(version: KdlVersion) -> &'static str {
+    match version {
+        KdlVersion::V1 => "1",
+        KdlVersion::V2 => "2",
+    }
+}
+
 impl PartialEq for KdlDocument {
     fn eq(&self, other: &Self) -> bool {
-        self.nodes == other.nodes && self.format == other.format
+        self.nodes == other.nodes && self.format == other.format && self.version == other.version
         // Intentionally omitted: self.span == other.span
     }
 }
@@ -37,16 +72,19 @@ impl std::hash::Hash for KdlDocument {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         self.nodes.hash(state);
         self.format.hash(state);
+        self.version.hash(state);
         // Intentionally omitted: self.span.hash(state)
     }
 }
 
+// `SourceSpan` has no `Default`, so this can only be derived without spans.
+#[cfg(feature = "span")]
 impl Default for KdlDocument {
     fn default() -> Self {
         Self {
             nodes: Default::default(),
             format: Default::default(),
-            #[cfg(feature = "span")]
+            version: Default::default(),
             span: SourceSpan::from(0..0),
         }
     }
@@ -86,6 +124,32 @@ impl KdlDocument {
             .find(move |n| n.name().value() == name)
     }
 
+    /// Iterates over the nodes in this document whose type annotation is
+    /// `ty`, as in `(ty)node`. Use [`Self::descendants`] to look further
+    /// down, or a [query](Self::query_all) like `(ty)[]`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlDocument;
+    /// let doc: KdlDocument = r#"
+    ///     (task)build { run "cargo build"; }
+    ///     (task)test { (task)unit; }
+    ///     settings
+    /// "#
+    /// .parse()
+    /// .unwrap();
+    /// let tasks = doc
+    ///     .nodes_with_ty("task")
+    ///     .map(|node| node.name().value())
+    ///     .collect::<Vec<_>>();
+    /// assert_eq!(tasks, ["build", "test"]);
+    /// assert_eq!(doc.descendants().filter(|node| node.has_ty("task")).count(), 3);
+    /// ```
+    pub fn nodes_with_ty<'a>(&'a self, ty: &'a str) -> impl Iterator<Item = &'a KdlNode> {
+        self.nodes.iter().filter(move |node| node.has_ty(ty))
+    }
+
     /// Gets the first argument (value) of the first child node with a
     /// matching name. This is a shorthand utility for cases where a document
     /// is being used as a key/value store.
@@ -147,7 +211,8 @@ impl KdlDocument {
     }
 
     /// This utility makes it easy to interact with a KDL convention where
-    /// child nodes named `-` are treated as array-ish values.
+    /// child nodes named `-` are treated as array-ish values. See
+    /// [`KdlNode::dash_values`].
     ///
     /// # Examples
     ///
@@ -167,14 +232,7 @@ impl KdlDocument {
     /// assert_eq!(doc.get_dash_args("foo"), vec![&1.into(), &2.into(), &false.into()]);
     /// ```
     pub fn get_dash_args(&self, name: &str) -> Vec<&KdlValue> {
-        self.get(name)
-            .and_then(|n| n.children())
-            .map(|doc| doc.nodes())
-            .unwrap_or_default()
-            .iter()
-            .filter(|e| e.name().value() == "-")
-            .filter_map(|e| e.get(0))
-            .collect()
+        self.get(name).map(KdlNode::dash_values).unwrap_or_default()
     }
 
     /// Returns a reference to this document's child nodes.
@@ -187,6 +245,107 @@ impl KdlDocument {
         &mut self.nodes
     }
 
+    /// Gets the version of KDL this document was parsed from, which is
+    /// [`KdlVersion::V2`] for documents that weren't parsed at all.
+    ///
+    /// Parsed nodes, entries, and identifiers keep their original
+    /// representation, so a document parsed from KDL v1 prints back out as
+    /// the same v1 text. Anything added or autoformatted afterwards is
+    /// written in KDL v2 syntax, though.
+    pub fn version(&self) -> KdlVersion {
+        self.version
+    }
+
+    /// Gets the version declared by a `/- kdl-version 1` or
+    /// `/- kdl-version 2` marker at the start of this document, if it has
+    /// one. This is independent of [`KdlDocument::version`], which is the
+    /// version the document was actually parsed as.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::{KdlDocument, KdlVersion};
+    /// let doc: KdlDocument = "// Config\n/- kdl-version 2\nnode 1".parse()?;
+    /// assert_eq!(doc.declared_version(), Some(KdlVersion::V2));
+    /// let doc: KdlDocument = "node 1".parse()?;
+    /// assert_eq!(doc.declared_version(), None);
+    /// # Ok::<(), miette::Report>(())
+    /// ```
+    pub fn declared_version(&self) -> Option<KdlVersion> {
+        self.version_marker().map(|(version, _)| version)
+    }
+
+    /// Adds, updates, or (with `None`) removes this document's
+    /// `/- kdl-version` marker. See [`KdlDocument::declared_version`].
+    ///
+    /// An existing marker is updated in place. A new one is added on its own
+    /// line at the very start of the document, after any byte order mark.
+    /// Removing a marker also removes the rest of its line, if there's only
+    /// whitespace on it. This only makes sense for a top-level document,
+    /// since markers aren't recognized anywhere else.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::{KdlDocument, KdlVersion};
+    /// let mut doc: KdlDocument = "// Config\nnode 1\n".parse()?;
+    /// doc.set_declared_version(Some(KdlVersion::V2));
+    /// assert_eq!(doc.to_string(), "/- kdl-version 2\n// Config\nnode 1\n");
+    ///
+    /// let mut doc: KdlDocument = "/-kdl-version 1 // old\nnode 1\n".parse()?;
+    /// doc.set_declared_version(Some(KdlVersion::V2));
+    /// assert_eq!(doc.to_string(), "/-kdl-version 2 // old\nnode 1\n");
+    /// doc.set_declared_version(None);
+    /// assert_eq!(doc.to_string(), " // old\nnode 1\n");
+    /// # Ok::<(), miette::Report>(())
+    /// ```
+    pub fn set_declared_version(&mut self, version: Option<KdlVersion>) {
+        let marker = self.version_marker().map(|(_, span)| span);
+        if marker.is_none() && version.is_none() {
+            return;
+        }
+        let leading = &mut self.format.get_or_insert_with(Default::default).leading;
+        match (marker, version) {
+            (Some(span), Some(version)) => {
+                leading
+                    .to_mut()
+                    .replace_range(span.end - 1..span.end, version_number(version));
+            }
+            (Some(span), None) => {
+                let after = &leading[span.end..];
+                let rest_of_line = after.len() - after.trim_start_matches([' ', '\t']).len();
+                let after = &after[rest_of_line..];
+                let end = if let Some(after) = after.strip_prefix("\r\n") {
+                    leading.len() - after.len()
+                } else if after.starts_with(is_newline) || after.starts_with(';') {
+                    leading.len() - after.len() + after.chars().next().map_or(0, char::len_utf8)
+                } else {
+                    span.end
+                };
+                leading.to_mut().replace_range(span.start..end, "");
+            }
+            (None, Some(version)) => {
+                let start = if leading.starts_with('\u{FEFF}') {
+                    '\u{FEFF}'.len_utf8()
+                } else {
+                    0
+                };
+                leading.to_mut().insert_str(
+                    start,
+                    &format!("/- kdl-version {}\n", version_number(version)),
+                );
+            }
+            (None, None) => unreachable!("returned early above"),
+        }
+    }
+
+    fn version_marker(&self) -> Option<(KdlVersion, std::ops::Range<usize>)> {
+        let leading = self.format.as_ref()?.leading.as_str();
+        crate::v2_parser::try_parse(crate::v2_parser::version_marker, leading)
+            .ok()
+            .flatten()
+    }
+
     /// Gets the formatting details for this entry.
     pub fn format(&self) -> Option<&KdlDocumentFormat> {
         self.format.as_ref()
@@ -229,199 +388,1743 @@ impl KdlDocument {
         }
     }
 
-    /// Auto-formats this Document, making everything nice while preserving
-    /// comments.
-    pub fn autoformat(&mut self) {
-        self.autoformat_impl(0, false);
+    /// Compares two documents by what they mean rather than how they're
+    /// written, ignoring formatting, comments, and spans. See
+    /// [`KdlNode::semantic_eq`].
+    pub fn semantic_eq(&self, other: &Self) -> bool {
+        self.nodes.len() == other.nodes.len()
+            && self
+                .nodes
+                .iter()
+                .zip(&other.nodes)
+                .all(|(a, b)| a.semantic_eq(b))
     }
 
-    /// Formats the document and removes all comments from the document.
-    pub fn autoformat_no_comments(&mut self) {
-        self.autoformat_impl(0, true);
-    }
-
-    // TODO(@zkat): These should all be moved into the query module itself,
-    // instead of being methods on the models
-    //
-    // /// Queries this Document's children according to the KQL query language,
-    // /// returning an iterator over all matching nodes.
-    // ///
-    // /// # NOTE
-    // ///
-    // /// Any query selectors that try to select the toplevel `scope()` will
-    // /// fail to match when using this method, since there's no [`KdlNode`] to
-    // /// return in this case.
-    // pub fn query_all(
-    //     &self,
-    //     query: impl IntoKdlQuery,
-    // ) -> Result<KdlQueryIterator<'_>, KdlDiagnostic> {
-    //     let parsed = query.into_query()?;
-    //     Ok(KdlQueryIterator::new(None, Some(self), parsed))
-    // }
-
-    // /// Queries this Document's children according to the KQL query language,
-    // /// returning the first match, if any.
-    // ///
-    // /// # NOTE
-    // ///
-    // /// Any query selectors that try to select the toplevel `scope()` will
-    // /// fail to match when using this method, since there's no [`KdlNode`] to
-    // /// return in this case.
-    // pub fn query(&self, query: impl IntoKdlQuery) -> Result<Option<&KdlNode>, KdlDiagnostic> {
-    //     let mut iter = self.query_all(query)?;
-    //     Ok(iter.next())
-    // }
-
-    // /// Queries this Document's children according to the KQL query language,
-    // /// picking the first match, and calling `.get(key)` on it, if the query
-    // /// succeeded.
-    // ///
-    // /// # NOTE
-    // ///
-    // /// Any query selectors that try to select the toplevel `scope()` will
-    // /// fail to match when using this method, since there's no [`KdlNode`] to
-    // /// return in this case.
-    // pub fn query_get(
-    //     &self,
-    //     query: impl IntoKdlQuery,
-    //     key: impl Into<NodeKey>,
-    // ) -> Result<Option<&KdlValue>, KdlDiagnostic> {
-    //     Ok(self.query(query)?.and_then(|node| node.get(key)))
-    // }
-
-    // /// Queries this Document's children according to the KQL query language,
-    // /// returning an iterator over all matching nodes, returning the requested
-    // /// field from each of those nodes and filtering out nodes that don't have
-    // /// it.
-    // ///
-    // /// # NOTE
-    // ///
-    // /// Any query selectors that try to select the toplevel `scope()` will
-    // /// fail to match when using this method, since there's no [`KdlNode`] to
-    // /// return in this case.
-    // pub fn query_get_all(
-    //     &self,
-    //     query: impl IntoKdlQuery,
-    //     key: impl Into<NodeKey>,
-    // ) -> Result<impl Iterator<Item = &KdlValue>, KdlDiagnostic> {
-    //     let key: NodeKey = key.into();
-    //     Ok(self
-    //         .query_all(query)?
-    //         .filter_map(move |node| node.get(key.clone())))
-    // }
-}
-
-impl std::str::FromStr for KdlDocument {
-    type Err = KdlParseFailure;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        crate::v2_parser::try_parse(crate::v2_parser::document, s)
+    /// Removes and returns the node at `index`, keeping the rest of the
+    /// document's formatting intact.
+    ///
+    /// Comments and whitespace in front of the removed node go with it, but
+    /// the following node takes over the removed node's spacing: any blank
+    /// lines that separated the removed node from the one before it are kept,
+    /// and the following node's own blank lines are dropped, so removing a
+    /// node never leaves doubled-up blank lines behind.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlDocument;
+    /// let mut doc: KdlDocument = "a\n\n// about b\nb\n\nc\n".parse().unwrap();
+    /// let b = doc.remove_node(1);
+    /// assert_eq!(b.name().value(), "b");
+    /// assert_eq!(doc.to_string(), "a\n\nc\n");
+    /// ```
+    pub fn remove_node(&mut self, index: usize) -> KdlNode {
+        let removed = self.nodes.remove(index);
+        let removed_blank_lines = removed
+            .format()
+            .map(|fmt| crate::fmt::split_blank_lines(&fmt.leading).0)
+            .unwrap_or("");
+        if let Some(next) = self.nodes.get_mut(index) {
+            if index == 0 {
+                // The first node's indentation lives in the document's
+                // leading text, but the next node brings its own.
+                if let Some(fmt) = self.format.as_mut() {
+                    let kept = fmt
+                        .leading
+                        .trim_end_matches(|c: char| c.is_whitespace() && !crate::fmt::is_newline(c))
+                        .len();
+                    fmt.leading.to_mut().truncate(kept);
+                }
+            }
+            if let Some(fmt) = next.format_mut() {
+                let rest = crate::fmt::split_blank_lines(&fmt.leading).1;
+                fmt.leading = format!("{removed_blank_lines}{rest}").into();
+            }
+        } else if let Some(prev) = index.checked_sub(1).and_then(|i| self.nodes.get_mut(i)) {
+            // Don't lose the final newline when removing the last node.
+            let removed_newline = removed
+                .format()
+                .map(|fmt| format!("{}{}", fmt.terminator, fmt.trailing))
+                .map_or(false, |end| end.ends_with(crate::fmt::is_newline));
+            if let Some(fmt) = prev.format_mut() {
+                if removed_newline
+                    && !format!("{}{}", fmt.terminator, fmt.trailing)
+                        .ends_with(crate::fmt::is_newline)
+                {
+                    fmt.trailing.to_mut().push('\n');
+                }
+            }
+        }
+        removed
     }
-}
 
-impl Display for KdlDocument {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.stringify(f, 0)
+    /// Removes and returns the first top-level node named `name`, repairing
+    /// the surrounding formatting like [`KdlDocument::remove_node`] does.
+    ///
+    /// This is handy for plucking out "header" nodes, such as metadata, before
+    /// processing the rest of a document.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlDocument;
+    /// let mut doc: KdlDocument = "@meta version=2\nconfig 1\n".parse().unwrap();
+    /// let meta = doc.take_node("@meta").unwrap();
+    /// assert_eq!(meta.get("version").and_then(|v| v.as_integer()), Some(2));
+    /// assert_eq!(doc.to_string(), "config 1\n");
+    /// ```
+    pub fn take_node(&mut self, name: &str) -> Option<KdlNode> {
+        let index = self
+            .nodes
+            .iter()
+            .position(|node| node.name().value() == name)?;
+        Some(self.remove_node(index))
     }
-}
 
-impl KdlDocument {
-    pub(crate) fn autoformat_impl(&mut self, indent: usize, no_comments: bool) {
-        if let Some(KdlDocumentFormat { leading, .. }) = self.format_mut() {
-            crate::fmt::autoformat_leading(leading, indent, no_comments);
-        }
-        let mut has_nodes = false;
-        for node in &mut self.nodes {
-            has_nodes = true;
-            node.autoformat_impl(indent, no_comments);
-        }
-        if let Some(KdlDocumentFormat { trailing, .. }) = self.format_mut() {
-            crate::fmt::autoformat_trailing(trailing, no_comments);
-            if !has_nodes {
-                trailing.push('\n');
+    /// Shifts the indentation of this document's contents by `levels` times
+    /// `unit`, for example to nest a top-level document inside another
+    /// node's children block.
+    ///
+    /// Every line in the document's formatting is affected, at every depth,
+    /// including the lines of comments and closing braces. Negative `levels`
+    /// dedent instead, removing at most as much indentation as each line
+    /// actually has. Blank lines are left alone, and so are the contents of
+    /// multi-line strings, since changing those would change their values.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlDocument;
+    /// let mut doc: KdlDocument = "a {\n    b\n}\n\nc\n".parse().unwrap();
+    /// doc.shift_indent(1, "    ");
+    /// assert_eq!(doc.to_string(), "    a {\n        b\n    }\n\n    c\n");
+    /// doc.shift_indent(-2, "    ");
+    /// assert_eq!(doc.to_string(), "a {\nb\n}\n\nc\n");
+    /// ```
+    pub fn shift_indent(&mut self, levels: i32, unit: &str) {
+        let indent;
+        let shift = if levels >= 0 {
+            indent = unit.repeat(levels as usize);
+            IndentShift::Add(&indent)
+        } else {
+            IndentShift::Remove {
+                unit,
+                count: levels.unsigned_abs() as usize,
             }
+        };
+        if self.format.is_none() && !self.nodes.is_empty() {
+            // The first node's indentation lives here.
+            self.format = Some(Default::default());
         }
+        self.shift_indent_impl(&shift, &mut true, false);
     }
 
-    pub(crate) fn stringify(
-        &self,
-        f: &mut std::fmt::Formatter<'_>,
-        indent: usize,
-    ) -> std::fmt::Result {
-        if let Some(KdlDocumentFormat { leading, .. }) = self.format() {
-            write!(f, "{}", leading)?;
+    /// Counts how many times each node name is used in this document, at any
+    /// depth, in order of first appearance.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlDocument;
+    /// let doc: KdlDocument = "a; b { a; c }; b".parse().unwrap();
+    /// let histogram = doc.node_name_histogram();
+    /// assert_eq!(
+    ///     histogram.into_iter().collect::<Vec<_>>(),
+    ///     vec![("a".into(), 2), ("b".into(), 2), ("c".into(), 1)]
+    /// );
+    /// ```
+    pub fn node_name_histogram(&self) -> IndexMap<String, usize> {
+        fn count(doc: &KdlDocument, histogram: &mut IndexMap<String, usize>) {
+            for node in &doc.nodes {
+                *histogram.entry(node.name().value().into()).or_default() += 1;
+                if let Some(children) = node.children() {
+                    count(children, histogram);
+                }
+            }
         }
-        for node in &self.nodes {
-            node.stringify(f, indent)?;
+        let mut histogram = IndexMap::new();
+        count(self, &mut histogram);
+        histogram
+    }
+
+    /// Calls `f` for every node in this document, recursing into children,
+    /// along with the names of the node's ancestors, outermost first. Top-level
+    /// nodes get an empty slice.
+    ///
+    /// Traversal is depth-first, with each node visited before its children,
+    /// in document order.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlDocument;
+    /// let doc: KdlDocument = "server {\n    tls {\n        cert \"a.pem\"\n    }\n}".parse().unwrap();
+    /// let mut paths = Vec::new();
+    /// doc.for_each_with_path(|ancestors, node| {
+    ///     paths.push(format!("{}/{}", ancestors.join("/"), node.name().value()));
+    /// });
+    /// assert_eq!(paths, ["/server", "server/tls", "server/tls/cert"]);
+    /// ```
+    pub fn for_each_with_path(&self, mut f: impl FnMut(&[&str], &KdlNode)) {
+        fn walk<'a>(
+            doc: &'a KdlDocument,
+            path: &mut Vec<&'a str>,
+            f: &mut impl FnMut(&[&str], &KdlNode),
+        ) {
+            for node in &doc.nodes {
+                f(path, node);
+                if let Some(children) = &node.children {
+                    path.push(node.name().value());
+                    walk(children, path, f);
+                    path.pop();
+                }
+            }
         }
-        if let Some(KdlDocumentFormat { trailing, .. }) = self.format() {
-            write!(f, "{}", trailing)?;
+        walk(self, &mut Vec::new(), &mut f);
+    }
+
+    /// Iterates over every node in this document, recursing into children.
+    ///
+    /// Traversal is depth-first, with each node visited before its children,
+    /// in document order. The iterator keeps its own stack, so it works on
+    /// documents of any depth.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlDocument;
+    /// let doc: KdlDocument = "a {\n    b {\n        c\n    }\n}\nd".parse().unwrap();
+    /// let names = doc
+    ///     .descendants()
+    ///     .map(|node| node.name().value())
+    ///     .collect::<Vec<_>>();
+    /// assert_eq!(names, ["a", "b", "c", "d"]);
+    /// ```
+    pub fn descendants(&self) -> impl Iterator<Item = &KdlNode> {
+        Descendants::new(&self.nodes)
+    }
+
+    /// Like [`Self::descendants`], but only yields nodes named `name`.
+    pub fn descendants_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a KdlNode> {
+        self.descendants()
+            .filter(move |node| node.name().value() == name)
+    }
+
+    /// Finds every node whose path of names from the top level matches the
+    /// `/`-separated glob `pattern`, depth-first and in document order.
+    ///
+    /// In each segment, `*` matches any run of characters and `?` matches
+    /// any one character, so `*` on its own matches any node name. A `**`
+    /// segment matches any number of levels, including none.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlDocument;
+    /// let doc: KdlDocument = r#"
+    ///     services {
+    ///         web { ports 80 443; }
+    ///         db { ports 5432; replica { ports 5433; } }
+    ///     }
+    ///     ports 22
+    /// "#
+    /// .parse()
+    /// .unwrap();
+    /// let ports = |pattern| {
+    ///     doc.match_paths(pattern)
+    ///         .flat_map(|node| node.entries())
+    ///         .map(|entry| entry.value().as_integer().unwrap())
+    ///         .collect::<Vec<_>>()
+    /// };
+    /// assert_eq!(ports("services/*/ports"), [80, 443, 5432]);
+    /// assert_eq!(ports("services/**/ports"), [80, 443, 5432, 5433]);
+    /// assert_eq!(ports("**/ports"), [80, 443, 5432, 5433, 22]);
+    /// assert_eq!(ports("services/d?/ports"), [5432]);
+    /// ```
+    pub fn match_paths(&self, pattern: &str) -> impl Iterator<Item = &KdlNode> {
+        let segments = pattern
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .collect::<Vec<_>>();
+        let mut out = Vec::new();
+        let mut stack = vec![(self.nodes.iter(), glob_closure(&segments, vec![0]))];
+        while let Some((nodes, states)) = stack.last_mut() {
+            let node = match nodes.next() {
+                Some(node) => node,
+                None => {
+                    stack.pop();
+                    continue;
+                }
+            };
+            let next = glob_step(&segments, states, node.name().value());
+            if next.contains(&segments.len()) {
+                out.push(node);
+            }
+            if let Some(children) = &node.children {
+                if next.iter().any(|state| *state < segments.len()) {
+                    stack.push((children.nodes.iter(), next));
+                }
+            }
         }
-        Ok(())
+        out.into_iter()
     }
-}
 
-impl IntoIterator for KdlDocument {
-    type Item = KdlNode;
-    type IntoIter = std::vec::IntoIter<Self::Item>;
+    /// Iterates over every entry (argument and property) of every node in
+    /// this document, recursing into children.
+    ///
+    /// Traversal is depth-first, with each node's own entries coming before
+    /// those of its children, in document order.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlDocument;
+    /// let doc: KdlDocument = "a 1 key=2 {\n    b 3\n}\nc key=4".parse().unwrap();
+    /// let keyed = doc
+    ///     .all_entries()
+    ///     .filter(|entry| entry.name().map_or(false, |name| name.value() == "key"))
+    ///     .count();
+    /// assert_eq!(keyed, 2);
+    /// ```
+    pub fn all_entries(&self) -> impl Iterator<Item = &KdlEntry> {
+        fn collect<'a>(doc: &'a KdlDocument, out: &mut Vec<&'a KdlEntry>) {
+            for node in &doc.nodes {
+                out.extend(&node.entries);
+                if let Some(children) = &node.children {
+                    collect(children, out);
+                }
+            }
+        }
+        let mut out = Vec::new();
+        collect(self, &mut out);
+        out.into_iter()
+    }
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.nodes.into_iter()
+    /// Like [`Self::all_entries`], but yields mutable references, for
+    /// editing entries throughout the tree in bulk.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::{KdlDocument, KdlValue};
+    /// let mut doc: KdlDocument = "a 1 {\n    b 2\n}".parse().unwrap();
+    /// for entry in doc.all_entries_mut() {
+    ///     if let Some(n) = entry.value().as_integer() {
+    ///         entry.set_value(KdlValue::Integer(n * 10));
+    ///     }
+    /// }
+    /// assert_eq!(doc.to_string(), "a 10 {\n    b 20\n}");
+    /// ```
+    pub fn all_entries_mut(&mut self) -> impl Iterator<Item = &mut KdlEntry> {
+        fn collect<'a>(doc: &'a mut KdlDocument, out: &mut Vec<&'a mut KdlEntry>) {
+            for node in &mut doc.nodes {
+                out.extend(&mut node.entries);
+                if let Some(children) = &mut node.children {
+                    collect(children, out);
+                }
+            }
+        }
+        let mut out = Vec::new();
+        collect(self, &mut out);
+        out.into_iter()
     }
-}
 
-/// Formatting details for [`KdlDocument`]s.
-#[derive(Debug, Clone, Default, Hash, Eq, PartialEq)]
-pub struct KdlDocumentFormat {
-    /// Whitespace and comments preceding the document's first node.
-    pub leading: String,
-    /// Whitespace and comments following the document's last node.
-    pub trailing: String,
-}
+    /// Total number of entries in this document, including those of nested
+    /// children. See [`Self::all_entries`].
+    pub fn entries_total(&self) -> usize {
+        self.nodes
+            .iter()
+            .map(|node| node.entries.len() + node.children.as_ref().map_or(0, Self::entries_total))
+            .sum()
+    }
 
-#[cfg(test)]
-mod test {
+    /// Iterates over this document's nodes along with their index and span,
+    /// for building offset-based lookup tables.
+    ///
+    /// Spans are only accurate for documents that haven't been modified
+    /// since they were parsed. See [`KdlNode::span`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlDocument;
+    /// let doc: KdlDocument = "a 1\nb 2".parse().unwrap();
+    /// let spans = doc
+    ///     .nodes_with_spans()
+    ///     .map(|(idx, span, _)| (idx, span.offset(), span.len()))
+    ///     .collect::<Vec<_>>();
+    /// assert_eq!(spans, vec![(0, 0, 3), (1, 4, 3)]);
+    /// ```
     #[cfg(feature = "span")]
-    use crate::KdlIdentifier;
-    use crate::{KdlEntry, KdlValue};
+    pub fn nodes_with_spans(&self) -> impl Iterator<Item = (usize, &SourceSpan, &KdlNode)> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .map(|(idx, node)| (idx, &node.span, node))
+    }
 
-    use super::*;
+    /// Like [`Self::nodes_with_spans`], but also recurses into children,
+    /// depth-first. Each node comes with its path of child indices from this
+    /// document, so `[1, 0]` is the first child of the second node.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlDocument;
+    /// let doc: KdlDocument = "a\nb {\n    c\n}".parse().unwrap();
+    /// let paths = doc
+    ///     .nodes_with_spans_recursive()
+    ///     .map(|(path, _, node)| (path, node.name().value().to_string()))
+    ///     .collect::<Vec<_>>();
+    /// assert_eq!(
+    ///     paths,
+    ///     vec![
+    ///         (vec![0], "a".into()),
+    ///         (vec![1], "b".into()),
+    ///         (vec![1, 0], "c".into()),
+    ///     ]
+    /// );
+    /// ```
+    #[cfg(feature = "span")]
+    pub fn nodes_with_spans_recursive(
+        &self,
+    ) -> impl Iterator<Item = (Vec<usize>, &SourceSpan, &KdlNode)> {
+        fn collect<'a>(
+            doc: &'a KdlDocument,
+            path: &mut Vec<usize>,
+            out: &mut Vec<(Vec<usize>, &'a SourceSpan, &'a KdlNode)>,
+        ) {
+            for (idx, span, node) in doc.nodes_with_spans() {
+                path.push(idx);
+                out.push((path.clone(), span, node));
+                if let Some(children) = node.children() {
+                    collect(children, path, out);
+                }
+                path.pop();
+            }
+        }
+        let mut out = Vec::new();
+        collect(self, &mut Vec::new(), &mut out);
+        out.into_iter()
+    }
 
-    #[test]
-    fn canonical_clear_fmt() -> miette::Result<()> {
-        let left_src = r#"
-// There is a node here
-first_node /*with cool comments, too */ param=1.03e2 /-"commented" "argument" {
-    // With nested nodes too
-    nested 1 2 3
-    nested_2 "hi" "world" // this one is cool
-}
-second_node param=153 { nested one=1 two=2; }"#;
-        let right_src = r#"
-first_node param=103.0       "argument" {
-        // Different indentation, because
-        // Why not
-        nested 1 2 3
-        nested_2 "hi" /* actually, "hello" */ "world"
-}
-// There is a node here
-second_node /* This time, the comment is here */ param=153 {
-        nested one=1 two=2
-}"#;
-        let mut left_doc: KdlDocument = left_src.parse()?;
-        let mut right_doc: KdlDocument = right_src.parse()?;
-        assert_ne!(left_doc, right_doc);
-        left_doc.clear_format_recursive();
-        right_doc.clear_format_recursive();
-        assert_eq!(left_doc, right_doc);
-        Ok(())
+    /// The innermost node whose span covers the source `offset`, along with
+    /// its ancestors, outermost first. Spans are half-open, so an offset
+    /// right after a node's last character isn't in it.
+    ///
+    /// Spans are only accurate for documents that haven't been modified
+    /// since they were parsed. See [`KdlNode::span`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlDocument;
+    /// let input = "a {\n    b 1 key=2\n}\nc";
+    /// let doc: KdlDocument = input.parse().unwrap();
+    /// let (node, ancestors) = doc.node_at_offset(input.find("key").unwrap()).unwrap();
+    /// assert_eq!(node.name().value(), "b");
+    /// assert_eq!(ancestors.len(), 1);
+    /// assert_eq!(ancestors[0].name().value(), "a");
+    /// assert!(doc.node_at_offset(input.len()).is_none());
+    /// ```
+    #[cfg(feature = "span")]
+    pub fn node_at_offset(&self, offset: usize) -> Option<(&KdlNode, Vec<&KdlNode>)> {
+        let mut ancestors = self.nodes_at_offset(offset);
+        let node = ancestors.pop()?;
+        Some((node, ancestors))
     }
 
-    #[test]
-    fn basic_parsing() -> miette::Result<()> {
-        let src = r#"
-            // Hello, world!
-            node 1
+    /// The entry whose span covers the source `offset`, along with the node
+    /// it's in and that node's ancestors, outermost first. This is `None`
+    /// for offsets between entries, or in node names.
+    ///
+    /// Like [`Self::node_at_offset`], this relies on spans from parsing.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlDocument;
+    /// let input = "a {\n    b 1 key=2\n}";
+    /// let doc: KdlDocument = input.parse().unwrap();
+    /// let (entry, nodes) = doc.entry_at_offset(input.find("=2").unwrap()).unwrap();
+    /// assert_eq!(entry.to_string(), " key=2");
+    /// assert_eq!(nodes.last().unwrap().name().value(), "b");
+    /// assert!(doc.entry_at_offset(input.find("b").unwrap()).is_none());
+    /// ```
+    #[cfg(feature = "span")]
+    pub fn entry_at_offset(&self, offset: usize) -> Option<(&KdlEntry, Vec<&KdlNode>)> {
+        let nodes = self.nodes_at_offset(offset);
+        let entry = nodes
+            .last()?
+            .entries()
+            .iter()
+            .find(|entry| span_contains(&entry.span(), offset))?;
+        Some((entry, nodes))
+    }
+
+    /// The chain of nodes covering `offset`, from the top level down.
+    #[cfg(feature = "span")]
+    fn nodes_at_offset(&self, offset: usize) -> Vec<&KdlNode> {
+        let mut chain = Vec::new();
+        let mut nodes = &self.nodes[..];
+        while let Some(node) = nodes.iter().find(|node| span_contains(&node.span, offset)) {
+            chain.push(node);
+            nodes = node
+                .children()
+                .map_or(&[][..], |children| &children.nodes[..]);
+        }
+        chain
+    }
+
+    /// Nesting depth of `target` within this document: 0 for top-level
+    /// nodes, 1 for their children, and so on. Returns `None` if `target`
+    /// isn't in this document.
+    ///
+    /// Nodes don't know their parents, so `target` is looked up by identity:
+    /// it has to be a reference into this document. With the `span` feature,
+    /// a node that isn't (such as a clone) is instead matched by its span,
+    /// which is only reliable for documents that haven't been modified since
+    /// they were parsed.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlDocument;
+    /// let doc: KdlDocument = "a {\n    b {\n        c\n    }\n}".parse().unwrap();
+    /// let b = doc.get("a").unwrap().children().unwrap().get("b").unwrap();
+    /// let c = b.children().unwrap().get("c").unwrap();
+    /// assert_eq!(doc.depth_of(&doc.nodes()[0]), Some(0));
+    /// assert_eq!(doc.depth_of(b), Some(1));
+    /// assert_eq!(doc.depth_of(c), Some(2));
+    /// assert_eq!(doc.depth_of(&"c".parse().unwrap()), None);
+    /// ```
+    pub fn depth_of(&self, target: &KdlNode) -> Option<usize> {
+        fn find(
+            doc: &KdlDocument,
+            depth: usize,
+            matches: &impl Fn(&KdlNode) -> bool,
+        ) -> Option<usize> {
+            doc.nodes.iter().find_map(|node| {
+                if matches(node) {
+                    Some(depth)
+                } else {
+                    find(node.children()?, depth + 1, matches)
+                }
+            })
+        }
+        let depth = find(self, 0, &|node| std::ptr::eq(node, target));
+        #[cfg(feature = "span")]
+        if depth.is_none() && !target.span.is_empty() {
+            return find(self, 0, &|node| node.span == target.span);
+        }
+        depth
+    }
+
+    /// Checks that this document can be written out as valid KDL that parses
+    /// back into the same values, which isn't guaranteed when it's been
+    /// built or modified programmatically. In particular, every identifier
+    /// (node name, property name, or type annotation) must be representable,
+    /// and every entry's value representation must parse back to its value.
+    ///
+    /// Formatting details other than identifier and value representations,
+    /// such as whitespace and comments, aren't checked.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::{KdlDocument, KdlEntryFormat, KdlValidationErrorKind};
+    /// let mut doc: KdlDocument = "node 1 key=value".parse().unwrap();
+    /// assert!(doc.validate().is_ok());
+    ///
+    /// let entry = &mut doc.nodes_mut()[0].entries_mut()[0];
+    /// entry.set_format(KdlEntryFormat {
+    ///     value_repr: "2".into(),
+    ///     leading: " ".into(),
+    ///     ..Default::default()
+    /// });
+    /// let errors = doc.validate().unwrap_err();
+    /// assert_eq!(errors.len(), 1);
+    /// assert_eq!(errors[0].path, vec![0]);
+    /// assert_eq!(errors[0].entry, Some(0));
+    /// assert!(matches!(
+    ///     errors[0].kind,
+    ///     KdlValidationErrorKind::InvalidValue { .. }
+    /// ));
+    /// ```
+    pub fn validate(&self) -> Result<(), Vec<KdlValidationError>> {
+        let mut errors = Vec::new();
+        self.validate_impl(&mut Vec::new(), &mut errors);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub(crate) fn validate_impl(
+        &self,
+        path: &mut Vec<usize>,
+        errors: &mut Vec<KdlValidationError>,
+    ) {
+        for (idx, node) in self.nodes.iter().enumerate() {
+            path.push(idx);
+            node.validate_impl(path, errors);
+            path.pop();
+        }
+    }
+
+    /// Rewrites every string value and identifier (node names, property
+    /// names, and type annotations) in this document in the given
+    /// [`StringStyle`]. Strings that can't be written in that style, such as
+    /// ones containing spaces with [`StringStyle::PreferBare`], are quoted
+    /// instead. Other values and all other formatting are left alone.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::{KdlDocument, StringStyle};
+    /// let mut doc: KdlDocument = r##"node "bare" #"raw"# x="a b" { "child" }"##.parse().unwrap();
+    /// doc.normalize_string_style(StringStyle::PreferBare);
+    /// assert_eq!(doc.to_string(), r#"node bare raw x="a b" { child }"#);
+    /// doc.normalize_string_style(StringStyle::Quoted);
+    /// assert_eq!(doc.to_string(), r#""node" "bare" "raw" "x"="a b" { "child" }"#);
+    /// ```
+    pub fn normalize_string_style(&mut self, style: StringStyle) {
+        for node in &mut self.nodes {
+            node.normalize_string_style(style);
+        }
+    }
+
+    /// Rewrites some aspects of this document's formatting into their
+    /// canonical form, as selected by `flags`, while leaving everything else,
+    /// including comments, exactly as it was. Unlike [`Self::autoformat`],
+    /// this allows keeping e.g. the original string quoting while still
+    /// normalizing numbers.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::{CanonicalizeFlags, KdlDocument};
+    /// let mut doc: KdlDocument = "a 0x10 ( t )#\"raw\"# {\n  b 1_000 // hi\n}".parse().unwrap();
+    /// doc.canonicalize_selective(CanonicalizeFlags::NUMBERS | CanonicalizeFlags::INDENTATION);
+    /// assert_eq!(doc.to_string(), "a 16 ( t )#\"raw\"# {\n    b 1000 // hi\n}");
+    /// ```
+    pub fn canonicalize_selective(&mut self, flags: CanonicalizeFlags) {
+        if flags.contains(CanonicalizeFlags::NUMBERS) {
+            for node in &mut self.nodes {
+                node.normalize_numbers();
+            }
+        }
+        if flags.contains(CanonicalizeFlags::STRING_QUOTING) {
+            self.normalize_string_style(StringStyle::PreferBare);
+        }
+        if flags.contains(CanonicalizeFlags::TYPE_SPACING) {
+            self.normalize_type_annotations();
+        }
+        if flags.contains(CanonicalizeFlags::INDENTATION) {
+            if self.format.is_none() && !self.nodes.is_empty() {
+                // The first node's indentation lives here.
+                self.format = Some(Default::default());
+            }
+            self.reindent_impl("    ", 0, &mut true);
+        }
+    }
+
+    /// Rewrites every type annotation in this document into the canonical
+    /// `(name)` form, so `( foo )1` becomes `(foo)1`. See
+    /// [`KdlNode::normalize_type_annotations`].
+    pub fn normalize_type_annotations(&mut self) {
+        for node in &mut self.nodes {
+            node.normalize_type_annotations();
+        }
+    }
+
+    /// Auto-formats this Document, making everything nice while preserving
+    /// comments.
+    pub fn autoformat(&mut self) {
+        self.autoformat_config(&KdlFormatConfig::default());
+    }
+
+    /// Formats the document and removes all comments from the document.
+    pub fn autoformat_no_comments(&mut self) {
+        self.autoformat_config(&KdlFormatConfig {
+            no_comments: true,
+            ..Default::default()
+        });
+    }
+
+    /// Auto-formats this document according to `config`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::{KdlDocument, KdlFormatConfig};
+    /// let mut doc: KdlDocument = "a {\n  b\n\n\n\n  /-c\n  d /-1 2\n}".parse().unwrap();
+    /// doc.autoformat_config(&KdlFormatConfig {
+    ///     indent: "\t",
+    ///     max_blank_lines: 1,
+    ///     remove_slashdashes: true,
+    ///     ..Default::default()
+    /// });
+    /// assert_eq!(doc.to_string(), "a {\n\tb\n\n\td 2\n}\n");
+    ///
+    /// let mut doc: KdlDocument = "server {\n  listen port=80\n  location path=\"/\" root=\"/srv\"\n}".parse().unwrap();
+    /// doc.autoformat_config(&KdlFormatConfig {
+    ///     indent: "  ",
+    ///     align_properties: true,
+    ///     ..Default::default()
+    /// });
+    /// assert_eq!(
+    ///     doc.to_string(),
+    ///     "server {\n  listen port  =80\n  location path=\"/\" root=\"/srv\"\n}\n"
+    /// );
+    /// ```
+    pub fn autoformat_config(&mut self, config: &KdlFormatConfig<'_>) {
+        self.autoformat_impl(0, config);
+        if config.align_properties {
+            self.align_properties();
+        }
+    }
+
+    /// Checks this document's node terminators against `rule`, returning a
+    /// warning for each `;` that breaks it, in document order. Requires the
+    /// `span` feature.
+    ///
+    /// The terminators are found through the nodes' spans, and the
+    /// diagnostics' source is this document's text, as returned by
+    /// `to_string`. This means the document has to be one that was parsed
+    /// and hasn't been modified since, so its spans point into the original
+    /// input. Terminators of nodes whose spans don't line up with the text
+    /// are skipped.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::{KdlDocument, TerminatorRule};
+    /// let doc: KdlDocument = "a; b;\nc {\n    d;\n}".parse().unwrap();
+    /// let offsets = |rule| {
+    ///     doc.lint_terminators(rule)
+    ///         .iter()
+    ///         .map(|diag| diag.span.offset())
+    ///         .collect::<Vec<_>>()
+    /// };
+    /// assert_eq!(offsets(TerminatorRule::NoSemicolons), [1, 4, 15]);
+    /// assert_eq!(offsets(TerminatorRule::NoRedundantSemicolons), [4, 15]);
+    /// ```
+    #[cfg(feature = "span")]
+    pub fn lint_terminators(&self, rule: TerminatorRule) -> Vec<KdlDiagnostic> {
+        let input = std::sync::Arc::new(self.to_string());
+        let mut offsets = self
+            .descendants()
+            .filter(|node| node.terminator_kind() == TerminatorKind::Semicolon)
+            .filter_map(|node| {
+                // A node's span ends where its contents do, which is where
+                // the trivia before its terminator start.
+                let fmt = node.format()?;
+                let offset = node.span().offset() + node.span().len() + fmt.before_terminator.len();
+                if input.get(offset..offset + 1) == Some(";") {
+                    Some(offset)
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        // Children's terminators come before their parent's.
+        offsets.sort_unstable();
+        offsets
+            .into_iter()
+            .filter(|&offset| match rule {
+                TerminatorRule::NoSemicolons => true,
+                TerminatorRule::NoRedundantSemicolons => ends_line(&input[offset + 1..]),
+            })
+            .map(|offset| KdlDiagnostic {
+                input: input.clone(),
+                span: (offset, 1).into(),
+                label: Some(match rule {
+                    TerminatorRule::NoSemicolons => "semicolon terminator",
+                    TerminatorRule::NoRedundantSemicolons => "redundant semicolon",
+                }),
+                help: Some(match rule {
+                    TerminatorRule::NoSemicolons => "Put the next node on its own line instead.",
+                    TerminatorRule::NoRedundantSemicolons => "Remove this `;`.",
+                }),
+                severity: miette::Severity::Warning,
+                kind: KdlErrorKind::TerminatorStyle(rule),
+            })
+            .collect()
+    }
+
+    /// Changes how the node at `index` is terminated, like
+    /// [`KdlNode::set_terminator_kind`], returning whether it was changed.
+    ///
+    /// Unlike on a lone node, [`TerminatorKind::Implicit`] can be set here,
+    /// but only on the last node, since any other one would be joined up
+    /// with the node after it. `false` is returned, and nothing changes, for
+    /// any other node, or if there's no node at `index`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::{KdlDocument, TerminatorKind};
+    /// let mut doc: KdlDocument = "a\nb;".parse().unwrap();
+    /// assert!(!doc.set_terminator_kind(0, TerminatorKind::Implicit));
+    /// assert!(doc.set_terminator_kind(1, TerminatorKind::Implicit));
+    /// assert_eq!(doc.to_string(), "a\nb");
+    /// ```
+    pub fn set_terminator_kind(&mut self, index: usize, kind: TerminatorKind) -> bool {
+        let last = index + 1 == self.nodes.len();
+        match self.nodes.get_mut(index) {
+            Some(_) if kind == TerminatorKind::Implicit && !last => false,
+            Some(node) => {
+                let was_newline = node.terminator_kind() == TerminatorKind::Newline;
+                node.replace_terminator(kind);
+                if kind == TerminatorKind::Newline && !was_newline {
+                    self.merge_newline_after(index);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// After the node at `index` was given a plain newline terminator, takes
+    /// the newline that follows it, if any, from the trivia after it, so it
+    /// doesn't turn into a blank line, along with any single-line comment
+    /// before it. Otherwise, drops the spaces that used to separate it from
+    /// the next node on the same line.
+    fn merge_newline_after(&mut self, index: usize) {
+        let (node, rest) = self.nodes[index..].split_first_mut().expect("checked");
+        let fmt = match node.format_mut() {
+            Some(fmt) if fmt.terminator == "\n" && fmt.trailing.is_empty() => fmt,
+            _ => return,
+        };
+        let next = match rest.first_mut() {
+            Some(next) => next.format_mut().map(|fmt| &mut fmt.leading),
+            None => self.format.as_mut().map(|fmt| &mut fmt.trailing),
+        };
+        let next = match next {
+            Some(next) => next,
+            None => return,
+        };
+        let text = next.to_string();
+        match crate::node::line_end(&text) {
+            Some((space, end)) => {
+                fmt.before_terminator =
+                    format!("{}{}", fmt.before_terminator, &text[..space]).into();
+                fmt.terminator = text[space..end].to_string().into();
+                *next = text[end..].to_string().into();
+            }
+            None => {
+                *next = text
+                    .trim_start_matches(|c: char| c.is_whitespace() && !is_newline(c))
+                    .to_string()
+                    .into()
+            }
+        }
+    }
+
+    /// Parses a document written in [KDL
+    /// v1](https://github.com/kdl-org/kdl/blob/1.0.0/SPEC.md), with the `v1`
+    /// feature.
+    ///
+    /// The result uses the same document model as a v2 document, with each
+    /// value's original v1 representation, so it prints back out as the
+    /// same v1 text. Its [`KdlDocument::version`] is [`KdlVersion::V1`].
+    ///
+    /// ```rust
+    /// # use kdl::{KdlDocument, KdlValue, KdlVersion};
+    /// let doc = KdlDocument::parse_v1("node \"v1 string\" true null r#\"raw\"#")?;
+    /// assert_eq!(doc.version(), KdlVersion::V1);
+    /// assert_eq!(doc.get_args("node")[1], &KdlValue::Bool(true));
+    /// assert_eq!(doc.to_string(), "node \"v1 string\" true null r#\"raw\"#");
+    ///
+    /// assert!(KdlDocument::parse_v1("node #true").is_err());
+    /// # Ok::<(), miette::Report>(())
+    /// ```
+    #[cfg(feature = "v1")]
+    pub fn parse_v1(input: &str) -> Result<Self, KdlParseFailure> {
+        crate::v2_parser::try_parse(crate::v1_parser::document, input)
+    }
+
+    /// Parses a document written in either KDL v1 or KDL v2, with the `v1`
+    /// feature.
+    ///
+    /// If the document starts with a `/- kdl-version 1` or `/- kdl-version 2`
+    /// marker, only that version is parsed. Otherwise, the document is
+    /// parsed as KDL v2 first, falling back to KDL v1 if that fails. Use
+    /// [`KdlDocument::version`] to find out which one was used.
+    ///
+    /// If neither version works, the error is the one from whichever parser
+    /// got further into the document.
+    ///
+    /// ```rust
+    /// # use kdl::{KdlDocument, KdlVersion};
+    /// let doc = KdlDocument::parse_auto("node \"v1 string\" true").unwrap();
+    /// assert_eq!(doc.version(), KdlVersion::V1);
+    /// assert_eq!(doc.to_string(), "node \"v1 string\" true");
+    ///
+    /// let doc = KdlDocument::parse_auto("node #true").unwrap();
+    /// assert_eq!(doc.version(), KdlVersion::V2);
+    /// ```
+    #[cfg(feature = "v1")]
+    pub fn parse_auto(input: &str) -> Result<Self, KdlParseFailure> {
+        use crate::{v1_parser, v2_parser};
+        let marker = v2_parser::try_parse(v2_parser::version_marker, input)
+            .ok()
+            .flatten()
+            .map(|(version, _)| version);
+        match marker {
+            Some(KdlVersion::V1) => v2_parser::try_parse(v1_parser::document, input),
+            Some(KdlVersion::V2) => v2_parser::try_parse(v2_parser::document, input),
+            None => v2_parser::try_parse(v2_parser::document, input).or_else(|v2_err| {
+                v2_parser::try_parse(v1_parser::document, input).map_err(|v1_err| {
+                    let furthest = |err: &KdlParseFailure| {
+                        err.diagnostics
+                            .iter()
+                            .map(|diag| diag.span.offset())
+                            .max()
+                            .unwrap_or(0)
+                    };
+                    if furthest(&v1_err) > furthest(&v2_err) {
+                        v1_err
+                    } else {
+                        v2_err
+                    }
+                })
+            }),
+        }
+    }
+
+    /// Parses a document using the given [`ParseOptions`].
+    ///
+    /// Parsing with [`ParseOptions::default()`] is the same as using
+    /// [`str::parse`].
+    pub fn parse_with(input: &str, options: &ParseOptions) -> Result<Self, KdlParseFailure> {
+        crate::v2_parser::try_parse_with(crate::v2_parser::document, input, options)
+    }
+
+    /// Parses a document as far as possible, returning whatever could be
+    /// parsed along with all the diagnostics for the parts that couldn't,
+    /// such as for an editor that wants to keep working with a broken
+    /// document.
+    ///
+    /// Broken nodes are skipped up to their node terminator, as with
+    /// [`ParseOptions::collect_all_diagnostics`], and left out of the
+    /// document, while the nodes around them, including their spans and
+    /// formatting, are kept. The recovered document doesn't necessarily
+    /// stringify back to `input`, and it's empty if nothing could be
+    /// recovered at all, such as when `input` is too long. If the diagnostics
+    /// are empty, the document is the same as the one [`str::parse`]
+    /// would've returned.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlDocument;
+    /// let (doc, diagnostics) = KdlDocument::parse_lossy("a 1\nb 2.\nc 3\n");
+    /// let names = doc
+    ///     .nodes()
+    ///     .iter()
+    ///     .map(|node| node.name().value().to_string())
+    ///     .collect::<Vec<_>>();
+    /// assert_eq!(names, ["a", "c"]);
+    /// assert_eq!(diagnostics.len(), 1);
+    /// ```
+    pub fn parse_lossy(input: &str) -> (Self, Vec<KdlDiagnostic>) {
+        let options = ParseOptions {
+            collect_all_diagnostics: true,
+            ..Default::default()
+        };
+        let (doc, diagnostics) =
+            crate::v2_parser::try_parse_lossy(crate::v2_parser::document, input, &options);
+        (doc.unwrap_or_default(), diagnostics)
+    }
+
+    /// Like [`KdlDocument::parse_lossy`], but for bytes that might not all
+    /// be valid UTF-8.
+    ///
+    /// Each invalid sequence is replaced with `U+FFFD REPLACEMENT CHARACTER`,
+    /// as by [`String::from_utf8_lossy`], and the result is parsed. Every
+    /// replacement gets a [`KdlErrorKind::InvalidUtf8`] warning, holding the
+    /// sequence's byte offset and length in `input`, and spanning its
+    /// replacement in the diagnostic's `input`. Those come first, followed
+    /// by any diagnostics from parsing. All spans, including those of the
+    /// document, are relative to the replaced text.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::{KdlDocument, KdlErrorKind};
+    /// let (doc, diagnostics) = KdlDocument::parse_bytes_lossy(b"a \"b\xffc\"\n");
+    /// assert_eq!(doc.to_string(), "a \"b\u{FFFD}c\"\n");
+    /// assert_eq!(diagnostics[0].kind, KdlErrorKind::InvalidUtf8(4, 1));
+    /// ```
+    pub fn parse_bytes_lossy(input: &[u8]) -> (Self, Vec<KdlDiagnostic>) {
+        let mut text = String::with_capacity(input.len());
+        let mut replacements = Vec::new();
+        let mut rest = input;
+        while !rest.is_empty() {
+            let offset = input.len() - rest.len();
+            match std::str::from_utf8(rest) {
+                Ok(valid) => {
+                    text.push_str(valid);
+                    break;
+                }
+                Err(err) => {
+                    let (valid, after) = rest.split_at(err.valid_up_to());
+                    text.push_str(std::str::from_utf8(valid).expect("checked above"));
+                    // Without an error length, the input ends in the middle
+                    // of a sequence.
+                    let len = err.error_len().unwrap_or(after.len());
+                    replacements.push((offset + valid.len(), len, text.len()));
+                    text.push(char::REPLACEMENT_CHARACTER);
+                    rest = &after[len..];
+                }
+            }
+        }
+        let (doc, parse_diagnostics) = Self::parse_lossy(&text);
+        let text = std::sync::Arc::new(text);
+        let mut diagnostics = replacements
+            .into_iter()
+            .map(|(offset, len, at)| KdlDiagnostic {
+                input: text.clone(),
+                span: (at, char::REPLACEMENT_CHARACTER.len_utf8()).into(),
+                label: Some("replaced"),
+                help: None,
+                severity: miette::Severity::Warning,
+                kind: KdlErrorKind::InvalidUtf8(offset, len),
+            })
+            .collect::<Vec<_>>();
+        diagnostics.extend(parse_diagnostics);
+        (doc, diagnostics)
+    }
+
+    /// Parses a document, stripping any leading byte order mark (BOM) and
+    /// reporting whether one was present.
+    ///
+    /// Normally, a BOM is kept as part of the document's leading format so
+    /// it round-trips. This is for callers that handle BOMs themselves: the
+    /// returned document never contains one, and the `bool` tells you
+    /// whether to re-emit it. Spans still refer to offsets in `input`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlDocument;
+    /// let (doc, had_bom) = KdlDocument::parse_reporting_bom("\u{FEFF}node 1").unwrap();
+    /// assert!(had_bom);
+    /// assert_eq!(doc.to_string(), "node 1");
+    /// ```
+    pub fn parse_reporting_bom(input: &str) -> Result<(Self, bool), KdlParseFailure> {
+        let mut doc: Self = input.parse()?;
+        let had_bom = input.starts_with('\u{FEFF}');
+        if had_bom {
+            if let Some(fmt) = doc.format_mut() {
+                fmt.leading = fmt.leading.trim_start_matches('\u{FEFF}').into();
+            }
+        }
+        Ok((doc, had_bom))
+    }
+
+    /// Creates a new document out of the top-level nodes at `indices`, in the
+    /// given order, keeping their children and formatting.
+    ///
+    /// Each node is reindented so it starts at column 0, along with
+    /// everything nested inside it. Whitespace before each node is dropped,
+    /// though comments attached to it are kept, and each node ends up on its
+    /// own line. The new document has no preamble or trailer of its own.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of `indices` is out of bounds.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlDocument;
+    /// let doc: KdlDocument = "a 1\nb {\n    c 2\n}\nd 3".parse().unwrap();
+    /// assert_eq!(doc.subset(&[2, 1]).to_string(), "d 3\nb {\n    c 2\n}\n");
+    /// ```
+    pub fn subset(&self, indices: &[usize]) -> KdlDocument {
+        let mut doc = KdlDocument::new();
+        for &index in indices {
+            let indent = self.node_indent(index);
+            let mut node = self.nodes[index].clone();
+            node.shift_indent_impl(
+                &IndentShift::Remove {
+                    unit: &indent,
+                    count: 1,
+                },
+                &mut true,
+            );
+            if let Some(fmt) = node.format_mut() {
+                fmt.leading = fmt.leading.trim_start().into();
+                if !format!("{}{}", fmt.terminator, fmt.trailing).ends_with(crate::fmt::is_newline)
+                {
+                    if fmt.terminator.is_empty() {
+                        fmt.terminator = "\n".into();
+                    } else {
+                        fmt.trailing.to_mut().push('\n');
+                    }
+                }
+            }
+            doc.nodes.push(node);
+        }
+        doc
+    }
+
+    /// Queries this document's nodes according to the [KQL query
+    /// language](KdlQuery), returning an iterator over all matching nodes,
+    /// in document order. `top()` stands for the document itself.
+    ///
+    /// Fails if `query` is a string that isn't a valid query.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlDocument;
+    /// let doc: KdlDocument = r#"
+    ///     package {
+    ///         name my-pkg
+    ///         dependencies {
+    ///             miette "7.2.0" optional=#true
+    ///             winnow "0.6"
+    ///         }
+    ///     }
+    ///     other { dependencies { ignored } }
+    /// "#.parse().unwrap();
+    ///
+    /// let deps = doc
+    ///     .query_all("top() > package > dependencies > []")
+    ///     .unwrap()
+    ///     .map(|node| node.name().value())
+    ///     .collect::<Vec<_>>();
+    /// assert_eq!(deps, ["miette", "winnow"]);
+    ///
+    /// let optional = doc.query_all("dependencies > [optional = #true]").unwrap();
+    /// assert_eq!(optional.count(), 1);
+    /// ```
+    pub fn query_all(
+        &self,
+        query: impl IntoKdlQuery,
+    ) -> Result<KdlQueryIterator<'_>, KdlParseFailure> {
+        Ok(KdlQueryIterator::new(&self.nodes, query.into_query()?))
+    }
+
+    /// Queries this document's nodes according to the [KQL query
+    /// language](KdlQuery), returning the first match, if any.
+    pub fn query(&self, query: impl IntoKdlQuery) -> Result<Option<&KdlNode>, KdlParseFailure> {
+        Ok(self.query_all(query)?.next())
+    }
+
+    /// Like [`Self::query_all`], but returns the matching nodes mutably, one
+    /// at a time. See [`KdlQueryIterMut`] for an example.
+    pub fn query_all_mut(
+        &mut self,
+        query: impl IntoKdlQuery,
+    ) -> Result<KdlQueryIterMut<'_>, KdlParseFailure> {
+        Ok(KdlQueryIterMut::new(&mut self.nodes, query.into_query()?))
+    }
+
+    /// Like [`Self::query`], but returns the first match mutably.
+    pub fn query_mut(
+        &mut self,
+        query: impl IntoKdlQuery,
+    ) -> Result<Option<&mut KdlNode>, KdlParseFailure> {
+        Ok(self.query_all_mut(query)?.into_first())
+    }
+
+    /// Queries this document's nodes according to the [KQL query
+    /// language](KdlQuery), picking the first match, and calling
+    /// [`.get(key)`](KdlNode::get) on it.
+    pub fn query_get(
+        &self,
+        query: impl IntoKdlQuery,
+        key: impl Into<NodeKey>,
+    ) -> Result<Option<&KdlValue>, KdlParseFailure> {
+        Ok(self.query(query)?.and_then(|node| node.get(key)))
+    }
+
+    /// Queries this document's nodes according to the [KQL query
+    /// language](KdlQuery), returning the requested value from each
+    /// matching node, and skipping nodes that don't have it.
+    pub fn query_get_all(
+        &self,
+        query: impl IntoKdlQuery,
+        key: impl Into<NodeKey>,
+    ) -> Result<impl Iterator<Item = &KdlValue>, KdlParseFailure> {
+        let key: NodeKey = key.into();
+        Ok(self
+            .query_all(query)?
+            .filter_map(move |node| node.get(key.clone())))
+    }
+
+    /// Gets the value at `path`, if there is one. See [`KdlPath`] for how
+    /// paths are resolved.
+    pub fn get_path(&self, path: &KdlPath) -> Option<&KdlValue> {
+        path.entry(&self.nodes).map(|entry| entry.value())
+    }
+
+    /// Gets a mutable reference to the value at `path`, if there is one.
+    pub fn get_path_mut(&mut self, path: &KdlPath) -> Option<&mut KdlValue> {
+        path.entry_mut(&mut self.nodes)
+            .map(|entry| entry.value_mut())
+    }
+
+    /// Sets the value at `path`, returning whether there was anywhere to
+    /// put it.
+    ///
+    /// A node with no arguments gets one. If `create` is true, nodes that
+    /// are missing along the way, including the last one, are appended
+    /// where they belong, so this always succeeds. New nodes don't have any
+    /// formatting of their own, so you may want to [`Self::autoformat`]
+    /// afterwards.
+    pub fn set_path(&mut self, path: &KdlPath, value: impl Into<KdlValue>, create: bool) -> bool {
+        path.set(&mut self.nodes, value, create).is_some()
+    }
+}
+
+/// The glob `segments` positions reachable from `states` by matching one more
+/// level named `name`. See [`KdlDocument::match_paths`].
+fn glob_step(segments: &[&str], states: &[usize], name: &str) -> Vec<usize> {
+    let mut next = Vec::new();
+    for &state in states {
+        match segments.get(state) {
+            Some(&"**") => next.push(state),
+            Some(segment) if glob_matches(segment, name) => next.push(state + 1),
+            _ => {}
+        }
+    }
+    glob_closure(segments, next)
+}
+
+/// Adds the positions after any `**` segments in `states`, since they can
+/// match zero levels.
+fn glob_closure(segments: &[&str], mut states: Vec<usize>) -> Vec<usize> {
+    let mut i = 0;
+    while i < states.len() {
+        if segments.get(states[i]) == Some(&"**") {
+            states.push(states[i] + 1);
+        }
+        i += 1;
+    }
+    states.sort_unstable();
+    states.dedup();
+    states
+}
+
+/// Whether `name` matches `pattern`, where `*` matches any run of characters
+/// and `?` any one character.
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+    let (mut p, mut n) = (0, 0);
+    // Where to retry from if the rest doesn't match: just after the last
+    // `*`, with it covering one more character.
+    let mut retry = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                retry = Some((p + 1, n));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match retry {
+                Some((star_p, star_n)) => {
+                    p = star_p;
+                    n = star_n + 1;
+                    retry = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Depth-first iterator over nodes and everything nested in them. See
+/// [`KdlDocument::descendants`].
+pub(crate) struct Descendants<'a> {
+    stack: Vec<std::slice::Iter<'a, KdlNode>>,
+}
+
+impl<'a> Descendants<'a> {
+    pub(crate) fn new(nodes: &'a [KdlNode]) -> Self {
+        Self {
+            stack: vec![nodes.iter()],
+        }
+    }
+}
+
+impl<'a> Iterator for Descendants<'a> {
+    type Item = &'a KdlNode;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.stack.last_mut()?.next() {
+                Some(node) => {
+                    if let Some(children) = &node.children {
+                        self.stack.push(children.nodes.iter());
+                    }
+                    return Some(node);
+                }
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "span")]
+fn span_contains(span: &SourceSpan, offset: usize) -> bool {
+    span.offset() <= offset && offset < span.offset() + span.len()
+}
+
+/// Parses two KDL documents and checks whether they mean the same thing,
+/// regardless of formatting and comments. See [`KdlDocument::semantic_eq`].
+///
+/// Returns the first parse failure, if either document fails to parse.
+///
+/// # Examples
+///
+/// ```rust
+/// assert!(kdl::semantically_equal(
+///     "node 0x10 key=\"value\" { child; }",
+///     "// same thing\nnode 16 \\\n    key=value {\n    child\n}\n",
+/// )?);
+/// assert!(!kdl::semantically_equal("node 1", "node 2")?);
+/// # Ok::<(), kdl::KdlParseFailure>(())
+/// ```
+pub fn semantically_equal(a: &str, b: &str) -> Result<bool, KdlParseFailure> {
+    let a: KdlDocument = a.parse()?;
+    let b: KdlDocument = b.parse()?;
+    Ok(a.semantic_eq(&b))
+}
+
+/// Splits a document into the source text of each of its top-level nodes,
+/// along with the parsed node.
+///
+/// Each node's text starts right after the previous node's terminator, so it
+/// includes any comments and whitespace before it, and the first and last
+/// ones also get whatever comes before and after all the nodes. Each one is
+/// a valid document with just that node in it, and joining them all back
+/// together gives back `input` exactly, unless `input` has no nodes at all,
+/// in which case there's nothing to return. The nodes' spans still refer to
+/// offsets in `input`.
+///
+/// # Examples
+///
+/// ```rust
+/// let input = "// config\nserver {\n    port 80\n}\n\nlog level=debug; cache\n";
+/// let parts = kdl::split_top_level(input)?;
+/// let texts = parts.iter().map(|(text, _)| text.as_str()).collect::<Vec<_>>();
+/// assert_eq!(
+///     texts,
+///     ["// config\nserver {\n    port 80\n}\n", "\nlog level=debug;", " cache\n"]
+/// );
+/// assert_eq!(parts[1].1.name().value(), "log");
+/// assert_eq!(texts.concat(), input);
+/// # Ok::<(), kdl::KdlParseFailure>(())
+/// ```
+#[cfg(feature = "span")]
+pub fn split_top_level(input: &str) -> Result<Vec<(String, KdlNode)>, KdlParseFailure> {
+    let doc: KdlDocument = input.parse()?;
+    let starts = doc
+        .nodes
+        .iter()
+        .enumerate()
+        .map(|(idx, node)| match idx {
+            0 => 0,
+            _ => node.span.offset() - node.format().map_or(0, |fmt| fmt.leading.len()),
+        })
+        .chain(std::iter::once(input.len()))
+        .collect::<Vec<_>>();
+    Ok(doc
+        .nodes
+        .into_iter()
+        .zip(starts.windows(2))
+        .map(|(node, range)| (input[range[0]..range[1]].to_string(), node))
+        .collect())
+}
+
+impl std::str::FromStr for KdlDocument {
+    type Err = KdlParseFailure;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        crate::v2_parser::try_parse(crate::v2_parser::document, s)
+    }
+}
+
+impl Display for KdlDocument {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.stringify(f, 0)
+    }
+}
+
+impl KdlDocument {
+    pub(crate) fn autoformat_impl(&mut self, depth: usize, config: &KdlFormatConfig<'_>) {
+        let indent = config.indent.repeat(depth);
+        if let Some(KdlDocumentFormat { leading, .. }) = self.format_mut() {
+            if config.remove_slashdashes {
+                *leading = crate::fmt::strip_slashdashes(leading, true).into();
+            }
+            crate::fmt::autoformat_leading(leading.to_mut(), &indent, config, false);
+        }
+        let mut has_nodes = false;
+        for node in &mut self.nodes {
+            node.autoformat_impl(depth, config, has_nodes);
+            has_nodes = true;
+        }
+        if let Some(KdlDocumentFormat { trailing, .. }) = self.format_mut() {
+            if config.remove_slashdashes {
+                *trailing = crate::fmt::strip_slashdashes(trailing, true).into();
+            }
+            crate::fmt::autoformat_trailing(trailing.to_mut(), config.no_comments);
+            if !has_nodes {
+                trailing.to_mut().push('\n');
+            }
+        }
+    }
+
+    /// Pads property keys so that the `=` of each node's first property lines
+    /// up with those of its siblings, then the same for second properties,
+    /// and so on, in this document and all its children blocks.
+    pub(crate) fn align_properties(&mut self) {
+        for column in 0.. {
+            let widths = self
+                .nodes
+                .iter()
+                .map(|node| node.property_key_width(column))
+                .collect::<Vec<_>>();
+            let max = match widths.iter().flatten().map(|(_, width)| *width).max() {
+                Some(max) => max,
+                None => break,
+            };
+            for (node, found) in self.nodes.iter_mut().zip(widths) {
+                if let Some((idx, width)) = found {
+                    if width < max {
+                        node.entries[idx].pad_after_key(max - width);
+                    }
+                }
+            }
+        }
+        for node in &mut self.nodes {
+            if let Some(children) = &mut node.children {
+                children.align_properties();
+            }
+        }
+    }
+
+    /// Re-indents this document, whose nodes are nested `depth` levels deep.
+    /// If `depth` is more than 0, this is a children block, and its trailing
+    /// text ends with the indentation of its closing brace.
+    pub(crate) fn reindent_impl(&mut self, unit: &str, depth: usize, at_line_start: &mut bool) {
+        let indent = unit.repeat(depth);
+        let outer = unit.repeat(depth.saturating_sub(1));
+        let has_nodes = !self.nodes.is_empty();
+        if let Some(fmt) = self.format.as_mut() {
+            let last = if has_nodes { &indent } else { &outer };
+            shift_decor(
+                fmt.leading.to_mut(),
+                &IndentShift::Set {
+                    indent: &indent,
+                    last,
+                },
+                at_line_start,
+                has_nodes || depth > 0,
+            );
+        }
+        for node in &mut self.nodes {
+            node.reindent_impl(unit, depth, at_line_start);
+        }
+        if let Some(fmt) = self.format.as_mut() {
+            shift_decor(
+                fmt.trailing.to_mut(),
+                &IndentShift::Set {
+                    indent: &indent,
+                    last: &outer,
+                },
+                at_line_start,
+                depth > 0,
+            );
+        }
+    }
+
+    /// `closes_block` says whether this is a children block, so its
+    /// trailing text is followed by a `}`.
+    pub(crate) fn shift_indent_impl(
+        &mut self,
+        shift: &IndentShift<'_>,
+        at_line_start: &mut bool,
+        closes_block: bool,
+    ) {
+        let has_nodes = !self.nodes.is_empty();
+        if let Some(fmt) = self.format.as_mut() {
+            shift_decor(
+                fmt.leading.to_mut(),
+                shift,
+                at_line_start,
+                has_nodes || closes_block,
+            );
+        }
+        for node in &mut self.nodes {
+            node.shift_indent_impl(shift, at_line_start);
+        }
+        if let Some(fmt) = self.format.as_mut() {
+            shift_decor(fmt.trailing.to_mut(), shift, at_line_start, closes_block);
+        }
+    }
+
+    /// The horizontal whitespace a top-level node is indented by.
+    fn node_indent(&self, index: usize) -> String {
+        let mut preceding = String::new();
+        if index == 0 {
+            if let Some(fmt) = self.format() {
+                preceding.push_str(&fmt.leading);
+            }
+        }
+        if let Some(fmt) = self.nodes[index].format() {
+            preceding.push_str(&fmt.leading);
+        }
+        let last_line = preceding
+            .rsplit(crate::fmt::is_newline)
+            .next()
+            .unwrap_or_default();
+        let content_len = last_line.trim_start().len();
+        last_line[..last_line.len() - content_len].into()
+    }
+
+    pub(crate) fn stringify(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        indent: usize,
+    ) -> std::fmt::Result {
+        if let Some(KdlDocumentFormat { leading, .. }) = self.format() {
+            write!(f, "{}", leading)?;
+        }
+        for node in &self.nodes {
+            node.stringify(f, indent)?;
+        }
+        if let Some(KdlDocumentFormat { trailing, .. }) = self.format() {
+            write!(f, "{}", trailing)?;
+        }
+        Ok(())
+    }
+}
+
+impl IntoIterator for KdlDocument {
+    type Item = KdlNode;
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.nodes.into_iter()
+    }
+}
+
+/// Whether `rest`, the text after a `;`, has nothing else before the end of
+/// its line but whitespace and comments, or the end of its block.
+#[cfg(feature = "span")]
+fn ends_line(rest: &str) -> bool {
+    let mut rest = rest.trim_start_matches(|c: char| c.is_whitespace() && !is_newline(c));
+    while let Some(comment) = rest.strip_prefix("/*") {
+        // Comments spanning lines count as ending this one.
+        match comment.find("*/") {
+            Some(end) if !comment[..end].contains(is_newline) => {
+                rest = comment[end + 2..]
+                    .trim_start_matches(|c: char| c.is_whitespace() && !is_newline(c));
+            }
+            _ => return true,
+        }
+    }
+    rest.is_empty()
+        || rest.starts_with(is_newline)
+        || rest.starts_with("//")
+        || rest.starts_with('}')
+}
+
+/// A style rule for node terminators, for use with
+/// [`KdlDocument::lint_terminators`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TerminatorRule {
+    /// Nodes must never be terminated with `;`. Nodes that share a line have
+    /// to be split up.
+    NoSemicolons,
+    /// `;` is only allowed between nodes on the same line, where it's needed,
+    /// and not at the end of a line or a block.
+    NoRedundantSemicolons,
+}
+
+impl Display for TerminatorRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoSemicolons => write!(f, "nodes must not end in `;`"),
+            Self::NoRedundantSemicolons => {
+                write!(f, "`;` is only allowed between nodes on the same line")
+            }
+        }
+    }
+}
+
+/// Options for [`KdlDocument::autoformat_config`]. The defaults format
+/// documents exactly like [`KdlDocument::autoformat`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdlFormatConfig<'a> {
+    /// What to indent each level of children blocks with. Four spaces by
+    /// default.
+    pub indent: &'a str,
+
+    /// How many blank lines in a row to keep between nodes and comments.
+    /// Longer runs are cut down to this many, and the default of `0` removes
+    /// them all. Blank lines at the start and end of a document or children
+    /// block are always removed.
+    pub max_blank_lines: usize,
+
+    /// Whether to remove comments, like
+    /// [`KdlDocument::autoformat_no_comments`]. Slashdashed nodes, entries,
+    /// and children blocks count as comments here, so they're removed too.
+    pub no_comments: bool,
+
+    /// Whether to remove slashdashed nodes, entries, and children blocks,
+    /// while keeping other comments.
+    pub remove_slashdashes: bool,
+
+    /// Whether to line up properties into columns, by padding the space
+    /// between their keys and their `=`s, gofmt-style. Each document and
+    /// children block is aligned on its own: the first properties of its
+    /// nodes line up with each other, then the second ones, and so on.
+    /// Arguments are left alone, other than being pushed to the right along
+    /// with the properties before them.
+    ///
+    /// Widths are counted in `char`s, so text that uses wide or combining
+    /// characters may not line up exactly.
+    pub align_properties: bool,
+}
+
+impl Default for KdlFormatConfig<'_> {
+    fn default() -> Self {
+        Self {
+            indent: "    ",
+            max_blank_lines: 0,
+            no_comments: false,
+            remove_slashdashes: false,
+            align_properties: false,
+        }
+    }
+}
+
+/// Which aspects of a document's formatting
+/// [`KdlDocument::canonicalize_selective`] should rewrite. Flags can be
+/// combined with `|`.
+///
+/// ```rust
+/// # use kdl::CanonicalizeFlags;
+/// let flags = CanonicalizeFlags::NUMBERS | CanonicalizeFlags::TYPE_SPACING;
+/// assert!(flags.contains(CanonicalizeFlags::NUMBERS));
+/// assert!(!flags.contains(CanonicalizeFlags::INDENTATION));
+/// ```
+#[derive(Debug, Clone, Copy, Default, Hash, Eq, PartialEq)]
+pub struct CanonicalizeFlags(u8);
+
+impl CanonicalizeFlags {
+    /// Rewrite nothing.
+    pub const NONE: Self = Self(0);
+    /// Write numbers in their canonical form, so `0x10` becomes `16` and
+    /// `1.5e1` becomes `15.0`.
+    pub const NUMBERS: Self = Self(1);
+    /// Write strings and identifiers as bare identifiers where possible, and
+    /// as quoted strings everywhere else. See
+    /// [`KdlDocument::normalize_string_style`].
+    pub const STRING_QUOTING: Self = Self(1 << 1);
+    /// Remove whitespace and comments in and around type annotations. See
+    /// [`KdlDocument::normalize_type_annotations`].
+    pub const TYPE_SPACING: Self = Self(1 << 2);
+    /// Indent each line by four spaces per nesting level, including comment
+    /// lines and closing braces.
+    pub const INDENTATION: Self = Self(1 << 3);
+    /// Rewrite everything listed above.
+    pub const ALL: Self = Self(0b1111);
+
+    /// Whether all the flags in `other` are set in `self`.
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl std::ops::BitOr for CanonicalizeFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl std::ops::BitOrAssign for CanonicalizeFlags {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+/// Formatting details for [`KdlDocument`]s.
+#[derive(Debug, Clone, Default, Hash, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KdlDocumentFormat {
+    /// Whitespace and comments preceding the document's first node.
+    pub leading: KdlTrivia,
+    /// Whitespace and comments following the document's last node.
+    pub trailing: KdlTrivia,
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(feature = "span")]
+    use crate::KdlIdentifier;
+    use crate::{KdlEntry, KdlValue};
+
+    use super::*;
+
+    #[test]
+    fn canonical_clear_fmt() -> miette::Result<()> {
+        let left_src = r#"
+// There is a node here
+first_node /*with cool comments, too */ param=1.03e2 /-"commented" "argument" {
+    // With nested nodes too
+    nested 1 2 3
+    nested_2 "hi" "world" // this one is cool
+}
+second_node param=153 { nested one=1 two=2; }"#;
+        let right_src = r#"
+first_node param=103.0       "argument" {
+        // Different indentation, because
+        // Why not
+        nested 1 2 3
+        nested_2 "hi" /* actually, "hello" */ "world"
+}
+// There is a node here
+second_node /* This time, the comment is here */ param=153 {
+        nested one=1 two=2
+}"#;
+        let mut left_doc: KdlDocument = left_src.parse()?;
+        let mut right_doc: KdlDocument = right_src.parse()?;
+        assert_ne!(left_doc, right_doc);
+        left_doc.clear_format_recursive();
+        right_doc.clear_format_recursive();
+        assert_eq!(left_doc, right_doc);
+        Ok(())
+    }
+
+    #[test]
+    fn basic_parsing() -> miette::Result<()> {
+        let src = r#"
+            // Hello, world!
+            node 1
             node two
             node item="three";
             node {
@@ -448,384 +2151,1775 @@ second_node /* This time, the comment is here */ param=153 {
     }
 
     #[test]
-    fn parsing() -> miette::Result<()> {
-        let src = "
-// This is the first node
-foo 1 2 three #null #true bar=\"baz\" {
-    - 1
-    - 2
-    - three
-    (mytype)something (\"name\")else\r
+    fn empty_documents() -> miette::Result<()> {
+        let collect_all = ParseOptions {
+            collect_all_diagnostics: true,
+            ..Default::default()
+        };
+        for input in [
+            "",
+            "   \n  ",
+            "\n\n",
+            "\u{FEFF}",
+            "\u{FEFF}  \n",
+            "// comment\n",
+            "/* comment */",
+            "/- node\n",
+            "/- node;",
+        ] {
+            for doc in [
+                input.parse::<KdlDocument>()?,
+                KdlDocument::parse_with(input, &collect_all)?,
+            ] {
+                assert!(doc.nodes().is_empty(), "{input:?}");
+                // With no nodes, everything ends up in the leading format,
+                // not split between leading and trailing.
+                let fmt = doc.format().unwrap();
+                assert_eq!(fmt.leading, input);
+                assert_eq!(fmt.trailing, "");
+                assert_eq!(doc.to_string(), input);
+            }
+
+            let (doc, had_bom) = KdlDocument::parse_reporting_bom(input)?;
+            assert!(doc.nodes().is_empty());
+            assert_eq!(had_bom, input.starts_with('\u{FEFF}'));
+            assert_eq!(doc.to_string(), input.trim_start_matches('\u{FEFF}'));
+
+            #[cfg(feature = "span")]
+            assert!(super::split_top_level(input)?.is_empty());
+            assert!(crate::validate_reader(input.as_bytes()).is_ok());
+        }
+
+        // A terminator needs a node to terminate.
+        for input in [";", "  ;  ", "\u{FEFF};"] {
+            assert!(input.parse::<KdlDocument>().is_err(), "{input:?}");
+            assert!(KdlDocument::parse_with(input, &collect_all).is_err());
+            assert!(crate::validate_reader(input.as_bytes()).is_err());
+        }
+        assert!("node {;}".parse::<KdlDocument>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_lossy() -> miette::Result<()> {
+        let input = "a 1\nb {\n    c 1.\n    d\n}\ne 0x; f #true\n";
+        let (doc, diagnostics) = KdlDocument::parse_lossy(input);
+        // Same diagnostics as a failed parse that collects all of them.
+        let failure = KdlDocument::parse_with(
+            input,
+            &ParseOptions {
+                collect_all_diagnostics: true,
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+        assert_eq!(diagnostics, failure.diagnostics);
+        assert_eq!(diagnostics.len(), 2);
+
+        let names = |doc: &KdlDocument| {
+            doc.nodes()
+                .iter()
+                .map(|node| node.name().value().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&doc), ["a", "b", "f"]);
+        assert_eq!(names(doc.get("b").unwrap().children().unwrap()), ["d"]);
+        assert_eq!(doc.get_arg("f"), Some(&KdlValue::Bool(true)));
+        #[cfg(feature = "span")]
+        for node in doc.nodes() {
+            let span = node.span();
+            assert!(input[span.offset()..].starts_with(node.name().value()));
+        }
+
+        // Valid input gives the same document as a normal parse.
+        let input = "a 1\nb {\n    c\n}\n";
+        let (doc, diagnostics) = KdlDocument::parse_lossy(input);
+        assert!(diagnostics.is_empty());
+        assert_eq!(doc, input.parse()?);
+
+        // Everything before a broken children block is kept, and so is
+        // what's in it.
+        let (doc, diagnostics) = KdlDocument::parse_lossy("a 1\nb {\n    c 1.\n    d\n");
+        assert_eq!(names(&doc), ["a", "b"]);
+        assert_eq!(names(doc.get("b").unwrap().children().unwrap()), ["d"]);
+        assert_eq!(diagnostics.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_bytes_lossy() -> miette::Result<()> {
+        let input = b"a \"\xc3\x28\" 1\nb \xf0\x9f\x98\xff 2.\nc \"\xe9\"";
+        let (doc, diagnostics) = KdlDocument::parse_bytes_lossy(input);
+        let kinds = diagnostics
+            .iter()
+            .map(|diag| diag.kind.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                KdlErrorKind::InvalidUtf8(3, 1),
+                KdlErrorKind::InvalidUtf8(11, 3),
+                KdlErrorKind::InvalidUtf8(14, 1),
+                KdlErrorKind::InvalidUtf8(22, 1),
+                KdlErrorKind::Context("float"),
+            ]
+        );
+        for diag in &diagnostics[..4] {
+            assert_eq!(diag.severity, miette::Severity::Warning);
+            assert_eq!(
+                &diag.input[diag.span.offset()..][..diag.span.len()],
+                "\u{FFFD}"
+            );
+        }
+        assert_eq!(doc.to_string(), "a \"\u{FFFD}(\" 1\nc \"\u{FFFD}\"");
+        Ok(())
+    }
+
+    #[test]
+    fn declared_version() -> miette::Result<()> {
+        let mut doc: KdlDocument = "\u{FEFF}node 1\n".parse()?;
+        assert_eq!(doc.declared_version(), None);
+        doc.set_declared_version(Some(KdlVersion::V1));
+        assert_eq!(doc.to_string(), "\u{FEFF}/- kdl-version 1\nnode 1\n");
+        assert_eq!(doc.declared_version(), Some(KdlVersion::V1));
+        doc.set_declared_version(None);
+        assert_eq!(doc.to_string(), "\u{FEFF}node 1\n");
+
+        let mut doc: KdlDocument = "/- kdl-version 2;\r\nnode 1\n".parse()?;
+        assert_eq!(doc.declared_version(), Some(KdlVersion::V2));
+        doc.set_declared_version(None);
+        assert_eq!(doc.to_string(), "\r\nnode 1\n");
+
+        // Only a marker at the very start counts.
+        let doc: KdlDocument = "node 1\n/- kdl-version 2\n".parse()?;
+        assert_eq!(doc.declared_version(), None);
+
+        let mut doc = KdlDocument::new();
+        doc.set_declared_version(None);
+        assert_eq!(doc, KdlDocument::new());
+        doc.set_declared_version(Some(KdlVersion::V2));
+        doc.nodes_mut().push(KdlNode::new("node"));
+        doc.autoformat();
+        assert_eq!(doc.to_string(), "/- kdl-version 2\nnode\n");
+        Ok(())
+    }
+
+    #[cfg(feature = "v1")]
+    #[test]
+    fn parse_v1() -> miette::Result<()> {
+        // Valid in both versions, but always read as v1.
+        let doc = KdlDocument::parse_v1("node 1 \"two\" { child; }")?;
+        assert_eq!(doc.version(), KdlVersion::V1);
+        assert_eq!(
+            doc.get("node").unwrap().children().unwrap().version(),
+            KdlVersion::V1
+        );
+        assert_eq!(doc.to_string(), "node 1 \"two\" { child; }");
+
+        assert!(KdlDocument::parse_v1("node #true").is_err());
+        assert!(KdlDocument::parse_v1("node \"\"\"\nmultiline\n\"\"\"").is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "v1")]
+    #[test]
+    fn parse_auto() -> miette::Result<()> {
+        let input = "node \"val\" true r\"raw\" {\n    (t)foo null; bar\n}\n";
+        let doc = KdlDocument::parse_auto(input)?;
+        assert_eq!(doc.version(), KdlVersion::V1);
+        assert_eq!(doc.to_string(), input);
+        assert_eq!(doc.get_arg("node"), Some(&"val".into()));
+        assert_eq!(doc.get_args("node")[1], &KdlValue::Bool(true));
+        let children = doc.get("node").unwrap().children().unwrap();
+        assert_eq!(children.version(), KdlVersion::V1);
+        assert_eq!(children.nodes()[0].ty().map(|t| t.value()), Some("t"));
+
+        let input = "node #true \"val\"\n";
+        let doc = KdlDocument::parse_auto(input)?;
+        assert_eq!(doc.version(), KdlVersion::V2);
+        assert_eq!(doc.to_string(), input);
+        assert_eq!(doc, input.parse()?);
+
+        // Valid in both versions, so v2 wins without a marker.
+        assert_eq!(
+            KdlDocument::parse_auto("node 1 \"two\"")?.version(),
+            KdlVersion::V2
+        );
+        for (input, version) in [
+            ("/- kdl-version 1\nnode 1 \"two\"", KdlVersion::V1),
+            ("\u{FEFF}// hi\n/-kdl-version 1;node true", KdlVersion::V1),
+            ("/- kdl-version 2\nnode 1 \"two\"", KdlVersion::V2),
+        ] {
+            let doc = KdlDocument::parse_auto(input)?;
+            assert_eq!(doc.version(), version, "{input:?}");
+            assert_eq!(doc.to_string(), input);
+        }
+        // The marker means only that version is tried.
+        assert!(KdlDocument::parse_auto("/- kdl-version 2\nnode true").is_err());
+        assert!(KdlDocument::parse_auto("/- kdl-version 1\nnode #true").is_err());
+
+        // When both fail, the error comes from whichever got further.
+        let err = KdlDocument::parse_auto("node \"v1\" true\nother foo\n").unwrap_err();
+        assert_eq!(err.diagnostics.len(), 1);
+        assert_eq!(err.diagnostics[0].span.offset(), 21);
+        assert_eq!(
+            err.diagnostics[0].label,
+            Some("plain identifiers can't be used here")
+        );
+        let err = KdlDocument::parse_auto("node #true\nother 1 x=").unwrap_err();
+        assert_eq!(
+            err.diagnostics,
+            "node #true\nother 1 x="
+                .parse::<KdlDocument>()
+                .unwrap_err()
+                .diagnostics
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parsing() -> miette::Result<()> {
+        let src = "
+// This is the first node
+foo 1 2 three #null #true bar=\"baz\" {
+    - 1
+    - 2
+    - three
+    (mytype)something (\"name\")else\r
+}
+
+null_id null_prop=#null
+true_id true_prop=#null
++false #true
+
+         bar \"indented\" // trailing whitespace after this\t
+/*
+Some random comment
+ */
+
+a; b; c;
+/-commented \"node\"
+
+another /*foo*/ \"node\" /-1 /*bar*/ #null;
+final;";
+        let mut doc: KdlDocument = src.parse()?;
+
+        assert_eq!(doc.get_arg("foo"), Some(&1.into()));
+        assert_eq!(
+            doc.get_dash_args("foo"),
+            vec![&1.into(), &2.into(), &"three".into()]
+        );
+        assert_eq!(
+            doc.format().map(|f| &f.leading[..]),
+            Some("\n// This is the first node\n")
+        );
+
+        let foo = doc.get("foo").expect("expected a foo node");
+        assert_eq!(foo.format().map(|f| &f.terminator[..]), Some("\n"));
+        assert_eq!(&foo[2], &"three".into());
+        assert_eq!(&foo["bar"], &"baz".into());
+        assert_eq!(
+            foo.children().unwrap().get_arg("something"),
+            Some(&"else".into())
+        );
+        assert_eq!(doc.get_arg("another"), Some(&"node".into()));
+
+        let null = doc.get("null_id").expect("expected a null_id node");
+        assert_eq!(&null["null_prop"], &KdlValue::Null);
+
+        let tru = doc.get("true_id").expect("expected a true_id node");
+        assert_eq!(&tru["true_prop"], &KdlValue::Null);
+
+        let plusfalse = doc.get("+false").expect("expected a +false node");
+        assert_eq!(&plusfalse[0], &KdlValue::Bool(true));
+
+        let bar = doc.get("bar").expect("expected a bar node");
+        assert_eq!(
+            format!("{}", bar),
+            "\n         bar \"indented\" // trailing whitespace after this\t\n"
+        );
+
+        let a = doc.get("a").expect("expected a node");
+        assert_eq!(
+            format!("{}", a),
+            "/*\nSome random comment\n */\n\na;".to_string()
+        );
+
+        let b = doc.get("b").expect("expected a node");
+        assert_eq!(format!("{}", b), " b;".to_string());
+
+        // Round-tripping works.
+        assert_eq!(format!("{}", doc), src);
+
+        // Programmatic manipulation works.
+        let mut node: KdlNode = "new\n".parse()?;
+        // Manual entry parsing preserves formatting/reprs. Note that
+        // if you're making KdlEntries this way, you need to inject
+        // your own whitespace (or format the node)
+        node.push(" \"blah\"=0xDEADbeef".parse::<KdlEntry>()?);
+        dbg!(&node);
+        doc.nodes_mut().push(node);
+
+        assert_eq!(
+            format!("{}", doc),
+            format!("{}new \"blah\"=0xDEADbeef\n", src)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn construction() {
+        let mut doc = KdlDocument::new();
+        doc.nodes_mut().push(KdlNode::new("foo"));
+
+        let mut bar = KdlNode::new("bar");
+        bar.insert("prop", "value");
+        bar.push(1);
+        bar.push(2);
+        bar.push(false);
+        bar.push(KdlValue::Null);
+
+        let subdoc = bar.ensure_children();
+        subdoc.nodes_mut().push(KdlNode::new("barchild"));
+        doc.nodes_mut().push(bar);
+        doc.nodes_mut().push(KdlNode::new("baz"));
+
+        doc.autoformat();
+
+        assert_eq!(
+            r#"foo
+bar prop=value 1 2 #false #null {
+    barchild
 }
+baz
+"#,
+            format!("{}", doc)
+        );
+    }
 
-null_id null_prop=#null
-true_id true_prop=#null
-+false #true
+    #[ignore = "There's still issues around formatting comments and esclines."]
+    #[test]
+    fn autoformat() -> miette::Result<()> {
+        let mut doc: KdlDocument = r##"
 
-         bar \"indented\" // trailing whitespace after this\t
-/*
-Some random comment
- */
+        /* x */ foo    1 "bar"=0xDEADbeef {
+    child1     1  ;
 
-a; b; c;
-/-commented \"node\"
+ // child 2 comment
 
-another /*foo*/ \"node\" /-1 /*bar*/ #null;
-final;";
-        let mut doc: KdlDocument = src.parse()?;
+        child2 2 // comment
+
+               child3    "
+
+   string\t
+   " \
+{
+       /*
+
+
+       multiline*/
+                                    inner1    \
+                    #"value"# \
+                    ;
+
+        inner2      \ //comment
+        {
+            inner3
+        }
+    }
+               }
+
+        // trailing comment here
+
+        "##
+        .parse()?;
+
+        KdlDocument::autoformat(&mut doc);
 
-        assert_eq!(doc.get_arg("foo"), Some(&1.into()));
         assert_eq!(
-            doc.get_dash_args("foo"),
-            vec![&1.into(), &2.into(), &"three".into()]
+            doc.to_string(),
+            r#"/* x */
+foo 1 bar=0xdeadbeef {
+    child1 1
+    // child 2 comment
+    child2 2 // comment
+    child3 "\nstring\t" {
+        /*
+
+
+       multiline*/
+        inner1 value
+        inner2 {
+            inner3
+        }
+    }
+}
+// trailing comment here"#
         );
+        Ok(())
+    }
+
+    #[test]
+    fn simple_autoformat() -> miette::Result<()> {
+        let mut doc: KdlDocument = "a { b { c { }; }; }".parse().unwrap();
+        KdlDocument::autoformat(&mut doc);
         assert_eq!(
-            doc.format().map(|f| &f.leading[..]),
-            Some("\n// This is the first node\n")
+            doc.to_string(),
+            r#"a {
+    b {
+        c {
+
+        }
+    }
+}
+"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn autoformat_config() -> miette::Result<()> {
+        let input = "// c\n\n\na 1 /-2 /-x=1 3 /-{ y } {\n  // k\n\n\n  b { c; }\n\n\n\n  /-d\n  e\n}\n/-b { z }\n\n\nc\n";
+
+        let mut doc: KdlDocument = input.parse()?;
+        doc.autoformat_config(&KdlFormatConfig::default());
+        let mut expected: KdlDocument = input.parse()?;
+        expected.autoformat();
+        assert_eq!(doc.to_string(), expected.to_string());
+        assert_eq!(
+            doc.to_string(),
+            "// c\na 1 /-2 /-x=1 3 /-{ y } {\n    // k\n    b {\n        c\n    }\n    /-d\n    e\n}\n/-b { z }\nc\n"
+        );
+
+        let mut doc: KdlDocument = input.parse()?;
+        doc.autoformat_config(&KdlFormatConfig {
+            indent: "\t",
+            max_blank_lines: 1,
+            remove_slashdashes: true,
+            ..Default::default()
+        });
+        assert_eq!(
+            doc.to_string(),
+            "// c\n\na 1 3 {\n\t// k\n\n\tb {\n\t\tc\n\t}\n\n\te\n}\n\nc\n"
+        );
+
+        let mut doc: KdlDocument = input.parse()?;
+        doc.autoformat_config(&KdlFormatConfig {
+            indent: "  ",
+            max_blank_lines: 2,
+            no_comments: true,
+            ..Default::default()
+        });
+        assert_eq!(
+            doc.to_string(),
+            "a 1 3 {\n  b {\n    c\n  }\n\n\n  e\n}\n\n\nc\n"
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "span")]
+    fn check_spans_for_doc(doc: &KdlDocument, source: &impl miette::SourceCode) {
+        for node in doc.nodes() {
+            check_spans_for_node(node, source);
+        }
+    }
+
+    #[cfg(feature = "span")]
+    fn check_spans_for_node(node: &KdlNode, source: &impl miette::SourceCode) {
+        use crate::KdlEntryFormat;
+
+        check_span_for_ident(node.name(), source);
+        if let Some(ty) = node.ty() {
+            check_span_for_ident(ty, source);
+        }
+
+        for entry in node.entries() {
+            if let Some(name) = entry.name() {
+                check_span_for_ident(name, source);
+            }
+            if let Some(ty) = entry.ty() {
+                check_span_for_ident(ty, source);
+            }
+            if let Some(KdlEntryFormat { value_repr, .. }) = entry.format() {
+                if entry.name().is_none() && entry.ty().is_none() {
+                    check_span(value_repr, entry.span(), source);
+                }
+            }
+        }
+        if let Some(children) = node.children() {
+            check_spans_for_doc(children, source);
+        }
+    }
+
+    #[cfg(feature = "span")]
+    #[track_caller]
+    fn check_span_for_ident(ident: &KdlIdentifier, source: &impl miette::SourceCode) {
+        if let Some(repr) = ident.repr() {
+            check_span(repr, ident.span(), source);
+        } else {
+            check_span(ident.value(), ident.span(), source);
+        }
+    }
+
+    #[cfg(feature = "span")]
+    #[track_caller]
+    fn check_span(expected: &str, span: SourceSpan, source: &impl miette::SourceCode) {
+        let span = source.read_span(&span, 0, 0).unwrap();
+        let span = std::str::from_utf8(span.data()).unwrap();
+        assert_eq!(span, expected);
+    }
+
+    #[cfg(feature = "span")]
+    #[test]
+    fn span_test() -> miette::Result<()> {
+        let input = r####"
+this {
+    is (a)"cool" document="to" read=(int)5 10.1 (u32)0x45
+    and x="" {
+        "it" /*shh*/ "has"="💯" ##"the"##
+        Best🎊est
+        "syntax ever"
+    }
+    "yknow?" 0x10
+}
+// that's
+nice
+inline { time; to; live "our" "dreams"; "y;all" }
+
+"####;
+
+        let doc: KdlDocument = input.parse()?;
+
+        // First check that all the identity-spans are correct
+        check_spans_for_doc(&doc, &input);
+
+        // Now check some more interesting concrete spans
+
+        // The whole document should be everything from the first node until the
+        // last before_terminator whitespace.
+        check_span(&input[1..(input.len() - 2)], doc.span(), &input);
+
+        // This one-liner node should be the whole line without leading whitespace
+        let is_node = doc
+            .get("this")
+            .unwrap()
+            .children()
+            .unwrap()
+            .get("is")
+            .unwrap();
+        check_span(
+            r##"is (a)"cool" document="to" read=(int)5 10.1 (u32)0x45"##,
+            is_node.span(),
+            &input,
         );
 
-        let foo = doc.get("foo").expect("expected a foo node");
-        assert_eq!(foo.format().map(|f| &f.terminator[..]), Some("\n"));
-        assert_eq!(&foo[2], &"three".into());
-        assert_eq!(&foo["bar"], &"baz".into());
-        assert_eq!(
-            foo.children().unwrap().get_arg("something"),
-            Some(&"else".into())
+        // Some simple with/without type hints
+        check_span(r#"(a)"cool""#, is_node.entry(0).unwrap().span(), &input);
+        check_span(
+            r#"read=(int)5"#,
+            is_node.entry("read").unwrap().span(),
+            &input,
         );
-        assert_eq!(doc.get_arg("another"), Some(&"node".into()));
-
-        let null = doc.get("null_id").expect("expected a null_id node");
-        assert_eq!(&null["null_prop"], &KdlValue::Null);
-
-        let tru = doc.get("true_id").expect("expected a true_id node");
-        assert_eq!(&tru["true_prop"], &KdlValue::Null);
+        check_span(r#"10.1"#, is_node.entry(1).unwrap().span(), &input);
+        check_span(r#"(u32)0x45"#, is_node.entry(2).unwrap().span(), &input);
 
-        let plusfalse = doc.get("+false").expect("expected a +false node");
-        assert_eq!(&plusfalse[0], &KdlValue::Bool(true));
+        // Now let's look at some messed up parts of that "and" node
+        let and_node = doc
+            .get("this")
+            .unwrap()
+            .children()
+            .unwrap()
+            .get("and")
+            .unwrap();
 
-        let bar = doc.get("bar").expect("expected a bar node");
-        assert_eq!(
-            format!("{}", bar),
-            "\n         bar \"indented\" // trailing whitespace after this\t\n"
+        // The node is what you expect, the whole line and its two braces
+        check_span(
+            r####"and x="" {
+        "it" /*shh*/ "has"="💯" ##"the"##
+        Best🎊est
+        "syntax ever"
+    }"####,
+            and_node.span(),
+            &input,
         );
 
-        let a = doc.get("a").expect("expected a node");
-        assert_eq!(
-            format!("{}", a),
-            "/*\nSome random comment\n */\n\na;".to_string()
+        // The child document is a little weird, it's the contents *inside* the braces
+        // without the surrounding whitespace/comments. Just the actual contents.
+        check_span(
+            r####""it" /*shh*/ "has"="💯" ##"the"##
+        Best🎊est
+        "syntax ever""####,
+            and_node.children().unwrap().span(),
+            &input,
         );
 
-        let b = doc.get("b").expect("expected a node");
-        assert_eq!(format!("{}", b), " b;".to_string());
+        // Oh hey don't forget to check that "x" entry
+        check_span(r#"x="""#, and_node.entry("x").unwrap().span(), &input);
 
-        // Round-tripping works.
-        assert_eq!(format!("{}", doc), src);
+        // Now the "it" node, more straightforward
+        let it_node = and_node.children().unwrap().get("it").unwrap();
+        check_span(
+            r####""it" /*shh*/ "has"="💯" ##"the"##"####,
+            it_node.span(),
+            &input,
+        );
+        check_span(
+            r#""has"="💯""#,
+            it_node.entry("has").unwrap().span(),
+            &input,
+        );
+        check_span(
+            r####"##"the"##"####,
+            it_node.entry(0).unwrap().span(),
+            &input,
+        );
 
-        // Programmatic manipulation works.
-        let mut node: KdlNode = "new\n".parse()?;
-        // Manual entry parsing preserves formatting/reprs. Note that
-        // if you're making KdlEntries this way, you need to inject
-        // your own whitespace (or format the node)
-        node.push(" \"blah\"=0xDEADbeef".parse::<KdlEntry>()?);
-        dbg!(&node);
-        doc.nodes_mut().push(node);
+        // Make sure inline nodes work ok
+        let inline_node = doc.get("inline").unwrap();
+        check_span(
+            r#"inline { time; to; live "our" "dreams"; "y;all" }"#,
+            inline_node.span(),
+            &input,
+        );
 
-        assert_eq!(
-            format!("{}", doc),
-            format!("{}new \"blah\"=0xDEADbeef\n", src)
+        let inline_children = inline_node.children().unwrap();
+        check_span(
+            r#"time; to; live "our" "dreams"; "y;all" "#,
+            inline_children.span(),
+            &input,
         );
 
+        let inline_nodes = inline_children.nodes();
+        check_span("time", inline_nodes[0].span(), &input);
+        check_span("to", inline_nodes[1].span(), &input);
+        check_span(r#"live "our" "dreams""#, inline_nodes[2].span(), &input);
+        check_span(r#""y;all""#, inline_nodes[3].span(), &input);
+
         Ok(())
     }
 
     #[test]
-    fn construction() {
-        let mut doc = KdlDocument::new();
-        doc.nodes_mut().push(KdlNode::new("foo"));
-
-        let mut bar = KdlNode::new("bar");
-        bar.insert("prop", "value");
-        bar.push(1);
-        bar.push(2);
-        bar.push(false);
-        bar.push(KdlValue::Null);
+    fn slashdashed_children() -> miette::Result<()> {
+        for src in [
+            "foo { /- bar }",
+            "foo {/-bar}",
+            "foo { /- bar; }",
+            "foo { /- bar; /- baz }",
+            "foo {\n    /- bar 1 2 { x }\n    /-baz;\n}\n",
+        ] {
+            let doc: KdlDocument = src.parse()?;
+            let children = doc.nodes()[0]
+                .children()
+                .expect("children block should be present");
+            assert!(
+                children.nodes().is_empty(),
+                "{src:?} should have no children"
+            );
+            assert_eq!(doc.to_string(), src);
+        }
 
-        let subdoc = bar.ensure_children();
-        subdoc.nodes_mut().push(KdlNode::new("barchild"));
-        doc.nodes_mut().push(bar);
-        doc.nodes_mut().push(KdlNode::new("baz"));
+        let doc: KdlDocument = "foo { /- a; b; /- c }".parse()?;
+        let children = doc.nodes()[0].children().unwrap();
+        assert_eq!(children.nodes().len(), 1);
+        assert_eq!(children.nodes()[0].name().value(), "b");
+        assert_eq!(doc.to_string(), "foo { /- a; b; /- c }");
 
-        doc.autoformat();
+        let doc: KdlDocument = "/- a;\nb\n/- c;".parse()?;
+        assert_eq!(doc.nodes().len(), 1);
+        assert_eq!(doc.to_string(), "/- a;\nb\n/- c;");
+        Ok(())
+    }
 
+    #[test]
+    fn normalize_type_annotations() -> miette::Result<()> {
+        let mut doc: KdlDocument =
+            "( u8 )node x=( t ) 1 ( \"a b\" )2 (t)3 {\n  ( /* q */ q )c (#\"raw\"#)\"s\"\n}\n"
+                .parse()?;
+        doc.normalize_type_annotations();
         assert_eq!(
-            r#"foo
-bar prop=value 1 2 #false #null {
-    barchild
-}
-baz
-"#,
-            format!("{}", doc)
+            doc.to_string(),
+            "(u8)node x=(t)1 (\"a b\")2 (t)3 {\n  (q)c (#\"raw\"#)\"s\"\n}\n"
         );
+        let node = &doc.nodes()[0];
+        let fmt = node.format().unwrap();
+        assert!(fmt.before_ty_name.is_empty() && fmt.after_ty_name.is_empty());
+        Ok(())
     }
 
-    #[ignore = "There's still issues around formatting comments and esclines."]
+    #[cfg(feature = "span")]
     #[test]
-    fn autoformat() -> miette::Result<()> {
-        let mut doc: KdlDocument = r##"
-
-        /* x */ foo    1 "bar"=0xDEADbeef {
-    child1     1  ;
-
- // child 2 comment
+    fn nodes_with_spans() -> miette::Result<()> {
+        let input = "a 1\nparent {\n    child x=1 { leaf; }\n    other\n}\nz";
+        let doc: KdlDocument = input.parse()?;
+        let top = doc
+            .nodes_with_spans()
+            .map(|(idx, span, node)| {
+                assert_eq!(*span, node.span());
+                (idx, &input[span.offset()..span.offset() + span.len()])
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(top.len(), 3);
+        assert_eq!(top[0], (0, "a 1"));
+        assert_eq!(top[2], (2, "z"));
+
+        let all = doc
+            .nodes_with_spans_recursive()
+            .map(|(path, span, node)| {
+                let text = &input[span.offset()..span.offset() + span.len()];
+                assert!(text.starts_with(node.name().value()));
+                (path, node.name().value().to_string())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            all,
+            vec![
+                (vec![0], "a".into()),
+                (vec![1], "parent".into()),
+                (vec![1, 0], "child".into()),
+                (vec![1, 0, 0], "leaf".into()),
+                (vec![1, 1], "other".into()),
+                (vec![2], "z".into()),
+            ]
+        );
+        assert_eq!(KdlDocument::new().nodes_with_spans_recursive().count(), 0);
+        Ok(())
+    }
 
-        child2 2 // comment
+    #[test]
+    fn match_paths() -> miette::Result<()> {
+        let doc: KdlDocument = "a { b { c; a { b; }; }; bc { c; } }\nb { c; }".parse()?;
+        let paths = |pattern| {
+            let mut out = Vec::new();
+            for node in doc.match_paths(pattern) {
+                let mut found = None;
+                doc.for_each_with_path(|ancestors, candidate| {
+                    if std::ptr::eq(node, candidate) {
+                        found = Some(format!("{}/{}", ancestors.join("/"), node.name().value()));
+                    }
+                });
+                out.push(found.unwrap());
+            }
+            out
+        };
+        assert_eq!(paths("a/b/c"), ["a/b/c"]);
+        assert_eq!(paths("*/c"), ["b/c"]);
+        assert_eq!(paths("a/*/c"), ["a/b/c", "a/bc/c"]);
+        assert_eq!(paths("a/b?/c"), ["a/bc/c"]);
+        assert_eq!(paths("/a//b/"), ["a/b"]);
+        assert_eq!(paths("**/b"), ["a/b", "a/b/a/b", "/b"]);
+        assert_eq!(paths("a/**/b"), ["a/b", "a/b/a/b"]);
+        assert_eq!(
+            paths("**/a/**"),
+            ["/a", "a/b", "a/b/c", "a/b/a", "a/b/a/b", "a/bc", "a/bc/c"]
+        );
+        assert_eq!(paths("**").len(), 9);
+        assert_eq!(paths("**/**/c").len(), 3);
+        assert!(paths("").is_empty());
+        assert!(paths("x/**").is_empty());
+        Ok(())
+    }
 
-               child3    "
+    #[test]
+    fn glob_matches() {
+        for (pattern, name, matches) in [
+            ("*", "", true),
+            ("*", "abc", true),
+            ("a*c", "abbbc", true),
+            ("a*c", "abcb", false),
+            ("*b*", "abc", true),
+            ("a?c", "abc", true),
+            ("a?c", "ac", false),
+            ("**", "x", true),
+            ("*a*a", "aaba", true),
+            ("é*", "éa", true),
+            ("abc", "ab", false),
+        ] {
+            assert_eq!(
+                super::glob_matches(pattern, name),
+                matches,
+                "{pattern:?} {name:?}"
+            );
+        }
+    }
 
-   string\t
-   " \
-{
-       /*
+    #[test]
+    fn descendants() -> miette::Result<()> {
+        let doc: KdlDocument = "a { b { a; c; }; a { d; } }\nb\na".parse()?;
+        let names = doc
+            .descendants()
+            .map(|node| node.name().value())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a", "b", "a", "c", "a", "d", "b", "a"]);
+        assert_eq!(doc.descendants_named("a").count(), 4);
+        assert_eq!(doc.descendants_named("x").count(), 0);
+
+        let a = doc.get("a").unwrap();
+        let names = a
+            .descendants()
+            .map(|node| node.name().value())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["b", "a", "c", "a", "d"]);
+        assert_eq!(a.descendants_named("a").count(), 2);
+        assert_eq!(doc.get("b").unwrap().descendants().count(), 0);
+        assert_eq!(KdlDocument::new().descendants().count(), 0);
+
+        let mut deep = KdlNode::new("leaf");
+        for _ in 0..1_000 {
+            let mut parent = KdlNode::new("level");
+            parent.ensure_children().nodes_mut().push(deep);
+            deep = parent;
+        }
+        assert_eq!(deep.descendants().count(), 1_000);
+        assert_eq!(deep.descendants_named("leaf").count(), 1);
+        Ok(())
+    }
 
+    #[cfg(feature = "span")]
+    #[test]
+    fn nodes_at_offsets() -> miette::Result<()> {
+        let input = "a 1\nparent {\n    child x=(t)1 { leaf; }\n    /-skipped 2\n}\nz";
+        let doc: KdlDocument = input.parse()?;
+        let names = |offset| {
+            doc.node_at_offset(offset).map(|(node, ancestors)| {
+                let mut names = ancestors
+                    .iter()
+                    .map(|node| node.name().value())
+                    .collect::<Vec<_>>();
+                names.push(node.name().value());
+                names
+            })
+        };
+        assert_eq!(names(0), Some(vec!["a"]));
+        assert_eq!(names(3), None);
+        assert_eq!(names(input.find('{').unwrap()), Some(vec!["parent"]));
+        assert_eq!(
+            names(input.find("leaf").unwrap()),
+            Some(vec!["parent", "child", "leaf"])
+        );
+        assert_eq!(
+            names(input.find("{ leaf").unwrap()),
+            Some(vec!["parent", "child"])
+        );
+        assert_eq!(names(input.find("skipped").unwrap()), Some(vec!["parent"]));
+        assert_eq!(names(input.len() - 1), Some(vec!["z"]));
+        assert_eq!(names(input.len()), None);
+
+        let entry = |offset| {
+            doc.entry_at_offset(offset)
+                .map(|(entry, nodes)| (entry.value().clone(), nodes.len()))
+        };
+        assert_eq!(entry(2), Some((1.into(), 1)));
+        assert_eq!(entry(input.find("(t)").unwrap()), Some((1.into(), 2)));
+        assert_eq!(entry(input.find("x=").unwrap()), Some((1.into(), 2)));
+        assert_eq!(entry(input.find("child").unwrap()), None);
+        assert_eq!(entry(input.find(" 2").unwrap() + 1), None);
+        Ok(())
+    }
 
-       multiline*/
-                                    inner1    \
-                    #"value"# \
-                    ;
+    #[cfg(feature = "span")]
+    #[test]
+    fn edits_only_touch_the_edited_node() -> miette::Result<()> {
+        let input = r#"// config
+first   1   "two"  // keep me
+target  (ty)"old"  key = 10 {
+        child   #true
+}
+   last /* spaced */ x=1;  other
+"#;
+        // Asserts that `edited` only differs from `input` inside `span`.
+        fn assert_diff_within(input: &str, edited: &str, span: SourceSpan) {
+            let (start, end) = (span.offset(), span.offset() + span.len());
+            assert_eq!(&edited[..start], &input[..start]);
+            assert!(edited.ends_with(&input[end..]), "{edited}");
+        }
+        let original: KdlDocument = input.parse()?;
+        assert_eq!(original.to_string(), input);
+        let span = original.nodes()[1].span();
+
+        let mut doc = original.clone();
+        let node = doc.get_mut("target").unwrap();
+        node.entries_mut()[0].set_value("new");
+        let edited = doc.to_string();
+        assert_ne!(edited, input);
+        assert!(edited.contains(r#"(ty)new  key = 10"#));
+        assert_diff_within(input, &edited, span);
+
+        let mut doc = original.clone();
+        let node = doc.get_mut("target").unwrap();
+        node.insert("key", 11);
+        node.push(KdlEntry::new(3));
+        node.name_mut().set_value("renamed");
+        assert_diff_within(input, &doc.to_string(), span);
+
+        let mut doc = original.clone();
+        let node = doc.get_mut("target").unwrap();
+        let child = node.ensure_children().get_mut("child").unwrap();
+        child.entries_mut()[0].set_value(false);
+        let edited = doc.to_string();
+        assert!(edited.contains("child   #false\n"));
+        assert_diff_within(input, &edited, span);
+        Ok(())
+    }
 
-        inner2      \ //comment
+    #[test]
+    fn depth_of() -> miette::Result<()> {
+        let doc: KdlDocument = "a\nb {\n    c\n    d {\n        e\n    }\n}\nf".parse()?;
+        let b = doc.get("b").unwrap();
+        let d = b.children().unwrap().get("d").unwrap();
+        let e = d.children().unwrap().get("e").unwrap();
+        assert_eq!(doc.depth_of(doc.get("a").unwrap()), Some(0));
+        assert_eq!(doc.depth_of(doc.get("f").unwrap()), Some(0));
+        assert_eq!(doc.depth_of(d), Some(1));
+        assert_eq!(doc.depth_of(e), Some(2));
+        assert_eq!(d.children().unwrap().depth_of(e), Some(0));
+
+        // Nodes from elsewhere aren't found, even if they're equal to one.
+        let unparsed = KdlNode::new("e");
+        assert_eq!(doc.depth_of(&unparsed), None);
+        #[cfg(feature = "span")]
         {
-            inner3
+            // ...unless their span says where they came from.
+            assert_eq!(doc.depth_of(&e.clone()), Some(2));
+            let mut unparsed = unparsed;
+            unparsed.set_span(e.span());
+            assert_eq!(doc.depth_of(&unparsed), Some(2));
         }
+        Ok(())
     }
-               }
-
-        // trailing comment here
 
-        "##
-        .parse()?;
+    #[cfg(feature = "span")]
+    #[test]
+    fn split_top_level() -> miette::Result<()> {
+        let input = "\u{FEFF}/* header */ (t)a 1;b {\n    c; d\n} // trailing\n/- skipped\n\te \\\n    2\n// end\n";
+        let parts = super::split_top_level(input)?;
+        let texts = parts
+            .iter()
+            .map(|(text, _)| text.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            texts,
+            [
+                "\u{FEFF}/* header */ (t)a 1;",
+                "b {\n    c; d\n} // trailing\n",
+                "/- skipped\n\te \\\n    2\n// end\n"
+            ]
+        );
+        assert_eq!(texts.concat(), input);
+        for (text, node) in &parts {
+            let doc: KdlDocument = text.parse()?;
+            assert_eq!(doc.nodes().len(), 1);
+            assert!(doc.nodes()[0].semantic_eq(node));
+        }
+        assert_eq!(&input[parts[2].1.span().offset()..][..1], "e");
 
-        KdlDocument::autoformat(&mut doc);
+        assert!(super::split_top_level("")?.is_empty());
+        assert!(super::split_top_level("// nothing\n")?.is_empty());
+        assert!(super::split_top_level("a {").is_err());
+        Ok(())
+    }
 
+    #[test]
+    fn for_each_with_path() -> miette::Result<()> {
+        let doc: KdlDocument = "a {\n    b {\n        c\n    }\n    d {}\n}\ne".parse()?;
+        let mut visited = Vec::new();
+        doc.for_each_with_path(|path, node| {
+            visited.push((path.join("."), node.name().value().to_string()));
+        });
+        let expected = [("", "a"), ("a", "b"), ("a.b", "c"), ("a", "d"), ("", "e")];
         assert_eq!(
-            doc.to_string(),
-            r#"/* x */
-foo 1 bar=0xdeadbeef {
-    child1 1
-    // child 2 comment
-    child2 2 // comment
-    child3 "\nstring\t" {
-        /*
+            visited,
+            expected
+                .iter()
+                .map(|(path, name)| (path.to_string(), name.to_string()))
+                .collect::<Vec<_>>()
+        );
 
+        let mut count = 0;
+        KdlDocument::new().for_each_with_path(|_, _| count += 1);
+        assert_eq!(count, 0);
+        Ok(())
+    }
 
-       multiline*/
-        inner1 value
-        inner2 {
-            inner3
+    #[test]
+    fn all_entries() -> miette::Result<()> {
+        let mut doc: KdlDocument =
+            "a 1 x=2 {\n    b 3 {\n        c 4\n    }\n    d\n}\ne 5".parse()?;
+        let values = |doc: &KdlDocument| {
+            doc.all_entries()
+                .map(|entry| entry.value().as_integer().unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(values(&doc), vec![1, 2, 3, 4, 5]);
+        assert_eq!(doc.entries_total(), 5);
+        assert_eq!(KdlDocument::new().entries_total(), 0);
+        assert_eq!(KdlDocument::new().all_entries().count(), 0);
+
+        for entry in doc.all_entries_mut() {
+            entry.set_value(KdlValue::Integer(entry.value().as_integer().unwrap() + 1));
         }
+        assert_eq!(values(&doc), vec![2, 3, 4, 5, 6]);
+        assert_eq!(
+            doc.to_string(),
+            "a 2 x=3 {\n    b 4 {\n        c 5\n    }\n    d\n}\ne 6"
+        );
+        Ok(())
     }
+
+    #[test]
+    fn align_properties() -> miette::Result<()> {
+        let input = r#"a x=1 yy=2
+bbb 1 x=1 y=2
+cc {
+    d key=1
+    (t)ee long_key=(u)#true 1 z=3
+    f 1 2
 }
-// trailing comment here"#
+"#;
+        let mut doc: KdlDocument = input.parse()?;
+        doc.autoformat_config(&KdlFormatConfig {
+            align_properties: true,
+            ..Default::default()
+        });
+        assert_eq!(
+            doc.to_string(),
+            r#"a x    =1 yy=2
+bbb 1 x=1 y =2
+cc {
+    d key         =1
+    (t)ee long_key=(u)#true 1 z=3
+    f 1 2
+}
+"#
         );
+        let reparsed: KdlDocument = doc.to_string().parse()?;
+        assert!(reparsed.semantic_eq(&input.parse()?));
+
+        // It goes along with the other options.
+        let mut tabbed: KdlDocument = input.parse()?;
+        tabbed.autoformat_config(&KdlFormatConfig {
+            indent: "\t",
+            align_properties: true,
+            ..Default::default()
+        });
+        assert_eq!(
+            tabbed.to_string(),
+            "a x    =1 yy=2\nbbb 1 x=1 y =2\ncc {\n\td key         =1\n\t(t)ee long_key=(u)#true 1 z=3\n\tf 1 2\n}\n"
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "span")]
+    #[test]
+    fn lint_terminators() -> miette::Result<()> {
+        let doc: KdlDocument = r#"a; b /* c */ ;
+(t)c "x;y" {
+    d; e {f;}; /* multi
+    line */
+    g /- h;
+};i
+j;  // comment
+"#
+        .parse()?;
+        let lint = |doc: &KdlDocument, rule| {
+            doc.lint_terminators(rule)
+                .into_iter()
+                .map(|diag| {
+                    assert_eq!(&diag.input[diag.span.offset()..][..1], ";");
+                    assert_eq!(diag.severity, miette::Severity::Warning);
+                    diag.span.offset()
+                })
+                .collect::<Vec<_>>()
+        };
+        let input = doc.to_string();
+        let all = input
+            .match_indices(';')
+            .map(|(offset, _)| offset)
+            .filter(|&offset| !input[..offset].ends_with("\"x"))
+            .collect::<Vec<_>>();
+        assert_eq!(lint(&doc, TerminatorRule::NoSemicolons), all);
+        // All but `a;`, `d;`, and `};i`. The one after `e {f;}` is followed
+        // by a comment that ends the line, and `g /- h;` is `g`'s.
+        assert_eq!(
+            lint(&doc, TerminatorRule::NoRedundantSemicolons),
+            [all[1], all[3], all[4], all[5], all[7]]
+        );
+
+        // Nodes whose spans don't match the text anymore are skipped.
+        let mut built = KdlDocument::new();
+        built
+            .nodes_mut()
+            .extend("x; y".parse::<KdlDocument>()?.nodes().to_vec());
+        built.nodes_mut().insert(0, "plain".parse()?);
+        assert!(lint(&built, TerminatorRule::NoSemicolons).is_empty());
+        assert!(lint(&KdlDocument::new(), TerminatorRule::NoSemicolons).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn set_terminator_kind() -> miette::Result<()> {
+        let mut doc: KdlDocument = "a\nb;\nc { d; }".parse()?;
+        // Only the last node can go without a terminator.
+        assert!(!doc.set_terminator_kind(0, TerminatorKind::Implicit));
+        assert!(!doc.set_terminator_kind(1, TerminatorKind::Implicit));
+        assert!(!doc.set_terminator_kind(3, TerminatorKind::Newline));
+        assert_eq!(doc.to_string(), "a\nb;\nc { d; }");
+        assert!(doc.set_terminator_kind(1, TerminatorKind::Newline));
+        let children = doc.nodes_mut()[2].children_mut().as_mut().unwrap();
+        assert!(children.set_terminator_kind(0, TerminatorKind::Implicit));
+        assert_eq!(doc.to_string(), "a\nb\nc { d }");
+        let reparsed: KdlDocument = doc.to_string().parse()?;
+        assert_eq!(reparsed.nodes().len(), 3);
+
+        // Newlines aren't doubled up, and nodes that shared a line are split.
+        let mut doc: KdlDocument = "a; b;\r\nc /* x */ ;  // y\n".parse()?;
+        for index in 0..3 {
+            assert!(doc.set_terminator_kind(index, TerminatorKind::Newline));
+        }
+        assert_eq!(doc.to_string(), "a\nb\r\nc /* x */   // y\n");
         Ok(())
     }
 
     #[test]
-    fn simple_autoformat() -> miette::Result<()> {
-        let mut doc: KdlDocument = "a { b { c { }; }; }".parse().unwrap();
-        KdlDocument::autoformat(&mut doc);
+    fn normalize_string_style() -> miette::Result<()> {
+        let input = r##"(type)"node" "true" #"r\aw"# "1x" {
+    (t)child key=#"has "quotes""# """
+        two
+        lines
+        """ 1 #null
+}
+"##;
+        let mut doc: KdlDocument = input.parse()?;
+        let original = doc.clone();
+
+        doc.normalize_string_style(StringStyle::PreferBare);
         assert_eq!(
             doc.to_string(),
-            r#"a {
-    b {
-        c {
+            r#"(type)node "true" "r\\aw" "1x" {
+    (t)child key="has \"quotes\"" "two\nlines" 1 #null
+}
+"#
+        );
+        assert_eq!(doc.validate(), Ok(()));
+        assert!(doc.semantic_eq(&original));
 
-        }
-    }
+        doc.normalize_string_style(StringStyle::Quoted);
+        assert_eq!(
+            doc.to_string(),
+            r#"("type")"node" "true" "r\\aw" "1x" {
+    ("t")"child" "key"="has \"quotes\"" "two\nlines" 1 #null
 }
 "#
         );
+        assert_eq!(doc.validate(), Ok(()));
+        assert!(doc.semantic_eq(&original));
+
+        // Nodes and entries that were built by hand get the same treatment.
+        let mut node = KdlNode::new("node");
+        node.push("arg");
+        node.push(KdlEntry::new_prop("key", 1));
+        node.normalize_string_style(StringStyle::Quoted);
+        assert_eq!(node.to_string(), "\"node\" \"arg\" \"key\"=1\n");
         Ok(())
     }
 
-    #[cfg(feature = "span")]
-    fn check_spans_for_doc(doc: &KdlDocument, source: &impl miette::SourceCode) {
-        for node in doc.nodes() {
-            check_spans_for_node(node, source);
-        }
-    }
+    #[test]
+    fn canonicalize_selective() -> miette::Result<()> {
+        let input = "// top\n  a 0x10 \"str\" (  t )1.5e1 {\n\t// inner\n\tb \\\n   1\n  \n      c {\n  d\n        // end of c\n   }\n  e {\n  }\n        }\n f\n";
+        let doc: KdlDocument = input.parse()?;
+        let canonicalized = |flags| {
+            let mut doc = doc.clone();
+            doc.canonicalize_selective(flags);
+            doc.to_string()
+        };
 
-    #[cfg(feature = "span")]
-    fn check_spans_for_node(node: &KdlNode, source: &impl miette::SourceCode) {
-        use crate::KdlEntryFormat;
+        assert_eq!(canonicalized(CanonicalizeFlags::NONE), input);
+        assert_eq!(
+            canonicalized(CanonicalizeFlags::NUMBERS),
+            input.replace("0x10", "16").replace("1.5e1", "15.0")
+        );
+        assert_eq!(
+            canonicalized(CanonicalizeFlags::STRING_QUOTING),
+            input.replace("\"str\"", "str")
+        );
+        assert_eq!(
+            canonicalized(CanonicalizeFlags::TYPE_SPACING),
+            input.replace("(  t )", "(t)")
+        );
+        let reindented = "// top\na 0x10 \"str\" (  t )1.5e1 {\n    // inner\n    b \\\n        1\n\n    c {\n        d\n        // end of c\n    }\n    e {\n    }\n}\nf\n";
+        assert_eq!(canonicalized(CanonicalizeFlags::INDENTATION), reindented);
 
-        check_span_for_ident(node.name(), source);
-        if let Some(ty) = node.ty() {
-            check_span_for_ident(ty, source);
-        }
+        let mut flags = CanonicalizeFlags::NUMBERS | CanonicalizeFlags::STRING_QUOTING;
+        flags |= CanonicalizeFlags::TYPE_SPACING | CanonicalizeFlags::INDENTATION;
+        assert_eq!(flags, CanonicalizeFlags::ALL);
+        assert_eq!(
+            canonicalized(flags),
+            reindented
+                .replace("0x10", "16")
+                .replace("1.5e1", "15.0")
+                .replace("\"str\"", "str")
+                .replace("(  t )", "(t)")
+        );
+        Ok(())
+    }
 
-        for entry in node.entries() {
-            if let Some(name) = entry.name() {
-                check_span_for_ident(name, source);
-            }
-            if let Some(ty) = entry.ty() {
-                check_span_for_ident(ty, source);
-            }
-            if let Some(KdlEntryFormat { value_repr, .. }) = entry.format() {
-                if entry.name().is_none() && entry.ty().is_none() {
-                    check_span(value_repr, entry.span(), source);
-                }
+    #[test]
+    fn validate() -> miette::Result<()> {
+        let doc: KdlDocument = include_str!("../examples/kdl-schema.kdl").parse()?;
+        assert_eq!(doc.validate(), Ok(()));
+
+        let mut doc: KdlDocument = "a 1\nb {\n    c x=(t)#true 2.5\n}".parse()?;
+        assert_eq!(doc.validate(), Ok(()));
+        let c = doc.nodes_mut()[1].ensure_children().get_mut("c").unwrap();
+        c.name_mut().set_repr("123");
+        c.entries_mut()[0].ty_mut().unwrap().set_repr("\"u\"");
+        c.entries_mut()[1].format_mut().unwrap().value_repr = "2.5.".into();
+        let mut bell = KdlEntry::new_prop("bell\u{7}", "ok");
+        bell.name_mut().unwrap().set_repr("\"bell\u{7}\"");
+        c.push(bell);
+        let mut nul = KdlEntry::new("\u{0}");
+        nul.set_format(crate::KdlEntryFormat {
+            value_repr: "\"\u{0}\"".into(),
+            ..Default::default()
+        });
+        c.push(nul);
+        // Without a custom repr, these are escaped, so they're fine.
+        c.push(KdlEntry::new_prop("bell\u{7}", "\u{0}\u{2028}"));
+        doc.nodes_mut()[0].set_ty("fine");
+
+        let errors = doc.validate().unwrap_err();
+        let summary = errors
+            .iter()
+            .map(|err| (err.path.clone(), err.entry, err.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    vec![1, 0],
+                    None,
+                    r#"Identifier "c" is written as `123`, which doesn't parse back to it."#.into()
+                ),
+                (
+                    vec![1, 0],
+                    Some(0),
+                    r#"Identifier "t" is written as `"u"`, which doesn't parse back to it."#.into()
+                ),
+                (
+                    vec![1, 0],
+                    Some(1),
+                    "Value Float(2.5) is written as `2.5.`, which doesn't parse back to it.".into()
+                ),
+                (
+                    vec![1, 0],
+                    Some(2),
+                    "Identifier \"bell\\u{7}\" is written as `\"bell\u{7}\"`, which doesn't parse back to it.".into()
+                ),
+                (
+                    vec![1, 0],
+                    Some(3),
+                    "Value String(\"\\0\") is written as `\"\u{0}\"`, which doesn't parse back to it.".into()
+                ),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn node_name_histogram() -> miette::Result<()> {
+        let doc: KdlDocument = r#"
+            package { name foo; version "1.0" }
+            dependencies {
+                "name" bar
+                /- name baz
+                package
             }
-        }
-        if let Some(children) = node.children() {
-            check_spans_for_doc(children, source);
-        }
+            "#
+        .parse()?;
+        let histogram = doc.node_name_histogram();
+        assert_eq!(
+            histogram
+                .iter()
+                .map(|(k, v)| (&k[..], *v))
+                .collect::<Vec<_>>(),
+            vec![
+                ("package", 2),
+                ("name", 2),
+                ("version", 1),
+                ("dependencies", 1)
+            ]
+        );
+        assert!(KdlDocument::new().node_name_histogram().is_empty());
+        Ok(())
     }
 
-    #[cfg(feature = "span")]
-    #[track_caller]
-    fn check_span_for_ident(ident: &KdlIdentifier, source: &impl miette::SourceCode) {
-        if let Some(repr) = ident.repr() {
-            check_span(repr, ident.span(), source);
-        } else {
-            check_span(ident.value(), ident.span(), source);
+    #[test]
+    fn remove_node() -> miette::Result<()> {
+        fn removed(input: &str, index: usize) -> miette::Result<String> {
+            let mut doc: KdlDocument = input.parse()?;
+            doc.remove_node(index);
+            Ok(doc.to_string())
         }
+        assert_eq!(removed("a\nb\nc\n", 1)?, "a\nc\n");
+        assert_eq!(removed("a\n\nb\nc\n", 1)?, "a\n\nc\n");
+        assert_eq!(removed("a\nb\n\nc\n", 1)?, "a\nc\n");
+        assert_eq!(removed("a\n\n// b\nb 1 // b\n\nc\n", 1)?, "a\n\nc\n");
+        assert_eq!(removed("a; b; c", 1)?, "a; c");
+        // First node.
+        assert_eq!(removed("// header\n\na\n\nb\n", 0)?, "// header\n\nb\n");
+        assert_eq!(removed("  a\n  b\n", 0)?, "  b\n");
+        // Last node.
+        assert_eq!(removed("a\nb\n", 1)?, "a\n");
+        assert_eq!(removed("a; b\n// end\n", 1)?, "a;\n// end\n");
+        assert_eq!(removed("a\n", 0)?, "");
+        // Children.
+        let mut doc: KdlDocument = "parent {\n    a\n\n    b\n    c\n}\n".parse()?;
+        let b = doc.nodes_mut()[0]
+            .children_mut()
+            .as_mut()
+            .unwrap()
+            .remove_node(1);
+        assert_eq!(b.name().value(), "b");
+        assert_eq!(doc.to_string(), "parent {\n    a\n\n    c\n}\n");
+        Ok(())
     }
 
-    #[cfg(feature = "span")]
-    #[track_caller]
-    fn check_span(expected: &str, span: SourceSpan, source: &impl miette::SourceCode) {
-        let span = source.read_span(&span, 0, 0).unwrap();
-        let span = std::str::from_utf8(span.data()).unwrap();
-        assert_eq!(span, expected);
+    #[test]
+    fn take_node() -> miette::Result<()> {
+        let mut doc: KdlDocument =
+            "// config\nfoo 1\n\n@meta 1\n@meta 2\nbar { @meta 3; }\n".parse()?;
+        let meta = doc.take_node("@meta").expect("first @meta");
+        assert_eq!(meta.entries()[0].value(), &KdlValue::Integer(1));
+        assert_eq!(
+            doc.to_string(),
+            "// config\nfoo 1\n\n@meta 2\nbar { @meta 3; }\n"
+        );
+        assert!(doc.take_node("@meta").is_some());
+        // Only top-level nodes are considered.
+        assert!(doc.take_node("@meta").is_none());
+        assert_eq!(doc.to_string(), "// config\nfoo 1\n\nbar { @meta 3; }\n");
+        Ok(())
     }
 
-    #[cfg(feature = "span")]
     #[test]
-    fn span_test() -> miette::Result<()> {
-        let input = r####"
-this {
-    is (a)"cool" document="to" read=(int)5 10.1 (u32)0x45
-    and x="" {
-        "it" /*shh*/ "has"="💯" ##"the"##
-        Best🎊est
-        "syntax ever"
-    }
-    "yknow?" 0x10
+    fn shift_indent() -> miette::Result<()> {
+        let input = r#"// leading
+a 1 \
+    2 {
+    /* block
+       comment */
+    b
+
+    c """
+        multi
+        """
 }
-// that's
-nice
-inline { time; to; live "our" "dreams"; "y;all" }
-
-"####;
+d; e
+// trailing
+"#;
+        let mut doc: KdlDocument = input.parse()?;
+        doc.shift_indent(1, "  ");
+        assert_eq!(
+            doc.to_string(),
+            r#"  // leading
+  a 1 \
+      2 {
+      /* block
+         comment */
+      b
+
+      c """
+        multi
+        """
+  }
+  d; e
+  // trailing
+"#
+        );
+        doc.shift_indent(-1, "  ");
+        assert_eq!(doc.to_string(), input);
+        // Dedenting clamps at zero.
+        doc.shift_indent(-5, "  ");
+        assert!(doc
+            .to_string()
+            .starts_with("// leading\na 1 \\\n2 {\n/* block\n comment */\nb\n"));
+
+        // Nesting one document inside another.
+        let mut inner: KdlDocument = "x 1\ny {\n    z\n}\n".parse()?;
+        inner.shift_indent(1, "    ");
+        assert_eq!(inner.to_string(), "    x 1\n    y {\n        z\n    }\n");
+
+        // Built documents without formatting.
+        let mut built = KdlDocument::new();
+        built.nodes_mut().push(KdlNode::new("a"));
+        built.nodes_mut().push(KdlNode::new("b"));
+        built.shift_indent(1, "\t");
+        assert_eq!(built.to_string(), "\ta\n\tb\n");
+        Ok(())
+    }
 
-        let doc: KdlDocument = input.parse()?;
+    #[test]
+    fn semantically_equal() -> miette::Result<()> {
+        assert!(super::semantically_equal(
+            "(t)a 1.0 x=#true { b \"c\"; }",
+            "( t )\"a\" /* hi */ 1.0 x=#true {\n    b c\n}",
+        )?);
+        assert!(super::semantically_equal("", "// nothing\n")?);
+        assert!(super::semantically_equal("a /-1 2", "a 2")?);
+        assert!(!super::semantically_equal("a 1 2", "a 2 1")?);
+        assert!(!super::semantically_equal("a x=1", "a y=1")?);
+        // Properties can come in any order, and the last duplicate wins.
+        assert!(super::semantically_equal("a x=1 2 y=3", "a y=3 2 x=1")?);
+        assert!(super::semantically_equal("a x=1 x=2", "a x=2")?);
+        assert!(!super::semantically_equal("a x=1 x=2", "a x=1")?);
+        assert!(!super::semantically_equal("a x=1 2 3", "a 3 x=1 2")?);
+        assert!(!super::semantically_equal("(t)a", "a")?);
+        assert!(!super::semantically_equal("a", "a {}")?);
+        assert!(!super::semantically_equal("a { b; }", "a { c; }")?);
+        assert!(!super::semantically_equal("a; b", "a")?);
+        assert!(super::semantically_equal("a {", "a").is_err());
+        assert!(super::semantically_equal("a", "a {").is_err());
+        Ok(())
+    }
 
-        // First check that all the identity-spans are correct
-        check_spans_for_doc(&doc, &input);
+    #[test]
+    fn subset() -> miette::Result<()> {
+        let doc: KdlDocument = r#"// preamble
+
+  // about foo
+  foo 1 {
+      bar 2
+        baz
+    /* inner */ quux
+  }
+qux; corge 3 { grault }
+// trailer
+"#
+        .parse()?;
+        assert_eq!(
+            doc.subset(&[0]).to_string(),
+            "foo 1 {\n    bar 2\n      baz\n  /* inner */ quux\n}\n"
+        );
+        assert_eq!(
+            doc.subset(&[2, 1]).to_string(),
+            "corge 3 { grault }\nqux;\n"
+        );
+        let subset = doc.subset(&[1, 0]);
+        assert_eq!(subset.nodes().len(), 2);
+        let reparsed: KdlDocument = subset.to_string().parse()?;
+        assert_eq!(reparsed.to_string(), subset.to_string());
+        assert_eq!(doc.subset(&[]).to_string(), "");
+        Ok(())
+    }
 
-        // Now check some more interesting concrete spans
+    #[test]
+    fn max_entries_per_node() -> miette::Result<()> {
+        let options = ParseOptions {
+            max_entries_per_node: Some(3),
+            ..Default::default()
+        };
+        let doc = KdlDocument::parse_with("a 1 2 x=3 {\n    b 1 2 3\n}", &options)?;
+        assert_eq!(doc.nodes()[0].entries().len(), 3);
+
+        let err =
+            KdlDocument::parse_with("a 1\nparent {\n    child 1 2 3 4\n}", &options).unwrap_err();
+        assert_eq!(err.diagnostics.len(), 1);
+        assert_eq!(err.diagnostics[0].span, (17..22).into());
+        assert_eq!(
+            err.diagnostics[0].to_string(),
+            "Node has more than 3 entries."
+        );
 
-        // The whole document should be everything from the first node until the
-        // last before_terminator whitespace.
-        check_span(&input[1..(input.len() - 2)], doc.span(), &input);
+        // Slashdashed entries are just whitespace.
+        assert!(KdlDocument::parse_with("a 1 /-2 3 x=4 /-5", &options).is_ok());
+        // Children aren't entries.
+        assert!(KdlDocument::parse_with("a 1 2 3 { b; }", &options).is_ok());
+        // No limit by default.
+        let many = format!("a{}", " 1".repeat(1000));
+        assert!(KdlDocument::parse_with(&many, &ParseOptions::default()).is_ok());
+        Ok(())
+    }
 
-        // This one-liner node should be the whole line without leading whitespace
-        let is_node = doc
-            .get("this")
-            .unwrap()
-            .children()
-            .unwrap()
-            .get("is")
-            .unwrap();
-        check_span(
-            r##"is (a)"cool" document="to" read=(int)5 10.1 (u32)0x45"##,
-            is_node.span(),
-            &input,
+    #[test]
+    fn max_depth() -> miette::Result<()> {
+        let options = ParseOptions {
+            max_depth: Some(2),
+            ..Default::default()
+        };
+        assert!(KdlDocument::parse_with("a { b { c; }; }\nd { e; }", &options).is_ok());
+        let err = KdlDocument::parse_with("a { b { c { d; }; }; }", &options).unwrap_err();
+        assert_eq!(err.diagnostics.len(), 1);
+        assert_eq!(err.diagnostics[0].span, (10..11).into());
+        assert_eq!(
+            err.diagnostics[0].to_string(),
+            "Children blocks are nested more than 2 deep."
         );
 
-        // Some simple with/without type hints
-        check_span(r#"(a)"cool""#, is_node.entry(0).unwrap().span(), &input);
-        check_span(
-            r#"read=(int)5"#,
-            is_node.entry("read").unwrap().span(),
-            &input,
-        );
-        check_span(r#"10.1"#, is_node.entry(1).unwrap().span(), &input);
-        check_span(r#"(u32)0x45"#, is_node.entry(2).unwrap().span(), &input);
+        // Slashdashed blocks count too.
+        assert!(KdlDocument::parse_with("a { b { /-{ c; }; }; }", &options).is_err());
+        let options = ParseOptions {
+            max_depth: Some(0),
+            ..Default::default()
+        };
+        assert!(KdlDocument::parse_with("a 1", &options).is_ok());
+        assert!(KdlDocument::parse_with("a {}", &options).is_err());
+        Ok(())
+    }
 
-        // Now let's look at some messed up parts of that "and" node
-        let and_node = doc
-            .get("this")
-            .unwrap()
-            .children()
+    #[test]
+    fn default_max_depth() {
+        // Unoptimized builds need more stack than test threads get to
+        // reach the default limit.
+        std::thread::Builder::new()
+            .stack_size(16 << 20)
+            .spawn(|| {
+                let depth = ParseOptions::DEFAULT_MAX_DEPTH;
+                let nested = |n| "a {".repeat(n) + &"}".repeat(n);
+                assert!(nested(depth).parse::<KdlDocument>().is_ok());
+                let err = nested(100_000).parse::<KdlDocument>().unwrap_err();
+                assert_eq!(err.diagnostics.len(), 1);
+                assert_eq!(err.diagnostics[0].span, (depth * 3 + 2, 1).into());
+                assert_eq!(
+                    err.diagnostics[0].kind,
+                    KdlErrorKind::TooDeep(ParseOptions::DEFAULT_MAX_DEPTH)
+                );
+                let options = ParseOptions {
+                    collect_all_diagnostics: true,
+                    ..Default::default()
+                };
+                assert!(KdlDocument::parse_with(&nested(100_000), &options).is_err());
+                #[cfg(feature = "v1")]
+                assert!(KdlDocument::parse_v1(&nested(100_000)).is_err());
+
+                // The limit can be lifted altogether.
+                let options = ParseOptions {
+                    max_depth: None,
+                    ..Default::default()
+                };
+                assert!(KdlDocument::parse_with(&nested(depth + 10), &options).is_ok());
+            })
             .unwrap()
-            .get("and")
+            .join()
             .unwrap();
+    }
 
-        // The node is what you expect, the whole line and its two braces
-        check_span(
-            r####"and x="" {
-        "it" /*shh*/ "has"="💯" ##"the"##
-        Best🎊est
-        "syntax ever"
-    }"####,
-            and_node.span(),
-            &input,
+    #[test]
+    fn max_input_len() -> miette::Result<()> {
+        let options = ParseOptions {
+            max_input_len: Some(8),
+            ..Default::default()
+        };
+        assert!(KdlDocument::parse_with("node 123", &options).is_ok());
+        let err = KdlDocument::parse_with("node 1234", &options).unwrap_err();
+        assert_eq!(err.diagnostics.len(), 1);
+        assert_eq!(err.diagnostics[0].span, (8..9).into());
+        assert_eq!(
+            err.diagnostics[0].to_string(),
+            "Input is longer than 8 bytes."
         );
+        Ok(())
+    }
 
-        // The child document is a little weird, it's the contents *inside* the braces
-        // without the surrounding whitespace/comments. Just the actual contents.
-        check_span(
-            r####""it" /*shh*/ "has"="💯" ##"the"##
-        Best🎊est
-        "syntax ever""####,
-            and_node.children().unwrap().span(),
-            &input,
+    #[test]
+    fn discard_format() -> miette::Result<()> {
+        let options = ParseOptions {
+            discard_format: true,
+            ..Default::default()
+        };
+        let input = "// comment\nnode   0x10 \"str\" key=(t)1.50 { child; }\n";
+        let doc = KdlDocument::parse_with(input, &options)?;
+        assert_eq!(doc.to_string(), "node 16 str key=(t)1.5 {\n    child\n}\n");
+        assert!(doc.semantic_eq(&input.parse()?));
+        assert!(doc.format().is_none());
+        let node = &doc.nodes()[0];
+        assert!(node.format().is_none());
+        assert!(node.name().repr().is_none());
+        assert!(node.entries().iter().all(|e| e.format().is_none()));
+        assert!(node.entries()[2].name().unwrap().repr().is_none());
+        assert!(node.entries()[2].ty().unwrap().repr().is_none());
+        let children = node.children().unwrap();
+        assert!(children.format().is_none());
+        assert!(children.nodes()[0].format().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn accept_signed_inf_nan() -> miette::Result<()> {
+        let input = "a #+inf x=#+nan #-inf";
+        let err = input.parse::<KdlDocument>().unwrap_err();
+        assert_eq!(err.diagnostics.len(), 1);
+        assert_eq!(err.diagnostics[0].span, (2..7).into());
+        assert_eq!(
+            err.diagnostics[0].help,
+            Some("Write #inf or #nan without a leading +.")
         );
 
-        // Oh hey don't forget to check that "x" entry
-        check_span(r#"x="""#, and_node.entry("x").unwrap().span(), &input);
+        let options = ParseOptions {
+            accept_signed_inf_nan: true,
+            ..Default::default()
+        };
+        let doc = KdlDocument::parse_with(input, &options)?;
+        let node = &doc.nodes()[0];
+        assert_eq!(node[0], KdlValue::Float(f64::INFINITY));
+        assert!(node["x"].as_float().unwrap().is_nan());
+        assert_eq!(node[1], KdlValue::Float(f64::NEG_INFINITY));
+        assert_eq!(doc.to_string(), input);
+        Ok(())
+    }
 
-        // Now the "it" node, more straightforward
-        let it_node = and_node.children().unwrap().get("it").unwrap();
-        check_span(
-            r####""it" /*shh*/ "has"="💯" ##"the"##"####,
-            it_node.span(),
-            &input,
-        );
-        check_span(
-            r#""has"="💯""#,
-            it_node.entry("has").unwrap().span(),
-            &input,
-        );
-        check_span(
-            r####"##"the"##"####,
-            it_node.entry(0).unwrap().span(),
-            &input,
-        );
+    #[test]
+    fn collect_all_diagnostics() {
+        let input =
+            "a #bogus 1\nb 2\nc #nope; d \"str; }\" #bad\nparent {\n    child #what\n    ok\n}\ne";
+        let err = input.parse::<KdlDocument>().unwrap_err();
+        assert_eq!(err.diagnostics.len(), 1);
+
+        let options = ParseOptions {
+            collect_all_diagnostics: true,
+            ..Default::default()
+        };
+        let err = KdlDocument::parse_with(input, &options).unwrap_err();
+        let spans = err
+            .diagnostics
+            .iter()
+            .map(|diag| (diag.span.offset(), diag.span.offset() + diag.span.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(spans.len(), 4);
+        for pair in spans.windows(2) {
+            assert!(pair[0].1 <= pair[1].0, "{:?} overlaps", pair);
+        }
+        for (offset, keyword) in [(3, "bogus"), (18, "nope"), (36, "bad"), (60, "what")] {
+            assert!(spans.iter().any(|span| span.0 == offset), "{keyword}");
+        }
 
-        // Make sure inline nodes work ok
-        let inline_node = doc.get("inline").unwrap();
-        check_span(
-            r#"inline { time; to; live "our" "dreams"; "y;all" }"#,
-            inline_node.span(),
-            &input,
-        );
+        // Nothing changes for valid documents.
+        let doc = KdlDocument::parse_with("a 1\nb {\n    c\n}\n", &options).unwrap();
+        assert_eq!(doc.to_string(), "a 1\nb {\n    c\n}\n");
+    }
 
-        let inline_children = inline_node.children().unwrap();
-        check_span(
-            r#"time; to; live "our" "dreams"; "y;all" "#,
-            inline_children.span(),
-            &input,
+    #[test]
+    fn max_diagnostics() {
+        let input = "a #bad1\nb #bad2\nc #bad3\nd #bad4\ne";
+        let mut options = ParseOptions {
+            collect_all_diagnostics: true,
+            max_diagnostics: Some(2),
+            ..Default::default()
+        };
+        let err = KdlDocument::parse_with(input, &options).unwrap_err();
+        assert_eq!(err.diagnostics.len(), 2);
+        assert!(err.diagnostics_truncated);
+        assert_eq!(err.diagnostics[1].span, (11..15).into());
+
+        options.stop_at_max_diagnostics = true;
+        let err = KdlDocument::parse_with(input, &options).unwrap_err();
+        assert_eq!(err.diagnostics.len(), 2);
+        assert!(err.diagnostics_truncated);
+        assert_eq!(err.diagnostics[1].span, (11..15).into());
+
+        // Nothing is truncated when everything fits.
+        options.max_diagnostics = Some(4);
+        for stop in [false, true] {
+            options.stop_at_max_diagnostics = stop;
+            let err = KdlDocument::parse_with(input, &options).unwrap_err();
+            assert_eq!(err.diagnostics.len(), 4);
+            assert!(!err.diagnostics_truncated);
+        }
+        assert!(KdlDocument::parse_with("a 1", &options).is_ok());
+
+        let err = input.parse::<KdlDocument>().unwrap_err();
+        assert!(!err.diagnostics_truncated);
+
+        // Failed attempts at recovering from the bad escape, which the parser
+        // backtracks out of, don't count towards the limit, so it doesn't
+        // stop early.
+        let input = "/-a \"\\q\"\nb #bad1\nc #bad2\nd #bad3\n";
+        options.max_diagnostics = Some(3);
+        options.stop_at_max_diagnostics = true;
+        let err = KdlDocument::parse_with(input, &options).unwrap_err();
+        assert_eq!(err.diagnostics.len(), 3);
+        assert!(err.diagnostics_truncated);
+    }
+
+    #[test]
+    fn custom_keywords() -> miette::Result<()> {
+        let options = ParseOptions {
+            custom_keywords: vec![
+                ("on".into(), KdlValue::Bool(true)),
+                ("off".into(), KdlValue::Bool(false)),
+                ("nullish".into(), KdlValue::Null),
+                ("half".into(), KdlValue::Float(0.5)),
+            ],
+            ..Default::default()
+        };
+        let input = "a #on x=(t)#off #nullish #true {\n    b #half;\n}";
+        let doc = KdlDocument::parse_with(input, &options)?;
+        let a = &doc.nodes()[0];
+        assert_eq!(a[0], KdlValue::Bool(true));
+        assert_eq!(a["x"], KdlValue::Bool(false));
+        assert_eq!(a.entry("x").unwrap().ty().unwrap().value(), "t");
+        assert_eq!(a[1], KdlValue::Null);
+        assert_eq!(a[2], KdlValue::Bool(true));
+        assert_eq!(
+            doc.get("a").unwrap().children().unwrap().get_arg("b"),
+            Some(&KdlValue::Float(0.5))
         );
+        assert_eq!(doc.to_string(), input);
 
-        let inline_nodes = inline_children.nodes();
-        check_span("time", inline_nodes[0].span(), &input);
-        check_span("to", inline_nodes[1].span(), &input);
-        check_span(r#"live "our" "dreams""#, inline_nodes[2].span(), &input);
-        check_span(r#""y;all""#, inline_nodes[3].span(), &input);
+        for bad in ["a #On", "a #onward", "a #dim"] {
+            assert!(KdlDocument::parse_with(bad, &options).is_err(), "{bad}");
+        }
+        // The spec's keywords stay the only ones by default.
+        assert!("a #on".parse::<KdlDocument>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_reporting_bom() -> miette::Result<()> {
+        let (doc, had_bom) = KdlDocument::parse_reporting_bom("\u{FEFF}// hi\nnode 1\n")?;
+        assert!(had_bom);
+        assert_eq!(doc.to_string(), "// hi\nnode 1\n");
+        #[cfg(feature = "span")]
+        assert_eq!(doc.nodes()[0].span(), SourceSpan::from((9, 6)));
+
+        let (doc, had_bom) = KdlDocument::parse_reporting_bom("node 1\n")?;
+        assert!(!had_bom);
+        assert_eq!(doc.to_string(), "node 1\n");
+
+        // The default parse keeps the BOM around.
+        let doc: KdlDocument = "\u{FEFF}node".parse()?;
+        assert_eq!(doc.to_string(), "\u{FEFF}node");
+
+        assert!(KdlDocument::parse_reporting_bom("\u{FEFF}node {").is_err());
+        Ok(())
+    }
 
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() -> miette::Result<()> {
+        let src = r##"
+// comment
+(ty)node 1 0x10 1.5 #true #null "str" key=(u8)255 /-skipped {
+    child "a\nb" #"raw"#; /* trailing */
+}
+"##;
+        let doc: KdlDocument = src.parse()?;
+        let text = crate::ser::to_string(&doc).unwrap();
+        let back: KdlDocument = crate::de::from_str(&text)?;
+        assert_eq!(back, doc);
+        assert_eq!(back.to_string(), src);
+        #[cfg(feature = "span")]
+        {
+            let spans = |doc: &KdlDocument| {
+                let mut spans = vec![doc.span()];
+                for node in doc.descendants() {
+                    spans.push(node.span());
+                    spans.push(node.name().span());
+                    spans.extend(node.entries().iter().map(|entry| entry.span()));
+                }
+                spans
+            };
+            assert_eq!(spans(&back), spans(&doc));
+        }
         Ok(())
     }
 
@@ -0,0 +1,424 @@
+use miette::SourceSpan;
+
+use crate::{
+    v1_parser::{self, Trivia},
+    v2_parser, KdlConversionError, KdlDocument, KdlEntry, KdlEntryFormat, KdlIdentifier, KdlNode,
+    KdlTrivia, KdlValue, KdlVersion,
+};
+
+impl KdlDocument {
+    /// Rewrites this document as valid [KDL
+    /// v1](https://github.com/kdl-org/kdl/blob/1.0.0/SPEC.md), with the `v1`
+    /// feature, keeping its whitespace and comments, for tools that only
+    /// understand v1. This is the reverse of [`KdlDocument::upgrade_to_v2`].
+    ///
+    /// Only what has to change does:
+    ///
+    /// * `#true`, `#false`, and `#null` become `true`, `false`, and `null`.
+    /// * Bare string values, like `foo`, get quoted.
+    /// * Identifiers that aren't valid bare identifiers in v1, like `a<b`,
+    ///   get quoted.
+    /// * Raw and multi-line strings become v1 raw strings, like `r#"..."#`,
+    ///   and strings using escapes v1 doesn't have, like `\s`, are written
+    ///   out again as v1 strings.
+    /// * Whitespace inside and after an entry's type annotation, around a
+    ///   property's `=`, and after a `/-` that spans lines, none of which v1
+    ///   allows, is removed.
+    /// * Slashdashed nodes, entries, and children blocks are downgraded too,
+    ///   and a `/- kdl-version 2` marker becomes `/- kdl-version 1`.
+    ///
+    /// Afterwards, the document's [`KdlDocument::version`] is
+    /// [`KdlVersion::V1`].
+    ///
+    /// # Errors
+    ///
+    /// Some things can't be written in v1 at all, and are reported instead
+    /// of being dropped: `#inf`, `#-inf`, and `#nan`, and comments in the
+    /// places where v1 doesn't allow whitespace, with the span of the entry
+    /// they're in. The document is left as it
+    /// was if that happens.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::{KdlDocument, KdlVersion};
+    /// let mut doc: KdlDocument = r##"
+    /// // Settings
+    /// server enabled=#true root=#"C:\srv"# name=main {
+    ///     (u16) port 80
+    ///     notes """
+    ///         one
+    ///         two
+    ///         """
+    /// }
+    /// "##.parse()?;
+    /// doc.downgrade_to_v1()?;
+    /// assert_eq!(doc.version(), KdlVersion::V1);
+    /// assert_eq!(doc.to_string(), r##"
+    /// // Settings
+    /// server enabled=true root=r"C:\srv" name="main" {
+    ///     (u16)port 80
+    ///     notes r"one
+    /// two"
+    /// }
+    /// "##);
+    ///
+    /// let mut doc: KdlDocument = "limit #inf".parse()?;
+    /// assert!(doc.downgrade_to_v1().is_err());
+    /// assert_eq!(doc.to_string(), "limit #inf");
+    /// # Ok::<(), miette::Report>(())
+    /// ```
+    pub fn downgrade_to_v1(&mut self) -> Result<(), KdlConversionError> {
+        let mut doc = self.clone();
+        downgrade_document(&mut doc)?;
+        *self = doc;
+        Ok(())
+    }
+
+    /// Writes this document out as KDL v1, without changing it. See
+    /// [`KdlDocument::downgrade_to_v1`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlDocument;
+    /// let doc: KdlDocument = "node #true bare\n".parse()?;
+    /// assert_eq!(doc.to_v1_string()?, "node true \"bare\"\n");
+    /// assert_eq!(doc.to_string(), "node #true bare\n");
+    /// # Ok::<(), miette::Report>(())
+    /// ```
+    pub fn to_v1_string(&self) -> Result<String, KdlConversionError> {
+        let mut doc = self.clone();
+        downgrade_document(&mut doc)?;
+        Ok(doc.to_string())
+    }
+}
+
+fn downgrade_document(doc: &mut KdlDocument) -> Result<(), KdlConversionError> {
+    if let Some(fmt) = doc.format_mut() {
+        downgrade_trivia(&mut fmt.leading, true)?;
+        downgrade_trivia(&mut fmt.trailing, true)?;
+    }
+    for node in doc.nodes_mut() {
+        downgrade_node(node)?;
+    }
+    doc.version = KdlVersion::V1;
+    Ok(())
+}
+
+fn downgrade_node(node: &mut KdlNode) -> Result<(), KdlConversionError> {
+    downgrade_identifier(&mut node.name);
+    if let Some(ty) = &mut node.ty {
+        downgrade_identifier(ty);
+    }
+    // A node's type annotation is always written without any whitespace,
+    // which is all v1 allows there.
+    if let Some(fmt) = &mut node.format {
+        downgrade_trivia(&mut fmt.leading, true)?;
+        downgrade_trivia(&mut fmt.before_children, false)?;
+        downgrade_trivia(&mut fmt.before_terminator, false)?;
+    }
+    for entry in &mut node.entries {
+        downgrade_entry(entry)?;
+    }
+    // An unformatted node puts a space before its children only if its last
+    // entry is unformatted too, so keep it if that entry got a format.
+    if node.format.is_none() && node.children.is_some() {
+        if let Some(fmt) = node
+            .entries
+            .last_mut()
+            .and_then(|entry| entry.format.as_mut())
+        {
+            if fmt.leading == " " && fmt.trailing.is_empty() {
+                fmt.trailing = " ".into();
+            }
+        }
+    }
+    if let Some(children) = &mut node.children {
+        downgrade_document(children)?;
+    }
+    Ok(())
+}
+
+fn downgrade_entry(entry: &mut KdlEntry) -> Result<(), KdlConversionError> {
+    let span = entry_span(entry);
+    if let KdlValue::Float(value) = entry.value {
+        if !value.is_finite() {
+            return Err(error(
+                span,
+                format!("KDL v1 has no way to write {}.", entry.value),
+            ));
+        }
+    }
+    for ident in entry.name.iter_mut().chain(entry.ty.iter_mut()) {
+        downgrade_identifier(ident);
+    }
+    if let Some(fmt) = &mut entry.format {
+        for gap in [
+            &mut fmt.before_ty_name,
+            &mut fmt.after_ty_name,
+            &mut fmt.after_ty,
+            &mut fmt.after_key,
+            &mut fmt.after_eq,
+        ] {
+            remove_gap(gap).map_err(|message| error(span, message))?;
+        }
+        downgrade_trivia(&mut fmt.leading, false)?;
+        if !v1_value_round_trips(&fmt.value_repr, &entry.value) {
+            fmt.value_repr = v1_value_repr(&fmt.value_repr, &entry.value);
+        }
+    } else {
+        let repr = entry.value.to_string();
+        if !v1_value_round_trips(&repr, &entry.value) {
+            entry.format = Some(KdlEntryFormat {
+                value_repr: v1_value_repr(&repr, &entry.value),
+                leading: " ".into(),
+                ..Default::default()
+            });
+        }
+    }
+    Ok(())
+}
+
+fn downgrade_identifier(ident: &mut KdlIdentifier) {
+    let round_trips = v2_parser::try_parse(v1_parser::identifier, &ident.to_string())
+        .map_or(false, |parsed| parsed.value() == ident.value());
+    if round_trips {
+        return;
+    }
+    let bare = v2_parser::try_parse(v1_parser::bare_identifier, ident.value()).is_ok();
+    let repr = if bare {
+        ident.value().to_string()
+    } else if ident.repr().map_or(false, |repr| repr.starts_with('#')) {
+        v1_raw_string(ident.value())
+    } else {
+        crate::value::quote_string(ident.value())
+    };
+    ident.set_repr(repr);
+}
+
+/// Whether `repr` is a valid v1 value that means `value`.
+fn v1_value_round_trips(repr: &str, value: &KdlValue) -> bool {
+    v2_parser::try_parse(v1_parser::bare_value, repr).map_or(false, |parsed| &parsed == value)
+}
+
+/// `value`, which is finite if it's a float, written as a v1 value. Strings
+/// that were raw or multi-line in v2, going by their v2 `repr`, become raw
+/// strings, and the rest are quoted.
+fn v1_value_repr(repr: &str, value: &KdlValue) -> String {
+    match value {
+        KdlValue::String(s) if repr.starts_with('#') || repr.starts_with("\"\"\"") => {
+            v1_raw_string(s)
+        }
+        KdlValue::String(s) => crate::value::quote_string(s),
+        KdlValue::Bool(b) => b.to_string(),
+        KdlValue::Null => "null".into(),
+        KdlValue::Integer(_) | KdlValue::Float(_) => value.to_string(),
+    }
+}
+
+/// `string` as a v1 raw string, with as few hashes as it takes.
+fn v1_raw_string(string: &str) -> String {
+    let hashes = string
+        .split('"')
+        .skip(1)
+        .map(|after| after.len() - after.trim_start_matches('#').len() + 1)
+        .max()
+        .unwrap_or(0);
+    let hashes = "#".repeat(hashes);
+    format!("r{hashes}\"{string}\"{hashes}")
+}
+
+/// Removes whitespace from somewhere v1 doesn't allow any, like inside a
+/// type annotation, or explains why it can't be.
+fn remove_gap(gap: &mut KdlTrivia) -> Result<(), &'static str> {
+    // Line continuations are just more whitespace here.
+    if gap.chars().all(|c| c.is_whitespace() || c == '\\') {
+        gap.clear();
+        Ok(())
+    } else {
+        Err("KDL v1 doesn't allow comments inside or after type annotations, or around `=`.")
+    }
+}
+
+/// Rewrites the slashdashed parts of some v2 formatting. `between_nodes`
+/// says whether it's found between nodes, where only nodes can be
+/// slashdashed, or inside a node.
+fn downgrade_trivia(trivia: &mut KdlTrivia, between_nodes: bool) -> Result<(), KdlConversionError> {
+    if !trivia.contains("/-") {
+        return Ok(());
+    }
+    let pieces = if between_nodes {
+        v2_parser::try_parse(v2_parser::node_trivia, trivia)
+    } else {
+        v2_parser::try_parse(v2_parser::entry_trivia, trivia)
+    };
+    let pieces = match pieces {
+        Ok(pieces) => pieces,
+        Err(_) => return Ok(()),
+    };
+    let mut downgraded = String::with_capacity(trivia.len());
+    for piece in pieces {
+        // Spans inside formatting don't point anywhere useful.
+        let without_span = |mut err: KdlConversionError| {
+            err.span = None;
+            err
+        };
+        match piece {
+            Trivia::Text(text)
+                if text.starts_with("/-") && text.contains(crate::fmt::is_newline) =>
+            {
+                if !text[2..].chars().all(char::is_whitespace) {
+                    return Err(error(
+                        None,
+                        "KDL v1 doesn't allow comments between `/-` and what it comments out, if they span lines.",
+                    ));
+                }
+                downgraded.push_str("/-");
+            }
+            Trivia::Text(text) => downgraded.push_str(text),
+            Trivia::Node(mut node) => {
+                downgrade_node(&mut node).map_err(without_span)?;
+                if node.name().value() == "kdl-version" {
+                    if let [version] = node.entries_mut().as_mut_slice() {
+                        if version.value() == &KdlValue::Integer(2) {
+                            version.set_value(1);
+                        }
+                    }
+                }
+                downgraded.push_str(&node.to_string());
+            }
+            Trivia::Entry(mut entry) => {
+                downgrade_entry(&mut entry).map_err(without_span)?;
+                downgraded.push_str(&entry.to_string());
+            }
+            Trivia::Children(mut children) => {
+                downgrade_document(&mut children).map_err(without_span)?;
+                downgraded.push('{');
+                downgraded.push_str(&children.to_string());
+                downgraded.push('}');
+            }
+        }
+    }
+    *trivia = downgraded.into();
+    Ok(())
+}
+
+fn error(span: Option<SourceSpan>, message: impl Into<String>) -> KdlConversionError {
+    KdlConversionError {
+        message: message.into(),
+        span,
+    }
+}
+
+#[cfg(feature = "span")]
+fn entry_span(entry: &KdlEntry) -> Option<SourceSpan> {
+    Some(entry.span())
+}
+
+#[cfg(not(feature = "span"))]
+fn entry_span(_entry: &KdlEntry) -> Option<SourceSpan> {
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn downgrade() -> miette::Result<()> {
+        let mut doc: KdlDocument = r####"/- kdl-version 2
+( type )node #true #false #null bare #"raw"# "a\sb" a<b=1 key = "value" {
+    /-
+    gone #null { nested #true; }
+    multi """
+        line
+          \"string\"
+        """
+    (#"raw ty"#)x 1.5e10 0x1F /- #false /-{ y bare; }
+}
+#"raw node"# /- k=#true
+"####
+            .parse()?;
+        doc.downgrade_to_v1()?;
+        assert_eq!(doc.version(), KdlVersion::V1);
+        assert_eq!(
+            doc.get("node").unwrap().children().unwrap().version(),
+            KdlVersion::V1
+        );
+        assert_eq!(
+            doc.to_string(),
+            r####"/- kdl-version 1
+(type)node true false null "bare" r"raw" "a b" "a<b"=1 key="value" {
+    /-gone null { nested true; }
+    multi r#"line
+  "string""#
+    (r"raw ty")x 1.5e10 0x1F /- false /-{ y "bare"; }
+}
+r"raw node" /- k=true
+"####
+        );
+        let v1 = KdlDocument::parse_v1(&doc.to_string())?;
+        assert_eq!(v1.to_string(), doc.to_string());
+        let multi = v1.get("node").unwrap().children().unwrap().get("multi");
+        assert_eq!(
+            multi.unwrap().entries()[0].value(),
+            &KdlValue::String("line\n  \"string\"".into())
+        );
+
+        // Unformatted documents get the same treatment.
+        let mut doc = KdlDocument::new();
+        let mut node = KdlNode::new("node");
+        node.push(true);
+        node.push("bare");
+        node.push(("<key>", KdlValue::Null));
+        node.ensure_children()
+            .nodes_mut()
+            .push(KdlNode::new("child"));
+        doc.nodes_mut().push(node);
+        doc.autoformat();
+        assert_eq!(
+            doc.to_v1_string()?,
+            "node true \"bare\" \"<key>\"=null {\n    child\n}\n"
+        );
+        doc.clear_format_recursive();
+        assert_eq!(
+            doc.to_v1_string()?,
+            "node true \"bare\" \"<key>\"=null {\n    child\n}\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn errors() -> miette::Result<()> {
+        for (input, message, at) in [
+            ("node 1 #nan", "KDL v1 has no way to write #nan.", Some("#nan")),
+            (
+                "node ( /* why */ u8)1",
+                "KDL v1 doesn't allow comments inside or after type annotations, or around `=`.",
+                Some("( /* why */ u8)1"),
+            ),
+            (
+                "/- // why\nnode",
+                "KDL v1 doesn't allow comments between `/-` and what it comments out, if they span lines.",
+                None,
+            ),
+            ("node /- #-inf", "KDL v1 has no way to write #-inf.", None),
+        ] {
+            let mut doc: KdlDocument = input.parse()?;
+            let err = doc.downgrade_to_v1().unwrap_err();
+            assert_eq!(err.message, message, "{input}");
+            assert_eq!(doc.to_string(), input, "{input}");
+            assert_eq!(doc.version(), KdlVersion::V2);
+            #[cfg(feature = "span")]
+            assert_eq!(
+                err.span.map(|span| &input[span.offset()..][..span.len()]),
+                at,
+                "{input}"
+            );
+            #[cfg(not(feature = "span"))]
+            let _ = at;
+        }
+        Ok(())
+    }
+}
@@ -2,13 +2,17 @@
 use miette::SourceSpan;
 use std::{fmt::Display, str::FromStr};
 
-use crate::{v2_parser, KdlIdentifier, KdlParseFailure, KdlValue};
+use crate::{
+    v2_parser, KdlCoercedValue, KdlIdentifier, KdlParseFailure, KdlReservedType, KdlTrivia,
+    KdlTypeError, KdlValidationErrorKind, KdlValue, StringStyle,
+};
 
 /// KDL Entries are the "arguments" to KDL nodes: either a (positional)
 /// [`Argument`](https://github.com/kdl-org/kdl/blob/main/SPEC.md#argument) or
 /// a (key/value)
 /// [`Property`](https://github.com/kdl-org/kdl/blob/main/SPEC.md#property)
 #[derive(Debug, Clone, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct KdlEntry {
     pub(crate) ty: Option<KdlIdentifier>,
     pub(crate) value: KdlValue,
@@ -51,6 +55,42 @@ impl KdlEntry {
         }
     }
 
+    /// Returns `true` if this is an argument (positional) entry.
+    pub fn is_argument(&self) -> bool {
+        self.name.is_none()
+    }
+
+    /// Returns `true` if this is a property (key/value) entry.
+    pub fn is_property(&self) -> bool {
+        self.name.is_some()
+    }
+
+    /// Returns `true` if this entry's value is a number written as a keyword,
+    /// such as `#inf`, `#-inf`, or `#nan`, rather than as a numeric literal.
+    ///
+    /// For entries with formatting details, such as parsed ones, this goes by
+    /// how the value was written. Otherwise, it's whether the value will be
+    /// written as a keyword, which is the case for non-finite floats.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlEntry;
+    /// assert!("#inf".parse::<KdlEntry>().unwrap().is_keyword_number());
+    /// assert!(!"1.5".parse::<KdlEntry>().unwrap().is_keyword_number());
+    /// assert!(KdlEntry::new(f64::NAN).is_keyword_number());
+    /// assert!(!KdlEntry::new(1.5).is_keyword_number());
+    /// ```
+    pub fn is_keyword_number(&self) -> bool {
+        match (&self.value, &self.format) {
+            (KdlValue::Integer(_) | KdlValue::Float(_), Some(fmt)) => {
+                fmt.value_repr.starts_with('#')
+            }
+            (KdlValue::Float(value), None) => !value.is_finite(),
+            _ => false,
+        }
+    }
+
     /// Gets a reference to this entry's name, if it's a property entry.
     pub fn name(&self) -> Option<&KdlIdentifier> {
         self.name.as_ref()
@@ -72,13 +112,162 @@ impl KdlEntry {
     }
 
     /// Gets a mutable reference to this entry's value.
+    ///
+    /// Changes made through this reference aren't reflected in the entry's
+    /// formatting, which still holds the original value's representation.
+    /// Use [`Self::set_value`] to keep the two in sync.
     pub fn value_mut(&mut self) -> &mut KdlValue {
         &mut self.value
     }
 
-    /// Sets the entry's value.
+    /// Sets the entry's value. If the entry has formatting details, its
+    /// value representation is updated to match, while the rest of its
+    /// formatting is left alone.
+    ///
+    /// An explicit `+` sign (see [`Self::has_explicit_sign`]) is dropped,
+    /// unless the entry was parsed with
+    /// [`ParseOptions::keep_explicit_signs`](crate::ParseOptions::keep_explicit_signs)
+    /// and the new value is a positive number, too.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::{KdlDocument, KdlEntry, ParseOptions};
+    /// let mut entry: KdlEntry = "offset=+5".parse().unwrap();
+    /// entry.set_value(10);
+    /// assert_eq!(entry.to_string(), "offset=10");
+    ///
+    /// let options = ParseOptions {
+    ///     keep_explicit_signs: true,
+    ///     ..Default::default()
+    /// };
+    /// let mut doc = KdlDocument::parse_with("node offset=+5", &options).unwrap();
+    /// let entry = &mut doc.nodes_mut()[0].entries_mut()[0];
+    /// entry.set_value(10);
+    /// assert_eq!(entry.to_string(), " offset=+10");
+    /// entry.set_value(-10);
+    /// assert_eq!(entry.to_string(), " offset=-10");
+    /// ```
     pub fn set_value(&mut self, value: impl Into<KdlValue>) {
+        let keep_sign = self.has_explicit_sign()
+            && self
+                .format
+                .as_ref()
+                .map_or(false, |fmt| fmt.keep_explicit_sign);
         self.value = value.into();
+        if let Some(fmt) = self.format.as_mut() {
+            fmt.value_repr = self.value.to_string();
+            let positive = match self.value {
+                KdlValue::Integer(value) => value >= 0,
+                KdlValue::Float(value) => value.is_finite() && value.is_sign_positive(),
+                _ => false,
+            };
+            if keep_sign && positive {
+                fmt.value_repr.insert(0, '+');
+            }
+        }
+    }
+
+    /// Returns `true` if this entry's value is a number written with an
+    /// explicit leading `+`, like `+5`, going by its formatting details.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlEntry;
+    /// assert!("+5".parse::<KdlEntry>().unwrap().has_explicit_sign());
+    /// assert!(!"5".parse::<KdlEntry>().unwrap().has_explicit_sign());
+    /// assert!(!"-5".parse::<KdlEntry>().unwrap().has_explicit_sign());
+    /// ```
+    pub fn has_explicit_sign(&self) -> bool {
+        matches!(self.value, KdlValue::Integer(_) | KdlValue::Float(_))
+            && self
+                .format
+                .as_ref()
+                .map_or(false, |fmt| fmt.value_repr.starts_with('+'))
+    }
+
+    /// Checks this entry's value against its type annotation, converting it
+    /// where possible, as with [`KdlValue::coerce`]. Entries without a type
+    /// annotation are [`KdlCoercedValue::Uninterpreted`].
+    pub fn coerce(&self) -> Result<KdlCoercedValue, KdlTypeError> {
+        match &self.ty {
+            Some(ty) => self.value.coerce(ty),
+            None => Ok(KdlCoercedValue::Uninterpreted(self.value.clone())),
+        }
+    }
+
+    /// Gets this entry's value as an `i64`, after checking it against its
+    /// type annotation, so `(u8)300` is an error even though 300 fits in an
+    /// `i64`. Floats, including integers annotated with a float type like
+    /// `(f32)5`, are errors too.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlEntry;
+    /// let entry = |s: &str| s.parse::<KdlEntry>().unwrap();
+    /// assert_eq!(entry("(u8)255").as_i64_checked(), Ok(255));
+    /// assert!(entry("(u8)300").as_i64_checked().is_err());
+    /// assert_eq!(entry("(custom)300").as_i64_checked(), Ok(300));
+    /// assert!(entry("0x8000_0000_0000_0000").as_i64_checked().is_err());
+    /// ```
+    pub fn as_i64_checked(&self) -> Result<i64, KdlTypeError> {
+        match self.coerce_to(KdlReservedType::I64)? {
+            KdlCoercedValue::Integer(int) => Ok(int as i64),
+            _ => unreachable!("Integer types always coerce to integers."),
+        }
+    }
+
+    /// Gets this entry's value as a `u64`, after checking it against its
+    /// type annotation, like [`Self::as_i64_checked`].
+    pub fn as_u64_checked(&self) -> Result<u64, KdlTypeError> {
+        match self.coerce_to(KdlReservedType::U64)? {
+            KdlCoercedValue::Integer(int) => Ok(int as u64),
+            _ => unreachable!("Integer types always coerce to integers."),
+        }
+    }
+
+    /// Gets this entry's value as an `f64`, after checking it against its
+    /// type annotation. Integers are converted, unless they're annotated
+    /// with a type whose range they're outside of.
+    pub fn as_f64_checked(&self) -> Result<f64, KdlTypeError> {
+        match self.coerce_to(KdlReservedType::F64)? {
+            KdlCoercedValue::Float(float) => Ok(float),
+            _ => unreachable!("Float types always coerce to floats."),
+        }
+    }
+
+    /// Gets this entry's value as an `f32`, after checking it against its
+    /// type annotation. Finite values too large for an `f32` are errors,
+    /// rather than turning into infinities.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlEntry;
+    /// let entry = |s: &str| s.parse::<KdlEntry>().unwrap();
+    /// assert_eq!(entry("(f32)1.5").as_f32_checked(), Ok(1.5));
+    /// assert_eq!(entry("(i8)-3").as_f32_checked(), Ok(-3.0));
+    /// assert!(entry("1e39").as_f32_checked().is_err());
+    /// assert!(entry("(f32)\"1.5\"").as_f32_checked().is_err());
+    /// ```
+    pub fn as_f32_checked(&self) -> Result<f32, KdlTypeError> {
+        match self.coerce_to(KdlReservedType::F32)? {
+            KdlCoercedValue::Float(float) => Ok(float as f32),
+            _ => unreachable!("Float types always coerce to floats."),
+        }
+    }
+
+    /// Coerces this entry's value according to its own type annotation,
+    /// then according to `target`.
+    fn coerce_to(&self, target: KdlReservedType) -> Result<KdlCoercedValue, KdlTypeError> {
+        let value = match self.coerce()? {
+            KdlCoercedValue::Integer(int) => KdlValue::Integer(int),
+            KdlCoercedValue::Float(float) => KdlValue::Float(float),
+            _ => self.value.clone(),
+        };
+        target.coerce(&value)
     }
 
     /// Gets this entry's span.
@@ -112,6 +301,11 @@ impl KdlEntry {
         self.ty = Some(ty.into());
     }
 
+    /// Whether this entry's type annotation is `ty`, as in `(ty)value`.
+    pub fn has_ty(&self, ty: &str) -> bool {
+        self.ty.as_ref().map_or(false, |own| own.value() == ty)
+    }
+
     /// Gets the formatting details for this entry.
     pub fn format(&self) -> Option<&KdlEntryFormat> {
         self.format.as_ref()
@@ -139,6 +333,27 @@ impl KdlEntry {
         }
     }
 
+    /// Parses a single argument or property, like `(u8)42` or `key="value"`.
+    ///
+    /// This is the same as using [`str::parse`]. The entry keeps its type
+    /// annotation and formatting, so writing it back out gives the input
+    /// unchanged. Anything after the entry, other than whitespace and
+    /// comments, is an error.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::{KdlEntry, KdlValue};
+    /// let entry = KdlEntry::parse("size=(u8)42").unwrap();
+    /// assert_eq!(entry.ty().map(|ty| ty.value()), Some("u8"));
+    /// assert_eq!(entry.value(), &KdlValue::Integer(42));
+    /// assert_eq!(entry.to_string(), "size=(u8)42");
+    /// assert!(KdlEntry::parse("42 foo").is_err());
+    /// ```
+    pub fn parse(input: &str) -> Result<Self, KdlParseFailure> {
+        input.parse()
+    }
+
     /// Clears leading and trailing text (whitespace, comments), as well as
     /// resetting this entry's value to its default representation.
     pub fn clear_format(&mut self) {
@@ -151,9 +366,36 @@ impl KdlEntry {
         }
     }
 
+    /// Compares two entries by what they mean rather than how they're
+    /// written: their type annotations, names, and values are compared, while
+    /// formatting (including identifier quoting and spacing inside type
+    /// annotations) and spans are ignored.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlEntry;
+    /// let left: KdlEntry = "(u8)10".parse().unwrap();
+    /// let right: KdlEntry = "( \"u8\" )0xa".parse().unwrap();
+    /// assert_ne!(left, right);
+    /// assert!(left.semantic_eq(&right));
+    /// ```
+    pub fn semantic_eq(&self, other: &Self) -> bool {
+        self.ty.as_ref().map(|ty| ty.value()) == other.ty.as_ref().map(|ty| ty.value())
+            && self.name.as_ref().map(|name| name.value())
+                == other.name.as_ref().map(|name| name.value())
+            && self.value == other.value
+    }
+
     /// Length of this entry when rendered as a string.
     pub fn len(&self) -> usize {
-        format!("{}", self).len()
+        self.repr_len()
+    }
+
+    /// Length in bytes of this entry's textual representation, including its
+    /// formatting, computed without allocating the string.
+    pub fn repr_len(&self) -> usize {
+        crate::fmt::display_len(self)
     }
 
     /// Returns true if this entry is completely empty (including whitespace).
@@ -161,8 +403,108 @@ impl KdlEntry {
         self.len() == 0
     }
 
+    /// Removes any whitespace and comments inside or right after this
+    /// entry's type annotation, so it's written as `(name)value`. The type
+    /// name's own representation, including any quoting, is kept.
+    pub fn normalize_type_annotation(&mut self) {
+        if self.ty.is_none() {
+            return;
+        }
+        if let Some(fmt) = self.format_mut() {
+            fmt.before_ty_name.clear();
+            fmt.after_ty_name.clear();
+            fmt.after_ty.clear();
+        }
+    }
+
+    /// Rewrites this entry's name, type annotation, and string value, if it
+    /// has one, in the given [`StringStyle`], where valid.
+    pub fn normalize_string_style(&mut self, style: StringStyle) {
+        for ident in self.name.iter_mut().chain(self.ty.iter_mut()) {
+            ident.normalize_string_style(style);
+        }
+        if let KdlValue::String(string) = &self.value {
+            let value_repr = style.render(string, true);
+            match self.format.as_mut() {
+                Some(fmt) => fmt.value_repr = value_repr,
+                None => {
+                    self.format = Some(KdlEntryFormat {
+                        value_repr,
+                        leading: " ".into(),
+                        ..Default::default()
+                    })
+                }
+            }
+        }
+    }
+
+    /// Pads the space between this entry's key and its `=` with `width`
+    /// spaces, giving it an explicit format if it doesn't have one yet.
+    pub(crate) fn pad_after_key(&mut self, width: usize) {
+        let value_repr = self.value.to_string();
+        let fmt = self.format.get_or_insert_with(|| KdlEntryFormat {
+            value_repr,
+            leading: " ".into(),
+            ..Default::default()
+        });
+        fmt.after_key
+            .to_mut()
+            .extend(std::iter::repeat(' ').take(width));
+    }
+
+    /// Rewrites this entry's value representation into its canonical form,
+    /// if its value is a number.
+    pub(crate) fn normalize_number(&mut self) {
+        if let (KdlValue::Integer(_) | KdlValue::Float(_), Some(fmt)) =
+            (&self.value, self.format.as_mut())
+        {
+            fmt.value_repr = self.value.to_string();
+        }
+    }
+
+    /// Checks that this entry's name and type annotation are valid
+    /// identifiers, and that its value, as written out, parses back to the
+    /// same value.
+    pub(crate) fn validate_impl(&self) -> Vec<KdlValidationErrorKind> {
+        let mut errors = Vec::new();
+        for ident in self.name.iter().chain(self.ty.iter()) {
+            if !ident.round_trips() {
+                errors.push(KdlValidationErrorKind::InvalidIdentifier {
+                    value: ident.value().into(),
+                    repr: ident.to_string(),
+                });
+            }
+        }
+        let repr = match &self.format {
+            Some(fmt) => fmt.value_repr.clone(),
+            None => self.value.to_string(),
+        };
+        let round_trips = v2_parser::try_parse(v2_parser::bare_value, &repr)
+            .map_or(false, |parsed| parsed.as_ref() == Some(&self.value));
+        if !round_trips {
+            errors.push(KdlValidationErrorKind::InvalidValue {
+                value: self.value.clone(),
+                repr,
+            });
+        }
+        errors
+    }
+
     /// Auto-formats this entry.
     pub fn autoformat(&mut self) {
+        self.autoformat_impl(false);
+    }
+
+    /// Auto-formats this entry. With `keep_slashdashes`, any slashdashed
+    /// entries right before it are kept, rather than being dropped along
+    /// with the rest of its formatting.
+    pub(crate) fn autoformat_impl(&mut self, keep_slashdashes: bool) {
+        let slashdashed = self
+            .format
+            .as_ref()
+            .filter(|_| keep_slashdashes)
+            .map(|f| crate::fmt::slashdashed_tail(&f.leading).to_string())
+            .unwrap_or_default();
         // TODO once MSRV allows:
         //self.format.take_if(|f| !f.autoformat_keep);
         if !self
@@ -177,6 +519,14 @@ impl KdlEntry {
         if let Some(name) = &mut self.name {
             name.autoformat();
         }
+
+        if !slashdashed.is_empty() && self.format.is_none() {
+            self.format = Some(KdlEntryFormat {
+                value_repr: self.value.to_string(),
+                leading: format!(" {slashdashed} ").into(),
+                ..Default::default()
+            });
+        }
     }
 }
 
@@ -255,28 +605,34 @@ impl FromStr for KdlEntry {
 
 /// Formatting details for [`KdlEntry`]s.
 #[derive(Debug, Default, Clone, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct KdlEntryFormat {
     /// The actual text representation of the entry's value.
     pub value_repr: String,
     /// Whitespace and comments preceding the entry itself.
-    pub leading: String,
+    pub leading: KdlTrivia,
     /// Whitespace and comments following the entry itself.
-    pub trailing: String,
+    pub trailing: KdlTrivia,
     /// Whitespace and comments after the entry's type annotation's closing
     /// `)`, before its value.
-    pub after_ty: String,
+    pub after_ty: KdlTrivia,
     /// Whitespace and comments between the opening `(` of an entry's type
     /// annotation and its actual type name.
-    pub before_ty_name: String,
+    pub before_ty_name: KdlTrivia,
     /// Whitespace and comments between the actual type name and the closing
     /// `)` in an entry's type annotation.
-    pub after_ty_name: String,
+    pub after_ty_name: KdlTrivia,
     /// Whitespace and comments between an entry's key name and its equals sign.
-    pub after_key: String,
+    pub after_key: KdlTrivia,
     /// Whitespace and comments between an entry's equals sign and its value.
-    pub after_eq: String,
+    pub after_eq: KdlTrivia,
     /// Do not clobber this format during autoformat
     pub autoformat_keep: bool,
+    /// Keep an explicit `+` sign on the value when it's replaced through
+    /// [`KdlEntry::set_value`]. Set by the parser when
+    /// [`ParseOptions::keep_explicit_signs`](crate::ParseOptions::keep_explicit_signs)
+    /// is on.
+    pub keep_explicit_sign: bool,
 }
 
 #[cfg(test)]
@@ -294,6 +650,104 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn explicit_sign() -> miette::Result<()> {
+        for (input, expected) in [
+            ("+5", true),
+            ("+0x10", true),
+            ("+1.5e3", true),
+            ("key=+0", true),
+            ("5", false),
+            ("-5", false),
+            ("\"+5\"", false),
+            ("+five", false),
+        ] {
+            let entry: KdlEntry = input.parse()?;
+            assert_eq!(entry.has_explicit_sign(), expected, "{input}");
+        }
+
+        // By default, setting a value normalizes it like any other.
+        let mut entry: KdlEntry = "(u8)+0x10".parse()?;
+        entry.set_value(0);
+        assert_eq!(entry.to_string(), "(u8)0");
+        assert!(!entry.has_explicit_sign());
+
+        let options = crate::ParseOptions {
+            keep_explicit_signs: true,
+            ..Default::default()
+        };
+        let mut doc = crate::KdlDocument::parse_with("node (u8)+0x10", &options)?;
+        let mut entry = doc.nodes_mut()[0].entries_mut().remove(0);
+        if let Some(fmt) = entry.format_mut() {
+            fmt.leading = "".into();
+        }
+        entry.set_value(0);
+        assert_eq!(entry.to_string(), "(u8)+0");
+        entry.set_value(2.5);
+        assert_eq!(entry.to_string(), "(u8)+2.5");
+        entry.set_value(f64::INFINITY);
+        assert_eq!(entry.to_string(), "(u8)#inf");
+        assert!(!entry.has_explicit_sign());
+        // Once it's gone, it's gone.
+        entry.set_value(1);
+        assert_eq!(entry.to_string(), "(u8)1");
+
+        let mut entry: KdlEntry = "+1".parse()?;
+        entry.set_value("text");
+        assert_eq!(entry.to_string(), "text");
+        assert!(!entry.has_explicit_sign());
+        Ok(())
+    }
+
+    #[test]
+    fn is_keyword_number() -> miette::Result<()> {
+        for (input, expected) in [
+            ("#inf", true),
+            ("#-inf", true),
+            ("#nan", true),
+            ("key=#inf", true),
+            ("(f64)#nan", true),
+            ("1.0", false),
+            ("1e308", false),
+            ("1", false),
+            ("#true", false),
+            ("\"#inf\"", false),
+        ] {
+            let entry: KdlEntry = input.parse()?;
+            assert_eq!(entry.is_keyword_number(), expected, "{input}");
+        }
+
+        let mut entry: KdlEntry = "1.0".parse()?;
+        entry.set_value(f64::INFINITY);
+        assert!(entry.is_keyword_number());
+        entry.set_value(2.5);
+        assert!(!entry.is_keyword_number());
+        entry.clear_format();
+        assert!(!entry.is_keyword_number());
+        entry.set_value(f64::NEG_INFINITY);
+        assert!(entry.is_keyword_number());
+        Ok(())
+    }
+
+    #[test]
+    fn semantic_eq() -> miette::Result<()> {
+        let entry: KdlEntry = "(u8)10".parse()?;
+        assert!(entry.semantic_eq(&"( u8 )10".parse()?));
+        assert!(entry.semantic_eq(&"(\"u8\")0xA".parse()?));
+        let mut built = KdlEntry::new(10);
+        built.set_ty("u8");
+        assert!(entry.semantic_eq(&built));
+        assert!(!entry.semantic_eq(&"(u16)10".parse()?));
+        assert!(!entry.semantic_eq(&"10".parse()?));
+        assert!(!entry.semantic_eq(&"(u8)11".parse()?));
+
+        let prop: KdlEntry = "name = \"x\"".parse()?;
+        assert!(prop.semantic_eq(&"\"name\"=x".parse()?));
+        assert!(!prop.semantic_eq(&"other=x".parse()?));
+        assert!(!prop.semantic_eq(&"x".parse()?));
+        Ok(())
+    }
+
     #[test]
     fn new() {
         let entry = KdlEntry::new(42);
@@ -390,7 +844,8 @@ mod test {
                     after_ty: "".into(),
                     after_key: "".into(),
                     after_eq: "".into(),
-                    autoformat_keep: false
+                    autoformat_keep: false,
+                    keep_explicit_sign: false,
                 }),
                 ty: Some("\"m\\\"eh\"".parse()?),
                 value: KdlValue::Integer(0xdeadbeef),
@@ -400,6 +855,19 @@ mod test {
             }
         );
 
+        for input in ["(u8)42", "key=##\"a\"#\"##", " x=#-inf ", "(f64)#nan"] {
+            assert_eq!(KdlEntry::parse(input)?.to_string(), input);
+        }
+        for (input, kind) in [
+            ("42 foo", crate::KdlErrorKind::TrailingInput("entry")),
+            ("a=1 b=2", crate::KdlErrorKind::TrailingInput("entry")),
+            ("", crate::KdlErrorKind::Context("entry")),
+        ] {
+            let err = KdlEntry::parse(input).unwrap_err();
+            assert_eq!(err.diagnostics.len(), 1, "{input:?}");
+            assert_eq!(err.diagnostics[0].kind, kind, "{input:?}");
+        }
+
         Ok(())
     }
 
@@ -1,4 +1,5 @@
 use std::{
+    collections::BTreeMap,
     num::{ParseFloatError, ParseIntError},
     sync::Arc,
 };
@@ -47,6 +48,282 @@ pub struct KdlParseFailure {
     /// Sub-diagnostics for this failure.
     #[related]
     pub diagnostics: Vec<KdlDiagnostic>,
+
+    /// Whether some diagnostics were left out because of
+    /// [`ParseOptions::max_diagnostics`](crate::ParseOptions::max_diagnostics).
+    pub diagnostics_truncated: bool,
+}
+
+impl KdlParseFailure {
+    /// Converts a byte offset into [`Self::input`], such as the start of a
+    /// diagnostic's span, into a 1-based line and column, with the column
+    /// counted in `char`s.
+    ///
+    /// Lines are split the same way KDL splits them, so `\r\n`, `U+0085`,
+    /// `U+2028`, and the rest of KDL's newlines all count as line breaks.
+    /// Offsets past the end of the input are clamped to the end of it, and
+    /// offsets inside a multi-byte character are rounded down to its start.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlDocument;
+    /// let err = "a 1\r\nb\u{2028}c 1.".parse::<KdlDocument>().unwrap_err();
+    /// let offset = err.diagnostics[0].span.offset();
+    /// assert_eq!(err.line_col(offset), (3, 4));
+    /// ```
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        line_col(&self.input, offset, false)
+    }
+
+    /// Like [`Self::line_col`], but with the column counted in UTF-16 code
+    /// units, which is what most editors and the Language Server Protocol
+    /// use. The line is still 1-based, as is the column.
+    pub fn line_col_utf16(&self, offset: usize) -> (usize, usize) {
+        line_col(&self.input, offset, true)
+    }
+}
+
+/// Finds the 1-based line and column of `offset` in `input`, counting the
+/// column in UTF-16 code units if `utf16` is set, or in `char`s otherwise.
+pub(crate) fn line_col(input: &str, offset: usize, utf16: bool) -> (usize, usize) {
+    let mut offset = offset.min(input.len());
+    while !input.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let (mut line, mut col) = (1, 1);
+    for (idx, c) in input[..offset].char_indices() {
+        // `\r\n` is a single newline, so only its `\n` starts a new line.
+        let crlf = c == '\r' && input[idx + 1..].starts_with('\n');
+        if crate::fmt::is_newline(c) && !crlf {
+            line += 1;
+            col = 1;
+        } else if utf16 {
+            col += c.len_utf16();
+        } else {
+            col += 1;
+        }
+    }
+    (line, col)
+}
+
+/// A collection of KDL parse failures from multiple sources, such as a
+/// directory full of KDL files, which renders as a single report.
+///
+/// Each failure is recorded along with the name of the source it came from,
+/// which is used as a header for that source's diagnostics when rendered.
+///
+/// ```no_run
+/// # use kdl::{KdlDocument, KdlDiagnosticReport};
+/// fn main() -> miette::Result<()> {
+///     let mut report = KdlDiagnosticReport::new();
+///     for name in ["a.kdl", "b.kdl"] {
+///         let src = std::fs::read_to_string(name).unwrap();
+///         report.record(name, src.parse::<KdlDocument>());
+///     }
+///     report.into_result()?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Diagnostic, Clone, Default, Eq, PartialEq, Error)]
+#[error("Failed to parse {} KDL source(s).", failures.len())]
+pub struct KdlDiagnosticReport {
+    /// Per-source failures in this report.
+    #[related]
+    pub failures: Vec<KdlSourceFailure>,
+}
+
+impl KdlDiagnosticReport {
+    /// Creates a new, empty report.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Adds a failure for the source with the given name.
+    pub fn push(&mut self, name: impl Into<String>, failure: KdlParseFailure) -> &mut Self {
+        self.failures.push(KdlSourceFailure {
+            name: name.into(),
+            input: failure.input,
+            diagnostics: failure.diagnostics,
+            diagnostics_truncated: failure.diagnostics_truncated,
+        });
+        self
+    }
+
+    /// Records the result of parsing the source with the given name, adding
+    /// its failure to this report if there was one, and returning the parsed
+    /// value otherwise.
+    pub fn record<T>(
+        &mut self,
+        name: impl Into<String>,
+        result: Result<T, KdlParseFailure>,
+    ) -> Option<T> {
+        match result {
+            Ok(val) => Some(val),
+            Err(failure) => {
+                self.push(name, failure);
+                None
+            }
+        }
+    }
+
+    /// Returns true if no failures have been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// Number of sources that failed to parse.
+    pub fn len(&self) -> usize {
+        self.failures.len()
+    }
+
+    /// Total number of diagnostics across all sources.
+    pub fn diagnostics_count(&self) -> usize {
+        self.failures.iter().map(|f| f.diagnostics.len()).sum()
+    }
+
+    /// Returns `Ok(())` if no failures were recorded, or the report itself
+    /// as an error otherwise.
+    pub fn into_result(self) -> Result<(), Self> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+/// A [`KdlParseFailure`] for a single, named source within a
+/// [`KdlDiagnosticReport`].
+#[derive(Debug, Diagnostic, Clone, Eq, PartialEq, Error)]
+#[error("{name}: failed to parse KDL.")]
+pub struct KdlSourceFailure {
+    /// Name of the source this failure came from, usually a file path.
+    pub name: String,
+
+    /// Original input that this failure came from.
+    #[source_code]
+    pub input: Arc<String>,
+
+    /// Sub-diagnostics for this failure.
+    #[related]
+    pub diagnostics: Vec<KdlDiagnostic>,
+
+    /// Whether some diagnostics were left out. See
+    /// [`KdlParseFailure::diagnostics_truncated`].
+    pub diagnostics_truncated: bool,
+}
+
+impl From<KdlSourceFailure> for KdlParseFailure {
+    fn from(failure: KdlSourceFailure) -> Self {
+        KdlParseFailure {
+            input: failure.input,
+            diagnostics: failure.diagnostics,
+            diagnostics_truncated: failure.diagnostics_truncated,
+        }
+    }
+}
+
+/// Schema violations found in a document, grouped by the path of the node
+/// each one was found on, so they can be summarized as a tree instead of a
+/// flat list.
+///
+/// Node paths are lists of child indices, starting from the document root:
+/// `[1, 0]` is the first child of the second top-level node, and `[]` is the
+/// document itself. Groups are kept in document order.
+///
+/// ```rust
+/// # use kdl::{KdlDiagnostic, KdlErrorKind, SchemaReport};
+/// # use std::sync::Arc;
+/// # let input = Arc::new(String::from("a; b { c; }"));
+/// let violation = |offset: usize, message: &str| KdlDiagnostic {
+///     input: input.clone(),
+///     span: (offset, 1).into(),
+///     label: None,
+///     help: None,
+///     severity: miette::Severity::Error,
+///     kind: KdlErrorKind::SchemaViolation(message.into()),
+/// };
+/// let mut report = SchemaReport::new();
+/// report.push(vec![1, 0], violation(7, "unknown node"));
+/// report.push(vec![0], violation(0, "missing argument"));
+/// report.push(vec![1, 0], violation(7, "missing property"));
+///
+/// let paths = report.groups().map(|(path, _)| path).collect::<Vec<_>>();
+/// assert_eq!(paths, vec![&[0][..], &[1, 0][..]]);
+/// assert_eq!(report.violations_at(&[1, 0]).len(), 2);
+/// assert_eq!(report.len(), 3);
+/// ```
+#[derive(Debug, Clone, Default, Eq, PartialEq, Error)]
+#[error("Document failed schema validation with {} violation(s).", self.len())]
+pub struct SchemaReport {
+    groups: BTreeMap<Vec<usize>, Vec<KdlDiagnostic>>,
+}
+
+impl SchemaReport {
+    /// Creates a new, empty report.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Adds a violation found on the node at `path`.
+    pub fn push(&mut self, path: impl Into<Vec<usize>>, violation: KdlDiagnostic) -> &mut Self {
+        self.groups.entry(path.into()).or_default().push(violation);
+        self
+    }
+
+    /// Returns true if no violations have been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// Total number of violations, across all nodes.
+    pub fn len(&self) -> usize {
+        self.groups.values().map(Vec::len).sum()
+    }
+
+    /// Iterates over node paths and the violations found on each of them, in
+    /// document order. A node comes right before its children.
+    pub fn groups(&self) -> impl Iterator<Item = (&[usize], &[KdlDiagnostic])> {
+        self.groups
+            .iter()
+            .map(|(path, violations)| (&path[..], &violations[..]))
+    }
+
+    /// Violations found on the node at `path`, if any.
+    pub fn violations_at(&self, path: &[usize]) -> &[KdlDiagnostic] {
+        self.groups
+            .get(path)
+            .map_or(&[], |violations| &violations[..])
+    }
+
+    /// Iterates over all violations, in the same order as [`Self::groups`].
+    pub fn violations(&self) -> impl Iterator<Item = &KdlDiagnostic> {
+        self.groups.values().flatten()
+    }
+
+    /// Returns `Ok(())` if no violations were recorded, or the report itself
+    /// as an error otherwise.
+    pub fn into_result(self) -> Result<(), Self> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl Diagnostic for SchemaReport {
+    fn code<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
+        Some(Box::new("kdl::schema_report"))
+    }
+
+    fn related<'a>(&'a self) -> Option<Box<dyn Iterator<Item = &'a dyn Diagnostic> + 'a>> {
+        Some(Box::new(
+            self.violations()
+                .map(|violation| violation as &dyn Diagnostic),
+        ))
+    }
 }
 
 /// An individual diagnostic message for a KDL parsing issue.
@@ -78,10 +355,199 @@ pub struct KdlDiagnostic {
     pub kind: KdlErrorKind,
 }
 
+/// Returned by [`KdlValue::try_float`](crate::KdlValue::try_float) when
+/// given NaN or an infinity.
+#[derive(Debug, Diagnostic, Clone, Copy, PartialEq, Error)]
+#[error("{value} is not a finite number.")]
+#[diagnostic(
+    code(kdl::non_finite_float),
+    help("Only finite floats are allowed here. Use `KdlValue::Float` directly to allow `#inf`, `#-inf`, and `#nan`.")
+)]
+pub struct NonFiniteError {
+    /// The rejected value.
+    pub value: f64,
+}
+
+/// Returned by [`KdlValue::from_json_scalar`](crate::KdlValue::from_json_scalar)
+/// and [`KdlValue::to_json_scalar`](crate::KdlValue::to_json_scalar) when a
+/// value has no equivalent on the other side.
+#[cfg(feature = "json")]
+#[derive(Debug, Diagnostic, Clone, PartialEq, Eq, Error)]
+pub enum JsonScalarError {
+    /// The JSON value was an array or an object, rather than a scalar.
+    #[error("Expected a JSON scalar, but got {0}.")]
+    #[diagnostic(
+        code(kdl::json_not_scalar),
+        help("Only numbers, strings, booleans, and null can be converted to a single KDL value.")
+    )]
+    NotScalar(&'static str),
+
+    /// The JSON value was an integer too large to fit in an `i128`.
+    #[error("JSON integer {0} doesn't fit in an i128.")]
+    #[diagnostic(code(kdl::json_integer_out_of_range))]
+    IntegerOutOfRange(String),
+
+    /// The KDL value was an integer outside the range of both `i64` and
+    /// `u64`, which is all JSON numbers can portably hold.
+    #[error("KDL integer {0} doesn't fit in a JSON number.")]
+    #[diagnostic(
+        code(kdl::json_integer_too_large),
+        help("JSON integers have to fit in an i64 or a u64.")
+    )]
+    IntegerTooLarge(i128),
+
+    /// The KDL value was `#inf`, `#-inf`, or `#nan`, which JSON numbers
+    /// can't represent. The string is the value as KDL.
+    #[error("KDL value {0} has no JSON equivalent.")]
+    #[diagnostic(code(kdl::json_non_finite))]
+    NonFinite(String),
+}
+
+/// Returned by [`KdlDocument::to_json`](crate::KdlDocument::to_json) and
+/// [`KdlDocument::from_json`](crate::KdlDocument::from_json) when
+/// something can't be converted. The pointer is a
+/// [JSON Pointer](https://www.rfc-editor.org/rfc/rfc6901) to where the
+/// problem is in the JSON form of the document, such as `/0/args/1`.
+#[cfg(feature = "json")]
+#[derive(Debug, Diagnostic, Clone, PartialEq, Eq, Error)]
+#[error("{message} (at {pointer:?})")]
+#[diagnostic(code(kdl::json_document))]
+pub struct JsonDocumentError {
+    /// Where the problem is, as a JSON Pointer.
+    pub pointer: String,
+    /// What went wrong.
+    pub message: String,
+}
+
+/// Returned when converting between KDL and another format or a Rust type
+/// fails: by the `jik` and `xik` functions, the `convert::toml` and
+/// `convert::yaml` functions, `KdlDocument::downgrade_to_v1`, and
+/// [`KdlDecode`](crate::KdlDecode).
+#[derive(Debug, Diagnostic, Clone, PartialEq, Eq, Error)]
+#[error("{message}")]
+#[diagnostic(code(kdl::conversion))]
+pub struct KdlConversionError {
+    /// What went wrong.
+    pub message: String,
+
+    /// Where the problem is, if that's known. Errors about the other format,
+    /// such as XML or YAML that can't be read, point into its source text.
+    /// Errors about a KDL document point at the node or entry with the
+    /// problem, which needs the `span` feature, and isn't known for
+    /// slashdashed nodes and entries.
+    #[label("here")]
+    pub span: Option<SourceSpan>,
+}
+
+/// Returned by [`ser::to_document`](crate::ser::to_document) and
+/// [`ser::to_string`](crate::ser::to_string) when a value can't be written
+/// as KDL.
+#[cfg(feature = "serde")]
+#[derive(Debug, Diagnostic, Clone, PartialEq, Eq, Error)]
+#[error("{message}")]
+#[diagnostic(code(kdl::serialize))]
+pub struct KdlSerializeError {
+    /// What went wrong.
+    pub message: String,
+}
+
+#[cfg(feature = "serde")]
+impl serde::ser::Error for KdlSerializeError {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        KdlSerializeError {
+            message: msg.to_string(),
+        }
+    }
+}
+
+/// Returned when a value doesn't match its reserved type annotation, as by
+/// [`KdlValue::coerce`](crate::KdlValue::coerce).
+#[derive(Debug, Diagnostic, Clone, PartialEq, Error)]
+pub enum KdlTypeError {
+    /// The value is a number, but doesn't fit in the annotated type.
+    #[error("({ty}){value} is out of range for {ty}.")]
+    #[diagnostic(code(kdl::type_out_of_range))]
+    OutOfRange {
+        /// The annotated type.
+        ty: &'static str,
+        /// The rejected value.
+        value: crate::KdlValue,
+    },
+
+    /// The value is the wrong kind of value for the annotated type, such as
+    /// a string annotated with `(u8)`.
+    #[error("({ty}){value} is not valid for {ty}, which requires {expected}.")]
+    #[diagnostic(code(kdl::type_mismatch))]
+    Mismatch {
+        /// The annotated type, or the type being converted to.
+        ty: &'static str,
+        /// What kind of value the type requires.
+        expected: &'static str,
+        /// The rejected value.
+        value: crate::KdlValue,
+    },
+
+    /// A `(base64)` string isn't valid base64.
+    #[error("{value:?} is not valid base64.")]
+    #[diagnostic(
+        code(kdl::invalid_base64),
+        help(
+            "(base64) strings must use the standard RFC 4648 alphabet, with optional `=` padding."
+        )
+    )]
+    InvalidBase64 {
+        /// The rejected string.
+        value: String,
+    },
+}
+
+/// A problem found by [`KdlDocument::validate`](crate::KdlDocument::validate).
+#[derive(Debug, Diagnostic, Clone, PartialEq, Error)]
+#[error("{kind}")]
+#[diagnostic(code(kdl::validation))]
+pub struct KdlValidationError {
+    /// Path of child indices from the document to the node with the problem,
+    /// so `[1, 0]` is the first child of the second top-level node.
+    pub path: Vec<usize>,
+
+    /// Index into the node's entries of the entry with the problem, or `None`
+    /// if the problem is with the node itself.
+    pub entry: Option<usize>,
+
+    /// What's wrong.
+    pub kind: KdlValidationErrorKind,
+}
+
+/// The specific problem behind a [`KdlValidationError`].
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum KdlValidationErrorKind {
+    /// A node name, property name, or type annotation is written out in a
+    /// way that doesn't parse back to its value.
+    #[error("Identifier {value:?} is written as `{repr}`, which doesn't parse back to it.")]
+    InvalidIdentifier {
+        /// The identifier's value.
+        value: String,
+        /// How the identifier is written out.
+        repr: String,
+    },
+
+    /// An entry's value is written out in a way that doesn't parse back to
+    /// it.
+    #[error("Value {value:?} is written as `{repr}`, which doesn't parse back to it.")]
+    InvalidValue {
+        /// The entry's value.
+        value: crate::KdlValue,
+        /// How the value is written out.
+        repr: String,
+    },
+}
+
 /// A type representing additional information specific to the type of error being returned.
 #[derive(Debug, Diagnostic, Clone, Eq, PartialEq, Error)]
 pub enum KdlErrorKind {
-    /// An error occurred while parsing an integer.
+    /// An error occurred while parsing an integer, usually because it
+    /// doesn't fit in an `i128`. Use [`ParseIntError::kind`] to tell
+    /// overflow apart from other problems.
     #[error(transparent)]
     #[diagnostic(code(kdl::parse_int))]
     ParseIntError(ParseIntError),
@@ -96,6 +562,109 @@ pub enum KdlErrorKind {
     #[diagnostic(code(kdl::negative_unsigned))]
     NegativeUnsignedError,
 
+    /// A unicode escape in a string refers to a surrogate codepoint, which
+    /// isn't a valid Unicode scalar value.
+    #[error(
+        "\\u{{{0:X}}} is not a valid Unicode scalar value (surrogate codepoints are disallowed)."
+    )]
+    #[diagnostic(code(kdl::surrogate_unicode_escape))]
+    SurrogateUnicodeEscape(u32),
+
+    /// A unicode escape in a string refers to a codepoint above `U+10FFFF`.
+    #[error("\\u{{{0:X}}} is not a valid Unicode scalar value (codepoints above U+10FFFF are disallowed).")]
+    #[diagnostic(code(kdl::out_of_range_unicode_escape))]
+    OutOfRangeUnicodeEscape(u32),
+
+    /// A string literal contains a code point that isn't allowed to appear
+    /// literally, such as a control character, rather than as an escape.
+    #[error("Disallowed code point U+{0:04X} in a string literal; use an escape like \\u{{{0:02X}}} instead.")]
+    #[diagnostic(code(kdl::disallowed_code_point))]
+    DisallowedCodePoint(u32),
+
+    /// A children block wasn't closed before the end of the input.
+    #[error("Missing closing brace for block opened here.")]
+    #[diagnostic(code(kdl::missing_closing_brace))]
+    MissingClosingBrace,
+
+    /// A single-line comment appears in the middle of a node, such as right
+    /// after a property's `=`, where a value was expected.
+    #[error("Comments aren't allowed here; move it to its own line or before the node.")]
+    #[diagnostic(code(kdl::misplaced_comment))]
+    MisplacedComment,
+
+    /// A node had more entries than allowed by
+    /// [`ParseOptions::max_entries_per_node`](crate::ParseOptions::max_entries_per_node).
+    #[error("Node has more than {0} entries.")]
+    #[diagnostic(code(kdl::too_many_entries))]
+    TooManyEntries(usize),
+
+    /// Children blocks were nested deeper than allowed by
+    /// [`ParseOptions::max_depth`](crate::ParseOptions::max_depth), which is
+    /// [`ParseOptions::DEFAULT_MAX_DEPTH`](crate::ParseOptions::DEFAULT_MAX_DEPTH)
+    /// by default.
+    #[error("Children blocks are nested more than {0} deep.")]
+    #[diagnostic(code(kdl::too_deep))]
+    TooDeep(usize),
+
+    /// The input was longer than allowed by
+    /// [`ParseOptions::max_input_len`](crate::ParseOptions::max_input_len).
+    #[error("Input is longer than {0} bytes.")]
+    #[diagnostic(code(kdl::input_too_long))]
+    InputTooLong(usize),
+
+    /// Found `#+inf` or `#+nan`, which aren't valid keywords unless
+    /// [`ParseOptions::accept_signed_inf_nan`](crate::ParseOptions::accept_signed_inf_nan)
+    /// is set.
+    #[error("Keywords can't have a leading `+`.")]
+    #[diagnostic(code(kdl::signed_inf_nan))]
+    SignedInfNan,
+
+    /// A node's entries don't follow an [`EntryOrderRule`](crate::EntryOrderRule).
+    #[error("Entry is out of order: {0}.")]
+    #[diagnostic(code(kdl::entry_order))]
+    EntryOrder(crate::EntryOrderRule),
+
+    /// A node terminator doesn't follow a
+    /// [`TerminatorRule`](crate::TerminatorRule).
+    #[error("Unwanted node terminator: {0}.")]
+    #[diagnostic(code(kdl::terminator_style))]
+    TerminatorStyle(crate::TerminatorRule),
+
+    /// A document doesn't match its schema. Usually found in a
+    /// [`SchemaReport`].
+    #[error("{0}")]
+    #[diagnostic(code(kdl::schema_violation))]
+    SchemaViolation(String),
+
+    /// A document didn't fit the type it was being deserialized into, with
+    /// the `serde` feature. See [`de`](crate::de).
+    #[cfg(feature = "serde")]
+    #[error("{0}")]
+    #[diagnostic(code(kdl::deserialize))]
+    Deserialize(String),
+
+    /// There was more input after something that has to be parsed on its
+    /// own, like a value parsed with [`KdlValue::parse`](crate::KdlValue::parse).
+    /// The string names what was parsed.
+    #[error("Unexpected input after the {0}.")]
+    #[diagnostic(code(kdl::trailing_input))]
+    TrailingInput(&'static str),
+
+    /// Some of the input wasn't valid UTF-8, and was replaced with
+    /// `U+FFFD REPLACEMENT CHARACTER` by
+    /// [`KdlDocument::parse_bytes_lossy`](crate::KdlDocument::parse_bytes_lossy).
+    /// Holds the byte offset and length of the invalid sequence in the
+    /// original input.
+    #[error("Invalid UTF-8 ({1} byte(s) at byte {0}) was replaced with U+FFFD.")]
+    #[diagnostic(code(kdl::invalid_utf8))]
+    InvalidUtf8(usize, usize),
+
+    /// Reading the input failed, such as in
+    /// [`validate_reader`](crate::validate_reader).
+    #[error("Failed to read input: {0}")]
+    #[diagnostic(code(kdl::io))]
+    Io(String),
+
     /// Generic parsing error. The given context string denotes the component
     /// that failed to parse.
     #[error("Expected {0}.")]
@@ -108,3 +677,106 @@ pub enum KdlErrorKind {
     #[diagnostic(code(kdl::other))]
     Other,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::KdlDocument;
+
+    #[test]
+    fn report_aggregation() {
+        let mut report = KdlDiagnosticReport::new();
+        assert!(report
+            .record("ok.kdl", "foo 1".parse::<KdlDocument>())
+            .is_some());
+        assert!(report
+            .record("bad.kdl", "foo 1.".parse::<KdlDocument>())
+            .is_none());
+        assert!(report
+            .record("worse.kdl", "foo (\"x".parse::<KdlDocument>())
+            .is_none());
+        assert_eq!(report.len(), 2);
+        assert_eq!(report.failures[0].name, "bad.kdl");
+        assert_eq!(&report.failures[0].input[..], "foo 1.");
+        assert!(report.diagnostics_count() >= 2);
+        assert_eq!(report.to_string(), "Failed to parse 2 KDL source(s).");
+        assert_eq!(
+            report.failures[1].to_string(),
+            "worse.kdl: failed to parse KDL."
+        );
+        assert!(report.into_result().is_err());
+        assert!(KdlDiagnosticReport::new().into_result().is_ok());
+    }
+    #[test]
+    fn schema_report_grouping() {
+        let input = Arc::new(String::from("a 1\nb {\n    c\n    d\n}"));
+        let violation = |offset: usize, message: &str| KdlDiagnostic {
+            input: input.clone(),
+            span: (offset, 1).into(),
+            label: None,
+            help: None,
+            severity: miette::Severity::Error,
+            kind: KdlErrorKind::SchemaViolation(message.into()),
+        };
+        let mut report = SchemaReport::new();
+        assert!(report.is_empty());
+        report
+            .push(vec![1, 1], violation(20, "d is not allowed"))
+            .push(vec![1], violation(4, "b needs an argument"))
+            .push(vec![1, 0], violation(14, "c needs an argument"))
+            .push(vec![1], violation(4, "b needs a property"));
+
+        let groups = report
+            .groups()
+            .map(|(path, violations)| (path.to_vec(), violations.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(groups, vec![(vec![1], 2), (vec![1, 0], 1), (vec![1, 1], 1)]);
+        assert_eq!(
+            report.violations_at(&[1])[1].to_string(),
+            "b needs a property"
+        );
+        assert!(report.violations_at(&[0]).is_empty());
+        assert_eq!(
+            report
+                .violations()
+                .map(|v| v.span.offset())
+                .collect::<Vec<_>>(),
+            vec![4, 4, 14, 20]
+        );
+        assert_eq!(report.related().unwrap().count(), 4);
+        assert_eq!(
+            report.to_string(),
+            "Document failed schema validation with 4 violation(s)."
+        );
+        assert!(report.into_result().is_err());
+        assert!(SchemaReport::new().into_result().is_ok());
+    }
+
+    #[test]
+    fn line_col() {
+        let input = "a\r\nb\rc\nd\u{85}e\u{2028}f\u{c}😀é x";
+        let failure = KdlParseFailure {
+            input: Arc::new(input.into()),
+            diagnostics: Vec::new(),
+            diagnostics_truncated: false,
+        };
+        let offset = |s: &str| input.find(s).unwrap();
+        assert_eq!(failure.line_col(0), (1, 1));
+        assert_eq!(failure.line_col(1), (1, 2));
+        // Between the `\r` and `\n` is still the first line.
+        assert_eq!(failure.line_col(2), (1, 3));
+        assert_eq!(failure.line_col(offset("b")), (2, 1));
+        assert_eq!(failure.line_col(offset("c")), (3, 1));
+        assert_eq!(failure.line_col(offset("d")), (4, 1));
+        assert_eq!(failure.line_col(offset("e")), (5, 1));
+        assert_eq!(failure.line_col(offset("f")), (6, 1));
+        assert_eq!(failure.line_col(offset("😀")), (7, 1));
+        assert_eq!(failure.line_col(offset("x")), (7, 4));
+        assert_eq!(failure.line_col_utf16(offset("x")), (7, 5));
+        // Inside the emoji rounds down to its start.
+        assert_eq!(failure.line_col(offset("😀") + 2), (7, 1));
+        // Past the end clamps to the end.
+        assert_eq!(failure.line_col(input.len() + 10), (7, 5));
+        assert_eq!(failure.line_col_utf16(usize::MAX), (7, 6));
+    }
+}
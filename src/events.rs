@@ -0,0 +1,414 @@
+use miette::SourceSpan;
+use winnow::{
+    combinator::{alt, eof, opt, peek},
+    error::ErrMode,
+    prelude::*,
+    stream::{Location, Stream},
+};
+
+use crate::{
+    v2_parser::{self, Input, KdlParseError},
+    KdlEntry, KdlErrorKind, KdlIdentifier, KdlParseFailure,
+};
+
+/// Parses KDL source text into a stream of [`KdlEvent`]s, without building a
+/// [`KdlDocument`](crate::KdlDocument).
+///
+/// This is intended for processing documents too large to comfortably hold
+/// in memory all at once, or when only a few of their nodes matter. Only the
+/// node currently being parsed is ever materialized, and children blocks are
+/// tracked with a counter rather than by recursing, so there's no limit on
+/// how deeply they can be nested.
+///
+/// Events are emitted in document order. Every [`KdlEvent::NodeStart`] is
+/// followed by one [`KdlEvent::Entry`] per entry, then optionally a
+/// [`KdlEvent::ChildrenStart`], the events for the children, and a
+/// [`KdlEvent::ChildrenEnd`], and finally a [`KdlEvent::NodeEnd`]. Comments
+/// and slashdashed nodes between nodes are reported as
+/// [`KdlEvent::Comment`]s. Anything else that doesn't affect the document's
+/// data, like whitespace, slashdashed entries, and comments inside nodes,
+/// is skipped.
+///
+/// Iteration stops after the first error.
+///
+/// # Examples
+///
+/// ```rust
+/// use kdl::{parse_events, KdlEvent};
+///
+/// let names = parse_events("a 1 { b; }\n// done\nc")
+///     .filter_map(|event| match event {
+///         Ok(KdlEvent::NodeStart { name, .. }) => Some(name.value().to_string()),
+///         _ => None,
+///     })
+///     .collect::<Vec<_>>();
+/// assert_eq!(names, ["a", "b", "c"]);
+///
+/// let mut events = parse_events("a {");
+/// assert!(matches!(events.next(), Some(Ok(KdlEvent::NodeStart { .. }))));
+/// assert!(matches!(events.next(), Some(Ok(KdlEvent::ChildrenStart { .. }))));
+/// assert!(events.next().unwrap().is_err());
+/// assert!(events.next().is_none());
+/// ```
+pub fn parse_events(input: &str) -> KdlEvents<'_> {
+    KdlEvents {
+        source: input,
+        input: v2_parser::unrecoverable_input(input),
+        state: State::Start,
+        depth: 0,
+    }
+}
+
+/// Something found while parsing a document with [`parse_events`]. Spans
+/// are relative to the start of the input.
+// Events are handed out one at a time, so boxing entries would only add an
+// allocation each.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum KdlEvent<'a> {
+    /// The start of a node.
+    NodeStart {
+        /// The node's type annotation, if any.
+        ty: Option<KdlIdentifier>,
+        /// The node's name.
+        name: KdlIdentifier,
+        /// Span of the node's type annotation and name.
+        span: SourceSpan,
+    },
+    /// One of the current node's entries.
+    Entry {
+        /// The entry itself.
+        entry: KdlEntry,
+        /// Span of the entry, without any whitespace or slashdashed entries
+        /// before it.
+        span: SourceSpan,
+    },
+    /// The `{` opening the current node's children block.
+    ChildrenStart {
+        /// Span of the `{`.
+        span: SourceSpan,
+    },
+    /// The `}` closing the current node's children block.
+    ChildrenEnd {
+        /// Span of the `}`.
+        span: SourceSpan,
+    },
+    /// The end of the current node.
+    NodeEnd {
+        /// Span of the node's terminator. This is empty if the node is the
+        /// last one in its document or children block and doesn't have one.
+        span: SourceSpan,
+    },
+    /// A comment or slashdashed node between nodes.
+    Comment {
+        /// The comment's text, including its `//`, `/*` and `*/`, or `/-`.
+        text: &'a str,
+        /// Span of the comment.
+        span: SourceSpan,
+    },
+}
+
+/// Iterator over the [`KdlEvent`]s in some KDL source text. Created by
+/// [`parse_events`].
+#[derive(Debug)]
+pub struct KdlEvents<'a> {
+    source: &'a str,
+    input: Input<'a>,
+    state: State,
+    /// How many children blocks are currently open.
+    depth: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// At the very start of the input, before any BOM.
+    Start,
+    /// Between nodes, at the top level or in a children block.
+    BetweenNodes,
+    /// After a node's name or one of its entries.
+    Entries,
+    /// After a node's `}`.
+    AfterChildren,
+    /// At the end of the input, or after an error.
+    Done,
+}
+
+impl<'a> Iterator for KdlEvents<'a> {
+    type Item = Result<KdlEvent<'a>, KdlParseFailure>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let event = match self.state {
+            State::Done => return None,
+            State::Start => {
+                self.state = State::BetweenNodes;
+                opt(v2_parser::bom)
+                    .parse_next(&mut self.input)
+                    .map(|_| None)
+            }
+            State::BetweenNodes => self.between_nodes(),
+            State::Entries => self.entries(),
+            State::AfterChildren => (opt(v2_parser::slashdashed_children), v2_parser::node_space0)
+                .parse_next(&mut self.input)
+                .and_then(|_| self.node_end())
+                .map(Some),
+        };
+        match event {
+            Ok(Some(event)) => Some(Ok(event)),
+            Ok(None) if self.state == State::Done => None,
+            Ok(None) => self.next(),
+            Err(err) => {
+                self.state = State::Done;
+                let mut err = match err {
+                    ErrMode::Backtrack(err) | ErrMode::Cut(err) => err,
+                    ErrMode::Incomplete(_) => KdlParseError {
+                        context: None,
+                        span: None,
+                        label: None,
+                        help: None,
+                        kind: None,
+                    },
+                };
+                if err.span.is_none() {
+                    let here = self.input.location();
+                    err.span = Some((here, 0).into());
+                }
+                Some(Err(v2_parser::failure_from_errs(vec![err], self.source)))
+            }
+        }
+    }
+}
+
+impl<'a> KdlEvents<'a> {
+    /// Skips whitespace between nodes, then emits the next comment,
+    /// `NodeStart`, or `ChildrenEnd`. Emits nothing at the end of the input.
+    fn between_nodes(&mut self) -> v2_parser::PResult<Option<KdlEvent<'a>>> {
+        let input = &mut self.input;
+        while opt(alt((v2_parser::newline, v2_parser::unicode_space)))
+            .parse_next(input)?
+            .is_some()
+        {}
+        let start = input.location();
+        let comment = opt(alt((
+            v2_parser::single_line_comment,
+            v2_parser::multi_line_comment,
+            v2_parser::slashdashed_node,
+        ))
+        .take())
+        .parse_next(input)?;
+        if let Some(text) = comment {
+            return Ok(Some(KdlEvent::Comment {
+                text,
+                span: (start..input.location()).into(),
+            }));
+        }
+        if opt(eof).parse_next(input)?.is_some() {
+            if self.depth > 0 {
+                return Err(ErrMode::Cut(KdlParseError {
+                    context: None,
+                    span: Some((start, 0).into()),
+                    label: Some("end of input"),
+                    help: Some("Add a `}` to close the open children block."),
+                    kind: Some(KdlErrorKind::MissingClosingBrace),
+                }));
+            }
+            self.state = State::Done;
+            return Ok(None);
+        }
+        if opt("}").parse_next(input)?.is_some() {
+            if self.depth == 0 {
+                return Err(ErrMode::Cut(KdlParseError {
+                    context: None,
+                    span: Some((start, 1).into()),
+                    label: Some("unexpected `}`"),
+                    help: Some("Remove this `}`, or add a `{` to go with it."),
+                    kind: None,
+                }));
+            }
+            self.depth -= 1;
+            self.state = State::AfterChildren;
+            return Ok(Some(KdlEvent::ChildrenEnd {
+                span: (start, 1).into(),
+            }));
+        }
+        let (ty, name) = (
+            opt(v2_parser::ty),
+            v2_parser::node_space0,
+            v2_parser::misplaced_comment,
+            v2_parser::identifier.context("node name"),
+        )
+            .map(|(ty, _, _, name)| (ty.and_then(|(_, ty, _)| ty), name))
+            .parse_next(input)?;
+        self.state = State::Entries;
+        Ok(Some(KdlEvent::NodeStart {
+            ty,
+            name,
+            span: (start..input.location()).into(),
+        }))
+    }
+
+    /// Emits the current node's next entry, or, once there are no more,
+    /// its `ChildrenStart` or `NodeEnd`.
+    fn entries(&mut self) -> v2_parser::PResult<Option<KdlEvent<'a>>> {
+        let input = &mut self.input;
+        let checkpoint = input.checkpoint();
+        match v2_parser::spaced_node_entry.with_span().parse_next(input) {
+            Ok((Some(entry), span)) => {
+                let leading = entry.format().map_or(0, |fmt| fmt.leading.len());
+                return Ok(Some(KdlEvent::Entry {
+                    entry,
+                    span: (span.start + leading..span.end).into(),
+                }));
+            }
+            Ok((None, _)) => {
+                return Err(ErrMode::Cut(KdlParseError {
+                    context: Some("entry"),
+                    span: None,
+                    label: None,
+                    help: None,
+                    kind: None,
+                }))
+            }
+            Err(ErrMode::Backtrack(_)) => input.reset(&checkpoint),
+            Err(err) => return Err(err),
+        }
+        v2_parser::before_node_children.parse_next(input)?;
+        let start = input.location();
+        if opt("{").parse_next(input)?.is_some() {
+            self.depth += 1;
+            self.state = State::BetweenNodes;
+            return Ok(Some(KdlEvent::ChildrenStart {
+                span: (start, 1).into(),
+            }));
+        }
+        self.node_end().map(Some)
+    }
+
+    /// Emits the end of the current node, after everything but its
+    /// terminator.
+    fn node_end(&mut self) -> v2_parser::PResult<KdlEvent<'a>> {
+        let input = &mut self.input;
+        let start = input.location();
+        let terminated = opt(v2_parser::node_terminator).parse_next(input)?.is_some()
+            || peek(opt(alt(("}".void(), eof.void()))))
+                .parse_next(input)?
+                .is_some();
+        if !terminated {
+            return Err(ErrMode::Cut(KdlParseError {
+                context: Some("node terminator"),
+                span: Some((start, 0).into()),
+                label: Some("expected a newline, `;`, or `}` here"),
+                help: None,
+                kind: None,
+            }));
+        }
+        self.state = State::BetweenNodes;
+        Ok(KdlEvent::NodeEnd {
+            span: (start..input.location()).into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::KdlDocument;
+
+    /// Renders events as short strings, for comparing.
+    fn events(input: &str) -> Vec<String> {
+        parse_events(input)
+            .map(|event| match event {
+                Ok(KdlEvent::NodeStart { ty, name, span }) => format!(
+                    "start {}{} @{}",
+                    ty.map(|ty| format!("({ty})")).unwrap_or_default(),
+                    name,
+                    span.offset()
+                ),
+                Ok(KdlEvent::Entry { entry, span }) => {
+                    let text = &input[span.offset()..][..span.len()];
+                    assert!(entry.to_string().trim_end().ends_with(text));
+                    format!("entry {text}")
+                }
+                Ok(KdlEvent::ChildrenStart { .. }) => "{".into(),
+                Ok(KdlEvent::ChildrenEnd { .. }) => "}".into(),
+                Ok(KdlEvent::NodeEnd { span }) => {
+                    format!("end {:?}", &input[span.offset()..][..span.len()])
+                }
+                Ok(KdlEvent::Comment { text, .. }) => format!("comment {text}"),
+                Err(failure) => format!("error @{}", failure.diagnostics[0].span.offset()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn events_in_order() {
+        assert_eq!(
+            events("\u{FEFF}// c\n(t)a 1 /-2 key=(u)#true /- { x } {\n    b; /- c\n    d /* in */ }\n/* after */ e"),
+            [
+                "comment // c\n",
+                "start (t)a @8",
+                "entry 1",
+                "entry key=(u)#true",
+                "{",
+                "start b @47",
+                "end \";\"",
+                "comment /- c\n",
+                "start d @59",
+                "end \"\"",
+                "}",
+                "end \"\\n\"",
+                "comment /* after */",
+                "start e @84",
+                "end \"\"",
+            ]
+        );
+        assert!(events("").is_empty());
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            events("a {\n    b 1."),
+            ["start a @0", "{", "start b @8", "error @12"]
+        );
+        assert_eq!(events("a }"), ["start a @0", "end \"\"", "error @2"]);
+        assert_eq!(events("a {"), ["start a @0", "{", "error @3"]);
+        assert_eq!(
+            events("a { b }c"),
+            ["start a @0", "{", "start b @4", "end \"\"", "}", "error @7"]
+        );
+    }
+
+    #[test]
+    fn deep_nesting() {
+        let depth = 100_000;
+        let input = "a {".repeat(depth) + &"}".repeat(depth);
+        let events = parse_events(&input).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(events.len(), depth * 4);
+    }
+
+    #[test]
+    fn matches_document() -> miette::Result<()> {
+        let input = include_str!("../examples/kdl-schema.kdl");
+        let doc: KdlDocument = input.parse()?;
+        let mut names = Vec::new();
+        let mut entries = 0;
+        for event in parse_events(input) {
+            match event? {
+                KdlEvent::NodeStart { name, .. } => names.push(name.value().to_string()),
+                KdlEvent::Entry { .. } => entries += 1,
+                _ => {}
+            }
+        }
+        let expected = doc
+            .descendants()
+            .map(|node| node.name().value().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names, expected);
+        let expected = doc
+            .descendants()
+            .map(|node| node.entries().len())
+            .sum::<usize>();
+        assert_eq!(entries, expected);
+        Ok(())
+    }
+}
@@ -0,0 +1,703 @@
+//! A C ABI for embedding this parser in non-Rust projects, with the `ffi`
+//! feature. The declarations are in `include/kdl.h`, and a shared or static
+//! library can be built with
+//! `cargo rustc --release --features ffi --crate-type cdylib` (or
+//! `staticlib`).
+//!
+//! # Conventions
+//!
+//! * Strings passed in are a pointer and a length in bytes, and must be
+//!   UTF-8. They don't need to be NUL-terminated.
+//! * Strings borrowed from a document, like node names, are also a pointer
+//!   and a length, and aren't NUL-terminated. Their length is stored through
+//!   a `len` out pointer, which is required: without one, these functions
+//!   return null.
+//! * Strings the library allocates, like [`kdl_document_to_string`]'s, are
+//!   NUL-terminated and must be freed with [`kdl_string_free`].
+//! * Documents returned by [`kdl_parse`] must be freed with
+//!   [`kdl_document_free`], and errors with [`kdl_error_free`]. Nodes,
+//!   entries, children, and diagnostics are borrowed from the document or
+//!   error they came from, and are valid until it's freed.
+//! * Every function accepts null pointers, and returns null, zero, or
+//!   `false` for them.
+
+use std::{
+    ffi::CString,
+    os::raw::{c_char, c_int},
+    ptr,
+};
+
+use crate::{KdlDocument, KdlEntry, KdlNode, KdlValue};
+
+/// The result of [`kdl_parse`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KdlStatus {
+    /// The input parsed successfully.
+    Ok = 0,
+    /// The input wasn't valid UTF-8.
+    InvalidUtf8 = 1,
+    /// The input wasn't valid KDL.
+    ParseError = 2,
+    /// A required pointer was null.
+    NullPointer = 3,
+}
+
+/// The type of an entry's value.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KdlValueType {
+    /// A string.
+    String = 0,
+    /// An integer.
+    Integer = 1,
+    /// A float.
+    Float = 2,
+    /// `#true` or `#false`.
+    Bool = 3,
+    /// `#null`.
+    Null = 4,
+}
+
+/// Why [`kdl_parse`] failed: one or more diagnostics.
+#[derive(Debug)]
+pub struct KdlFfiError {
+    diagnostics: Vec<KdlFfiDiagnostic>,
+}
+
+/// A single problem with the input to [`kdl_parse`].
+#[derive(Debug)]
+pub struct KdlFfiDiagnostic {
+    message: CString,
+    help: Option<CString>,
+    offset: usize,
+    len: usize,
+    line: usize,
+    column: usize,
+}
+
+fn c_string(s: &str) -> CString {
+    // Written-out KDL escapes NULs, and diagnostic messages have none, so
+    // this never changes anything in practice.
+    CString::new(s.replace('\0', "\u{FFFD}")).expect("NULs were replaced")
+}
+
+unsafe fn bytes<'a>(ptr: *const c_char, len: usize) -> Option<&'a [u8]> {
+    (!ptr.is_null()).then(|| std::slice::from_raw_parts(ptr.cast(), len))
+}
+
+unsafe fn str_arg<'a>(ptr: *const c_char, len: usize) -> Option<&'a str> {
+    std::str::from_utf8(bytes(ptr, len)?).ok()
+}
+
+/// `s`, storing its length in `*len`, or null if `len` is null, since the
+/// caller couldn't tell where `s` ends without it.
+unsafe fn borrowed_str(s: &str, len: *mut usize) -> *const c_char {
+    if len.is_null() {
+        return ptr::null();
+    }
+    *len = s.len();
+    s.as_ptr().cast()
+}
+
+/// Parses `len` bytes of KDL at `input`. On success, stores a new document
+/// in `*document`. On failure, stores a new error in `*error`, if `error`
+/// isn't null.
+///
+/// # Safety
+///
+/// `input` must point to `len` readable bytes, and `document` and `error`
+/// must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn kdl_parse(
+    input: *const c_char,
+    len: usize,
+    document: *mut *mut KdlDocument,
+    error: *mut *mut KdlFfiError,
+) -> KdlStatus {
+    let input = match bytes(input, len) {
+        Some(input) if !document.is_null() => input,
+        _ => return KdlStatus::NullPointer,
+    };
+    let fail = |status, diagnostics| {
+        if !error.is_null() {
+            *error = Box::into_raw(Box::new(KdlFfiError { diagnostics }));
+        }
+        status
+    };
+    let input = match std::str::from_utf8(input) {
+        Ok(input) => input,
+        Err(err) => {
+            let offset = err.valid_up_to();
+            let valid = std::str::from_utf8(&input[..offset]).expect("checked");
+            let (line, column) = crate::error::line_col(valid, offset, false);
+            return fail(
+                KdlStatus::InvalidUtf8,
+                vec![KdlFfiDiagnostic {
+                    message: c_string("Input is not valid UTF-8."),
+                    help: None,
+                    offset,
+                    len: err.error_len().unwrap_or(input.len() - offset),
+                    line,
+                    column,
+                }],
+            );
+        }
+    };
+    match input.parse::<KdlDocument>() {
+        Ok(doc) => {
+            *document = Box::into_raw(Box::new(doc));
+            KdlStatus::Ok
+        }
+        Err(failure) => {
+            let diagnostics = failure
+                .diagnostics
+                .iter()
+                .map(|diagnostic| {
+                    let (line, column) = failure.line_col(diagnostic.span.offset());
+                    KdlFfiDiagnostic {
+                        message: c_string(&diagnostic.to_string()),
+                        help: diagnostic.help.map(c_string),
+                        offset: diagnostic.span.offset(),
+                        len: diagnostic.span.len(),
+                        line,
+                        column,
+                    }
+                })
+                .collect();
+            fail(KdlStatus::ParseError, diagnostics)
+        }
+    }
+}
+
+/// Frees a document returned by [`kdl_parse`].
+///
+/// # Safety
+///
+/// `document` must be null or a document from [`kdl_parse`] that hasn't
+/// been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn kdl_document_free(document: *mut KdlDocument) {
+    if !document.is_null() {
+        drop(Box::from_raw(document));
+    }
+}
+
+/// Frees an error returned by [`kdl_parse`].
+///
+/// # Safety
+///
+/// `error` must be null or an error from [`kdl_parse`] that hasn't been
+/// freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn kdl_error_free(error: *mut KdlFfiError) {
+    if !error.is_null() {
+        drop(Box::from_raw(error));
+    }
+}
+
+/// Frees a string returned by this library.
+///
+/// # Safety
+///
+/// `string` must be null or a string returned by this library that hasn't
+/// been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn kdl_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+/// The number of diagnostics in `error`.
+///
+/// # Safety
+///
+/// `error` must be null or a live error from [`kdl_parse`].
+#[no_mangle]
+pub unsafe extern "C" fn kdl_error_diagnostic_count(error: *const KdlFfiError) -> usize {
+    error.as_ref().map_or(0, |error| error.diagnostics.len())
+}
+
+/// The `index`th diagnostic in `error`, or null if it's out of range.
+///
+/// # Safety
+///
+/// `error` must be null or a live error from [`kdl_parse`].
+#[no_mangle]
+pub unsafe extern "C" fn kdl_error_diagnostic_at(
+    error: *const KdlFfiError,
+    index: usize,
+) -> *const KdlFfiDiagnostic {
+    error
+        .as_ref()
+        .and_then(|error| error.diagnostics.get(index))
+        .map_or(ptr::null(), |diagnostic| diagnostic)
+}
+
+/// The diagnostic's message, NUL-terminated.
+///
+/// # Safety
+///
+/// `diagnostic` must be null or borrowed from a live error.
+#[no_mangle]
+pub unsafe extern "C" fn kdl_diagnostic_message(
+    diagnostic: *const KdlFfiDiagnostic,
+) -> *const c_char {
+    diagnostic
+        .as_ref()
+        .map_or(ptr::null(), |diagnostic| diagnostic.message.as_ptr())
+}
+
+/// The diagnostic's suggested fix, NUL-terminated, or null if it has none.
+///
+/// # Safety
+///
+/// `diagnostic` must be null or borrowed from a live error.
+#[no_mangle]
+pub unsafe extern "C" fn kdl_diagnostic_help(diagnostic: *const KdlFfiDiagnostic) -> *const c_char {
+    diagnostic
+        .as_ref()
+        .and_then(|diagnostic| diagnostic.help.as_ref())
+        .map_or(ptr::null(), |help| help.as_ptr())
+}
+
+/// Stores where the problem is: its byte offset and length in the input,
+/// and the 1-based line and column of its start, with columns counted in
+/// characters. Any of the out pointers may be null.
+///
+/// # Safety
+///
+/// `diagnostic` must be null or borrowed from a live error, and the out
+/// pointers must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn kdl_diagnostic_location(
+    diagnostic: *const KdlFfiDiagnostic,
+    offset: *mut usize,
+    len: *mut usize,
+    line: *mut usize,
+    column: *mut usize,
+) -> bool {
+    let diagnostic = match diagnostic.as_ref() {
+        Some(diagnostic) => diagnostic,
+        None => return false,
+    };
+    for (out, value) in [
+        (offset, diagnostic.offset),
+        (len, diagnostic.len),
+        (line, diagnostic.line),
+        (column, diagnostic.column),
+    ] {
+        if !out.is_null() {
+            *out = value;
+        }
+    }
+    true
+}
+
+/// Writes `document` out as KDL, as a new string.
+///
+/// # Safety
+///
+/// `document` must be null or a live document.
+#[no_mangle]
+pub unsafe extern "C" fn kdl_document_to_string(document: *const KdlDocument) -> *mut c_char {
+    document
+        .as_ref()
+        .map_or(ptr::null_mut(), |doc| c_string(&doc.to_string()).into_raw())
+}
+
+/// The number of nodes in `document`.
+///
+/// # Safety
+///
+/// `document` must be null or a live document.
+#[no_mangle]
+pub unsafe extern "C" fn kdl_document_node_count(document: *const KdlDocument) -> usize {
+    document.as_ref().map_or(0, |doc| doc.nodes().len())
+}
+
+/// The `index`th node in `document`, or null if it's out of range.
+///
+/// # Safety
+///
+/// `document` must be null or a live document.
+#[no_mangle]
+pub unsafe extern "C" fn kdl_document_node_at(
+    document: *const KdlDocument,
+    index: usize,
+) -> *const KdlNode {
+    document
+        .as_ref()
+        .and_then(|doc| doc.nodes().get(index))
+        .map_or(ptr::null(), |node| node)
+}
+
+/// The first node in `document` named `name`, or null if there's none.
+///
+/// # Safety
+///
+/// `document` must be null or a live document, and `name` must point to
+/// `name_len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn kdl_document_get_node(
+    document: *const KdlDocument,
+    name: *const c_char,
+    name_len: usize,
+) -> *const KdlNode {
+    match (document.as_ref(), str_arg(name, name_len)) {
+        (Some(doc), Some(name)) => doc.get(name).map_or(ptr::null(), |node| node),
+        _ => ptr::null(),
+    }
+}
+
+/// The node's name, storing its length in `*len`.
+///
+/// # Safety
+///
+/// `node` must be null or borrowed from a live document, and `len` must be
+/// null or valid for writes. If `len` is null, this returns null. The result
+/// isn't NUL-terminated, and is only valid until the document is freed.
+#[no_mangle]
+pub unsafe extern "C" fn kdl_node_name(node: *const KdlNode, len: *mut usize) -> *const c_char {
+    node.as_ref()
+        .map_or(ptr::null(), |node| borrowed_str(node.name().value(), len))
+}
+
+/// The node's type annotation, storing its length in `*len`, or null if it
+/// has none.
+///
+/// # Safety
+///
+/// `node` must be null or borrowed from a live document, and `len` must be
+/// null or valid for writes. If `len` is null, this returns null. The result
+/// isn't NUL-terminated, and is only valid until the document is freed.
+#[no_mangle]
+pub unsafe extern "C" fn kdl_node_type(node: *const KdlNode, len: *mut usize) -> *const c_char {
+    node.as_ref()
+        .and_then(|node| node.ty())
+        .map_or(ptr::null(), |ty| borrowed_str(ty.value(), len))
+}
+
+/// The node's children, or null if it has no children block.
+///
+/// # Safety
+///
+/// `node` must be null or borrowed from a live document.
+#[no_mangle]
+pub unsafe extern "C" fn kdl_node_children(node: *const KdlNode) -> *const KdlDocument {
+    node.as_ref()
+        .and_then(|node| node.children())
+        .map_or(ptr::null(), |children| children)
+}
+
+/// The number of entries, both arguments and properties, on `node`.
+///
+/// # Safety
+///
+/// `node` must be null or borrowed from a live document.
+#[no_mangle]
+pub unsafe extern "C" fn kdl_node_entry_count(node: *const KdlNode) -> usize {
+    node.as_ref().map_or(0, |node| node.entries().len())
+}
+
+/// The `index`th entry on `node`, or null if it's out of range.
+///
+/// # Safety
+///
+/// `node` must be null or borrowed from a live document.
+#[no_mangle]
+pub unsafe extern "C" fn kdl_node_entry_at(node: *const KdlNode, index: usize) -> *const KdlEntry {
+    node.as_ref()
+        .and_then(|node| node.entries().get(index))
+        .map_or(ptr::null(), |entry| entry)
+}
+
+/// The last property on `node` named `name`, or null if there's none.
+///
+/// # Safety
+///
+/// `node` must be null or borrowed from a live document, and `name` must
+/// point to `name_len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn kdl_node_get_property(
+    node: *const KdlNode,
+    name: *const c_char,
+    name_len: usize,
+) -> *const KdlEntry {
+    match (node.as_ref(), str_arg(name, name_len)) {
+        (Some(node), Some(name)) => node.entry(name).map_or(ptr::null(), |entry| entry),
+        _ => ptr::null(),
+    }
+}
+
+/// Writes `node` out as KDL, as a new string.
+///
+/// # Safety
+///
+/// `node` must be null or borrowed from a live document.
+#[no_mangle]
+pub unsafe extern "C" fn kdl_node_to_string(node: *const KdlNode) -> *mut c_char {
+    node.as_ref().map_or(ptr::null_mut(), |node| {
+        c_string(&node.to_string()).into_raw()
+    })
+}
+
+/// The entry's property name, storing its length in `*len`, or null if it's
+/// an argument.
+///
+/// # Safety
+///
+/// `entry` must be null or borrowed from a live document, and `len` must be
+/// null or valid for writes. If `len` is null, this returns null. The result
+/// isn't NUL-terminated, and is only valid until the document is freed.
+#[no_mangle]
+pub unsafe extern "C" fn kdl_entry_name(entry: *const KdlEntry, len: *mut usize) -> *const c_char {
+    entry
+        .as_ref()
+        .and_then(|entry| entry.name())
+        .map_or(ptr::null(), |name| borrowed_str(name.value(), len))
+}
+
+/// The entry's type annotation, storing its length in `*len`, or null if it
+/// has none.
+///
+/// # Safety
+///
+/// `entry` must be null or borrowed from a live document, and `len` must be
+/// null or valid for writes. If `len` is null, this returns null. The result
+/// isn't NUL-terminated, and is only valid until the document is freed.
+#[no_mangle]
+pub unsafe extern "C" fn kdl_entry_type(entry: *const KdlEntry, len: *mut usize) -> *const c_char {
+    entry
+        .as_ref()
+        .and_then(|entry| entry.ty())
+        .map_or(ptr::null(), |ty| borrowed_str(ty.value(), len))
+}
+
+/// The type of the entry's value. Null entries are reported as
+/// [`KdlValueType::Null`].
+///
+/// # Safety
+///
+/// `entry` must be null or borrowed from a live document.
+#[no_mangle]
+pub unsafe extern "C" fn kdl_entry_value_type(entry: *const KdlEntry) -> KdlValueType {
+    match entry.as_ref().map(|entry| entry.value()) {
+        Some(KdlValue::String(_)) => KdlValueType::String,
+        Some(KdlValue::Integer(_)) => KdlValueType::Integer,
+        Some(KdlValue::Float(_)) => KdlValueType::Float,
+        Some(KdlValue::Bool(_)) => KdlValueType::Bool,
+        Some(KdlValue::Null) | None => KdlValueType::Null,
+    }
+}
+
+/// The entry's string value, storing its length in `*len`, or null if it
+/// isn't a string.
+///
+/// # Safety
+///
+/// `entry` must be null or borrowed from a live document, and `len` must be
+/// null or valid for writes. If `len` is null, this returns null. The result
+/// isn't NUL-terminated, and is only valid until the document is freed.
+#[no_mangle]
+pub unsafe extern "C" fn kdl_entry_as_string(
+    entry: *const KdlEntry,
+    len: *mut usize,
+) -> *const c_char {
+    entry
+        .as_ref()
+        .and_then(|entry| entry.value().as_string())
+        .map_or(ptr::null(), |s| borrowed_str(s, len))
+}
+
+/// Stores the entry's integer value in `*out`, if it's an integer that fits
+/// in an `int64_t`.
+///
+/// # Safety
+///
+/// `entry` must be null or borrowed from a live document, and `out` must be
+/// null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn kdl_entry_as_integer(entry: *const KdlEntry, out: *mut i64) -> bool {
+    match entry.as_ref().map(|entry| entry.value()) {
+        Some(KdlValue::Integer(i)) if !out.is_null() => match i64::try_from(*i) {
+            Ok(i) => {
+                *out = i;
+                true
+            }
+            Err(_) => false,
+        },
+        _ => false,
+    }
+}
+
+/// Stores the entry's value in `*out`, if it's a float.
+///
+/// # Safety
+///
+/// `entry` must be null or borrowed from a live document, and `out` must be
+/// null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn kdl_entry_as_float(entry: *const KdlEntry, out: *mut f64) -> bool {
+    match entry.as_ref().map(|entry| entry.value()) {
+        Some(KdlValue::Float(f)) if !out.is_null() => {
+            *out = *f;
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Stores the entry's value in `*out` as 0 or 1, if it's a boolean.
+///
+/// # Safety
+///
+/// `entry` must be null or borrowed from a live document, and `out` must be
+/// null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn kdl_entry_as_bool(entry: *const KdlEntry, out: *mut c_int) -> bool {
+    match entry.as_ref().map(|entry| entry.value()) {
+        Some(KdlValue::Bool(b)) if !out.is_null() => {
+            *out = c_int::from(*b);
+            true
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::CStr;
+
+    use super::*;
+
+    unsafe fn parse(input: &[u8]) -> Result<*mut KdlDocument, (KdlStatus, *mut KdlFfiError)> {
+        let mut doc = ptr::null_mut();
+        let mut error = ptr::null_mut();
+        match kdl_parse(input.as_ptr().cast(), input.len(), &mut doc, &mut error) {
+            KdlStatus::Ok => Ok(doc),
+            status => Err((status, error)),
+        }
+    }
+
+    unsafe fn borrowed(f: impl FnOnce(*mut usize) -> *const c_char) -> Option<String> {
+        let mut len = 0;
+        let ptr = f(&mut len);
+        (!ptr.is_null()).then(|| {
+            String::from_utf8(std::slice::from_raw_parts(ptr.cast(), len).to_vec()).unwrap()
+        })
+    }
+
+    #[test]
+    fn documents() {
+        unsafe {
+            let doc = parse(b"server \"web\" port=80 { (ip)host \"::1\"; tls #true; }").unwrap();
+            assert_eq!(kdl_document_node_count(doc), 1);
+            let server = kdl_document_get_node(doc, "server".as_ptr().cast(), 6);
+            assert_eq!(server, kdl_document_node_at(doc, 0));
+            assert_eq!(
+                borrowed(|len| kdl_node_name(server, len)).unwrap(),
+                "server"
+            );
+            // Borrowed strings aren't NUL-terminated, so they need a length.
+            assert_eq!(kdl_node_name(server, ptr::null_mut()), ptr::null());
+            assert_eq!(kdl_node_entry_count(server), 2);
+
+            let web = kdl_node_entry_at(server, 0);
+            assert_eq!(borrowed(|len| kdl_entry_name(web, len)), None);
+            assert_eq!(kdl_entry_value_type(web), KdlValueType::String);
+            assert_eq!(
+                borrowed(|len| kdl_entry_as_string(web, len)).unwrap(),
+                "web"
+            );
+            assert_eq!(kdl_entry_as_string(web, ptr::null_mut()), ptr::null());
+
+            let port = kdl_node_get_property(server, "port".as_ptr().cast(), 4);
+            let mut i = 0;
+            assert!(kdl_entry_as_integer(port, &mut i));
+            assert_eq!(i, 80);
+            assert!(!kdl_entry_as_float(port, &mut 0.0));
+
+            let children = kdl_node_children(server);
+            let host = kdl_document_node_at(children, 0);
+            assert_eq!(borrowed(|len| kdl_node_type(host, len)).unwrap(), "ip");
+            let tls = kdl_node_entry_at(kdl_document_node_at(children, 1), 0);
+            let mut b = 0;
+            assert!(kdl_entry_as_bool(tls, &mut b));
+            assert_eq!(b, 1);
+            assert_eq!(kdl_document_node_at(children, 2), ptr::null());
+
+            let string = kdl_node_to_string(host);
+            assert_eq!(
+                CStr::from_ptr(string).to_str().unwrap(),
+                "(ip)host \"::1\";"
+            );
+            kdl_string_free(string);
+            kdl_document_free(doc);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        unsafe {
+            let (status, error) = parse(b"node {\nfoo=").unwrap_err();
+            assert_eq!(status, KdlStatus::ParseError);
+            assert!(kdl_error_diagnostic_count(error) > 0);
+            let diagnostic = kdl_error_diagnostic_at(error, 0);
+            assert!(!CStr::from_ptr(kdl_diagnostic_message(diagnostic))
+                .to_bytes()
+                .is_empty());
+            let mut line = 0;
+            assert!(kdl_diagnostic_location(
+                diagnostic,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                &mut line,
+                ptr::null_mut()
+            ));
+            assert_eq!(line, 2);
+            kdl_error_free(error);
+
+            let (status, error) = parse(b"a \"\xff\"").unwrap_err();
+            assert_eq!(status, KdlStatus::InvalidUtf8);
+            let diagnostic = kdl_error_diagnostic_at(error, 0);
+            let (mut offset, mut len, mut column) = (0, 0, 0);
+            kdl_diagnostic_location(
+                diagnostic,
+                &mut offset,
+                &mut len,
+                ptr::null_mut(),
+                &mut column,
+            );
+            assert_eq!((offset, len, column), (3, 1, 4));
+            assert_eq!(
+                CStr::from_ptr(kdl_diagnostic_message(diagnostic)).to_str(),
+                Ok("Input is not valid UTF-8.")
+            );
+            assert_eq!(kdl_error_diagnostic_at(error, 1), ptr::null());
+            kdl_error_free(error);
+
+            // Lines are counted the same way as for parse errors.
+            let (_, error) = parse(b"a\r\nb\xe2\x80\xa8c \xff").unwrap_err();
+            let diagnostic = kdl_error_diagnostic_at(error, 0);
+            let (mut line, mut column) = (0, 0);
+            kdl_diagnostic_location(
+                diagnostic,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                &mut line,
+                &mut column,
+            );
+            assert_eq!((line, column), (3, 3));
+            kdl_error_free(error);
+
+            let mut doc = ptr::null_mut();
+            assert_eq!(
+                kdl_parse(ptr::null(), 0, &mut doc, ptr::null_mut()),
+                KdlStatus::NullPointer
+            );
+            assert_eq!(kdl_document_node_count(ptr::null()), 0);
+        }
+    }
+}
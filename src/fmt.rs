@@ -1,22 +1,52 @@
 use std::fmt::Write as _;
 
-pub(crate) fn autoformat_leading(leading: &mut String, indent: usize, no_comments: bool) {
+use crate::{KdlFormatConfig, Token, TokenKind};
+
+/// Rewrites the decor before a node, or at the start of a document, as one
+/// comment per line at `indent`, followed by `indent` for the node itself.
+/// Runs of blank lines are kept, up to `config.max_blank_lines` of them,
+/// except at the very start, unless `after_content` says something comes
+/// before this decor.
+pub(crate) fn autoformat_leading(
+    leading: &mut String,
+    indent: &str,
+    config: &KdlFormatConfig<'_>,
+    after_content: bool,
+) {
     let mut result = String::new();
-    if !no_comments {
-        let input = leading.trim();
-        if !input.is_empty() {
-            for line in input.lines() {
-                let trimmed = line.trim();
-                if !trimmed.is_empty() {
-                    writeln!(result, "{:indent$}{}", "", trimmed, indent = indent).unwrap();
-                }
+    let mut blank_lines = 0;
+    let mut started = after_content;
+    for line in leading.split_inclusive(is_newline) {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            if line.ends_with(is_newline) {
+                blank_lines += 1;
             }
+            continue;
+        }
+        if config.no_comments {
+            continue;
         }
+        if started {
+            push_blank_lines(&mut result, blank_lines, config);
+        }
+        blank_lines = 0;
+        started = true;
+        writeln!(result, "{indent}{trimmed}").unwrap();
+    }
+    if started {
+        push_blank_lines(&mut result, blank_lines, config);
     }
-    write!(result, "{:indent$}", "", indent = indent).unwrap();
+    result.push_str(indent);
     *leading = result;
 }
 
+fn push_blank_lines(result: &mut String, count: usize, config: &KdlFormatConfig<'_>) {
+    for _ in 0..count.min(config.max_blank_lines) {
+        result.push('\n');
+    }
+}
+
 pub(crate) fn autoformat_trailing(decor: &mut String, no_comments: bool) {
     if decor.is_empty() {
         return;
@@ -33,3 +63,284 @@ pub(crate) fn autoformat_trailing(decor: &mut String, no_comments: bool) {
     }
     *decor = result;
 }
+
+/// Removes every slashdash in `decor`, along with whatever it comments out.
+/// With `nodes`, that's a whole node, up to and including its terminator.
+/// Otherwise, it's a single entry or children block.
+pub(crate) fn strip_slashdashes(decor: &str, nodes: bool) -> String {
+    let tokens = crate::tokenize(decor).collect::<Vec<_>>();
+    let slice = |t: &Token| &decor[t.span.offset()..t.span.offset() + t.span.len()];
+    let mut result = String::with_capacity(decor.len());
+    let mut idx = 0;
+    while idx < tokens.len() {
+        let token = &tokens[idx];
+        if token.kind != TokenKind::Comment || slice(token) != "/-" {
+            result.push_str(slice(token));
+            idx += 1;
+            continue;
+        }
+        idx += 1;
+        if nodes {
+            idx = skip_node(&tokens, idx, &slice);
+        } else {
+            idx = skip_entry(&tokens, idx, &slice);
+        }
+    }
+    result
+}
+
+/// Returns the index of the first token after the node starting at `idx`,
+/// and its terminator.
+fn skip_node<'a>(tokens: &[Token], mut idx: usize, slice: &impl Fn(&Token) -> &'a str) -> usize {
+    let mut depth = 0usize;
+    let mut escaped = false;
+    while let Some(token) = tokens.get(idx) {
+        let text = slice(token);
+        idx += 1;
+        match token.kind {
+            TokenKind::Punctuation if text == "{" => depth += 1,
+            TokenKind::Punctuation if text == "}" => depth = depth.saturating_sub(1),
+            TokenKind::Punctuation if text == ";" && depth == 0 => break,
+            TokenKind::Punctuation if text == "\\" => escaped = true,
+            TokenKind::Newline => {
+                if escaped {
+                    escaped = false;
+                } else if depth == 0 {
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    idx
+}
+
+/// Returns the index of the first token after the entry or children block
+/// starting at `idx`.
+fn skip_entry<'a>(tokens: &[Token], mut idx: usize, slice: &impl Fn(&Token) -> &'a str) -> usize {
+    let skip_space = |mut idx: usize| {
+        while tokens.get(idx).map_or(false, |t| {
+            matches!(t.kind, TokenKind::Whitespace | TokenKind::Newline)
+                || (t.kind == TokenKind::Comment && slice(t) != "/-")
+        }) {
+            idx += 1;
+        }
+        idx
+    };
+    let skip_value = |mut idx: usize| {
+        if tokens
+            .get(idx)
+            .map_or(false, |t| t.kind == TokenKind::TypeAnnotation)
+        {
+            idx = skip_space(idx + 1);
+        }
+        if tokens.get(idx).map_or(false, |t| {
+            matches!(
+                t.kind,
+                TokenKind::Identifier | TokenKind::String | TokenKind::Number | TokenKind::Keyword
+            )
+        }) {
+            idx += 1;
+        }
+        idx
+    };
+    idx = skip_space(idx);
+    if tokens.get(idx).map_or(false, |t| slice(t) == "{") {
+        let mut depth = 0usize;
+        while let Some(token) = tokens.get(idx) {
+            idx += 1;
+            if token.kind == TokenKind::Punctuation {
+                match slice(token) {
+                    "{" => depth += 1,
+                    "}" => {
+                        depth -= 1;
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        return idx;
+    }
+    idx = skip_value(idx);
+    let after_key = skip_space(idx);
+    if tokens.get(after_key).map_or(false, |t| slice(t) == "=") {
+        idx = skip_value(skip_space(after_key + 1));
+    }
+    idx
+}
+
+/// The part of `decor` from its first slashdash onwards, without trailing
+/// whitespace, or an empty string if there's no slashdash.
+pub(crate) fn slashdashed_tail(decor: &str) -> &str {
+    crate::tokenize(decor)
+        .find(|t| {
+            t.kind == TokenKind::Comment
+                && t.span.len() == 2
+                && decor[t.span.offset()..].starts_with("/-")
+        })
+        .map_or("", |t| decor[t.span.offset()..].trim_end())
+}
+
+/// A change in indentation, applied to every line of some formatting text.
+pub(crate) enum IndentShift<'a> {
+    /// Indent every line by this much.
+    Add(&'a str),
+    /// Remove up to `count` copies of `unit` from the start of every line.
+    Remove { unit: &'a str, count: usize },
+    /// Replace the existing indentation of every line with `indent`, except
+    /// for the last one, which gets `last` instead. The last line is the
+    /// one followed by whatever comes after the decor, such as a closing
+    /// brace.
+    Set { indent: &'a str, last: &'a str },
+}
+
+/// Applies `shift` to the start of each line of `decor`. `at_line_start`
+/// says whether `decor` itself starts a line, and is updated to say whether
+/// whatever comes after it will. `followed_by_content` says whether `decor`
+/// is immediately followed by something that should be indented along with
+/// it, such as a node name or a closing brace.
+///
+/// Blank lines are never indented, to avoid leaving trailing whitespace
+/// behind.
+pub(crate) fn shift_decor(
+    decor: &mut String,
+    shift: &IndentShift<'_>,
+    at_line_start: &mut bool,
+    followed_by_content: bool,
+) {
+    let mut segments = decor.split_inclusive(is_newline).collect::<Vec<_>>();
+    if decor.is_empty() || decor.ends_with(is_newline) {
+        // Whatever follows starts a new line, too.
+        segments.push("");
+    }
+    let last = segments.len() - 1;
+    let mut result = String::with_capacity(decor.len());
+    for (idx, line) in segments.into_iter().enumerate() {
+        if idx > 0 || *at_line_start {
+            match shift {
+                IndentShift::Add(indent) => {
+                    let blank = line.trim().is_empty() && !(idx == last && followed_by_content);
+                    if !blank {
+                        result.push_str(indent);
+                    }
+                    result.push_str(line);
+                }
+                IndentShift::Set {
+                    indent,
+                    last: last_indent,
+                } => {
+                    let line =
+                        line.trim_start_matches(|c: char| c.is_whitespace() && !is_newline(c));
+                    let followed_by_content = idx == last && followed_by_content;
+                    if !line.trim().is_empty() || followed_by_content {
+                        result.push_str(if idx == last { last_indent } else { indent });
+                    }
+                    result.push_str(line);
+                }
+                IndentShift::Remove { unit, count } => {
+                    let mut line = line;
+                    for _ in 0..*count {
+                        match line.strip_prefix(unit) {
+                            Some(stripped) if !unit.is_empty() => line = stripped,
+                            _ => break,
+                        }
+                    }
+                    result.push_str(line);
+                }
+            }
+        } else {
+            result.push_str(line);
+        }
+    }
+    if !result.is_empty() {
+        *at_line_start = result.ends_with(is_newline);
+    }
+    *decor = result;
+}
+
+pub(crate) fn is_newline(c: char) -> bool {
+    matches!(
+        c,
+        '\r' | '\n' | '\u{0085}' | '\u{000C}' | '\u{2028}' | '\u{2029}'
+    )
+}
+
+/// Splits `decor` into its leading blank lines (including their newlines) and
+/// whatever comes after them.
+pub(crate) fn split_blank_lines(decor: &str) -> (&str, &str) {
+    let mut idx = 0;
+    for line in decor.split_inclusive(is_newline) {
+        if !line.ends_with(is_newline) || !line.trim().is_empty() {
+            break;
+        }
+        idx += line.len();
+    }
+    decor.split_at(idx)
+}
+
+/// Replaces every escline in `decor` (along with the whitespace around it)
+/// with a single space. Comments at the end of an escline are kept as
+/// multi-line comments, unless that's not possible, in which case the
+/// escline is left alone.
+pub(crate) fn collapse_esclines(decor: &str) -> String {
+    let slice = |t: &crate::Token| &decor[t.span.offset()..t.span.offset() + t.span.len()];
+    let tokens = crate::tokenize(decor).collect::<Vec<_>>();
+    let mut result = String::with_capacity(decor.len());
+    let mut idx = 0;
+    while idx < tokens.len() {
+        let token = &tokens[idx];
+        if token.kind != TokenKind::Punctuation || slice(token) != "\\" {
+            result.push_str(slice(token));
+            idx += 1;
+            continue;
+        }
+        let mut end = idx + 1;
+        let mut comment = None;
+        while let Some(next) = tokens.get(end) {
+            match next.kind {
+                TokenKind::Whitespace | TokenKind::Newline => {}
+                TokenKind::Comment if comment.is_none() && slice(next).starts_with("//") => {
+                    comment = Some(slice(next)["//".len()..].trim());
+                }
+                _ => break,
+            }
+            end += 1;
+        }
+        match comment {
+            Some(comment) if comment.contains("*/") || comment.contains("/*") => {
+                // Can't be turned into a multi-line comment.
+                for token in &tokens[idx..end] {
+                    result.push_str(slice(token));
+                }
+            }
+            Some(comment) => {
+                result.truncate(result.trim_end().len());
+                write!(result, " /* {comment} */ ").unwrap();
+            }
+            None => {
+                result.truncate(result.trim_end().len());
+                result.push(' ');
+            }
+        }
+        idx = end;
+    }
+    result
+}
+
+/// Length in bytes of `value`'s [`Display`](std::fmt::Display) output,
+/// computed without building the string.
+pub(crate) fn display_len(value: &impl std::fmt::Display) -> usize {
+    struct Counter(usize);
+    impl std::fmt::Write for Counter {
+        fn write_str(&mut self, s: &str) -> std::fmt::Result {
+            self.0 += s.len();
+            Ok(())
+        }
+    }
+    let mut counter = Counter(0);
+    write!(counter, "{value}").expect("Counting never fails.");
+    counter.0
+}
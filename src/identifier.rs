@@ -2,11 +2,12 @@
 use miette::SourceSpan;
 use std::{fmt::Display, str::FromStr};
 
-use crate::{v2_parser, KdlParseFailure, KdlValue};
+use crate::{v2_parser, KdlParseFailure, KdlValue, StringStyle};
 
 /// Represents a KDL
 /// [Identifier](https://github.com/kdl-org/kdl/blob/main/SPEC.md#identifier).
 #[derive(Debug, Clone, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct KdlIdentifier {
     pub(crate) value: String,
     pub(crate) repr: Option<String>,
@@ -35,9 +36,11 @@ impl KdlIdentifier {
         &self.value
     }
 
-    /// Sets the string value for this identifier.
+    /// Sets the string value for this identifier. This also clears its
+    /// custom representation, if any, since it would no longer match.
     pub fn set_value(&mut self, value: impl Into<String>) {
         self.value = value.into();
+        self.repr = None;
     }
 
     /// Gets this identifier's span.
@@ -87,6 +90,42 @@ impl KdlIdentifier {
     pub fn autoformat(&mut self) {
         self.repr = None;
     }
+
+    /// Rewrites this identifier in the given [`StringStyle`], where valid.
+    pub fn normalize_string_style(&mut self, style: StringStyle) {
+        self.repr = Some(style.render(&self.value, false));
+    }
+
+    /// Whether this identifier, as written out, parses back to the same
+    /// value.
+    pub(crate) fn round_trips(&self) -> bool {
+        self.to_string()
+            .parse::<KdlIdentifier>()
+            .map_or(false, |parsed| parsed.value == self.value)
+    }
+
+    /// Returns a copy of this identifier with its value converted to the
+    /// given Unicode normalization form. If that changes the value, the
+    /// custom representation (if any) is dropped, since it would no longer
+    /// match.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::{KdlIdentifier, NormalizationForm};
+    /// let ident: KdlIdentifier = "\"cafe\u{301}\"".parse().unwrap();
+    /// assert_eq!(ident.normalized(NormalizationForm::Nfc).to_string(), "caf\u{e9}");
+    /// ```
+    #[cfg(feature = "unicode-normalization")]
+    pub fn normalized(&self, form: crate::NormalizationForm) -> KdlIdentifier {
+        let value = form.apply(&self.value);
+        let mut ident = self.clone();
+        if value != self.value {
+            ident.value = value;
+            ident.repr = None;
+        }
+        ident
+    }
 }
 
 impl Display for KdlIdentifier {
@@ -187,4 +226,20 @@ mod test {
         custom_repr.set_repr(r#""foo/bar""#.to_string());
         assert_eq!(format!("{}", custom_repr), r#""foo/bar""#);
     }
+    #[cfg(feature = "unicode-normalization")]
+    #[test]
+    fn normalized() -> miette::Result<()> {
+        use crate::NormalizationForm;
+
+        let quoted: KdlIdentifier = "\"A\u{30a}\"".parse()?;
+        let normalized = quoted.normalized(NormalizationForm::Nfc);
+        assert_eq!(normalized.value(), "\u{c5}");
+        assert_eq!(normalized.repr(), None);
+
+        // Already-normalized identifiers keep their representation.
+        let plain: KdlIdentifier = "\u{c5}".parse()?;
+        assert_eq!(plain.normalized(NormalizationForm::Nfc), plain);
+        assert_eq!(plain.normalized(NormalizationForm::Nfd).value(), "A\u{30a}");
+        Ok(())
+    }
 }
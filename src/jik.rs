@@ -0,0 +1,296 @@
+//! [JSON-in-KDL](https://github.com/kdl-org/kdl/blob/main/JSON-IN-KDL.md)
+//! (JiK), the KDL microsyntax for encoding arbitrary JSON, with the
+//! `json` feature.
+//!
+//! A JSON value is written as a single node, conventionally named `-`,
+//! whose name is otherwise ignored:
+//!
+//! * A literal is the node's only argument: `- "hello"`, `- 1`, `- #null`.
+//! * An array's elements are the node's arguments, followed by its
+//!   children, which are all named `-`: `- 1 2 3`, or
+//!   `- { - 1; - a=1; }` for elements that are themselves arrays or
+//!   objects.
+//! * An object's members are the node's properties, followed by its
+//!   children, named after their keys: `- name=kdl { tags a b; }`.
+//! * Anything that would otherwise be ambiguous, like an empty or
+//!   single-element array or an empty object, is annotated with `(array)`
+//!   or `(object)`: `(array)-`, `(array)- 1`, `(object)-`.
+//!
+//! # Examples
+//!
+//! ```rust
+//! # use serde_json::json;
+//! let node: kdl::KdlNode = r#"
+//! - name=kdl {
+//!     tags rust config
+//!     authors {
+//!         - name=Kat
+//!     }
+//!     (array)keywords
+//! }
+//! "#.parse()?;
+//! let json = kdl::jik::to_json(&node)?;
+//! assert_eq!(
+//!     json,
+//!     json!({
+//!         "name": "kdl",
+//!         "tags": ["rust", "config"],
+//!         "authors": [{ "name": "Kat" }],
+//!         "keywords": [],
+//!     })
+//! );
+//! assert_eq!(kdl::jik::to_json(&kdl::jik::from_json(&json)?)?, json);
+//! # Ok::<(), miette::Report>(())
+//! ```
+
+use serde_json::{Map, Value};
+
+use crate::{KdlConversionError, KdlEntry, KdlNode, KdlValue};
+
+/// Reads the JSON value encoded by `node`. See the [module docs](self) for
+/// the encoding.
+pub fn to_json(node: &KdlNode) -> Result<Value, KdlConversionError> {
+    let args = node.entries().iter().filter(|entry| entry.name().is_none());
+    let props = node.entries().iter().filter(|entry| entry.name().is_some());
+    let children = node.children().map_or(&[][..], |doc| doc.nodes());
+    let ty = node.ty().map(|ty| ty.value());
+    let is_array = match ty {
+        Some("array") => true,
+        Some("object") => false,
+        _ => {
+            if props.clone().next().is_some()
+                || children.iter().any(|child| child.name().value() != "-")
+            {
+                false
+            } else if children.is_empty() {
+                match node.entries() {
+                    [] => {
+                        return Err(node_error(
+                            node,
+                            "An empty node is ambiguous. Annotate it with (array) or (object).",
+                        ))
+                    }
+                    [arg] => return value_to_json(arg),
+                    _ => true,
+                }
+            } else {
+                true
+            }
+        }
+    };
+
+    if is_array {
+        if let Some(prop) = props.clone().next() {
+            return Err(entry_error(prop, "Arrays can't have properties."));
+        }
+        if let Some(child) = children.iter().find(|child| child.name().value() != "-") {
+            return Err(node_error(child, "Array elements must be named `-`."));
+        }
+        let mut array = args.map(value_to_json).collect::<Result<Vec<_>, _>>()?;
+        for child in children {
+            array.push(to_json(child)?);
+        }
+        Ok(Value::Array(array))
+    } else {
+        if let Some(arg) = args.clone().next() {
+            return Err(entry_error(arg, "Objects can't have arguments."));
+        }
+        let mut object = Map::new();
+        for prop in props {
+            let key = prop.name().expect("props have names").value();
+            object.insert(key.into(), value_to_json(prop)?);
+        }
+        for child in children {
+            object.insert(child.name().value().into(), to_json(child)?);
+        }
+        Ok(Value::Object(object))
+    }
+}
+
+/// Encodes `json` as a node named `-`. See the [module docs](self) for the
+/// encoding. The result is autoformatted.
+///
+/// Fails only for JSON integers too large for KDL, which are only possible
+/// with `serde_json`'s `arbitrary_precision` feature.
+pub fn from_json(json: &Value) -> Result<KdlNode, KdlConversionError> {
+    let mut node = node_from_json(json)?;
+    node.autoformat();
+    Ok(node)
+}
+
+fn node_from_json(json: &Value) -> Result<KdlNode, KdlConversionError> {
+    let mut node = KdlNode::new("-");
+    match json {
+        Value::Array(array) => {
+            if array.iter().all(is_scalar) {
+                for value in array {
+                    node.push(KdlEntry::new(scalar_from_json(value)?));
+                }
+                if array.len() < 2 {
+                    node.set_ty("array");
+                }
+            } else {
+                let children = node.ensure_children().nodes_mut();
+                for value in array {
+                    children.push(node_from_json(value)?);
+                }
+            }
+        }
+        Value::Object(object) => {
+            for (key, value) in object {
+                if is_scalar(value) {
+                    node.push(KdlEntry::new_prop(key.as_str(), scalar_from_json(value)?));
+                } else {
+                    let mut child = node_from_json(value)?;
+                    child.set_name(key.as_str());
+                    node.ensure_children().nodes_mut().push(child);
+                }
+            }
+            let looks_like_array = node.entries().is_empty()
+                && node.children().map_or(true, |doc| {
+                    doc.nodes().iter().all(|n| n.name().value() == "-")
+                });
+            if looks_like_array {
+                node.set_ty("object");
+            }
+        }
+        scalar => node.push(KdlEntry::new(scalar_from_json(scalar)?)),
+    }
+    Ok(node)
+}
+
+fn is_scalar(json: &Value) -> bool {
+    !matches!(json, Value::Array(_) | Value::Object(_))
+}
+
+fn scalar_from_json(json: &Value) -> Result<KdlValue, KdlConversionError> {
+    KdlValue::from_json_scalar(json).map_err(|err| KdlConversionError {
+        message: err.to_string(),
+        span: None,
+    })
+}
+
+fn value_to_json(entry: &KdlEntry) -> Result<Value, KdlConversionError> {
+    entry
+        .value()
+        .to_json_scalar()
+        .map_err(|err| entry_error(entry, err.to_string()))
+}
+
+#[cfg(feature = "span")]
+fn node_error(node: &KdlNode, message: impl Into<String>) -> KdlConversionError {
+    KdlConversionError {
+        message: message.into(),
+        span: Some(node.span()),
+    }
+}
+
+#[cfg(not(feature = "span"))]
+fn node_error(_node: &KdlNode, message: impl Into<String>) -> KdlConversionError {
+    KdlConversionError {
+        message: message.into(),
+        span: None,
+    }
+}
+
+#[cfg(feature = "span")]
+fn entry_error(entry: &KdlEntry, message: impl Into<String>) -> KdlConversionError {
+    KdlConversionError {
+        message: message.into(),
+        span: Some(entry.span()),
+    }
+}
+
+#[cfg(not(feature = "span"))]
+fn entry_error(_entry: &KdlEntry, message: impl Into<String>) -> KdlConversionError {
+    KdlConversionError {
+        message: message.into(),
+        span: None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    fn jik(input: &str) -> Result<Value, KdlConversionError> {
+        to_json(&input.parse().unwrap())
+    }
+
+    #[test]
+    fn decoding() -> miette::Result<()> {
+        assert_eq!(jik("- 1")?, json!(1));
+        assert_eq!(jik("- #null")?, json!(null));
+        assert_eq!(jik("(u8)- 1")?, json!(1));
+        assert_eq!(jik("- 1 2")?, json!([1, 2]));
+        assert_eq!(jik("(array)- 1")?, json!([1]));
+        assert_eq!(jik("(array)-")?, json!([]));
+        assert_eq!(jik("(object)-")?, json!({}));
+        assert_eq!(jik("- a=1")?, json!({ "a": 1 }));
+        assert_eq!(jik("- 1 { - 2; - a=3; }")?, json!([1, 2, { "a": 3 }]));
+        assert_eq!(jik("- { - 1; }")?, json!([1]));
+        assert_eq!(jik("(object)- { - 1; }")?, json!({ "-": 1 }));
+        assert_eq!(
+            jik("anything a=1 { b 2 3; c { d #true; }; }")?,
+            json!({ "a": 1, "b": [2, 3], "c": { "d": true } })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn encoding() -> miette::Result<()> {
+        for (json, kdl) in [
+            (json!("hi"), "- hi\n"),
+            (json!([]), "(array)-\n"),
+            (json!([1]), "(array)- 1\n"),
+            (json!([1, "a", null]), "- 1 a #null\n"),
+            (json!({}), "(object)-\n"),
+            (json!([[1, 2], {}]), "- {\n    - 1 2\n    (object)-\n}\n"),
+            (
+                json!({ "a": 1, "b": [2], "c": { "d": true } }),
+                "- a=1 {\n    (array)b 2\n    c d=#true\n}\n",
+            ),
+            (json!({ "-": [1, 2] }), "(object)- {\n    - 1 2\n}\n"),
+        ] {
+            let node = from_json(&json)?;
+            assert_eq!(node.to_string(), kdl, "{json}");
+            assert_eq!(to_json(&node)?, json, "{kdl}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn errors() {
+        for (input, message, at) in [
+            (
+                "-",
+                "An empty node is ambiguous. Annotate it with (array) or (object).",
+                "-",
+            ),
+            ("(array)- a=1", "Arrays can't have properties.", "a=1"),
+            (
+                "(array)- { a 1; }",
+                "Array elements must be named `-`.",
+                "a 1",
+            ),
+            ("- 1 a=1", "Objects can't have arguments.", "1"),
+            (
+                "- { a { - #inf; }; }",
+                "KDL value #inf has no JSON equivalent.",
+                "#inf",
+            ),
+        ] {
+            let err = jik(input).unwrap_err();
+            assert_eq!(err.message, message, "{input}");
+            #[cfg(feature = "span")]
+            {
+                let span = err.span.unwrap();
+                assert_eq!(&input[span.offset()..][..span.len()], at, "{input}");
+            }
+            #[cfg(not(feature = "span"))]
+            let _ = at;
+        }
+    }
+}
@@ -0,0 +1,318 @@
+use serde_json::{Map, Value};
+
+use crate::{JsonDocumentError, KdlDocument, KdlEntry, KdlIdentifier, KdlNode, KdlValue};
+
+impl KdlDocument {
+    /// Converts this document to JSON, with this layout:
+    ///
+    /// * The document is an array of nodes.
+    /// * A node is an object with its `"name"`, and, when it has them, its
+    ///   `"type"` annotation, its `"args"` as an array, its `"props"` as an
+    ///   object, and its `"children"` as another array of nodes. An empty
+    ///   children block (`node {}`) is an empty `"children"` array.
+    /// * Values are JSON scalars, unless they have a type annotation, in
+    ///   which case they're objects like `{"type": "u8", "value": 255}`.
+    ///
+    /// [`KdlDocument::from_json`] reads this layout back. Formatting and
+    /// comments are lost, and properties are kept by name, so only the last
+    /// of any duplicates survives, but the result means the same thing.
+    ///
+    /// JSON numbers can't hold `#inf`, `#-inf`, `#nan`, or integers outside
+    /// the `i64` and `u64` ranges, so those are errors.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlDocument;
+    /// let doc: KdlDocument = "(person)author Kat email=(email)\"kat@example.com\" { active #true; }".parse()?;
+    /// assert_eq!(
+    ///     doc.to_json()?,
+    ///     serde_json::json!([{
+    ///         "name": "author",
+    ///         "type": "person",
+    ///         "args": ["Kat"],
+    ///         "props": { "email": { "type": "email", "value": "kat@example.com" } },
+    ///         "children": [{ "name": "active", "args": [true] }],
+    ///     }])
+    /// );
+    /// # Ok::<(), miette::Report>(())
+    /// ```
+    pub fn to_json(&self) -> Result<Value, JsonDocumentError> {
+        nodes_to_json(self.nodes(), "")
+    }
+
+    /// Reads a document from the JSON layout written by
+    /// [`KdlDocument::to_json`]. The result is autoformatted.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlDocument;
+    /// let json = serde_json::json!([
+    ///     { "name": "server", "args": ["localhost"], "props": { "port": 8080 } },
+    ///     { "name": "empty", "children": [] },
+    /// ]);
+    /// let doc = KdlDocument::from_json(&json)?;
+    /// assert_eq!(doc.to_string(), "server localhost port=8080\nempty {\n}\n");
+    /// assert_eq!(doc.to_json()?, json);
+    ///
+    /// let err = KdlDocument::from_json(&serde_json::json!([{ "args": [] }])).unwrap_err();
+    /// assert_eq!(err.pointer, "/0");
+    /// # Ok::<(), miette::Report>(())
+    /// ```
+    pub fn from_json(json: &Value) -> Result<Self, JsonDocumentError> {
+        let mut doc = KdlDocument::new();
+        *doc.nodes_mut() = nodes_from_json(json, "")?;
+        doc.autoformat();
+        Ok(doc)
+    }
+}
+
+fn error(pointer: &str, message: impl Into<String>) -> JsonDocumentError {
+    JsonDocumentError {
+        pointer: pointer.into(),
+        message: message.into(),
+    }
+}
+
+/// Appends `token` to a JSON Pointer, escaping it as RFC 6901 requires.
+fn push_pointer(pointer: &str, token: impl std::fmt::Display) -> String {
+    let token = token.to_string().replace('~', "~0").replace('/', "~1");
+    format!("{pointer}/{token}")
+}
+
+fn nodes_to_json(nodes: &[KdlNode], pointer: &str) -> Result<Value, JsonDocumentError> {
+    nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| node_to_json(node, &push_pointer(pointer, i)))
+        .collect()
+}
+
+fn node_to_json(node: &KdlNode, pointer: &str) -> Result<Value, JsonDocumentError> {
+    let mut object = Map::new();
+    object.insert("name".into(), node.name().value().into());
+    if let Some(ty) = node.ty() {
+        object.insert("type".into(), ty.value().into());
+    }
+    let mut args = Vec::new();
+    let mut props = Map::new();
+    for entry in node.entries() {
+        match entry.name() {
+            Some(name) => {
+                let pointer = push_pointer(&push_pointer(pointer, "props"), name.value());
+                props.insert(name.value().into(), value_to_json(entry, &pointer)?);
+            }
+            None => {
+                let pointer = push_pointer(&push_pointer(pointer, "args"), args.len());
+                args.push(value_to_json(entry, &pointer)?);
+            }
+        }
+    }
+    if !args.is_empty() {
+        object.insert("args".into(), args.into());
+    }
+    if !props.is_empty() {
+        object.insert("props".into(), props.into());
+    }
+    if let Some(children) = node.children() {
+        let pointer = push_pointer(pointer, "children");
+        object.insert(
+            "children".into(),
+            nodes_to_json(children.nodes(), &pointer)?,
+        );
+    }
+    Ok(object.into())
+}
+
+fn value_to_json(entry: &KdlEntry, pointer: &str) -> Result<Value, JsonDocumentError> {
+    let value = entry
+        .value()
+        .to_json_scalar()
+        .map_err(|err| error(pointer, err.to_string()))?;
+    Ok(match entry.ty() {
+        Some(ty) => serde_json::json!({ "type": ty.value(), "value": value }),
+        None => value,
+    })
+}
+
+fn nodes_from_json(json: &Value, pointer: &str) -> Result<Vec<KdlNode>, JsonDocumentError> {
+    let nodes = json
+        .as_array()
+        .ok_or_else(|| error(pointer, "Expected an array of nodes."))?;
+    nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| node_from_json(node, &push_pointer(pointer, i)))
+        .collect()
+}
+
+fn node_from_json(json: &Value, pointer: &str) -> Result<KdlNode, JsonDocumentError> {
+    let object = json
+        .as_object()
+        .ok_or_else(|| error(pointer, "Expected a node object."))?;
+    let name = object
+        .get("name")
+        .and_then(Value::as_str)
+        .ok_or_else(|| error(pointer, "Expected a node with a string \"name\"."))?;
+    let mut node = KdlNode::new(name);
+    for (key, value) in object {
+        let pointer = push_pointer(pointer, key);
+        match key.as_str() {
+            "name" => {}
+            "type" => {
+                let ty = value
+                    .as_str()
+                    .ok_or_else(|| error(&pointer, "Expected a string."))?;
+                node.set_ty(ty);
+            }
+            "args" => {
+                let args = value
+                    .as_array()
+                    .ok_or_else(|| error(&pointer, "Expected an array of values."))?;
+                for (i, arg) in args.iter().enumerate() {
+                    let (ty, value) = value_from_json(arg, &push_pointer(&pointer, i))?;
+                    let mut entry = KdlEntry::new(value);
+                    if let Some(ty) = ty {
+                        entry.set_ty(ty);
+                    }
+                    node.push(entry);
+                }
+            }
+            "props" => {
+                let props = value
+                    .as_object()
+                    .ok_or_else(|| error(&pointer, "Expected an object of values."))?;
+                for (key, prop) in props {
+                    let (ty, value) = value_from_json(prop, &push_pointer(&pointer, key))?;
+                    let mut entry = KdlEntry::new_prop(key.as_str(), value);
+                    if let Some(ty) = ty {
+                        entry.set_ty(ty);
+                    }
+                    node.push(entry);
+                }
+            }
+            "children" => {
+                let children = nodes_from_json(value, &pointer)?;
+                node.ensure_children().nodes_mut().extend(children);
+            }
+            _ => return Err(error(&pointer, format!("Unexpected node field {key:?}."))),
+        }
+    }
+    Ok(node)
+}
+
+fn value_from_json(
+    json: &Value,
+    pointer: &str,
+) -> Result<(Option<KdlIdentifier>, KdlValue), JsonDocumentError> {
+    match json {
+        Value::Object(object) => {
+            let ty = object.get("type").and_then(Value::as_str);
+            match (ty, object.get("value")) {
+                (Some(ty), Some(value)) if object.len() == 2 => {
+                    let value = KdlValue::from_json_scalar(value)
+                        .map_err(|err| error(&push_pointer(pointer, "value"), err.to_string()))?;
+                    Ok((Some(ty.into()), value))
+                }
+                _ => Err(error(
+                    pointer,
+                    "Expected a scalar, or an object with only a string \"type\" and a \"value\".",
+                )),
+            }
+        }
+        json => KdlValue::from_json_scalar(json)
+            .map(|value| (None, value))
+            .map_err(|err| error(pointer, err.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn round_trip() -> miette::Result<()> {
+        let doc: KdlDocument = r#"
+        // comments are dropped
+        (t)node 1 -2 3.5 "four" #true #null (u8)7 a=1 b=(date)"2024-01-01" {
+            child
+            "with/slash~tilde" x=1
+            empty {}
+        }
+        "#
+        .parse()?;
+        let json = doc.to_json()?;
+        assert_eq!(
+            json,
+            json!([{
+                "name": "node",
+                "type": "t",
+                "args": [1, -2, 3.5, "four", true, null, { "type": "u8", "value": 7 }],
+                "props": { "a": 1, "b": { "type": "date", "value": "2024-01-01" } },
+                "children": [
+                    { "name": "child" },
+                    { "name": "with/slash~tilde", "props": { "x": 1 } },
+                    { "name": "empty", "children": [] },
+                ],
+            }])
+        );
+        let back = KdlDocument::from_json(&json)?;
+        assert_eq!(back.to_json()?, json);
+        let mut expected = doc.clone();
+        expected.clear_format_recursive();
+        let mut actual = back.clone();
+        actual.clear_format_recursive();
+        assert_eq!(actual.to_string(), expected.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn errors() -> miette::Result<()> {
+        let doc: KdlDocument =
+            "a { \"b/c\" x=#inf; }\nd 170141183460469231731687303715884105727".parse()?;
+        let err = doc.to_json().unwrap_err();
+        assert_eq!(err.pointer, "/0/children/0/props/x");
+        assert_eq!(err.message, "KDL value #inf has no JSON equivalent.");
+
+        let doc: KdlDocument = "d 170141183460469231731687303715884105727".parse()?;
+        assert_eq!(doc.to_json().unwrap_err().pointer, "/0/args/0");
+
+        for (json, pointer, message) in [
+            (json!({}), "", "Expected an array of nodes."),
+            (json!([1]), "/0", "Expected a node object."),
+            (
+                json!([{ "name": 1 }]),
+                "/0",
+                "Expected a node with a string \"name\".",
+            ),
+            (
+                json!([{ "name": "a", "extra": 1 }]),
+                "/0/extra",
+                "Unexpected node field \"extra\".",
+            ),
+            (
+                json!([{ "name": "a", "args": [[1]] }]),
+                "/0/args/0",
+                "Expected a JSON scalar, but got an array.",
+            ),
+            (
+                json!([{ "name": "a", "props": { "x/y": { "type": "t" } } }]),
+                "/0/props/x~1y",
+                "Expected a scalar, or an object with only a string \"type\" and a \"value\".",
+            ),
+            (
+                json!([{ "name": "a", "children": [{ "name": "b", "args": [{ "type": "t", "value": {} }] }] }]),
+                "/0/children/0/args/0/value",
+                "Expected a JSON scalar, but got an object.",
+            ),
+        ] {
+            let err = KdlDocument::from_json(&json).unwrap_err();
+            assert_eq!(err.pointer, pointer, "{json}");
+            assert_eq!(err.message, message, "{json}");
+        }
+        Ok(())
+    }
+}
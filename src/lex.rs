@@ -0,0 +1,241 @@
+//! Lossless tokenizing of KDL source text.
+//!
+//! [`tokenize`] splits a document into [`Token`]s following the same lexical
+//! rules as the parser, which makes it a good base for syntax highlighters,
+//! formatters, and other tools that work on source text rather than on a
+//! [`KdlDocument`](crate::KdlDocument). Concatenating the text of all tokens
+//! always gives back the original input.
+//!
+//! Everything in here is also re-exported from the crate root.
+
+use miette::SourceSpan;
+use winnow::stream::{Location, Stream};
+
+use crate::v2_parser::{self, Input};
+
+/// Splits KDL source text into a flat stream of [`Token`]s.
+///
+/// This is intended for things like syntax highlighting, where a full
+/// [`KdlDocument`](crate::KdlDocument) isn't needed. Unlike the document
+/// parser, tokenizing never fails: anything that can't be recognized as a
+/// valid token is emitted as a [`TokenKind::Error`] token instead, and
+/// tokenizing resumes at the next whitespace.
+///
+/// Tokens are emitted in order and their spans cover the entire input
+/// without gaps or overlaps.
+///
+/// # Examples
+///
+/// ```rust
+/// use kdl::{tokenize, TokenKind};
+///
+/// let kinds = tokenize("node (t)1 key=#true")
+///     .map(|t| t.kind)
+///     .collect::<Vec<_>>();
+/// assert_eq!(
+///     kinds,
+///     vec![
+///         TokenKind::Identifier,
+///         TokenKind::Whitespace,
+///         TokenKind::TypeAnnotation,
+///         TokenKind::Number,
+///         TokenKind::Whitespace,
+///         TokenKind::Identifier,
+///         TokenKind::Punctuation,
+///         TokenKind::Keyword,
+///     ]
+/// );
+/// ```
+pub fn tokenize(input: &str) -> Tokens<'_> {
+    Tokens {
+        input: v2_parser::unrecoverable_input(input),
+    }
+}
+
+/// A single lexical token produced by [`tokenize`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Token {
+    /// What kind of token this is.
+    pub kind: TokenKind,
+    /// Location of this token in the original input.
+    pub span: SourceSpan,
+}
+
+/// The kind of a [`Token`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenKind {
+    /// A bare identifier string, such as a node name, property key, or
+    /// unquoted string value.
+    Identifier,
+    /// A quoted or raw string, including its quotes and hashes.
+    String,
+    /// A decimal, hexadecimal, octal, or binary number.
+    Number,
+    /// A keyword or keyword number, such as `#true` or `#inf`.
+    Keyword,
+    /// A complete type annotation, including its parentheses.
+    TypeAnnotation,
+    /// A single-line or multi-line comment, or a slashdash (`/-`) marker.
+    Comment,
+    /// Structural punctuation: `{`, `}`, `(`, `)`, `;`, `=`, or an escline's
+    /// `\`.
+    Punctuation,
+    /// A run of Unicode spaces on a single line.
+    Whitespace,
+    /// A single newline, such as `\n` or `\r\n`.
+    Newline,
+    /// Text that could not be recognized as any other kind of token.
+    Error,
+}
+
+/// Iterator over the [`Token`]s in some KDL source text. Created by
+/// [`tokenize`].
+#[derive(Debug)]
+pub struct Tokens<'a> {
+    input: Input<'a>,
+}
+
+impl Iterator for Tokens<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.input.eof_offset() == 0 {
+            return None;
+        }
+        let start = self.input.location();
+        let checkpoint = self.input.checkpoint();
+        let kind = match v2_parser::token(&mut self.input) {
+            Ok(kind) if self.input.location() > start => kind,
+            _ => {
+                self.input.reset(&checkpoint);
+                // Always make progress, then slurp up the rest of the bad
+                // text so we don't emit a separate error for every char.
+                self.input.next_token();
+                while let Some((_, c)) = self.input.peek_token() {
+                    if c.is_whitespace() {
+                        break;
+                    }
+                    self.input.next_token();
+                }
+                TokenKind::Error
+            }
+        };
+        Some(Token {
+            kind,
+            span: (start..self.input.location()).into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn kinds_and_text(input: &str) -> Vec<(TokenKind, &str)> {
+        tokenize(input)
+            .map(|t| {
+                (
+                    t.kind,
+                    &input[t.span.offset()..t.span.offset() + t.span.len()],
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn basic_tokens() {
+        use TokenKind::*;
+        assert_eq!(
+            kinds_and_text("(t)node \"arg\" /- 0x1F prop=#inf { child; } // hi\n"),
+            vec![
+                (TypeAnnotation, "(t)"),
+                (Identifier, "node"),
+                (Whitespace, " "),
+                (String, "\"arg\""),
+                (Whitespace, " "),
+                (Comment, "/-"),
+                (Whitespace, " "),
+                (Number, "0x1F"),
+                (Whitespace, " "),
+                (Identifier, "prop"),
+                (Punctuation, "="),
+                (Keyword, "#inf"),
+                (Whitespace, " "),
+                (Punctuation, "{"),
+                (Whitespace, " "),
+                (Identifier, "child"),
+                (Punctuation, ";"),
+                (Whitespace, " "),
+                (Punctuation, "}"),
+                (Whitespace, " "),
+                (Comment, "// hi"),
+                (Newline, "\n"),
+            ]
+        );
+    }
+
+    #[test]
+    fn strings_and_comments() {
+        use TokenKind::*;
+        assert_eq!(
+            kinds_and_text("a ##\"raw\"# str\"## /* nested /* c */ */ -1.5e3"),
+            vec![
+                (Identifier, "a"),
+                (Whitespace, " "),
+                (String, "##\"raw\"# str\"##"),
+                (Whitespace, " "),
+                (Comment, "/* nested /* c */ */"),
+                (Whitespace, " "),
+                (Number, "-1.5e3"),
+            ]
+        );
+    }
+
+    #[test]
+    fn never_fails() {
+        use TokenKind::*;
+        assert_eq!(
+            kinds_and_text("foo #bogus \"unterminated"),
+            vec![
+                (Identifier, "foo"),
+                (Whitespace, " "),
+                (Error, "#bogus"),
+                (Whitespace, " "),
+                (Error, "\"unterminated"),
+            ]
+        );
+        assert_eq!(tokenize("").count(), 0);
+    }
+
+    #[test]
+    fn newlines() {
+        use TokenKind::*;
+        assert_eq!(
+            kinds_and_text("a \\ // c\r\n  b\n\n"),
+            vec![
+                (Identifier, "a"),
+                (Whitespace, " "),
+                (Punctuation, "\\"),
+                (Whitespace, " "),
+                (Comment, "// c"),
+                (Newline, "\r\n"),
+                (Whitespace, "  "),
+                (Identifier, "b"),
+                (Newline, "\n"),
+                (Newline, "\n"),
+            ]
+        );
+    }
+
+    #[test]
+    fn spans_cover_input() {
+        let input = include_str!("../examples/kdl-schema.kdl");
+        let mut offset = 0;
+        for token in tokenize(input) {
+            assert_eq!(token.span.offset(), offset);
+            assert!(!token.span.is_empty());
+            offset += token.span.len();
+        }
+        assert_eq!(offset, input.len());
+    }
+}
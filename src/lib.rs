@@ -15,7 +15,25 @@
 //!
 //! This crate supports parsing [KDL
 //! 2.0.0-draft.6](https://github.com/kdl-org/kdl/releases/tag/2.0.0-draft.6)
-//! 
+//!
+//! ## KDL v1
+//!
+//! With the `v1` feature, which is on by default, [`KdlDocument::parse_v1`]
+//! parses documents written in KDL v1, and [`KdlDocument::parse_auto`]
+//! takes documents in either version. It goes by a `/- kdl-version` marker
+//! if there is one, and otherwise tries v2, then v1.
+//! [`KdlDocument::version`] tells you which one it was.
+//!
+//! ```rust
+//! # #[cfg(feature = "v1")]
+//! # {
+//! use kdl::{KdlDocument, KdlVersion};
+//!
+//! let doc = KdlDocument::parse_auto("/- kdl-version 1\nnode true").unwrap();
+//! assert_eq!(doc.version(), KdlVersion::V1);
+//! # }
+//! ```
+//!
 //! ## Example
 //!
 //! ```rust
@@ -69,6 +87,85 @@
 //! [`KdlDocument`], [`KdlNode`], [`KdlEntry`], and [`KdlIdentifier`] can all
 //! be parsed and managed this way.
 //!
+//! ## Query Engine
+//!
+//! `kdl` includes a query engine for
+//! [KQL](https://github.com/kdl-org/kdl/blob/main/QUERY-SPEC.md), which lets you
+//! pick out nodes from a document using a CSS Selectors-style syntax. See
+//! [`KdlQuery`] for the details.
+//!
+//! Queries can be done from either a [`KdlDocument`] or a [`KdlNode`], with
+//! mostly the same semantics.
+//!
+//! ```rust
+//! use kdl::KdlDocument;
+//!
+//! let doc = r#"
+//! a {
+//!     b 1
+//!     c 2
+//!     d 3 {
+//!         e prop=hello
+//!     }
+//! }
+//! "#.parse::<KdlDocument>().expect("failed to parse KDL");
+//!
+//! let results = doc.query("a > b").expect("failed to parse query");
+//! assert_eq!(results, Some(&doc.nodes()[0].children().unwrap().nodes()[0]));
+//!
+//! let results = doc.query_get("e", "prop").expect("failed to parse query");
+//! assert_eq!(results, Some(&"hello".into()));
+//!
+//! let results = doc.query_get_all("a > []", 0).expect("failed to parse query").collect::<Vec<_>>();
+//! assert_eq!(results, vec![&1.into(), &2.into(), &3.into()]);
+//! ```
+//!
+//! ## Serde
+//!
+//! With the `serde` feature, `kdl::ser::to_string` and
+//! `kdl::ser::to_document` write any `Serialize` type as KDL, turning
+//! struct fields into nodes and properties. See the `ser` module for the
+//! details. Going the other way, `kdl::de::from_str` and
+//! `kdl::de::from_document` read any `Deserialize` type back out of a
+//! document, with diagnostics pointing at whatever didn't fit.
+//!
+//! The document model itself ([`KdlDocument`], [`KdlNode`], [`KdlEntry`],
+//! [`KdlValue`], and the rest) also implements `Serialize` and `Deserialize`
+//! with the `serde` feature, including formatting and spans, so parsed
+//! documents can be cached or sent elsewhere and come back exactly as they
+//! were.
+//!
+//! ## Typed Decoding
+//!
+//! [`KdlDecode`] and [`KdlEncode`] read and write types directly from and to
+//! nodes: struct fields map to arguments, properties, and children. With the
+//! `derive` feature, they can be derived:
+//!
+//! ```rust
+//! # #[cfg(feature = "derive")]
+//! # mod example {
+//! use kdl::{KdlDecode, KdlEncode};
+//!
+//! #[derive(KdlDecode, KdlEncode)]
+//! struct Server {
+//!     #[kdl(argument)]
+//!     host: String,
+//!     #[kdl(property)]
+//!     port: Option<u16>,
+//!     #[kdl(children, name = "route")]
+//!     routes: Vec<Route>,
+//! }
+//!
+//! #[derive(KdlDecode, KdlEncode)]
+//! struct Route {
+//!     #[kdl(argument)]
+//!     path: String,
+//! }
+//! # }
+//! ```
+//!
+//! See [`KdlDecode`] for all the field attributes.
+//!
 //! ## Error Reporting
 //!
 //! [`KdlError`] implements [`miette::Diagnostic`] and can be used to display
@@ -133,63 +230,78 @@
 //! The code in this repository is covered by [the Apache-2.0
 //! License](LICENSE).
 
-// TODO(@zkat): bring this back later.
-// ### Query Engine
-
-// `kdl` includes a query engine for
-// [KQL](https://github.com/kdl-org/kdl/blob/main/QUERY-SPEC.md), which lets you
-// pick out nodes from a document using a CSS Selectors-style syntax.
-
-// Queries can be done from either a [`KdlDocument`] or a [`KdlNode`], with
-// mostly the same semantics.
-
-// ```rust
-// use kdl::KdlDocument;
-
-// let doc = r#"
-// a {
-//     b 1
-//     c 2
-//     d 3 {
-//         e prop="hello"
-//     }
-// }
-// "#.parse::<KdlDocument>().expect("failed to parse KDL");
-
-// let results = doc.query("a > b").expect("failed to parse query");
-// assert_eq!(results, Some(&doc.nodes()[0].children().unwrap().nodes()[0]));
-
-// let results = doc.query_get("e", "prop").expect("failed to parse query");
-// assert_eq!(results, Some(&"hello".into()));
-
-// let results = doc.query_get_all("a > []", 0).expect("failed to parse query").collect::<Vec<_>>();
-// assert_eq!(results, vec![&1.into(), &2.into(), &3.into()]);
-// ```
-
 #![deny(missing_debug_implementations, nonstandard_style)]
 #![warn(missing_docs, rust_2018_idioms, unreachable_pub)]
 // #![cfg_attr(test, deny(warnings))]
 #![doc(html_favicon_url = "https://kdl.dev/favicon.ico")]
 #![doc(html_logo_url = "https://kdl.dev/logo.svg")]
 
+pub use decode::*;
+#[cfg(feature = "span")]
+pub use diff::*;
 pub use document::*;
 pub use entry::*;
 pub use error::*;
+pub use events::*;
 pub use identifier::*;
+pub use lex::*;
+pub use merge::*;
 pub use node::*;
-// pub use query::*;
+pub use options::*;
+pub use path::*;
+pub use query::*;
+pub use reader::*;
+pub use trivia::*;
+pub use types::*;
 pub use value::*;
 
+#[cfg(feature = "derive")]
+pub use kdl_derive::{KdlDecode, KdlEncode};
+
+#[cfg(any(feature = "toml", feature = "yaml"))]
+pub mod convert;
+#[cfg(feature = "serde")]
+pub mod de;
+mod decode;
+#[cfg(feature = "span")]
+mod diff;
 mod document;
+#[cfg(feature = "v1")]
+mod downgrade;
 mod entry;
 mod error;
+mod events;
+#[cfg(feature = "ffi")]
+pub mod ffi;
 mod fmt;
 mod identifier;
+#[cfg(feature = "json")]
+pub mod jik;
+#[cfg(feature = "json")]
+mod json;
+pub mod lex;
+#[cfg(feature = "lsp")]
+mod lsp;
+mod merge;
 mod node;
-// mod nom_compat;
-// mod query;
-// mod query_parser;
-// mod v1_parser;
+mod options;
+mod path;
+mod path_parser;
+mod query;
+mod query_parser;
+mod reader;
+#[cfg(feature = "span")]
+mod reparse;
+#[cfg(feature = "serde")]
+pub mod ser;
+mod trivia;
+mod types;
+#[cfg(feature = "v1")]
+mod upgrade;
+#[cfg(feature = "v1")]
+mod v1_parser;
 mod value;
 
 mod v2_parser;
+#[cfg(feature = "xik")]
+pub mod xik;
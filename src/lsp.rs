@@ -0,0 +1,122 @@
+use lsp_types::{
+    Diagnostic, DiagnosticRelatedInformation, DiagnosticSeverity, Location, NumberOrString,
+    Position, Range, Url,
+};
+use miette::Diagnostic as _;
+
+use crate::KdlDiagnostic;
+
+impl KdlDiagnostic {
+    /// The LSP range of this diagnostic's span: 0-based lines, with
+    /// characters counted in UTF-16 code units, as LSP expects by default.
+    pub fn lsp_range(&self) -> Range {
+        let position = |offset| {
+            let (line, col) = crate::error::line_col(&self.input, offset, true);
+            Position::new(line as u32 - 1, col as u32 - 1)
+        };
+        Range::new(
+            position(self.span.offset()),
+            position(self.span.offset() + self.span.len()),
+        )
+    }
+
+    /// Converts this diagnostic for a language server, like the
+    /// [`From`] impl, and also attaches its label as related information at
+    /// `uri`, the document it came from, so editors can show it alongside
+    /// the message.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlDocument;
+    /// let err = "node {\n  child".parse::<KdlDocument>().unwrap_err();
+    /// let uri = lsp_types::Url::parse("file:///config.kdl").unwrap();
+    /// let diagnostic = err.diagnostics[0].to_lsp_diagnostic(&uri);
+    /// assert_eq!(diagnostic.range.start, lsp_types::Position::new(0, 5));
+    /// let related = &diagnostic.related_information.unwrap()[0];
+    /// assert_eq!(related.message, "block opened here");
+    /// assert_eq!(related.location.uri, uri);
+    /// ```
+    pub fn to_lsp_diagnostic(&self, uri: &Url) -> Diagnostic {
+        let mut diagnostic = Diagnostic::from(self);
+        if let Some(label) = self.label {
+            diagnostic.related_information = Some(vec![DiagnosticRelatedInformation {
+                location: Location::new(uri.clone(), diagnostic.range),
+                message: label.into(),
+            }]);
+        }
+        diagnostic
+    }
+}
+
+/// Converts a diagnostic for a language server, with its range, severity,
+/// and code. Its help text, if any, is appended to the message, since LSP
+/// has nowhere else to put it. See [`KdlDiagnostic::to_lsp_diagnostic`] to
+/// also include related information.
+impl From<&KdlDiagnostic> for Diagnostic {
+    fn from(diagnostic: &KdlDiagnostic) -> Self {
+        let severity = match diagnostic.severity {
+            miette::Severity::Error => DiagnosticSeverity::ERROR,
+            miette::Severity::Warning => DiagnosticSeverity::WARNING,
+            miette::Severity::Advice => DiagnosticSeverity::INFORMATION,
+        };
+        let mut message = diagnostic.to_string();
+        if let Some(help) = diagnostic.help {
+            message.push_str("\nhelp: ");
+            message.push_str(help);
+        }
+        Diagnostic {
+            range: diagnostic.lsp_range(),
+            severity: Some(severity),
+            code: diagnostic
+                .kind
+                .code()
+                .map(|code| NumberOrString::String(code.to_string())),
+            source: Some("kdl".into()),
+            message,
+            ..Diagnostic::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::KdlErrorKind;
+
+    use super::*;
+
+    #[test]
+    fn conversion() {
+        let diagnostic = KdlDiagnostic {
+            input: Arc::new("a 1\n😀 b 0x".into()),
+            span: (11, 2).into(),
+            label: None,
+            help: Some("Add some hex digits."),
+            severity: miette::Severity::Warning,
+            kind: KdlErrorKind::NegativeUnsignedError,
+        };
+        let lsp = Diagnostic::from(&diagnostic);
+        assert_eq!(
+            lsp.range,
+            Range::new(Position::new(1, 5), Position::new(1, 7))
+        );
+        assert_eq!(lsp.severity, Some(DiagnosticSeverity::WARNING));
+        assert_eq!(
+            lsp.code,
+            Some(NumberOrString::String("kdl::negative_unsigned".into()))
+        );
+        assert_eq!(lsp.source.as_deref(), Some("kdl"));
+        assert_eq!(
+            lsp.message,
+            format!("{diagnostic}\nhelp: Add some hex digits.")
+        );
+        let uri = Url::parse("file:///a.kdl").unwrap();
+        assert_eq!(diagnostic.to_lsp_diagnostic(&uri).related_information, None);
+
+        let err = "a 0x".parse::<crate::KdlDocument>().unwrap_err();
+        let lsp = Diagnostic::from(&err.diagnostics[0]);
+        assert_eq!(lsp.severity, Some(DiagnosticSeverity::ERROR));
+    }
+}
@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+
+use crate::{KdlDocument, KdlNode};
+
+/// Rules for [`KdlDocument::overlay`] and [`KdlNode::overlay`]. The defaults
+/// merge nodes recursively, let the overlay's properties win, and let its
+/// arrays replace the base's.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct MergePolicy {
+    /// What happens to nodes with the same name.
+    pub nodes: NodeMergeRule,
+    /// What happens to properties with the same name.
+    pub props: PropMergeRule,
+    /// What happens to arrays: the arguments of a node, and the children
+    /// named `-` in a document or children block.
+    pub arrays: ArrayMergeRule,
+}
+
+/// How [`MergePolicy`] combines an overlay node with a base node of the same
+/// name. The first node with a given name in the overlay is matched with the
+/// first one with that name in the base, the second with the second, and so
+/// on. Overlay nodes with no match are always appended.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum NodeMergeRule {
+    /// Merge the overlay node into the base node: its type annotation,
+    /// arguments, and properties are combined according to the rest of the
+    /// policy, and its children are merged recursively.
+    #[default]
+    Merge,
+    /// Replace the base node with the overlay node.
+    Replace,
+    /// Never match nodes, and append every overlay node instead.
+    Append,
+}
+
+/// How [`MergePolicy`] combines properties with the same name.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum PropMergeRule {
+    /// The overlay's property replaces the base's.
+    #[default]
+    Override,
+    /// The base's property is kept, so the overlay only fills in properties
+    /// the base doesn't have.
+    Keep,
+}
+
+/// How [`MergePolicy`] combines arrays.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ArrayMergeRule {
+    /// If the overlay has any elements, they replace the base's. An overlay
+    /// with none leaves the base's alone.
+    #[default]
+    Replace,
+    /// The overlay's elements are appended to the base's.
+    Concat,
+}
+
+impl KdlDocument {
+    /// Merges `other` over this document, according to `policy`. This is
+    /// meant for layered configuration, like defaults, then a user's
+    /// config file, then command line overrides.
+    ///
+    /// Nodes, entries, and children taken from `other` keep their
+    /// formatting, so the result may need to be autoformatted.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::{ArrayMergeRule, KdlDocument, MergePolicy};
+    /// let mut config: KdlDocument = "server host=localhost port=80 { tags a b; }\nlog info".parse()?;
+    /// let user: KdlDocument = "server port=8080 { tags c; }".parse()?;
+    /// config.overlay(&user, MergePolicy::default());
+    /// config.autoformat();
+    /// assert_eq!(config.to_string(), "server host=localhost port=8080 {\n    tags c\n}\nlog info\n");
+    ///
+    /// let concat = MergePolicy {
+    ///     arrays: ArrayMergeRule::Concat,
+    ///     ..MergePolicy::default()
+    /// };
+    /// config.overlay(&"server { tags d; }".parse()?, concat);
+    /// assert_eq!(config.get("server").unwrap().children().unwrap().get_args("tags"), [&"c".into(), &"d".into()]);
+    /// # Ok::<(), miette::Report>(())
+    /// ```
+    pub fn overlay(&mut self, other: &KdlDocument, policy: MergePolicy) {
+        overlay_nodes(self.nodes_mut(), other.nodes(), policy);
+    }
+}
+
+impl KdlNode {
+    /// Merges `other` over this node, according to `policy`, as if both
+    /// were matched by [`KdlDocument::overlay`] with
+    /// [`NodeMergeRule::Merge`]. This node keeps its name.
+    pub fn overlay(&mut self, other: &KdlNode, policy: MergePolicy) {
+        if let Some(ty) = other.ty() {
+            self.set_ty(ty.clone());
+        }
+
+        let args = other
+            .entries()
+            .iter()
+            .filter(|entry| entry.name().is_none());
+        if args.clone().next().is_some() {
+            if policy.arrays == ArrayMergeRule::Replace {
+                self.entries_mut().retain(|entry| entry.name().is_some());
+            }
+            let end = self
+                .entries()
+                .iter()
+                .rposition(|entry| entry.name().is_none())
+                .map_or(0, |i| i + 1);
+            self.entries_mut().splice(end..end, args.cloned());
+        }
+
+        for prop in other
+            .entries()
+            .iter()
+            .filter(|entry| entry.name().is_some())
+        {
+            let existing = self
+                .entries_mut()
+                .iter_mut()
+                .rev()
+                .find(|entry| entry.name() == prop.name());
+            match existing {
+                Some(existing) if policy.props == PropMergeRule::Override => {
+                    *existing = prop.clone();
+                }
+                Some(_) => {}
+                None => self.entries_mut().push(prop.clone()),
+            }
+        }
+
+        if let Some(children) = other.children() {
+            overlay_nodes(self.ensure_children().nodes_mut(), children.nodes(), policy);
+        }
+    }
+}
+
+fn overlay_nodes(base: &mut Vec<KdlNode>, overlay: &[KdlNode], policy: MergePolicy) {
+    let is_dash = |node: &KdlNode| node.name().value() == "-";
+    if policy.arrays == ArrayMergeRule::Replace && overlay.iter().any(is_dash) {
+        base.retain(|node| !is_dash(node));
+    }
+
+    let mut seen = HashMap::<&str, usize>::new();
+    for node in overlay {
+        if is_dash(node) || policy.nodes == NodeMergeRule::Append {
+            base.push(node.clone());
+            continue;
+        }
+        let name = node.name().value();
+        let nth = seen.entry(name).or_default();
+        let existing = base
+            .iter_mut()
+            .filter(|existing| existing.name().value() == name)
+            .nth(*nth);
+        *nth += 1;
+        match (existing, policy.nodes) {
+            (Some(existing), NodeMergeRule::Replace) => *existing = node.clone(),
+            (Some(existing), _) => existing.overlay(node, policy),
+            (None, _) => base.push(node.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn merged(base: &str, overlay: &str, policy: MergePolicy) -> String {
+        let mut doc: KdlDocument = base.parse().unwrap();
+        doc.overlay(&overlay.parse().unwrap(), policy);
+        doc.autoformat();
+        doc.to_string()
+    }
+
+    #[test]
+    fn layers() {
+        let defaults = r#"
+            server host=localhost port=80 {
+                route "/"
+            }
+            plugins {
+                - auth
+                - cache
+            }
+            log info
+        "#;
+        let user = r#"
+            server port=8080 tls=#true
+            plugins {
+                - metrics
+            }
+            log debug
+        "#;
+        let cli = "(debug)log trace\nextra 1";
+        let mut config: KdlDocument = defaults.parse().unwrap();
+        config.overlay(&user.parse().unwrap(), MergePolicy::default());
+        config.overlay(&cli.parse().unwrap(), MergePolicy::default());
+        config.autoformat();
+        assert_eq!(
+            config.to_string(),
+            "server host=localhost port=8080 tls=#true {\n    route \"/\"\n}\nplugins {\n    - metrics\n}\n(debug)log trace\nextra 1\n"
+        );
+    }
+
+    #[test]
+    fn policies() {
+        let base = "a 1 2 x=1 { - 1; b y=1; }\na 3";
+        let overlay = "a 4 x=2 z=3 { - 2; b y=2; }\na\na 5";
+
+        assert_eq!(
+            merged(base, overlay, MergePolicy::default()),
+            "a 4 x=2 z=3 {\n    b y=2\n    - 2\n}\na 3\na 5\n"
+        );
+        assert_eq!(
+            merged(
+                base,
+                overlay,
+                MergePolicy {
+                    arrays: ArrayMergeRule::Concat,
+                    props: PropMergeRule::Keep,
+                    ..MergePolicy::default()
+                }
+            ),
+            "a 1 2 4 x=1 z=3 {\n    - 1\n    b y=1\n    - 2\n}\na 3\na 5\n"
+        );
+        assert_eq!(
+            merged(
+                base,
+                overlay,
+                MergePolicy {
+                    nodes: NodeMergeRule::Replace,
+                    ..MergePolicy::default()
+                }
+            ),
+            "a 4 x=2 z=3 {\n    - 2\n    b y=2\n}\na\na 5\n"
+        );
+        assert_eq!(
+            merged(
+                base,
+                overlay,
+                MergePolicy {
+                    nodes: NodeMergeRule::Append,
+                    ..MergePolicy::default()
+                }
+            ),
+            "a 1 2 x=1 {\n    - 1\n    b y=1\n}\na 3\na 4 x=2 z=3 {\n    - 2\n    b y=2\n}\na\na 5\n"
+        );
+    }
+}
@@ -1,22 +1,26 @@
 use std::{
-    fmt::Display,
-    ops::{Index, IndexMut},
+    fmt::{Display, Write as _},
+    ops::{Index, IndexMut, RangeBounds},
     str::FromStr,
+    sync::Arc,
 };
 
 #[cfg(feature = "span")]
 use miette::SourceSpan;
 
 use crate::{
-    v2_parser, KdlDocument, KdlDocumentFormat, KdlEntry, KdlIdentifier, KdlParseFailure, KdlValue,
+    document::Descendants,
+    fmt::{is_newline, shift_decor, IndentShift},
+    v2_parser, IntoKdlQuery, KdlDiagnostic, KdlDocument, KdlDocumentFormat, KdlEntry,
+    KdlEntryFormat, KdlErrorKind, KdlFormatConfig, KdlIdentifier, KdlParseFailure, KdlQueryIterMut,
+    KdlQueryIterator, KdlTrivia, KdlValidationError, KdlValidationErrorKind, KdlValue, StringStyle,
 };
 
-static INDENT: usize = 4;
-
 /// Represents an individual KDL
 /// [`Node`](https://github.com/kdl-org/kdl/blob/main/SPEC.md#node) inside a
 /// KDL Document.
 #[derive(Debug, Clone, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct KdlNode {
     pub(crate) ty: Option<KdlIdentifier>,
     pub(crate) name: KdlIdentifier,
@@ -26,8 +30,36 @@ pub struct KdlNode {
     pub(crate) format: Option<KdlNodeFormat>,
     #[cfg(feature = "span")]
     pub(crate) span: SourceSpan,
+    #[cfg(feature = "user-data")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) user_data: UserData,
+}
+
+/// Arbitrary data attached to a node by its user. It's never compared,
+/// hashed, or written out, and isn't carried over when the node is cloned,
+/// since it can't be cloned itself.
+#[cfg(feature = "user-data")]
+#[derive(Debug, Default)]
+pub(crate) struct UserData(Option<Box<dyn std::any::Any + Send + Sync>>);
+
+#[cfg(feature = "user-data")]
+impl Clone for UserData {
+    fn clone(&self) -> Self {
+        Self(None)
+    }
 }
 
+// So `KdlNode` can still derive `Eq`. It doesn't compare user data anyway.
+#[cfg(feature = "user-data")]
+impl PartialEq for UserData {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+#[cfg(feature = "user-data")]
+impl Eq for UserData {}
+
 impl PartialEq for KdlNode {
     fn eq(&self, other: &Self) -> bool {
         self.ty == other.ty
@@ -36,6 +68,7 @@ impl PartialEq for KdlNode {
             && self.children == other.children
             && self.format == other.format
         // intentionally omitted: self.span == other.span
+        // intentionally omitted: self.user_data == other.user_data
     }
 }
 
@@ -47,6 +80,7 @@ impl std::hash::Hash for KdlNode {
         self.children.hash(state);
         self.format.hash(state);
         // Intentionally omitted: self.span.hash(state);
+        // Intentionally omitted: self.user_data.hash(state);
     }
 }
 
@@ -64,6 +98,8 @@ impl KdlNode {
             }),
             #[cfg(feature = "span")]
             span: SourceSpan::from(0..0),
+            #[cfg(feature = "user-data")]
+            user_data: UserData::default(),
         }
     }
 
@@ -98,6 +134,39 @@ impl KdlNode {
         self.span = span.into();
     }
 
+    /// Gets the data attached to this node with [`Self::set_user_data`], if
+    /// any.
+    ///
+    /// User data is for tools to annotate a tree in place. It doesn't take
+    /// part in equality or hashing, is never written out, and is dropped
+    /// from clones of the node.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlNode;
+    /// let mut node = KdlNode::new("foo");
+    /// node.set_user_data(42u32);
+    /// assert_eq!(node.user_data().and_then(|d| d.downcast_ref()), Some(&42u32));
+    /// assert_eq!(node, KdlNode::new("foo"));
+    /// ```
+    #[cfg(feature = "user-data")]
+    pub fn user_data(&self) -> Option<&(dyn std::any::Any + Send + Sync)> {
+        self.user_data.0.as_deref()
+    }
+
+    /// Gets a mutable reference to this node's user data slot.
+    #[cfg(feature = "user-data")]
+    pub fn user_data_mut(&mut self) -> &mut Option<Box<dyn std::any::Any + Send + Sync>> {
+        &mut self.user_data.0
+    }
+
+    /// Attaches `data` to this node, replacing any data already there.
+    #[cfg(feature = "user-data")]
+    pub fn set_user_data(&mut self, data: impl std::any::Any + Send + Sync) {
+        self.user_data.0 = Some(Box::new(data));
+    }
+
     /// Gets the node's type identifier, if any.
     pub fn ty(&self) -> Option<&KdlIdentifier> {
         self.ty.as_ref()
@@ -113,6 +182,11 @@ impl KdlNode {
         self.ty = Some(ty.into());
     }
 
+    /// Whether this node's type annotation is `ty`, as in `(ty)node`.
+    pub fn has_ty(&self, ty: &str) -> bool {
+        self.ty.as_ref().map_or(false, |own| own.value() == ty)
+    }
+
     /// Returns a reference to this node's entries (arguments and properties).
     pub fn entries(&self) -> &[KdlEntry] {
         &self.entries
@@ -124,9 +198,36 @@ impl KdlNode {
         &mut self.entries
     }
 
+    /// Iterates over this node's entries (arguments and properties) whose
+    /// type annotation is `ty`, as in `node (ty)1 key=(ty)2`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlNode;
+    /// let node: KdlNode = "task (env)HOME (dir)\"/tmp\" out=(dir)build".parse().unwrap();
+    /// let dirs = node
+    ///     .entries_with_ty("dir")
+    ///     .map(|entry| entry.value().as_string().unwrap())
+    ///     .collect::<Vec<_>>();
+    /// assert_eq!(dirs, ["/tmp", "build"]);
+    /// ```
+    pub fn entries_with_ty<'a>(&'a self, ty: &'a str) -> impl Iterator<Item = &'a KdlEntry> {
+        self.entries.iter().filter(move |entry| entry.has_ty(ty))
+    }
+
     /// Length of this node when rendered as a string.
     pub fn len(&self) -> usize {
-        format!("{}", self).len()
+        self.repr_len()
+    }
+
+    /// Length in bytes of this node's textual representation, including its
+    /// formatting, as produced by its [`Display`] implementation.
+    ///
+    /// This is computed without allocating the string, which makes it
+    /// suitable for things like deciding whether a node fits on a line.
+    pub fn repr_len(&self) -> usize {
+        crate::fmt::display_len(self)
     }
 
     /// Returns true if this node is completely empty (including whitespace).
@@ -170,6 +271,36 @@ impl KdlNode {
         self.entry_impl(key.into())
     }
 
+    /// Gets the value of the property named `name`, if there is one and it's
+    /// a string. Like [`Self::get`], the last property with that name wins.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlNode;
+    /// let node: KdlNode = r#"server host="localhost" port=8080 tls=#true"#.parse().unwrap();
+    /// assert_eq!(node.prop_str("host"), Some("localhost"));
+    /// assert_eq!(node.prop_i64("port"), Some(8080));
+    /// assert_eq!(node.prop_bool("tls"), Some(true));
+    /// assert_eq!(node.prop_str("port"), None);
+    /// assert_eq!(node.prop_bool("missing"), None);
+    /// ```
+    pub fn prop_str(&self, name: &str) -> Option<&str> {
+        self.get(name)?.as_string()
+    }
+
+    /// Gets the value of the property named `name`, if there is one and it's
+    /// an integer that fits in an `i64`. See [`Self::prop_str`].
+    pub fn prop_i64(&self, name: &str) -> Option<i64> {
+        self.get(name)?.as_integer()?.try_into().ok()
+    }
+
+    /// Gets the value of the property named `name`, if there is one and it's
+    /// a boolean. See [`Self::prop_str`].
+    pub fn prop_bool(&self, name: &str) -> Option<bool> {
+        self.get(name)?.as_bool()
+    }
+
     fn entry_impl(&self, key: NodeKey) -> Option<&KdlEntry> {
         match key {
             NodeKey::Key(key) => {
@@ -353,6 +484,87 @@ impl KdlNode {
         &mut self.children
     }
 
+    /// Iterates over this node's children, if any. Nodes without a children
+    /// block just yield nothing.
+    pub fn children_iter(&self) -> std::slice::Iter<'_, KdlNode> {
+        self.children()
+            .map_or(&[][..], |children| children.nodes())
+            .iter()
+    }
+
+    /// Like [`Self::children_iter`], but back to front.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlNode;
+    /// let node: KdlNode = "parent { a; b; c; }".parse().unwrap();
+    /// let names = node
+    ///     .children_iter_rev()
+    ///     .map(|child| child.name().value())
+    ///     .collect::<Vec<_>>();
+    /// assert_eq!(names, ["c", "b", "a"]);
+    /// ```
+    pub fn children_iter_rev(&self) -> std::iter::Rev<std::slice::Iter<'_, KdlNode>> {
+        self.children_iter().rev()
+    }
+
+    /// Gets the first argument of each of this node's children named `-`,
+    /// which is the KDL convention for arrays. Children with no arguments
+    /// are skipped. See [`KdlDocument::get_dash_args`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlNode;
+    /// let node: KdlNode = "ports { - 80; - 443; other 1; }".parse().unwrap();
+    /// assert_eq!(node.dash_values(), vec![&80.into(), &443.into()]);
+    /// ```
+    pub fn dash_values(&self) -> Vec<&KdlValue> {
+        self.dash_children()
+            .filter_map(|node| node.get(0))
+            .collect()
+    }
+
+    /// Iterates over this node's children named `-`, for arrays of values
+    /// with more than one entry or children of their own.
+    pub fn dash_children(&self) -> impl Iterator<Item = &KdlNode> {
+        self.children_iter()
+            .filter(|node| node.name().value() == "-")
+    }
+
+    /// Iterates over this node's children and everything nested in them,
+    /// depth-first. Doesn't include this node itself. See
+    /// [`KdlDocument::descendants`].
+    pub fn descendants(&self) -> impl Iterator<Item = &KdlNode> {
+        Descendants::new(self.children.as_ref().map_or(&[][..], |c| c.nodes()))
+    }
+
+    /// Like [`Self::descendants`], but only yields nodes named `name`.
+    pub fn descendants_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a KdlNode> {
+        self.descendants()
+            .filter(move |node| node.name().value() == name)
+    }
+
+    /// Gets the child at `index`, if this node has children and there are
+    /// enough of them.
+    pub fn child(&self, index: usize) -> Option<&KdlNode> {
+        self.children.as_ref()?.nodes.get(index)
+    }
+
+    /// Gets a mutable reference to the child at `index`, if this node has
+    /// children and there are enough of them.
+    pub fn child_mut(&mut self, index: usize) -> Option<&mut KdlNode> {
+        self.children.as_mut()?.nodes.get_mut(index)
+    }
+
+    /// Gets the children in `range`, if this node has children and the range
+    /// is in bounds.
+    pub fn child_range(&self, range: impl RangeBounds<usize>) -> Option<&[KdlNode]> {
+        let bounds = (range.start_bound().cloned(), range.end_bound().cloned());
+        self.children.as_ref()?.nodes.get(bounds)
+    }
+
     /// Sets the KdlDocument representing this node's children.
     pub fn set_children(&mut self, children: KdlDocument) {
         self.children = Some(children);
@@ -386,57 +598,634 @@ impl KdlNode {
     pub fn set_format(&mut self, format: KdlNodeFormat) {
         self.format = Some(format);
     }
+    /// Gets the comment between this node's contents and its terminator, if
+    /// any, including its comment delimiters.
+    ///
+    /// If a node is terminated by a single-line comment, that comment is
+    /// returned (without its newline). Otherwise, a multi-line comment right
+    /// before the terminator (such as the one in `foo 1 /* here */;`) is
+    /// returned.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlNode;
+    /// let node: KdlNode = "foo 1 // explanation\n".parse().unwrap();
+    /// assert_eq!(node.trailing_comment(), Some("// explanation"));
+    /// ```
+    pub fn trailing_comment(&self) -> Option<&str> {
+        let fmt = self.format()?;
+        if fmt.terminator.starts_with("//") {
+            Some(fmt.terminator.trim_end_matches(crate::fmt::is_newline))
+        } else {
+            let before = fmt.before_terminator.trim();
+            if before.starts_with("/*") {
+                Some(before)
+            } else {
+                None
+            }
+        }
+    }
+
+    /// Sets the comment between this node's contents and its terminator,
+    /// replacing any existing one. `comment` is the text of the comment,
+    /// without delimiters. Returns whether the comment was set.
+    ///
+    /// Nodes terminated by a newline (or not explicitly terminated at all)
+    /// get a single-line comment that takes the newline's place. Nodes
+    /// terminated by `;`, as well as comments that span multiple lines, use
+    /// a multi-line comment placed right before the terminator, so the
+    /// terminator itself is never commented out.
+    ///
+    /// Multi-line comments nest, so they can't hold text whose `/*` and `*/`
+    /// don't pair up. Such text gets a single-line comment instead, if it
+    /// fits on one line, and on a node terminated by `;`, that comment
+    /// replaces the `;`, so the node ends up terminated by the comment's
+    /// newline. If it spans multiple lines as well, it can't be written as
+    /// either kind of comment, so `false` is returned and the node is left
+    /// as it was.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlNode;
+    /// let mut node: KdlNode = "foo 1\n".parse().unwrap();
+    /// assert!(node.set_trailing_comment("the answer"));
+    /// assert_eq!(node.to_string(), "foo 1 // the answer\n");
+    ///
+    /// let mut node: KdlNode = "foo 1;".parse().unwrap();
+    /// node.set_trailing_comment("the answer");
+    /// assert_eq!(node.to_string(), "foo 1 /* the answer */;");
+    /// node.set_trailing_comment("*/ unpaired");
+    /// assert_eq!(node.to_string(), "foo 1 // */ unpaired\n");
+    ///
+    /// assert!(!node.set_trailing_comment("*/ on\ntwo lines"));
+    /// assert_eq!(node.to_string(), "foo 1 // */ unpaired\n");
+    /// ```
+    pub fn set_trailing_comment(&mut self, comment: impl AsRef<str>) -> bool {
+        let comment = comment.as_ref();
+        let multiline = comment.contains(crate::fmt::is_newline);
+        let nests = nests_in_block_comment(comment);
+        if !nests && multiline {
+            return false;
+        }
+        self.clear_trailing_comment();
+        let fmt = self.format.get_or_insert_with(Default::default);
+        if nests && (fmt.terminator == ";" || multiline) {
+            fmt.before_terminator = format!(" /* {comment} */").into();
+        } else {
+            fmt.before_terminator = " ".into();
+            fmt.terminator = format!("// {comment}\n").into();
+        }
+        true
+    }
+
+    /// Removes the comment between this node's contents and its terminator,
+    /// if any, along with any whitespace around it. A single-line comment
+    /// terminator is replaced with a newline.
+    pub fn clear_trailing_comment(&mut self) {
+        if let Some(fmt) = self.format_mut() {
+            fmt.before_terminator.clear();
+            if fmt.terminator.starts_with("//") {
+                fmt.terminator = "\n".into();
+            }
+        }
+    }
+
+    /// Compares two nodes by what they mean rather than how they're written:
+    /// their type annotations, names, entries (see
+    /// [`KdlEntry::semantic_eq`]), and children are compared, while
+    /// formatting, comments, and spans are ignored.
+    ///
+    /// Arguments are compared in order, but properties aren't, and only the
+    /// last of several properties with the same name counts, as it's the one
+    /// that applies.
+    ///
+    /// A node with an empty children block is not considered equal to one
+    /// without any children block.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlNode;
+    /// let left: KdlNode = "node x=1 2 x=3 y=4".parse().unwrap();
+    /// let right: KdlNode = "node y=4 2 x=3".parse().unwrap();
+    /// assert!(left.semantic_eq(&right));
+    /// assert!(!left.semantic_eq(&"node y=4 x=1 2".parse().unwrap()));
+    /// ```
+    pub fn semantic_eq(&self, other: &Self) -> bool {
+        fn args(node: &KdlNode) -> Vec<&KdlEntry> {
+            node.entries
+                .iter()
+                .filter(|entry| entry.name().is_none())
+                .collect()
+        }
+        fn props(node: &KdlNode) -> std::collections::HashMap<&str, &KdlEntry> {
+            node.entries
+                .iter()
+                .filter_map(|entry| Some((entry.name()?.value(), entry)))
+                .collect()
+        }
+        let (self_args, other_args) = (args(self), args(other));
+        let (self_props, other_props) = (props(self), props(other));
+        self.ty.as_ref().map(|ty| ty.value()) == other.ty.as_ref().map(|ty| ty.value())
+            && self.name.value() == other.name.value()
+            && self_args.len() == other_args.len()
+            && self_args
+                .iter()
+                .zip(&other_args)
+                .all(|(a, b)| a.semantic_eq(b))
+            && self_props.len() == other_props.len()
+            && self_props
+                .iter()
+                .all(|(name, a)| other_props.get(name).map_or(false, |b| a.semantic_eq(b)))
+            && match (&self.children, &other.children) {
+                (Some(a), Some(b)) => a.semantic_eq(b),
+                (None, None) => true,
+                _ => false,
+            }
+    }
+
+    /// How this node is terminated, going by its formatting. Nodes without
+    /// formatting details are written out on their own line, so they count
+    /// as [`TerminatorKind::Newline`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::{KdlDocument, TerminatorKind};
+    /// let doc: KdlDocument = "a; b // comment\nc".parse().unwrap();
+    /// let kinds = doc
+    ///     .nodes()
+    ///     .iter()
+    ///     .map(|node| node.terminator_kind())
+    ///     .collect::<Vec<_>>();
+    /// assert_eq!(
+    ///     kinds,
+    ///     [
+    ///         TerminatorKind::Semicolon,
+    ///         TerminatorKind::Newline,
+    ///         TerminatorKind::Implicit
+    ///     ]
+    /// );
+    /// ```
+    pub fn terminator_kind(&self) -> TerminatorKind {
+        match self.format() {
+            Some(fmt) if fmt.terminator.starts_with(';') => TerminatorKind::Semicolon,
+            Some(fmt) if fmt.terminator.is_empty() => TerminatorKind::Implicit,
+            _ => TerminatorKind::Newline,
+        }
+    }
+
+    /// Changes how this node is terminated, the fix for a
+    /// [`KdlDocument::lint_terminators`](crate::KdlDocument::lint_terminators)
+    /// warning. Returns whether the node's terminator was changed.
+    ///
+    /// A single-line comment that was terminating the node is kept, after
+    /// the new terminator. Nodes without formatting details are already
+    /// newline-terminated, so setting [`TerminatorKind::Newline`] on one
+    /// leaves it alone.
+    ///
+    /// [`TerminatorKind::Implicit`] is never applied here, and `false` is
+    /// returned, since it'd join this node up with whatever comes after it
+    /// unless it's the last node of its document or children block. Use
+    /// [`KdlDocument::set_terminator_kind`](crate::KdlDocument::set_terminator_kind)
+    /// for that.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::{KdlDocument, TerminatorKind};
+    /// let mut doc: KdlDocument = "a // comment\nb".parse().unwrap();
+    /// for node in doc.nodes_mut() {
+    ///     assert!(node.set_terminator_kind(TerminatorKind::Semicolon));
+    /// }
+    /// assert_eq!(doc.to_string(), "a; // comment\nb;");
+    /// for node in doc.nodes_mut() {
+    ///     node.set_terminator_kind(TerminatorKind::Newline);
+    /// }
+    /// assert_eq!(doc.to_string(), "a // comment\nb\n");
+    /// assert!(!doc.nodes_mut()[0].set_terminator_kind(TerminatorKind::Implicit));
+    /// ```
+    pub fn set_terminator_kind(&mut self, kind: TerminatorKind) -> bool {
+        if kind == TerminatorKind::Implicit {
+            return false;
+        }
+        self.replace_terminator(kind);
+        true
+    }
+
+    /// Does the work of [`Self::set_terminator_kind`], without checking
+    /// whether `kind` fits where this node is.
+    pub(crate) fn replace_terminator(&mut self, kind: TerminatorKind) {
+        if kind == TerminatorKind::Newline && self.format.is_none() {
+            return;
+        }
+        let fmt = self.format.get_or_insert_with(Default::default);
+        let terminator = match kind {
+            TerminatorKind::Semicolon => ";",
+            TerminatorKind::Newline => "\n",
+            TerminatorKind::Implicit => "",
+        };
+        let old = std::mem::replace(&mut fmt.terminator, terminator.into());
+        if old.starts_with("//") {
+            // Keep the comment, and the space before it, after the new
+            // terminator.
+            let before = fmt.before_terminator.trim_end().len();
+            let space = fmt.before_terminator.to_mut().split_off(before);
+            fmt.trailing = format!("{space}{old}{}", fmt.trailing).into();
+        } else if kind == TerminatorKind::Newline {
+            // A newline, or a single-line comment, right after the old
+            // terminator can terminate the node itself.
+            let trailing = fmt.trailing.to_string();
+            if let Some((space, end)) = line_end(&trailing) {
+                fmt.before_terminator =
+                    format!("{}{}", fmt.before_terminator, &trailing[..space]).into();
+                fmt.terminator = trailing[space..end].to_string().into();
+                fmt.trailing = trailing[end..].to_string().into();
+            }
+        }
+    }
+
+    /// Checks whether this node's entries are ordered according to `rule`,
+    /// returning a warning pointing at the first entry that's out of order,
+    /// if any.
+    ///
+    /// The diagnostic's source is this node's own text, so it can be rendered
+    /// on its own. Use [`KdlEntry::span`] to find the offending entry in the
+    /// original document instead.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::{EntryOrderRule, KdlNode};
+    /// let node: KdlNode = "node a=1 2".parse().unwrap();
+    /// let diag = node.lint_entry_order(EntryOrderRule::ArgumentsFirst).unwrap();
+    /// assert_eq!(diag.span, (9..10).into());
+    /// assert!(node.lint_entry_order(EntryOrderRule::PropertiesFirst).is_none());
+    /// ```
+    pub fn lint_entry_order(&self, rule: EntryOrderRule) -> Option<KdlDiagnostic> {
+        let misplaced = |entry: &KdlEntry| match rule {
+            EntryOrderRule::ArgumentsFirst => entry.is_argument(),
+            EntryOrderRule::PropertiesFirst => entry.is_property(),
+        };
+        let first_other = self.entries.iter().position(|e| !misplaced(e))?;
+        let bad = first_other + self.entries[first_other..].iter().position(misplaced)?;
+
+        // Find the entry in this node's text, the same way `stringify` lays
+        // it out.
+        let mut offset = match self.format() {
+            Some(fmt) => fmt.leading.len(),
+            None => 0,
+        };
+        offset += self.ty.as_ref().map_or(0, |ty| ty.to_string().len() + 2);
+        offset += self.name.to_string().len();
+        for entry in &self.entries[..bad] {
+            offset += entry.repr_len() + usize::from(entry.format().is_none());
+        }
+        let entry = &self.entries[bad];
+        let (leading, trailing) = match entry.format() {
+            Some(fmt) => (fmt.leading.len(), fmt.trailing.len()),
+            None => (1, 0),
+        };
+        let start = offset + leading;
+        let end = offset + usize::from(entry.format().is_none()) + entry.repr_len() - trailing;
+        Some(KdlDiagnostic {
+            input: Arc::new(self.to_string()),
+            span: (start..end).into(),
+            label: Some("out of order"),
+            help: Some(match rule {
+                EntryOrderRule::ArgumentsFirst => {
+                    "Move this argument before the node's properties."
+                }
+                EntryOrderRule::PropertiesFirst => {
+                    "Move this property before the node's arguments."
+                }
+            }),
+            severity: miette::Severity::Warning,
+            kind: KdlErrorKind::EntryOrder(rule),
+        })
+    }
+
+    /// Removes any line continuations (esclines) between this node's name,
+    /// entries, and children block, so they're all on a single line.
+    ///
+    /// Comments at the end of continued lines are turned into multi-line
+    /// comments, so they aren't lost.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlNode;
+    /// let mut node: KdlNode = "foo 1 \\\n    2 \\ // two\n    3".parse().unwrap();
+    /// node.collapse_esclines();
+    /// assert_eq!(node.to_string(), "foo 1 2 /* two */ 3");
+    /// ```
+    pub fn collapse_esclines(&mut self) {
+        use crate::fmt::collapse_esclines;
+        for entry in &mut self.entries {
+            if let Some(fmt) = entry.format_mut() {
+                fmt.leading = collapse_esclines(&fmt.leading).into();
+                fmt.trailing = collapse_esclines(&fmt.trailing).into();
+            }
+        }
+        if let Some(fmt) = self.format_mut() {
+            fmt.before_children = collapse_esclines(&fmt.before_children).into();
+            fmt.before_terminator = collapse_esclines(&fmt.before_terminator).into();
+        }
+    }
+
+    /// Reflows this node's entries so that no line is longer than `width`
+    /// characters, wherever possible, by continuing long lines with esclines.
+    /// Any existing esclines are removed first.
+    ///
+    /// Continuation lines are indented four spaces past the indentation at
+    /// the end of this node's own leading whitespace. An entry that doesn't
+    /// fit on a line of its own is still put on its own line.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlNode;
+    /// let mut node: KdlNode = "node 1 2 3 4 5".parse().unwrap();
+    /// node.expand_to_esclines(9);
+    /// assert_eq!(node.to_string(), "node 1 2 \\\n    3 4 5");
+    /// ```
+    pub fn expand_to_esclines(&mut self, width: usize) {
+        self.collapse_esclines();
+        let indent = self
+            .format()
+            .map(|fmt| {
+                let last_line = fmt
+                    .leading
+                    .rsplit(crate::fmt::is_newline)
+                    .next()
+                    .unwrap_or_default();
+                last_line[..last_line.len() - last_line.trim_start().len()].to_string()
+            })
+            .unwrap_or_default();
+        let mut column = indent.chars().count()
+            + self
+                .ty
+                .as_ref()
+                .map_or(0, |ty| ty.to_string().chars().count() + 2)
+            + self.name.to_string().chars().count();
+        let entry_count = self.entries.len();
+        let children_need_space = self.children.is_some() && self.format.is_none();
+        for (idx, entry) in self.entries.iter_mut().enumerate() {
+            if entry.format().is_none() {
+                entry.set_format(KdlEntryFormat {
+                    value_repr: entry.value().to_string(),
+                    leading: " ".into(),
+                    trailing: if children_need_space && idx + 1 == entry_count {
+                        " ".into()
+                    } else {
+                        "".into()
+                    },
+                    ..Default::default()
+                });
+            }
+            let rendered = entry.to_string();
+            let first_line = rendered.lines().next().unwrap_or_default();
+            if column + first_line.chars().count() > width {
+                let fmt = entry.format_mut().expect("set above");
+                fmt.leading = format!(" \\\n{indent}    {}", fmt.leading.trim_start()).into();
+                column = 0;
+            }
+            let rendered = entry.to_string();
+            column = match rendered.rfind(crate::fmt::is_newline) {
+                Some(idx) => rendered[idx + 1..].chars().count(),
+                None => column + rendered.chars().count(),
+            };
+        }
+    }
+
+    /// Rewrites the type annotations of this node, its entries, and all its
+    /// descendants into the canonical `(name)` form, without any whitespace
+    /// or comments inside them or between them and what they annotate. Type
+    /// names keep their original representation, including any quoting.
+    pub fn normalize_type_annotations(&mut self) {
+        if self.ty.is_some() {
+            if let Some(fmt) = self.format_mut() {
+                fmt.before_ty_name.clear();
+                fmt.after_ty_name.clear();
+                fmt.after_ty.clear();
+            }
+        }
+        for entry in &mut self.entries {
+            entry.normalize_type_annotation();
+        }
+        if let Some(children) = &mut self.children {
+            children.normalize_type_annotations();
+        }
+    }
+
+    /// Rewrites every string value and identifier in this node and its
+    /// descendants in the given [`StringStyle`], where valid. See
+    /// [`KdlDocument::normalize_string_style`].
+    pub fn normalize_string_style(&mut self, style: StringStyle) {
+        for ident in self.ty.iter_mut().chain(Some(&mut self.name)) {
+            ident.normalize_string_style(style);
+        }
+        for entry in &mut self.entries {
+            entry.normalize_string_style(style);
+        }
+        if let Some(children) = &mut self.children {
+            children.normalize_string_style(style);
+        }
+    }
+
+    /// Collects problems with this node and its descendants into `errors`.
+    /// `path` is this node's path, and is left as it was.
+    pub(crate) fn validate_impl(
+        &self,
+        path: &mut Vec<usize>,
+        errors: &mut Vec<KdlValidationError>,
+    ) {
+        for ident in self.ty.iter().chain(Some(&self.name)) {
+            if !ident.round_trips() {
+                errors.push(KdlValidationError {
+                    path: path.clone(),
+                    entry: None,
+                    kind: KdlValidationErrorKind::InvalidIdentifier {
+                        value: ident.value().into(),
+                        repr: ident.to_string(),
+                    },
+                });
+            }
+        }
+        for (idx, entry) in self.entries.iter().enumerate() {
+            for kind in entry.validate_impl() {
+                errors.push(KdlValidationError {
+                    path: path.clone(),
+                    entry: Some(idx),
+                    kind,
+                });
+            }
+        }
+        if let Some(children) = &self.children {
+            children.validate_impl(path, errors);
+        }
+    }
+
     /// Auto-formats this node and its contents.
     pub fn autoformat(&mut self) {
-        self.autoformat_impl(0, false);
+        self.autoformat_config(&KdlFormatConfig::default());
     }
 
     /// Auto-formats this node and its contents, stripping comments.
     pub fn autoformat_no_comments(&mut self) {
-        self.autoformat_impl(0, true);
-    }
-
-    // TODO(@zkat): These should all be moved into the query module, instead
-    // of being model methods.
-    //
-    // /// Queries this Node according to the KQL
-    // query language, /// returning an iterator over all matching nodes. pub
-    // fn query_all( &self, query: impl IntoKdlQuery, ) ->
-    //     Result<KdlQueryIterator<'_>, KdlDiagnostic> { let q =
-    //     query.into_query()?; Ok(KdlQueryIterator::new(Some(self), None, q))
-    // }
-
-    // /// Queries this Node according to the KQL query language,
-    // /// returning the first match, if any.
-    // pub fn query(&self, query: impl IntoKdlQuery) -> Result<Option<&KdlNode>, KdlDiagnostic> {
-    //     Ok(self.query_all(query)?.next())
-    // }
-
-    // /// Queries this Node according to the KQL query language,
-    // /// picking the first match, and calling `.get(key)` on it, if the query
-    // /// succeeded.
-    // pub fn query_get(
-    //     &self,
-    //     query: impl IntoKdlQuery,
-    //     key: impl Into<NodeKey>,
-    // ) -> Result<Option<&KdlValue>, KdlDiagnostic> {
-    //     Ok(self.query(query)?.and_then(|node| node.get(key)))
-    // }
-
-    // /// Queries this Node according to the KQL query language,
-    // /// returning an iterator over all matching nodes, returning the requested
-    // /// field from each of those nodes and filtering out nodes that don't have
-    // /// it.
-    // pub fn query_get_all(
-    //     &self,
-    //     query: impl IntoKdlQuery,
-    //     key: impl Into<NodeKey>,
-    // ) -> Result<impl Iterator<Item = &KdlValue>, KdlDiagnostic> {
-    //     let key: NodeKey = key.into();
-    //     Ok(self
-    //         .query_all(query)?
-    //         .filter_map(move |node| node.get(key.clone())))
-    // }
+        self.autoformat_config(&KdlFormatConfig {
+            no_comments: true,
+            ..Default::default()
+        });
+    }
+
+    /// Auto-formats this node and its contents according to `config`. See
+    /// [`KdlDocument::autoformat_config`].
+    pub fn autoformat_config(&mut self, config: &KdlFormatConfig<'_>) {
+        self.autoformat_impl(0, config, false);
+        if config.align_properties {
+            if let Some(children) = &mut self.children {
+                children.align_properties();
+            }
+        }
+    }
+
+    /// Queries this node's children according to the [KQL query
+    /// language](KdlQuery), returning an iterator over all matching nodes.
+    /// `top()` stands for this node, so `top() > []` matches its direct
+    /// children. The node itself is never part of the results.
+    pub fn query_all(
+        &self,
+        query: impl IntoKdlQuery,
+    ) -> Result<KdlQueryIterator<'_>, KdlParseFailure> {
+        let children = self.children().map_or(&[][..], |children| children.nodes());
+        Ok(KdlQueryIterator::new(children, query.into_query()?))
+    }
+
+    /// Queries this node's children according to the [KQL query
+    /// language](KdlQuery), returning the first match, if any.
+    pub fn query(&self, query: impl IntoKdlQuery) -> Result<Option<&KdlNode>, KdlParseFailure> {
+        Ok(self.query_all(query)?.next())
+    }
+
+    /// Like [`Self::query_all`], but returns the matching nodes mutably, one
+    /// at a time. See [`KdlQueryIterMut`] for an example.
+    pub fn query_all_mut(
+        &mut self,
+        query: impl IntoKdlQuery,
+    ) -> Result<KdlQueryIterMut<'_>, KdlParseFailure> {
+        let children = match &mut self.children {
+            Some(children) => &mut children.nodes_mut()[..],
+            None => &mut [],
+        };
+        Ok(KdlQueryIterMut::new(children, query.into_query()?))
+    }
+
+    /// Like [`Self::query`], but returns the first match mutably.
+    pub fn query_mut(
+        &mut self,
+        query: impl IntoKdlQuery,
+    ) -> Result<Option<&mut KdlNode>, KdlParseFailure> {
+        Ok(self.query_all_mut(query)?.into_first())
+    }
+
+    /// Queries this node's children according to the [KQL query
+    /// language](KdlQuery), picking the first match, and calling
+    /// [`.get(key)`](KdlNode::get) on it.
+    pub fn query_get(
+        &self,
+        query: impl IntoKdlQuery,
+        key: impl Into<NodeKey>,
+    ) -> Result<Option<&KdlValue>, KdlParseFailure> {
+        Ok(self.query(query)?.and_then(|node| node.get(key)))
+    }
+
+    /// Queries this node's children according to the [KQL query
+    /// language](KdlQuery), returning the requested value from each
+    /// matching node, and skipping nodes that don't have it.
+    pub fn query_get_all(
+        &self,
+        query: impl IntoKdlQuery,
+        key: impl Into<NodeKey>,
+    ) -> Result<impl Iterator<Item = &KdlValue>, KdlParseFailure> {
+        let key: NodeKey = key.into();
+        Ok(self
+            .query_all(query)?
+            .filter_map(move |node| node.get(key.clone())))
+    }
+}
+
+/// If `trivia` starts with a newline, or a single-line comment and its
+/// newline, after some spaces, returns where the spaces end, and where the
+/// newline does.
+pub(crate) fn line_end(trivia: &str) -> Option<(usize, usize)> {
+    let rest = trivia.trim_start_matches(|c: char| c.is_whitespace() && !is_newline(c));
+    if !rest.starts_with("//") && !rest.starts_with(is_newline) {
+        return None;
+    }
+    let end = rest.find(is_newline)?;
+    let newline = if rest[end..].starts_with("\r\n") {
+        2
+    } else {
+        rest[end..].chars().next().map_or(0, char::len_utf8)
+    };
+    let space = trivia.len() - rest.len();
+    Some((space, space + end + newline))
+}
+
+/// Whether `text` can go inside a multi-line comment without ending it
+/// early or leaving it open, given that multi-line comments nest.
+fn nests_in_block_comment(text: &str) -> bool {
+    let mut depth = 0usize;
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("*/") {
+            match depth.checked_sub(1) {
+                Some(outer) => depth = outer,
+                None => return false,
+            }
+            rest = &rest[2..];
+        } else if rest.starts_with("/*") {
+            depth += 1;
+            rest = &rest[2..];
+        } else {
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    depth == 0
+}
+
+/// How a [`KdlNode`] is terminated. See [`KdlNode::terminator_kind`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TerminatorKind {
+    /// An explicit `;`.
+    Semicolon,
+    /// A newline, possibly preceded by a single-line comment.
+    Newline,
+    /// Nothing: the node is the last one before the end of the input or the
+    /// closing brace of its block.
+    Implicit,
+}
+
+/// A style rule for the order of a [`KdlNode`]'s entries, for use with
+/// [`KdlNode::lint_entry_order`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EntryOrderRule {
+    /// All arguments must come before any properties.
+    ArgumentsFirst,
+    /// All properties must come before any arguments.
+    PropertiesFirst,
+}
+
+impl Display for EntryOrderRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ArgumentsFirst => write!(f, "arguments must come before properties"),
+            Self::PropertiesFirst => write!(f, "properties must come before arguments"),
+        }
+    }
 }
 
 /// Represents a [`KdlNode`]'s entry key.
@@ -505,6 +1294,22 @@ impl FromStr for KdlNode {
     }
 }
 
+impl Drop for KdlNode {
+    // Dropping children recursively would overflow the stack on deeply nested
+    // trees, so they're taken apart from a stack on the heap instead.
+    fn drop(&mut self) {
+        let mut stack = match self.children.take() {
+            Some(children) => vec![children],
+            None => return,
+        };
+        while let Some(mut doc) = stack.pop() {
+            for mut node in doc.nodes.drain(..) {
+                stack.extend(node.children.take());
+            }
+        }
+    }
+}
+
 impl Display for KdlNode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.stringify(f, 0)
@@ -512,7 +1317,16 @@ impl Display for KdlNode {
 }
 
 impl KdlNode {
-    pub(crate) fn autoformat_impl(&mut self, indent: usize, no_comments: bool) {
+    /// `after_content` says whether anything comes before this node in its
+    /// document or children block.
+    pub(crate) fn autoformat_impl(
+        &mut self,
+        depth: usize,
+        config: &KdlFormatConfig<'_>,
+        after_content: bool,
+    ) {
+        let indent = config.indent.repeat(depth);
+        let keep_slashdashes = !config.remove_slashdashes && !config.no_comments;
         if let Some(KdlNodeFormat {
             leading,
             before_terminator,
@@ -522,20 +1336,30 @@ impl KdlNode {
             ..
         }) = self.format_mut()
         {
-            crate::fmt::autoformat_leading(leading, indent, no_comments);
-            crate::fmt::autoformat_trailing(before_terminator, no_comments);
-            crate::fmt::autoformat_trailing(trailing, no_comments);
+            if config.remove_slashdashes {
+                *leading = crate::fmt::strip_slashdashes(leading, true).into();
+                *before_terminator = crate::fmt::strip_slashdashes(before_terminator, false).into();
+                *trailing = crate::fmt::strip_slashdashes(trailing, true).into();
+            }
+            crate::fmt::autoformat_leading(leading.to_mut(), &indent, config, after_content);
+            crate::fmt::autoformat_trailing(before_terminator.to_mut(), config.no_comments);
+            crate::fmt::autoformat_trailing(trailing.to_mut(), config.no_comments);
             *trailing = trailing.trim().into();
             if !terminator.starts_with('\n') {
                 *terminator = "\n".into();
             }
             if let Some(c) = trailing.chars().next() {
                 if !c.is_whitespace() {
-                    trailing.insert(0, ' ');
+                    trailing.to_mut().insert(0, ' ');
                 }
             }
 
-            *before_children = " ".into();
+            let slashdashed = crate::fmt::slashdashed_tail(before_children);
+            *before_children = if keep_slashdashes && !slashdashed.is_empty() {
+                format!(" {slashdashed} ").into()
+            } else {
+                " ".into()
+            };
         } else {
             self.set_format(KdlNodeFormat {
                 terminator: "\n".into(),
@@ -547,27 +1371,197 @@ impl KdlNode {
             ty.clear_format()
         }
         for entry in &mut self.entries {
-            entry.autoformat();
+            entry.autoformat_impl(keep_slashdashes);
         }
         if let Some(children) = self.children.as_mut() {
-            children.autoformat_impl(indent + INDENT, no_comments);
+            children.autoformat_impl(depth + 1, config);
             if let Some(KdlDocumentFormat { leading, trailing }) = children.format_mut() {
-                *leading = leading.trim().into();
-                leading.push('\n');
-                trailing.push_str(format!("{:indent$}", "", indent = indent).as_str());
+                // Comments at the start of the block go on their own lines,
+                // like the nodes after them.
+                let child_indent = config.indent.repeat(depth + 1);
+                let comments = leading.strip_suffix(&child_indent[..]).unwrap_or(leading);
+                *leading = format!("\n{comments}").into();
+                trailing.to_mut().push_str(&indent);
+            }
+        }
+    }
+
+    /// Finds this node's `column`th property, and measures how wide this
+    /// node's last line is when rendered up to the end of that property's
+    /// key (and whatever follows it before the `=`), in characters. Returns
+    /// the property's index in [`Self::entries`] along with the width.
+    pub(crate) fn property_key_width(&self, column: usize) -> Option<(usize, usize)> {
+        let (idx, prop) = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.name().is_some())
+            .nth(column)?;
+        let mut line = String::new();
+        if let Some(ty) = &self.ty {
+            write!(line, "({})", ty).unwrap();
+        }
+        write!(line, "{}", self.name).unwrap();
+        for entry in &self.entries[..idx] {
+            if entry.format().is_none() {
+                line.push(' ');
+            }
+            write!(line, "{}", entry).unwrap();
+        }
+        match prop.format() {
+            Some(fmt) => write!(line, "{}", fmt.leading).unwrap(),
+            None => line.push(' '),
+        }
+        write!(line, "{}", prop.name().unwrap()).unwrap();
+        if let Some(fmt) = prop.format() {
+            line.push_str(&fmt.after_key);
+        }
+        let last_line = line.rsplit(is_newline).next().unwrap_or_default();
+        Some((idx, last_line.chars().count()))
+    }
+
+    pub(crate) fn shift_indent_impl(&mut self, shift: &IndentShift<'_>, at_line_start: &mut bool) {
+        if let Some(fmt) = self.format.as_mut() {
+            shift_decor(fmt.leading.to_mut(), shift, at_line_start, true);
+        }
+        // Type annotations and names are never empty.
+        *at_line_start = false;
+        for entry in &mut self.entries {
+            if let Some(fmt) = entry.format_mut() {
+                shift_decor(fmt.leading.to_mut(), shift, at_line_start, true);
+                *at_line_start = false;
+                shift_decor(fmt.trailing.to_mut(), shift, at_line_start, false);
+            } else {
+                *at_line_start = false;
+            }
+        }
+        if let Some(children) = self.children.as_mut() {
+            if let Some(fmt) = self.format.as_mut() {
+                shift_decor(fmt.before_children.to_mut(), shift, at_line_start, true);
+            }
+            *at_line_start = false;
+            children.shift_indent_impl(shift, at_line_start, true);
+            *at_line_start = false;
+        }
+        if let Some(fmt) = self.format.as_mut() {
+            shift_decor(fmt.before_terminator.to_mut(), shift, at_line_start, false);
+            shift_decor(fmt.terminator.to_mut(), shift, at_line_start, false);
+            shift_decor(fmt.trailing.to_mut(), shift, at_line_start, false);
+        }
+    }
+
+    /// Re-indents this node, which is nested `depth` levels deep, to
+    /// `depth` copies of `unit`. Continuation lines get one more level.
+    pub(crate) fn reindent_impl(&mut self, unit: &str, depth: usize, at_line_start: &mut bool) {
+        let indent = unit.repeat(depth);
+        let inner = unit.repeat(depth + 1);
+        let own = IndentShift::Set {
+            indent: &indent,
+            last: &indent,
+        };
+        let continued = IndentShift::Set {
+            indent: &inner,
+            last: &inner,
+        };
+        if let Some(fmt) = self.format.as_mut() {
+            shift_decor(fmt.leading.to_mut(), &own, at_line_start, true);
+        }
+        *at_line_start = false;
+        for entry in &mut self.entries {
+            if let Some(fmt) = entry.format_mut() {
+                shift_decor(fmt.leading.to_mut(), &continued, at_line_start, true);
+                *at_line_start = false;
+                shift_decor(fmt.trailing.to_mut(), &continued, at_line_start, false);
+            } else {
+                *at_line_start = false;
+            }
+        }
+        if let Some(children) = self.children.as_mut() {
+            if let Some(fmt) = self.format.as_mut() {
+                shift_decor(
+                    fmt.before_children.to_mut(),
+                    &continued,
+                    at_line_start,
+                    true,
+                );
+            }
+            *at_line_start = false;
+            children.reindent_impl(unit, depth + 1, at_line_start);
+            *at_line_start = false;
+        }
+        if let Some(fmt) = self.format.as_mut() {
+            shift_decor(
+                fmt.before_terminator.to_mut(),
+                &continued,
+                at_line_start,
+                false,
+            );
+            shift_decor(fmt.terminator.to_mut(), &own, at_line_start, false);
+            shift_decor(fmt.trailing.to_mut(), &own, at_line_start, false);
+        }
+    }
+
+    /// Rewrites the representation of every number in this node and its
+    /// descendants into its canonical form.
+    pub(crate) fn normalize_numbers(&mut self) {
+        for entry in &mut self.entries {
+            entry.normalize_number();
+        }
+        if let Some(children) = &mut self.children {
+            for node in children.nodes_mut() {
+                node.normalize_numbers();
             }
         }
     }
 
+    /// Writes this node out, along with its children. Children blocks are
+    /// written from an explicit stack rather than by recursing, so that
+    /// arbitrarily deep trees can't overflow the call stack.
     pub(crate) fn stringify(
         &self,
         f: &mut std::fmt::Formatter<'_>,
         indent: usize,
     ) -> std::fmt::Result {
+        let mut stack = vec![Stringify::Node(self, indent)];
+        while let Some(step) = stack.pop() {
+            match step {
+                Stringify::Node(node, indent) => {
+                    node.stringify_head(f, indent)?;
+                    match &node.children {
+                        Some(children) => {
+                            write!(f, "{{")?;
+                            match children.format() {
+                                Some(KdlDocumentFormat { leading, .. }) => write!(f, "{leading}")?,
+                                None => writeln!(f)?,
+                            }
+                            stack.push(Stringify::Close(node, indent));
+                            for child in children.nodes().iter().rev() {
+                                stack.push(Stringify::Node(child, indent + 4));
+                            }
+                        }
+                        None => node.stringify_tail(f)?,
+                    }
+                }
+                Stringify::Close(node, indent) => {
+                    match node.children.as_ref().and_then(|c| c.format()) {
+                        Some(KdlDocumentFormat { trailing, .. }) => write!(f, "{trailing}")?,
+                        None => write_indent(f, indent)?,
+                    }
+                    write!(f, "}}")?;
+                    node.stringify_tail(f)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes everything up to, but not including, the `{` of this node's
+    /// children block, if it has one.
+    fn stringify_head(&self, f: &mut std::fmt::Formatter<'_>, indent: usize) -> std::fmt::Result {
         if let Some(KdlNodeFormat { leading, .. }) = self.format() {
             write!(f, "{}", leading)?;
         } else {
-            write!(f, "{:indent$}", "", indent = indent)?;
+            write_indent(f, indent)?;
         }
         if let Some(ty) = &self.ty {
             write!(f, "({})", ty)?;
@@ -581,7 +1575,7 @@ impl KdlNode {
             write!(f, "{}", entry)?;
             space_before_children = entry.format().is_none();
         }
-        if let Some(children) = &self.children {
+        if self.children.is_some() {
             if let Some(KdlNodeFormat {
                 before_children, ..
             }) = self.format()
@@ -590,16 +1584,13 @@ impl KdlNode {
             } else if space_before_children {
                 write!(f, " ")?;
             }
-            write!(f, "{{")?;
-            if children.format().is_none() {
-                writeln!(f)?;
-            }
-            children.stringify(f, indent + 4)?;
-            if children.format().is_none() {
-                write!(f, "{:indent$}", "", indent = indent)?;
-            }
-            write!(f, "}}")?;
         }
+        Ok(())
+    }
+
+    /// Writes everything after this node's children block, or after its
+    /// entries if it has none.
+    fn stringify_tail(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if let Some(KdlNodeFormat {
             before_terminator,
             terminator,
@@ -608,30 +1599,53 @@ impl KdlNode {
         }) = self.format()
         {
             write!(f, "{before_terminator}{terminator}{trailing}")?;
+        } else {
+            writeln!(f)?;
         }
         Ok(())
     }
 }
 
+/// Writes `indent` spaces. Unlike a format width, this works for any
+/// indentation, however deep.
+fn write_indent(f: &mut std::fmt::Formatter<'_>, indent: usize) -> std::fmt::Result {
+    const SPACES: &str = "                                                                ";
+    let mut left = indent;
+    while left > 0 {
+        let len = left.min(SPACES.len());
+        f.write_str(&SPACES[..len])?;
+        left -= len;
+    }
+    Ok(())
+}
+
+/// A step in [`KdlNode::stringify`]'s work: writing out a node at some
+/// indentation, or closing its children block once they've all been written.
+enum Stringify<'a> {
+    Node(&'a KdlNode, usize),
+    Close(&'a KdlNode, usize),
+}
+
 /// Formatting details for [`KdlNode`].
 #[derive(Debug, Clone, Default, Hash, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct KdlNodeFormat {
     /// Whitespace and comments preceding the node itself.
-    pub leading: String,
+    pub leading: KdlTrivia,
     /// Whitespace and comments between the opening `(` of a type annotation and the actual annotation name.
-    pub before_ty_name: String,
+    pub before_ty_name: KdlTrivia,
     /// Whitespace and comments between the annotation name and the closing `)`.
-    pub after_ty_name: String,
+    pub after_ty_name: KdlTrivia,
     /// Whitespace and comments after a node's type annotation.
-    pub after_ty: String,
+    pub after_ty: KdlTrivia,
     /// Whitespace and comments preceding the node's children block.
-    pub before_children: String,
+    pub before_children: KdlTrivia,
     /// Whitespace and comments right before the node's terminator.
-    pub before_terminator: String,
+    pub before_terminator: KdlTrivia,
     /// The terminator for the node.
-    pub terminator: String,
+    pub terminator: KdlTrivia,
     /// Whitespace and comments following the node itself, after the terminator.
-    pub trailing: String,
+    pub trailing: KdlTrivia,
 }
 
 #[cfg(test)]
@@ -653,6 +1667,90 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn typed_props() -> miette::Result<()> {
+        let node: KdlNode =
+            r#"node "arg" s="one" s="two" big=0x1_0000_0000_0000_0000 n=-3 f=1.0 b=#false"#
+                .parse()?;
+        assert_eq!(node.prop_str("s"), Some("two"));
+        assert_eq!(node.prop_i64("n"), Some(-3));
+        assert_eq!(node.prop_i64("big"), None);
+        assert_eq!(node.prop_i64("f"), None);
+        assert_eq!(node.prop_bool("b"), Some(false));
+        assert_eq!(node.prop_bool("s"), None);
+        // Arguments aren't properties.
+        assert_eq!(node.prop_str("0"), None);
+        assert_eq!(node.prop_str("arg"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn child_access() -> miette::Result<()> {
+        let mut node: KdlNode = "parent {\n    a\n    b\n    c\n}".parse()?;
+        let names = |iter: &mut dyn Iterator<Item = &KdlNode>| {
+            iter.map(|child| child.name().value().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&mut node.children_iter()), ["a", "b", "c"]);
+        assert_eq!(names(&mut node.children_iter_rev()), ["c", "b", "a"]);
+        assert_eq!(node.child(1).map(|child| child.name().value()), Some("b"));
+        assert!(node.child(3).is_none());
+        assert_eq!(node.child_range(1..).map(<[_]>::len), Some(2));
+        assert_eq!(node.child_range(..=2).map(<[_]>::len), Some(3));
+        assert!(node.child_range(2..4).is_none());
+
+        node.child_mut(2).unwrap().push(1);
+        assert_eq!(node.to_string(), "parent {\n    a\n    b\n    c 1\n}");
+        assert!(node.child_mut(3).is_none());
+
+        let mut childless: KdlNode = "leaf 1".parse()?;
+        assert_eq!(childless.children_iter().count(), 0);
+        assert_eq!(childless.children_iter_rev().count(), 0);
+        assert!(childless.child(0).is_none());
+        assert!(childless.child_mut(0).is_none());
+        assert!(childless.child_range(..).is_none());
+        Ok(())
+    }
+
+    #[cfg(feature = "user-data")]
+    #[test]
+    fn user_data() -> miette::Result<()> {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+
+        let hash = |node: &KdlNode| {
+            let mut hasher = DefaultHasher::new();
+            node.hash(&mut hasher);
+            hasher.finish()
+        };
+        let plain: KdlNode = "node 1 key=#true".parse()?;
+        let mut node = plain.clone();
+        assert!(node.user_data().is_none());
+
+        node.set_user_data(String::from("dirty"));
+        assert_eq!(
+            node.user_data()
+                .and_then(|data| data.downcast_ref::<String>()),
+            Some(&"dirty".to_string())
+        );
+        assert_eq!(node, plain);
+        assert_eq!(hash(&node), hash(&plain));
+        assert_eq!(node.to_string(), plain.to_string());
+
+        if let Some(data) = node.user_data_mut() {
+            data.downcast_mut::<String>().unwrap().push('!');
+        }
+        assert_eq!(
+            node.user_data()
+                .and_then(|data| data.downcast_ref::<String>()),
+            Some(&"dirty!".to_string())
+        );
+        assert!(node.clone().user_data().is_none());
+        assert!(node.user_data_mut().take().is_some());
+        assert!(node.user_data().is_none());
+        Ok(())
+    }
+
     #[test]
     fn parsing() -> miette::Result<()> {
         let node: KdlNode = "\n\t  (\"ty\")\"node\" 0xDEADbeef;\n".parse()?;
@@ -684,6 +1782,181 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn repr_len() -> miette::Result<()> {
+        for input in [
+            "foo",
+            "(t)foo 1 \"two\" three=#true { bar; }\n",
+            "  foo /* c */ 0x10 \\\n  x=1.5e3 // hi\n",
+            "ノード \"ü\" {\n    child #\"raw\"#\n}",
+        ] {
+            let node: KdlNode = input.parse()?;
+            assert_eq!(node.repr_len(), node.to_string().len());
+            assert_eq!(node.repr_len(), input.len());
+        }
+        let mut node = KdlNode::new("built");
+        node.push(("prop", f64::INFINITY));
+        node.ensure_children()
+            .nodes_mut()
+            .push(KdlNode::new("child"));
+        assert_eq!(node.repr_len(), node.to_string().len());
+        Ok(())
+    }
+
+    #[test]
+    fn lint_entry_order() -> miette::Result<()> {
+        let node: KdlNode = "  (t)node 1 x=2 /* c */ (u)3 y=4".parse()?;
+        let diag = node
+            .lint_entry_order(EntryOrderRule::ArgumentsFirst)
+            .expect("argument after property");
+        assert_eq!(
+            &diag.input[diag.span.offset()..diag.span.offset() + diag.span.len()],
+            "(u)3"
+        );
+        assert_eq!(diag.severity, miette::Severity::Warning);
+        assert_eq!(
+            diag.to_string(),
+            "Entry is out of order: arguments must come before properties."
+        );
+        let diag = node
+            .lint_entry_order(EntryOrderRule::PropertiesFirst)
+            .expect("property after argument");
+        assert_eq!(
+            &diag.input[diag.span.offset()..diag.span.offset() + diag.span.len()],
+            "x=2"
+        );
+
+        assert!("node 1 2 x=1 y=2"
+            .parse::<KdlNode>()?
+            .lint_entry_order(EntryOrderRule::ArgumentsFirst)
+            .is_none());
+        assert!("node x=1 1"
+            .parse::<KdlNode>()?
+            .lint_entry_order(EntryOrderRule::PropertiesFirst)
+            .is_none());
+
+        let mut built = KdlNode::new("built");
+        built.push(("key", 1));
+        built.push("arg");
+        let diag = built
+            .lint_entry_order(EntryOrderRule::ArgumentsFirst)
+            .unwrap();
+        assert_eq!(
+            &diag.input[diag.span.offset()..diag.span.offset() + diag.span.len()],
+            "arg"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn collapse_esclines() -> miette::Result<()> {
+        let mut node: KdlNode =
+            "foo 1 \\\n    2\\   // two\n  /- \"\\\\\" 3 \\ // a */ b\n  4 \\\n{ bar; }\n"
+                .parse()?;
+        node.collapse_esclines();
+        assert_eq!(
+            node.to_string(),
+            "foo 1 2 /* two */ /- \"\\\\\" 3 \\ // a */ b\n  4 { bar; }\n"
+        );
+        let mut node: KdlNode = "foo 1 2".parse()?;
+        node.collapse_esclines();
+        assert_eq!(node.to_string(), "foo 1 2");
+        Ok(())
+    }
+
+    #[test]
+    fn expand_to_esclines() -> miette::Result<()> {
+        let mut doc: crate::KdlDocument =
+            "parent {\n    first\n    child \\\n  aaa bbb ccc=1 ddd\n}\n".parse()?;
+        let child = &mut doc.nodes_mut()[0]
+            .children_mut()
+            .as_mut()
+            .unwrap()
+            .nodes_mut()[1];
+        child.expand_to_esclines(20);
+        assert_eq!(
+            doc.to_string(),
+            "parent {\n    first\n    child aaa bbb \\\n        ccc=1 ddd\n}\n"
+        );
+        let reparsed: crate::KdlDocument = doc.to_string().parse()?;
+        assert_eq!(
+            reparsed.nodes()[0].children().unwrap().nodes()[1]
+                .entries()
+                .len(),
+            4
+        );
+
+        let mut node = KdlNode::new("node");
+        node.push("a long string value");
+        node.push(1);
+        node.expand_to_esclines(10);
+        assert_eq!(
+            node.to_string(),
+            "node \\\n    \"a long string value\" \\\n    1\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn trailing_comment() -> miette::Result<()> {
+        let node: KdlNode = "foo 1 // comment\n".parse()?;
+        assert_eq!(node.trailing_comment(), Some("// comment"));
+        let node: KdlNode = "foo 1 /* c */;".parse()?;
+        assert_eq!(node.trailing_comment(), Some("/* c */"));
+        let node: KdlNode = "foo 1;".parse()?;
+        assert_eq!(node.trailing_comment(), None);
+
+        let mut node: KdlNode = "foo 1 // old\n".parse()?;
+        node.set_trailing_comment("new");
+        assert_eq!(node.to_string(), "foo 1 // new\n");
+        node.set_trailing_comment("multi\nline");
+        assert_eq!(node.to_string(), "foo 1 /* multi\nline */\n");
+        node.clear_trailing_comment();
+        assert_eq!(node.to_string(), "foo 1\n");
+
+        let mut node: KdlNode = "foo 1 /* c */;".parse()?;
+        node.set_trailing_comment("d");
+        assert_eq!(node.to_string(), "foo 1 /* d */;");
+        assert_eq!(node.trailing_comment(), Some("/* d */"));
+
+        let mut node = KdlNode::new("foo");
+        node.push(1);
+        node.set_trailing_comment("built");
+        assert_eq!(node.to_string(), "foo 1 // built\n\n");
+
+        let mut doc: crate::KdlDocument = "a 1\nb 2;c 3\n".parse()?;
+        doc.nodes_mut()[0].set_trailing_comment("x");
+        doc.nodes_mut()[1].set_trailing_comment("y");
+        assert_eq!(doc.to_string(), "a 1 // x\nb 2 /* y */;c 3\n");
+        let reparsed: crate::KdlDocument = doc.to_string().parse()?;
+        assert_eq!(reparsed.nodes().len(), 3);
+
+        // Comment text can't close the comment early.
+        let mut doc: crate::KdlDocument = "a 1\nb 2;c 3\n".parse()?;
+        doc.nodes_mut()[0].set_trailing_comment("nested /* x */\nlines");
+        doc.nodes_mut()[1].set_trailing_comment("x */ y");
+        assert_eq!(
+            doc.to_string(),
+            "a 1 /* nested /* x */\nlines */\nb 2 // x */ y\nc 3\n"
+        );
+        let reparsed: crate::KdlDocument = doc.to_string().parse()?;
+        assert_eq!(reparsed.nodes().len(), 3);
+        assert_eq!(reparsed.nodes()[1].trailing_comment(), Some("// x */ y"));
+        let mut node: KdlNode = "a;".parse()?;
+        node.set_trailing_comment("/* open");
+        assert_eq!(node.to_string(), "a // /* open\n");
+        Ok(())
+    }
+
+    #[test]
+    fn unpaired_multiline_trailing_comment() -> miette::Result<()> {
+        let mut node: KdlNode = "a /* old */;".parse()?;
+        assert!(!node.set_trailing_comment("x */\ny"));
+        assert!(!node.set_trailing_comment("/* x\ny"));
+        assert_eq!(node.to_string(), "a /* old */;");
+        Ok(())
+    }
+
     #[test]
     fn indexing() {
         let mut node = KdlNode::new("foo");
@@ -753,4 +2026,96 @@ mod test {
         assert_eq!(node.entries().len(), 1, "key removal should succeed");
         node.remove(0); // should panic here
     }
+
+    #[test]
+    fn set_terminator_kind() -> miette::Result<()> {
+        let set = |input: &str, kind| -> miette::Result<String> {
+            let mut node: KdlNode = input.parse()?;
+            assert!(node.set_terminator_kind(kind));
+            assert_eq!(node.terminator_kind(), kind);
+            Ok(node.to_string())
+        };
+        assert_eq!(set("a 1\n", TerminatorKind::Semicolon)?, "a 1;");
+        assert_eq!(set("a 1;", TerminatorKind::Newline)?, "a 1\n");
+        assert_eq!(
+            set("a /* c */ // d\r\n", TerminatorKind::Semicolon)?,
+            "a /* c */; // d\r\n"
+        );
+        assert_eq!(set("a;  // d\r\n", TerminatorKind::Newline)?, "a  // d\r\n");
+        assert_eq!(set("a; \r\n", TerminatorKind::Newline)?, "a \r\n");
+
+        // A node can't tell whether it's the last one.
+        let mut node: KdlNode = "a 1;".parse()?;
+        assert!(!node.set_terminator_kind(TerminatorKind::Implicit));
+        assert_eq!(node.to_string(), "a 1;");
+
+        let mut node = KdlNode::new("a");
+        node.clear_format();
+        node.set_terminator_kind(TerminatorKind::Newline);
+        assert!(node.format().is_none());
+        node.set_terminator_kind(TerminatorKind::Semicolon);
+        assert_eq!(node.to_string(), "a;");
+        Ok(())
+    }
+
+    #[test]
+    fn display_deep_tree() {
+        const DEPTH: usize = 100_000;
+        let mut node = KdlNode::new("a");
+        for _ in 1..DEPTH {
+            let mut parent = KdlNode::new("a");
+            let children = parent.ensure_children();
+            children.set_format(KdlDocumentFormat::default());
+            children.nodes_mut().push(node);
+            node = parent;
+        }
+        let expected = "a{".repeat(DEPTH - 1) + "a\n" + &"}\n".repeat(DEPTH - 1);
+        assert!(node.to_string() == expected);
+    }
+
+    #[test]
+    fn display_deep_unformatted_tree() {
+        const DEPTH: usize = 2_000;
+        let unformatted = |name| {
+            let mut node = KdlNode::new(name);
+            node.clear_format();
+            node
+        };
+        let mut node = unformatted("a");
+        for _ in 1..DEPTH {
+            let mut parent = unformatted("a");
+            parent.ensure_children().nodes_mut().push(node);
+            node = parent;
+        }
+        let text = node.to_string();
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), DEPTH * 2 - 1);
+        assert_eq!(
+            lines[DEPTH - 1],
+            format!("{}a", " ".repeat((DEPTH - 1) * 4))
+        );
+        assert_eq!(lines[DEPTH], format!("{}}}", " ".repeat((DEPTH - 2) * 4)));
+
+        // Indentation past what a format width can hold.
+        struct Indented<'a>(&'a KdlNode, usize);
+        impl Display for Indented<'_> {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                self.0.stringify(f, self.1)
+            }
+        }
+        let indent = u16::MAX as usize + 10;
+        let mut small = unformatted("b");
+        small.ensure_children().nodes_mut().push(unformatted("c"));
+        let text = Indented(&small, indent).to_string();
+        let pad = " ".repeat(indent);
+        assert_eq!(text, format!("{pad}b {{\n{pad}    c\n{pad}}}\n"));
+
+        let mut children = node.children_mut().take();
+        while let Some(mut doc) = children {
+            children = doc
+                .nodes_mut()
+                .pop()
+                .and_then(|mut n| n.children_mut().take());
+        }
+    }
 }
@@ -0,0 +1,148 @@
+use crate::KdlValue;
+
+/// Options for tuning how KDL text is parsed, for use with
+/// [`KdlDocument::parse_with`](crate::KdlDocument::parse_with).
+///
+/// The defaults accept any input that's valid according to the spec, with no
+/// limits on its size or shape, apart from how deeply children blocks may be
+/// nested (see [`ParseOptions::max_depth`]). When parsing untrusted input,
+/// you'll likely want to set some more limits.
+///
+/// # Examples
+///
+/// ```rust
+/// use kdl::{KdlDocument, ParseOptions};
+///
+/// let options = ParseOptions {
+///     max_entries_per_node: Some(2),
+///     max_depth: Some(1),
+///     max_input_len: Some(1024),
+///     ..Default::default()
+/// };
+/// assert!(KdlDocument::parse_with("node 1 2 { child; }", &options).is_ok());
+/// assert!(KdlDocument::parse_with("node 1 2 3", &options).is_err());
+/// assert!(KdlDocument::parse_with("node { child { grandchild; }; }", &options).is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Maximum number of entries (arguments and properties) a single node
+    /// may have. Slashdashed entries don't count towards this limit. `None`
+    /// means unlimited.
+    pub max_entries_per_node: Option<usize>,
+
+    /// Whether to accept `#+inf` and `#+nan` as alternative spellings of
+    /// `#inf` and `#nan`, as emitted by some other KDL generators. These
+    /// aren't valid KDL, so they're rejected by default.
+    pub accept_signed_inf_nan: bool,
+
+    /// Whether to keep going after running into a broken node, in order to
+    /// report as many errors as possible at once, such as in an editor.
+    ///
+    /// When a node can't be parsed, its error is recorded, the rest of it is
+    /// skipped up to its node terminator, and parsing continues with the
+    /// next node, at any nesting depth. By default, parsing stops at the
+    /// first error that can't be recovered from within the node itself.
+    pub collect_all_diagnostics: bool,
+
+    /// Maximum number of diagnostics a [`KdlParseFailure`] may hold. Any
+    /// more are dropped, and
+    /// [`KdlParseFailure::diagnostics_truncated`] is set. `None` means
+    /// unlimited.
+    ///
+    /// [`KdlParseFailure`]: crate::KdlParseFailure
+    /// [`KdlParseFailure::diagnostics_truncated`]: crate::KdlParseFailure::diagnostics_truncated
+    pub max_diagnostics: Option<usize>,
+
+    /// Whether to stop parsing as soon as there are more errors than
+    /// [`Self::max_diagnostics`] allows, instead of silently parsing the rest
+    /// of the input. Stopping early is faster on badly broken input.
+    pub stop_at_max_diagnostics: bool,
+
+    /// Extra keywords to recognize on top of the spec's, such as for a
+    /// dialect built on KDL, along with the values they stand for. Keywords
+    /// are listed without their leading `#`, and have to match the whole
+    /// keyword exactly, so `("on".into(), KdlValue::Bool(true))` makes `#on`
+    /// parse as `#true`, but not `#On` or `#one`. Other unknown keywords are
+    /// still errors.
+    ///
+    /// Entries keep the keyword's original text as their value
+    /// representation, so documents that use these keywords still
+    /// round-trip.
+    ///
+    /// ```rust
+    /// # use kdl::{KdlDocument, KdlValue, ParseOptions};
+    /// let options = ParseOptions {
+    ///     custom_keywords: vec![
+    ///         ("on".into(), KdlValue::Bool(true)),
+    ///         ("off".into(), KdlValue::Bool(false)),
+    ///     ],
+    ///     ..Default::default()
+    /// };
+    /// let doc = KdlDocument::parse_with("light #on", &options).unwrap();
+    /// assert_eq!(doc.get_arg("light"), Some(&KdlValue::Bool(true)));
+    /// assert_eq!(doc.to_string(), "light #on");
+    /// assert!(KdlDocument::parse_with("light #dim", &options).is_err());
+    /// ```
+    pub custom_keywords: Vec<(String, KdlValue)>,
+
+    /// Maximum nesting depth of children blocks. Top-level nodes are at
+    /// depth 0, their children at depth 1, and so on, so `Some(0)` rejects
+    /// children blocks altogether. `None` means unlimited. Defaults to
+    /// `Some(`[`ParseOptions::DEFAULT_MAX_DEPTH`]`)`.
+    ///
+    /// The parser recurses once per level, so this is what keeps deeply
+    /// nested input like `a {a {a {...}}}` from overflowing the stack.
+    /// Raising it far past the default, or lifting it, needs a
+    /// correspondingly bigger stack.
+    pub max_depth: Option<usize>,
+
+    /// Maximum length of the input, in bytes. Longer input is rejected
+    /// before any of it is parsed. `None` means unlimited.
+    pub max_input_len: Option<usize>,
+
+    /// Whether to throw away formatting (whitespace, comments, and the
+    /// original representation of values and identifiers) instead of
+    /// keeping it for round-tripping. Parsed documents are then written out
+    /// as if they'd been built from scratch, and only their data is kept.
+    ///
+    /// The parser doesn't allocate any of the formatting to begin with, so
+    /// all the `format` fields and identifier representations are `None`,
+    /// saving most of the allocations a round-trippable parse would make.
+    pub discard_format: bool,
+
+    /// Whether numbers written with an explicit leading `+`, like `+5`, keep
+    /// it when their value is replaced through
+    /// [`KdlEntry::set_value`](crate::KdlEntry::set_value), as long as the
+    /// new value is positive. By default, the new value is written out the
+    /// same way as any other, without a sign.
+    pub keep_explicit_signs: bool,
+}
+
+impl ParseOptions {
+    /// How deeply children blocks may be nested by default, as set by
+    /// [`ParseOptions::max_depth`]. In optimized builds, this fits
+    /// comfortably in the 2 MiB of stack that spawned threads get by
+    /// default. Unoptimized builds use several times as much stack per level.
+    pub const DEFAULT_MAX_DEPTH: usize = 64;
+}
+
+/// What `ParseOptions::default()` returns, for parsers that don't take any
+/// options.
+pub(crate) static DEFAULT_PARSE_OPTIONS: ParseOptions = ParseOptions {
+    max_entries_per_node: None,
+    accept_signed_inf_nan: false,
+    collect_all_diagnostics: false,
+    max_diagnostics: None,
+    stop_at_max_diagnostics: false,
+    custom_keywords: Vec::new(),
+    max_depth: Some(ParseOptions::DEFAULT_MAX_DEPTH),
+    max_input_len: None,
+    discard_format: false,
+    keep_explicit_signs: false,
+};
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        DEFAULT_PARSE_OPTIONS.clone()
+    }
+}
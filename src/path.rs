@@ -0,0 +1,292 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::{path_parser, v2_parser, value, KdlEntry, KdlNode, KdlParseFailure, KdlValue};
+
+/// A path to a value in a document, like `server.listeners[2].port`.
+///
+/// Each `.`-separated segment names a node, and can pick between siblings
+/// with the same name using a zero-based index, so `listeners[2]` is the
+/// third `listeners` node. Names with dots, brackets, or whitespace in them
+/// can be quoted, as in `hosts."example.com".port`.
+///
+/// The last segment points to a property on the node before it, if there is
+/// one by that name. Otherwise, it points to the first argument of the
+/// child node it names. That way, `server.port` finds `8080` in both
+/// `server port=8080` and `server { port 8080; }`. A last segment with an
+/// index always points to a node.
+///
+/// # Examples
+///
+/// ```rust
+/// # use kdl::{KdlDocument, KdlPath, KdlValue};
+/// let mut doc: KdlDocument = r#"
+/// server {
+///     listeners port=80
+///     listeners port=443 { tls #true; }
+/// }
+/// "#
+/// .parse()
+/// .unwrap();
+///
+/// let port = KdlPath::parse("server.listeners[1].port").unwrap();
+/// assert_eq!(doc.get_path(&port), Some(&KdlValue::Integer(443)));
+/// assert!(doc.set_path(&port, 8443, false));
+///
+/// let tls = KdlPath::parse("server.listeners[1].tls").unwrap();
+/// assert_eq!(doc.get_path(&tls), Some(&KdlValue::Bool(true)));
+///
+/// let timeout = KdlPath::parse("server.limits.timeout").unwrap();
+/// assert!(!doc.set_path(&timeout, 30, false));
+/// assert!(doc.set_path(&timeout, 30, true));
+/// assert_eq!(doc.get_path(&timeout), Some(&KdlValue::Integer(30)));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KdlPath(pub(crate) Vec<KdlPathSegment>);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct KdlPathSegment {
+    pub(crate) name: String,
+    pub(crate) index: Option<usize>,
+}
+
+impl KdlPath {
+    /// Parses a path, like `server.listeners[2].port`.
+    ///
+    /// This is the same as using [`str::parse`].
+    pub fn parse(input: &str) -> Result<Self, KdlParseFailure> {
+        input.parse()
+    }
+
+    /// The entry this path points to, if there is one.
+    pub(crate) fn entry<'a>(&self, nodes: &'a [KdlNode]) -> Option<&'a KdlEntry> {
+        let (last, parents) = self.0.split_last().expect("Paths are never empty.");
+        let mut nodes = nodes;
+        let mut parent = None;
+        for segment in parents {
+            let node = segment.find(nodes)?;
+            nodes = node.children().map_or(&[][..], |children| children.nodes());
+            parent = Some(node);
+        }
+        if last.index.is_none() {
+            if let Some(entry) = parent.and_then(|parent| parent.entry(last.name.as_str())) {
+                return Some(entry);
+            }
+        }
+        last.find(nodes)?.entry(0)
+    }
+
+    /// Like [`Self::entry`], but mutable.
+    pub(crate) fn entry_mut<'a>(&self, nodes: &'a mut Vec<KdlNode>) -> Option<&'a mut KdlEntry> {
+        let node = self.parent(nodes, false)?;
+        self.last_entry(node, false, false)
+    }
+
+    /// Sets the value this path points to, creating any missing nodes along
+    /// the way if `create` is true. Returns `None` if it couldn't.
+    pub(crate) fn set(
+        &self,
+        nodes: &mut Vec<KdlNode>,
+        value: impl Into<KdlValue>,
+        create: bool,
+    ) -> Option<()> {
+        let node = self.parent(nodes, create)?;
+        self.last_entry(node, create, true)?.set_value(value);
+        Some(())
+    }
+
+    /// The node before the last segment, or `nodes` themselves for a
+    /// single-segment path.
+    fn parent<'a>(&self, nodes: &'a mut Vec<KdlNode>, create: bool) -> Option<Parent<'a>> {
+        let parents = &self.0[..self.0.len() - 1];
+        let mut parent = Parent::Nodes(nodes);
+        for segment in parents {
+            let nodes = parent.into_children(create)?;
+            parent = Parent::Node(segment.find_mut(nodes, create)?);
+        }
+        Some(parent)
+    }
+
+    /// The entry the last segment points to in `parent`. If `create` is
+    /// true, a missing node is appended, and if `fill` is true and the node
+    /// has no arguments, a `#null` one is inserted for the caller to set.
+    fn last_entry<'a>(
+        &self,
+        parent: Parent<'a>,
+        create: bool,
+        fill: bool,
+    ) -> Option<&'a mut KdlEntry> {
+        let last = self.0.last().expect("Paths are never empty.");
+        let parent = match parent {
+            Parent::Node(node)
+                if last.index.is_none() && node.entry(last.name.as_str()).is_some() =>
+            {
+                return node.entry_mut(last.name.as_str());
+            }
+            parent => parent,
+        };
+        let node = last.find_mut(parent.into_children(create)?, create)?;
+        if fill && node.entry(0).is_none() {
+            node.insert(0, KdlEntry::new(KdlValue::Null));
+        }
+        node.entry_mut(0)
+    }
+}
+
+/// Where [`KdlPath`] resolution is at: either the top-level nodes, or a
+/// node whose children are next.
+enum Parent<'a> {
+    Nodes(&'a mut Vec<KdlNode>),
+    Node(&'a mut KdlNode),
+}
+
+impl<'a> Parent<'a> {
+    fn into_children(self, create: bool) -> Option<&'a mut Vec<KdlNode>> {
+        match self {
+            Parent::Nodes(nodes) => Some(nodes),
+            Parent::Node(node) if create => Some(node.ensure_children().nodes_mut()),
+            Parent::Node(node) => Some(node.children_mut().as_mut()?.nodes_mut()),
+        }
+    }
+}
+
+impl KdlPathSegment {
+    fn find<'a>(&self, nodes: &'a [KdlNode]) -> Option<&'a KdlNode> {
+        nodes
+            .iter()
+            .filter(|node| node.name().value() == self.name)
+            .nth(self.index.unwrap_or(0))
+    }
+
+    /// Like [`Self::find`], but appends nodes until there are enough if
+    /// `create` is true.
+    fn find_mut<'a>(&self, nodes: &'a mut Vec<KdlNode>, create: bool) -> Option<&'a mut KdlNode> {
+        let index = self.index.unwrap_or(0);
+        if create {
+            let count = nodes
+                .iter()
+                .filter(|node| node.name().value() == self.name)
+                .count();
+            for _ in count..=index {
+                nodes.push(KdlNode::new(self.name.as_str()));
+            }
+        }
+        nodes
+            .iter_mut()
+            .filter(|node| node.name().value() == self.name)
+            .nth(index)
+    }
+}
+
+impl FromStr for KdlPath {
+    type Err = KdlParseFailure;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        v2_parser::try_parse(path_parser::path, s)
+    }
+}
+
+impl Display for KdlPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, segment) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ".")?;
+            }
+            let name = &segment.name;
+            if name.is_empty()
+                || name.contains(|c| c == '.' || v2_parser::is_disallowed_ident_char(c))
+            {
+                write!(f, "{}", value::quote_string(name))?;
+            } else {
+                write!(f, "{name}")?;
+            }
+            if let Some(index) = segment.index {
+                write!(f, "[{index}]")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::KdlDocument;
+
+    use super::*;
+
+    #[test]
+    fn parsing() -> miette::Result<()> {
+        let path = KdlPath::parse(r##"server.listeners[2]."a.b"[0].#"c d"#.port"##)?;
+        assert_eq!(
+            path.0
+                .iter()
+                .map(|s| (&s.name[..], s.index))
+                .collect::<Vec<_>>(),
+            [
+                ("server", None),
+                ("listeners", Some(2)),
+                ("a.b", Some(0)),
+                ("c d", None),
+                ("port", None)
+            ]
+        );
+        assert_eq!(
+            path.to_string(),
+            r#"server.listeners[2]."a.b"[0]."c d".port"#
+        );
+        assert_eq!(path.to_string().parse::<KdlPath>()?, path);
+
+        for (input, offset) in [
+            ("", 0),
+            ("a.", 2),
+            (".a", 0),
+            ("a..b", 2),
+            ("a[", 2),
+            ("a[x]", 2),
+            ("a[1", 3),
+            ("a b", 1),
+            ("#true", 0),
+        ] {
+            let err = KdlPath::parse(input).unwrap_err();
+            assert_eq!(err.diagnostics.len(), 1, "{input:?}");
+            assert_eq!(err.diagnostics[0].span.offset(), offset, "{input:?}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn get_and_set() -> miette::Result<()> {
+        let mut doc: KdlDocument = "a 1 x=2 { x 3; b; b 4 y=5 { y 6; } }\nc".parse()?;
+        let get = |doc: &KdlDocument, path: &str| doc.get_path(&path.parse().unwrap()).cloned();
+        assert_eq!(get(&doc, "a"), Some(KdlValue::Integer(1)));
+        assert_eq!(get(&doc, "a.x"), Some(KdlValue::Integer(2)));
+        assert_eq!(get(&doc, "a.x[0]"), Some(KdlValue::Integer(3)));
+        assert_eq!(get(&doc, "a.b"), None);
+        assert_eq!(get(&doc, "a.b[1]"), Some(KdlValue::Integer(4)));
+        assert_eq!(get(&doc, "a.b[1].y"), Some(KdlValue::Integer(5)));
+        assert_eq!(get(&doc, "a.b[2]"), None);
+        assert_eq!(get(&doc, "c.x"), None);
+        assert_eq!(get(&doc, "x"), None);
+
+        *doc.get_path_mut(&"a.b[1].y[0]".parse()?).unwrap() = 7.into();
+        assert_eq!(get(&doc, "a.b[1].y[0]"), Some(KdlValue::Integer(7)));
+        assert!(doc.get_path_mut(&"a.b".parse()?).is_none());
+
+        assert!(doc.set_path(&"a.x".parse()?, 0x10, false));
+        assert!(doc.set_path(&"a.b".parse()?, "set", false));
+        assert!(!doc.set_path(&"c.d".parse()?, 1, false));
+        assert!(!doc.set_path(&"a.b[3]".parse()?, 1, false));
+        assert_eq!(
+            doc.to_string(),
+            "a 1 x=16 { x 3; b set; b 4 y=5 { y 6; } }\nc"
+        );
+
+        assert!(doc.set_path(&"c.d.e".parse()?, 1, true));
+        assert!(doc.set_path(&"a.b[3]".parse()?, 2, true));
+        assert!(doc.set_path(&"f".parse()?, 3, true));
+        assert_eq!(get(&doc, "c.d.e"), Some(KdlValue::Integer(1)));
+        assert_eq!(get(&doc, "a.b[2]"), None);
+        assert_eq!(get(&doc, "a.b[3]"), Some(KdlValue::Integer(2)));
+        assert_eq!(get(&doc, "f"), Some(KdlValue::Integer(3)));
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+use winnow::{
+    ascii::digit1,
+    combinator::{opt, peek},
+    prelude::*,
+    stream::Location,
+    token::{one_of, take_while},
+};
+
+use crate::{
+    path::{KdlPath, KdlPathSegment},
+    v2_parser::{
+        cut, end_of_input, is_disallowed_ident_char, not_end_of_input, string, Input, PResult,
+    },
+    KdlValue,
+};
+
+static SEGMENT_HELP: &str = "Paths are node names separated by dots, each with an optional [index], as in server.listeners[2].port. Names with dots or spaces in them can be quoted.";
+
+/// `path := segment ('.' segment)*`
+pub(crate) fn path(input: &mut Input<'_>) -> PResult<KdlPath> {
+    not_end_of_input("path").parse_next(input)?;
+    let mut segments = vec![segment(input)?];
+    while opt('.').parse_next(input)?.is_some() {
+        segments.push(segment(input)?);
+    }
+    end_of_input("path").parse_next(input)?;
+    Ok(KdlPath(segments))
+}
+
+/// `segment := (quoted-string | raw-string | bare-name) ('[' integer ']')?`
+///
+/// `bare-name` is any run of identifier characters other than `.`.
+fn segment(input: &mut Input<'_>) -> PResult<KdlPathSegment> {
+    let start = input.location();
+    let name = if peek(opt(one_of(['"', '#']))).parse_next(input)?.is_some() {
+        match opt(string).parse_next(input)? {
+            Some(Some(KdlValue::String(name))) => name,
+            _ => {
+                return cut(
+                    input,
+                    start,
+                    "node name",
+                    "expected a string",
+                    Some(SEGMENT_HELP),
+                )
+            }
+        }
+    } else {
+        match opt(take_while(1.., |c| {
+            c != '.' && !is_disallowed_ident_char(c)
+        }))
+        .parse_next(input)?
+        {
+            Some(name) => name.to_string(),
+            None => {
+                return cut(
+                    input,
+                    start,
+                    "node name",
+                    "expected a node name",
+                    Some(SEGMENT_HELP),
+                )
+            }
+        }
+    };
+    let index = if opt('[').parse_next(input)?.is_some() {
+        let start = input.location();
+        let index = match opt(digit1.try_map(str::parse::<usize>)).parse_next(input)? {
+            Some(index) => index,
+            None => return cut(input, start, "index", "expected an index", None),
+        };
+        if opt(']').parse_next(input)?.is_none() {
+            let start = input.location();
+            return cut(
+                input,
+                start,
+                "closing ']' for this index",
+                "expected here",
+                None,
+            );
+        }
+        Some(index)
+    } else {
+        None
+    };
+    Ok(KdlPathSegment { name, index })
+}
@@ -1,18 +1,103 @@
-use std::{collections::VecDeque, str::FromStr, sync::Arc};
+use std::{cmp::Ordering, str::FromStr, sync::Arc};
 
-use crate::{query_parser::KdlQueryParser, KdlDiagnostic, KdlDocument, KdlNode, KdlValue};
+use crate::{query_parser, v2_parser, KdlNode, KdlParseFailure, KdlValue};
 
 /// A parsed KQL query. For details on the syntax, see the [KQL
 /// spec](https://github.com/kdl-org/kdl/blob/main/QUERY-SPEC.md).
+///
+/// A query is one or more selectors separated by `||`, and matches any node
+/// that any of them match. Each selector is a chain of filters, related by
+/// combinators:
+///
+/// * `a b`: a `b` node that's a descendant of an `a` node.
+/// * `a > b`: a `b` node that's a direct child of an `a` node.
+/// * `a + b`: a `b` node that comes right after a sibling `a` node.
+/// * `a ~ b`: a `b` node that comes after a sibling `a` node, anywhere.
+/// * `top()`: the root of whatever's being queried. `top() > a` only matches
+///   `a` nodes at the top level, and `top()` on its own matches all of them.
+///
+/// A filter is an optional type annotation matcher, like `(author)`, or `()`
+/// for any annotation, an optional node name, and any number of accessor
+/// matchers in square brackets:
+///
+/// * `[]`: any node.
+/// * `[val()]`, `[val(1)]`: nodes with a first, or second, argument.
+/// * `[prop(name)]`, or just `[name]`: nodes with a `name` property.
+/// * `[name()]`, `[tag()]`: the node's name, or its type annotation, which
+///   only makes sense with a comparison.
+///
+/// Accessors can be compared to a KDL value, as in `[name = "foo"]` or
+/// `[val() >= 0x10]`, using `=`, `!=`, `>`, `>=`, `<`, `<=`, or, for
+/// strings, `^=` (starts with), `$=` (ends with), and `*=` (contains).
+/// Values are compared as KDL values, so `0x10` and `16` are equal. Since
+/// identifiers can contain characters like `>` and `+`, combinators and
+/// operators may need whitespace around them to be recognized.
+///
+/// # Examples
+///
+/// ```rust
+/// # use kdl::{KdlDocument, KdlQuery};
+/// let doc: KdlDocument = r#"
+///     package name=foo {
+///         dependencies {
+///             bar version="1.0"
+///             (dev)baz version="2.0"
+///         }
+///     }
+/// "#
+/// .parse()
+/// .unwrap();
+/// let query: KdlQuery = r#"package[name = foo] dependencies > (dev)[]"#.parse().unwrap();
+/// let names = doc
+///     .query_all(&query)
+///     .unwrap()
+///     .map(|node| node.name().value())
+///     .collect::<Vec<_>>();
+/// assert_eq!(names, ["baz"]);
+/// ```
 #[derive(Debug, Clone, PartialEq)]
-pub struct KdlQuery(pub(crate) Vec<KdlQuerySelector>);
+pub struct KdlQuery(pub(crate) Arc<[KdlQuerySelector]>);
+
+impl KdlQuery {
+    /// Parses a query, so it can be run any number of times without being
+    /// parsed again.
+    ///
+    /// This is the same as using [`str::parse`]. Passing a `&KdlQuery` to
+    /// methods like [`KdlDocument::query_all`] only bumps a reference count,
+    /// and clones of a query share their selectors. Malformed queries fail
+    /// with diagnostics pointing into the query string.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::{KdlDocument, KdlQuery};
+    /// let query = KdlQuery::parse("dependencies > [version]").unwrap();
+    /// for input in ["dependencies { foo version=1 }", "dependencies { bar }"] {
+    ///     let doc: KdlDocument = input.parse().unwrap();
+    ///     let count = doc.query_all(&query).unwrap().count();
+    ///     assert!(count <= 1);
+    /// }
+    /// assert!(KdlQuery::parse("a[val(").is_err());
+    /// ```
+    ///
+    /// [`KdlDocument::query_all`]: crate::KdlDocument::query_all
+    pub fn parse(input: &str) -> Result<Self, KdlParseFailure> {
+        input.parse()
+    }
+
+    /// Whether this query matches the node at the end of `path`.
+    fn matches(&self, path: &[Level<'_>]) -> bool {
+        self.0.iter().any(|selector| {
+            !path.is_empty() && selector.matches(&selector.0, &path[..path.len() - 1], path.last())
+        })
+    }
+}
 
 impl FromStr for KdlQuery {
-    type Err = KdlDiagnostic;
+    type Err = KdlParseFailure;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let parser = KdlQueryParser::new(s);
-        parser.parse(crate::query_parser::query(&parser))
+        v2_parser::try_parse(query_parser::query, s)
     }
 }
 
@@ -20,148 +105,121 @@ impl FromStr for KdlQuery {
 pub trait IntoKdlQuery: IntoQuerySealed {}
 
 impl IntoKdlQuery for KdlQuery {}
+impl IntoKdlQuery for &KdlQuery {}
 impl IntoKdlQuery for String {}
-impl<'a> IntoKdlQuery for &'a str {}
-impl<'a> IntoKdlQuery for &'a String {}
+impl IntoKdlQuery for &str {}
+impl IntoKdlQuery for &String {}
 
 #[doc(hidden)]
 pub trait IntoQuerySealed {
-    fn into_query(self) -> Result<KdlQuery, KdlDiagnostic>;
+    fn into_query(self) -> Result<KdlQuery, KdlParseFailure>;
 }
 
 impl IntoQuerySealed for KdlQuery {
-    fn into_query(self) -> Result<KdlQuery, KdlDiagnostic> {
+    fn into_query(self) -> Result<KdlQuery, KdlParseFailure> {
         Ok(self)
     }
 }
 
+impl IntoQuerySealed for &KdlQuery {
+    fn into_query(self) -> Result<KdlQuery, KdlParseFailure> {
+        Ok(self.clone())
+    }
+}
+
 impl IntoQuerySealed for &str {
-    fn into_query(self) -> Result<KdlQuery, KdlDiagnostic> {
+    fn into_query(self) -> Result<KdlQuery, KdlParseFailure> {
         self.parse()
     }
 }
 
 impl IntoQuerySealed for String {
-    fn into_query(self) -> Result<KdlQuery, KdlDiagnostic> {
+    fn into_query(self) -> Result<KdlQuery, KdlParseFailure> {
         self.parse()
     }
 }
 
 impl IntoQuerySealed for &String {
-    fn into_query(self) -> Result<KdlQuery, KdlDiagnostic> {
+    fn into_query(self) -> Result<KdlQuery, KdlParseFailure> {
         self.parse()
     }
 }
 
+/// One node in a path from the root: the list of nodes it's in, and its
+/// index there.
+type Level<'a> = (&'a [KdlNode], usize);
+
+/// A chain of filters, from left to right.
 #[derive(Debug, Clone, PartialEq)]
-pub(crate) struct KdlQuerySelector(pub(crate) Vec<KdlQuerySelectorSegment>);
+pub(crate) struct KdlQuerySelector(pub(crate) Vec<KdlQuerySegment>);
 
 impl KdlQuerySelector {
-    fn matches(&self, crumb: Arc<Breadcrumb<'_>>, scope: Option<&KdlNode>) -> bool {
-        if self.0.is_empty() {
-            // I don't think this is possible, but just in case.
-            return false;
-        }
-
-        let mut segments = self.0.iter().rev();
-        let end = segments
-            .next()
-            .expect("This should've had at least one item.");
-
-        // When doing a query from a node, instead of a document, we have to
-        // skip matching on the node itself, unless the query is just
-        // `scope()`.
-        if let Some(scope) = &scope {
-            // We're in node-query mode.
-            if crumb.next.is_none() {
-                // We're almost definitely looking at the scope node itself,
-                // but just check. We'll do no further processing.
-                if end.is_scope() {
-                    return crumb.node == *scope;
-                } else {
-                    return false;
-                }
+    /// Whether the last of `segments` matches `node`, with the rest matching
+    /// its surroundings. `node` is `None` for the root, and `parents` are
+    /// its ancestors.
+    fn matches(
+        &self,
+        segments: &[KdlQuerySegment],
+        parents: &[Level<'_>],
+        node: Option<&Level<'_>>,
+    ) -> bool {
+        let (last, rest) = match segments.split_last() {
+            Some(split) => split,
+            None => return false,
+        };
+        let filter_matches = match (&last.filter, node) {
+            (KdlQueryFilter::Top, node) => node.is_none(),
+            (KdlQueryFilter::Matchers(matchers), Some((nodes, idx))) => {
+                matchers.iter().all(|matcher| matcher.matches(&nodes[*idx]))
             }
-        }
-
-        if !end.matcher.matches(crumb.node) {
-            // If the final segment doesn't even match the node, don't bother
-            // looking any further.
+            (KdlQueryFilter::Matchers(_), None) => false,
+        };
+        if !filter_matches {
             return false;
         }
-
-        let mut node = crumb.node;
-        let mut next = crumb.next.clone();
-        let mut parent_doc = crumb.parent_doc;
-        'segments: for segment in segments {
-            use KdlSegmentCombinator::*;
-            match segment.op.as_ref().expect("This should've had an op.") {
-                Child | Descendant => {
-                    while let Some(crumb) = next.clone() {
-                        if segment.matcher.matches(crumb.node) {
-                            continue 'segments;
-                        }
-
-                        // We only loop once if the op is `Child`. Otherwise,
-                        // we keep going up the tree!
-                        if segment.op == Some(Child) {
-                            break;
-                        }
-
-                        next = crumb.next.clone();
-                        if let Some(crumb) = &next {
-                            node = crumb.node;
-                        }
-                        parent_doc = crumb.parent_doc;
-                    }
-
-                    if segment.is_scope() {
-                        return next.map(|crumb| crumb.node) == scope;
-                    }
-
-                    return false;
+        let combinator = match &last.combinator {
+            Some(combinator) => combinator,
+            // This is the first segment. Anything that's not `top()` can be
+            // anywhere, while `top()` only matches the root anyway.
+            None => return true,
+        };
+        match combinator {
+            KdlQueryCombinator::Child => match parents.split_last() {
+                Some((parent, grandparents)) => self.matches(rest, grandparents, Some(parent)),
+                None => node.is_some() && self.matches(rest, &[], None),
+            },
+            KdlQueryCombinator::Descendant => {
+                node.is_some()
+                    && ((0..parents.len())
+                        .rev()
+                        .any(|i| self.matches(rest, &parents[..i], Some(&parents[i])))
+                        || self.matches(rest, &[], None))
+            }
+            KdlQueryCombinator::Neighbor => match node {
+                Some((nodes, idx)) if *idx > 0 => {
+                    self.matches(rest, parents, Some(&(nodes, idx - 1)))
                 }
-                Neighbor | Sibling => {
-                    if let Some(parent) = &parent_doc {
-                        for n in parent
-                            .nodes()
-                            .iter()
-                            .rev()
-                            .skip_while(|n| !std::ptr::eq(*n, node))
-                            .skip(1)
-                        {
-                            if segment.matcher.matches(n) {
-                                node = n;
-                                continue 'segments;
-                            }
-                            if segment.op == Some(Neighbor) {
-                                break;
-                            }
-                        }
-                    }
-                    return false;
+                _ => false,
+            },
+            KdlQueryCombinator::Sibling => match node {
+                Some((nodes, idx)) => {
+                    (0..*idx).any(|i| self.matches(rest, parents, Some(&(nodes, i))))
                 }
-            }
+                None => false,
+            },
         }
-
-        true
     }
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub(crate) struct KdlQuerySelectorSegment {
-    pub(crate) op: Option<KdlSegmentCombinator>,
-    pub(crate) matcher: KdlQueryMatcher,
+pub(crate) struct KdlQuerySegment {
+    /// How this segment relates to the previous one. `None` for the first.
+    pub(crate) combinator: Option<KdlQueryCombinator>,
+    pub(crate) filter: KdlQueryFilter,
 }
 
-impl KdlQuerySelectorSegment {
-    fn is_scope(&self) -> bool {
-        self.matcher.0.len() == 1 && self.matcher.0[0].accessor == KdlQueryMatcherAccessor::Scope
-    }
-}
-
-#[derive(Debug, Clone, PartialEq)]
-pub(crate) enum KdlSegmentCombinator {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum KdlQueryCombinator {
     Child,
     Descendant,
     Neighbor,
@@ -169,84 +227,70 @@ pub(crate) enum KdlSegmentCombinator {
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub(crate) struct KdlQueryMatcher(pub(crate) Vec<KdlQueryMatcherDetails>);
-
-impl KdlQueryMatcher {
-    pub(crate) fn matches(&self, node: &KdlNode) -> bool {
-        self.0.iter().all(|m| m.matches(node))
-    }
+pub(crate) enum KdlQueryFilter {
+    /// `top()`
+    Top,
+    /// All of these have to match. Empty for `[]`.
+    Matchers(Vec<KdlQueryMatcher>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub(crate) struct KdlQueryMatcherDetails {
-    pub(crate) accessor: KdlQueryMatcherAccessor,
-    pub(crate) op: KdlQueryAttributeOp,
-    pub(crate) value: Option<KdlValue>,
+pub(crate) enum KdlQueryMatcher {
+    /// A plain node name.
+    Name(String),
+    /// `(ty)`, or `()` for any type annotation.
+    Tag(Option<String>),
+    /// `[accessor]` or `[accessor op value]`.
+    Accessor(KdlQueryAccessor, Option<(KdlQueryOp, KdlValue)>),
 }
 
-impl KdlQueryMatcherDetails {
-    pub(crate) fn matches(&self, node: &KdlNode) -> bool {
-        use KdlQueryAttributeOp::*;
-        use KdlQueryMatcherAccessor::*;
-
-        match (&self.accessor, &self.op, &self.value) {
-            (Scope, _, _) => false,
-            (Annotation | Node, op, Some(KdlValue::String(s) | KdlValue::RawString(s))) => {
-                let lhs = match &self.accessor {
-                    Annotation => node.ty().map(|ty| ty.value()),
-                    Node => Some(node.name().value()),
-                    _ => unreachable!(),
-                };
-                let ss = Some(&s[..]);
-                match op {
-                    Equal => lhs == ss,
-                    NotEqual => lhs != ss,
-                    Gt => lhs > ss,
-                    Gte => lhs >= ss,
-                    Lt => lhs < ss,
-                    Lte => lhs <= ss,
-                    StartsWith => lhs.map(|lhs| lhs.starts_with(s)).unwrap_or(false),
-                    EndsWith => lhs.map(|lhs| lhs.ends_with(s)).unwrap_or(false),
-                    Contains => lhs.map(|lhs| lhs.contains(s)).unwrap_or(false),
-                }
-            }
-            (Annotation | Node, _op, Some(_)) => false,
-            // This is `()blah`.
-            (Annotation, _, None) => node.ty().is_some(),
-            // This is `[]`.
-            (Node, _, None) => true,
-            (Arg(_) | Prop(_), op, val @ Some(_)) => {
-                let val = val.as_ref();
-                let lhs = match &self.accessor {
-                    Arg(Some(idx)) => node.get(*idx),
-                    Arg(None) => node.get(0),
-                    Prop(name) => node.get(&name[..]),
-                    _ => unreachable!(),
-                };
-                match &op {
-                    Equal => lhs == val,
-                    NotEqual => lhs != val,
-                    Gt => lhs > val,
-                    Gte => lhs >= val,
-                    Lt => lhs < val,
-                    Lte => lhs <= val,
-                    StartsWith | EndsWith | Contains => {
-                        unreachable!("This should have been caught by the parser")
+impl KdlQueryMatcher {
+    fn matches(&self, node: &KdlNode) -> bool {
+        match self {
+            KdlQueryMatcher::Name(name) => node.name().value() == name,
+            KdlQueryMatcher::Tag(None) => node.ty().is_some(),
+            KdlQueryMatcher::Tag(Some(ty)) => node.ty().map(|ty| ty.value()) == Some(ty),
+            KdlQueryMatcher::Accessor(accessor, comparison) => {
+                let name;
+                let lhs = match accessor {
+                    KdlQueryAccessor::Val(idx) => node.get(*idx),
+                    KdlQueryAccessor::Prop(prop) => node.get(&prop[..]),
+                    KdlQueryAccessor::Name => {
+                        name = KdlValue::String(node.name().value().into());
+                        Some(&name)
+                    }
+                    KdlQueryAccessor::Tag => {
+                        name = KdlValue::String(match node.ty() {
+                            Some(ty) => ty.value().into(),
+                            None => return false,
+                        });
+                        Some(&name)
                     }
+                };
+                match (lhs, comparison) {
+                    (Some(lhs), Some((op, rhs))) => op.compare(lhs, rhs),
+                    (lhs, None) => lhs.is_some(),
+                    (None, Some(_)) => false,
                 }
             }
-            (Arg(_) | Prop(_), _op, None) => match &self.accessor {
-                Arg(Some(idx)) => node.get(*idx).is_some(),
-                Arg(None) => node.get(0).is_some(),
-                Prop(name) => node.get(&name[..]).is_some(),
-                _ => unreachable!(),
-            },
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub(crate) enum KdlQueryAttributeOp {
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum KdlQueryAccessor {
+    /// `val()`, or `val(n)`.
+    Val(usize),
+    /// `prop(name)`, or just `name`.
+    Prop(String),
+    /// `name()`
+    Name,
+    /// `tag()`
+    Tag,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum KdlQueryOp {
     Equal,
     NotEqual,
     Gt,
@@ -258,52 +302,116 @@ pub(crate) enum KdlQueryAttributeOp {
     Contains,
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub(crate) enum KdlQueryMatcherAccessor {
-    Scope,
-    Node,
-    Annotation,
-    Arg(Option<usize>),
-    Prop(String),
+impl KdlQueryOp {
+    /// Whether this operator only works on strings.
+    pub(crate) fn is_string_op(self) -> bool {
+        matches!(
+            self,
+            KdlQueryOp::StartsWith | KdlQueryOp::EndsWith | KdlQueryOp::Contains
+        )
+    }
+
+    /// Whether this operator only works on numbers.
+    pub(crate) fn is_number_op(self) -> bool {
+        matches!(
+            self,
+            KdlQueryOp::Gt | KdlQueryOp::Gte | KdlQueryOp::Lt | KdlQueryOp::Lte
+        )
+    }
+
+    fn compare(self, lhs: &KdlValue, rhs: &KdlValue) -> bool {
+        use KdlQueryOp::*;
+        match self {
+            Equal => values_eq(lhs, rhs),
+            NotEqual => !values_eq(lhs, rhs),
+            Gt | Gte | Lt | Lte => match numeric_cmp(lhs, rhs) {
+                Some(ordering) => match self {
+                    Gt => ordering == Ordering::Greater,
+                    Gte => ordering != Ordering::Less,
+                    Lt => ordering == Ordering::Less,
+                    _ => ordering != Ordering::Greater,
+                },
+                None => false,
+            },
+            StartsWith | EndsWith | Contains => match (lhs.as_string(), rhs.as_string()) {
+                (Some(lhs), Some(rhs)) => match self {
+                    StartsWith => lhs.starts_with(rhs),
+                    EndsWith => lhs.ends_with(rhs),
+                    _ => lhs.contains(rhs),
+                },
+                _ => false,
+            },
+        }
+    }
 }
-#[derive(Debug, Clone)]
-struct Breadcrumb<'a> {
-    node: &'a KdlNode,
-    parent_doc: Option<&'a KdlDocument>,
-    next: Option<Arc<Breadcrumb<'a>>>,
+
+/// Compares two numbers by their numeric value, whether they're integers or
+/// floats.
+fn numeric_cmp(lhs: &KdlValue, rhs: &KdlValue) -> Option<Ordering> {
+    match (lhs, rhs) {
+        (KdlValue::Integer(lhs), KdlValue::Integer(rhs)) => Some(lhs.cmp(rhs)),
+        (KdlValue::Integer(lhs), KdlValue::Float(rhs)) => (*lhs as f64).partial_cmp(rhs),
+        (KdlValue::Float(lhs), KdlValue::Integer(rhs)) => lhs.partial_cmp(&(*rhs as f64)),
+        (KdlValue::Float(lhs), KdlValue::Float(rhs)) => lhs.partial_cmp(rhs),
+        _ => None,
+    }
 }
 
-/// Iterator for results of a KDL query over a [`KdlDocument`].
+fn values_eq(lhs: &KdlValue, rhs: &KdlValue) -> bool {
+    match numeric_cmp(lhs, rhs) {
+        Some(ordering) => ordering == Ordering::Equal,
+        None => lhs == rhs,
+    }
+}
+
+/// Iterator over the results of a KDL query, in document order. Created by
+/// [`KdlDocument::query_all`](crate::KdlDocument::query_all) and
+/// [`KdlNode::query_all`].
 #[derive(Debug, Clone)]
 pub struct KdlQueryIterator<'a> {
-    scope: Option<&'a KdlNode>,
     query: KdlQuery,
-    q: VecDeque<Arc<Breadcrumb<'a>>>,
+    root: &'a [KdlNode],
+    /// The path to the node that was looked at last, from the root.
+    path: Vec<Level<'a>>,
+    started: bool,
 }
 
 impl<'a> KdlQueryIterator<'a> {
-    pub(crate) fn new(
-        scope: Option<&'a KdlNode>,
-        ctx_doc: Option<&'a KdlDocument>,
-        query: KdlQuery,
-    ) -> Self {
-        let mut q = VecDeque::new();
-        if let Some(scope) = scope {
-            q.push_back(Arc::new(Breadcrumb {
-                node: scope,
-                parent_doc: None,
-                next: None,
-            }));
-        } else if let Some(doc) = ctx_doc {
-            for node in doc.nodes() {
-                q.push_front(Arc::new(Breadcrumb {
-                    node,
-                    parent_doc: Some(doc),
-                    next: None,
-                }));
+    pub(crate) fn new(root: &'a [KdlNode], query: KdlQuery) -> Self {
+        Self {
+            query,
+            root,
+            path: Vec::new(),
+            started: false,
+        }
+    }
+
+    /// Moves on to the next node, depth-first, returning `false` once there
+    /// are no more.
+    fn advance(&mut self) -> bool {
+        if !self.started {
+            self.started = true;
+            if self.root.is_empty() {
+                return false;
+            }
+            self.path.push((self.root, 0));
+            return true;
+        }
+        if let Some((nodes, idx)) = self.path.last() {
+            if let Some(children) = nodes[*idx].children() {
+                if !children.nodes().is_empty() {
+                    self.path.push((children.nodes(), 0));
+                    return true;
+                }
+            }
+        }
+        while let Some((nodes, idx)) = self.path.pop() {
+            if idx + 1 < nodes.len() {
+                self.path.push((nodes, idx + 1));
+                return true;
             }
         }
-        Self { scope, query, q }
+        false
     }
 }
 
@@ -311,24 +419,306 @@ impl<'a> Iterator for KdlQueryIterator<'a> {
     type Item = &'a KdlNode;
 
     fn next(&mut self) -> Option<Self::Item> {
-        while let Some(crumb) = self.q.pop_back() {
-            if let Some(children) = crumb.node.children() {
-                for node in children.nodes().iter().rev() {
-                    self.q.push_back(Arc::new(Breadcrumb {
-                        node,
-                        parent_doc: Some(children),
-                        next: Some(crumb.clone()),
-                    }));
-                }
+        while self.advance() {
+            if self.query.matches(&self.path) {
+                return self.path.last().map(|(nodes, idx)| &nodes[*idx]);
             }
-            for selector in &self.query.0 {
-                if selector.matches(crumb.clone(), self.scope) {
-                    return Some(crumb.node);
-                }
+        }
+        None
+    }
+}
+
+/// Mutable counterpart of [`KdlQueryIterator`], created by
+/// [`KdlDocument::query_all_mut`](crate::KdlDocument::query_all_mut) and
+/// [`KdlNode::query_all_mut`].
+///
+/// A query can match both a node and some of its descendants, so this can't
+/// be an [`Iterator`], which would let both be borrowed mutably at once.
+/// Instead, each node returned by [`KdlQueryIterMut::next`] has to be let go
+/// of before asking for the next one.
+///
+/// Matches are found as the iterator goes, so changes to a returned node's
+/// children affect whether (and which of) them match afterwards.
+///
+/// # Examples
+///
+/// ```rust
+/// # use kdl::KdlDocument;
+/// let mut doc: KdlDocument = "deps {\n    a version=1\n    b version=2\n}".parse().unwrap();
+/// let mut matches = doc.query_all_mut("deps > [version]").unwrap();
+/// while let Some(node) = matches.next() {
+///     let version = node.get("version").and_then(|v| v.as_integer()).unwrap();
+///     node.insert("version", version + 1);
+/// }
+/// assert_eq!(doc.to_string(), "deps {\n    a version=2\n    b version=3\n}");
+/// ```
+#[derive(Debug)]
+pub struct KdlQueryIterMut<'a> {
+    query: KdlQuery,
+    root: &'a mut [KdlNode],
+    /// Child indices leading to the node that was looked at last.
+    path: Vec<usize>,
+    started: bool,
+}
+
+impl<'a> KdlQueryIterMut<'a> {
+    pub(crate) fn new(root: &'a mut [KdlNode], query: KdlQuery) -> Self {
+        Self {
+            query,
+            root,
+            path: Vec::new(),
+            started: false,
+        }
+    }
+
+    /// Gets the next matching node, in document order, if there are any
+    /// more.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<&mut KdlNode> {
+        while self.advance() {
+            if self.query.matches(&levels(self.root, &self.path)) {
+                return Some(node_at(self.root, &self.path));
             }
         }
+        None
+    }
 
-        // Otherwise, we're done! Just return None and the iterator's done.
+    /// Gets the first match, for as long as the original borrow lasts.
+    pub(crate) fn into_first(mut self) -> Option<&'a mut KdlNode> {
+        while self.advance() {
+            if self.query.matches(&levels(self.root, &self.path)) {
+                return Some(node_at(self.root, &self.path));
+            }
+        }
         None
     }
+
+    /// Moves on to the next node, depth-first, returning `false` once there
+    /// are no more.
+    fn advance(&mut self) -> bool {
+        if !self.started {
+            self.started = true;
+            if self.root.is_empty() {
+                return false;
+            }
+            self.path.push(0);
+            return true;
+        }
+        let levels = levels(self.root, &self.path);
+        if let Some((nodes, idx)) = levels.last() {
+            if nodes[*idx]
+                .children()
+                .map_or(false, |children| !children.nodes().is_empty())
+            {
+                self.path.push(0);
+                return true;
+            }
+        }
+        for (nodes, idx) in levels.into_iter().rev() {
+            self.path.pop();
+            if idx + 1 < nodes.len() {
+                self.path.push(idx + 1);
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// The node at the end of `path`, starting at `root`.
+fn node_at<'a>(root: &'a mut [KdlNode], path: &[usize]) -> &'a mut KdlNode {
+    let (last, parents) = path.split_last().expect("Paths are never empty.");
+    let mut nodes = root;
+    for idx in parents {
+        nodes = nodes[*idx]
+            .children_mut()
+            .as_mut()
+            .expect("Nodes on the path have children.")
+            .nodes_mut();
+    }
+    &mut nodes[*last]
+}
+
+/// The [`Level`]s along `path`, starting at `root`.
+fn levels<'a>(root: &'a [KdlNode], path: &[usize]) -> Vec<Level<'a>> {
+    let mut levels = Vec::with_capacity(path.len());
+    let mut nodes = root;
+    for idx in path {
+        levels.push((nodes, *idx));
+        nodes = nodes[*idx]
+            .children()
+            .map_or(&[][..], |children| children.nodes());
+    }
+    levels
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{KdlDocument, KdlErrorKind};
+
+    use super::*;
+
+    fn names<'a>(doc: &'a KdlDocument, query: &str) -> Vec<&'a str> {
+        doc.query_all(query)
+            .unwrap()
+            .map(|node| node.name().value())
+            .collect()
+    }
+
+    #[test]
+    fn selectors() -> miette::Result<()> {
+        let doc: KdlDocument = r#"
+            a {
+                b {
+                    c
+                    b
+                }
+                c
+            }
+            b { c; }
+            c
+            "#
+        .parse()?;
+        assert_eq!(names(&doc, "c"), ["c", "c", "c", "c"]);
+        assert_eq!(names(&doc, "b c"), ["c", "c"]);
+        assert_eq!(names(&doc, "a c"), ["c", "c"]);
+        assert_eq!(names(&doc, "a > c"), ["c"]);
+        assert_eq!(names(&doc, "a > b > b"), ["b"]);
+        assert_eq!(names(&doc, "top() > c"), ["c"]);
+        assert_eq!(names(&doc, "top() > b c"), ["c"]);
+        assert_eq!(names(&doc, "top()"), ["a", "b", "c"]);
+        assert_eq!(names(&doc, "top() > []"), ["a", "b", "c"]);
+        assert_eq!(names(&doc, "b + c"), ["c", "c"]);
+        assert_eq!(names(&doc, "a ~ c"), ["c"]);
+        assert_eq!(names(&doc, "a + c"), Vec::<&str>::new());
+        assert_eq!(names(&doc, "c || a"), ["a", "c", "c", "c", "c"]);
+        assert_eq!(names(&doc, "[]").len(), 8);
+        assert_eq!(names(&doc, "nope"), Vec::<&str>::new());
+        Ok(())
+    }
+
+    #[test]
+    fn matchers() -> miette::Result<()> {
+        let doc: KdlDocument = r#"
+            package name=foo version=16 {
+                (author)person "Alice" email="alice@example.com"
+                (maintainer)person "Bob"
+                person
+            }
+            package name=bar version=1.5
+            "#
+        .parse()?;
+        assert_eq!(names(&doc, "package[name = foo] > person").len(), 3);
+        assert_eq!(names(&doc, r#"package[name="bar"]"#), ["package"]);
+        assert_eq!(names(&doc, "[prop(version) = 0x10]"), ["package"]);
+        assert_eq!(names(&doc, "[version > 1]").len(), 2);
+        assert_eq!(names(&doc, "[version <= 1.5]").len(), 1);
+        assert_eq!(names(&doc, "[version >= 16.0]").len(), 1);
+        assert_eq!(names(&doc, "(author)").len(), 1);
+        assert_eq!(names(&doc, "()person").len(), 2);
+        assert_eq!(names(&doc, "person[val()]").len(), 2);
+        assert_eq!(names(&doc, "person[val(1)]").len(), 0);
+        assert_eq!(names(&doc, "[val() = Alice]").len(), 1);
+        assert_eq!(names(&doc, "[val() != Alice]").len(), 1);
+        assert_eq!(names(&doc, "[email]").len(), 1);
+        assert_eq!(names(&doc, "[email $= \"example.com\"]").len(), 1);
+        assert_eq!(names(&doc, "[name() ^= pack]").len(), 2);
+        assert_eq!(names(&doc, "[name() *= ers]").len(), 3);
+        assert_eq!(names(&doc, "[tag() = maintainer]").len(), 1);
+        assert_eq!(names(&doc, "[tag()]").len(), 2);
+        // Props and args are distinct.
+        assert_eq!(names(&doc, "[val() = foo]").len(), 0);
+        assert_eq!(names(&doc, "[Alice]").len(), 0);
+
+        assert_eq!(
+            doc.query("person[val()]")?.and_then(|node| node.get(0)),
+            Some(&KdlValue::String("Alice".into()))
+        );
+        assert!(doc.query("nope")?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn node_queries() -> miette::Result<()> {
+        let doc: KdlDocument = "a { b { c; }; c }\nc".parse()?;
+        let a = doc.get("a").unwrap();
+        let names = |query| {
+            a.query_all(query)
+                .unwrap()
+                .map(|node| node.name().value())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names("c"), ["c", "c"]);
+        assert_eq!(names("top() > c"), ["c"]);
+        assert_eq!(names("top()"), ["b", "c"]);
+        assert_eq!(a.query_get("b > c", 0)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn mutable_queries() -> miette::Result<()> {
+        let mut doc: KdlDocument = "a { b { c; }; c }\nc".parse()?;
+        let mut matches = doc.query_all_mut("c || b")?;
+        let mut count = 0;
+        while let Some(node) = matches.next() {
+            // Replacing children mid-walk only affects what's left to visit.
+            if node.name().value() == "b" {
+                node.set_children("c; c".parse::<KdlDocument>()?);
+            }
+            node.insert("n", count);
+            count += 1;
+        }
+        assert_eq!(count, 5);
+        assert_eq!(doc.to_string(), "a { b n=0 {c n=1; c n=2}; c n=3 }\nc n=4");
+
+        doc.query_mut("top() > c")?.unwrap().set_name("d");
+        assert_eq!(names(&doc, "top() > []"), ["a", "d"]);
+
+        let a = doc.get_mut("a").unwrap();
+        a.query_mut("b > c")?.unwrap().set_name("e");
+        assert!(a.query_mut("top() > e")?.is_none());
+        assert_eq!(a.query_all_mut("c")?.next().unwrap().name().value(), "c");
+        assert!(doc.query_mut("x")?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn precompiled() -> miette::Result<()> {
+        let query = KdlQuery::parse("b > c")?;
+        let copy = query.clone();
+        assert!(Arc::ptr_eq(&query.0, &copy.0));
+        for (input, count) in [("b { c; c }", 2), ("a { b { c; }; c }", 1), ("c", 0)] {
+            let doc: KdlDocument = input.parse()?;
+            assert_eq!(doc.query_all(&query)?.count(), count, "{input:?}");
+        }
+        let err = KdlQuery::parse("a >").unwrap_err();
+        assert_eq!(err.input.as_str(), "a >");
+        Ok(())
+    }
+
+    #[test]
+    fn errors() {
+        for (query, offset) in [
+            ("", 0),
+            ("a >", 3),
+            ("a[", 2),
+            ("a[val(", 6),
+            ("a[foo()]", 2),
+            ("a[foo ^= 1]", 9),
+            ("a[foo > bar]", 8),
+            ("a[foo =]", 7),
+            ("a(t)", 1),
+            ("[x]a", 3),
+            ("a > top()", 4),
+            ("a ||", 4),
+            ("a ] b", 2),
+        ] {
+            let err = query.parse::<KdlQuery>().unwrap_err();
+            assert_eq!(err.diagnostics.len(), 1, "{query:?}");
+            assert_eq!(err.diagnostics[0].span.offset(), offset, "{query:?}");
+        }
+        let err = "a[foo ^= 1]".parse::<KdlQuery>().unwrap_err();
+        assert_eq!(err.diagnostics[0].span, (9, 1).into());
+        assert!(matches!(err.diagnostics[0].kind, KdlErrorKind::Context(_)));
+    }
 }
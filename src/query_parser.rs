@@ -1,593 +1,283 @@
-use crate::nom_compat::many0;
-use crate::query::{
-    KdlQuery, KdlQueryAttributeOp, KdlQueryMatcher, KdlQueryMatcherAccessor,
-    KdlQueryMatcherDetails, KdlQuerySelector, KdlQuerySelectorSegment, KdlSegmentCombinator,
+use winnow::{
+    ascii::digit1,
+    combinator::{alt, eof, opt, peek, repeat},
+    prelude::*,
+    stream::{Location, Stream},
 };
-use crate::v1_parser::{value, KdlParser};
-use crate::{KdlDiagnostic, KdlErrorKind, KdlParseError, KdlValue};
-use miette::Severity;
-use nom::branch::alt;
-use nom::bytes::complete::tag;
-use nom::combinator::{all_consuming, cut, map, opt, recognize};
-use nom::error::context;
-use nom::multi::separated_list1;
-use nom::sequence::{delimited, preceded, terminated};
-use nom::{Finish, IResult, Offset, Parser};
 
-pub(crate) struct KdlQueryParser<'a>(KdlParser<'a>);
-
-impl<'a> KdlQueryParser<'a> {
-    pub(crate) fn new(full_input: &'a str) -> Self {
-        Self(KdlParser::new(full_input))
-    }
-
-    pub(crate) fn parse<T, P>(&self, parser: P) -> Result<T, KdlDiagnostic>
-    where
-        P: Parser<&'a str, T, KdlParseError<&'a str>>,
-    {
-        all_consuming(parser)(self.0.full_input)
-            .finish()
-            .map(|(_, arg)| arg)
-            .map_err(|e| {
-                let span_substr = &e.input[..e.len];
-                KdlDiagnostic {
-                    input: self.0.full_input.into(),
-                    span: self.0.span_from_substr(span_substr),
-                    help: if let Some(help) = e.help {
-                        Some(help)
-                    } else if e.kind.is_none() && e.context.is_none() {
-                        Some("The general syntax for queries is '(type)nodename[prop=value], anothernode, etc'. For more details, please see https://github.com/kdl-org/kdl/blob/main/QUERY-SPEC.md")
-                    } else {
-                        None
-                    },
-                    label: e.label,
-                    kind: if let Some(kind) = e.kind {
-                        kind
-                    } else if let Some(ctx) = e.context {
-                        KdlErrorKind::Context(ctx)
-                    } else {
-                        KdlErrorKind::Context("a valid KQL query")
-                    },
-                    severity: Severity::Error,
-                }
-            })
-    }
-}
-
-fn set_details<'a>(
-    mut err: nom::Err<KdlParseError<&'a str>>,
-    start: &'a str,
-    label: Option<&'static str>,
-    help: Option<&'static str>,
-) -> nom::Err<KdlParseError<&'a str>> {
-    match &mut err {
-        nom::Err::Error(e) | nom::Err::Failure(e) => {
-            if !e.touched {
-                e.len = start.offset(e.input);
-                e.input = start;
-                e.label = label;
-                e.help = help;
-                e.touched = true;
-            }
-        }
-        _ => {}
-    }
-    err
-}
-
-pub(crate) fn query<'a: 'b, 'b>(
-    kdl_parser: &'b KdlQueryParser<'a>,
-) -> impl FnMut(&'a str) -> IResult<&'a str, KdlQuery, KdlParseError<&'a str>> + 'b {
-    map(
-        separated_list1(
-            delimited(whitespace, tag(","), whitespace),
-            query_selector(kdl_parser),
-        ),
-        KdlQuery,
-    )
-}
+use crate::{
+    query::{
+        KdlQuery, KdlQueryAccessor, KdlQueryCombinator, KdlQueryFilter, KdlQueryMatcher,
+        KdlQueryOp, KdlQuerySegment, KdlQuerySelector,
+    },
+    v2_parser::{
+        bare_value, cut, end_of_input, identifier, newline, not_end_of_input, unicode_space, Input,
+        PResult,
+    },
+    KdlValue,
+};
 
-fn query_selector<'a: 'b, 'b>(
-    kdl_parser: &'b KdlQueryParser<'a>,
-) -> impl Fn(&'a str) -> IResult<&'a str, KdlQuerySelector, KdlParseError<&'a str>> + 'b {
-    move |input| {
-        let mut segments = Vec::new();
-        let mut is_scope = true;
-        let mut input = input;
-        loop {
-            let (inp, _) = whitespace(input)?;
-            input = inp;
-            let (inp, matchers) = node_matchers(kdl_parser, is_scope)(input)?;
-            input = inp;
-            let (inp, _) = whitespace(input)?;
-            input = inp;
-            let (inp, op) = opt(segment_combinator)(input)?;
-            input = inp;
-            let is_last = op.is_none();
-            segments.push(KdlQuerySelectorSegment {
-                op,
-                matcher: KdlQueryMatcher(matchers),
-            });
-            if is_last {
-                break;
-            }
-            is_scope = false;
+static SELECTOR_HELP: &str = "The syntax for node filters is (type)name[matcher], as in (dev)package[name = foo]. For more details, see https://github.com/kdl-org/kdl/blob/main/QUERY-SPEC.md";
+static ACCESSOR_HELP: &str =
+    "Accessors are val(), val(index), prop(name), name(), tag(), or a bare property name.";
+
+/// `query := q-ws* selector (q-ws* '||' q-ws* selector)* q-ws*`
+pub(crate) fn query(input: &mut Input<'_>) -> PResult<KdlQuery> {
+    qws0.parse_next(input)?;
+    not_end_of_input("query").parse_next(input)?;
+    let mut selectors = vec![selector.parse_next(input)?];
+    loop {
+        qws0.parse_next(input)?;
+        if opt("||").parse_next(input)?.is_none() {
+            break;
         }
-        let (input, _) = whitespace(input)?;
-        Ok((input, KdlQuerySelector(segments)))
+        qws0.parse_next(input)?;
+        selectors.push(selector.parse_next(input)?);
     }
+    end_of_input("query").parse_next(input)?;
+    Ok(KdlQuery(selectors.into()))
 }
 
-fn segment_combinator(input: &str) -> IResult<&str, KdlSegmentCombinator, KdlParseError<&str>> {
-    alt((
-        map(tag(">>"), |_| KdlSegmentCombinator::Descendant),
-        map(tag(">"), |_| KdlSegmentCombinator::Child),
-        map(tag("++"), |_| KdlSegmentCombinator::Sibling),
-        map(tag("+"), |_| KdlSegmentCombinator::Neighbor),
-    ))(input)
-}
-
-fn node_matchers<'a: 'b, 'b>(
-    kdl_parser: &'b KdlQueryParser<'a>,
-    is_scope: bool,
-) -> impl Fn(&'a str) -> IResult<&'a str, Vec<KdlQueryMatcherDetails>, KdlParseError<&'a str>> + 'b
-{
-    move |input| {
-        let mut matchers = Vec::new();
-
-        let (input, _) = whitespace(input)?;
-
-        let start = input;
-        let (input, scope) = opt(scope_accessor)(input)?;
-        if let Some(xsr) = scope {
-            if is_scope {
-                matchers.push(KdlQueryMatcherDetails {
-                    op: KdlQueryAttributeOp::Equal,
-                    accessor: xsr,
-                    value: None,
-                });
-                return Ok((input, matchers));
-            } else {
-                return Err(nom::Err::Error(KdlParseError {
-                    input: start,
-                    len: start.len() - input.len(),
-                    kind: None,
-                    label: Some("scope()"),
-                    help: Some("Make sure scope() precedes any other items within a (comma-separated) selector."),
-                    touched: false,
-                    context: Some("scope() to be the first item in this selector"),
-                }));
-            }
-        }
-
-        let (input, details) = opt(annotation_matcher(kdl_parser))(input)?;
-        if let Some(details) = details {
-            matchers.push(details);
-            let start = input;
-            let (input, typed) = opt(annotation_matcher(kdl_parser))(input)?;
-            if typed.is_some() {
-                return Err(nom::Err::Error(KdlParseError {
-                    input: start,
-                    len: start.len() - input.len(),
-                    kind: None,
-                    label: Some("type annotation"),
-                    help: Some("The syntax for node selectors is (type)node[attribute=value]."),
-                    touched: false,
-                    context: Some("only one type annotation per selector"),
-                }));
-            }
-        }
-
-        let (input, node) = opt(crate::v1_parser::identifier(&kdl_parser.0))(input)?;
-        if let Some(node) = node {
-            matchers.push(KdlQueryMatcherDetails {
-                op: KdlQueryAttributeOp::Equal,
-                value: Some(KdlValue::String(node.value().to_owned())),
-                accessor: KdlQueryMatcherAccessor::Node,
-            });
-        }
-
-        let start = input;
-        let (input, typed) = opt(annotation_matcher(kdl_parser))(input)?;
-        if typed.is_some() {
-            return Err(nom::Err::Error(KdlParseError {
-                input: start,
-                len: start.len() - input.len(),
-                kind: None,
-                label: Some("type annotation"),
-                help: Some("The syntax for node selectors is (type)node[attribute=value]."),
-                touched: false,
-                context: Some("type annotation to not be used after a node name"),
-            }));
-        }
-
-        let start = input;
-        let (input, mut attribute_matchers) = many0(attribute_matcher(kdl_parser))(input)?;
-        matchers.append(&mut attribute_matchers);
-
-        if matchers.is_empty() {
-            Err(nom::Err::Error(KdlParseError {
-                input: start,
-                len: 0,
-                kind: None,
-                label: Some("node matcher"),
-                help: Some("node matcher must not be empty"),
-                touched: false,
-                context: Some("a valid node matcher"),
-            }))
+/// `selector := filter (combinator filter)*`
+///
+/// `>`, `+`, and `~` are combinators only when followed by whitespace.
+/// Otherwise, they're part of an identifier, like they would be in a KDL
+/// document. Plain whitespace is the descendant combinator.
+fn selector(input: &mut Input<'_>) -> PResult<KdlQuerySelector> {
+    let mut segments = vec![KdlQuerySegment {
+        combinator: None,
+        filter: filter(input, true)?,
+    }];
+    loop {
+        let checkpoint = input.checkpoint();
+        let spaced = !qws0.take().parse_next(input)?.is_empty();
+        let combinator = if let Some(combinator) = opt(combinator).parse_next(input)? {
+            qws0.parse_next(input)?;
+            combinator
+        } else if spaced && peek(opt(alt((eof, "||")))).parse_next(input)?.is_none() {
+            KdlQueryCombinator::Descendant
         } else {
-            // Check for trailing type annotations.
-            let start = input;
-            let (end, typed) = opt(annotation_matcher(kdl_parser))(input)?;
-            if typed.is_some() {
-                return Err(nom::Err::Error(KdlParseError {
-                    input: start,
-                    len: start.len() - end.len(),
-                    kind: None,
-                    label: Some("type annotation"),
-                    help: Some("The syntax for node selectors is (type)node[attribute=value]."),
-                    touched: false,
-                    context: Some("type annotation to come before attribute matcher(s)"),
-                }));
-            }
-
-            // Check for trailing node name matcher.
-            let (end, ident) = opt(crate::v1_parser::identifier(&kdl_parser.0))(input)?;
-            if ident.is_some() {
-                return Err(nom::Err::Error(KdlParseError {
-                    input: start,
-                    len: start.len() - end.len(),
-                    kind: None,
-                    label: Some("node name"),
-                    help: Some("The syntax for node selectors is (type)node[attribute=value]."),
-                    touched: false,
-                    context: Some("node name to come before attribute matcher(s)"),
-                }));
-            }
-
-            Ok((input, matchers))
-        }
+            input.reset(&checkpoint);
+            break;
+        };
+        segments.push(KdlQuerySegment {
+            combinator: Some(combinator),
+            filter: filter(input, false)?,
+        });
     }
+    // A lone `top()` selects everything at the top level.
+    if segments.len() == 1 && segments[0].filter == KdlQueryFilter::Top {
+        segments.push(KdlQuerySegment {
+            combinator: Some(KdlQueryCombinator::Child),
+            filter: KdlQueryFilter::Matchers(Vec::new()),
+        });
+    }
+    Ok(KdlQuerySelector(segments))
 }
 
-fn attribute_matcher<'a: 'b, 'b>(
-    kdl_parser: &'b KdlQueryParser<'a>,
-) -> impl Fn(&'a str) -> IResult<&'a str, KdlQueryMatcherDetails, KdlParseError<&'a str>> + 'b {
-    move |input| {
-        let start = input;
-        let (input, _) = tag("[")(input)?;
-        let (input, _) = whitespace(input)?;
-        let (input, matcher) = attribute_matcher_inner(kdl_parser)(input)?;
-        let (input, _) = whitespace(input)?;
-        let (input, _) = context("a closing ']' for this attribute matcher", cut(tag("]")))(input)
-            .map_err(|e| set_details(e, start, Some("partial attribute matcher"), None))?;
-
-        Ok((input, matcher))
-    }
+/// `combinator := ('>' | '+' | '~') (q-ws | eof)`
+fn combinator(input: &mut Input<'_>) -> PResult<KdlQueryCombinator> {
+    let combinator = alt((
+        ">".value(KdlQueryCombinator::Child),
+        "+".value(KdlQueryCombinator::Neighbor),
+        "~".value(KdlQueryCombinator::Sibling),
+    ))
+    .parse_next(input)?;
+    peek(alt((qws, eof.void()))).parse_next(input)?;
+    Ok(combinator)
 }
 
-fn attribute_matcher_inner<'a: 'b, 'b>(
-    kdl_parser: &'b KdlQueryParser<'a>,
-) -> impl Fn(&'a str) -> IResult<&'a str, KdlQueryMatcherDetails, KdlParseError<&'a str>> + 'b {
-    move |input| {
-        let (input, xsr) = opt(accessor(kdl_parser))(input)?;
-        if let Some(xsr) = xsr {
-            let (input, _) = whitespace(input)?;
-            let (input, op) = opt(attribute_op)(input)?;
-            let (input, _) = whitespace(input)?;
-            if let Some(op) = op {
-                let prev = input;
-                let (input, val) = opt(crate::v1_parser::value)(input)?;
-                // Make sure it's a syntax error to try and use string
-                // operators with non-string arguments.
-                if let Some((_, value)) = val {
-                    if matches!(
-                        op,
-                        KdlQueryAttributeOp::StartsWith
-                            | KdlQueryAttributeOp::EndsWith
-                            | KdlQueryAttributeOp::Contains
-                    ) {
-                        if value.is_string() {
-                            Ok((
-                                input,
-                                KdlQueryMatcherDetails {
-                                    op,
-                                    value: Some(value),
-                                    accessor: xsr,
-                                },
-                            ))
-                        } else {
-                            Err(nom::Err::Failure(KdlParseError {
-                                input: prev,
-                                len: prev.len() - input.len(),
-                                kind: None,
-                                label: Some("non-string operator value"),
-                                help: Some("Only strings can be used as arguments for string-related operators (*=, ^=, $=)."),
-                                touched: false,
-                                context: Some("a string as an operator value"),
-                            }))
-                        }
-                    } else {
-                        Ok((
-                            input,
-                            KdlQueryMatcherDetails {
-                                op,
-                                value: Some(value),
-                                accessor: xsr,
-                            },
-                        ))
-                    }
-                } else {
-                    Err(nom::Err::Failure(KdlParseError {
-                        input: prev,
-                        len: 0,
-                        kind: None,
-                        label: Some("operator value"),
-                        help: Some("Only valid KDL values can be used on the right hand side of attribute matcher operators."),
-                        touched: false,
-                        context: Some("a valid operator argument"),
-                    }))
-                }
-            } else {
-                Ok((
-                    input,
-                    KdlQueryMatcherDetails {
-                        op: KdlQueryAttributeOp::Equal,
-                        value: None,
-                        accessor: xsr,
-                    },
-                ))
-            }
-        } else {
-            Ok((
+/// `filter := 'top()' | type-matcher? identifier? accessor-matcher*`, where
+/// at least one of the latter must be present.
+fn filter(input: &mut Input<'_>, first: bool) -> PResult<KdlQueryFilter> {
+    let start = input.location();
+    if opt("top()").parse_next(input)?.is_some() {
+        if !first {
+            return cut(
                 input,
-                KdlQueryMatcherDetails {
-                    op: KdlQueryAttributeOp::Equal,
-                    value: None,
-                    accessor: KdlQueryMatcherAccessor::Node,
-                },
-            ))
+                start,
+                "top() at the start of a selector",
+                "top() in the middle of a selector",
+                Some("top() stands for the root of the query, so it can only be at the start of a selector."),
+            );
         }
+        return Ok(KdlQueryFilter::Top);
     }
-}
-
-fn attribute_op(input: &str) -> IResult<&str, KdlQueryAttributeOp, KdlParseError<&str>> {
-    alt((
-        map(tag("="), |_| KdlQueryAttributeOp::Equal),
-        map(tag("!="), |_| KdlQueryAttributeOp::NotEqual),
-        map(tag(">"), |_| KdlQueryAttributeOp::Gt),
-        map(tag(">="), |_| KdlQueryAttributeOp::Gte),
-        map(tag("<"), |_| KdlQueryAttributeOp::Lt),
-        map(tag("<="), |_| KdlQueryAttributeOp::Lte),
-        map(tag("^="), |_| KdlQueryAttributeOp::StartsWith),
-        map(tag("$="), |_| KdlQueryAttributeOp::EndsWith),
-        map(tag("*="), |_| KdlQueryAttributeOp::Contains),
-    ))(input)
-}
-
-fn annotation_matcher<'a: 'b, 'b>(
-    kdl_parser: &'b KdlQueryParser<'a>,
-) -> impl Fn(&'a str) -> IResult<&'a str, KdlQueryMatcherDetails, KdlParseError<&'a str>> + 'b {
-    move |input| {
-        let start = input;
-        let (input, _) = tag("(")(input)?;
-        let (input, _) = whitespace(input)?;
-        let (input, ty) = opt(crate::v1_parser::identifier(&kdl_parser.0))(input)?;
-        let (input, _) = context("closing ')' for type annotation", cut(tag(")")))(input)
-            .map_err(|e| set_details(e, start, Some("annotation"), Some("annotations can only be KDL identifiers (including string identifiers), and can't have any space inside the parentheses.")))?;
-        Ok((
+    let mut matchers = Vec::new();
+    let mut empty = true;
+    if let Some(tag) = opt(tag_matcher).parse_next(input)? {
+        matchers.push(tag);
+        empty = false;
+    }
+    if let Some(name) = opt(identifier).parse_next(input)? {
+        matchers.push(KdlQueryMatcher::Name(name.value().into()));
+        empty = false;
+    }
+    while let Some(matcher) = opt(accessor_matcher).parse_next(input)? {
+        matchers.extend(matcher);
+        empty = false;
+    }
+    if empty {
+        return cut(
             input,
-            KdlQueryMatcherDetails {
-                op: KdlQueryAttributeOp::Equal,
-                value: ty.map(|ident| KdlValue::String(ident.value().to_owned())),
-                accessor: KdlQueryMatcherAccessor::Annotation,
-            },
-        ))
+            start,
+            "node filter",
+            "expected a node filter",
+            Some(SELECTOR_HELP),
+        );
     }
+    Ok(KdlQueryFilter::Matchers(matchers))
 }
 
-fn scope_accessor(input: &str) -> IResult<&str, KdlQueryMatcherAccessor, KdlParseError<&str>> {
-    let start = input;
-    let (input, _) = tag("scope(")(input)?;
-    let (input, _) = context(
-        "a valid scope accessor",
-        cut(preceded(whitespace, tag(")"))),
-    )(input)
-    .map_err(|e| set_details(e, start, Some("partial scope accessor"), None))?;
-    Ok((input, KdlQueryMatcherAccessor::Scope))
+/// `type-matcher := '(' q-ws* identifier? q-ws* ')'`
+fn tag_matcher(input: &mut Input<'_>) -> PResult<KdlQueryMatcher> {
+    "(".parse_next(input)?;
+    qws0.parse_next(input)?;
+    let ty = opt(identifier).parse_next(input)?;
+    qws0.parse_next(input)?;
+    closing(input, ")", "closing ')' for this type matcher")?;
+    Ok(KdlQueryMatcher::Tag(ty.map(|ty| ty.value().into())))
 }
 
-fn accessor<'a: 'b, 'b>(
-    kdl_parser: &'b KdlQueryParser<'a>,
-) -> impl Fn(&'a str) -> IResult<&'a str, KdlQueryMatcherAccessor, KdlParseError<&'a str>> + 'b {
-    move |input| {
-        let (input, accessor) = alt((
-            type_accessor,
-            arg_accessor,
-            prop_accessor(kdl_parser),
-            prop_name_accessor(kdl_parser),
-            bad_accessor(kdl_parser),
-        ))(input)?;
-
-        Ok((input, accessor))
+/// `accessor-matcher := '[' q-ws* (accessor q-ws* (operator q-ws* value q-ws*)?)? ']'`
+///
+/// Returns `None` for `[]`, which matches any node.
+fn accessor_matcher(input: &mut Input<'_>) -> PResult<Option<KdlQueryMatcher>> {
+    "[".parse_next(input)?;
+    qws0.parse_next(input)?;
+    if opt("]").parse_next(input)?.is_some() {
+        return Ok(None);
     }
-}
-
-fn type_accessor(input: &str) -> IResult<&str, KdlQueryMatcherAccessor, KdlParseError<&str>> {
-    let start = input;
-    let (input, _) = tag("type")(input)?;
-    let (input, _) = context(
-        "an opening '(' for a 'type()' accessor",
-        preceded(whitespace, tag("(")),
-    )(input)
-    .map_err(|e| set_details(e, start, Some("partial type accessor"), None))?;
-    let (input, _) = context(
-        "a closing ')' for this 'type()' accessor",
-        cut(preceded(whitespace, tag(")"))),
-    )(input)
-    .map_err(|e| {
-        set_details(
-            e,
-            start,
-            Some("partial type accessor"),
-            Some("type() accessors don't take any arguments. Use e.g. [type() = \"foo\"] instead."),
-        )
-    })?;
-    Ok((input, KdlQueryMatcherAccessor::Annotation))
-}
-
-fn arg_accessor(input: &str) -> IResult<&str, KdlQueryMatcherAccessor, KdlParseError<&str>> {
-    let (input, _) = tag("arg")(input)?;
-    let (input, arg) = parenthesized_arg(input)?;
-    if let Some(arg) = arg {
-        if let Some(index) = arg
-            .as_i64()
-            .and_then(|arg| -> Option<usize> { arg.try_into().ok() })
-        {
-            Ok((input, KdlQueryMatcherAccessor::Arg(Some(index))))
-        } else {
-            Err(nom::Err::Error(KdlParseError {
+    let accessor = accessor(input)?;
+    qws0.parse_next(input)?;
+    let comparison = if let Some(op) = opt(operator).parse_next(input)? {
+        qws0.parse_next(input)?;
+        let start = input.location();
+        let value = match opt(bare_value).parse_next(input)? {
+            Some(Some(value)) => value,
+            _ => {
+                return cut(
+                    input,
+                    start,
+                    "value to compare against",
+                    "expected a value",
+                    None,
+                )
+            }
+        };
+        if op.is_string_op() && !matches!(value, KdlValue::String(_)) {
+            return cut(
+                input,
+                start,
+                "string",
+                "not a string",
+                Some("Only strings can be used with the string operators ^=, $=, and *=."),
+            );
+        }
+        if op.is_number_op() && !matches!(value, KdlValue::Integer(_) | KdlValue::Float(_)) {
+            return cut(
                 input,
-                len: 0,
-                kind: None,
-                label: Some("arg accessor"),
-                help: Some("arg accessor must be an integer"),
-                touched: false,
-                context: Some("a valid arg accessor"),
-            }))
+                start,
+                "number",
+                "not a number",
+                Some("Only numbers can be used with the ordering operators >, >=, <, and <=."),
+            );
         }
+        qws0.parse_next(input)?;
+        Some((op, value))
     } else {
-        Ok((input, KdlQueryMatcherAccessor::Arg(None)))
-    }
+        None
+    };
+    closing(input, "]", "closing ']' for this matcher")?;
+    Ok(Some(KdlQueryMatcher::Accessor(accessor, comparison)))
 }
 
-fn prop_name_accessor<'a: 'b, 'b>(
-    kdl_parser: &'b KdlQueryParser<'a>,
-) -> impl Fn(&'a str) -> IResult<&'a str, KdlQueryMatcherAccessor, KdlParseError<&'a str>> + 'b {
-    move |input| {
-        let start = input;
-        let (input, prop_name) = crate::v1_parser::identifier(&kdl_parser.0)(input)?;
-        let (_, paren) = opt(preceded(whitespace, tag("(")))(input)?;
-        if paren.is_some() {
-            Err(nom::Err::Error(KdlParseError {
-                input: start,
-                len: 0,
-                kind: None,
-                label: Some("accessor"),
-                help: Some("accessor must be one of: type(), arg(), prop(), propname"),
-                touched: false,
-                context: Some("a valid accessor"),
-            }))
-        } else {
-            Ok((
+/// `accessor := 'val(' q-ws* integer? q-ws* ')' | 'prop(' q-ws* identifier q-ws* ')' | 'name()' | 'tag()' | identifier`
+fn accessor(input: &mut Input<'_>) -> PResult<KdlQueryAccessor> {
+    let start = input.location();
+    let name = match opt(identifier).parse_next(input)? {
+        Some(name) => name,
+        None => {
+            return cut(
                 input,
-                KdlQueryMatcherAccessor::Prop(prop_name.value().to_owned()),
-            ))
+                start,
+                "accessor",
+                "expected an accessor",
+                Some(ACCESSOR_HELP),
+            )
         }
+    };
+    if opt("(").parse_next(input)?.is_none() {
+        return Ok(KdlQueryAccessor::Prop(name.value().into()));
     }
+    qws0.parse_next(input)?;
+    let accessor = match name.value() {
+        "val" => KdlQueryAccessor::Val(
+            opt(digit1.try_map(str::parse::<usize>))
+                .parse_next(input)?
+                .unwrap_or(0),
+        ),
+        "prop" => match opt(identifier).parse_next(input)? {
+            Some(prop) => KdlQueryAccessor::Prop(prop.value().into()),
+            None => {
+                let start = input.location();
+                return cut(
+                    input,
+                    start,
+                    "property name",
+                    "expected a property name",
+                    None,
+                );
+            }
+        },
+        "name" => KdlQueryAccessor::Name,
+        "tag" => KdlQueryAccessor::Tag,
+        _ => {
+            let _: Option<&str> = opt(")").parse_next(input)?;
+            return cut(
+                input,
+                start,
+                "valid accessor",
+                "unknown accessor",
+                Some(ACCESSOR_HELP),
+            );
+        }
+    };
+    qws0.parse_next(input)?;
+    closing(input, ")", "closing ')' for this accessor")?;
+    Ok(accessor)
 }
 
-fn prop_accessor<'a: 'b, 'b>(
-    kdl_parser: &'b KdlQueryParser<'a>,
-) -> impl Fn(&'a str) -> IResult<&'a str, KdlQueryMatcherAccessor, KdlParseError<&'a str>> + 'b {
-    move |input| {
-        let (input, _) = tag("prop")(input)?;
-        let (input, val) = parenthesized_prop(kdl_parser)(input)?;
-        Ok((input, KdlQueryMatcherAccessor::Prop(val)))
-    }
-}
-
-fn parenthesized_arg(input: &str) -> IResult<&str, Option<KdlValue>, KdlParseError<&str>> {
-    let (input, _) = tag("(")(input)?;
-    let (input, maybe_value) = opt(value)(input)?;
-    let (input, _) = tag(")")(input)?;
-
-    if let Some((_, val)) = maybe_value {
-        Ok((input, Some(val)))
-    } else {
-        Ok((input, None))
-    }
+/// `operator := '=' | '!=' | '>' | '>=' | '<' | '<=' | '^=' | '$=' | '*='`
+fn operator(input: &mut Input<'_>) -> PResult<KdlQueryOp> {
+    alt((
+        "!=".value(KdlQueryOp::NotEqual),
+        ">=".value(KdlQueryOp::Gte),
+        "<=".value(KdlQueryOp::Lte),
+        "^=".value(KdlQueryOp::StartsWith),
+        "$=".value(KdlQueryOp::EndsWith),
+        "*=".value(KdlQueryOp::Contains),
+        "=".value(KdlQueryOp::Equal),
+        ">".value(KdlQueryOp::Gt),
+        "<".value(KdlQueryOp::Lt),
+    ))
+    .parse_next(input)
 }
 
-fn parenthesized_prop<'a: 'b, 'b>(
-    kdl_parser: &'b KdlQueryParser<'a>,
-) -> impl Fn(&'a str) -> IResult<&'a str, String, KdlParseError<&'a str>> + 'b {
-    move |input| {
-        let (input, _) = tag("(")(input)?;
-        let (input, prop) = crate::v1_parser::identifier(&kdl_parser.0)(input)?;
-        let (input, _) = tag(")")(input)?;
-        Ok((input, prop.value().to_owned()))
+/// Expects `close`, failing with a zero-width error right here otherwise.
+fn closing(input: &mut Input<'_>, close: &'static str, context: &'static str) -> PResult<()> {
+    if opt(close).parse_next(input)?.is_none() {
+        let start = input.location();
+        return cut(input, start, context, "expected here", None);
     }
+    Ok(())
 }
 
-fn bad_accessor<'a: 'b, 'b>(
-    kdl_parser: &'b KdlQueryParser<'a>,
-) -> impl Fn(&'a str) -> IResult<&'a str, KdlQueryMatcherAccessor, KdlParseError<&'a str>> + 'b {
-    move |input| {
-        let start = input;
-
-        let (input, scope) = opt(preceded(
-            tag("scope"),
-            preceded(
-                whitespace,
-                opt(terminated(tag("("), opt(preceded(whitespace, tag(")"))))),
-            ),
-        ))(input)?;
-
-        if scope.is_some() {
-            return Err(nom::Err::Failure(KdlParseError {
-                input: start,
-                len: start.len() - input.len(),
-                kind: None,
-                label: Some("incorrect scope() accessor"),
-                help: Some("Accessors must be one of: type(), arg(), prop(), propname"),
-                touched: false,
-                context: Some(
-                    "'scope()' to be the first item only at the top level of the query selector",
-                ),
-            }));
-        }
-
-        let (input, ident) = opt(terminated(
-            crate::v1_parser::identifier(&kdl_parser.0),
-            preceded(
-                whitespace,
-                terminated(tag("("), opt(preceded(whitespace, tag(")")))),
-            ),
-        ))(input)?;
-
-        if let Some(ident) = ident {
-            match ident.value() {
-                "type" | "arg" | "prop" | "val" => {}
-                _ => {
-                    return Err(nom::Err::Failure(KdlParseError {
-                        input: start,
-                        len: start.len() - input.len(),
-                        kind: None,
-                        label: Some("invalid attribute accessor"),
-                        help: Some("Accessors must be one of: type(), arg(), prop(), propname"),
-                        touched: false,
-                        context: Some("a valid attribute accessor"),
-                    }));
-                }
-            }
-        }
-
-        Err(nom::Err::Error(KdlParseError {
-            input: start,
-            len: 0,
-            kind: None,
-            label: Some("accessor"),
-            help: Some("accessor must be one of: type(), arg(), prop(), propname"),
-            touched: false,
-            context: Some("a valid accessor"),
-        }))
-    }
+/// `q-ws := unicode-space | newline`
+fn qws(input: &mut Input<'_>) -> PResult<()> {
+    alt((unicode_space, newline)).parse_next(input)
 }
 
-fn whitespace(input: &str) -> IResult<&str, &str, KdlParseError<&str>> {
-    recognize(many0(alt((
-        crate::v1_parser::unicode_space,
-        crate::v1_parser::newline,
-    ))))(input)
+fn qws0(input: &mut Input<'_>) -> PResult<()> {
+    repeat(0.., qws).parse_next(input)
 }
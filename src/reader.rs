@@ -0,0 +1,931 @@
+use std::{
+    io::{BufRead, BufReader, Read},
+    sync::Arc,
+};
+
+use miette::Severity;
+
+use crate::{
+    KdlDiagnostic, KdlDocument, KdlErrorKind, KdlNode, KdlParseFailure, ParseOptions, TokenKind,
+};
+
+/// Checks that everything read from `reader` is a valid KDL document, without
+/// holding all of it in memory at once.
+///
+/// The input is read line by line, and parsed one top-level node at a time,
+/// so memory use is bounded by the size of the largest top-level node
+/// (including its children) rather than the size of the whole input. Parsing
+/// stops at the first node that fails to parse.
+///
+/// Since the rest of the input is gone by then, the returned failure's
+/// `input` only holds the broken node, preceded by blank lines in place of
+/// everything before it. Its diagnostics point at the right lines and
+/// columns, but their byte offsets are only approximate. Failing to read
+/// from `reader`, or reading invalid UTF-8, is reported as a
+/// [`KdlErrorKind::Io`] diagnostic.
+///
+/// # Examples
+///
+/// ```rust
+/// let input = "package {\n    name kdl\n}\nversion 1\n";
+/// assert!(kdl::validate_reader(input.as_bytes()).is_ok());
+///
+/// let err = kdl::validate_reader("a 1\nb 2.\nc 3\n".as_bytes()).unwrap_err();
+/// assert_eq!(err.diagnostics.len(), 1);
+/// ```
+pub fn validate_reader<R: Read>(reader: R) -> Result<(), KdlParseFailure> {
+    let mut reader = BufReader::new(reader);
+    let mut scanner = NodeScanner::default();
+    let mut chunk = String::new();
+    let mut lines_before_chunk = 0;
+    let mut lines_in_chunk = 0;
+    loop {
+        let line_start = chunk.len();
+        let read = reader
+            .read_line(&mut chunk)
+            .map_err(|err| io_failure(&chunk, lines_before_chunk, err))?;
+        if read == 0 {
+            break;
+        }
+        lines_in_chunk += 1;
+        if scanner.scan_line(&chunk[line_start..]) {
+            validate_chunk(&chunk, lines_before_chunk)?;
+            chunk.clear();
+            lines_before_chunk += lines_in_chunk;
+            lines_in_chunk = 0;
+        }
+    }
+    validate_chunk(&chunk, lines_before_chunk)
+}
+
+/// Parses KDL input that arrives a piece at a time, such as from a socket,
+/// handing back each top-level node as soon as all of it has been fed in.
+///
+/// Like [`validate_reader`], the input is scanned line by line, and only
+/// the top-level node currently being fed in is held in memory. Concatenating
+/// the nodes handed back gives back the whole input, except for any
+/// whitespace and comments after the last node. Spans are relative to the
+/// start of the whole input.
+///
+/// If a node fails to parse, [`KdlStreamParser::feed`] or
+/// [`KdlStreamParser::finish`] returns the failure, with diagnostics pointing
+/// at the right lines and columns as described for [`validate_reader`]. The
+/// broken node is then skipped, and parsing picks up after it with the next
+/// call, starting with whatever was fed in after the broken node.
+///
+/// # Examples
+///
+/// ```rust
+/// use kdl::KdlStreamParser;
+///
+/// let mut parser = KdlStreamParser::new();
+/// assert!(parser.feed(b"package {\n    name k").unwrap().is_empty());
+/// assert!(parser.feed(b"dl\n}\nvers").unwrap()[0].name().value() == "package");
+/// let nodes = parser.finish().unwrap();
+/// assert_eq!(nodes[0].to_string(), "vers");
+/// # Ok::<(), kdl::KdlParseFailure>(())
+/// ```
+#[derive(Debug, Default)]
+pub struct KdlStreamParser {
+    options: ParseOptions,
+    scanner: NodeScanner,
+    /// Bytes fed in after the last complete line.
+    partial_line: Vec<u8>,
+    /// How much of `partial_line` is known not to contain a newline.
+    scanned: usize,
+    /// Complete lines that don't end in complete nodes yet.
+    chunk: String,
+    /// How many bytes and lines came before `chunk`.
+    bytes_before_chunk: usize,
+    lines_before_chunk: usize,
+    lines_in_chunk: usize,
+}
+
+impl KdlStreamParser {
+    /// Creates a new stream parser, which parses each node as if by
+    /// [`str::parse`].
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Creates a new stream parser, which parses each node with the given
+    /// [`ParseOptions`]. [`ParseOptions::max_input_len`] applies to each
+    /// top-level node separately.
+    pub fn with_options(options: ParseOptions) -> Self {
+        Self {
+            options,
+            ..Default::default()
+        }
+    }
+
+    /// Feeds in the next piece of input, returning the top-level nodes it
+    /// completed, if any.
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<Vec<KdlNode>, KdlParseFailure> {
+        self.partial_line.extend_from_slice(bytes);
+        let mut nodes = Vec::new();
+        let mut start = 0;
+        let result = loop {
+            match line_end(&self.partial_line, self.scanned.max(start)) {
+                Ok(end) => {
+                    let line = self.partial_line[start..end].to_vec();
+                    start = end;
+                    if let Err(failure) = self.feed_line(line, &mut nodes) {
+                        self.scanned = start;
+                        break Err(failure);
+                    }
+                }
+                Err(scanned) => {
+                    self.scanned = scanned;
+                    break Ok(nodes);
+                }
+            }
+        };
+        self.partial_line.drain(..start);
+        self.scanned -= start;
+        result
+    }
+
+    /// Signals the end of the input, returning the last top-level nodes,
+    /// if any.
+    pub fn finish(mut self) -> Result<Vec<KdlNode>, KdlParseFailure> {
+        let mut nodes = Vec::new();
+        let line = std::mem::take(&mut self.partial_line);
+        if !line.is_empty() {
+            self.feed_line(line, &mut nodes)?;
+        }
+        self.parse_chunk(&mut nodes)?;
+        Ok(nodes)
+    }
+
+    /// Like [`Self::finish`], but puts `nodes`, the ones handed back so far,
+    /// and the last ones together into a whole document, as if it had all
+    /// been parsed at once.
+    #[cfg(feature = "futures-io")]
+    fn finish_document(mut self, mut nodes: Vec<KdlNode>) -> Result<KdlDocument, KdlParseFailure> {
+        let line = std::mem::take(&mut self.partial_line);
+        if !line.is_empty() {
+            self.feed_line(line, &mut nodes)?;
+        }
+        self.parse_chunk(&mut nodes)?;
+        // All that's left now are the whitespace and comments after the last
+        // node, and the first node has the ones before it. Without any nodes,
+        // they're all leading instead.
+        let mut trailing = crate::KdlTrivia::from(std::mem::take(&mut self.chunk));
+        let leading = match nodes.first_mut() {
+            Some(node) => node
+                .format_mut()
+                .map(|fmt| std::mem::take(&mut fmt.leading))
+                .unwrap_or_default(),
+            None => std::mem::take(&mut trailing),
+        };
+        let mut doc = KdlDocument::new();
+        #[cfg(feature = "span")]
+        doc.set_span({
+            let start = leading.len();
+            let end = nodes
+                .last()
+                .map_or(start, |node| node.span().offset() + node.span().len());
+            start..end
+        });
+        if !self.options.discard_format {
+            doc.set_format(crate::KdlDocumentFormat { leading, trailing });
+        }
+        *doc.nodes_mut() = nodes;
+        Ok(doc)
+    }
+
+    fn feed_line(
+        &mut self,
+        line: Vec<u8>,
+        nodes: &mut Vec<KdlNode>,
+    ) -> Result<(), KdlParseFailure> {
+        let line = String::from_utf8(line).map_err(|err| {
+            let err = std::io::Error::new(std::io::ErrorKind::InvalidData, err);
+            io_failure(&self.chunk, self.lines_before_chunk, err)
+        })?;
+        self.chunk.push_str(&line);
+        self.lines_in_chunk += 1;
+        if self.scanner.scan_line(&line) {
+            self.parse_chunk(nodes)?;
+        }
+        Ok(())
+    }
+
+    /// Parses the nodes in `chunk`, adding them to `nodes`. A chunk with only
+    /// whitespace and comments in it is kept around to go in front of the
+    /// next node instead.
+    fn parse_chunk(&mut self, nodes: &mut Vec<KdlNode>) -> Result<(), KdlParseFailure> {
+        let parsed = parse_chunk(&self.chunk, self.lines_before_chunk, &self.options);
+        let doc = match parsed {
+            Ok(doc) if doc.nodes().is_empty() => return Ok(()),
+            Ok(doc) => Ok(doc),
+            Err(failure) => Err(failure),
+        };
+        let bytes_before = self.bytes_before_chunk;
+        self.bytes_before_chunk += self.chunk.len();
+        self.lines_before_chunk += self.lines_in_chunk;
+        self.lines_in_chunk = 0;
+        self.chunk.clear();
+        let mut doc = doc?;
+        // The chunk's own leading and trailing trivia go with its nodes, so
+        // that nothing's lost in between them.
+        let (leading, trailing) = match doc.format_mut() {
+            Some(fmt) => (
+                std::mem::take(&mut fmt.leading),
+                std::mem::take(&mut fmt.trailing),
+            ),
+            None => Default::default(),
+        };
+        let mut chunk_nodes = std::mem::take(doc.nodes_mut());
+        if let Some(fmt) = chunk_nodes.first_mut().and_then(|node| node.format_mut()) {
+            fmt.leading.to_mut().insert_str(0, &leading);
+        }
+        if let Some(fmt) = chunk_nodes.last_mut().and_then(|node| node.format_mut()) {
+            fmt.trailing.to_mut().push_str(&trailing);
+        }
+        #[cfg(feature = "span")]
+        for node in &mut chunk_nodes {
+            shift_spans(node, bytes_before as isize);
+        }
+        #[cfg(not(feature = "span"))]
+        let _ = bytes_before;
+        nodes.extend(chunk_nodes);
+        Ok(())
+    }
+}
+
+impl KdlDocument {
+    /// Reads all of `reader` and parses it as a document, as if by
+    /// [`str::parse`].
+    ///
+    /// Input starting with a UTF-16 byte order mark, as often saved by
+    /// Windows editors, is transcoded to UTF-8 first, without the BOM.
+    /// Anything else has to be UTF-8. The returned failure's `input` is the
+    /// transcoded text, so diagnostics point at the right characters even
+    /// though their byte offsets don't match the original input. Failing to
+    /// read from `reader`, or reading invalid text, is reported as a
+    /// [`KdlErrorKind::Io`] diagnostic.
+    ///
+    /// With the `encoding` feature, [`KdlDocument::from_reader_with_encoding`]
+    /// also handles other encodings.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlDocument;
+    /// let utf16 = "\u{FEFF}node 1\n"
+    ///     .encode_utf16()
+    ///     .flat_map(u16::to_le_bytes)
+    ///     .collect::<Vec<u8>>();
+    /// let doc = KdlDocument::from_reader(&utf16[..])?;
+    /// assert_eq!(doc.to_string(), "node 1\n");
+    /// # Ok::<(), kdl::KdlParseFailure>(())
+    /// ```
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<KdlDocument, KdlParseFailure> {
+        let mut bytes = Vec::new();
+        reader
+            .read_to_end(&mut bytes)
+            .map_err(|err| io_failure("", 0, err))?;
+        let text = match bytes.get(..2) {
+            Some([0xFF, 0xFE]) => decode_utf16(&bytes[2..], u16::from_le_bytes)?,
+            Some([0xFE, 0xFF]) => decode_utf16(&bytes[2..], u16::from_be_bytes)?,
+            _ => String::from_utf8(bytes).map_err(|err| {
+                let valid =
+                    String::from_utf8_lossy(&err.as_bytes()[..err.utf8_error().valid_up_to()])
+                        .into_owned();
+                let err = std::io::Error::new(std::io::ErrorKind::InvalidData, err.utf8_error());
+                io_failure(&valid, 0, err)
+            })?,
+        };
+        text.parse()
+    }
+
+    /// Like [`KdlDocument::from_reader`], but decodes input without a byte
+    /// order mark as `encoding` instead of requiring UTF-8. Input with a
+    /// UTF-8 or UTF-16 BOM is decoded according to that, regardless of
+    /// `encoding`. Malformed input is reported as a [`KdlErrorKind::Io`]
+    /// diagnostic rather than replaced.
+    ///
+    /// Only available with the `encoding` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlDocument;
+    /// let latin1 = b"caf\xe9 1\n";
+    /// let doc = KdlDocument::from_reader_with_encoding(&latin1[..], encoding_rs::WINDOWS_1252)?;
+    /// assert_eq!(doc.nodes()[0].name().value(), "caf\u{e9}");
+    /// # Ok::<(), kdl::KdlParseFailure>(())
+    /// ```
+    #[cfg(feature = "encoding")]
+    pub fn from_reader_with_encoding<R: Read>(
+        mut reader: R,
+        encoding: &'static encoding_rs::Encoding,
+    ) -> Result<KdlDocument, KdlParseFailure> {
+        let mut bytes = Vec::new();
+        reader
+            .read_to_end(&mut bytes)
+            .map_err(|err| io_failure("", 0, err))?;
+        let (text, encoding, malformed) = encoding.decode(&bytes);
+        if malformed {
+            let err = std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("input is not valid {}", encoding.name()),
+            );
+            return Err(io_failure(&text, 0, err));
+        }
+        text.parse()
+    }
+}
+
+/// Decodes UTF-16 `bytes`, two at a time with `unit`, into a string.
+fn decode_utf16(bytes: &[u8], unit: fn([u8; 2]) -> u16) -> Result<String, KdlParseFailure> {
+    let units = bytes.chunks(2).map(|pair| match pair {
+        [a, b] => unit([*a, *b]),
+        // A stray byte at the end can't be valid, whatever it is.
+        _ => 0xDC00,
+    });
+    let mut text = String::with_capacity(bytes.len() / 2);
+    for c in std::char::decode_utf16(units) {
+        match c {
+            Ok(c) => text.push(c),
+            Err(_) => {
+                let err = std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "input is not valid UTF-16",
+                );
+                return Err(io_failure(&text, 0, err));
+            }
+        }
+    }
+    Ok(text)
+}
+
+#[cfg(feature = "futures-io")]
+impl KdlDocument {
+    /// Reads and parses a document from an async `reader`, such as a file or
+    /// socket, without blocking the task while waiting for input.
+    ///
+    /// The input is parsed a top-level node at a time as it comes in, using
+    /// [`KdlStreamParser`], so there's no separate parsing pass once it's
+    /// all been read. Control goes back to the executor whenever `reader`
+    /// has to wait for more input. The result is the same as parsing all of
+    /// the input with [`str::parse`], except that diagnostics
+    /// only cover the top-level node that failed to parse, as described for
+    /// [`validate_reader`].
+    ///
+    /// This works with any [`futures_io::AsyncRead`]. Tokio's readers can be
+    /// adapted to it with `tokio_util::compat`.
+    ///
+    /// Only available with the `futures-io` feature.
+    pub async fn from_async_reader<R>(reader: R) -> Result<KdlDocument, KdlParseFailure>
+    where
+        R: futures_io::AsyncRead + Unpin,
+    {
+        Self::from_async_reader_with(reader, ParseOptions::default()).await
+    }
+
+    /// Like [`KdlDocument::from_async_reader`], but with the given
+    /// [`ParseOptions`]. [`ParseOptions::max_input_len`] applies to each
+    /// top-level node separately.
+    pub async fn from_async_reader_with<R>(
+        mut reader: R,
+        options: ParseOptions,
+    ) -> Result<KdlDocument, KdlParseFailure>
+    where
+        R: futures_io::AsyncRead + Unpin,
+    {
+        let mut parser = KdlStreamParser::with_options(options);
+        let mut nodes = Vec::new();
+        let mut buf = vec![0; 8 * 1024];
+        loop {
+            let read = ReadChunk {
+                reader: &mut reader,
+                buf: &mut buf,
+            }
+            .await;
+            match read {
+                Ok(0) => break,
+                Ok(read) => nodes.extend(parser.feed(&buf[..read])?),
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(io_failure(&parser.chunk, parser.lines_before_chunk, err)),
+            }
+        }
+        parser.finish_document(nodes)
+    }
+}
+
+/// Future for a single read from an async reader into `buf`.
+#[cfg(feature = "futures-io")]
+struct ReadChunk<'a, R> {
+    reader: &'a mut R,
+    buf: &'a mut [u8],
+}
+
+#[cfg(feature = "futures-io")]
+impl<R: futures_io::AsyncRead + Unpin> std::future::Future for ReadChunk<'_, R> {
+    type Output = std::io::Result<usize>;
+
+    fn poll(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Self::Output> {
+        let this = &mut *self;
+        std::pin::Pin::new(&mut *this.reader).poll_read(cx, this.buf)
+    }
+}
+
+/// Moves all the spans in `node` `by` bytes later, or earlier if negative.
+#[cfg(feature = "span")]
+pub(crate) fn shift_spans(node: &mut KdlNode, by: isize) {
+    fn shift(span: miette::SourceSpan, by: isize) -> miette::SourceSpan {
+        ((span.offset() as isize + by) as usize, span.len()).into()
+    }
+    node.set_span(shift(node.span(), by));
+    let name = node.name_mut();
+    name.set_span(shift(name.span(), by));
+    if let Some(ty) = node.ty_mut() {
+        ty.set_span(shift(ty.span(), by));
+    }
+    for entry in node.entries_mut() {
+        entry.set_span(shift(entry.span(), by));
+        for ident in entry.name.iter_mut().chain(entry.ty.iter_mut()) {
+            ident.set_span(shift(ident.span(), by));
+        }
+    }
+    if let Some(children) = node.children_mut() {
+        children.set_span(shift(children.span(), by));
+        for child in children.nodes_mut() {
+            shift_spans(child, by);
+        }
+    }
+}
+
+/// Parses a chunk of complete top-level nodes, which started after
+/// `lines_before` lines of input.
+fn validate_chunk(chunk: &str, lines_before: usize) -> Result<(), KdlParseFailure> {
+    if chunk.trim().is_empty() {
+        return Ok(());
+    }
+    parse_chunk(chunk, lines_before, &ParseOptions::default()).map(|_| ())
+}
+
+/// Parses a chunk of complete top-level nodes, which started after
+/// `lines_before` lines of input, pointing any diagnostics at the right
+/// lines.
+fn parse_chunk(
+    chunk: &str,
+    lines_before: usize,
+    options: &ParseOptions,
+) -> Result<KdlDocument, KdlParseFailure> {
+    KdlDocument::parse_with(chunk, options).map_err(|failure| {
+        let input = Arc::new(format!("{}{}", "\n".repeat(lines_before), chunk));
+        KdlParseFailure {
+            diagnostics: failure
+                .diagnostics
+                .into_iter()
+                .map(|diag| KdlDiagnostic {
+                    input: input.clone(),
+                    span: (diag.span.offset() + lines_before, diag.span.len()).into(),
+                    ..diag
+                })
+                .collect(),
+            input,
+            diagnostics_truncated: failure.diagnostics_truncated,
+        }
+    })
+}
+
+fn io_failure(chunk: &str, lines_before: usize, err: std::io::Error) -> KdlParseFailure {
+    let input = Arc::new(format!("{}{}", "\n".repeat(lines_before), chunk));
+    KdlParseFailure {
+        diagnostics: vec![KdlDiagnostic {
+            input: input.clone(),
+            span: (input.len(), 0).into(),
+            label: Some("while reading here"),
+            help: None,
+            severity: Severity::Error,
+            kind: KdlErrorKind::Io(err.to_string()),
+        }],
+        input,
+        diagnostics_truncated: false,
+    }
+}
+
+/// Tracks just enough of KDL's syntax, line by line, to tell where top-level
+/// nodes end, using the same tokenizer as [`tokenize`](crate::tokenize). It
+/// doesn't validate anything: when in doubt, it keeps going, since parsing
+/// several nodes together is always fine.
+#[derive(Debug, Default)]
+struct NodeScanner {
+    /// The unterminated string or comment the input so far ends in, if any,
+    /// which has to be tokenized together with the lines after it.
+    pending: String,
+    depth: usize,
+    /// Whether the input so far ends in an escline, or a slashdash that
+    /// applies to something on a later line.
+    continued: bool,
+}
+
+impl NodeScanner {
+    /// Scans the next line of input, including its newline, and returns
+    /// whether the input so far ends with complete top-level nodes.
+    fn scan_line(&mut self, line: &str) -> bool {
+        // Until a line could end the pending string or comment, there's no
+        // need to tokenize it again.
+        if !self.pending.is_empty() && !might_end(&self.pending, line) {
+            self.pending.push_str(line);
+            return false;
+        }
+        self.pending.push_str(line);
+        let text = std::mem::take(&mut self.pending);
+        let mut depth = self.depth;
+        let mut continued = self.continued;
+        for token in crate::tokenize(&text) {
+            let start = token.span.offset();
+            let token_text = &text[start..start + token.span.len()];
+            match token.kind {
+                TokenKind::Error if runs_on(&text[start..]) => {
+                    self.pending = text[start..].to_string();
+                    self.depth = depth;
+                    self.continued = continued;
+                    return false;
+                }
+                TokenKind::Punctuation if token_text == "\\" => continued = true,
+                TokenKind::Punctuation if token_text == "{" => {
+                    continued = false;
+                    depth += 1;
+                }
+                TokenKind::Punctuation if token_text == "}" => {
+                    continued = false;
+                    depth = depth.saturating_sub(1);
+                }
+                TokenKind::Comment if token_text == "/-" => continued = true,
+                TokenKind::Comment | TokenKind::Whitespace | TokenKind::Newline => {}
+                _ => continued = false,
+            }
+        }
+        self.depth = depth;
+        self.continued = continued;
+        depth == 0 && !continued
+    }
+}
+
+/// Whether `rest`, which starts with text the tokenizer couldn't make out,
+/// starts a comment or string that might only end on a later line:
+/// a multi-line comment or string, or a string whose line ends in an
+/// escaped newline.
+fn runs_on(rest: &str) -> bool {
+    let unhashed = rest.trim_start_matches('#');
+    rest.starts_with("/*")
+        || unhashed.starts_with("\"\"\"")
+        || (rest.starts_with('"') && rest.trim_end().ends_with('\\'))
+}
+
+/// Whether `line` could end `pending`, a string or comment that [`runs_on`]
+/// found to run on past the lines before it.
+fn might_end(pending: &str, line: &str) -> bool {
+    if pending.starts_with("/*") {
+        line.contains("*/")
+    } else if pending.trim_start_matches('#').starts_with("\"\"\"") {
+        line.contains("\"\"\"")
+    } else {
+        line.contains('"') || !line.trim_end().ends_with('\\')
+    }
+}
+
+/// Finds the end of the first line in `bytes`, including its newline,
+/// looking from `from` on. Any of the newlines KDL allows ends a line. If
+/// there's none yet, returns how far `bytes` is known not to have one, which
+/// stops short of a `\r` or a partial character at the end, since the bytes
+/// after them might change that.
+fn line_end(bytes: &[u8], from: usize) -> Result<usize, usize> {
+    let mut i = from;
+    while i < bytes.len() {
+        let len = match bytes[i] {
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => 1,
+        };
+        let c = match bytes.get(i..i + len) {
+            Some(c) => c,
+            None => return Err(i),
+        };
+        if c == b"\r" {
+            return match bytes.get(i + 1) {
+                Some(b'\n') => Ok(i + 2),
+                Some(_) => Ok(i + 1),
+                None => Err(i),
+            };
+        }
+        match std::str::from_utf8(c) {
+            Ok(c) if c.starts_with(crate::fmt::is_newline) => return Ok(i + len),
+            Ok(_) => i += len,
+            // Invalid UTF-8 is reported once the line is complete.
+            Err(_) => i += 1,
+        }
+    }
+    Err(i)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::KdlDocument;
+
+    /// Splits `input` into the chunks `validate_reader` would parse.
+    fn chunks(input: &str) -> Vec<String> {
+        let mut scanner = NodeScanner::default();
+        let mut chunks = vec![String::new()];
+        for line in input.split_inclusive('\n') {
+            chunks.last_mut().unwrap().push_str(line);
+            if scanner.scan_line(line) {
+                chunks.push(String::new());
+            }
+        }
+        chunks.retain(|chunk| !chunk.is_empty());
+        chunks
+    }
+
+    #[test]
+    fn node_boundaries() {
+        assert_eq!(chunks("a 1\nb 2; c 3\n"), vec!["a 1\n", "b 2; c 3\n"]);
+        assert_eq!(
+            chunks("a {\n    b \"}\" /* } */ #\"}\"#\n}\nc\n"),
+            vec!["a {\n    b \"}\" /* } */ #\"}\"#\n}\n", "c\n"]
+        );
+        assert_eq!(
+            chunks("a \\\n    1 // comment\n/-\nb\nc /* multi\nline */ 2\n"),
+            vec![
+                "a \\\n    1 // comment\n",
+                "/-\nb\n",
+                "c /* multi\nline */ 2\n"
+            ]
+        );
+        assert_eq!(
+            chunks("a \"\"\"\n    }\n    \"\"\"\nb ##\"\"\"\n    \"\"\"#\n    \"\"\"##\nc\n"),
+            vec![
+                "a \"\"\"\n    }\n    \"\"\"\n",
+                "b ##\"\"\"\n    \"\"\"#\n    \"\"\"##\n",
+                "c\n"
+            ]
+        );
+        assert_eq!(
+            chunks("a \"escaped \\\" quote {\"\nb\n"),
+            vec!["a \"escaped \\\" quote {\"\n", "b\n"]
+        );
+        assert_eq!(
+            chunks("a \"x \\\n  }\" {\n}\nb \"broken\nc\n"),
+            vec!["a \"x \\\n  }\" {\n}\n", "b \"broken\n", "c\n"]
+        );
+    }
+
+    #[test]
+    fn pending_tokens() {
+        // Only the unterminated string is kept around, and lines that can't
+        // end it aren't tokenized again.
+        let mut scanner = NodeScanner::default();
+        assert!(!scanner.scan_line("a {\n"));
+        assert!(!scanner.scan_line("    b \"\"\"\n"));
+        assert_eq!(scanner.pending, "\"\"\"\n");
+        assert!(!scanner.scan_line("    } /* x\n"));
+        assert_eq!(scanner.pending, "\"\"\"\n    } /* x\n");
+        assert!(!scanner.scan_line("    \"\"\" /* x\n"));
+        assert_eq!(scanner.pending, "/* x\n");
+        assert!(scanner.scan_line("*/ }\n"));
+        assert!(scanner.pending.is_empty());
+    }
+
+    #[test]
+    fn validates_in_chunks() {
+        let input = include_str!("../examples/kdl-schema.kdl");
+        assert!(validate_reader(input.as_bytes()).is_ok());
+        assert!(validate_reader("".as_bytes()).is_ok());
+        assert!(validate_reader("\u{FEFF}node\n".as_bytes()).is_ok());
+
+        let input = "a 1\nb {\n    c 1.\n}\nd 1.\n";
+        let err = validate_reader(input.as_bytes()).unwrap_err();
+        assert_eq!(err.diagnostics.len(), 1);
+        // Line and column match the original input.
+        let expected = input.parse::<crate::KdlDocument>().unwrap_err();
+        assert_eq!(
+            err.line_col(err.diagnostics[0].span.offset()),
+            expected.line_col(expected.diagnostics[0].span.offset())
+        );
+
+        // Unclosed blocks are still caught at the end.
+        assert!(validate_reader("a {\n    b\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn read_errors() {
+        let err = validate_reader(&b"a 1\nb \xff\n"[..]).unwrap_err();
+        assert!(matches!(err.diagnostics[0].kind, KdlErrorKind::Io(_)));
+    }
+
+    #[test]
+    fn stream_parser() -> miette::Result<()> {
+        let input = include_str!("../examples/kdl-schema.kdl");
+        let expected: KdlDocument = input.parse()?;
+        for chunk_size in [1, 7, 64, input.len()] {
+            let mut parser = KdlStreamParser::new();
+            let mut nodes = Vec::new();
+            for chunk in input.as_bytes().chunks(chunk_size) {
+                nodes.extend(parser.feed(chunk)?);
+            }
+            nodes.extend(parser.finish()?);
+            assert_eq!(nodes.len(), expected.nodes().len());
+            let text = nodes
+                .iter()
+                .map(|node| node.to_string())
+                .collect::<String>();
+            assert!(text == input);
+            for (node, expected) in nodes.iter().zip(expected.nodes()) {
+                assert_eq!(node, expected);
+                #[cfg(feature = "span")]
+                assert_eq!(node.span(), expected.span());
+            }
+        }
+
+        // Nodes come out as soon as they're complete, with any comments
+        // before them.
+        let mut parser = KdlStreamParser::new();
+        assert!(parser.feed(b"// first\n")?.is_empty());
+        assert!(parser.feed(b"a 1\nb {\n")?[0].name().value() == "a");
+        let nodes = parser.feed(b"    c \"\xc3")?;
+        assert!(nodes.is_empty());
+        let nodes = parser.feed(b"\xa9\"\n}\n")?;
+        assert_eq!(nodes[0].to_string(), "b {\n    c \"\u{e9}\"\n}\n");
+        assert!(parser.finish()?.is_empty());
+
+        // Broken nodes are skipped.
+        let mut parser = KdlStreamParser::new();
+        let err = parser.feed(b"a 1\nb 1.\nc 3\n").unwrap_err();
+        assert_eq!(err.line_col(err.diagnostics[0].span.offset()), (2, 4));
+        let names = parser.feed(b"d 4\n")?;
+        let names = names.iter().map(|n| n.name().value()).collect::<Vec<_>>();
+        assert_eq!(names, ["c", "d"]);
+        let mut parser = KdlStreamParser::new();
+        assert!(parser.feed(b"a \xff\n").is_err());
+
+        // Any newline ends a line, and a `\r\n` can be split up.
+        let mut parser = KdlStreamParser::new();
+        assert!(parser.feed(b"a 1\r")?.is_empty());
+        let nodes = parser.feed(b"\nb 2\rc 3\xe2\x80")?;
+        let texts = nodes.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        assert_eq!(texts, ["a 1\r\n", "b 2\r"]);
+        let nodes = parser.feed(b"\xa8d 4\x0ce 5")?;
+        let texts = nodes.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        assert_eq!(texts, ["c 3\u{2028}", "d 4\u{c}"]);
+        assert_eq!(parser.finish()?[0].to_string(), "e 5");
+        let mut parser = KdlStreamParser::new();
+        parser.feed(b"a {\n")?;
+        assert!(parser.finish().is_err());
+        Ok(())
+    }
+
+    /// Polls `future` to completion on the current thread.
+    #[cfg(feature = "futures-io")]
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+        fn raw() -> RawWaker {
+            RawWaker::new(std::ptr::null(), &VTABLE)
+        }
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(|_| raw(), |_| {}, |_| {}, |_| {});
+        let waker = unsafe { Waker::from_raw(raw()) };
+        let mut cx = Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    /// Hands out `input` a few bytes at a time, making the reader wait
+    /// before each read.
+    #[cfg(feature = "futures-io")]
+    struct SlowReader<'a> {
+        input: &'a [u8],
+        ready: bool,
+    }
+
+    #[cfg(feature = "futures-io")]
+    impl futures_io::AsyncRead for SlowReader<'_> {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &mut [u8],
+        ) -> std::task::Poll<std::io::Result<usize>> {
+            self.ready = !self.ready;
+            if self.ready {
+                cx.waker().wake_by_ref();
+                return std::task::Poll::Pending;
+            }
+            let read = buf.len().min(self.input.len()).min(5);
+            buf[..read].copy_from_slice(&self.input[..read]);
+            self.input = &self.input[read..];
+            std::task::Poll::Ready(Ok(read))
+        }
+    }
+
+    #[cfg(feature = "futures-io")]
+    #[test]
+    fn from_async_reader() -> miette::Result<()> {
+        let inputs = [
+            include_str!("../examples/kdl-schema.kdl"),
+            "\u{FEFF}// before\na 1 {\n    b\n}\n/* after */\n",
+            "  // only comments\n",
+            "",
+        ];
+        for input in inputs {
+            let expected: KdlDocument = input.parse()?;
+            let reader = SlowReader {
+                input: input.as_bytes(),
+                ready: false,
+            };
+            let doc = block_on(KdlDocument::from_async_reader(reader))?;
+            assert_eq!(doc, expected);
+            assert_eq!(doc.to_string(), input);
+            #[cfg(feature = "span")]
+            assert_eq!(doc.span(), expected.span());
+        }
+
+        let options = ParseOptions {
+            discard_format: true,
+            ..Default::default()
+        };
+        let doc = block_on(KdlDocument::from_async_reader_with(
+            &b"// c\na 1\n"[..],
+            options.clone(),
+        ))?;
+        assert_eq!(doc, KdlDocument::parse_with("// c\na 1\n", &options)?);
+
+        let reader = SlowReader {
+            input: b"a 1\nb 2.\nc 3\n",
+            ready: false,
+        };
+        let err = block_on(KdlDocument::from_async_reader(reader)).unwrap_err();
+        assert_eq!(err.line_col(err.diagnostics[0].span.offset()), (2, 4));
+        Ok(())
+    }
+
+    #[test]
+    fn from_reader() -> miette::Result<()> {
+        let input = "// \u{1F600}\nnode \"\u{e9}\" {\n    child\n}\n";
+        let expected: KdlDocument = input.parse()?;
+        let with_bom = format!("\u{FEFF}{input}");
+        let le = with_bom
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect::<Vec<u8>>();
+        let be = with_bom
+            .encode_utf16()
+            .flat_map(u16::to_be_bytes)
+            .collect::<Vec<u8>>();
+        assert_eq!(KdlDocument::from_reader(&le[..])?, expected);
+        assert_eq!(KdlDocument::from_reader(&be[..])?, expected);
+        assert_eq!(KdlDocument::from_reader(input.as_bytes())?, expected);
+        let doc = KdlDocument::from_reader(with_bom.as_bytes())?;
+        assert_eq!(doc.to_string(), with_bom);
+
+        // Parse errors point into the transcoded text.
+        let broken = "\u{FEFF}a 1\nb 2.\n"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect::<Vec<u8>>();
+        let err = KdlDocument::from_reader(&broken[..]).unwrap_err();
+        assert_eq!(err.line_col(err.diagnostics[0].span.offset()), (2, 4));
+
+        for bad in [
+            &b"a \xff"[..],
+            &le[..le.len() - 1],
+            &[0xFF, 0xFE, 0x00, 0xD8],
+        ] {
+            let err = KdlDocument::from_reader(bad).unwrap_err();
+            assert!(matches!(err.diagnostics[0].kind, KdlErrorKind::Io(_)));
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "encoding")]
+    #[test]
+    fn from_reader_with_encoding() -> miette::Result<()> {
+        let doc = KdlDocument::from_reader_with_encoding(
+            &b"a \"\xe9\"\n"[..],
+            encoding_rs::WINDOWS_1252,
+        )?;
+        assert_eq!(doc, "a \"\u{e9}\"\n".parse()?);
+        let le = "\u{FEFF}a 1\n"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect::<Vec<u8>>();
+        let doc = KdlDocument::from_reader_with_encoding(&le[..], encoding_rs::WINDOWS_1252)?;
+        assert_eq!(doc.to_string(), "a 1\n");
+        let err = KdlDocument::from_reader_with_encoding(&b"a \x82"[..], encoding_rs::SHIFT_JIS)
+            .unwrap_err();
+        assert!(matches!(err.diagnostics[0].kind, KdlErrorKind::Io(_)));
+        Ok(())
+    }
+}
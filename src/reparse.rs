@@ -0,0 +1,311 @@
+use std::ops::Range;
+
+use crate::{KdlDocument, KdlNode, KdlParseFailure, KdlVersion};
+
+impl KdlDocument {
+    /// Updates this document after replacing `range` of its text with
+    /// `replacement`, parsing again only the top-level nodes the edit
+    /// touches, and shifting the spans of everything after them.
+    ///
+    /// This is meant for editors and language servers, which would
+    /// otherwise have to parse the whole document again on every keystroke.
+    /// The document's text is what it stringifies to, and its spans have to
+    /// match it, as they do after parsing it.
+    ///
+    /// The result is meant to be the same as parsing the edited text from
+    /// scratch, so edits are only handled locally where the touched nodes'
+    /// text can't parse any differently on its own than in context. If the
+    /// edit opens a children block or a string that runs on past the nodes
+    /// it touches, leaves the last touched node without a terminator of its
+    /// own, or this document has no formatting, the whole document is
+    /// parsed again instead.
+    ///
+    /// If the edited text doesn't parse, the failure is returned, and the
+    /// document is left as it was.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range` is out of bounds, or doesn't start and end on char
+    /// boundaries, like [`String::replace_range`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlDocument;
+    /// let mut doc: KdlDocument = "a 1\nb 2\nc 3\n".parse()?;
+    /// doc.reparse_edit(6..7, "\"two\"")?;
+    /// assert_eq!(doc.to_string(), "a 1\nb \"two\"\nc 3\n");
+    /// assert_eq!(doc.nodes()[2].span().offset(), 12);
+    /// assert!(doc.reparse_edit(0..1, "{").is_err());
+    /// # Ok::<(), kdl::KdlParseFailure>(())
+    /// ```
+    pub fn reparse_edit(
+        &mut self,
+        range: Range<usize>,
+        replacement: &str,
+    ) -> Result<(), KdlParseFailure> {
+        if self.reparse_locally(range.clone(), replacement).is_some() {
+            return Ok(());
+        }
+        let mut text = self.to_string();
+        text.replace_range(range, replacement);
+        *self = match self.version {
+            #[cfg(feature = "v1")]
+            KdlVersion::V1 => KdlDocument::parse_v1(&text)?,
+            _ => text.parse()?,
+        };
+        Ok(())
+    }
+
+    /// Tries to apply an edit by parsing only the top-level nodes it
+    /// touches, returning `None`, and leaving the document alone, if that's
+    /// not enough to get the same result as parsing everything.
+    fn reparse_locally(&mut self, range: Range<usize>, replacement: &str) -> Option<()> {
+        let fmt = self.format()?;
+        if self.version != KdlVersion::V2 || self.nodes.is_empty() {
+            return None;
+        }
+        // Where each node's text, including its leading trivia, starts.
+        let starts = self
+            .nodes
+            .iter()
+            .map(|node| {
+                node.span()
+                    .offset()
+                    .checked_sub(node.format()?.leading.len())
+            })
+            .collect::<Option<Vec<_>>>()?;
+        // The first and last nodes touched by the edit. Touching a node's
+        // edges counts too, since that might join it up with its neighbor.
+        let first = match starts.iter().rposition(|start| *start <= range.start) {
+            Some(first) if starts[first] == range.start => first.saturating_sub(1),
+            first => first.unwrap_or(0),
+        };
+        let mut last = starts
+            .iter()
+            .rposition(|start| *start <= range.end)
+            .unwrap_or(0);
+        let at_start = first == 0;
+
+        // The text of the touched nodes, plus the document's own trivia at
+        // either end. Nodes on the same line as the last one go in too, since
+        // the edit might start a comment that runs on into them.
+        let offset = if at_start { 0 } else { starts[first] };
+        let mut text = String::new();
+        if at_start {
+            text.push_str(&fmt.leading);
+        }
+        for node in &self.nodes[first..=last] {
+            text.push_str(&node.to_string());
+        }
+        while last + 1 < self.nodes.len() && !text.ends_with(crate::fmt::is_newline) {
+            last += 1;
+            text.push_str(&self.nodes[last].to_string());
+        }
+        let at_end = last == self.nodes.len() - 1;
+        if at_end {
+            text.push_str(&fmt.trailing);
+        }
+        let end = offset + text.len();
+        if (!at_end && end != starts[last + 1]) || range.start < offset || range.end > end {
+            return None;
+        }
+        text.replace_range(range.start - offset..range.end - offset, replacement);
+        let delta = replacement.len() as isize - range.len() as isize;
+
+        let mut region: KdlDocument = text.parse().ok()?;
+        // Unless it's the end of the document, the edited text has to end
+        // with a newline that ends a node, and not an escline, or it
+        // might've run on into the next one. Its last node also needs a
+        // terminator of its own, since going without one, and leaving
+        // anything after it as trailing trivia (like a slashdashed node right
+        // after an argument), is only allowed at the end of a document.
+        if !at_end {
+            let ends_node = |trivia: &str| trivia.ends_with(crate::fmt::is_newline);
+            let trailing = region.format().map_or("", |fmt| &fmt.trailing[..]);
+            let terminator = region
+                .nodes
+                .last()
+                .and_then(|node| node.format())
+                .map_or("", |fmt| &fmt.terminator[..]);
+            let complete = !terminator.is_empty()
+                && if trailing.is_empty() {
+                    ends_node(terminator)
+                } else {
+                    ends_node(trailing)
+                };
+            if !complete {
+                return None;
+            }
+        }
+        // Without any nodes left, the document's span depends on the nodes
+        // around the edited ones.
+        if region.nodes.is_empty() && !(at_start && at_end) {
+            return None;
+        }
+        for node in &mut region.nodes {
+            crate::reader::shift_spans(node, offset as isize);
+        }
+        let region_fmt = region.format.take().unwrap_or_default();
+        let mut new_nodes = std::mem::take(&mut region.nodes);
+        let region_span = region.span();
+        let start = if at_start {
+            offset + region_span.offset()
+        } else {
+            self.span.offset()
+        };
+        let end = if at_end {
+            offset + region_span.offset() + region_span.len()
+        } else {
+            ((self.span.offset() + self.span.len()) as isize + delta) as usize
+        };
+        self.span = (start..end).into();
+
+        // Trivia around the edited nodes belong to whatever comes next.
+        let mut carried = String::new();
+        if !at_start {
+            carried.push_str(&region_fmt.leading);
+        }
+        if let Some(node) = new_nodes.first_mut() {
+            prepend_leading(node, &mut carried);
+        }
+        if !at_end {
+            carried.push_str(&region_fmt.trailing);
+        }
+        for node in &mut self.nodes[last + 1..] {
+            crate::reader::shift_spans(node, delta);
+            prepend_leading(node, &mut carried);
+        }
+        self.nodes.splice(first..=last, new_nodes);
+
+        let fmt = self.format.get_or_insert_with(Default::default);
+        if at_start {
+            fmt.leading = region_fmt.leading;
+        }
+        if at_end {
+            fmt.trailing = region_fmt.trailing;
+        }
+        // Whatever's before the first node is the document's.
+        if let Some(node_fmt) = self.nodes.first_mut().and_then(|node| node.format_mut()) {
+            let leading = std::mem::take(&mut node_fmt.leading);
+            fmt.leading.to_mut().push_str(&leading);
+        }
+        Some(())
+    }
+}
+
+/// Moves `carried` trivia to the front of `node`'s leading trivia.
+fn prepend_leading(node: &mut KdlNode, carried: &mut String) {
+    if carried.is_empty() {
+        return;
+    }
+    if let Some(fmt) = node.format_mut() {
+        fmt.leading.to_mut().insert_str(0, carried);
+        carried.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Checks that every edit in `edits`, at every offset in `input`, gives
+    /// the same result as parsing the edited text from scratch.
+    fn check_edits(input: &str, edits: &[(usize, &str)]) {
+        let doc: KdlDocument = input.parse().unwrap();
+        let mut local = 0;
+        let mut valid = 0;
+        for start in (0..=input.len()).filter(|i| input.is_char_boundary(*i)) {
+            for (len, replacement) in edits {
+                let end = start + len;
+                if end > input.len() || !input.is_char_boundary(end) {
+                    continue;
+                }
+                let mut text = input.to_string();
+                text.replace_range(start..end, replacement);
+                let expected = text.parse::<KdlDocument>();
+                let mut edited = doc.clone();
+                let result = edited.reparse_edit(start..end, replacement);
+                let context = format!("{start}..{end} => {replacement:?} in {input:?}");
+                match expected {
+                    Ok(expected) => {
+                        assert!(result.is_ok(), "{context}");
+                        valid += 1;
+                        local += doc
+                            .clone()
+                            .reparse_locally(start..end, replacement)
+                            .is_some() as usize;
+                        assert_eq!(edited, expected, "{context}");
+                        assert_eq!(edited.to_string(), expected.to_string(), "{context}");
+                        assert_eq!(edited.span(), expected.span(), "{context}");
+                        let mut spans = Vec::new();
+                        let mut expected_spans = Vec::new();
+                        for (node, expected) in edited.descendants().zip(expected.descendants()) {
+                            spans.push(node.span());
+                            expected_spans.push(expected.span());
+                            for (entry, expected) in node.entries().iter().zip(expected.entries()) {
+                                spans.push(entry.span());
+                                expected_spans.push(expected.span());
+                            }
+                        }
+                        assert_eq!(spans, expected_spans, "{context}");
+                    }
+                    Err(_) => {
+                        assert!(result.is_err(), "{context}");
+                        assert_eq!(edited, doc, "{context}");
+                    }
+                }
+            }
+        }
+        // Most edits shouldn't need a full parse.
+        assert!(local * 2 > valid, "{local} of {valid} edits were local");
+    }
+
+    #[test]
+    fn same_as_full_parse() {
+        let edits = [
+            (0, "x"),
+            (0, " "),
+            (0, "\n"),
+            (0, ";"),
+            (0, "{"),
+            (0, "}"),
+            (0, "\""),
+            (0, "/*"),
+            (0, "*/"),
+            (0, "//"),
+            (0, "/-"),
+            (0, "\\"),
+            (0, "z {\n"),
+            (1, ""),
+            (2, ""),
+            (3, "q"),
+        ];
+        check_edits("// c\na 1 {\n    b \"s\"\n}\n/- x\nc key=2; d\n\n", &edits);
+        check_edits("a\nb\n", &edits);
+        check_edits("a /* \n */ 1\n  b\\\n 2\nc\n// end", &edits);
+    }
+
+    #[test]
+    fn context_sensitive_edits() {
+        // On its own, `a 1/- c` parses as `a 1` with a slashdashed node after
+        // it, but that's only allowed at the end of a document.
+        let input = "xyzwv\na 1 // c\nb 2\n";
+        let mut doc: KdlDocument = input.parse().unwrap();
+        assert!(doc.clone().reparse_locally(9..12, "/-").is_none());
+        assert!(doc.reparse_edit(9..12, "/-").is_err());
+        assert_eq!(doc.to_string(), input);
+        check_edits(input, &[(3, "/-"), (3, "/- "), (1, "/-")]);
+    }
+
+    #[test]
+    fn shifts_later_spans() -> miette::Result<()> {
+        let mut doc: KdlDocument = "a 1\nb 2 { c; }\nd\n".parse()?;
+        doc.reparse_edit(2..3, "100")?;
+        assert_eq!(doc.to_string(), "a 100\nb 2 { c; }\nd\n");
+        let c = &doc.nodes()[1].children().unwrap().nodes()[0];
+        assert_eq!(c.span().offset(), 12);
+        assert_eq!(doc.nodes()[2].span().offset(), 17);
+        Ok(())
+    }
+}
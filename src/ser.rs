@@ -0,0 +1,675 @@
+//! Writing any [`Serialize`] type as a KDL document, with the `serde`
+//! feature.
+//!
+//! Structs and maps become nodes, one per field, named after the field. A
+//! field holding a single value, like a number or a string, becomes that
+//! node's argument at the top level, and a property anywhere else.
+//! Everything else becomes a child node:
+//!
+//! * Structs and maps nested in a field become a node with the same rules
+//!   applied to their own fields, so simple fields turn into properties.
+//! * Sequences, tuples, and tuple structs become a node with one `-` child
+//!   per element, following the usual KDL convention for lists.
+//! * Enum variants with data become a node annotated with the variant name,
+//!   as in `(Circle)shape 5`. Unit variants are just their name, as a
+//!   string.
+//! * Byte buffers, when serialized as bytes, are `(base64)` strings.
+//!
+//! Fields set to `None` are left out, and newtype structs are written as
+//! whatever they wrap. A top-level sequence becomes a document of `-` nodes.
+//!
+//! # Examples
+//!
+//! ```rust
+//! # use serde::Serialize;
+//! #[derive(Serialize)]
+//! struct Config {
+//!     name: String,
+//!     server: Server,
+//!     tags: Vec<String>,
+//!     contact: Option<String>,
+//! }
+//!
+//! #[derive(Serialize)]
+//! struct Server {
+//!     host: String,
+//!     port: u16,
+//! }
+//!
+//! let config = Config {
+//!     name: "demo".into(),
+//!     server: Server {
+//!         host: "localhost".into(),
+//!         port: 8080,
+//!     },
+//!     tags: vec!["a".into(), "b".into()],
+//!     contact: None,
+//! };
+//! assert_eq!(
+//!     kdl::ser::to_string(&config).unwrap(),
+//!     "name demo\nserver host=localhost port=8080\ntags {\n    - a\n    - b\n}\n"
+//! );
+//! ```
+
+use serde::{
+    ser::{
+        self, Error as _, SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant,
+        SerializeTuple, SerializeTupleStruct, SerializeTupleVariant,
+    },
+    Serialize,
+};
+
+use crate::{types, KdlDocument, KdlEntry, KdlNode, KdlSerializeError, KdlValue};
+
+/// Serializes `value` as a [`KdlDocument`]. `value` has to be a struct, a
+/// map, or a sequence. See the [module docs](self) for how values are
+/// mapped to nodes.
+///
+/// The document is autoformatted.
+pub fn to_document<T: Serialize + ?Sized>(value: &T) -> Result<KdlDocument, KdlSerializeError> {
+    let mut doc = KdlDocument::new();
+    match value.serialize(ItemSerializer)? {
+        Item::Map(fields) => {
+            for (name, item) in fields {
+                if !matches!(item, Item::None) {
+                    doc.nodes_mut().push(item.into_node(&name));
+                }
+            }
+        }
+        Item::Seq(items) => {
+            for item in items {
+                doc.nodes_mut().push(item.into_node("-"));
+            }
+        }
+        _ => {
+            return Err(KdlSerializeError::custom(
+                "only structs, maps, and sequences can be serialized as KDL documents",
+            ))
+        }
+    }
+    doc.autoformat();
+    Ok(doc)
+}
+
+/// Serializes `value` as KDL text. See [`to_document`].
+pub fn to_string<T: Serialize + ?Sized>(value: &T) -> Result<String, KdlSerializeError> {
+    Ok(to_document(value)?.to_string())
+}
+
+/// A serialized value, before it's known whether it ends up as an entry or
+/// a node.
+enum Item {
+    Value(KdlValue, Option<&'static str>),
+    None,
+    Seq(Vec<Item>),
+    Map(Vec<(String, Item)>),
+    Variant(&'static str, Box<Item>),
+}
+
+impl Item {
+    fn into_node(self, name: &str) -> KdlNode {
+        let mut node = KdlNode::new(name);
+        match self {
+            Item::Value(value, ty) => node.push(entry(None, value, ty)),
+            Item::None => node.push(KdlValue::Null),
+            Item::Seq(items) => {
+                if !items.is_empty() {
+                    let children = node.ensure_children().nodes_mut();
+                    for item in items {
+                        children.push(item.into_node("-"));
+                    }
+                }
+            }
+            Item::Map(fields) => {
+                for (key, item) in fields {
+                    match item {
+                        Item::Value(value, ty) => node.push(entry(Some(key), value, ty)),
+                        Item::None => {}
+                        item => node
+                            .ensure_children()
+                            .nodes_mut()
+                            .push(item.into_node(&key)),
+                    }
+                }
+            }
+            Item::Variant(variant, item) => {
+                node = item.into_node(name);
+                node.set_ty(variant);
+            }
+        }
+        node
+    }
+}
+
+fn entry(name: Option<String>, value: KdlValue, ty: Option<&'static str>) -> KdlEntry {
+    let mut entry = match name {
+        Some(name) => KdlEntry::new_prop(name, value),
+        None => KdlEntry::new(value),
+    };
+    if let Some(ty) = ty {
+        entry.set_ty(ty);
+    }
+    entry
+}
+
+struct ItemSerializer;
+
+impl ser::Serializer for ItemSerializer {
+    type Ok = Item;
+    type Error = KdlSerializeError;
+    type SerializeSeq = SeqSerializer;
+    type SerializeTuple = SeqSerializer;
+    type SerializeTupleStruct = SeqSerializer;
+    type SerializeTupleVariant = SeqSerializer;
+    type SerializeMap = MapSerializer;
+    type SerializeStruct = MapSerializer;
+    type SerializeStructVariant = MapSerializer;
+
+    fn serialize_bool(self, v: bool) -> Result<Item, KdlSerializeError> {
+        Ok(Item::Value(v.into(), None))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Item, KdlSerializeError> {
+        self.serialize_i128(v.into())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Item, KdlSerializeError> {
+        self.serialize_i128(v.into())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Item, KdlSerializeError> {
+        self.serialize_i128(v.into())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Item, KdlSerializeError> {
+        self.serialize_i128(v.into())
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<Item, KdlSerializeError> {
+        Ok(Item::Value(KdlValue::Integer(v), None))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Item, KdlSerializeError> {
+        self.serialize_i128(v.into())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Item, KdlSerializeError> {
+        self.serialize_i128(v.into())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Item, KdlSerializeError> {
+        self.serialize_i128(v.into())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Item, KdlSerializeError> {
+        self.serialize_i128(v.into())
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<Item, KdlSerializeError> {
+        match i128::try_from(v) {
+            Ok(v) => self.serialize_i128(v),
+            Err(_) => Err(KdlSerializeError::custom(format!(
+                "{v} is too large for a KDL integer"
+            ))),
+        }
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Item, KdlSerializeError> {
+        self.serialize_f64(v.into())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Item, KdlSerializeError> {
+        Ok(Item::Value(KdlValue::Float(v), None))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Item, KdlSerializeError> {
+        self.serialize_str(v.encode_utf8(&mut [0; 4]))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Item, KdlSerializeError> {
+        Ok(Item::Value(v.into(), None))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Item, KdlSerializeError> {
+        Ok(Item::Value(types::encode_base64(v).into(), Some("base64")))
+    }
+
+    fn serialize_none(self) -> Result<Item, KdlSerializeError> {
+        Ok(Item::None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Item, KdlSerializeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Item, KdlSerializeError> {
+        Ok(Item::Value(KdlValue::Null, None))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Item, KdlSerializeError> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Item, KdlSerializeError> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Item, KdlSerializeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Item, KdlSerializeError> {
+        Ok(Item::Variant(variant, Box::new(value.serialize(self)?)))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SeqSerializer, KdlSerializeError> {
+        Ok(SeqSerializer {
+            items: Vec::with_capacity(len.unwrap_or(0)),
+            variant: None,
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SeqSerializer, KdlSerializeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SeqSerializer, KdlSerializeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SeqSerializer, KdlSerializeError> {
+        Ok(SeqSerializer {
+            items: Vec::with_capacity(len),
+            variant: Some(variant),
+        })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<MapSerializer, KdlSerializeError> {
+        Ok(MapSerializer {
+            fields: Vec::with_capacity(len.unwrap_or(0)),
+            key: None,
+            variant: None,
+        })
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<MapSerializer, KdlSerializeError> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<MapSerializer, KdlSerializeError> {
+        Ok(MapSerializer {
+            fields: Vec::with_capacity(len),
+            key: None,
+            variant: Some(variant),
+        })
+    }
+}
+
+struct SeqSerializer {
+    items: Vec<Item>,
+    variant: Option<&'static str>,
+}
+
+impl SeqSerializer {
+    fn finish(self) -> Item {
+        let seq = Item::Seq(self.items);
+        match self.variant {
+            Some(variant) => Item::Variant(variant, Box::new(seq)),
+            None => seq,
+        }
+    }
+}
+
+impl SerializeSeq for SeqSerializer {
+    type Ok = Item;
+    type Error = KdlSerializeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), KdlSerializeError> {
+        self.items.push(value.serialize(ItemSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Item, KdlSerializeError> {
+        Ok(self.finish())
+    }
+}
+
+impl SerializeTuple for SeqSerializer {
+    type Ok = Item;
+    type Error = KdlSerializeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), KdlSerializeError> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Item, KdlSerializeError> {
+        Ok(self.finish())
+    }
+}
+
+impl SerializeTupleStruct for SeqSerializer {
+    type Ok = Item;
+    type Error = KdlSerializeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), KdlSerializeError> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Item, KdlSerializeError> {
+        Ok(self.finish())
+    }
+}
+
+impl SerializeTupleVariant for SeqSerializer {
+    type Ok = Item;
+    type Error = KdlSerializeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), KdlSerializeError> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Item, KdlSerializeError> {
+        Ok(self.finish())
+    }
+}
+
+struct MapSerializer {
+    fields: Vec<(String, Item)>,
+    key: Option<String>,
+    variant: Option<&'static str>,
+}
+
+impl MapSerializer {
+    fn finish(self) -> Item {
+        let map = Item::Map(self.fields);
+        match self.variant {
+            Some(variant) => Item::Variant(variant, Box::new(map)),
+            None => map,
+        }
+    }
+}
+
+impl SerializeMap for MapSerializer {
+    type Ok = Item;
+    type Error = KdlSerializeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), KdlSerializeError> {
+        let key = match key.serialize(ItemSerializer)? {
+            Item::Value(KdlValue::String(key), None) => key,
+            Item::Value(
+                value @ (KdlValue::Integer(_) | KdlValue::Float(_) | KdlValue::Bool(_)),
+                None,
+            ) => value.to_string(),
+            _ => {
+                return Err(KdlSerializeError::custom(
+                    "map keys must be strings, numbers, or booleans",
+                ))
+            }
+        };
+        self.key = Some(key);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), KdlSerializeError> {
+        let key = self
+            .key
+            .take()
+            .expect("serialize_value is always called after serialize_key");
+        self.fields.push((key, value.serialize(ItemSerializer)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Item, KdlSerializeError> {
+        Ok(self.finish())
+    }
+}
+
+impl SerializeStruct for MapSerializer {
+    type Ok = Item;
+    type Error = KdlSerializeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), KdlSerializeError> {
+        self.fields
+            .push((key.into(), value.serialize(ItemSerializer)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Item, KdlSerializeError> {
+        Ok(self.finish())
+    }
+}
+
+impl SerializeStructVariant for MapSerializer {
+    type Ok = Item;
+    type Error = KdlSerializeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), KdlSerializeError> {
+        SerializeStruct::serialize_field(self, key, value)
+    }
+
+    fn end(self) -> Result<Item, KdlSerializeError> {
+        Ok(self.finish())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use serde::Serialize;
+
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Package {
+        name: &'static str,
+        version: (u8, u8, u8),
+        #[serde(with = "bytes")]
+        checksum: Vec<u8>,
+        license: Option<&'static str>,
+        authors: Vec<Author>,
+        build: Build,
+        features: BTreeMap<&'static str, Vec<&'static str>>,
+    }
+
+    #[derive(Serialize)]
+    struct Author {
+        name: &'static str,
+        email: Option<&'static str>,
+    }
+
+    #[derive(Serialize)]
+    enum Build {
+        Script(&'static str),
+    }
+
+    mod bytes {
+        pub(super) fn serialize<S: serde::Serializer>(
+            bytes: &[u8],
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            serializer.serialize_bytes(bytes)
+        }
+    }
+
+    #[test]
+    fn documents() -> Result<(), KdlSerializeError> {
+        let package = Package {
+            name: "kdl",
+            version: (6, 0, 0),
+            checksum: vec![0xfb, 0xff],
+            license: None,
+            authors: vec![
+                Author {
+                    name: "Kat",
+                    email: Some("kat@example.com"),
+                },
+                Author {
+                    name: "Someone Else",
+                    email: None,
+                },
+            ],
+            build: Build::Script("build.rs"),
+            features: [("default", vec!["span"]), ("span", vec![])]
+                .into_iter()
+                .collect(),
+        };
+        assert_eq!(
+            to_string(&package)?,
+            r#"name kdl
+version {
+    - 6
+    - 0
+    - 0
+}
+checksum (base64)"+/8="
+authors {
+    - name=Kat email=kat@example.com
+    - name="Someone Else"
+}
+(Script)build build.rs
+features {
+    default {
+        - span
+    }
+    span
+}
+"#
+        );
+
+        assert_eq!(to_string(&[1, 2])?, "- 1\n- 2\n");
+        assert_eq!(to_string(&BTreeMap::from([(1, "one")]))?, "\"1\" one\n");
+        assert_eq!(to_string(&Vec::<u8>::new())?, "");
+        Ok(())
+    }
+
+    #[test]
+    fn values() -> Result<(), KdlSerializeError> {
+        #[derive(Serialize)]
+        enum Shape {
+            Point,
+            Circle { radius: f64 },
+            Line((i8, i8), (i8, i8)),
+        }
+
+        #[derive(Serialize)]
+        struct Unit;
+
+        #[derive(Serialize)]
+        struct Values {
+            shapes: Vec<Shape>,
+            unit: (),
+            unit_struct: Unit,
+            char: char,
+            big: u128,
+            nested: Option<Option<bool>>,
+        }
+
+        let values = Values {
+            shapes: vec![
+                Shape::Point,
+                Shape::Circle { radius: 1.5 },
+                Shape::Line((0, 0), (1, -1)),
+            ],
+            unit: (),
+            unit_struct: Unit,
+            char: '"',
+            big: u64::MAX.into(),
+            nested: Some(None),
+        };
+        assert_eq!(
+            to_string(&values)?,
+            r#"shapes {
+    - Point
+    (Circle)- radius=1.5
+    (Line)- {
+        - {
+            - 0
+            - 0
+        }
+        - {
+            - 1
+            - -1
+        }
+    }
+}
+unit #null
+unit_struct #null
+char "\""
+big 18446744073709551615
+"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn errors() {
+        assert!(to_string(&1).is_err());
+        assert!(to_string(&"string").is_err());
+        assert!(to_string(&[u128::MAX]).is_err());
+        assert!(to_string(&BTreeMap::from([((1, 2), 3)])).is_err());
+        let err = to_string(&Some(1)).unwrap_err();
+        assert_eq!(
+            err.message,
+            "only structs, maps, and sequences can be serialized as KDL documents"
+        );
+    }
+}
@@ -0,0 +1,215 @@
+use std::{
+    borrow::Borrow,
+    fmt::{Debug, Display},
+    hash::{Hash, Hasher},
+    ops::{Deref, Range},
+    sync::Arc,
+};
+
+/// Whitespace, comments, and other text kept around for formatting, like a
+/// node's [`KdlNodeFormat::leading`](crate::KdlNodeFormat::leading).
+///
+/// Parsing doesn't copy trivia out of the input one string at a time.
+/// Instead, all the trivia of a parsed document share a single copy of its
+/// source, and each refers to its own part of it, until it's changed
+/// through [`KdlTrivia::to_mut`]. Otherwise, it works like a `str`, which
+/// it derefs to, and compares and hashes like one too.
+///
+/// Note that as long as any trivia from a parsed document is around, so is
+/// the document's whole source.
+///
+/// ```rust
+/// # use kdl::{KdlDocument, KdlTrivia};
+/// let mut doc: KdlDocument = "// comment\nnode".parse()?;
+/// let fmt = doc.format_mut().unwrap();
+/// assert_eq!(fmt.leading, "// comment\n");
+/// fmt.leading.to_mut().push_str("// another\n");
+/// assert_eq!(doc.to_string(), "// comment\n// another\nnode");
+/// # Ok::<(), kdl::KdlParseFailure>(())
+/// ```
+#[derive(Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "String", into = "String"))]
+pub struct KdlTrivia(Repr);
+
+#[derive(Clone)]
+enum Repr {
+    Owned(String),
+    Shared(Arc<str>, Range<usize>),
+}
+
+impl Default for Repr {
+    fn default() -> Self {
+        Repr::Owned(String::new())
+    }
+}
+
+impl KdlTrivia {
+    /// Creates new, empty trivia.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Refers to `range` of a shared `source`, without copying it.
+    pub(crate) fn shared(source: Arc<str>, range: Range<usize>) -> Self {
+        debug_assert!(source.get(range.clone()).is_some());
+        Self(Repr::Shared(source, range))
+    }
+
+    /// Gets the text of these trivia.
+    pub fn as_str(&self) -> &str {
+        match &self.0 {
+            Repr::Owned(text) => text,
+            Repr::Shared(source, range) => &source[range.clone()],
+        }
+    }
+
+    /// Gets a mutable reference to the text of these trivia, copying it out
+    /// of the document's shared source first if needed.
+    pub fn to_mut(&mut self) -> &mut String {
+        if let Repr::Shared(source, range) = &self.0 {
+            self.0 = Repr::Owned(source[range.clone()].to_string());
+        }
+        match &mut self.0 {
+            Repr::Owned(text) => text,
+            Repr::Shared(..) => unreachable!(),
+        }
+    }
+
+    /// Empties these trivia, letting go of the document's shared source.
+    pub fn clear(&mut self) {
+        self.0 = Repr::default();
+    }
+
+    /// Turns these trivia into a `String`.
+    pub fn into_string(self) -> String {
+        match self.0 {
+            Repr::Owned(text) => text,
+            Repr::Shared(source, range) => source[range].to_string(),
+        }
+    }
+}
+
+impl Deref for KdlTrivia {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for KdlTrivia {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Borrow<str> for KdlTrivia {
+    fn borrow(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Display for KdlTrivia {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self.as_str(), f)
+    }
+}
+
+impl Debug for KdlTrivia {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl PartialEq for KdlTrivia {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for KdlTrivia {}
+
+impl Hash for KdlTrivia {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state);
+    }
+}
+
+impl PartialEq<str> for KdlTrivia {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for KdlTrivia {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialEq<String> for KdlTrivia {
+    fn eq(&self, other: &String) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl From<&str> for KdlTrivia {
+    fn from(text: &str) -> Self {
+        Self(Repr::Owned(text.into()))
+    }
+}
+
+impl From<String> for KdlTrivia {
+    fn from(text: String) -> Self {
+        Self(Repr::Owned(text))
+    }
+}
+
+impl From<KdlTrivia> for String {
+    fn from(trivia: KdlTrivia) -> Self {
+        trivia.into_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn shared() {
+        let source: Arc<str> = "node /* c */ 1".into();
+        let mut trivia = KdlTrivia::shared(source.clone(), 4..13);
+        assert_eq!(trivia, " /* c */ ");
+        assert_eq!(trivia, KdlTrivia::from(" /* c */ "));
+        assert_eq!(Arc::strong_count(&source), 2);
+        trivia.to_mut().push('x');
+        assert_eq!(trivia, " /* c */ x");
+        assert_eq!(Arc::strong_count(&source), 1);
+    }
+
+    #[test]
+    fn parsed_trivia_share_source() -> miette::Result<()> {
+        let doc: crate::KdlDocument = "// c\nnode 1 /* x */ { child; }\n".parse()?;
+        let node = &doc.nodes()[0];
+        let trivia = [
+            &doc.format().unwrap().leading,
+            &node.entries()[0].format().unwrap().leading,
+            &node.format().unwrap().before_children,
+            &node.children().unwrap().nodes()[0]
+                .format()
+                .unwrap()
+                .terminator,
+        ];
+        assert_eq!(trivia, ["// c\n", " ", " /* x */ ", ";"]);
+        let sources = trivia
+            .iter()
+            .map(|trivia| match &trivia.0 {
+                Repr::Shared(source, _) => source.clone(),
+                Repr::Owned(text) => panic!("{text:?} was copied"),
+            })
+            .collect::<Vec<_>>();
+        assert!(sources.iter().all(|s| Arc::ptr_eq(s, &sources[0])));
+        Ok(())
+    }
+}
@@ -0,0 +1,442 @@
+use std::fmt::Display;
+
+use crate::{KdlTypeError, KdlValue};
+
+/// One of the type annotations
+/// [reserved](https://github.com/kdl-org/kdl/blob/main/SPEC.md#reserved-type-annotations-for-numbers-without-decimals)
+/// by the KDL spec.
+///
+/// Use [`KdlReservedType::from_name`] to look one up, and
+/// [`KdlReservedType::coerce`] (or [`KdlValue::coerce`]) to check and convert
+/// a value annotated with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KdlReservedType {
+    /// `i8`
+    I8,
+    /// `i16`
+    I16,
+    /// `i32`
+    I32,
+    /// `i64`
+    I64,
+    /// `i128`
+    I128,
+    /// `isize`, treated as 64 bits wide.
+    Isize,
+    /// `u8`
+    U8,
+    /// `u16`
+    U16,
+    /// `u32`
+    U32,
+    /// `u64`
+    U64,
+    /// `u128`
+    U128,
+    /// `usize`, treated as 64 bits wide.
+    Usize,
+    /// `f32`
+    F32,
+    /// `f64`
+    F64,
+    /// `decimal64`
+    Decimal64,
+    /// `decimal128`
+    Decimal128,
+    /// `date-time`
+    DateTime,
+    /// `time`
+    Time,
+    /// `date`
+    Date,
+    /// `duration`
+    Duration,
+    /// `decimal`
+    Decimal,
+    /// `currency`
+    Currency,
+    /// `country-2`
+    Country2,
+    /// `country-3`
+    Country3,
+    /// `country-subdivision`
+    CountrySubdivision,
+    /// `email`
+    Email,
+    /// `idn-email`
+    IdnEmail,
+    /// `hostname`
+    Hostname,
+    /// `idn-hostname`
+    IdnHostname,
+    /// `ipv4`
+    Ipv4,
+    /// `ipv6`
+    Ipv6,
+    /// `url`
+    Url,
+    /// `url-reference`
+    UrlReference,
+    /// `irl`
+    Irl,
+    /// `irl-reference`
+    IrlReference,
+    /// `url-template`
+    UrlTemplate,
+    /// `uuid`
+    Uuid,
+    /// `regex`
+    Regex,
+    /// `base64`
+    Base64,
+}
+
+static RESERVED_TYPES: &[(&str, KdlReservedType)] = &[
+    ("i8", KdlReservedType::I8),
+    ("i16", KdlReservedType::I16),
+    ("i32", KdlReservedType::I32),
+    ("i64", KdlReservedType::I64),
+    ("i128", KdlReservedType::I128),
+    ("isize", KdlReservedType::Isize),
+    ("u8", KdlReservedType::U8),
+    ("u16", KdlReservedType::U16),
+    ("u32", KdlReservedType::U32),
+    ("u64", KdlReservedType::U64),
+    ("u128", KdlReservedType::U128),
+    ("usize", KdlReservedType::Usize),
+    ("f32", KdlReservedType::F32),
+    ("f64", KdlReservedType::F64),
+    ("decimal64", KdlReservedType::Decimal64),
+    ("decimal128", KdlReservedType::Decimal128),
+    ("date-time", KdlReservedType::DateTime),
+    ("time", KdlReservedType::Time),
+    ("date", KdlReservedType::Date),
+    ("duration", KdlReservedType::Duration),
+    ("decimal", KdlReservedType::Decimal),
+    ("currency", KdlReservedType::Currency),
+    ("country-2", KdlReservedType::Country2),
+    ("country-3", KdlReservedType::Country3),
+    ("country-subdivision", KdlReservedType::CountrySubdivision),
+    ("email", KdlReservedType::Email),
+    ("idn-email", KdlReservedType::IdnEmail),
+    ("hostname", KdlReservedType::Hostname),
+    ("idn-hostname", KdlReservedType::IdnHostname),
+    ("ipv4", KdlReservedType::Ipv4),
+    ("ipv6", KdlReservedType::Ipv6),
+    ("url", KdlReservedType::Url),
+    ("url-reference", KdlReservedType::UrlReference),
+    ("irl", KdlReservedType::Irl),
+    ("irl-reference", KdlReservedType::IrlReference),
+    ("url-template", KdlReservedType::UrlTemplate),
+    ("uuid", KdlReservedType::Uuid),
+    ("regex", KdlReservedType::Regex),
+    ("base64", KdlReservedType::Base64),
+];
+
+/// The result of checking a value against its type annotation with
+/// [`KdlValue::coerce`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum KdlCoercedValue {
+    /// An integer that fits in its annotated integer type.
+    Integer(i128),
+    /// A number annotated with a float or decimal type, which fits in it.
+    Float(f64),
+    /// The bytes decoded from a `(base64)` string.
+    Bytes(Vec<u8>),
+    /// A string annotated with one of the reserved string types, such as
+    /// `(date-time)` or `(uuid)`. Only the value's type is checked, not the
+    /// string's format.
+    String(String),
+    /// The value, unchanged, because its type annotation isn't a reserved
+    /// one. Custom annotations are up to the application to interpret.
+    Uninterpreted(KdlValue),
+}
+
+impl KdlReservedType {
+    /// Looks up a reserved type annotation by name, returning `None` for
+    /// custom annotations.
+    pub fn from_name(name: &str) -> Option<Self> {
+        RESERVED_TYPES
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, ty)| *ty)
+    }
+
+    /// Gets this type's name, as written in a type annotation.
+    pub fn name(self) -> &'static str {
+        RESERVED_TYPES
+            .iter()
+            .find(|(_, ty)| *ty == self)
+            .map(|(name, _)| *name)
+            .expect("Every reserved type is in the table.")
+    }
+
+    /// The inclusive range of integers this type can hold, if it's an
+    /// integer type.
+    pub fn integer_range(self) -> Option<(i128, i128)> {
+        use KdlReservedType::*;
+        Some(match self {
+            I8 => (i8::MIN.into(), i8::MAX.into()),
+            I16 => (i16::MIN.into(), i16::MAX.into()),
+            I32 => (i32::MIN.into(), i32::MAX.into()),
+            I64 | Isize => (i64::MIN.into(), i64::MAX.into()),
+            I128 => (i128::MIN, i128::MAX),
+            U8 => (0, u8::MAX.into()),
+            U16 => (0, u16::MAX.into()),
+            U32 => (0, u32::MAX.into()),
+            U64 | Usize => (0, u64::MAX.into()),
+            // KdlValue::Integer can't hold anything past i128::MAX anyway.
+            U128 => (0, i128::MAX),
+            _ => return None,
+        })
+    }
+
+    /// Checks that `value` is valid for this type, converting it where
+    /// there's a natural Rust equivalent.
+    ///
+    /// Integer types require an integer in the type's range, and float and
+    /// decimal types require a number that doesn't overflow them. `(base64)`
+    /// requires a string of valid
+    /// [RFC 4648](https://datatracker.ietf.org/doc/html/rfc4648#section-4)
+    /// base64, and is decoded to bytes. The remaining types require a
+    /// string, but its format isn't checked.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::{KdlCoercedValue, KdlReservedType, KdlValue};
+    /// let u8_ty = KdlReservedType::from_name("u8").unwrap();
+    /// assert_eq!(u8_ty.coerce(&KdlValue::Integer(255)), Ok(KdlCoercedValue::Integer(255)));
+    /// assert!(u8_ty.coerce(&KdlValue::Integer(300)).is_err());
+    /// assert!(u8_ty.coerce(&KdlValue::Integer(-1)).is_err());
+    /// ```
+    pub fn coerce(self, value: &KdlValue) -> Result<KdlCoercedValue, KdlTypeError> {
+        use KdlReservedType::*;
+        if let Some((min, max)) = self.integer_range() {
+            return match value {
+                KdlValue::Integer(int) if (min..=max).contains(int) => {
+                    Ok(KdlCoercedValue::Integer(*int))
+                }
+                KdlValue::Integer(_) => Err(self.out_of_range(value)),
+                _ => Err(self.mismatch("an integer", value)),
+            };
+        }
+        match self {
+            F32 | F64 | Decimal64 | Decimal128 | Decimal => {
+                let float = match value {
+                    KdlValue::Float(float) => *float,
+                    KdlValue::Integer(int) => *int as f64,
+                    _ => return Err(self.mismatch("a number", value)),
+                };
+                let max = match self {
+                    F32 => f32::MAX.into(),
+                    _ => f64::MAX,
+                };
+                // Non-finite values are fine, as long as they were written
+                // that way rather than overflowing.
+                if float.is_finite() && float.abs() > max {
+                    return Err(self.out_of_range(value));
+                }
+                Ok(KdlCoercedValue::Float(float))
+            }
+            Base64 => match value {
+                KdlValue::String(string) => decode_base64(string)
+                    .map(KdlCoercedValue::Bytes)
+                    .ok_or_else(|| KdlTypeError::InvalidBase64 {
+                        value: string.clone(),
+                    }),
+                _ => Err(self.mismatch("a string", value)),
+            },
+            _ => match value {
+                KdlValue::String(string) => Ok(KdlCoercedValue::String(string.clone())),
+                _ => Err(self.mismatch("a string", value)),
+            },
+        }
+    }
+
+    fn out_of_range(self, value: &KdlValue) -> KdlTypeError {
+        KdlTypeError::OutOfRange {
+            ty: self.name(),
+            value: value.clone(),
+        }
+    }
+
+    fn mismatch(self, expected: &'static str, value: &KdlValue) -> KdlTypeError {
+        KdlTypeError::Mismatch {
+            ty: self.name(),
+            expected,
+            value: value.clone(),
+        }
+    }
+}
+
+impl Display for KdlReservedType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Encodes `bytes` as standard, padded base64.
+#[cfg(feature = "serde")]
+pub(crate) fn encode_base64(bytes: &[u8]) -> String {
+    const DIGITS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity((bytes.len() + 2) / 3 * 4);
+    for chunk in bytes.chunks(3) {
+        let acc = chunk
+            .iter()
+            .fold(0u32, |acc, &byte| acc << 8 | u32::from(byte))
+            << (8 * (3 - chunk.len()));
+        for idx in 0..4 {
+            if idx <= chunk.len() {
+                out.push(DIGITS[(acc >> (18 - 6 * idx) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Decodes standard base64, with or without padding, returning `None` if
+/// `input` isn't valid base64.
+pub(crate) fn decode_base64(input: &str) -> Option<Vec<u8>> {
+    let digit = |c: u8| match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    };
+    let unpadded = input.trim_end_matches('=');
+    let padding = input.len() - unpadded.len();
+    if unpadded.len() % 4 == 1
+        || padding > 2
+        || (padding > 0 && (unpadded.len() + padding) % 4 != 0)
+    {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(unpadded.len() * 3 / 4);
+    for chunk in unpadded.as_bytes().chunks(4) {
+        let mut acc = 0u32;
+        for &c in chunk {
+            acc = acc << 6 | u32::from(digit(c)?);
+        }
+        let bits = chunk.len() * 6;
+        let extra = bits % 8;
+        // The bits left over from a partial chunk must be zero.
+        if acc & ((1 << extra) - 1) != 0 {
+            return None;
+        }
+        acc >>= extra;
+        for idx in (0..bits / 8).rev() {
+            bytes.push((acc >> (idx * 8)) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn integer_bounds() {
+        let ty = |name| KdlReservedType::from_name(name).unwrap();
+        for (name, min, max) in [
+            ("i8", -128, 127),
+            ("i16", -32768, 32767),
+            ("u8", 0, 255),
+            ("u16", 0, 65535),
+            ("u32", 0, u32::MAX.into()),
+            ("i64", i64::MIN.into(), i64::MAX.into()),
+            ("usize", 0, u64::MAX.into()),
+        ] {
+            for int in [min, max] {
+                assert_eq!(
+                    ty(name).coerce(&KdlValue::Integer(int)),
+                    Ok(KdlCoercedValue::Integer(int)),
+                    "({name}){int}"
+                );
+            }
+            for int in [min - 1, max + 1] {
+                assert!(
+                    matches!(
+                        ty(name).coerce(&KdlValue::Integer(int)),
+                        Err(KdlTypeError::OutOfRange { .. })
+                    ),
+                    "({name}){int}"
+                );
+            }
+        }
+        assert!(matches!(
+            ty("u8").coerce(&KdlValue::Float(1.0)),
+            Err(KdlTypeError::Mismatch { .. })
+        ));
+        assert!(matches!(
+            ty("i32").coerce(&"1".into()),
+            Err(KdlTypeError::Mismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn floats() {
+        let f32_ty = KdlReservedType::F32;
+        assert_eq!(
+            f32_ty.coerce(&KdlValue::Integer(3)),
+            Ok(KdlCoercedValue::Float(3.0))
+        );
+        assert_eq!(
+            f32_ty.coerce(&KdlValue::Float(f64::INFINITY)),
+            Ok(KdlCoercedValue::Float(f64::INFINITY))
+        );
+        assert!(matches!(
+            f32_ty.coerce(&KdlValue::Float(1e39)),
+            Err(KdlTypeError::OutOfRange { .. })
+        ));
+        assert_eq!(
+            KdlReservedType::F64.coerce(&KdlValue::Float(1e39)),
+            Ok(KdlCoercedValue::Float(1e39))
+        );
+    }
+
+    #[test]
+    fn base64() {
+        for (input, output) in [
+            ("", &b""[..]),
+            ("Zg==", b"f"),
+            ("Zm8=", b"fo"),
+            ("Zm9v", b"foo"),
+            ("Zm9vYg", b"foob"),
+            ("Zm9vYmE=", b"fooba"),
+            ("Zm9vYmFy", b"foobar"),
+            ("+/8=", &[0xfb, 0xff]),
+        ] {
+            assert_eq!(decode_base64(input).as_deref(), Some(output), "{input:?}");
+            #[cfg(feature = "serde")]
+            if input.len() % 4 == 0 {
+                assert_eq!(encode_base64(output), input);
+            }
+        }
+        for input in ["Z", "Zg=", "Zg===", "Zh==", "Zm9v!", "=Zm9"] {
+            assert_eq!(decode_base64(input), None, "{input:?}");
+        }
+        assert!(matches!(
+            KdlReservedType::Base64.coerce(&"nope!".into()),
+            Err(KdlTypeError::InvalidBase64 { .. })
+        ));
+    }
+
+    #[test]
+    fn names() {
+        for (name, ty) in RESERVED_TYPES {
+            assert_eq!(KdlReservedType::from_name(name), Some(*ty));
+            assert_eq!(ty.name(), *name);
+        }
+        assert_eq!(KdlReservedType::from_name("custom"), None);
+        assert_eq!(
+            KdlReservedType::Uuid.coerce(&"123".into()),
+            Ok(KdlCoercedValue::String("123".into()))
+        );
+    }
+}
@@ -0,0 +1,252 @@
+use crate::{
+    v1_parser::{self, Trivia},
+    v2_parser, KdlDocument, KdlEntry, KdlIdentifier, KdlNode, KdlTrivia, KdlValue, KdlVersion,
+};
+
+impl KdlDocument {
+    /// Rewrites a document parsed with [`KdlDocument::parse_v1`] into valid
+    /// KDL v2, with the `v1` feature, keeping its whitespace and comments.
+    ///
+    /// Only what has to change does:
+    ///
+    /// * `true`, `false`, and `null` become `#true`, `#false`, and `#null`.
+    /// * Raw strings like `r"..."` and `r#"..."#` become `#"..."#`.
+    /// * Identifiers that aren't valid bare identifiers in v2, like `true`
+    ///   or `#foo`, get quoted.
+    /// * Strings using escapes v2 no longer has, like `\/`, or spanning
+    ///   several lines, are written out again as v2 strings.
+    /// * Slashdashed nodes, entries, and children blocks are upgraded too,
+    ///   and a `/- kdl-version 1` marker becomes `/- kdl-version 2`.
+    /// * Slashdashed children blocks followed by more entries are moved
+    ///   after the last entry, since v2 doesn't allow entries after them.
+    ///
+    /// Everything else, including numbers and strings that mean the same
+    /// thing in both versions, is kept as written. Afterwards, the document's
+    /// [`KdlDocument::version`] is [`KdlVersion::V2`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::{KdlDocument, KdlVersion};
+    /// let mut doc = KdlDocument::parse_v1(r#"
+    /// // Settings
+    /// server enabled=true root=r"C:\srv" {
+    ///     /- port 80
+    ///     "true" null /* none */ 0xff
+    /// }
+    /// "#)?;
+    /// doc.upgrade_to_v2();
+    /// assert_eq!(doc.version(), KdlVersion::V2);
+    /// assert_eq!(doc.to_string(), r##"
+    /// // Settings
+    /// server enabled=#true root=#"C:\srv"# {
+    ///     /- port 80
+    ///     "true" #null /* none */ 0xff
+    /// }
+    /// "##);
+    /// assert_eq!(doc, doc.to_string().parse()?);
+    /// # Ok::<(), miette::Report>(())
+    /// ```
+    pub fn upgrade_to_v2(&mut self) {
+        if let Some(fmt) = self.format_mut() {
+            upgrade_trivia(&mut fmt.leading, true, &mut Vec::new());
+            upgrade_trivia(&mut fmt.trailing, true, &mut Vec::new());
+        }
+        for node in self.nodes_mut() {
+            node.upgrade_to_v2();
+        }
+        self.version = KdlVersion::V2;
+    }
+}
+
+impl KdlNode {
+    /// Rewrites a node parsed with [`KdlDocument::parse_v1`] into valid KDL
+    /// v2, along with its children. See [`KdlDocument::upgrade_to_v2`].
+    pub fn upgrade_to_v2(&mut self) {
+        upgrade_identifier(self.name_mut());
+        if let Some(ty) = self.ty_mut() {
+            upgrade_identifier(ty);
+        }
+        let mut moved = Vec::new();
+        for entry in self.entries_mut() {
+            upgrade_entry(entry, &mut moved);
+        }
+        let has_children = self.children().is_some();
+        if let Some(fmt) = self.format_mut() {
+            upgrade_trivia(&mut fmt.leading, true, &mut moved);
+            upgrade_trivia(&mut fmt.before_children, false, &mut Vec::new());
+            upgrade_trivia(&mut fmt.before_terminator, false, &mut Vec::new());
+            // v2 doesn't allow entries after a slashdashed children block,
+            // so those go after the last entry instead.
+            let after_entries = if has_children {
+                &mut fmt.before_children
+            } else {
+                &mut fmt.before_terminator
+            };
+            for block in moved.iter().rev() {
+                let after_entries = after_entries.to_mut();
+                if !after_entries.starts_with(char::is_whitespace) {
+                    after_entries.insert(0, ' ');
+                }
+                after_entries.insert_str(0, block);
+                after_entries.insert(0, ' ');
+            }
+        }
+        if let Some(children) = self.children_mut() {
+            children.upgrade_to_v2();
+        }
+    }
+}
+
+/// Upgrades an entry, adding any slashdashed children blocks right before it
+/// to `moved`, since they can't stay there in v2.
+fn upgrade_entry(entry: &mut KdlEntry, moved: &mut Vec<String>) {
+    for ident in entry.name.iter_mut().chain(entry.ty.iter_mut()) {
+        upgrade_identifier(ident);
+    }
+    if let Some(fmt) = entry.format.as_mut() {
+        upgrade_trivia(&mut fmt.leading, false, moved);
+        if !value_round_trips(&fmt.value_repr, &entry.value) {
+            fmt.value_repr = v2_raw_string(&fmt.value_repr)
+                .filter(|raw| value_round_trips(raw, &entry.value))
+                .unwrap_or_else(|| entry.value.to_string());
+        }
+    }
+}
+
+fn upgrade_identifier(ident: &mut KdlIdentifier) {
+    if ident.round_trips() {
+        return;
+    }
+    match ident.repr().and_then(v2_raw_string) {
+        Some(raw) => {
+            ident.set_repr(raw);
+            if !ident.round_trips() {
+                ident.clear_format();
+            }
+        }
+        None => ident.clear_format(),
+    }
+}
+
+/// Rewrites the slashdashed parts of some v1 formatting. `between_nodes`
+/// says whether it's found between nodes, where only nodes can be
+/// slashdashed, or inside a node. Inside a node, slashdashed children blocks
+/// are taken out and added to `moved` instead.
+fn upgrade_trivia(trivia: &mut KdlTrivia, between_nodes: bool, moved: &mut Vec<String>) {
+    if !trivia.contains("/-") {
+        return;
+    }
+    let pieces = if between_nodes {
+        v2_parser::try_parse(v1_parser::node_trivia, trivia)
+    } else {
+        v2_parser::try_parse(v1_parser::entry_trivia, trivia)
+    };
+    // Anything that doesn't parse as v1 is left alone.
+    let pieces = match pieces {
+        Ok(pieces) => pieces,
+        Err(_) => return,
+    };
+    let mut upgraded = String::with_capacity(trivia.len());
+    // The `/-` and space before the next slashdashed item.
+    let mut slashdash = "";
+    for piece in pieces {
+        match piece {
+            Trivia::Text(text) if text.starts_with("/-") => slashdash = text,
+            Trivia::Text(text) => upgraded.push_str(text),
+            Trivia::Node(mut node) => {
+                node.upgrade_to_v2();
+                if node.name().value() == "kdl-version" {
+                    if let [version] = node.entries_mut().as_mut_slice() {
+                        if version.value() == &KdlValue::Integer(1) {
+                            version.set_value(2);
+                        }
+                    }
+                }
+                upgraded.push_str(slashdash);
+                upgraded.push_str(&node.to_string());
+            }
+            Trivia::Entry(mut entry) => {
+                upgrade_entry(&mut entry, moved);
+                upgraded.push_str(slashdash);
+                upgraded.push_str(&entry.to_string());
+            }
+            Trivia::Children(mut children) => {
+                children.upgrade_to_v2();
+                moved.push(format!("{slashdash}{{{children}}}"));
+            }
+        }
+    }
+    *trivia = upgraded.into();
+}
+
+/// Whether `repr` is a valid v2 value that means `value`.
+fn value_round_trips(repr: &str, value: &KdlValue) -> bool {
+    v2_parser::try_parse(v2_parser::bare_value, repr)
+        .map_or(false, |parsed| parsed.as_ref() == Some(value))
+}
+
+/// Rewrites a v1 raw string, like `r#"..."#`, as a v2 one, like `#"..."#`.
+/// The result may still be invalid if the string spans several lines.
+fn v2_raw_string(repr: &str) -> Option<String> {
+    let rest = repr.strip_prefix('r')?;
+    let hashes = &rest[..rest.len() - rest.trim_start_matches('#').len()];
+    let body = rest[hashes.len()..]
+        .strip_prefix('"')?
+        .strip_suffix(hashes)?
+        .strip_suffix('"')?;
+    let hashes = "#".repeat(hashes.len().max(1));
+    Some(format!("{hashes}\"{body}\"{hashes}"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn upgrade() -> miette::Result<()> {
+        let mut doc = KdlDocument::parse_v1(
+            r####"/- kdl-version 1
+(type)node true false null r"raw" r##"has "# in it"## "a\/b" {
+    "bare" #hash=1 r#"raw ident"#=2 "needs quotes"=3
+    /- gone null { nested true; }
+    multi "line
+string"
+}
+inf /-true /-{ x null; } 1.5e10 /* comment */ ; "null" \
+    0x1F
+"####,
+        )?;
+        doc.upgrade_to_v2();
+        assert_eq!(doc.version(), KdlVersion::V2);
+        assert_eq!(
+            doc.get("node").unwrap().children().unwrap().version(),
+            KdlVersion::V2
+        );
+        assert_eq!(
+            doc.to_string(),
+            r####"/- kdl-version 2
+(type)node #true #false #null #"raw"# ##"has "# in it"## "a/b" {
+    "bare" "#hash"=1 #"raw ident"#=2 "needs quotes"=3
+    /- gone #null { nested #true; }
+    multi "line\nstring"
+}
+"inf" /-#true  1.5e10 /-{ x #null; } /* comment */ ; "null" \
+    0x1F
+"####
+        );
+        let reparsed: KdlDocument = doc.to_string().parse()?;
+        assert_eq!(reparsed.to_string(), doc.to_string());
+        assert_eq!(
+            reparsed.get("node").unwrap().entries()[3].value(),
+            &KdlValue::String("raw".into())
+        );
+
+        // Already-valid v2 is left alone.
+        let input = "node #true \"x\" /- #\"raw\"# { - 1; }\n";
+        let mut doc: KdlDocument = input.parse()?;
+        doc.upgrade_to_v2();
+        assert_eq!(doc.to_string(), input);
+        Ok(())
+    }
+}
@@ -1,11 +1,11 @@
 use std::{
-    num::{ParseFloatError, ParseIntError},
+    cell::{Cell, RefCell},
+    num::{IntErrorKind, ParseFloatError, ParseIntError},
     sync::Arc,
 };
 
 use miette::{Severity, SourceSpan};
 
-use num::CheckedMul;
 use winnow::{
     ascii::{digit1, hex_digit1, oct_digit1, Caseless},
     combinator::{
@@ -13,35 +13,165 @@ use winnow::{
         separated, terminated,
     },
     error::{
-        AddContext, ContextError, ErrorKind, FromExternalError, FromRecoverableError, ParserError,
+        AddContext, ContextError, ErrMode, FromExternalError, FromRecoverableError, ParserError,
         StrContext, StrContextValue,
     },
     prelude::*,
-    stream::{AsChar, Location, Recoverable, Stream},
-    token::{any, none_of, one_of, take_while},
-    Located,
+    stream::{AsChar, Location, Offset, Recover, Recoverable, Stateful, Stream},
+    token::{any, none_of, one_of, rest, take_while},
+    LocatingSlice,
 };
 
+// winnow 0.6.26 deprecated `ErrorKind`, but it's still part of the signatures
+// of the error traits we implement.
+#[allow(deprecated)]
+use winnow::error::ErrorKind;
+
 use crate::{
     KdlDiagnostic, KdlDocument, KdlDocumentFormat, KdlEntry, KdlEntryFormat, KdlErrorKind,
-    KdlIdentifier, KdlNode, KdlNodeFormat, KdlParseFailure, KdlValue,
+    KdlIdentifier, KdlNode, KdlNodeFormat, KdlParseFailure, KdlTrivia, KdlValue, KdlVersion,
+    ParseOptions, TokenKind, DEFAULT_PARSE_OPTIONS,
 };
 
-type Input<'a> = Recoverable<Located<&'a str>, KdlParseError>;
-type PResult<T> = winnow::PResult<T, KdlParseError>;
+#[cfg(feature = "v1")]
+use crate::v1_parser::Trivia;
+
+pub(crate) type Input<'a> =
+    Recoverable<Stateful<LocatingSlice<&'a str>, ParseState<'a>>, KdlParseError>;
+pub(crate) type PResult<T> = winnow::ModalResult<T, KdlParseError>;
+
+/// Parser state: the options in effect, and some bookkeeping about the parse
+/// so far. Derefs to the options.
+#[derive(Debug, Clone)]
+pub(crate) struct ParseState<'a> {
+    options: &'a ParseOptions,
+    /// The whole input, which trivia refer to.
+    source: &'a str,
+    /// A shared copy of `source`, made the first time any trivia need it.
+    shared_source: RefCell<Option<Arc<str>>>,
+    /// Number of errors recorded for recovery so far.
+    errors: Cell<usize>,
+    /// Number of children blocks the parser is currently inside of.
+    depth: Cell<usize>,
+}
+
+impl<'a> ParseState<'a> {
+    fn new(options: &'a ParseOptions, source: &'a str) -> Self {
+        Self {
+            options,
+            source,
+            shared_source: RefCell::new(None),
+            errors: Cell::new(0),
+            depth: Cell::new(0),
+        }
+    }
+
+    /// Whether to record formatting and original representations, rather
+    /// than leaving them out. See [`ParseOptions::discard_format`].
+    fn keep_format(&self) -> bool {
+        !self.discard_format
+    }
+
+    /// Turns `text`, a slice of the input, into trivia that refer to a
+    /// shared copy of the input instead of copying `text` out of it.
+    pub(crate) fn trivia(&self, text: &str) -> KdlTrivia {
+        let start = (text.as_ptr() as usize).wrapping_sub(self.source.as_ptr() as usize);
+        let in_source = self
+            .source
+            .len()
+            .checked_sub(text.len())
+            .map_or(false, |last| start <= last);
+        if text.is_empty() || !in_source {
+            return text.into();
+        }
+        let source = self
+            .shared_source
+            .borrow_mut()
+            .get_or_insert_with(|| self.source.into())
+            .clone();
+        KdlTrivia::shared(source, start..start + text.len())
+    }
+
+    /// Whether parsing should stop here because more errors have been
+    /// recorded than will be reported. See
+    /// [`ParseOptions::stop_at_max_diagnostics`].
+    fn should_stop(&self) -> bool {
+        self.stop_at_max_diagnostics
+            && self
+                .max_diagnostics
+                .map_or(false, |max| self.errors.get() > max)
+    }
+}
+
+impl std::ops::Deref for ParseState<'_> {
+    type Target = ParseOptions;
+
+    fn deref(&self) -> &ParseOptions {
+        self.options
+    }
+}
 
 pub(crate) fn try_parse<'a, P: Parser<Input<'a>, T, KdlParseError>, T>(
+    parser: P,
+    input: &'a str,
+) -> Result<T, KdlParseFailure> {
+    try_parse_with(parser, input, &DEFAULT_PARSE_OPTIONS)
+}
+
+pub(crate) fn try_parse_with<'a, P: Parser<Input<'a>, T, KdlParseError>, T>(
     mut parser: P,
     input: &'a str,
+    options: &'a ParseOptions,
 ) -> Result<T, KdlParseFailure> {
-    let (_, maybe_val, errs) = parser.recoverable_parse(Located::new(input));
+    if let Some(err) = input_too_long(input, options) {
+        return Err(failure_from_errs(vec![err], input));
+    }
+    let (_, maybe_val, mut errs) = parser.recoverable_parse(Stateful {
+        input: LocatingSlice::new(input),
+        state: ParseState::new(options, input),
+    });
     if let (Some(v), true) = (maybe_val, errs.is_empty()) {
         Ok(v)
     } else {
-        Err(failure_from_errs(errs, input))
+        let max = options.max_diagnostics.unwrap_or(usize::MAX);
+        let truncated = errs.len() > max;
+        errs.truncate(max);
+        let mut failure = failure_from_errs(errs, input);
+        failure.diagnostics_truncated = truncated;
+        Err(failure)
     }
 }
 
+/// Like [`try_parse_with`], but returns whatever was recovered along with
+/// the diagnostics, instead of discarding it as soon as there's an error.
+pub(crate) fn try_parse_lossy<'a, P: Parser<Input<'a>, T, KdlParseError>, T>(
+    mut parser: P,
+    input: &'a str,
+    options: &'a ParseOptions,
+) -> (Option<T>, Vec<KdlDiagnostic>) {
+    if let Some(err) = input_too_long(input, options) {
+        return (None, failure_from_errs(vec![err], input).diagnostics);
+    }
+    let (_, maybe_val, errs) = parser.recoverable_parse(Stateful {
+        input: LocatingSlice::new(input),
+        state: ParseState::new(options, input),
+    });
+    (maybe_val, failure_from_errs(errs, input).diagnostics)
+}
+
+/// An error for all of `input` past [`ParseOptions::max_input_len`], if
+/// it's too long.
+fn input_too_long(input: &str, options: &ParseOptions) -> Option<KdlParseError> {
+    let max = options.max_input_len.filter(|max| input.len() > *max)?;
+    Some(KdlParseError {
+        context: None,
+        span: Some((max..input.len()).into()),
+        label: Some("past the limit"),
+        help: Some("Split the input up, or raise `max_input_len`."),
+        kind: Some(KdlErrorKind::InputTooLong(max)),
+    })
+}
+
 pub(crate) fn failure_from_errs(errs: Vec<KdlParseError>, input: &str) -> KdlParseFailure {
     let src = Arc::new(String::from(input));
     KdlParseFailure {
@@ -54,13 +184,16 @@ pub(crate) fn failure_from_errs(errs: Vec<KdlParseError>, input: &str) -> KdlPar
                 label: e.label,
                 help: e.help,
                 severity: Severity::Error,
-                kind: if let Some(ctx) = e.context {
+                kind: if let Some(kind) = e.kind {
+                    kind
+                } else if let Some(ctx) = e.context {
                     KdlErrorKind::Context(ctx)
                 } else {
                     KdlErrorKind::Other
                 },
             })
             .collect(),
+        diagnostics_truncated: false,
     }
 }
 
@@ -73,6 +206,24 @@ pub(crate) struct KdlParseError {
     pub(crate) kind: Option<KdlErrorKind>,
 }
 
+/// Fails with a Cut error spanning from `start` to the current location.
+pub(crate) fn cut<T>(
+    input: &mut Input<'_>,
+    start: usize,
+    context: &'static str,
+    label: &'static str,
+    help: Option<&'static str>,
+) -> PResult<T> {
+    Err(ErrMode::Cut(KdlParseError {
+        context: Some(context),
+        span: Some((start..input.location()).into()),
+        label: Some(label),
+        help,
+        kind: None,
+    }))
+}
+
+#[allow(deprecated)]
 impl<I: Stream> ParserError<I> for KdlParseError {
     fn from_error_kind(_input: &I, _kind: ErrorKind) -> Self {
         Self {
@@ -106,10 +257,14 @@ impl<I: Stream> AddContext<I> for KdlParseError {
     }
 }
 
+// `try_map` rewinds to where the failed parser started before building these,
+// so the best they can do is point there.
+#[allow(deprecated)]
 impl<'a> FromExternalError<Input<'a>, ParseIntError> for KdlParseError {
-    fn from_external_error(_: &Input<'a>, _kind: ErrorKind, e: ParseIntError) -> Self {
+    fn from_external_error(input: &Input<'a>, _kind: ErrorKind, e: ParseIntError) -> Self {
+        let start = input.location();
         KdlParseError {
-            span: None,
+            span: Some((start..start).into()),
             label: None,
             help: None,
             context: None,
@@ -118,10 +273,12 @@ impl<'a> FromExternalError<Input<'a>, ParseIntError> for KdlParseError {
     }
 }
 
+#[allow(deprecated)]
 impl<'a> FromExternalError<Input<'a>, ParseFloatError> for KdlParseError {
-    fn from_external_error(_input: &Input<'a>, _kind: ErrorKind, e: ParseFloatError) -> Self {
+    fn from_external_error(input: &Input<'a>, _kind: ErrorKind, e: ParseFloatError) -> Self {
+        let start = input.location();
         KdlParseError {
-            span: None,
+            span: Some((start..start).into()),
             label: None,
             help: None,
             context: None,
@@ -130,30 +287,12 @@ impl<'a> FromExternalError<Input<'a>, ParseFloatError> for KdlParseError {
     }
 }
 
-struct NegativeUnsignedError;
-
-impl<'a> FromExternalError<Input<'a>, NegativeUnsignedError> for KdlParseError {
-    fn from_external_error(
-        _input: &Input<'a>,
-        _kind: ErrorKind,
-        _e: NegativeUnsignedError,
-    ) -> Self {
-        KdlParseError {
-            span: None,
-            label: None,
-            help: None,
-            context: None,
-            kind: Some(KdlErrorKind::NegativeUnsignedError),
-        }
-    }
-}
-
-impl<I: Stream + Location> FromRecoverableError<I, Self> for KdlParseError {
+impl<'a> FromRecoverableError<Input<'a>, Self> for KdlParseError {
     #[inline]
     fn from_recoverable_error(
-        token_start: &<I as Stream>::Checkpoint,
-        _err_start: &<I as Stream>::Checkpoint,
-        input: &I,
+        token_start: &<Input<'a> as Stream>::Checkpoint,
+        _err_start: &<Input<'a> as Stream>::Checkpoint,
+        input: &Input<'a>,
         mut e: Self,
     ) -> Self {
         e.span = e.span.or_else(|| {
@@ -163,12 +302,12 @@ impl<I: Stream + Location> FromRecoverableError<I, Self> for KdlParseError {
     }
 }
 
-impl<I: Stream + Location> FromRecoverableError<I, ContextError> for KdlParseError {
+impl<'a> FromRecoverableError<Input<'a>, ContextError> for KdlParseError {
     #[inline]
     fn from_recoverable_error(
-        token_start: &<I as Stream>::Checkpoint,
-        _err_start: &<I as Stream>::Checkpoint,
-        input: &I,
+        token_start: &<Input<'a> as Stream>::Checkpoint,
+        _err_start: &<Input<'a> as Stream>::Checkpoint,
+        input: &Input<'a>,
         e: ContextError,
     ) -> Self {
         KdlParseError {
@@ -186,6 +325,48 @@ impl<I: Stream + Location> FromRecoverableError<I, ContextError> for KdlParseErr
     }
 }
 
+/// Records `err` for recovery, and counts it towards
+/// [`ParseOptions::max_diagnostics`] once it's actually been recorded.
+fn record_err<'s>(
+    input: &mut Input<'s>,
+    token_start: &<Input<'s> as Stream>::Checkpoint,
+    err_start: &<Input<'s> as Stream>::Checkpoint,
+    err: ErrMode<KdlParseError>,
+) -> PResult<()> {
+    input.record_err(token_start, err_start, err)?;
+    input.state.errors.set(input.state.errors.get() + 1);
+    Ok(())
+}
+
+/// Runs `parser`, recovering from its errors with `recover`, like
+/// [`Parser::resume_after`] does, but only counts errors once they've
+/// actually been recorded (see [`record_err`]). winnow also builds
+/// recoverable errors for failures it gives up on, which the parser may still
+/// backtrack out of, so errors can't be counted as they're built.
+fn resume_after<'s, O>(
+    input: &mut Input<'s>,
+    mut parser: impl Parser<Input<'s>, O, KdlParseError>,
+    mut recover: impl Parser<Input<'s>, (), KdlParseError>,
+) -> PResult<Option<O>> {
+    let token_start = input.checkpoint();
+    let err = match parser.parse_next(input) {
+        Ok(o) => return Ok(Some(o)),
+        Err(ErrMode::Incomplete(needed)) => return Err(ErrMode::Incomplete(needed)),
+        Err(err) => err,
+    };
+    let err_start = input.checkpoint();
+    let err = if recover.parse_next(input).is_ok() {
+        match record_err(input, &token_start, &err_start, err) {
+            Ok(()) => return Ok(None),
+            Err(err) => err,
+        }
+    } else {
+        err
+    };
+    input.reset(&err_start);
+    Err(err.map(|err| KdlParseError::from_recoverable_error(&token_start, &err_start, input, err)))
+}
+
 /// Consumes the rest of a value we've cut_err on, so we can contine the parse.
 // TODO: maybe use this for detecting invalid codepoints with useful errors?
 fn badval(input: &mut Input<'_>) -> PResult<()> {
@@ -209,8 +390,45 @@ fn lbl(label: &'static str) -> &'static str {
 }
 
 #[cfg(test)]
-fn new_input(s: &str) -> Input<'_> {
-    Recoverable::new(Located::new(s))
+pub(crate) fn new_input(s: &str) -> Input<'_> {
+    Recoverable::new(Stateful {
+        input: LocatingSlice::new(s),
+        state: ParseState::new(&DEFAULT_PARSE_OPTIONS, s),
+    })
+}
+
+/// Input for recognizers that should fail outright instead of recovering,
+/// such as the tokenizer's.
+pub(crate) fn unrecoverable_input(input: &str) -> Input<'_> {
+    Recoverable::unrecoverable(Stateful {
+        input: LocatingSlice::new(input),
+        state: ParseState::new(&DEFAULT_PARSE_OPTIONS, input),
+    })
+}
+
+/// Recognizes a single lexical token, without checking that it's in a
+/// grammatically valid position.
+pub(crate) fn token(input: &mut Input<'_>) -> PResult<TokenKind> {
+    alt((
+        repeat(1.., unicode_space)
+            .map(|()| ())
+            .value(TokenKind::Whitespace),
+        newline.value(TokenKind::Newline),
+        (
+            "//",
+            repeat(0.., (not(alt((newline, eof.void()))), any)).map(|()| ()),
+        )
+            .value(TokenKind::Comment),
+        multi_line_comment.value(TokenKind::Comment),
+        "/-".value(TokenKind::Comment),
+        keyword.value(TokenKind::Keyword),
+        ty.value(TokenKind::TypeAnnotation),
+        number.value(TokenKind::Number),
+        alt((raw_string, quoted_string)).value(TokenKind::String),
+        identifier_string.value(TokenKind::Identifier),
+        one_of(['{', '}', '(', ')', ';', '=', '\\']).value(TokenKind::Punctuation),
+    ))
+    .parse_next(input)
 }
 
 /// `document := bom? nodes`
@@ -219,36 +437,224 @@ pub(crate) fn document(input: &mut Input<'_>) -> PResult<KdlDocument> {
     let mut doc = nodes.parse_next(input)?;
     if let Some(bom) = bom {
         if let Some(fmt) = doc.format_mut() {
-            fmt.leading = format!("{bom}{}", fmt.leading);
+            fmt.leading = format!("{bom}{}", fmt.leading).into();
         }
     }
     Ok(doc)
 }
 
+/// `version-marker := bom? line-space* '/-' line-space* 'kdl-version' node-space+ ('1' | '2')`
+///
+/// Looks for a version marker at the start of a document without parsing the
+/// rest of it, so it works for documents in either version. Everything after
+/// the marker, if any, is consumed and ignored. The marker is a comment in
+/// both versions, so it's still kept as part of the document when it's parsed
+/// for real.
+///
+/// Along with the version, returns where the marker is, from its `/-` up to
+/// and including the version number.
+pub(crate) fn version_marker(
+    input: &mut Input<'_>,
+) -> PResult<Option<(KdlVersion, std::ops::Range<usize>)>> {
+    let version = opt(preceded(
+        (opt(bom), repeat(0.., line_space).map(|()| ())),
+        (
+            slashdash,
+            "kdl-version",
+            node_space1,
+            terminated(
+                alt(('1'.value(KdlVersion::V1), '2'.value(KdlVersion::V2))),
+                not(identifier_char),
+            ),
+        )
+            .with_span()
+            .map(|((_, _, _, version), span)| (version, span)),
+    ))
+    .parse_next(input)?;
+    rest.parse_next(input)?;
+    Ok(version)
+}
+
+/// A slashdashed node, along with its own node terminator, if any. These are
+/// treated as part of the surrounding whitespace.
+pub(crate) fn slashdashed_node(input: &mut Input<'_>) -> PResult<()> {
+    (slashdash, base_node, opt(node_terminator))
+        .void()
+        .parse_next(input)
+}
+
+/// Splits up formatting found between nodes, like a node's leading
+/// formatting, so the slashdashed nodes in it can be rewritten.
+#[cfg(feature = "v1")]
+pub(crate) fn node_trivia<'s>(input: &mut Input<'s>) -> PResult<Vec<Trivia<'s>>> {
+    repeat(
+        0..,
+        alt((
+            line_space.take().map(Trivia::Text),
+            slashdash.take().map(Trivia::Text),
+            terminated(base_node, opt(node_terminator)).map(Trivia::Node),
+        )),
+    )
+    .parse_next(input)
+}
+
+/// Splits up formatting found inside a node, like an entry's leading
+/// formatting, so the slashdashed entries and children blocks in it can be
+/// rewritten.
+#[cfg(feature = "v1")]
+pub(crate) fn entry_trivia<'s>(input: &mut Input<'s>) -> PResult<Vec<Trivia<'s>>> {
+    repeat(
+        0..,
+        alt((
+            node_space.take().map(Trivia::Text),
+            slashdash.take().map(Trivia::Text),
+            alt((prop, value))
+                .verify_map(|entry| entry)
+                .map(Trivia::Entry),
+            node_children.map(Trivia::Children),
+        )),
+    )
+    .parse_next(input)
+}
+
 /// `nodes := (line-space* node)* line-space*`
 fn nodes(input: &mut Input<'_>) -> PResult<KdlDocument> {
-    let (leading, (nodes, _span), _final_terminator, trailing) = (
-        repeat(0.., alt((line_space.void(), (slashdash, base_node).void())))
-            .map(|()| ())
-            .take(),
-        separated(0.., node, node_terminator).with_span(),
-        opt(node_terminator),
-        repeat(0.., alt((line_space.void(), (slashdash, base_node).void())))
-            .map(|()| ())
-            .take(),
-    )
+    let leading = repeat(0.., alt((line_space.void(), slashdashed_node)))
+        .map(|()| ())
+        .take()
+        .parse_next(input)?;
+    let (nodes, _span) = separated(0.., recovering_node, node_terminator)
+        .with_span()
+        .parse_next(input)?;
+    let nodes: Vec<Option<KdlNode>> = nodes;
+    // The last node's terminator is already part of its format. Without any
+    // nodes, there's nothing for a terminator to terminate, so it's left to
+    // fail below instead of being silently dropped.
+    if !nodes.is_empty() {
+        opt(node_terminator).parse_next(input)?;
+    }
+    let trailing = repeat(0.., alt((line_space.void(), slashdashed_node)))
+        .map(|()| ())
+        .take()
         .parse_next(input)?;
+    let nodes = nodes.into_iter().flatten().collect();
     Ok(KdlDocument {
         nodes,
-        format: Some(KdlDocumentFormat {
-            leading: leading.into(),
-            trailing: trailing.into(),
+        format: input.state.keep_format().then(|| KdlDocumentFormat {
+            leading: input.state.trivia(leading),
+            trailing: input.state.trivia(trailing),
         }),
+        version: KdlVersion::V2,
         #[cfg(feature = "span")]
         span: _span.into(),
     })
 }
 
+/// A node, or, if [`ParseOptions::collect_all_diagnostics`] is set and the
+/// node is broken, `None` after recording the error and skipping to the
+/// node's terminator.
+///
+/// Also where parsing stops early once enough errors have been recorded, if
+/// [`ParseOptions::stop_at_max_diagnostics`] is set.
+fn recovering_node(input: &mut Input<'_>) -> PResult<Option<KdlNode>> {
+    if input.state.should_stop() {
+        return Err(ErrMode::Cut(KdlParseError::from_input(input)));
+    }
+    if !input.state.collect_all_diagnostics {
+        return node.map(Some).parse_next(input);
+    }
+    let start = input.checkpoint();
+    let err = match node.parse_next(input) {
+        Ok(node) => return Ok(Some(node)),
+        Err(ErrMode::Cut(err)) => err,
+        Err(err) => {
+            // Only garbage where a node should be counts as a broken node.
+            // Closing braces and the end of input just mean there are no
+            // more nodes here.
+            let err_start = input.checkpoint();
+            repeat(0.., line_space).map(|()| ()).parse_next(input)?;
+            let at_end = peek(opt(alt(("}".void(), eof.void()))))
+                .parse_next(input)?
+                .is_some();
+            input.reset(&err_start);
+            match err {
+                ErrMode::Backtrack(err) if !at_end => err,
+                _ => {
+                    input.reset(&start);
+                    return Err(err);
+                }
+            }
+        }
+    };
+    let err_start = input.checkpoint();
+    let err_location = input.location();
+    let (_, rest) = input.peek_finish();
+    input.next_slice(bad_node_len(rest));
+    let span = err
+        .span
+        .unwrap_or_else(|| (err_location..input.location()).into());
+    record_err(
+        input,
+        &start,
+        &err_start,
+        ErrMode::Cut(KdlParseError {
+            span: Some(span),
+            ..err
+        }),
+    )?;
+    Ok(None)
+}
+
+/// Length of the rest of a broken node, up to (but not including) its node
+/// terminator or the closing brace of the block it's in. Strings, comments,
+/// and children blocks are skipped over as a whole, as far as the tokenizer
+/// can make them out.
+fn bad_node_len(text: &str) -> usize {
+    let mut depth = 0usize;
+    for token in crate::tokenize(text) {
+        let start = token.span.offset();
+        let token_text = &text[start..start + token.span.len()];
+        match token.kind {
+            TokenKind::Newline if depth == 0 => return start,
+            TokenKind::Comment if depth == 0 && token_text.starts_with("//") => return start,
+            TokenKind::Punctuation => match token_text {
+                ";" | "}" if depth == 0 => return start,
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => {}
+            },
+            // Unrecognized text runs on up to the next whitespace, which
+            // might be past the end of the node.
+            TokenKind::Error if depth == 0 => {
+                if let Some(end) = token_text.find(|c| c == ';' || c == '}') {
+                    return start + end;
+                }
+            }
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+#[cfg(test)]
+#[test]
+fn bad_node_len_test() {
+    for (text, len) in [
+        ("x y\nz", 3),
+        ("x; z", 1),
+        ("x } z", 2),
+        ("x // c\nz", 2),
+        ("x \"a; }\" y\nz", 10),
+        ("x /* \n; */ y;", 12),
+        ("x { a; b\n } y;", 13),
+        ("x #\"a;b\"# y;", 11),
+        ("0x;z", 2),
+        ("x { y", 5),
+    ] {
+        assert_eq!(bad_node_len(text), len, "{text:?}");
+    }
+}
+
 /// base-node := slashdash? type? node-space* string
 ///      (node-space+ slashdash? node-prop-or-arg)*
 ///      (node-space+ slashdash node-children)*
@@ -258,34 +664,42 @@ fn nodes(input: &mut Input<'_>) -> PResult<KdlDocument> {
 /// node := base-node node-space* node-terminator
 /// final-node := base-node node-space* node-terminator?
 fn node(input: &mut Input<'_>) -> PResult<KdlNode> {
-    let leading = repeat(0.., alt((line_space.void(), (slashdash, base_node).void())))
+    let leading = repeat(0.., alt((line_space.void(), slashdashed_node)))
         .map(|()| ())
         .take()
         .parse_next(input)?;
     let mut nd = base_node.parse_next(input)?;
     if let Some(fmt) = nd.format_mut() {
-        fmt.leading = leading.into();
+        fmt.leading = input.state.trivia(leading);
     }
     Ok(nd)
 }
 
 fn base_node(input: &mut Input<'_>) -> PResult<KdlNode> {
-    let ((ty, after_ty, name, entries, children), _span) = (
+    let start = input.location();
+    let (ty, after_ty, (name, name_span), entries) = (
         opt(ty),
-        node_space0.take(),
-        identifier,
-        repeat(
-            0..,
-            (peek(node_space1), node_entry).map(|(_, e): ((), _)| e),
-        )
-        .map(|e: Vec<Option<KdlEntry>>| e.into_iter().flatten().collect::<Vec<KdlEntry>>()),
-        opt((before_node_children.take(), node_children)),
+        terminated(node_space0.take(), misplaced_comment),
+        identifier.with_span(),
+        node_entries,
     )
-        .with_span()
         .parse_next(input)?;
+    if let Some(max) = input.state.max_entries_per_node {
+        if peek(opt(spaced_node_entry)).parse_next(input)?.is_some() {
+            return Err(ErrMode::Cut(KdlParseError {
+                context: None,
+                span: Some(name_span.into()),
+                label: Some("node with too many entries"),
+                help: Some("Split this node's entries up, or raise `max_entries_per_node`."),
+                kind: Some(KdlErrorKind::TooManyEntries(max)),
+            }));
+        }
+    }
+    let children = opt((before_node_children.take(), node_children)).parse_next(input)?;
+    let _span = start..input.location();
     let (before_terminator, terminator) = if children.is_some() {
         (
-            opt(slashdashed_children).take(),
+            (opt(slashdashed_children), node_space0).take(),
             peek(opt(node_terminator).take()),
         )
             .parse_next(input)?
@@ -298,27 +712,44 @@ fn base_node(input: &mut Input<'_>) -> PResult<KdlNode> {
     };
     let (before_inner_ty, ty, after_inner_ty) = ty.unwrap_or_default();
     let (before_children, children) = children
-        .map(|(before_children, children)| (before_children.into(), Some(children)))
+        .map(|(before_children, children)| (input.state.trivia(before_children), Some(children)))
         .unwrap_or(("".into(), None));
     Ok(KdlNode {
         ty,
         name,
         entries,
         children,
-        format: Some(KdlNodeFormat {
-            before_ty_name: before_inner_ty.into(),
-            after_ty_name: after_inner_ty.into(),
-            after_ty: after_ty.into(),
+        format: input.state.keep_format().then(|| KdlNodeFormat {
+            before_ty_name: input.state.trivia(before_inner_ty),
+            after_ty_name: input.state.trivia(after_inner_ty),
+            after_ty: input.state.trivia(after_ty),
             before_children,
-            before_terminator: before_terminator.into(),
-            terminator: terminator.into(),
+            before_terminator: input.state.trivia(before_terminator),
+            terminator: input.state.trivia(terminator),
             ..Default::default()
         }),
         #[cfg(feature = "span")]
         span: _span.into(),
+        #[cfg(feature = "user-data")]
+        user_data: Default::default(),
     })
 }
 
+/// A node's entries, up to `max_entries_per_node` of them, if set.
+fn node_entries(input: &mut Input<'_>) -> PResult<Vec<KdlEntry>> {
+    let entries: Vec<Option<KdlEntry>> = match input.state.max_entries_per_node {
+        Some(max) => repeat(0..=max, spaced_node_entry).parse_next(input)?,
+        None => repeat(0.., spaced_node_entry).parse_next(input)?,
+    };
+    Ok(entries.into_iter().flatten().collect())
+}
+
+pub(crate) fn spaced_node_entry(input: &mut Input<'_>) -> PResult<Option<KdlEntry>> {
+    (peek(node_space1), node_entry)
+        .map(|(_, e): ((), _)| e)
+        .parse_next(input)
+}
+
 #[cfg(test)]
 #[test]
 fn test_node() {
@@ -334,7 +765,9 @@ fn test_node() {
             entries: vec![],
             children: None,
             format: Some(Default::default()),
-            span: (0..7).into()
+            span: (0..7).into(),
+            #[cfg(feature = "user-data")]
+            user_data: Default::default(),
         }
     );
 
@@ -362,11 +795,122 @@ fn test_node() {
             format: Some(KdlNodeFormat {
                 ..Default::default()
             }),
-            span: (0..8).into()
+            span: (0..8).into(),
+            #[cfg(feature = "user-data")]
+            user_data: Default::default(),
         }
     );
 }
 
+#[cfg(test)]
+#[test]
+fn missing_closing_brace_test() {
+    let input = "a {\n    b 1\n    c {\n        d 2\n";
+    let err = try_parse(document, input).unwrap_err();
+    assert_eq!(err.diagnostics.len(), 2);
+    let mut spans = err
+        .diagnostics
+        .iter()
+        .map(|diag| {
+            assert!(matches!(diag.kind, KdlErrorKind::MissingClosingBrace));
+            assert_eq!(diag.label, Some("block opened here"));
+            diag.span
+        })
+        .collect::<Vec<_>>();
+    spans.sort_by_key(|span| span.offset());
+    assert_eq!(spans, vec![(2..3).into(), (18..19).into()]);
+    assert_eq!(
+        err.diagnostics[0].to_string(),
+        "Missing closing brace for block opened here."
+    );
+
+    // The rest of the document is still there.
+    let (doc, errs) = recoverable_document("a {\n    b 1\n    c {\n        d 2\n");
+    assert_eq!(errs.len(), 2);
+    let a = doc.unwrap().nodes()[0].clone();
+    assert_eq!(a.child(0).map(|b| b.name().value()), Some("b"));
+    assert_eq!(
+        a.child(1)
+            .and_then(|c| c.child(0))
+            .map(|d| d.entries().len()),
+        Some(1)
+    );
+
+    // Anywhere but the end, a missing `}` is still a plain syntax error.
+    let err = try_parse(document, "a { b 1 ) c").unwrap_err();
+    assert_eq!(err.diagnostics.len(), 1);
+    assert!(!matches!(
+        err.diagnostics[0].kind,
+        KdlErrorKind::MissingClosingBrace
+    ));
+}
+
+#[cfg(test)]
+fn recoverable_document(input: &str) -> (Option<KdlDocument>, Vec<KdlParseError>) {
+    let (_, doc, errs) = document.recoverable_parse(Stateful {
+        input: LocatingSlice::new(input),
+        state: ParseState::new(&DEFAULT_PARSE_OPTIONS, input),
+    });
+    (doc, errs)
+}
+
+#[cfg(test)]
+#[test]
+fn misplaced_comments_test() {
+    for (input, offset) in [
+        ("node key=// comment\n1", 9),
+        ("node key= // comment\n1", 10),
+        ("node (t)// comment\n1", 8),
+        ("(t) // comment\nnode", 4),
+        ("node (// comment\nt)1", 6),
+        ("node (t // comment\n)1", 8),
+    ] {
+        let err = try_parse(document, input).unwrap_err();
+        assert_eq!(err.diagnostics.len(), 1, "{input:?}");
+        let diag = &err.diagnostics[0];
+        assert!(
+            matches!(diag.kind, KdlErrorKind::MisplacedComment),
+            "{input:?}: {:?}",
+            diag.kind
+        );
+        assert_eq!(diag.span, (offset, 2).into(), "{input:?}");
+    }
+    assert_eq!(
+        try_parse(document, "node key=// c\n1")
+            .unwrap_err()
+            .diagnostics[0]
+            .to_string(),
+        "Comments aren't allowed here; move it to its own line or before the node."
+    );
+
+    // They're fine where they end a node, and block comments are fine
+    // anywhere whitespace is.
+    for input in [
+        "node 1 // comment",
+        "node key=1// comment",
+        "node // comment\nother",
+        "node key=/* comment */1 (/* a */t/* b */)/* c */2",
+    ] {
+        assert!(try_parse(document, input).is_ok(), "{input:?}");
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn space_after_children_test() {
+    for input in [
+        "a {} // comment\nb",
+        "a { c; } /* comment */\nb",
+        "a {}  \nb",
+        "a {} /- {} ;b",
+        "a {} \\\n  ;b",
+    ] {
+        let doc = try_parse(document, input).unwrap();
+        assert_eq!(doc.nodes().len(), 2, "{input:?}");
+        assert_eq!(doc.to_string(), input);
+    }
+}
+
 pub(crate) fn padded_node(input: &mut Input<'_>) -> PResult<KdlNode> {
     let ((mut node, _terminator, trailing), _span) = (
         node,
@@ -378,7 +922,7 @@ pub(crate) fn padded_node(input: &mut Input<'_>) -> PResult<KdlNode> {
         .with_span()
         .parse_next(input)?;
     if let Some(fmt) = node.format_mut() {
-        fmt.trailing = trailing.into();
+        fmt.trailing = input.state.trivia(trailing);
     }
     #[cfg(feature = "span")]
     {
@@ -389,7 +933,10 @@ pub(crate) fn padded_node(input: &mut Input<'_>) -> PResult<KdlNode> {
 
 pub(crate) fn padded_node_entry(input: &mut Input<'_>) -> PResult<KdlEntry> {
     let ((leading, entry, trailing), _span) = (
-        repeat(0.., line_space).map(|_: ()| ()).take(),
+        terminated(
+            repeat(0.., line_space).map(|_: ()| ()).take(),
+            not_end_of_input("entry"),
+        ),
         node_entry,
         repeat(0.., alt((line_space, node_space)))
             .map(|_: ()| ())
@@ -397,10 +944,11 @@ pub(crate) fn padded_node_entry(input: &mut Input<'_>) -> PResult<KdlEntry> {
     )
         .with_span()
         .parse_next(input)?;
+    end_of_input("entry").parse_next(input)?;
     if let Some(entry) = entry.map(|mut val| {
         if let Some(fmt) = val.format_mut() {
-            fmt.leading = format!("{leading}{}", fmt.leading);
-            fmt.trailing = format!("{}{trailing}", fmt.trailing);
+            fmt.leading = format!("{leading}{}", fmt.leading).into();
+            fmt.trailing = format!("{}{trailing}", fmt.trailing).into();
         }
         #[cfg(feature = "span")]
         {
@@ -414,6 +962,65 @@ pub(crate) fn padded_node_entry(input: &mut Input<'_>) -> PResult<KdlEntry> {
     }
 }
 
+/// A single value, optionally type-annotated, surrounded by whitespace and
+/// comments. The type annotation is accepted but not part of the result.
+pub(crate) fn padded_value(input: &mut Input<'_>) -> PResult<KdlValue> {
+    let entry = delimited(
+        (
+            repeat(0.., line_space).map(|_: ()| ()),
+            not_end_of_input("value"),
+        ),
+        value,
+        repeat(0.., alt((line_space, node_space))).map(|_: ()| ()),
+    )
+    .parse_next(input)?;
+    end_of_input("value").parse_next(input)?;
+    match entry {
+        Some(entry) => Ok(entry.value),
+        None => fail.parse_next(input),
+    }
+}
+
+/// Fails with a clear error when there's nothing left to parse, instead of
+/// whatever the first thing that was tried expected. `what` names what
+/// should be here.
+pub(crate) fn not_end_of_input<'a>(
+    what: &'static str,
+) -> impl Parser<Input<'a>, (), KdlParseError> {
+    move |input: &mut Input<'a>| {
+        let start = input.location();
+        if peek(opt(eof)).parse_next(input)?.is_some() {
+            return Err(ErrMode::Cut(KdlParseError {
+                context: Some(what),
+                span: Some((start..start).into()),
+                label: Some("input ends here"),
+                help: None,
+                kind: None,
+            }));
+        }
+        Ok(())
+    }
+}
+
+/// Fails with a [`KdlErrorKind::TrailingInput`] error pointing at whatever's
+/// left, unless the input's all been parsed. `what` names what was parsed.
+pub(crate) fn end_of_input<'a>(what: &'static str) -> impl Parser<Input<'a>, (), KdlParseError> {
+    move |input: &mut Input<'a>| {
+        let start = input.location();
+        if opt(eof).parse_next(input)?.is_none() {
+            let rest = input.eof_offset();
+            return Err(ErrMode::Cut(KdlParseError {
+                context: None,
+                span: Some((start..start + rest).into()),
+                label: Some("unexpected input"),
+                help: Some("Only one item can be parsed here. Parse a document to read several."),
+                kind: Some(KdlErrorKind::TrailingInput(what)),
+            }));
+        }
+        Ok(())
+    }
+}
+
 /// `node-prop-or-arg := prop | value`
 fn node_entry(input: &mut Input<'_>) -> PResult<Option<KdlEntry>> {
     let (leading, mut entry) = (
@@ -423,7 +1030,7 @@ fn node_entry(input: &mut Input<'_>) -> PResult<Option<KdlEntry>> {
         .parse_next(input)?;
     entry = entry.map(|mut e| {
         if let Some(fmt) = e.format_mut() {
-            fmt.leading = leading.into();
+            fmt.leading = input.state.trivia(leading);
         }
         e
     });
@@ -498,7 +1105,7 @@ fn entry_test() {
             format: Some(KdlEntryFormat {
                 value_repr: "2".into(),
                 leading: "/- foo=1 ".into(),
-                after_ty: " ".into(),
+                after_key: " ".into(),
                 after_eq: " ".into(),
                 ..Default::default()
             }),
@@ -519,7 +1126,7 @@ fn entry_test() {
             format: Some(KdlEntryFormat {
                 value_repr: "2".into(),
                 leading: "/- \nfoo = 1 ".into(),
-                after_ty: " ".into(),
+                after_key: " ".into(),
                 after_eq: " ".into(),
                 ..Default::default()
             }),
@@ -528,7 +1135,7 @@ fn entry_test() {
     );
 }
 
-fn before_node_children(input: &mut Input<'_>) -> PResult<()> {
+pub(crate) fn before_node_children(input: &mut Input<'_>) -> PResult<()> {
     alt((
         (
             node_space1,
@@ -555,7 +1162,7 @@ fn before_node_children_test() {
     assert!(before_node_children.parse(new_input(" /- { bar }")).is_ok());
 }
 
-fn slashdashed_children(input: &mut Input<'_>) -> PResult<()> {
+pub(crate) fn slashdashed_children(input: &mut Input<'_>) -> PResult<()> {
     node_space0.parse_next(input)?;
     separated(
         1..,
@@ -574,12 +1181,65 @@ fn around_children_test() {
 }
 
 /// `node-children := '{' nodes final-node? '}'`
+///
+/// A block that's still open at the end of the input is closed implicitly,
+/// after recording an error pointing at its `{`, so that editors still get
+/// the rest of the document's structure.
 fn node_children(input: &mut Input<'_>) -> PResult<KdlDocument> {
-    delimited("{", nodes, cut_err("}")).parse_next(input)
+    let open = input.location();
+    "{".parse_next(input)?;
+    let children = nested(input, open, nodes)?;
+    if opt("}").parse_next(input)?.is_none() {
+        let at_eof = input.checkpoint();
+        if opt(eof).parse_next(input)?.is_none() {
+            // Anywhere else, it's just broken.
+            cut_err("}").parse_next(input)?;
+        }
+        record_err(
+            input,
+            &at_eof,
+            &at_eof,
+            ErrMode::Cut(KdlParseError {
+                context: None,
+                span: Some((open..open + 1).into()),
+                label: Some("block opened here"),
+                help: Some("Add a `}` to close this block."),
+                kind: Some(KdlErrorKind::MissingClosingBrace),
+            }),
+        )?;
+    }
+    Ok(children)
+}
+
+/// Runs `inner` on the inside of a children block whose `{` is at `open`,
+/// one level deeper than before. Fails with [`KdlErrorKind::TooDeep`] instead
+/// if that's deeper than [`ParseOptions::max_depth`] allows, since every level
+/// costs another round of recursion.
+pub(crate) fn nested<'s, T>(
+    input: &mut Input<'s>,
+    open: usize,
+    mut inner: impl Parser<Input<'s>, T, KdlParseError>,
+) -> PResult<T> {
+    let depth = input.state.depth.get() + 1;
+    if let Some(max) = input.state.max_depth {
+        if depth > max {
+            return Err(ErrMode::Cut(KdlParseError {
+                context: None,
+                span: Some((open..open + 1).into()),
+                label: Some("children block nested too deep"),
+                help: Some("Flatten the document, or raise `max_depth`."),
+                kind: Some(KdlErrorKind::TooDeep(max)),
+            }));
+        }
+    }
+    input.state.depth.set(depth);
+    let result = inner.parse_next(input);
+    input.state.depth.set(depth - 1);
+    result
 }
 
 /// `node-terminator := single-line-comment | newline | ';' | eof`
-fn node_terminator(input: &mut Input<'_>) -> PResult<()> {
+pub(crate) fn node_terminator(input: &mut Input<'_>) -> PResult<()> {
     alt((";".void(), newline, single_line_comment)).parse_next(input)
 }
 
@@ -589,7 +1249,7 @@ fn prop(input: &mut Input<'_>) -> PResult<Option<KdlEntry>> {
         identifier,
         node_space0.take(),
         equals_sign.take(),
-        node_space0.take(),
+        terminated(node_space0.take(), misplaced_comment),
         cut_err(value),
     )
         .with_span()
@@ -597,8 +1257,8 @@ fn prop(input: &mut Input<'_>) -> PResult<Option<KdlEntry>> {
     Ok(value.map(|mut value| {
         value.name = Some(key);
         if let Some(fmt) = value.format_mut() {
-            fmt.after_ty = after_key.into();
-            fmt.after_eq = after_eq.into();
+            fmt.after_key = input.state.trivia(after_key);
+            fmt.after_eq = input.state.trivia(after_eq);
         }
         #[cfg(feature = "span")]
         {
@@ -608,10 +1268,15 @@ fn prop(input: &mut Input<'_>) -> PResult<Option<KdlEntry>> {
     }))
 }
 
+/// A bare value, without a type annotation: `string | number | keyword`.
+pub(crate) fn bare_value(input: &mut Input<'_>) -> PResult<Option<KdlValue>> {
+    alt((keyword.map(Some), number.map(Some), string)).parse_next(input)
+}
+
 /// `value := type? optional-node-space (string | number | keyword)`
 fn value(input: &mut Input<'_>) -> PResult<Option<KdlEntry>> {
     let ((ty, (value, raw)), _span) = (
-        opt((ty, node_space0.take())),
+        opt((ty, terminated(node_space0.take(), misplaced_comment))),
         alt((keyword.map(Some), number.map(Some), string)).with_taken(),
     )
         .with_span()
@@ -621,11 +1286,12 @@ fn value(input: &mut Input<'_>) -> PResult<Option<KdlEntry>> {
         ty,
         value,
         name: None,
-        format: Some(KdlEntryFormat {
+        format: input.state.keep_format().then(|| KdlEntryFormat {
             value_repr: raw.into(),
-            after_ty: after_ty.into(),
-            before_ty_name: before_ty_name.into(),
-            after_ty_name: after_ty_name.into(),
+            after_ty: input.state.trivia(after_ty),
+            before_ty_name: input.state.trivia(before_ty_name),
+            after_ty_name: input.state.trivia(after_ty_name),
+            keep_explicit_sign: input.state.keep_explicit_signs,
             ..Default::default()
         }),
         #[cfg(feature = "span")]
@@ -634,19 +1300,38 @@ fn value(input: &mut Input<'_>) -> PResult<Option<KdlEntry>> {
 }
 
 /// `type := '(' optional-node-space string optional-node-space ')'`
-fn ty<'s>(input: &mut Input<'s>) -> PResult<(&'s str, Option<KdlIdentifier>, &'s str)> {
+pub(crate) fn ty<'s>(input: &mut Input<'s>) -> PResult<(&'s str, Option<KdlIdentifier>, &'s str)> {
     "(".parse_next(input)?;
-    let (before_ty, ty, after_ty) = (
-        node_space0.take(),
-        cut_err(identifier.context(lbl("type name")))
-            .resume_after((badval, peek(")").void(), badval).void()),
-        node_space0.take(),
-    )
-        .parse_next(input)?;
+    let before_ty = terminated(node_space0.take(), misplaced_comment).parse_next(input)?;
+    let ty = resume_after(
+        input,
+        cut_err(identifier.context(lbl("type name"))),
+        (badval, peek(")").void(), badval).void(),
+    )?;
+    let after_ty = terminated(node_space0.take(), misplaced_comment).parse_next(input)?;
     cut_err(")").parse_next(input)?;
     Ok((before_ty, ty, after_ty))
 }
 
+/// Fails with a targeted error if there's a single-line comment right here,
+/// where a value, identifier, or type annotation's parenthesis is expected.
+/// These only ever end nodes, so anywhere else inside one, they're a common
+/// mistake that would otherwise get a confusing error about what was
+/// expected instead.
+pub(crate) fn misplaced_comment(input: &mut Input<'_>) -> PResult<()> {
+    let start = input.location();
+    if peek(opt("//")).parse_next(input)?.is_some() {
+        return Err(ErrMode::Cut(KdlParseError {
+            context: None,
+            span: Some((start..start + 2).into()),
+            label: Some("comment"),
+            help: Some("Use a /* block comment */ if it has to stay on this line."),
+            kind: Some(KdlErrorKind::MisplacedComment),
+        }));
+    }
+    Ok(())
+}
+
 /// `line-space := newline | ws | single-line-comment`
 fn line_space(input: &mut Input<'_>) -> PResult<()> {
     alt((newline, ws, single_line_comment)).parse_next(input)
@@ -657,7 +1342,7 @@ fn node_space(input: &mut Input<'_>) -> PResult<()> {
     alt(((wss, escline, wss).void(), wsp)).parse_next(input)
 }
 
-fn node_space0(input: &mut Input<'_>) -> PResult<()> {
+pub(crate) fn node_space0(input: &mut Input<'_>) -> PResult<()> {
     repeat(0.., node_space).parse_next(input)
 }
 
@@ -683,7 +1368,9 @@ pub(crate) fn identifier(input: &mut Input<'_>) -> PResult<KdlIdentifier> {
         .with_taken()
         .with_span()
         .parse_next(input)?;
-    ident.set_repr(raw);
+    if input.state.keep_format() {
+        ident.set_repr(raw);
+    }
     #[cfg(feature = "span")]
     {
         ident.set_span(_span);
@@ -773,71 +1460,38 @@ fn equals_sign(input: &mut Input<'_>) -> PResult<()> {
 fn quoted_string<'s>(input: &mut Input<'s>) -> PResult<Option<KdlValue>> {
     let quotes = alt((("\"\"\"", newline).take(), "\"")).parse_next(input)?;
     let is_multiline = quotes.len() > 1;
-    let ml_prefix: Option<String> = if is_multiline {
-        Some(
-            peek(preceded(
-                repeat_till(
-                    0..,
-                    (
-                        repeat(0.., (not(newline), opt(ws_escape), string_char)).map(|()| ()),
-                        newline,
-                    ),
-                    peek(terminated(
-                        repeat(0.., unicode_space).map(|()| ()),
-                        "\"\"\"",
-                    )),
+    let body: Option<String> = if is_multiline {
+        let remaining = peek(rest).parse_next(input)?;
+        let prefix = match ml_prefix(remaining, "\"\"\"", true) {
+            Some(prefix) => prefix,
+            None => return fail.parse_next(input),
+        };
+        resume_after(
+            input,
+            |input: &mut Input<'s>| {
+                dedented_lines(
+                    input,
+                    prefix,
+                    "\"\"\"",
+                    "matching multiline string prefix",
+                    |c| c != '\\' && c != '"',
+                    |input| preceded(opt(ws_escape), string_char).parse_next(input),
                 )
-                .map(|((), ())| ()),
-                terminated(repeat(0.., unicode_space).map(|()| ()).take(), "\"\"\""),
-            ))
-            .parse_next(input)?
-            .to_string(),
-        )
+            },
+            quoted_string_badval,
+        )?
     } else {
-        None
-    };
-    let body: Option<String> = if let Some(prefix) = ml_prefix {
-        repeat_till(
-            0..,
-            (
-                cut_err(alt((&prefix[..], peek(newline).take())))
-                    .context(lbl("matching multiline string prefix")),
-                alt((
-                    newline.take().map(|_| "\n".to_string()),
-                    repeat_till(
-                        0..,
-                        (not(newline), opt(ws_escape), string_char).map(|(_, _, s)| s),
-                        newline,
-                    )
-                    // multiline string literal newlines are normalized to `\n`
-                    .map(|(s, _): (String, _)| format!("{s}\n")),
-                )),
+        resume_after(
+            input,
+            repeat_till(
+                0..,
+                (not(newline), opt(ws_escape), string_char).map(|(_, _, s)| s),
+                (repeat(0.., unicode_space).map(|()| ()).take(), peek("\"")),
             )
-                .map(|(_, s)| s),
-            (
-                &prefix[..],
-                repeat(0.., unicode_space).map(|()| ()).take(),
-                peek("\"\"\""),
-            ),
-        )
-        .map(|(s, _): (Vec<String>, (_, _, _))| {
-            let mut s = s.join("");
-            // Slice off the `\n` at the end of the last line.
-            s.truncate(s.len() - 1);
-            s
-        })
-        .resume_after(quoted_string_badval)
-        .parse_next(input)?
-    } else {
-        repeat_till(
-            0..,
-            (not(newline), opt(ws_escape), string_char).map(|(_, _, s)| s),
-            (repeat(0.., unicode_space).map(|()| ()).take(), peek("\"")),
-        )
-        .map(|(s, (end, _)): (String, (&'s str, _))| format!("{s}{end}"))
-        .context(lbl("quoted string"))
-        .resume_after(quoted_string_badval)
-        .parse_next(input)?
+            .map(|(s, (end, _)): (String, (&'s str, _))| format!("{s}{end}"))
+            .context(lbl("quoted string")),
+            quoted_string_badval,
+        )?
     };
     let closing_quotes = if is_multiline {
         "\"\"\"".context(lbl("multiline string closing quotes"))
@@ -848,6 +1502,86 @@ fn quoted_string<'s>(input: &mut Input<'s>) -> PResult<Option<KdlValue>> {
     Ok(body.map(KdlValue::String))
 }
 
+/// Finds the indentation prefix of a multi-line string whose body starts at
+/// the beginning of `rest`: the whitespace between the last newline and the
+/// closing quotes, `close`. This is only a quick scan for the end of the
+/// string, with `escapes` saying whether a backslash escapes the character
+/// after it. Returns `None` if the closing quotes are missing or aren't on a
+/// line of their own.
+fn ml_prefix<'s>(rest: &'s str, close: &str, escapes: bool) -> Option<&'s str> {
+    let end = if escapes {
+        // The body can't contain a literal `"`, so it ends at the first
+        // unescaped one.
+        let mut chars = rest.char_indices();
+        loop {
+            match chars.next()? {
+                (_, '\\') => {
+                    chars.next();
+                }
+                (idx, '"') => break idx,
+                _ => {}
+            }
+        }
+    } else {
+        rest.find(close)?
+    };
+    if !rest[end..].starts_with(close) {
+        return None;
+    }
+    let body = &rest[..end];
+    let last_line = body
+        .char_indices()
+        .rev()
+        .find(|(_, c)| crate::fmt::is_newline(*c))
+        .map_or(body, |(idx, c)| &body[idx + c.len_utf8()..]);
+    last_line
+        .chars()
+        .all(|c| UNICODE_SPACES.contains(&c))
+        .then(|| last_line)
+}
+
+/// Parses the lines of a multi-line string body up to (but not including)
+/// its closing quotes, `close`, stripping `prefix` from each line as it goes.
+/// Empty lines don't need the prefix, but any other line that doesn't start
+/// with it is an error. Newlines are normalized to `\n`, and the one before
+/// the closing quotes isn't part of the value.
+///
+/// Runs of characters matching `plain` are copied over as-is. Anything else
+/// (other than a newline) is parsed with `special`.
+fn dedented_lines<'s>(
+    input: &mut Input<'s>,
+    prefix: &str,
+    close: &str,
+    context: &'static str,
+    plain: impl Fn(char) -> bool,
+    mut special: impl FnMut(&mut Input<'s>) -> PResult<char>,
+) -> PResult<String> {
+    let plain = |c: char| plain(c) && !crate::fmt::is_newline(c) && !is_disallowed_unicode(c);
+    let mut body = String::new();
+    loop {
+        let closing = opt((prefix, repeat(0.., unicode_space).map(|()| ()), peek(close)))
+            .parse_next(input)?;
+        if closing.is_some() {
+            break;
+        }
+        cut_err(alt((prefix, peek(newline).take())))
+            .context(lbl(context))
+            .parse_next(input)?;
+        loop {
+            body.push_str(take_while(0.., &plain).parse_next(input)?);
+            if opt(newline).parse_next(input)?.is_some() {
+                break;
+            }
+            body.push(special(input)?);
+        }
+        // multiline string literal newlines are normalized to `\n`
+        body.push('\n');
+    }
+    // Slice off the `\n` at the end of the last line.
+    body.pop();
+    Ok(body)
+}
+
 /// Like badval, but is able to slurp up invalid raw strings, which contain whitespace.
 fn quoted_string_badval(input: &mut Input<'_>) -> PResult<()> {
     let terminator = (peek("\""), peek(alt((ws, newline, eof.void()))));
@@ -863,10 +1597,25 @@ fn string_char(input: &mut Input<'_>) -> PResult<char> {
     alt((
         escaped_char,
         (not(disallowed_unicode), none_of(['\\', '"'])).map(|(_, c)| c),
+        disallowed_string_char,
     ))
     .parse_next(input)
 }
 
+/// Fails with a targeted error pointing at a literal disallowed code point,
+/// such as a stray control character, in a string.
+fn disallowed_string_char(input: &mut Input<'_>) -> PResult<char> {
+    let start = input.location();
+    let c = one_of(is_disallowed_unicode).parse_next(input)?;
+    Err(ErrMode::Cut(KdlParseError {
+        context: None,
+        span: Some((start..input.location()).into()),
+        label: Some("disallowed code point"),
+        help: None,
+        kind: Some(KdlErrorKind::DisallowedCodePoint(c as u32)),
+    }))
+}
+
 fn ws_escape(input: &mut Input<'_>) -> PResult<()> {
     (
         "\\",
@@ -893,117 +1642,90 @@ fn escaped_char(input: &mut Input<'_>) -> PResult<char> {
             "t".value('\t'),
             "s".value(' '),
         )),
-        (
-            "u{",
-            cut_err(take_while(1..6, AsChar::is_hex_digit)),
-            cut_err("}"),
-        )
-            .context(lbl("unicode escape char"))
-            .verify_map(|(_, hx, _)| {
-                let val = u32::from_str_radix(hx, 16)
-                    .expect("Should have already been validated to be a hex string.");
-                char::from_u32(val)
-            }),
+        unicode_escape,
     ))
     .parse_next(input)
 }
 
+/// The `u{...}` part of a unicode escape. Escapes that aren't Unicode scalar
+/// values get their own diagnostics, since "expected a string" doesn't say
+/// much about what's wrong with `\u{D800}`.
+fn unicode_escape(input: &mut Input<'_>) -> PResult<char> {
+    let ((_, hx, _), span) = (
+        "u{",
+        cut_err(take_while(1..=6, AsChar::is_hex_digit)),
+        cut_err("}"),
+    )
+        .context(lbl("unicode escape char"))
+        .with_span()
+        .parse_next(input)?;
+    let val = u32::from_str_radix(hx, 16)
+        .expect("Should have already been validated to be a hex string.");
+    char::from_u32(val).ok_or_else(|| {
+        ErrMode::Cut(KdlParseError {
+            context: None,
+            // Include the leading `\`.
+            span: Some((span.start.saturating_sub(1)..span.end).into()),
+            label: Some("invalid unicode escape"),
+            help: Some("Unicode escapes must be at most U+10FFFF, and can't be surrogates (U+D800 through U+DFFF)."),
+            kind: Some(if (0xD800..=0xDFFF).contains(&val) {
+                KdlErrorKind::SurrogateUnicodeEscape(val)
+            } else {
+                KdlErrorKind::OutOfRangeUnicodeEscape(val)
+            }),
+        })
+    })
+}
+
 /// `raw-string := '#' raw-string-quotes '#' | '#' raw-string '#'`
 /// `raw-string-quotes := '"' single-line-raw-string-body '"' | '"""' newline multi-line-raw-string-body newline unicode-space*) '"""'`
 /// `single-line-raw-string-body := (unicode - newline - disallowed-literal-code-points)*`
 /// `multi-line-raw-string-body := (unicode - disallowed-literal-code-points)`
-fn raw_string(input: &mut Input<'_>) -> PResult<Option<KdlValue>> {
+fn raw_string<'s>(input: &mut Input<'s>) -> PResult<Option<KdlValue>> {
     let hashes: String = repeat(1.., "#").parse_next(input)?;
     let quotes = alt((("\"\"\"", newline).take(), "\"")).parse_next(input)?;
     let is_multiline = quotes.len() > 1;
-    dbg!(&quotes);
-    dbg!(is_multiline);
-    let ml_prefix: Option<String> = if is_multiline {
-        Some(
-            peek(preceded(
-                repeat_till(
-                    0..,
-                    (
-                        repeat(
-                            0..,
-                            (
-                                not(newline),
-                                not(disallowed_unicode),
-                                not(("\"\"\"", &hashes[..])),
-                                any,
-                            ),
-                        )
-                        .map(|()| ()),
-                        newline,
-                    ),
-                    peek(terminated(
-                        repeat(0.., unicode_space).map(|()| ()),
-                        ("\"\"\"", &hashes[..]),
-                    )),
+    let body: Option<String> = if is_multiline {
+        let close = format!("\"\"\"{hashes}");
+        let remaining = peek(rest).parse_next(input)?;
+        let prefix = match ml_prefix(remaining, &close, false) {
+            Some(prefix) => prefix,
+            None => return fail.parse_next(input),
+        };
+        resume_after(
+            input,
+            |input: &mut Input<'s>| {
+                dedented_lines(
+                    input,
+                    prefix,
+                    &close,
+                    "matching multiline raw string prefix",
+                    |c| c != '"',
+                    |input| {
+                        preceded((not(&close[..]), not(disallowed_unicode)), any).parse_next(input)
+                    },
                 )
-                .map(|((), ())| ()),
-                terminated(
-                    repeat(0.., unicode_space).map(|()| ()).take(),
-                    ("\"\"\"", &hashes[..]),
-                ),
-            ))
-            .parse_next(input)?
-            .to_string(),
-        )
-    } else {
-        None
-    };
-    dbg!(&ml_prefix);
-    let body: Option<String> = if let Some(prefix) = ml_prefix {
-        repeat_till(
-            0..,
-            (
-                cut_err(alt((&prefix[..], peek(newline).take())))
-                    .context(lbl("matching multiline raw string prefix")),
-                alt((
-                    newline.take().map(|_| "\n".to_string()),
-                    repeat_till(
-                        0..,
-                        (not(newline), not(("\"\"\"", &hashes[..])), any)
-                            .map(|((), (), _)| ())
-                            .take(),
-                        newline,
-                    )
-                    // multiline string literal newlines are normalized to `\n`
-                    .map(|(s, _): (Vec<&str>, _)| format!("{}\n", s.join(""))),
-                )),
-            )
-                .map(|(_, s)| s),
-            (
-                &prefix[..],
-                repeat(0.., unicode_space).map(|()| ()).take(),
-                peek(("\"\"\"", &hashes[..])),
-            ),
-        )
-        .map(|(s, _): (Vec<String>, (_, _, _))| {
-            let mut s = s.join("");
-            // Slice off the `\n` at the end of the last line.
-            s.truncate(s.len() - 1);
-            s
-        })
-        .resume_after(raw_string_badval)
-        .parse_next(input)?
+            },
+            raw_string_badval,
+        )?
     } else {
-        repeat_till(
-            0..,
-            (
-                not(disallowed_unicode),
-                not(newline),
-                not(("\"", &hashes[..])),
-                any,
+        resume_after(
+            input,
+            repeat_till(
+                0..,
+                (
+                    not(disallowed_unicode),
+                    not(newline),
+                    not(("\"", &hashes[..])),
+                    any,
+                )
+                    .map(|(_, _, _, s)| s),
+                peek(("\"", &hashes[..])),
             )
-                .map(|(_, _, _, s)| s),
-            peek(("\"", &hashes[..])),
-        )
-        .map(|(s, _): (String, _)| s)
-        .context(lbl("raw string"))
-        .resume_after(raw_string_badval)
-        .parse_next(input)?
+            .map(|(s, _): (String, _)| s)
+            .context(lbl("raw string")),
+            raw_string_badval,
+        )?
     };
     let closing_quotes = if is_multiline {
         "\"\"\"".context(lbl("multiline raw string closing quotes"))
@@ -1053,6 +1775,120 @@ mod string_tests {
         );
     }
 
+    #[test]
+    fn invalid_unicode_escapes() {
+        assert_eq!(
+            string.parse(new_input("\"\\u{10FFFF}\"")).unwrap(),
+            Some(KdlValue::String("\u{10FFFF}".into()))
+        );
+        let err = try_parse(document, "foo \"a\\u{d800}b\"").unwrap_err();
+        assert_eq!(err.diagnostics.len(), 1);
+        assert_eq!(err.diagnostics[0].span, (6..14).into());
+        assert_eq!(
+            err.diagnostics[0].to_string(),
+            "\\u{D800} is not a valid Unicode scalar value (surrogate codepoints are disallowed)."
+        );
+        let err = try_parse(document, "foo \"\\u{110000}\"").unwrap_err();
+        assert_eq!(err.diagnostics.len(), 1);
+        assert_eq!(err.diagnostics[0].span, (5..15).into());
+        assert!(matches!(
+            err.diagnostics[0].kind,
+            KdlErrorKind::OutOfRangeUnicodeEscape(0x110000)
+        ));
+    }
+
+    #[test]
+    fn integer_overflow() {
+        // The literal always runs from `start` to the end of the input.
+        for (input, start, kind) in [
+            (
+                "node 170141183460469231731687303715884105728",
+                5,
+                IntErrorKind::PosOverflow,
+            ),
+            (
+                "node 1_000_000_000_000_000_000_000_000_000_000_000_000_000",
+                5,
+                IntErrorKind::PosOverflow,
+            ),
+            (
+                "node key=-0x8000_0000_0000_0000_0000_0000_0000_0001",
+                9,
+                IntErrorKind::NegOverflow,
+            ),
+            (
+                "node 0o7777777777777777777777777777777777777777777",
+                5,
+                IntErrorKind::PosOverflow,
+            ),
+            (
+                "node (u8)+0b1_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000",
+                9,
+                IntErrorKind::PosOverflow,
+            ),
+        ] {
+            let err = try_parse(document, input).unwrap_err();
+            assert_eq!(err.diagnostics.len(), 1, "{input:?}");
+            let diag = &err.diagnostics[0];
+            assert_eq!(diag.span, (start, input.len() - start).into(), "{input:?}");
+            match &diag.kind {
+                KdlErrorKind::ParseIntError(e) => assert_eq!(e.kind(), &kind, "{input:?}"),
+                other => panic!("{input:?}: {other:?}"),
+            }
+            assert!(diag.help.unwrap().contains("2^127"));
+        }
+
+        // The extremes themselves are fine, as are floats with huge integer
+        // parts.
+        for (input, value) in [
+            (
+                "-170141183460469231731687303715884105728",
+                KdlValue::Integer(i128::MIN),
+            ),
+            (
+                "0x7fff_ffff_ffff_ffff_ffff_ffff_ffff_ffff",
+                KdlValue::Integer(i128::MAX),
+            ),
+            (
+                "1234567890123456789012345678901234567890.5",
+                KdlValue::Float(1.2345678901234568e39),
+            ),
+            (
+                "-1234567890123456789012345678901234567890e-10",
+                KdlValue::Float(-1.2345678901234568e29),
+            ),
+        ] {
+            assert_eq!(try_parse(padded_value, input).unwrap(), value, "{input:?}");
+        }
+    }
+
+    #[test]
+    fn disallowed_string_chars() {
+        let err = try_parse(document, "foo \"ab\u{7}c\" 1").unwrap_err();
+        assert_eq!(err.diagnostics.len(), 1);
+        assert_eq!(err.diagnostics[0].span, (7..8).into());
+        assert_eq!(
+            err.diagnostics[0].to_string(),
+            "Disallowed code point U+0007 in a string literal; use an escape like \\u{07} instead."
+        );
+        let err = try_parse(document, "foo \"\"\"\n  a\u{200E}\n  \"\"\"").unwrap_err();
+        assert_eq!(err.diagnostics.len(), 1);
+        assert_eq!(err.diagnostics[0].span, (11..14).into());
+        assert!(matches!(
+            err.diagnostics[0].kind,
+            KdlErrorKind::DisallowedCodePoint(0x200E)
+        ));
+        assert_eq!(
+            err.diagnostics[0].to_string(),
+            "Disallowed code point U+200E in a string literal; use an escape like \\u{200E} instead."
+        );
+        // Escapes for them are fine.
+        assert_eq!(
+            string.parse(new_input("\"\\u{7}\"")).unwrap(),
+            Some(KdlValue::String("\u{7}".into()))
+        );
+    }
+
     #[test]
     fn multiline_quoted_string() {
         assert_eq!(
@@ -1097,6 +1933,56 @@ mod string_tests {
         assert!(string
             .parse(new_input("\"\"\"\nfoo\n  bar\n  baz\n  \"\"\""))
             .is_err());
+        assert_eq!(
+            string.parse(new_input("\"\"\"\n\"\"\"")).unwrap(),
+            Some(KdlValue::String("".into()))
+        );
+        assert_eq!(
+            string
+                .parse(new_input("\"\"\"\n  \\\"\\\"\\\"\n  \"\"\""))
+                .unwrap(),
+            Some(KdlValue::String("\"\"\"".into()))
+        );
+        assert!(string.parse(new_input("\"\"\"\nfoo\n")).is_err());
+        assert!(string.parse(new_input("\"\"\"\nfoo \"\"\"")).is_err());
+        let body = "    line\r\n".repeat(10_000);
+        assert_eq!(
+            string
+                .parse(new_input(&format!("\"\"\"\n{body}    \"\"\"")))
+                .unwrap(),
+            Some(KdlValue::String("line\n".repeat(10_000).trim_end().into()))
+        );
+    }
+
+    /// Checks that parsing multi-line strings takes linear time, from 10 KB
+    /// to 10 MB. Timing-dependent, so it only runs when asked for, ideally
+    /// with `cargo test --release multiline_string_scaling -- --ignored
+    /// --nocapture`.
+    #[test]
+    #[ignore = "timing benchmark"]
+    fn multiline_string_scaling() {
+        const TOTAL: usize = 10_000_000;
+        let mut per_byte = Vec::new();
+        for size in [10_000, 100_000, 1_000_000, 10_000_000] {
+            let line = "    some text with an \\\"escape\\\" in it\n";
+            let input = format!("\"\"\"\n{}    \"\"\"", line.repeat(size / line.len()));
+            // Parse small inputs repeatedly, for about the same total work
+            // at every size.
+            let runs = (TOTAL / input.len()).max(1);
+            let start = std::time::Instant::now();
+            for _ in 0..runs {
+                let value = string.parse(new_input(&input)).unwrap();
+                assert!(matches!(value, Some(KdlValue::String(_))));
+            }
+            let nanos = start.elapsed().as_nanos() as f64 / (runs * input.len()) as f64;
+            println!("{:>10} bytes: {nanos:.1} ns/byte", input.len());
+            per_byte.push(nanos);
+        }
+        let (first, last) = (per_byte[0], per_byte[per_byte.len() - 1]);
+        assert!(
+            last < first * 3.0,
+            "{last:.1} ns/byte at 10 MB vs. {first:.1} at 10 KB"
+        );
     }
 
     #[test]
@@ -1137,6 +2023,13 @@ mod string_tests {
             .parse(new_input("#\"\"\"\nfoo\n  bar\n  baz\n  \"\"\"#"))
             .is_err());
 
+        assert_eq!(
+            string
+                .parse(new_input("##\"\"\"\n  \"\"\"#\n  \"\"\"##"))
+                .unwrap(),
+            Some(KdlValue::String("\"\"\"#".into()))
+        );
+        assert!(string.parse(new_input("#\"\"\"\nfoo\n\"\"\"")).is_err());
         assert!(string.parse(new_input("#\"\nfoo\nbar\nbaz\n\"#")).is_err());
         assert!(string.parse(new_input("\"\nfoo\nbar\nbaz\n\"")).is_err());
     }
@@ -1167,8 +2060,42 @@ mod string_tests {
 /// keyword-number := '#inf' | '#-inf' | '#nan'
 /// ````
 fn keyword(input: &mut Input<'_>) -> PResult<KdlValue> {
+    let start = input.location();
     let _ = "#".parse_next(input)?;
     not(one_of(['#', '"'])).parse_next(input)?;
+    if let Some(value) = opt(preceded(
+        "+",
+        alt((
+            Caseless("inf").value(KdlValue::Float(f64::INFINITY)),
+            Caseless("nan").value(KdlValue::Float(f64::NAN)),
+        )),
+    ))
+    .parse_next(input)?
+    {
+        if input.state.accept_signed_inf_nan {
+            return Ok(value);
+        }
+        return Err(ErrMode::Cut(KdlParseError {
+            context: None,
+            span: Some((start..input.location()).into()),
+            label: Some("signed keyword"),
+            help: Some("Write #inf or #nan without a leading +."),
+            kind: Some(KdlErrorKind::SignedInfNan),
+        }));
+    }
+    if !input.state.custom_keywords.is_empty() {
+        let word = peek(take_while(0.., |c| !is_disallowed_ident_char(c))).parse_next(input)?;
+        let custom = input
+            .state
+            .custom_keywords
+            .iter()
+            .find(|(keyword, _)| keyword == word)
+            .map(|(_, value)| value.clone());
+        if let Some(value) = custom {
+            input.next_slice(word.len());
+            return Ok(value);
+        }
+    }
     cut_err(alt((
         Caseless("true").value(KdlValue::Bool(true)),
         Caseless("false").value(KdlValue::Bool(false)),
@@ -1182,7 +2109,7 @@ fn keyword(input: &mut Input<'_>) -> PResult<KdlValue> {
 }
 
 /// `bom := '\u{FEFF}'`
-fn bom(input: &mut Input<'_>) -> PResult<()> {
+pub(crate) fn bom(input: &mut Input<'_>) -> PResult<()> {
     "\u{FEFF}".void().parse_next(input)
 }
 
@@ -1243,7 +2170,7 @@ static NEWLINES: [&str; 7] = [
 ];
 
 /// `newline := <See Table>`
-fn newline(input: &mut Input<'_>) -> PResult<()> {
+pub(crate) fn newline(input: &mut Input<'_>) -> PResult<()> {
     alt(NEWLINES)
         .void()
         .context(lbl("newline"))
@@ -1263,19 +2190,19 @@ fn ws(input: &mut Input<'_>) -> PResult<()> {
     alt((unicode_space, multi_line_comment)).parse_next(input)
 }
 
-static UNICODE_SPACES: [char; 19] = [
+pub(crate) static UNICODE_SPACES: [char; 19] = [
     '\u{0009}', '\u{000B}', '\u{0020}', '\u{00A0}', '\u{1680}', '\u{2000}', '\u{2001}', '\u{2002}',
     '\u{2003}', '\u{2004}', '\u{2005}', '\u{2006}', '\u{2007}', '\u{2008}', '\u{2009}', '\u{200A}',
     '\u{202F}', '\u{205F}', '\u{3000}',
 ];
 
 /// `unicode-space := <See Table>`
-fn unicode_space(input: &mut Input<'_>) -> PResult<()> {
+pub(crate) fn unicode_space(input: &mut Input<'_>) -> PResult<()> {
     one_of(UNICODE_SPACES).void().parse_next(input)
 }
 
 /// `single-line-comment := '//' ^newline* (newline | eof)`
-fn single_line_comment(input: &mut Input<'_>) -> PResult<()> {
+pub(crate) fn single_line_comment(input: &mut Input<'_>) -> PResult<()> {
     "//".parse_next(input)?;
     repeat_till(
         0..,
@@ -1287,7 +2214,7 @@ fn single_line_comment(input: &mut Input<'_>) -> PResult<()> {
 }
 
 /// `multi-line-comment := '/*' commented-block`
-fn multi_line_comment(input: &mut Input<'_>) -> PResult<()> {
+pub(crate) fn multi_line_comment(input: &mut Input<'_>) -> PResult<()> {
     "/*".parse_next(input)?;
     cut_err(commented_block)
         .context(lbl("closing of multi-line comment"))
@@ -1349,10 +2276,13 @@ fn slashdash_tests() {
     assert!(node
         .parse(new_input("/- foo bar\nnode /-1 2 /-3 { x }"))
         .is_ok());
+    assert!(document.parse(new_input("/- a; b")).is_ok());
+    assert!(document.parse(new_input("a; /- b; c")).is_ok());
+    assert!(document.parse(new_input("foo { /- a; b }")).is_ok());
 }
 
 /// `number := keyword-number | hex | octal | binary | decimal`
-fn number(input: &mut Input<'_>) -> PResult<KdlValue> {
+pub(crate) fn number(input: &mut Input<'_>) -> PResult<KdlValue> {
     alt((float_value, integer_value)).parse_next(input)
 }
 
@@ -1367,14 +2297,15 @@ fn float_value(input: &mut Input<'_>) -> PResult<KdlValue> {
 fn float<T: ParseFloat>(input: &mut Input<'_>) -> PResult<T> {
     alt((
         (
-            decimal::<i128>,
-            opt(preceded('.', cut_err(udecimal::<i128>))),
+            signum,
+            udecimal,
+            opt(preceded('.', cut_err(udecimal))),
             Caseless("e"),
             opt(one_of(['-', '+'])),
-            cut_err(udecimal::<i128>),
+            cut_err(udecimal),
         )
             .take(),
-        (decimal::<i128>, '.', cut_err(udecimal::<i128>)).take(),
+        (signum, udecimal, '.', cut_err(udecimal)).take(),
     ))
     .try_map(|float_str| T::parse_float(&str::replace(float_str, "_", "")))
     .context(lbl("float"))
@@ -1419,16 +2350,10 @@ fn integer_value(input: &mut Input<'_>) -> PResult<KdlValue> {
 
 /// Non-float decimal
 fn decimal<T: FromStrRadix + MaybeNegatable>(input: &mut Input<'_>) -> PResult<T> {
+    let start = input.location();
     let positive = signum.parse_next(input)?;
-    udecimal::<T>
-        .try_map(|x| {
-            if positive {
-                Ok(x)
-            } else {
-                x.negated().ok_or(NegativeUnsignedError)
-            }
-        })
-        .parse_next(input)
+    let digits = udecimal.parse_next(input)?;
+    integer_from_digits(input, start, positive, &digits, 10)
 }
 
 #[cfg(test)]
@@ -1441,7 +2366,9 @@ fn decimal_test() {
 }
 
 /// `integer := digit (digit | '_')*`
-fn udecimal<T: FromStrRadix>(input: &mut Input<'_>) -> PResult<T> {
+///
+/// Returns the digits, without any `_`s.
+fn udecimal(input: &mut Input<'_>) -> PResult<String> {
     (
         digit1,
         cut_err(repeat(
@@ -1449,27 +2376,62 @@ fn udecimal<T: FromStrRadix>(input: &mut Input<'_>) -> PResult<T> {
             alt(("_", take_while(1.., AsChar::is_dec_digit).take())),
         )),
     )
-        .try_map(|(l, r): (&str, Vec<&str>)| {
-            T::from_str_radix(&format!("{l}{}", str::replace(&r.join(""), "_", "")), 10)
-        })
+        .map(|(l, r): (&str, Vec<&str>)| format!("{l}{}", str::replace(&r.join(""), "_", "")))
         .parse_next(input)
 }
 
+/// Converts an integer literal's digits, without any `_`s, into a number.
+/// If that fails, such as because it doesn't fit, the error points at the
+/// whole literal, sign and prefix included, which starts at `start`.
+fn integer_from_digits<T: FromStrRadix + MaybeNegatable>(
+    input: &Input<'_>,
+    start: usize,
+    positive: bool,
+    digits: &str,
+    radix: u32,
+) -> PResult<T> {
+    let span = Some((start..input.location()).into());
+    let result = if positive {
+        T::from_str_radix(digits, radix)
+    } else if T::SIGNED {
+        // Parsing the sign along with the digits, rather than negating
+        // afterwards, lets the most negative value through.
+        T::from_str_radix(&format!("-{digits}"), radix)
+    } else {
+        return Err(ErrMode::Cut(KdlParseError {
+            context: None,
+            span,
+            label: Some("negative number"),
+            help: None,
+            kind: Some(KdlErrorKind::NegativeUnsignedError),
+        }));
+    };
+    result.map_err(|e| {
+        let help = match e.kind() {
+            IntErrorKind::PosOverflow | IntErrorKind::NegOverflow => Some(
+                "Integers must be between -2^127 and 2^127 - 1. Use a string for larger numbers.",
+            ),
+            _ => None,
+        };
+        ErrMode::Cut(KdlParseError {
+            context: None,
+            span,
+            label: Some("integer literal"),
+            help,
+            kind: Some(KdlErrorKind::ParseIntError(e)),
+        })
+    })
+}
+
 /// `hex := sign? '0x' hex-digit (hex-digit | '_')*`
 fn hex<T: FromStrRadix + MaybeNegatable>(input: &mut Input<'_>) -> PResult<T> {
+    let start = input.location();
     let positive = signum.parse_next(input)?;
-    uhex::<T>
-        .try_map(|x| {
-            if positive {
-                Ok(x)
-            } else {
-                x.negated().ok_or(NegativeUnsignedError)
-            }
-        })
-        .parse_next(input)
+    let digits = uhex.parse_next(input)?;
+    integer_from_digits(input, start, positive, &digits, 16)
 }
 
-fn uhex<T: FromStrRadix>(input: &mut Input<'_>) -> PResult<T> {
+fn uhex(input: &mut Input<'_>) -> PResult<String> {
     alt(("0x", "0X")).parse_next(input)?;
     cut_err((
         hex_digit1,
@@ -1478,9 +2440,7 @@ fn uhex<T: FromStrRadix>(input: &mut Input<'_>) -> PResult<T> {
             alt(("_", take_while(1.., AsChar::is_hex_digit).take())),
         ),
     ))
-    .try_map(|(l, r): (&str, Vec<&str>)| {
-        T::from_str_radix(&format!("{l}{}", str::replace(&r.join(""), "_", "")), 16)
-    })
+    .map(|(l, r): (&str, Vec<&str>)| format!("{l}{}", str::replace(&r.join(""), "_", "")))
     .context(lbl("hexadecimal"))
     .parse_next(input)
 }
@@ -1513,19 +2473,13 @@ fn test_hex() {
 
 /// `octal := sign? '0o' [0-7] [0-7_]*`
 fn octal<T: FromStrRadix + MaybeNegatable>(input: &mut Input<'_>) -> PResult<T> {
+    let start = input.location();
     let positive = signum.parse_next(input)?;
-    uoctal::<T>
-        .try_map(|x| {
-            if positive {
-                Ok(x)
-            } else {
-                x.negated().ok_or(NegativeUnsignedError)
-            }
-        })
-        .parse_next(input)
+    let digits = uoctal.parse_next(input)?;
+    integer_from_digits(input, start, positive, &digits, 8)
 }
 
-fn uoctal<T: FromStrRadix>(input: &mut Input<'_>) -> PResult<T> {
+fn uoctal(input: &mut Input<'_>) -> PResult<String> {
     alt(("0o", "0O")).parse_next(input)?;
     cut_err((
         oct_digit1,
@@ -1534,9 +2488,7 @@ fn uoctal<T: FromStrRadix>(input: &mut Input<'_>) -> PResult<T> {
             alt(("_", take_while(1.., AsChar::is_oct_digit).take())),
         ),
     ))
-    .try_map(|(l, r): (&str, Vec<&str>)| {
-        T::from_str_radix(&format!("{l}{}", str::replace(&r.join(""), "_", "")), 8)
-    })
+    .map(|(l, r): (&str, Vec<&str>)| format!("{l}{}", str::replace(&r.join(""), "_", "")))
     .context(lbl("octal"))
     .parse_next(input)
 }
@@ -1552,26 +2504,18 @@ fn test_octal() {
 
 /// `binary := sign? '0b' ('0' | '1') ('0' | '1' | '_')*`
 fn binary<T: FromStrRadix + MaybeNegatable>(input: &mut Input<'_>) -> PResult<T> {
+    let start = input.location();
     let positive = signum.parse_next(input)?;
-    ubinary::<T>
-        .try_map(|x| {
-            if positive {
-                Ok(x)
-            } else {
-                x.negated().ok_or(NegativeUnsignedError)
-            }
-        })
-        .parse_next(input)
+    let digits = ubinary.parse_next(input)?;
+    integer_from_digits(input, start, positive, &digits, 2)
 }
 
-fn ubinary<T: FromStrRadix>(input: &mut Input<'_>) -> PResult<T> {
+fn ubinary(input: &mut Input<'_>) -> PResult<String> {
     alt(("0b", "0B")).parse_next(input)?;
     cut_err(
-        (alt(("0", "1")), repeat(0.., alt(("0", "1", "_")))).try_map(
-            move |(x, xs): (&str, Vec<&str>)| {
-                T::from_str_radix(&format!("{x}{}", str::replace(&xs.join(""), "_", "")), 2)
-            },
-        ),
+        (alt(("0", "1")), repeat(0.., alt(("0", "1", "_")))).map(|(x, xs): (&str, Vec<&str>)| {
+            format!("{x}{}", str::replace(&xs.join(""), "_", ""))
+        }),
     )
     .context(lbl("binary"))
     .parse_next(input)
@@ -1624,17 +2568,15 @@ macro_rules! impl_from_str_radix {
 
 impl_from_str_radix!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
 
-trait MaybeNegatable: CheckedMul {
-    fn negated(&self) -> Option<Self>;
+trait MaybeNegatable {
+    const SIGNED: bool;
 }
 
 macro_rules! impl_negatable_signed {
     ($($t:ty),*) => {
         $(
             impl MaybeNegatable for $t {
-                fn negated(&self) -> Option<Self> {
-                    Some(self * -1)
-                }
+                const SIGNED: bool = true;
             }
         )*
     };
@@ -1644,9 +2586,7 @@ macro_rules! impl_negatable_unsigned {
     ($($t:ty),*) => {
         $(
             impl MaybeNegatable for $t {
-                fn negated(&self) -> Option<Self> {
-                    None
-                }
+                const SIGNED: bool = false;
             }
         )*
     };
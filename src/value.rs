@@ -1,7 +1,18 @@
-use std::fmt::Display;
+use std::{
+    fmt::{Display, Write as _},
+    str::FromStr,
+};
+
+#[cfg(feature = "json")]
+use crate::JsonScalarError;
+use crate::{
+    fmt::is_newline, v2_parser, KdlCoercedValue, KdlIdentifier, KdlParseFailure, KdlReservedType,
+    KdlTypeError, NonFiniteError,
+};
 
 /// A specific [KDL Value](https://github.com/kdl-org/kdl/blob/main/SPEC.md#value).
 #[derive(Debug, Clone, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum KdlValue {
     /// A [KDL String](https://github.com/kdl-org/kdl/blob/main/SPEC.md#string).
     String(String),
@@ -12,6 +23,9 @@ pub enum KdlValue {
 
     /// A floating point [KDL
     /// Number](https://github.com/kdl-org/kdl/blob/main/SPEC.md#number)
+    ///
+    /// Non-finite values are allowed, and are written out as `#inf`, `#-inf`,
+    /// or `#nan`. Use [`KdlValue::try_float`] to reject them instead.
     Float(f64),
 
     /// A [KDL Boolean](https://github.com/kdl-org/kdl/blob/main/SPEC.md#boolean).
@@ -21,6 +35,36 @@ pub enum KdlValue {
     Null,
 }
 
+/// A Unicode normalization form, for use with [`KdlValue::normalized`] and
+/// [`KdlIdentifier::normalized`](crate::KdlIdentifier::normalized).
+///
+/// Requires the `unicode-normalization` feature.
+#[cfg(feature = "unicode-normalization")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NormalizationForm {
+    /// Canonical decomposition, followed by canonical composition.
+    Nfc,
+    /// Canonical decomposition.
+    Nfd,
+    /// Compatibility decomposition, followed by canonical composition.
+    Nfkc,
+    /// Compatibility decomposition.
+    Nfkd,
+}
+
+#[cfg(feature = "unicode-normalization")]
+impl NormalizationForm {
+    pub(crate) fn apply(self, string: &str) -> String {
+        use unicode_normalization::UnicodeNormalization;
+        match self {
+            Self::Nfc => string.nfc().collect(),
+            Self::Nfd => string.nfd().collect(),
+            Self::Nfkc => string.nfkc().collect(),
+            Self::Nfkd => string.nfkd().collect(),
+        }
+    }
+}
+
 impl Eq for KdlValue {}
 
 impl PartialEq for KdlValue {
@@ -86,6 +130,48 @@ impl std::hash::Hash for KdlValue {
 }
 
 impl KdlValue {
+    /// Parses a single value, like `#true`, `0xff`, or `"some text"`.
+    ///
+    /// This is the same as using [`str::parse`]. A leading type annotation
+    /// is allowed, but isn't part of the value: use [`KdlEntry::parse`] to
+    /// keep it, along with the value's exact representation. Anything after
+    /// the value, other than whitespace and comments, is an error.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlValue;
+    /// assert_eq!(KdlValue::parse("#true").unwrap(), KdlValue::Bool(true));
+    /// assert_eq!(KdlValue::parse("(u8)42").unwrap(), KdlValue::Integer(42));
+    /// assert_eq!(
+    ///     KdlValue::parse(r##"#"raw "text""#"##).unwrap(),
+    ///     KdlValue::String(r#"raw "text""#.into())
+    /// );
+    /// assert!(KdlValue::parse("42 foo").is_err());
+    /// ```
+    ///
+    /// [`KdlEntry::parse`]: crate::KdlEntry::parse
+    pub fn parse(input: &str) -> Result<Self, KdlParseFailure> {
+        input.parse()
+    }
+
+    /// Creates a [`KdlValue::Float`], but only if `value` is finite.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlValue;
+    /// assert_eq!(KdlValue::try_float(1.5), Ok(KdlValue::Float(1.5)));
+    /// assert!(KdlValue::try_float(f64::NAN).is_err());
+    /// ```
+    pub fn try_float(value: f64) -> Result<Self, NonFiniteError> {
+        if value.is_finite() {
+            Ok(Self::Float(value))
+        } else {
+            Err(NonFiniteError { value })
+        }
+    }
+
     /// Returns `true` if the value is a [`KdlValue::String`].
     pub fn is_string(&self) -> bool {
         matches!(self, Self::String(..))
@@ -140,6 +226,33 @@ impl KdlValue {
         }
     }
 
+    /// Checks this value against the type annotation `ty`, converting it
+    /// where possible. Annotations that aren't [reserved](KdlReservedType)
+    /// by the spec are left for the application to interpret, so they
+    /// always succeed with [`KdlCoercedValue::Uninterpreted`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::{KdlCoercedValue, KdlIdentifier, KdlValue};
+    /// let value = KdlValue::String("aGk=".into());
+    /// assert_eq!(
+    ///     value.coerce(&KdlIdentifier::from("base64")),
+    ///     Ok(KdlCoercedValue::Bytes(b"hi".to_vec()))
+    /// );
+    /// assert_eq!(
+    ///     value.coerce(&KdlIdentifier::from("my-type")),
+    ///     Ok(KdlCoercedValue::Uninterpreted(value.clone()))
+    /// );
+    /// assert!(KdlValue::Integer(300).coerce(&KdlIdentifier::from("u8")).is_err());
+    /// ```
+    pub fn coerce(&self, ty: &KdlIdentifier) -> Result<KdlCoercedValue, KdlTypeError> {
+        match KdlReservedType::from_name(ty.value()) {
+            Some(reserved) => reserved.coerce(self),
+            None => Ok(KdlCoercedValue::Uninterpreted(self.clone())),
+        }
+    }
+
     /// Returns `Some(bool)` if the `KdlValue` is a [`KdlValue::Bool`], otherwise returns `None`.
     pub fn as_bool(&self) -> Option<bool> {
         if let Self::Bool(v) = self {
@@ -148,6 +261,132 @@ impl KdlValue {
             None
         }
     }
+
+    /// Converts a scalar JSON value into the equivalent `KdlValue`. Integers
+    /// become [`KdlValue::Integer`], and any other numbers become
+    /// [`KdlValue::Float`]. Arrays and objects have no single-value
+    /// equivalent, and are rejected.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlValue;
+    /// let json = serde_json::json!(["hi", 1, 1.5, true, null]);
+    /// let values = json
+    ///     .as_array()
+    ///     .unwrap()
+    ///     .iter()
+    ///     .map(|v| KdlValue::from_json_scalar(v).unwrap())
+    ///     .collect::<Vec<_>>();
+    /// assert_eq!(
+    ///     values,
+    ///     vec![
+    ///         "hi".into(),
+    ///         1.into(),
+    ///         1.5.into(),
+    ///         true.into(),
+    ///         KdlValue::Null
+    ///     ]
+    /// );
+    /// assert!(KdlValue::from_json_scalar(&json).is_err());
+    /// ```
+    #[cfg(feature = "json")]
+    pub fn from_json_scalar(value: &serde_json::Value) -> Result<KdlValue, JsonScalarError> {
+        use serde_json::Value;
+        Ok(match value {
+            Value::Null => Self::Null,
+            Value::Bool(b) => Self::Bool(*b),
+            Value::String(s) => Self::String(s.clone()),
+            Value::Number(n) => {
+                if let Some(i) = n.as_i64() {
+                    Self::Integer(i.into())
+                } else if let Some(u) = n.as_u64() {
+                    Self::Integer(u.into())
+                } else {
+                    // A float, or an integer outside both ranges, which
+                    // serde_json can hold if it keeps numbers' exact text.
+                    let repr = n.to_string();
+                    if repr.contains(['.', 'e', 'E']) {
+                        Self::Float(n.as_f64().unwrap_or(f64::NAN))
+                    } else {
+                        Self::Integer(
+                            repr.parse()
+                                .map_err(|_| JsonScalarError::IntegerOutOfRange(repr))?,
+                        )
+                    }
+                }
+            }
+            Value::Array(_) => return Err(JsonScalarError::NotScalar("an array")),
+            Value::Object(_) => return Err(JsonScalarError::NotScalar("an object")),
+        })
+    }
+
+    /// Converts this value into the equivalent JSON scalar. This is the
+    /// reverse of [`KdlValue::from_json_scalar`], except that JSON has no
+    /// room for integers outside the `i64` and `u64` ranges, or for `#inf`,
+    /// `#-inf`, and `#nan`, so those are rejected.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::KdlValue;
+    /// assert_eq!(KdlValue::Integer(1).to_json_scalar(), Ok(serde_json::json!(1)));
+    /// assert_eq!(KdlValue::Null.to_json_scalar(), Ok(serde_json::Value::Null));
+    /// assert!(KdlValue::Integer(i128::MAX).to_json_scalar().is_err());
+    /// assert!(KdlValue::Float(f64::NAN).to_json_scalar().is_err());
+    /// ```
+    #[cfg(feature = "json")]
+    pub fn to_json_scalar(&self) -> Result<serde_json::Value, JsonScalarError> {
+        use serde_json::{Number, Value};
+        Ok(match self {
+            Self::Null => Value::Null,
+            Self::Bool(b) => Value::Bool(*b),
+            Self::String(s) => Value::String(s.clone()),
+            Self::Integer(i) => {
+                if let Ok(i) = i64::try_from(*i) {
+                    i.into()
+                } else if let Ok(u) = u64::try_from(*i) {
+                    u.into()
+                } else {
+                    return Err(JsonScalarError::IntegerTooLarge(*i));
+                }
+            }
+            Self::Float(f) => match Number::from_f64(*f) {
+                Some(n) => Value::Number(n),
+                None => return Err(JsonScalarError::NonFinite(self.to_string())),
+            },
+        })
+    }
+
+    /// Returns a copy of this value with its string, if it's a
+    /// [`KdlValue::String`], converted to the given Unicode normalization
+    /// form. Other values are returned as-is.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use kdl::{KdlValue, NormalizationForm};
+    /// let decomposed = KdlValue::from("cafe\u{301}");
+    /// assert_eq!(
+    ///     decomposed.normalized(NormalizationForm::Nfc),
+    ///     KdlValue::from("caf\u{e9}")
+    /// );
+    /// ```
+    #[cfg(feature = "unicode-normalization")]
+    pub fn normalized(&self, form: NormalizationForm) -> KdlValue {
+        match self {
+            Self::String(s) => Self::String(form.apply(s)),
+            other => other.clone(),
+        }
+    }
+}
+
+impl FromStr for KdlValue {
+    type Err = KdlParseFailure;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        v2_parser::try_parse(v2_parser::padded_value, s)
+    }
 }
 
 impl Display for KdlValue {
@@ -165,7 +404,7 @@ impl Display for KdlValue {
                 } else if value.is_nan() {
                     "#nan".into()
                 } else {
-                    format!("{:?}", *value)
+                    float_repr(*value)
                 }
             ),
             Self::Bool(value) => write!(f, "#{}", value),
@@ -174,18 +413,30 @@ impl Display for KdlValue {
     }
 }
 
+/// Writes out a finite float so it reads back as a float, never an integer:
+/// `5.0` rather than `5`.
+fn float_repr(value: f64) -> String {
+    // `Debug` already picks the shortest representation that round-trips,
+    // and uses an exponent for very large or small values, but doesn't
+    // promise a decimal point otherwise.
+    let mut repr = format!("{:?}", value);
+    if !repr.contains(['.', 'e', 'E']) {
+        repr.push_str(".0");
+    }
+    repr
+}
+
 fn is_plain_ident(ident: &str) -> bool {
-    let ident_bytes = ident.as_bytes();
+    // Anything that starts with a digit, even after a sign or a dot, would
+    // be read as a number.
+    let unsigned = ident
+        .strip_prefix(|c| c == '+' || c == '-')
+        .unwrap_or(ident);
+    let undotted = unsigned.strip_prefix('.').unwrap_or(unsigned);
     ident
         .find(crate::v2_parser::is_disallowed_ident_char)
         .is_none()
-        && ident_bytes.first().map(|c| c.is_ascii_digit()) != Some(true)
-        && !(ident
-            .chars()
-            .next()
-            .map(|c| c == '.' || c == '-' || c == '+')
-            == Some(true)
-            && ident_bytes.get(1).map(|c| c.is_ascii_digit()) == Some(true))
+        && undotted.as_bytes().first().map(|c| c.is_ascii_digit()) != Some(true)
 }
 
 #[cfg(test)]
@@ -193,29 +444,148 @@ fn is_plain_ident(ident: &str) -> bool {
 fn plain_ident_test() {
     assert!(is_plain_ident("foo123,bar"));
     assert!(is_plain_ident("foo123~!@$%^&*.:'|?+<>,"));
+    assert!(is_plain_ident("+.foo"));
+    for number_like in ["1", "-1", ".1", "+.0", "-.5e3"] {
+        assert!(!is_plain_ident(number_like), "{number_like}");
+    }
 }
 
 impl KdlValue {
     fn write_string(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let string = self.as_string().unwrap();
-        if !string.is_empty() && is_plain_ident(string) {
-            write!(f, "{string}")?;
+        if requires_quoting(string) {
+            write!(f, "{}", quote_string(string))
         } else {
-            write!(f, "\"")?;
-            for char in string.chars() {
-                match char {
-                    '\\' | '"' => write!(f, "\\{}", char)?,
-                    '\n' => write!(f, "\\n")?,
-                    '\r' => write!(f, "\\r")?,
-                    '\t' => write!(f, "\\t")?,
-                    '\u{08}' => write!(f, "\\b")?,
-                    '\u{0C}' => write!(f, "\\f")?,
-                    _ => write!(f, "{}", char)?,
-                }
+            write!(f, "{string}")
+        }
+    }
+}
+
+/// Whether `string` has to be quoted, rather than written as a bare
+/// identifier string.
+pub(crate) fn requires_quoting(string: &str) -> bool {
+    string.is_empty()
+        || !is_plain_ident(string)
+        || matches!(string, "true" | "false" | "null" | "inf" | "-inf" | "nan")
+}
+
+/// `string` as written by [`StringStyle::Auto`]: as a bare identifier string
+/// where possible, or else as a raw string if that's no longer than the
+/// quoted string would be with all its escapes, or else as a quoted string.
+/// With `multi_line`, strings with newlines in them become multi-line quoted
+/// strings, rather than using `\n` escapes.
+fn encode_string(string: &str, multi_line: bool) -> String {
+    if !requires_quoting(string) {
+        string.into()
+    } else if multi_line && string.contains('\n') {
+        multi_line_string(string)
+    } else if let Some(raw) = raw_string(string) {
+        raw
+    } else {
+        quote_string(string)
+    }
+}
+
+/// `string` as a single-line raw string, if it can be written as one and
+/// that's no longer than quoting it.
+fn raw_string(string: &str) -> Option<String> {
+    if string.contains(|c| is_newline(c) || v2_parser::is_disallowed_unicode(c)) {
+        return None;
+    }
+    let escapes = string.matches(['\\', '"']).count();
+    // Enough hashes that no `"` in the string can close it early.
+    let hashes = string
+        .split('"')
+        .skip(1)
+        .map(|after| after.len() - after.trim_start_matches('#').len() + 1)
+        .max()
+        .unwrap_or(1);
+    if 2 * hashes > escapes {
+        return None;
+    }
+    let hashes = "#".repeat(hashes);
+    Some(format!("{hashes}\"{string}\"{hashes}"))
+}
+
+/// `string` as a multi-line quoted string, without any indentation.
+/// Whitespace-only lines are escaped so they aren't mistaken for blank ones.
+fn multi_line_string(string: &str) -> String {
+    let mut quoted = String::with_capacity(string.len() + 8);
+    quoted.push_str("\"\"\"\n");
+    for (idx, line) in string.split('\n').enumerate() {
+        if idx > 0 {
+            quoted.push('\n');
+        }
+        let blank = line.chars().all(|c| v2_parser::UNICODE_SPACES.contains(&c));
+        for char in line.chars() {
+            match char {
+                ' ' if blank => quoted.push_str("\\s"),
+                c if blank && c != '\t' => write!(quoted, "\\u{{{:x}}}", c as u32).unwrap(),
+                _ => escape_char(&mut quoted, char),
             }
-            write!(f, "\"")?;
         }
-        Ok(())
+    }
+    quoted.push_str("\n\"\"\"");
+    quoted
+}
+
+/// `string` as a quoted string, with escapes where needed.
+pub(crate) fn quote_string(string: &str) -> String {
+    let mut quoted = String::with_capacity(string.len() + 2);
+    quoted.push('"');
+    for char in string.chars() {
+        escape_char(&mut quoted, char);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Pushes `char` to `quoted`, escaping it if it can't appear literally in a
+/// single-line quoted string.
+fn escape_char(quoted: &mut String, char: char) {
+    match char {
+        '\\' | '"' => {
+            quoted.push('\\');
+            quoted.push(char);
+        }
+        '\n' => quoted.push_str("\\n"),
+        '\r' => quoted.push_str("\\r"),
+        '\t' => quoted.push_str("\\t"),
+        '\u{08}' => quoted.push_str("\\b"),
+        '\u{0C}' => quoted.push_str("\\f"),
+        c if is_newline(c) || v2_parser::is_disallowed_unicode(c) => {
+            write!(quoted, "\\u{{{:x}}}", c as u32).unwrap()
+        }
+        _ => quoted.push(char),
+    }
+}
+
+/// How string values and identifiers should be written, for
+/// [`KdlDocument::normalize_string_style`](crate::KdlDocument::normalize_string_style).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StringStyle {
+    /// Always use quoted strings, like `"foo"`.
+    Quoted,
+    /// Use bare identifier strings, like `foo`, wherever they're allowed,
+    /// and quoted strings everywhere else.
+    PreferBare,
+    /// Like [`StringStyle::PreferBare`], but use raw strings, like
+    /// `#"C:\dir\"#`, where that's no longer than escaping all the
+    /// backslashes and quotes would be, and multi-line strings for string
+    /// values with newlines in them. Identifiers are never multi-line.
+    Auto,
+}
+
+impl StringStyle {
+    /// `string` written in this style, or in a valid alternative if that's
+    /// not possible. `multi_line` says whether a multi-line string is
+    /// allowed, which it is for values, but not identifiers.
+    pub(crate) fn render(self, string: &str, multi_line: bool) -> String {
+        match self {
+            Self::Auto => encode_string(string, multi_line),
+            Self::PreferBare if !requires_quoting(string) => string.into(),
+            _ => quote_string(string),
+        }
     }
 }
 
@@ -261,14 +631,163 @@ where
     }
 }
 
+#[cfg(feature = "json")]
+impl TryFrom<&serde_json::Value> for KdlValue {
+    type Error = JsonScalarError;
+
+    fn try_from(value: &serde_json::Value) -> Result<Self, Self::Error> {
+        KdlValue::from_json_scalar(value)
+    }
+}
+
+#[cfg(feature = "json")]
+impl TryFrom<serde_json::Value> for KdlValue {
+    type Error = JsonScalarError;
+
+    fn try_from(value: serde_json::Value) -> Result<Self, Self::Error> {
+        match value {
+            serde_json::Value::String(s) => Ok(KdlValue::String(s)),
+            value => KdlValue::from_json_scalar(&value),
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+impl TryFrom<&KdlValue> for serde_json::Value {
+    type Error = JsonScalarError;
+
+    fn try_from(value: &KdlValue) -> Result<Self, Self::Error> {
+        value.to_json_scalar()
+    }
+}
+
+#[cfg(feature = "json")]
+impl TryFrom<KdlValue> for serde_json::Value {
+    type Error = JsonScalarError;
+
+    fn try_from(value: KdlValue) -> Result<Self, Self::Error> {
+        match value {
+            KdlValue::String(s) => Ok(serde_json::Value::String(s)),
+            value => value.to_json_scalar(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use proptest::prelude::*;
+
     use super::*;
+    use crate::{KdlDocument, KdlErrorKind, KdlIdentifier};
+
+    proptest! {
+        #[test]
+        fn string_repr_round_trips(
+            string in prop_oneof![
+                any::<String>(),
+                "[a-z0-9 .+\\-\"\\\\#=(){};/\n\r\t\\x{0}\\x{7f}\\x{85}\\x{a0}\\x{2028}\\x{feff}]*",
+            ]
+        ) {
+            let value = KdlValue::String(string.clone());
+            let repr = value.to_string();
+            prop_assert_eq!(KdlValue::parse(&repr).ok(), Some(value.clone()), "{}", repr);
+
+            let ident = KdlIdentifier::from(string.clone());
+            let ident_repr = ident.to_string();
+            let parsed = ident_repr.parse::<KdlIdentifier>();
+            prop_assert_eq!(parsed.ok().map(|i| i.value), Some(string.clone()), "{}", ident_repr);
+
+            let doc: KdlDocument = format!("{ident_repr} {repr}\n").parse().unwrap();
+            prop_assert_eq!(doc.nodes()[0].name().value(), string.as_str());
+            prop_assert_eq!(doc.nodes()[0].entries()[0].value(), &value);
+
+            let ident_repr = StringStyle::Auto.render(&string, false);
+            let repr = StringStyle::Auto.render(&string, true);
+            let doc: KdlDocument = format!("{ident_repr} {repr}\n").parse().unwrap();
+            prop_assert_eq!(doc.nodes()[0].name().value(), string.as_str());
+            prop_assert_eq!(doc.nodes()[0].entries()[0].value(), &value);
+        }
+    }
+
+    #[test]
+    fn parsing() -> miette::Result<()> {
+        assert_eq!(KdlValue::parse("(u8)42")?, KdlValue::Integer(42));
+        assert_eq!(
+            " 0xf_f // hex\n".parse::<KdlValue>()?,
+            KdlValue::Integer(255)
+        );
+        assert_eq!(KdlValue::parse("#null")?, KdlValue::Null);
+        assert_eq!(
+            KdlValue::parse("#-inf")?,
+            KdlValue::Float(f64::NEG_INFINITY)
+        );
+        assert!(matches!(KdlValue::parse("#nan")?, KdlValue::Float(v) if v.is_nan()));
+        assert_eq!(
+            KdlValue::parse(r###"##"a "# b"##"###)?,
+            KdlValue::String(r##"a "# b"##.into())
+        );
+        assert_eq!(KdlValue::parse("bare")?, KdlValue::String("bare".into()));
+
+        for (input, kind, span) in [
+            ("42 foo", KdlErrorKind::TrailingInput("value"), (3, 3)),
+            ("key=1", KdlErrorKind::TrailingInput("value"), (3, 2)),
+            ("1;", KdlErrorKind::TrailingInput("value"), (1, 1)),
+            ("", KdlErrorKind::Context("value"), (0, 0)),
+            ("  ", KdlErrorKind::Context("value"), (2, 0)),
+        ] {
+            let err = KdlValue::parse(input).unwrap_err();
+            assert_eq!(err.diagnostics.len(), 1, "{input:?}");
+            assert_eq!(err.diagnostics[0].kind, kind, "{input:?}");
+            assert_eq!(err.diagnostics[0].span, span.into(), "{input:?}");
+        }
+        assert!(KdlValue::parse("1.").is_err());
+        assert!(KdlValue::parse("(u8)").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn try_float() {
+        assert_eq!(KdlValue::try_float(-0.5), Ok(KdlValue::Float(-0.5)));
+        assert_eq!(KdlValue::try_float(f64::MAX), Ok(KdlValue::Float(f64::MAX)));
+        for value in [f64::INFINITY, f64::NEG_INFINITY] {
+            assert_eq!(KdlValue::try_float(value), Err(NonFiniteError { value }));
+        }
+        let err = KdlValue::try_float(f64::NAN).unwrap_err();
+        assert!(err.value.is_nan());
+        assert_eq!(err.to_string(), "NaN is not a finite number.");
+        // The plain constructor stays permissive.
+        assert_eq!(KdlValue::Float(f64::NAN).to_string(), "#nan");
+    }
+
+    #[test]
+    fn auto_string_style() {
+        let auto = |string: &str| StringStyle::Auto.render(string, true);
+        assert_eq!(auto("foo"), "foo");
+        assert_eq!(auto("foo\n"), "\"\"\"\nfoo\n\n\"\"\"");
+        assert_eq!(StringStyle::Auto.render("foo\n", false), r#""foo\n""#);
+        assert_eq!(auto("foo bar"), r#""foo bar""#);
+        assert_eq!(auto(r#"C:\dir\file "name""#), r##"#"C:\dir\file "name""#"##);
+        assert_eq!(
+            auto(r##"say "#hi" and "x""##),
+            r###"##"say "#hi" and "x""##"###
+        );
+        // Not worth two hashes for just two escapes.
+        assert_eq!(auto(r##"a\"#b"##), r##""a\\\"#b""##);
+        assert_eq!(auto("one \\"), r#""one \\""#);
+        // Whitespace-only lines are escaped, so they aren't taken for blank ones.
+        assert_eq!(
+            auto("  \n\ta\r\n\u{2028}\""),
+            "\"\"\"\n\\s\\s\n\\ta\\r\n\\u{2028}\\\"\n\"\"\""
+        );
+    }
 
     #[test]
     fn formatting() {
         let string = KdlValue::String("foo\n".into());
         assert_eq!(format!("{}", string), r#""foo\n""#);
+        // Newlines and disallowed code points are always escaped.
+        let string = KdlValue::String("\u{85}\u{2028}\u{feff}\u{0}".into());
+        assert_eq!(format!("{}", string), r#""\u{85}\u{2028}\u{feff}\u{0}""#);
 
         let integer = KdlValue::Integer(1234567890);
         assert_eq!(format!("{}", integer), "1234567890");
@@ -279,7 +798,118 @@ mod test {
         let boolean = KdlValue::Bool(true);
         assert_eq!(format!("{}", boolean), "#true");
 
+        // Integral floats keep a decimal point or exponent, so they still
+        // read back as floats.
+        for value in [5.0, -5.0, 0.0, -0.0, 1e20, 1e-7, f64::MAX] {
+            let repr = KdlValue::Float(value).to_string();
+            assert!(repr.contains(['.', 'e']), "{repr}");
+            let parsed: KdlValue = format!("node {repr}")
+                .parse::<crate::KdlDocument>()
+                .unwrap()
+                .nodes()[0]
+                .entries()[0]
+                .value()
+                .clone();
+            assert_eq!(parsed, KdlValue::Float(value), "{repr}");
+        }
+        assert_eq!(KdlValue::Float(5.0).to_string(), "5.0");
+
         let null = KdlValue::Null;
         assert_eq!(format!("{}", null), "#null");
+
+        // Strings that look like keywords can't be bare.
+        for keyword in ["true", "false", "null", "inf", "-inf", "nan"] {
+            let string = KdlValue::String(keyword.into());
+            assert_eq!(format!("{}", string), format!("\"{keyword}\""));
+        }
+    }
+    #[cfg(feature = "unicode-normalization")]
+    #[test]
+    fn normalized() {
+        let composed = KdlValue::from("\u{c5}ngstr\u{f6}m");
+        let decomposed = KdlValue::from("A\u{30a}ngstro\u{308}m");
+        assert_ne!(composed, decomposed);
+        assert_eq!(decomposed.normalized(NormalizationForm::Nfc), composed);
+        assert_eq!(composed.normalized(NormalizationForm::Nfd), decomposed);
+
+        let ligature = KdlValue::from("\u{fb01}le");
+        assert_eq!(ligature.normalized(NormalizationForm::Nfc), ligature);
+        assert_eq!(
+            ligature.normalized(NormalizationForm::Nfkc),
+            KdlValue::from("file")
+        );
+        assert_eq!(
+            ligature.normalized(NormalizationForm::Nfkd),
+            KdlValue::from("file")
+        );
+
+        for value in [KdlValue::Integer(1), KdlValue::Bool(true), KdlValue::Null] {
+            assert_eq!(value.normalized(NormalizationForm::Nfkd), value);
+        }
+    }
+    #[cfg(feature = "json")]
+    #[test]
+    fn to_json_scalar() {
+        use serde_json::{json, Value};
+        for value in [
+            KdlValue::Integer(-5),
+            KdlValue::Integer(u64::MAX.into()),
+            KdlValue::Float(2.5e10),
+            KdlValue::String("a\"b".into()),
+            KdlValue::Bool(false),
+            KdlValue::Null,
+        ] {
+            let json = Value::try_from(&value).unwrap();
+            assert_eq!(KdlValue::try_from(json), Ok(value));
+        }
+        assert_eq!(Value::try_from(KdlValue::from("s")), Ok(json!("s")));
+        assert_eq!(
+            KdlValue::Integer(i128::MIN).to_json_scalar(),
+            Err(JsonScalarError::IntegerTooLarge(i128::MIN))
+        );
+        assert_eq!(
+            KdlValue::Integer(u64::MAX as i128 + 1).to_json_scalar(),
+            Err(JsonScalarError::IntegerTooLarge(u64::MAX as i128 + 1))
+        );
+        let err = KdlValue::Float(f64::NEG_INFINITY)
+            .to_json_scalar()
+            .unwrap_err();
+        assert_eq!(err.to_string(), "KDL value #-inf has no JSON equivalent.");
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn from_json_scalar() {
+        use serde_json::json;
+        assert_eq!(
+            KdlValue::from_json_scalar(&json!(-5)),
+            Ok(KdlValue::Integer(-5))
+        );
+        assert_eq!(
+            KdlValue::from_json_scalar(&json!(u64::MAX)),
+            Ok(KdlValue::Integer(u64::MAX.into()))
+        );
+        assert_eq!(
+            KdlValue::from_json_scalar(&json!(2.5e10)),
+            Ok(KdlValue::Float(2.5e10))
+        );
+        assert_eq!(
+            KdlValue::from_json_scalar(&json!("a\"b")),
+            Ok(KdlValue::String("a\"b".into()))
+        );
+        assert_eq!(
+            KdlValue::from_json_scalar(&json!(false)),
+            Ok(KdlValue::Bool(false))
+        );
+        assert_eq!(KdlValue::from_json_scalar(&json!(null)), Ok(KdlValue::Null));
+        assert_eq!(
+            KdlValue::from_json_scalar(&json!([1])),
+            Err(JsonScalarError::NotScalar("an array"))
+        );
+        let err = KdlValue::from_json_scalar(&json!({ "a": 1 })).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Expected a JSON scalar, but got an object."
+        );
     }
 }
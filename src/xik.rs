@@ -0,0 +1,554 @@
+//! [XML-in-KDL](https://github.com/kdl-org/kdl/blob/main/XML-IN-KDL.md)
+//! (XiK), the KDL microsyntax for representing XML documents, with the
+//! `xik` feature.
+//!
+//! XML is translated mechanically:
+//!
+//! * An element is a node with the element's name. Its attributes are
+//!   properties, and its content is a children block. If the content is
+//!   only text, it's the node's single argument instead:
+//!   `title "Hello" lang=en`.
+//! * Text mixed in with other content is a `-` node with the text as its
+//!   argument: `p { - "Some "; b bold; - " text."; }`. CDATA sections are
+//!   read as text.
+//! * A comment is a `!` node with the comment as its argument.
+//! * A processing instruction, including the `<?xml ...?>` declaration, is
+//!   a node named `?` followed by its target. Its content is written as
+//!   properties if it looks like attributes, and as a single argument
+//!   otherwise: `?xml version="1.0"`.
+//! * A doctype is a `!doctype` node with the rest of the declaration as its
+//!   argument: `!doctype html`.
+//!
+//! Type annotations are ignored, and numbers and booleans are allowed
+//! wherever text is, so hand-written XiK can say `rect width=10`.
+//!
+//! The translation goes through a small tree of [`XmlNode`]s, which can also
+//! be built from, or handed to, another XML library.
+//!
+//! # Examples
+//!
+//! ```rust
+//! let doc = kdl::xik::from_xml(r#"<?xml version="1.0"?>
+//! <!-- settings -->
+//! <config env="prod">
+//!   <name>My &amp; App</name>
+//!   <p>Some <b>bold</b> text.</p>
+//! </config>"#)?;
+//! assert_eq!(
+//!     doc.to_string(),
+//!     r#"?xml version="1.0"
+//! ! " settings "
+//! config env=prod {
+//!     name "My & App"
+//!     p {
+//!         - "Some "
+//!         b bold
+//!         - " text."
+//!     }
+//! }
+//! "#
+//! );
+//! assert_eq!(
+//!     kdl::xik::to_xml(&doc)?,
+//!     r#"<?xml version="1.0"?>
+//! <!-- settings -->
+//! <config env="prod"><name>My &amp; App</name><p>Some <b>bold</b> text.</p></config>
+//! "#
+//! );
+//! # Ok::<(), miette::Report>(())
+//! ```
+
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader,
+};
+
+use crate::{KdlConversionError, KdlDocument, KdlEntry, KdlNode, KdlValue};
+
+/// A node in an XML document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum XmlNode {
+    /// An element.
+    Element(XmlElement),
+    /// Text, already unescaped.
+    Text(String),
+    /// A comment, without its `<!--` and `-->`.
+    Comment(String),
+    /// A processing instruction, like `<?xml-stylesheet href="a.css"?>`.
+    ProcessingInstruction {
+        /// The instruction's target, like `xml-stylesheet`.
+        target: String,
+        /// Everything after the target, like `href="a.css"`.
+        data: String,
+    },
+    /// A doctype, without its `<!DOCTYPE` and `>`.
+    Doctype(String),
+}
+
+/// An XML element.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct XmlElement {
+    /// The element's name, including any namespace prefix.
+    pub name: String,
+    /// The element's attributes, in order, with their values unescaped.
+    pub attributes: Vec<(String, String)>,
+    /// The element's content.
+    pub children: Vec<XmlNode>,
+}
+
+/// Translates an XML document to XiK. See [`parse_xml`] and
+/// [`to_document`].
+pub fn from_xml(xml: &str) -> Result<KdlDocument, KdlConversionError> {
+    Ok(to_document(&parse_xml(xml)?))
+}
+
+/// Translates a XiK document to XML. See [`from_document`] and
+/// [`write_xml`].
+pub fn to_xml(doc: &KdlDocument) -> Result<String, KdlConversionError> {
+    Ok(write_xml(&from_document(doc)?))
+}
+
+/// Parses XML text into a tree. Text that's only whitespace, like the
+/// indentation between elements, is dropped.
+pub fn parse_xml(xml: &str) -> Result<Vec<XmlNode>, KdlConversionError> {
+    let mut reader = Reader::from_str(xml);
+    let mut open = Vec::<XmlElement>::new();
+    let mut top = Vec::new();
+    loop {
+        let event = reader
+            .read_event()
+            .map_err(|err| xml_error(err, reader.error_position()))?;
+        let position = reader.buffer_position();
+        let node = match event {
+            Event::Start(start) => {
+                open.push(element(&start, position)?);
+                continue;
+            }
+            Event::Empty(start) => XmlNode::Element(element(&start, position)?),
+            Event::End(_) => match open.pop() {
+                Some(element) => XmlNode::Element(element),
+                None => return Err(xml_error("Unexpected closing tag.", position)),
+            },
+            Event::Text(text) => {
+                let text = text.unescape().map_err(|err| xml_error(err, position))?;
+                if text.trim().is_empty() {
+                    continue;
+                }
+                XmlNode::Text(text.into_owned())
+            }
+            Event::CData(cdata) => XmlNode::Text(utf8(&cdata, position)?.into()),
+            Event::Comment(comment) => XmlNode::Comment(utf8(&comment, position)?.into()),
+            Event::Decl(decl) => {
+                let content = utf8(&decl, position)?;
+                XmlNode::ProcessingInstruction {
+                    target: "xml".into(),
+                    data: content.trim_start_matches("xml").trim().into(),
+                }
+            }
+            Event::PI(pi) => XmlNode::ProcessingInstruction {
+                target: utf8(pi.target(), position)?.into(),
+                data: utf8(pi.content(), position)?.trim().into(),
+            },
+            Event::DocType(doctype) => XmlNode::Doctype(utf8(&doctype, position)?.trim().into()),
+            Event::Eof => break,
+        };
+        let siblings = match open.last_mut() {
+            Some(parent) => &mut parent.children,
+            None => &mut top,
+        };
+        match (siblings.last_mut(), node) {
+            // Text interrupted by a CDATA section is still one run of text.
+            (Some(XmlNode::Text(last)), XmlNode::Text(text)) => last.push_str(&text),
+            (_, node) => siblings.push(node),
+        }
+    }
+    match open.last() {
+        Some(element) => Err(xml_error(
+            format!("Unclosed element <{}>.", element.name),
+            xml.len() as u64,
+        )),
+        None => Ok(top),
+    }
+}
+
+/// Writes a tree out as XML text. Elements are written compactly, so text
+/// comes out exactly as it went in, and top-level nodes are separated by
+/// newlines.
+pub fn write_xml(nodes: &[XmlNode]) -> String {
+    let mut out = String::new();
+    for node in nodes {
+        write_node(&mut out, node);
+        out.push('\n');
+    }
+    out
+}
+
+/// Translates a tree to an autoformatted XiK document.
+pub fn to_document(nodes: &[XmlNode]) -> KdlDocument {
+    let mut doc = KdlDocument::new();
+    doc.nodes_mut().extend(nodes.iter().map(node_to_kdl));
+    doc.autoformat();
+    doc
+}
+
+/// Reads a tree from a XiK document.
+pub fn from_document(doc: &KdlDocument) -> Result<Vec<XmlNode>, KdlConversionError> {
+    doc.nodes().iter().map(node_from_kdl).collect()
+}
+
+fn element(start: &BytesStart<'_>, position: u64) -> Result<XmlElement, KdlConversionError> {
+    let mut element = XmlElement {
+        name: utf8(start.name().as_ref(), position)?.into(),
+        ..XmlElement::default()
+    };
+    for attr in start.attributes() {
+        let attr = attr.map_err(|err| xml_error(err, position))?;
+        let value = attr
+            .unescape_value()
+            .map_err(|err| xml_error(err, position))?;
+        element.attributes.push((
+            utf8(attr.key.as_ref(), position)?.into(),
+            value.into_owned(),
+        ));
+    }
+    Ok(element)
+}
+
+fn utf8(bytes: &[u8], position: u64) -> Result<&str, KdlConversionError> {
+    std::str::from_utf8(bytes).map_err(|err| xml_error(err, position))
+}
+
+fn xml_error(err: impl std::fmt::Display, position: u64) -> KdlConversionError {
+    KdlConversionError {
+        message: err.to_string(),
+        span: Some((position as usize, 0).into()),
+    }
+}
+
+fn write_node(out: &mut String, node: &XmlNode) {
+    match node {
+        XmlNode::Element(element) => {
+            out.push('<');
+            out.push_str(&element.name);
+            for (name, value) in &element.attributes {
+                out.push_str(&format!(" {name}=\"{}\"", escape(value, true)));
+            }
+            if element.children.is_empty() {
+                out.push_str("/>");
+            } else {
+                out.push('>');
+                for child in &element.children {
+                    write_node(out, child);
+                }
+                out.push_str(&format!("</{}>", element.name));
+            }
+        }
+        XmlNode::Text(text) => out.push_str(&escape(text, false)),
+        XmlNode::Comment(comment) => out.push_str(&format!("<!--{comment}-->")),
+        XmlNode::ProcessingInstruction { target, data } if data.is_empty() => {
+            out.push_str(&format!("<?{target}?>"))
+        }
+        XmlNode::ProcessingInstruction { target, data } => {
+            out.push_str(&format!("<?{target} {data}?>"))
+        }
+        XmlNode::Doctype(doctype) => out.push_str(&format!("<!DOCTYPE {doctype}>")),
+    }
+}
+
+fn escape(text: &str, attribute: bool) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' if attribute => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn node_to_kdl(node: &XmlNode) -> KdlNode {
+    match node {
+        XmlNode::Element(element) => {
+            let mut node = KdlNode::new(element.name.as_str());
+            match &element.children[..] {
+                [] => {}
+                [XmlNode::Text(text)] => node.push(text.as_str()),
+                children => node
+                    .ensure_children()
+                    .nodes_mut()
+                    .extend(children.iter().map(node_to_kdl)),
+            }
+            for (name, value) in &element.attributes {
+                node.push((name.as_str(), value.as_str()));
+            }
+            node
+        }
+        XmlNode::Text(text) => text_node("-", text),
+        XmlNode::Comment(comment) => text_node("!", comment),
+        XmlNode::ProcessingInstruction { target, data } => {
+            let mut node = KdlNode::new(format!("?{target}"));
+            match pseudo_attributes(data) {
+                Some(attributes) => {
+                    for (name, value) in attributes {
+                        node.push((name, value));
+                    }
+                }
+                None => node.push(data.as_str()),
+            }
+            node
+        }
+        XmlNode::Doctype(doctype) => text_node("!doctype", doctype),
+    }
+}
+
+fn text_node(name: &str, text: &str) -> KdlNode {
+    let mut node = KdlNode::new(name);
+    node.push(text);
+    node
+}
+
+/// Splits processing instruction data like `a="1" b='2'` into its
+/// pseudo-attributes, if that's what it looks like.
+fn pseudo_attributes(data: &str) -> Option<Vec<(&str, &str)>> {
+    let mut attributes = Vec::new();
+    let mut rest = data.trim_start();
+    while !rest.is_empty() {
+        let (name, after) = rest.split_once('=')?;
+        if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'') {
+            return None;
+        }
+        let quote = after.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let (value, after) = after[1..].split_once(quote)?;
+        if !after.is_empty() && !after.starts_with(char::is_whitespace) {
+            return None;
+        }
+        attributes.push((name, value));
+        rest = after.trim_start();
+    }
+    Some(attributes)
+}
+
+fn node_from_kdl(node: &KdlNode) -> Result<XmlNode, KdlConversionError> {
+    let name = node.name().value();
+    match name {
+        "-" => Ok(XmlNode::Text(only_text(node)?)),
+        "!" => Ok(XmlNode::Comment(only_text(node)?)),
+        "!doctype" => Ok(XmlNode::Doctype(only_text(node)?)),
+        _ if name.starts_with('?') => {
+            if node.children().is_some() {
+                return Err(node_error(
+                    node,
+                    "Processing instructions can't have children.",
+                ));
+            }
+            let mut data = Vec::new();
+            for entry in node.entries() {
+                let text = entry_text(entry)?;
+                data.push(match entry.name() {
+                    Some(key) if text.contains('"') => format!("{}='{text}'", key.value()),
+                    Some(key) => format!("{}=\"{text}\"", key.value()),
+                    None => text,
+                });
+            }
+            Ok(XmlNode::ProcessingInstruction {
+                target: name[1..].into(),
+                data: data.join(" "),
+            })
+        }
+        _ => {
+            let mut element = XmlElement {
+                name: name.into(),
+                ..XmlElement::default()
+            };
+            for entry in node.entries() {
+                match entry.name() {
+                    Some(key) => element
+                        .attributes
+                        .push((key.value().into(), entry_text(entry)?)),
+                    None if element.children.is_empty() => {
+                        element.children.push(XmlNode::Text(entry_text(entry)?))
+                    }
+                    None => {
+                        return Err(entry_error(
+                            entry,
+                            "Elements can only have one argument, for their text.",
+                        ))
+                    }
+                }
+            }
+            for child in node.children().map_or(&[][..], |doc| doc.nodes()) {
+                element.children.push(node_from_kdl(child)?);
+            }
+            Ok(XmlNode::Element(element))
+        }
+    }
+}
+
+/// Reads the single argument of a text, comment, or doctype node.
+fn only_text(node: &KdlNode) -> Result<String, KdlConversionError> {
+    match (node.entries(), node.children()) {
+        ([entry], None) if entry.name().is_none() => entry_text(entry),
+        _ => Err(node_error(
+            node,
+            format!(
+                "`{}` nodes must have exactly one argument and nothing else.",
+                node.name().value()
+            ),
+        )),
+    }
+}
+
+fn entry_text(entry: &KdlEntry) -> Result<String, KdlConversionError> {
+    match entry.value() {
+        KdlValue::String(text) => Ok(text.clone()),
+        KdlValue::Bool(b) => Ok(b.to_string()),
+        KdlValue::Null => Err(entry_error(entry, "#null has no XML equivalent.")),
+        number => Ok(number.to_string()),
+    }
+}
+
+#[cfg(feature = "span")]
+fn node_error(node: &KdlNode, message: impl Into<String>) -> KdlConversionError {
+    KdlConversionError {
+        message: message.into(),
+        span: Some(node.span()),
+    }
+}
+
+#[cfg(not(feature = "span"))]
+fn node_error(_node: &KdlNode, message: impl Into<String>) -> KdlConversionError {
+    KdlConversionError {
+        message: message.into(),
+        span: None,
+    }
+}
+
+#[cfg(feature = "span")]
+fn entry_error(entry: &KdlEntry, message: impl Into<String>) -> KdlConversionError {
+    KdlConversionError {
+        message: message.into(),
+        span: Some(entry.span()),
+    }
+}
+
+#[cfg(not(feature = "span"))]
+fn entry_error(_entry: &KdlEntry, message: impl Into<String>) -> KdlConversionError {
+    KdlConversionError {
+        message: message.into(),
+        span: None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() -> miette::Result<()> {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<?xml-stylesheet type="text/xsl" href='a "b".xsl'?>
+<?app free-form data?>
+<!DOCTYPE html>
+<html xmlns:svg="http://www.w3.org/2000/svg" lang="en">
+  <!-- a comment -->
+  <head><title>A &lt;title&gt;</title><meta charset="utf-8"/></head>
+  <body class="a &quot;b&quot;">
+    <p>Some <b>bold</b> and <![CDATA[<raw>]]> text.</p>
+    <svg:rect width="10"/>
+  </body>
+</html>"#;
+        let doc = from_xml(xml)?;
+        assert_eq!(
+            doc.to_string(),
+            r#"?xml version="1.0" encoding=UTF-8
+?xml-stylesheet type="text/xsl" href="a \"b\".xsl"
+?app "free-form data"
+!doctype html
+html xmlns:svg="http://www.w3.org/2000/svg" lang=en {
+    ! " a comment "
+    head {
+        title "A <title>"
+        meta charset=utf-8
+    }
+    body class="a \"b\"" {
+        p {
+            - "Some "
+            b bold
+            - " and <raw> text."
+        }
+        svg:rect width="10"
+    }
+}
+"#
+        );
+        assert_eq!(
+            to_xml(&doc)?,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<?xml-stylesheet type="text/xsl" href='a "b".xsl'?>
+<?app free-form data?>
+<!DOCTYPE html>
+<html xmlns:svg="http://www.w3.org/2000/svg" lang="en"><!-- a comment --><head><title>A &lt;title&gt;</title><meta charset="utf-8"/></head><body class="a &quot;b&quot;"><p>Some <b>bold</b> and &lt;raw&gt; text.</p><svg:rect width="10"/></body></html>
+"#
+        );
+        assert_eq!(from_document(&doc)?, parse_xml(&to_xml(&doc)?)?);
+        Ok(())
+    }
+
+    #[test]
+    fn hand_written() -> miette::Result<()> {
+        let doc: KdlDocument = r#"
+        (svg)svg width=100 visible=#true {
+            text 1.5 {
+                - " more"
+            }
+        }
+        "#
+        .parse()?;
+        assert_eq!(
+            to_xml(&doc)?,
+            "<svg width=\"100\" visible=\"true\"><text>1.5 more</text></svg>\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn errors() -> miette::Result<()> {
+        let err = parse_xml("<a><b></a>").unwrap_err();
+        assert!(err.span.is_some());
+        let err = parse_xml("<a>").unwrap_err();
+        assert_eq!(err.message, "Unclosed element <a>.");
+
+        for (input, message, at) in [
+            (
+                "a 1 2",
+                "Elements can only have one argument, for their text.",
+                "2",
+            ),
+            ("a x=#null", "#null has no XML equivalent.", "x=#null"),
+            (
+                "! a b",
+                "`!` nodes must have exactly one argument and nothing else.",
+                "! a b",
+            ),
+            (
+                "?pi { a; }",
+                "Processing instructions can't have children.",
+                "?pi { a; }",
+            ),
+        ] {
+            let err = from_document(&input.parse()?).unwrap_err();
+            assert_eq!(err.message, message, "{input}");
+            #[cfg(feature = "span")]
+            {
+                let span = err.span.unwrap();
+                assert_eq!(&input[span.offset()..][..span.len()], at, "{input}");
+            }
+            #[cfg(not(feature = "span"))]
+            let _ = at;
+        }
+        Ok(())
+    }
+}
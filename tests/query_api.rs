@@ -1,3 +1,6 @@
+// These exercise every `IntoKdlQuery` impl, including the one for `&String`.
+#![allow(clippy::needless_borrows_for_generic_args)]
+
 use kdl::{KdlDocument, KdlQuery};
 use miette::Result;
 
@@ -13,11 +16,11 @@ fn document_query_all() -> Result<()> {
     let results = doc.query_all("bar".parse::<KdlQuery>()?)?;
     assert_eq!(results.count(), 1);
 
-    let results = doc.query_all("scope()")?;
+    let results = doc.query_all("top()")?;
     assert_eq!(
         results.count(),
-        0,
-        "scope() on its own doesn't return anything if querying from a doc."
+        3,
+        "top() on its own matches every top-level node of a doc."
     );
 
     Ok(())
@@ -32,14 +35,14 @@ fn document_query() -> Result<()> {
     assert!(doc.query(&String::from("bar"))?.is_some());
     assert!(doc.query("bar".parse::<KdlQuery>()?)?.is_some());
 
-    assert!(doc.query("scope()")?.is_none());
+    assert_eq!(doc.query("top()")?, Some(&doc.nodes()[0]));
 
     Ok(())
 }
 
 #[test]
 fn document_query_get() -> Result<()> {
-    let doc = "foo\nbar true\nbaz".parse::<KdlDocument>()?;
+    let doc = "foo\nbar #true\nbaz".parse::<KdlDocument>()?;
 
     assert_eq!(doc.query_get("bar", 0)?, Some(&true.into()));
     assert_eq!(doc.query_get(String::from("bar"), 0)?, Some(&true.into()));
@@ -54,7 +57,7 @@ fn document_query_get() -> Result<()> {
 
 #[test]
 fn document_query_get_all() -> Result<()> {
-    let doc = "foo\nbar true\nbaz false".parse::<KdlDocument>()?;
+    let doc = "foo\nbar #true\nbaz #false".parse::<KdlDocument>()?;
 
     assert_eq!(
         doc.query_get_all("[]", 0)?.collect::<Vec<_>>(),
@@ -90,13 +93,14 @@ fn node_query_all() -> Result<()> {
     let results = node.query_all("b".parse::<KdlQuery>()?)?;
     assert_eq!(results.count(), 1);
 
-    let results = node.query_all("scope()")?.collect::<Vec<_>>();
-    assert_eq!(results[0], node);
+    // The node itself is never part of the results.
+    let results = node.query_all("top()")?.collect::<Vec<_>>();
+    assert_eq!(results, vec![&node.children().unwrap().nodes()[0]]);
 
-    let results = node.query_all("scope() > a".parse::<KdlQuery>()?)?;
+    let results = node.query_all("top() > a".parse::<KdlQuery>()?)?;
     assert_eq!(results.count(), 1);
 
-    let results = node.query_all("scope() > b".parse::<KdlQuery>()?)?;
+    let results = node.query_all("top() > b".parse::<KdlQuery>()?)?;
     assert_eq!(results.count(), 0);
 
     Ok(())
@@ -121,9 +125,9 @@ fn node_query() -> Result<()> {
     assert!(node.query(&String::from("b"))?.is_some());
     assert!(node.query("b".parse::<KdlQuery>()?)?.is_some());
 
-    assert_eq!(node.query("scope()")?, Some(node));
-    assert!(node.query("scope() > a")?.is_some());
-    assert!(node.query("scope() > b")?.is_none());
+    assert_eq!(node.query("top()")?, node.query("a")?);
+    assert!(node.query("top() > a")?.is_some());
+    assert!(node.query("top() > b")?.is_none());
 
     Ok(())
 }
@@ -133,8 +137,8 @@ fn node_query_get() -> Result<()> {
     let doc = r#"
         foo
         bar 1 2 3 {
-            a false {
-                b true
+            a #false {
+                b #true
             }
         }
         baz
@@ -150,9 +154,9 @@ fn node_query_get() -> Result<()> {
         Some(&true.into())
     );
 
-    assert_eq!(node.query_get("scope()", 0)?, Some(&1.into()));
-    assert_eq!(node.query_get("scope() > a", 0)?, Some(&false.into()));
-    assert!(node.query_get("scope() > b", "prop")?.is_none());
+    assert_eq!(node.query_get("top()", 0)?, Some(&false.into()));
+    assert_eq!(node.query_get("top() > a", 0)?, Some(&false.into()));
+    assert!(node.query_get("top() > b", "prop")?.is_none());
     Ok(())
 }
 
@@ -161,8 +165,8 @@ fn node_query_get_all() -> Result<()> {
     let doc = r#"
         foo
         bar 1 2 3 {
-            a false {
-                b true
+            a #false {
+                b #true
             }
         }
         baz
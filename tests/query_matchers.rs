@@ -3,7 +3,7 @@ use miette::Result;
 use pretty_assertions::assert_eq;
 
 #[test]
-fn scope_alone() -> Result<()> {
+fn top_alone() -> Result<()> {
     let doc: KdlDocument = r#"
             foo {
                 bar
@@ -14,21 +14,29 @@ fn scope_alone() -> Result<()> {
             "#
     .parse()?;
 
-    let results = doc.query_all("scope()")?.collect::<Vec<&KdlNode>>();
+    let results = doc.query_all("top()")?.collect::<Vec<&KdlNode>>();
 
-    assert_eq!(results, Vec::<&KdlNode>::new());
+    assert_eq!(results, doc.nodes().iter().collect::<Vec<&KdlNode>>());
 
     let results = doc.nodes()[0]
-        .query_all("scope()")?
+        .query_all("top()")?
         .collect::<Vec<&KdlNode>>();
 
-    assert_eq!(results, vec![&doc.nodes()[0]]);
+    assert_eq!(
+        results,
+        doc.nodes()[0]
+            .children()
+            .unwrap()
+            .nodes()
+            .iter()
+            .collect::<Vec<&KdlNode>>()
+    );
 
     Ok(())
 }
 
 #[test]
-fn scope_only_at_top() -> Result<()> {
+fn top_only_at_top() -> Result<()> {
     let doc: KdlDocument = r#"
             foo {
                 bar
@@ -36,10 +44,7 @@ fn scope_only_at_top() -> Result<()> {
             "#
     .parse()?;
 
-    assert!(
-        doc.query_all("foo >> scope()").is_err(),
-        "scope() must be at the top level"
-    );
+    assert!(doc.query_all("foo top()").is_err(), "top() must come first");
 
     Ok(())
 }
@@ -143,7 +148,7 @@ fn prop_matcher() -> Result<()> {
 }
 
 #[test]
-fn empty_arg_matcher() -> Result<()> {
+fn empty_val_matcher() -> Result<()> {
     let doc: KdlDocument = r#"
             foo {
                 bar 1
@@ -160,7 +165,7 @@ fn empty_arg_matcher() -> Result<()> {
             "#
     .parse()?;
 
-    let results = doc.query_all("[arg() = 1]")?.collect::<Vec<&KdlNode>>();
+    let results = doc.query_all("[val() = 1]")?.collect::<Vec<&KdlNode>>();
 
     assert_eq!(
         results,
@@ -173,7 +178,7 @@ fn empty_arg_matcher() -> Result<()> {
         ]
     );
 
-    let results = doc.query_all("[arg()]")?.collect::<Vec<&KdlNode>>();
+    let results = doc.query_all("[val()]")?.collect::<Vec<&KdlNode>>();
 
     assert_eq!(
         results,
@@ -191,7 +196,7 @@ fn empty_arg_matcher() -> Result<()> {
 }
 
 #[test]
-fn indexed_arg_matcher() -> Result<()> {
+fn indexed_val_matcher() -> Result<()> {
     let doc: KdlDocument = r#"
             foo {
                 bar 1 2
@@ -208,7 +213,7 @@ fn indexed_arg_matcher() -> Result<()> {
             "#
     .parse()?;
 
-    let results = doc.query_all("[arg(1) = 2]")?.collect::<Vec<&KdlNode>>();
+    let results = doc.query_all("[val(1) = 2]")?.collect::<Vec<&KdlNode>>();
 
     assert_eq!(
         results,
@@ -221,7 +226,7 @@ fn indexed_arg_matcher() -> Result<()> {
         ]
     );
 
-    let results = doc.query_all("[arg(2) = 2]")?.collect::<Vec<&KdlNode>>();
+    let results = doc.query_all("[val(2) = 2]")?.collect::<Vec<&KdlNode>>();
 
     assert_eq!(
         results,
@@ -275,7 +280,7 @@ fn type_annotation_matcher() -> Result<()> {
     );
 
     let results = doc
-        .query_all("[type() = \"here\"]")?
+        .query_all("[tag() = \"here\"]")?
         .collect::<Vec<&KdlNode>>();
 
     assert_eq!(
@@ -3,7 +3,7 @@ use miette::Result;
 use pretty_assertions::assert_eq;
 
 #[test]
-fn scope_with_all_children() -> Result<()> {
+fn top_with_all_children() -> Result<()> {
     let doc: KdlDocument = r#"
             foo {
                 bar
@@ -14,14 +14,14 @@ fn scope_with_all_children() -> Result<()> {
             "#
     .parse()?;
 
-    let results = doc.query_all("scope() > []")?.collect::<Vec<&KdlNode>>();
+    let results = doc.query_all("top() > []")?.collect::<Vec<&KdlNode>>();
 
     assert_eq!(&results, &doc.nodes().iter().collect::<Vec<&KdlNode>>());
     Ok(())
 }
 
 #[test]
-fn scope_child_by_name() -> Result<()> {
+fn top_child_by_name() -> Result<()> {
     let doc: KdlDocument = r#"
             foo {
                 bar
@@ -35,13 +35,13 @@ fn scope_child_by_name() -> Result<()> {
             "#
     .parse()?;
 
-    let results = doc.query_all("scope() > bar")?.collect::<Vec<&KdlNode>>();
+    let results = doc.query_all("top() > bar")?.collect::<Vec<&KdlNode>>();
 
     assert_eq!(results, vec![&doc.nodes()[1]]);
 
     // Scope from a specific node.
     let results = results[0]
-        .query_all("scope() > a")?
+        .query_all("top() > a")?
         .collect::<Vec<&KdlNode>>();
 
     assert_eq!(
@@ -53,7 +53,7 @@ fn scope_child_by_name() -> Result<()> {
 }
 
 #[test]
-fn scope_descendants() -> Result<()> {
+fn top_descendants() -> Result<()> {
     let doc: KdlDocument = r#"
             foo {
                 bar
@@ -64,7 +64,7 @@ fn scope_descendants() -> Result<()> {
             "#
     .parse()?;
 
-    let results = doc.query_all("scope() >> bar")?.collect::<Vec<&KdlNode>>();
+    let results = doc.query_all("top() bar")?.collect::<Vec<&KdlNode>>();
 
     assert_eq!(
         results,
@@ -77,7 +77,7 @@ fn scope_descendants() -> Result<()> {
 }
 
 #[test]
-fn scope_only_at_top() -> Result<()> {
+fn top_only_at_top() -> Result<()> {
     let doc: KdlDocument = r#"
             foo {
                 bar
@@ -85,10 +85,7 @@ fn scope_only_at_top() -> Result<()> {
             "#
     .parse()?;
 
-    assert!(
-        doc.query_all("foo >> scope()").is_err(),
-        "scope() must be at the top level"
-    );
+    assert!(doc.query_all("foo top()").is_err(), "top() must come first");
 
     Ok(())
 }
@@ -135,7 +132,7 @@ fn node_descendants() -> Result<()> {
             "#
     .parse()?;
 
-    let results = doc.query_all("foo >> bar")?.collect::<Vec<&KdlNode>>();
+    let results = doc.query_all("foo bar")?.collect::<Vec<&KdlNode>>();
 
     assert_eq!(
         results,
@@ -227,16 +224,16 @@ fn node_sibling() -> Result<()> {
             "#
     .parse()?;
 
-    let results = doc.query_all("foo ++ bar")?.collect::<Vec<&KdlNode>>();
+    let results = doc.query_all("foo ~ bar")?.collect::<Vec<&KdlNode>>();
 
     assert_eq!(results, vec![&doc.nodes()[1]]);
 
-    let results = doc.query_all("foo ++ baz")?.collect::<Vec<&KdlNode>>();
+    let results = doc.query_all("foo ~ baz")?.collect::<Vec<&KdlNode>>();
 
     assert_eq!(results, vec![&doc.nodes()[2]]);
 
     let results = doc
-        .query_all("foo ++ bar ++ other")?
+        .query_all("foo ~ bar ~ other")?
         .collect::<Vec<&KdlNode>>();
 
     assert_eq!(results, vec![&doc.nodes()[4]]);
@@ -262,7 +259,7 @@ fn multiple_selectors() -> Result<()> {
             "#
     .parse()?;
 
-    let results = doc.query_all("foo, baz")?.collect::<Vec<&KdlNode>>();
+    let results = doc.query_all("foo || baz")?.collect::<Vec<&KdlNode>>();
 
     assert_eq!(
         results,
@@ -302,7 +299,7 @@ fn all_combined() -> Result<()> {
     .parse()?;
 
     let results = doc
-        .query_all("foo >> baz > foo + bar ++ other")?
+        .query_all("foo baz > foo + bar ~ other")?
         .collect::<Vec<&KdlNode>>();
 
     assert_eq!(
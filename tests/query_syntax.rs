@@ -0,0 +1,57 @@
+use kdl::KdlDocument;
+use miette::Result;
+
+#[test]
+fn syntax_errors() -> Result<()> {
+    macro_rules! assert_syntax_errors {
+            ($(($input:expr, $msg:expr, ($offset:expr, $len:expr))),*) => {
+                $(
+                    let err = "node".parse::<KdlDocument>()
+                        .unwrap()
+                        .query_all($input)
+                        .expect_err("query parse should've failed.");
+                    let diag = &err.diagnostics[0];
+                    assert_eq!(diag.to_string(), $msg, "unexpected error message for {:?}", $input);
+                    assert_eq!(diag.span.offset(), $offset, "unexpected span offset for {:?}", $input);
+                    assert_eq!(diag.span.len(), $len, "unexpected span length for {:?}", $input);
+                )*
+            }
+        }
+
+    assert_syntax_errors! {
+        ("", "Expected query.", (0, 0)),
+        (" top(", "Unexpected input after the query.", (4, 1)),
+        ("(", "Expected closing ')' for this type matcher.", (1, 0)),
+        (")", "Expected node filter.", (0, 0)),
+        ("[", "Expected accessor.", (1, 0)),
+        ("]", "Expected node filter.", (0, 0)),
+        ("a ||", "Expected node filter.", (4, 0)),
+        ("a >", "Expected node filter.", (3, 0)),
+        ("[] > top( )", "Unexpected input after the query.", (8, 3)),
+        ("()(type)", "Unexpected input after the query.", (2, 6)),
+        ("(type)()", "Unexpected input after the query.", (6, 2)),
+        ("name(type)", "Unexpected input after the query.", (4, 6)),
+        ("[]name", "Unexpected input after the query.", (2, 4)),
+        ("[]()", "Unexpected input after the query.", (2, 2)),
+        ("[tag(blah)]", "Expected closing ')' for this accessor.", (5, 0)),
+        ("[val(]", "Expected closing ')' for this accessor.", (5, 0)),
+        ("[top()]", "Expected valid accessor.", (1, 5)),
+        ("[top ( )]", "Expected closing ']' for this matcher.", (5, 0)),
+        ("[other()]", "Expected valid accessor.", (1, 7)),
+        ("[val()1]", "Expected closing ']' for this matcher.", (6, 0)),
+        ("[val() 1]", "Expected closing ']' for this matcher.", (7, 0)),
+        ("[val() = #nope]", "Expected keyword.", (9, 1)),
+        // Only string values are allowed here.
+        ("[val()*=1]", "Expected string.", (8, 1)),
+        ("[val()^=1]", "Expected string.", (8, 1)),
+        ("[val()$=1]", "Expected string.", (8, 1)),
+        ("[val()*=#null]", "Expected string.", (8, 5)),
+        ("[val()^=#null]", "Expected string.", (8, 5)),
+        ("[val()$=#null]", "Expected string.", (8, 5)),
+        ("[val()*=#true]", "Expected string.", (8, 5)),
+        ("[val()^=#true]", "Expected string.", (8, 5)),
+        ("[val()$=#true]", "Expected string.", (8, 5))
+    }
+
+    Ok(())
+}